@@ -0,0 +1,3 @@
+pub mod stake_client;
+
+pub use stake_client::{StakeClient, StakeClientError};
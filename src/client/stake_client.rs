@@ -0,0 +1,293 @@
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    system_program, sysvar,
+    transaction::Transaction,
+};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::node::stake_check::{verify_stake_account, StakeCheckError};
+use crate::node::submit::{submit_with_retry, RetryPolicy, SubmitError};
+use crate::program::stake::{
+    find_delegation_address, find_reward_config_address, find_stake_address,
+    find_validator_delegations_address, StakeAccount, StakeInstruction,
+};
+
+#[derive(Error, Debug)]
+pub enum StakeClientError {
+    #[error("RPC error: {0}")]
+    Rpc(String),
+    #[error(transparent)]
+    Submit(#[from] SubmitError),
+    #[error(transparent)]
+    Stake(#[from] StakeCheckError),
+}
+
+/// Stakes `amount` lamports from `staker` into its derived stake account,
+/// locked for `lock_period` seconds.
+pub fn create_stake_instruction(program_id: &Pubkey, staker: &Pubkey, amount: u64, lock_period: i64) -> Instruction {
+    let (stake_address, _) = find_stake_address(program_id, staker);
+    Instruction::new_with_borsh(
+        *program_id,
+        &StakeInstruction::CreateStake { amount, lock_period },
+        vec![
+            AccountMeta::new(*staker, true),
+            AccountMeta::new(stake_address, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+    )
+}
+
+/// Withdraws `amount` lamports of unlocked stake back to `staker`. The
+/// reward config rides along so rewards accrue before the stake shrinks.
+pub fn withdraw_instruction(program_id: &Pubkey, staker: &Pubkey, amount: u64) -> Instruction {
+    let (stake_address, _) = find_stake_address(program_id, staker);
+    let (reward_config, _) = find_reward_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &StakeInstruction::Withdraw { amount },
+        vec![
+            AccountMeta::new(*staker, true),
+            AccountMeta::new(stake_address, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(reward_config, false),
+        ],
+    )
+}
+
+/// Rewrites `staker`'s stake account in the current layout if it was
+/// created in a legacy one, paying the extra rent from `staker`.
+pub fn migrate_stake_instruction(program_id: &Pubkey, staker: &Pubkey) -> Instruction {
+    let (stake_address, _) = find_stake_address(program_id, staker);
+    Instruction::new_with_borsh(
+        *program_id,
+        &StakeInstruction::MigrateStake,
+        vec![
+            AccountMeta::new(*staker, true),
+            AccountMeta::new(stake_address, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+    )
+}
+
+/// Delegates `amount` lamports from `delegator` to `validator`.
+pub fn delegate_instruction(program_id: &Pubkey, delegator: &Pubkey, validator: &Pubkey, amount: u64) -> Instruction {
+    let (delegation, _) = find_delegation_address(program_id, delegator, validator);
+    let (totals, _) = find_validator_delegations_address(program_id, validator);
+    Instruction::new_with_borsh(
+        *program_id,
+        &StakeInstruction::Delegate { validator: *validator, amount },
+        vec![
+            AccountMeta::new(*delegator, true),
+            AccountMeta::new(delegation, false),
+            AccountMeta::new(totals, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Credits `validator`'s stake account with `amount` of `epoch` rewards,
+/// signed by the reward `authority`.
+pub fn distribute_rewards_instruction(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    validator: &Pubkey,
+    epoch: u64,
+    amount: u64,
+) -> Instruction {
+    let (stake_address, _) = find_stake_address(program_id, validator);
+    let (reward_config, _) = find_reward_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &StakeInstruction::DistributeRewards { epoch, amount },
+        vec![
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new(reward_config, false),
+            AccountMeta::new(stake_address, false),
+        ],
+    )
+}
+
+/// Reward credits packed into one transaction, well within its size limit.
+pub const MAX_REWARDS_PER_TRANSACTION: usize = 10;
+
+/// Stakes, withdraws and delegates on behalf of one keypair, submitting
+/// through the node's RPC client.
+#[derive(Debug, Clone)]
+pub struct StakeClient {
+    rpc: Arc<RpcClient>,
+    keypair: Arc<Keypair>,
+    program_id: Pubkey,
+    retry_policy: RetryPolicy,
+}
+
+impl StakeClient {
+    pub fn new(rpc: Arc<RpcClient>, keypair: Arc<Keypair>, program_id: Pubkey) -> Self {
+        StakeClient {
+            rpc,
+            keypair,
+            program_id,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn staker(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+
+    pub fn stake_address(&self) -> Pubkey {
+        find_stake_address(&self.program_id, &self.staker()).0
+    }
+
+    /// Reads the staker's stake account from chain and checks it holds at
+    /// least `min_stake` active lamports.
+    pub async fn stake_account(&self, min_stake: u64) -> Result<StakeAccount, StakeClientError> {
+        Ok(verify_stake_account(self.rpc.as_ref(), &self.program_id, &self.staker(), min_stake).await?)
+    }
+
+    pub async fn create_stake(&self, amount: u64, lock_period: i64) -> Result<Signature, StakeClientError> {
+        self.submit(&[create_stake_instruction(&self.program_id, &self.staker(), amount, lock_period)])
+            .await
+    }
+
+    pub async fn withdraw(&self, amount: u64) -> Result<Signature, StakeClientError> {
+        self.submit(&[withdraw_instruction(&self.program_id, &self.staker(), amount)]).await
+    }
+
+    pub async fn migrate_stake(&self) -> Result<Signature, StakeClientError> {
+        self.submit(&[migrate_stake_instruction(&self.program_id, &self.staker())]).await
+    }
+
+    pub async fn delegate(&self, validator: &Pubkey, amount: u64) -> Result<Signature, StakeClientError> {
+        self.submit(&[delegate_instruction(&self.program_id, &self.staker(), validator, amount)])
+            .await
+    }
+
+    /// Credits each validator its share of `epoch` rewards, as the reward
+    /// authority, in batches of `MAX_REWARDS_PER_TRANSACTION`. A batch that
+    /// fails is resubmitted one share per transaction, so one bad account
+    /// does not hold back the rest. Returns the signatures and the shares
+    /// left unpaid with their errors.
+    pub async fn distribute_rewards(
+        &self,
+        epoch: u64,
+        rewards: &[(Pubkey, u64)],
+    ) -> (Vec<Signature>, Vec<(Pubkey, StakeClientError)>) {
+        let instruction = |(validator, amount): &(Pubkey, u64)| {
+            distribute_rewards_instruction(&self.program_id, &self.staker(), validator, epoch, *amount)
+        };
+        let (mut signatures, mut unpaid) = (Vec::new(), Vec::new());
+        for batch in rewards.chunks(MAX_REWARDS_PER_TRANSACTION) {
+            let instructions: Vec<Instruction> = batch.iter().map(instruction).collect();
+            match self.submit(&instructions).await {
+                Ok(signature) => signatures.push(signature),
+                Err(e) if batch.len() == 1 => unpaid.push((batch[0].0, e)),
+                Err(_) => {
+                    for share in batch {
+                        match self.submit(&[instruction(share)]).await {
+                            Ok(signature) => signatures.push(signature),
+                            Err(e) => unpaid.push((share.0, e)),
+                        }
+                    }
+                }
+            }
+        }
+        (signatures, unpaid)
+    }
+
+    async fn submit(&self, instructions: &[Instruction]) -> Result<Signature, StakeClientError> {
+        let blockhash = self
+            .rpc
+            .get_latest_blockhash()
+            .await
+            .map_err(|e| StakeClientError::Rpc(e.to_string()))?;
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.keypair.pubkey()),
+            &[self.keypair.as_ref()],
+            blockhash,
+        );
+        Ok(submit_with_retry(self.rpc.as_ref(), &transaction, self.retry_policy).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::stake::{process_instruction, Delegation};
+    use borsh::BorshDeserialize;
+    use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+
+    const MIN_STAKE: u64 = 10_000_000_000;
+
+    async fn process(context: &mut ProgramTestContext, instruction: Instruction) {
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer],
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+    }
+
+    #[test]
+    fn test_instructions_target_derived_addresses() {
+        let program_id = Pubkey::new_unique();
+        let staker = Pubkey::new_unique();
+        let validator = Pubkey::new_unique();
+
+        let create = create_stake_instruction(&program_id, &staker, MIN_STAKE, 60);
+        assert_eq!(create.accounts[1].pubkey, find_stake_address(&program_id, &staker).0);
+        assert!(create.accounts[0].is_signer && !create.accounts[1].is_signer);
+        assert_eq!(
+            StakeInstruction::try_from_slice(&create.data).unwrap(),
+            StakeInstruction::CreateStake { amount: MIN_STAKE, lock_period: 60 }
+        );
+
+        let delegate = delegate_instruction(&program_id, &staker, &validator, 5);
+        assert_eq!(delegate.accounts[1].pubkey, find_delegation_address(&program_id, &staker, &validator).0);
+        assert_eq!(delegate.accounts[2].pubkey, find_validator_delegations_address(&program_id, &validator).0);
+
+        let reward = distribute_rewards_instruction(&program_id, &staker, &validator, 3, 40);
+        assert_eq!(reward.accounts[1].pubkey, find_reward_config_address(&program_id).0);
+        assert_eq!(reward.accounts[2].pubkey, find_stake_address(&program_id, &validator).0);
+        assert!(reward.accounts[0].is_signer && reward.accounts[1].is_writable && reward.accounts[2].is_writable);
+
+        let migrate = migrate_stake_instruction(&program_id, &staker);
+        assert_eq!(migrate.accounts[1].pubkey, find_stake_address(&program_id, &staker).0);
+        assert!(migrate.accounts[0].is_signer && migrate.accounts[1].is_writable);
+    }
+
+    #[tokio::test]
+    async fn test_instructions_accepted_by_stake_program() {
+        let program_id = Pubkey::new_unique();
+        let mut context = ProgramTest::new("fractis_stake", program_id, processor!(process_instruction))
+            .start_with_context()
+            .await;
+        let staker = context.payer.pubkey();
+        let validator = Pubkey::new_unique();
+
+        process(&mut context, create_stake_instruction(&program_id, &staker, MIN_STAKE, 0)).await;
+        process(&mut context, withdraw_instruction(&program_id, &staker, 1_000)).await;
+        process(&mut context, delegate_instruction(&program_id, &staker, &validator, 2_000)).await;
+
+        let (stake_address, _) = find_stake_address(&program_id, &staker);
+        let stake = context.banks_client.get_account(stake_address).await.unwrap().unwrap();
+        assert_eq!(StakeAccount::try_from_slice(&stake.data).unwrap().amount, MIN_STAKE - 1_000);
+
+        let (delegation_address, _) = find_delegation_address(&program_id, &staker, &validator);
+        let delegation = context.banks_client.get_account(delegation_address).await.unwrap().unwrap();
+        assert_eq!(Delegation::try_from_slice(&delegation.data).unwrap().amount, 2_000);
+    }
+}
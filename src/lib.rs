@@ -0,0 +1,6 @@
+pub mod client;
+#[cfg(feature = "llm")]
+pub mod llm;
+pub mod node;
+pub mod program;
+pub mod utils;
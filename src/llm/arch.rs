@@ -0,0 +1,150 @@
+use candle_transformers::models::llama::Config;
+
+use crate::node::config::ModelArch;
+
+/// How a vocabulary spells raw bytes in its token pieces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenEncoding {
+    /// `▁` for spaces and `<0xNN>` byte-fallback pieces (Llama 2, Mistral).
+    SentencePiece,
+    /// Every byte mapped to a printable character, GPT-2 style (Llama 3).
+    ByteLevel,
+}
+
+impl TokenEncoding {
+    /// Appends the bytes `piece` stands for to `out`.
+    pub fn piece_bytes(&self, piece: &str, out: &mut Vec<u8>) {
+        match self {
+            TokenEncoding::SentencePiece => crate::llm::model::token_piece_bytes(piece, out),
+            TokenEncoding::ByteLevel => {
+                for c in piece.chars() {
+                    match byte_level_byte(c) {
+                        Some(byte) => out.push(byte),
+                        None => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Inverse of the GPT-2 byte-to-character table: printable Latin-1 bytes
+/// stand for themselves, the other 68 are shifted past U+00FF in order.
+fn byte_level_byte(c: char) -> Option<u8> {
+    let printable = |b: u32| (33..=126).contains(&b) || (161..=172).contains(&b) || (174..=255).contains(&b);
+    let code = c as u32;
+    if code < 256 && printable(code) {
+        return Some(code as u8);
+    }
+    let shifted = code.checked_sub(256)? as usize;
+    (0u32..256).filter(|&b| !printable(b)).nth(shifted).map(|b| b as u8)
+}
+
+/// Everything that differs between the supported model families.
+#[derive(Debug, Clone)]
+pub struct ModelSpec {
+    pub arch: ModelArch,
+    /// Human-readable family and size, e.g. `LLaMA-2 7B`.
+    pub display_name: &'static str,
+    /// Release of the weights, part of the model ID peers compare.
+    pub version: &'static str,
+    pub release_date: &'static str,
+    pub config: Config,
+    pub context_length: usize,
+    pub bos_token: &'static str,
+    pub eos_token: &'static str,
+    pub encoding: TokenEncoding,
+}
+
+impl ModelSpec {
+    pub fn new(arch: ModelArch) -> Self {
+        let llama2 = Config::config_7b_v2(false);
+        let (config, context_length, eos_token, encoding) = match arch {
+            ModelArch::Llama2_7B => (llama2, 4096, "</s>", TokenEncoding::SentencePiece),
+            ModelArch::Llama3_8B => (
+                Config {
+                    intermediate_size: 14336,
+                    vocab_size: 128256,
+                    num_key_value_heads: 8,
+                    rope_theta: 500_000.0,
+                    ..llama2
+                },
+                // Llama 3 was trained on 8192 tokens, but candle 0.4 only
+                // builds rotary tables for 4096 positions.
+                4096,
+                "<|end_of_text|>",
+                TokenEncoding::ByteLevel,
+            ),
+            // Mistral's sliding window is not modelled, so its context stops at the window.
+            ModelArch::Mistral7B => (
+                Config {
+                    intermediate_size: 14336,
+                    num_key_value_heads: 8,
+                    ..llama2
+                },
+                4096,
+                "</s>",
+                TokenEncoding::SentencePiece,
+            ),
+            ModelArch::TinyLlama1_1B => (
+                Config {
+                    hidden_size: 2048,
+                    intermediate_size: 5632,
+                    num_hidden_layers: 22,
+                    num_key_value_heads: 4,
+                    ..llama2
+                },
+                2048,
+                "</s>",
+                TokenEncoding::SentencePiece,
+            ),
+        };
+        let bos_token = match encoding {
+            TokenEncoding::SentencePiece => "<s>",
+            TokenEncoding::ByteLevel => "<|begin_of_text|>",
+        };
+        let (display_name, version, release_date) = match arch {
+            ModelArch::Llama2_7B => ("LLaMA-2 7B", "2.0.1", "2023-12"),
+            ModelArch::Llama3_8B => ("Llama 3 8B", "3.0", "2024-04"),
+            ModelArch::Mistral7B => ("Mistral 7B", "0.1", "2023-09"),
+            ModelArch::TinyLlama1_1B => ("TinyLlama 1.1B", "1.0", "2024-01"),
+        };
+        ModelSpec {
+            arch,
+            display_name,
+            version,
+            release_date,
+            config,
+            context_length,
+            bos_token,
+            eos_token,
+            encoding,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_level_pieces_decode_to_bytes() {
+        let mut bytes = Vec::new();
+        TokenEncoding::ByteLevel.piece_bytes("ĠhelloĊ", &mut bytes);
+        TokenEncoding::ByteLevel.piece_bytes("Ã©", &mut bytes);
+        assert_eq!(String::from_utf8(bytes).unwrap(), " hello\né");
+    }
+
+    #[test]
+    fn test_every_arch_has_a_spec() {
+        for arch in ModelArch::ALL {
+            let spec = ModelSpec::new(arch);
+            assert_eq!(spec.arch, arch);
+            assert_eq!(spec.config.hidden_size % spec.config.num_attention_heads, 0);
+            assert_eq!(spec.config.num_attention_heads % spec.config.num_key_value_heads, 0);
+        }
+        assert_eq!(ModelSpec::new(ModelArch::TinyLlama1_1B).context_length, 2048);
+        assert_eq!(ModelSpec::new(ModelArch::Llama3_8B).context_length, 4096);
+        assert_ne!(ModelSpec::new(ModelArch::Llama2_7B).version, ModelSpec::new(ModelArch::Llama3_8B).version);
+    }
+}
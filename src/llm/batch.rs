@@ -0,0 +1,165 @@
+use log::{debug, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout_at, Instant};
+
+use crate::llm::model::Truncation;
+use crate::llm::sampling::SamplingParams;
+
+const QUEUE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchRequest {
+    pub prompt: String,
+    pub max_tokens: usize,
+    pub sampling: SamplingParams,
+    pub truncation: Truncation,
+}
+
+/// Runs a group of requests gathered by `BatchQueue`, on the blocking pool.
+/// How much of the group actually shares forward passes is up to the
+/// generator. Results must line up with `requests`.
+pub trait BatchGenerator: Send + Sync + 'static {
+    fn generate_batch(&self, requests: &[BatchRequest]) -> Vec<Result<String, String>>;
+}
+
+struct Pending {
+    request: BatchRequest,
+    reply: oneshot::Sender<Result<String, String>>,
+}
+
+/// Collects generation requests for up to `window` (or until `max_batch_size`
+/// are waiting) and hands them to the generator in one call. `LightLLM`
+/// only buckets by length: prompts of exactly equal token length share
+/// forward passes, and each other length runs as its own group, so a batch
+/// of differently sized prompts costs about as much as running them in turn.
+/// There is no padding or attention masking to merge lengths.
+#[derive(Debug, Clone)]
+pub struct BatchQueue {
+    tx: mpsc::Sender<Pending>,
+    model_version: String,
+}
+
+impl BatchQueue {
+    pub fn spawn(generator: Arc<dyn BatchGenerator>, window: Duration, max_batch_size: usize) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(run_batches(generator, rx, window, max_batch_size.max(1)));
+        BatchQueue { tx, model_version: String::new() }
+    }
+
+    /// Labels results with the version of the model behind `generator`.
+    pub fn with_model_version(mut self, model_version: String) -> Self {
+        self.model_version = model_version;
+        self
+    }
+
+    pub fn model_version(&self) -> &str {
+        &self.model_version
+    }
+
+    pub async fn submit(&self, request: BatchRequest) -> Result<String, String> {
+        let (reply, response) = oneshot::channel();
+        self.tx
+            .send(Pending { request, reply })
+            .await
+            .map_err(|_| "Generation queue is closed".to_string())?;
+        response
+            .await
+            .map_err(|_| "Generation worker dropped the request".to_string())?
+    }
+}
+
+async fn run_batches(
+    generator: Arc<dyn BatchGenerator>,
+    mut rx: mpsc::Receiver<Pending>,
+    window: Duration,
+    max_batch_size: usize,
+) {
+    while let Some(first) = rx.recv().await {
+        let deadline = Instant::now() + window;
+        let mut batch = vec![first];
+
+        while batch.len() < max_batch_size {
+            match timeout_at(deadline, rx.recv()).await {
+                Ok(Some(pending)) => batch.push(pending),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        debug!("Running generation batch of {}", batch.len());
+        let (requests, replies): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .map(|pending| (pending.request, pending.reply))
+            .unzip();
+
+        let generator = Arc::clone(&generator);
+        let results = match tokio::task::spawn_blocking(move || generator.generate_batch(&requests)).await {
+            Ok(results) => results,
+            Err(e) => {
+                warn!("Generation batch panicked: {}", e);
+                Vec::new()
+            }
+        };
+
+        let mut results = results.into_iter();
+        for reply in replies {
+            let result = results
+                .next()
+                .unwrap_or_else(|| Err("Generator returned too few results".to_string()));
+            let _ = reply.send(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    #[derive(Default)]
+    struct RecordingGenerator {
+        batches: Mutex<Vec<usize>>,
+    }
+
+    impl BatchGenerator for RecordingGenerator {
+        fn generate_batch(&self, requests: &[BatchRequest]) -> Vec<Result<String, String>> {
+            self.batches.lock().push(requests.len());
+            requests.iter().map(|r| Ok(r.prompt.to_uppercase())).collect()
+        }
+    }
+
+    fn request(prompt: &str) -> BatchRequest {
+        BatchRequest {
+            prompt: prompt.to_string(),
+            max_tokens: 16,
+            sampling: SamplingParams::default(),
+            truncation: Truncation::Clamp,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_within_window_share_one_invocation() {
+        let generator = Arc::new(RecordingGenerator::default());
+        let queue = BatchQueue::spawn(generator.clone(), Duration::from_millis(100), 8);
+
+        let results = futures::future::join_all(
+            ["a", "b", "c", "d"].iter().map(|p| queue.submit(request(p))),
+        )
+        .await;
+
+        let texts: Vec<String> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(texts, vec!["A", "B", "C", "D"]);
+        assert_eq!(*generator.batches.lock(), vec![4]);
+    }
+
+    #[tokio::test]
+    async fn test_batches_capped_at_max_batch_size() {
+        let generator = Arc::new(RecordingGenerator::default());
+        let queue = BatchQueue::spawn(generator.clone(), Duration::from_millis(100), 2);
+
+        futures::future::join_all((0..5).map(|i| queue.submit(request(&i.to_string())))).await;
+
+        assert_eq!(*generator.batches.lock(), vec![2, 2, 1]);
+    }
+}
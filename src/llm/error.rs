@@ -0,0 +1,32 @@
+use solana_sdk::pubkey::ParsePubkeyError;
+use thiserror::Error;
+
+use crate::node::training::TrainingError;
+
+#[derive(Error, Debug)]
+pub enum LlmError {
+    #[error("Model error: {0}")]
+    Candle(#[from] candle_core::Error),
+    #[error("Tokenizer error: {0}")]
+    Tokenizer(#[from] tokenizers::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid shard index: {0}")]
+    ShardIndex(#[from] serde_json::Error),
+    #[error("Invalid model weights: {0}")]
+    Weights(String),
+    /// The request itself is unusable: bad sampling parameters, or a prompt
+    /// that does not fit the context window.
+    #[error("Invalid request: {0}")]
+    Request(String),
+    #[error("Generated tokens are not valid UTF-8 (valid up to byte {valid_up_to})")]
+    InvalidUtf8 { valid_up_to: usize },
+    #[error("Invalid trainer pubkey {0}: {1}")]
+    TrainerPubkey(String, ParsePubkeyError),
+    #[error("Training batch has no text to learn from")]
+    EmptyBatch,
+    #[error("Weight map lock poisoned")]
+    Poisoned,
+    #[error(transparent)]
+    Training(#[from] TrainingError),
+}
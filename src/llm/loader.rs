@@ -0,0 +1,208 @@
+use candle_core::quantized::gguf_file;
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::quantized_llama::ModelWeights;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::llm::error::LlmError;
+
+const SHARD_INDEX_FILE: &str = "model.safetensors.index.json";
+const SAFETENSORS_EXTENSION: &str = "safetensors";
+
+#[derive(Debug, Deserialize)]
+struct ShardIndex {
+    weight_map: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardSet {
+    pub files: Vec<PathBuf>,
+    pub expected_tensors: Option<HashSet<String>>,
+}
+
+pub fn resolve_shards(path: &Path) -> Result<ShardSet, LlmError> {
+    if path.is_dir() {
+        let index_path = path.join(SHARD_INDEX_FILE);
+        if index_path.exists() {
+            return read_index(&index_path);
+        }
+
+        let mut files: Vec<PathBuf> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map_or(false, |ext| ext == SAFETENSORS_EXTENSION))
+            .collect();
+        files.sort();
+
+        if files.is_empty() {
+            return Err(LlmError::Weights(format!("No safetensors shards found in {}", path.display())));
+        }
+        return Ok(ShardSet { files, expected_tensors: None });
+    }
+
+    if path.extension().map_or(false, |ext| ext == "json") {
+        return read_index(path);
+    }
+
+    Ok(ShardSet {
+        files: vec![path.to_path_buf()],
+        expected_tensors: None,
+    })
+}
+
+fn read_index(index_path: &Path) -> Result<ShardSet, LlmError> {
+    let index: ShardIndex = serde_json::from_str(&fs::read_to_string(index_path)?)?;
+    let base = index_path.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut files: Vec<PathBuf> = index.weight_map
+        .values()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|file| base.join(file))
+        .collect();
+    files.sort();
+
+    Ok(ShardSet {
+        files,
+        expected_tensors: Some(index.weight_map.into_keys().collect()),
+    })
+}
+
+pub fn load_tensors(
+    path: &Path,
+    device: &Device,
+    parallel: bool,
+) -> Result<HashMap<String, Tensor>, LlmError> {
+    let shards = resolve_shards(path)?;
+
+    let loaded: Vec<Result<HashMap<String, Tensor>, String>> = if parallel && shards.files.len() > 1 {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = shards.files
+                .iter()
+                .map(|file| scope.spawn(move || load_shard(file, device)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or_else(|_| Err("Shard loader thread panicked".to_string())))
+                .collect()
+        })
+    } else {
+        shards.files.iter().map(|file| load_shard(file, device)).collect()
+    };
+
+    let mut tensors = HashMap::new();
+    for shard in loaded {
+        for (name, tensor) in shard.map_err(LlmError::Weights)? {
+            if tensors.insert(name.clone(), tensor).is_some() {
+                return Err(LlmError::Weights(format!("Tensor {} appears in more than one shard", name)));
+            }
+        }
+    }
+
+    if let Some(expected) = &shards.expected_tensors {
+        let mut missing: Vec<&String> = expected
+            .iter()
+            .filter(|name| !tensors.contains_key(*name))
+            .collect();
+        if !missing.is_empty() {
+            missing.sort();
+            return Err(LlmError::Weights(format!("Missing tensors across shards: {:?}", missing)));
+        }
+    }
+
+    Ok(tensors)
+}
+
+fn load_shard(file: &Path, device: &Device) -> Result<HashMap<String, Tensor>, String> {
+    candle_core::safetensors::load(file, device)
+        .map_err(|e| format!("Failed to load shard {}: {}", file.display(), e))
+}
+
+pub fn var_builder(
+    path: &Path,
+    dtype: DType,
+    device: &Device,
+) -> Result<VarBuilder<'static>, LlmError> {
+    let tensors = load_tensors(path, device, true)?;
+    Ok(VarBuilder::from_tensors(tensors, dtype, device))
+}
+
+/// Quantized Llama-family weights from a single GGUF file. The layer shapes
+/// come from the file's own metadata.
+pub fn load_gguf(path: &Path, device: &Device) -> Result<ModelWeights, LlmError> {
+    let mut file = fs::File::open(path)?;
+    let content = gguf_file::Content::read(&mut file)
+        .map_err(|e| LlmError::Weights(format!("Failed to read GGUF file {}: {}", path.display(), e)))?;
+    Ok(ModelWeights::from_gguf(content, &mut file, device)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_nn::{linear, Module};
+
+    fn sample_tensors(device: &Device) -> HashMap<String, Tensor> {
+        let mut tensors = HashMap::new();
+        tensors.insert(
+            "proj.weight".to_string(),
+            Tensor::arange(0f32, 12f32, device).unwrap().reshape((3, 4)).unwrap(),
+        );
+        tensors.insert(
+            "proj.bias".to_string(),
+            Tensor::new(&[0.5f32, -1.0, 2.0], device).unwrap(),
+        );
+        tensors
+    }
+
+    fn forward(vb: VarBuilder) -> Vec<f32> {
+        let layer = linear(4, 3, vb.pp("proj")).unwrap();
+        let input = Tensor::new(&[[1f32, 2.0, 3.0, 4.0]], &Device::Cpu).unwrap();
+        layer.forward(&input).unwrap().flatten_all().unwrap().to_vec1().unwrap()
+    }
+
+    fn write_sharded(dir: &Path, tensors: &HashMap<String, Tensor>) {
+        let mut weight_map = HashMap::new();
+        for (i, (name, tensor)) in tensors.iter().enumerate() {
+            let file = format!("model-{:05}-of-00002.safetensors", i + 1);
+            candle_core::safetensors::save(&HashMap::from([(name.clone(), tensor.clone())]), dir.join(&file)).unwrap();
+            weight_map.insert(name.clone(), file);
+        }
+        let index = serde_json::json!({ "metadata": {}, "weight_map": weight_map });
+        fs::write(dir.join(SHARD_INDEX_FILE), index.to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_sharded_model_matches_single_file() {
+        let device = Device::Cpu;
+        let tensors = sample_tensors(&device);
+
+        let single_dir = tempfile::tempdir().unwrap();
+        let single_file = single_dir.path().join("model.safetensors");
+        candle_core::safetensors::save(&tensors, &single_file).unwrap();
+
+        let sharded_dir = tempfile::tempdir().unwrap();
+        write_sharded(sharded_dir.path(), &tensors);
+
+        let single = var_builder(&single_file, DType::F32, &device).unwrap();
+        let sharded = var_builder(sharded_dir.path(), DType::F32, &device).unwrap();
+
+        assert_eq!(forward(single), forward(sharded));
+    }
+
+    #[test]
+    fn test_missing_shard_tensor_rejected() {
+        let device = Device::Cpu;
+        let dir = tempfile::tempdir().unwrap();
+        write_sharded(dir.path(), &sample_tensors(&device));
+
+        let index_path = dir.path().join(SHARD_INDEX_FILE);
+        let mut index: serde_json::Value = serde_json::from_str(&fs::read_to_string(&index_path).unwrap()).unwrap();
+        index["weight_map"]["proj.extra"] = serde_json::json!("model-00001-of-00002.safetensors");
+        fs::write(&index_path, index.to_string()).unwrap();
+
+        let err = load_tensors(&index_path, &device, true).unwrap_err();
+        assert!(err.to_string().contains("proj.extra"));
+    }
+}
@@ -0,0 +1,158 @@
+use candle_core::{DType, Tensor};
+use candle_nn::{init, linear_no_bias, Init, Linear, Module, VarBuilder};
+use std::collections::HashMap;
+
+use crate::node::config::LoraConfig;
+
+/// Name segments of an adapter's two factors, as PEFT writes them, e.g.
+/// `model.layers.0.self_attn.q_proj.lora_A.weight`.
+pub const LORA_A: &str = "lora_A";
+pub const LORA_B: &str = "lora_B";
+
+/// Where adapter weights live and which projections get one. Mirrors the
+/// base model's `VarBuilder` path by path.
+#[derive(Clone)]
+pub struct AdapterBuilder<'a> {
+    config: &'a LoraConfig,
+    vb: VarBuilder<'a>,
+}
+
+impl<'a> AdapterBuilder<'a> {
+    pub fn new(config: &'a LoraConfig, vb: VarBuilder<'a>) -> Self {
+        AdapterBuilder { config, vb }
+    }
+
+    pub fn pp(&self, segment: &str) -> Self {
+        AdapterBuilder { config: self.config, vb: self.vb.pp(segment) }
+    }
+}
+
+/// A frozen projection plus, where targeted, a low-rank update:
+/// `y = x W^T + scale * x A^T B^T`. `B` starts at zero, so a fresh adapter
+/// leaves the model unchanged.
+#[derive(Debug, Clone)]
+pub struct LoraLinear {
+    base: Linear,
+    adapter: Option<(Linear, Linear)>,
+    scale: f64,
+}
+
+impl LoraLinear {
+    /// `module` is the projection's own name, such as `q_proj`, matched
+    /// against the config's target modules.
+    pub fn load(
+        in_dim: usize,
+        out_dim: usize,
+        module: &str,
+        vb: VarBuilder,
+        adapters: Option<&AdapterBuilder>,
+    ) -> candle_core::Result<Self> {
+        let base = linear_no_bias(in_dim, out_dim, vb.pp(module))?;
+        let Some(adapters) = adapters.filter(|a| a.config.targets(module)) else {
+            return Ok(LoraLinear { base, adapter: None, scale: 0.0 });
+        };
+        let vb = adapters.vb.pp(module);
+        let rank = adapters.config.rank;
+        let a = vb.pp(LORA_A).get_with_hints((rank, in_dim), "weight", init::DEFAULT_KAIMING_NORMAL)?;
+        let b = vb.pp(LORA_B).get_with_hints((out_dim, rank), "weight", Init::Const(0.0))?;
+        Ok(LoraLinear {
+            base,
+            adapter: Some((Linear::new(a, None), Linear::new(b, None))),
+            scale: adapters.config.scale(),
+        })
+    }
+}
+
+impl Module for LoraLinear {
+    fn forward(&self, xs: &Tensor) -> candle_core::Result<Tensor> {
+        let out = self.base.forward(xs)?;
+        match &self.adapter {
+            Some((a, b)) => out + (b.forward(&a.forward(xs)?)? * self.scale)?,
+            None => Ok(out),
+        }
+    }
+}
+
+/// Folds each adapter in `adapter` into the matching `<module>.weight` of
+/// `weights`, keyed as the base checkpoint is. Returns how many projections
+/// changed.
+pub fn merge_adapter(
+    weights: &mut HashMap<String, Tensor>,
+    adapter: &HashMap<String, Tensor>,
+    scale: f64,
+) -> Result<usize, String> {
+    let suffix = format!(".{}.weight", LORA_A);
+    let mut modules: Vec<&str> = adapter.keys().filter_map(|name| name.strip_suffix(&suffix)).collect();
+    modules.sort();
+
+    for module in &modules {
+        let a = &adapter[&format!("{}{}", module, suffix)];
+        let b = adapter
+            .get(&format!("{}.{}.weight", module, LORA_B))
+            .ok_or_else(|| format!("Adapter has {} for {} but no {}", LORA_A, module, LORA_B))?;
+        let name = format!("{}.weight", module);
+        let base = weights.get(&name).ok_or_else(|| format!("Adapter targets {}, which the model does not have", name))?;
+
+        let merge = || -> candle_core::Result<Tensor> {
+            let delta = (b.to_dtype(DType::F32)?.matmul(&a.to_dtype(DType::F32)?)? * scale)?;
+            (base.to_dtype(DType::F32)? + delta)?.to_dtype(base.dtype())
+        };
+        let merged = merge().map_err(|e| format!("Cannot merge adapter into {}: {}", name, e))?;
+        weights.insert(name, merged);
+    }
+    Ok(modules.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Device;
+    use candle_nn::VarMap;
+
+    fn lora(rank: usize) -> LoraConfig {
+        LoraConfig { rank, alpha: 2.0 * rank as f64, target_modules: vec!["q_proj".to_string()], adapter_path: None }
+    }
+
+    #[test]
+    fn test_fresh_adapter_leaves_output_unchanged() {
+        let (base, adapters) = (VarMap::new(), VarMap::new());
+        let config = lora(2);
+        let builder = AdapterBuilder::new(&config, VarBuilder::from_varmap(&adapters, DType::F32, &Device::Cpu));
+        let base_vb = VarBuilder::from_varmap(&base, DType::F32, &Device::Cpu);
+        let layer = LoraLinear::load(4, 3, "q_proj", base_vb.clone(), Some(&builder)).unwrap();
+        let plain = LoraLinear::load(4, 3, "k_proj", base_vb, Some(&builder)).unwrap();
+
+        assert!(layer.adapter.is_some() && plain.adapter.is_none());
+        assert_eq!(adapters.all_vars().len(), 2);
+        let xs = Tensor::new(&[[1f32, -2.0, 0.5, 3.0]], &Device::Cpu).unwrap();
+        assert_eq!(
+            layer.forward(&xs).unwrap().to_vec2::<f32>().unwrap(),
+            layer.base.forward(&xs).unwrap().to_vec2::<f32>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_merged_weights_match_adapter_output() {
+        let device = Device::Cpu;
+        let w = Tensor::new(&[[1f32, 0.0], [0.0, 1.0]], &device).unwrap();
+        let a = Tensor::new(&[[1f32, 2.0]], &device).unwrap();
+        let b = Tensor::new(&[[0.5f32], [-1.0]], &device).unwrap();
+        let mut weights = HashMap::from([("layer.q_proj.weight".to_string(), w.clone())]);
+        let adapter = HashMap::from([
+            ("layer.q_proj.lora_A.weight".to_string(), a.clone()),
+            ("layer.q_proj.lora_B.weight".to_string(), b.clone()),
+        ]);
+
+        assert_eq!(merge_adapter(&mut weights, &adapter, 2.0), Ok(1));
+        let layer = LoraLinear { base: Linear::new(w, None), adapter: Some((Linear::new(a, None), Linear::new(b, None))), scale: 2.0 };
+        let xs = Tensor::new(&[[3f32, -1.0]], &device).unwrap();
+        let merged = Linear::new(weights["layer.q_proj.weight"].clone(), None);
+        assert_eq!(
+            merged.forward(&xs).unwrap().to_vec2::<f32>().unwrap(),
+            layer.forward(&xs).unwrap().to_vec2::<f32>().unwrap()
+        );
+
+        let orphan = HashMap::from([("layer.v_proj.lora_A.weight".to_string(), Tensor::zeros((1, 2), DType::F32, &device).unwrap())]);
+        assert!(merge_adapter(&mut weights, &orphan, 1.0).is_err());
+    }
+}
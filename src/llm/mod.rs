@@ -0,0 +1,21 @@
+pub mod arch;
+pub mod batch;
+pub mod error;
+pub mod loader;
+pub mod lora;
+pub mod model;
+pub mod prefix;
+pub mod repetition;
+pub mod sampling;
+#[cfg(feature = "api")]
+pub mod server;
+pub mod train;
+
+pub use arch::{ModelSpec, TokenEncoding};
+pub use batch::{BatchGenerator, BatchQueue, BatchRequest};
+pub use error::LlmError;
+pub use model::{token_stream, DecodeMode, FinishReason, GenerationOutput, LightLLM, DistributedTrainer, ModelLock, TokenReceiver, Truncation};
+pub use lora::{AdapterBuilder, LoraLinear};
+pub use repetition::{RepetitionConfig, RepetitionGuard};
+pub use sampling::{SamplingParams, StopMatcher};
+pub use train::{AdamW, AdamWConfig, CausalLm};
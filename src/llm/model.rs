@@ -1,121 +1,1292 @@
-use candle_core::{Device, Tensor};
-use candle_transformers::models::llama::{Config, Llama};
-use candle_nn::VarBuilder;
+use candle_core::backprop::GradStore;
+use candle_core::{DType, Device, Tensor, Var};
+use candle_nn::{VarBuilder, VarMap};
+use candle_transformers::generation::LogitsProcessor;
+use candle_transformers::models::llama::{Cache, Llama};
+use candle_transformers::models::quantized_llama::ModelWeights;
+use futures::stream::{self, Stream};
 use tokenizers::Tokenizer;
+use tokio::sync::{mpsc, Mutex};
+use log::{info, warn};
+use std::collections::BTreeMap;
+use solana_sdk::hash::hash;
+use solana_sdk::pubkey::Pubkey;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 
-const MODEL_VERSION: &str = "2.0.1";
-const MODEL_RELEASE_DATE: &str = "2023-12";
-const MODEL_CONTEXT_LENGTH: usize = 4096;
+use crate::llm::arch::ModelSpec;
+use crate::llm::batch::{BatchGenerator, BatchRequest};
+use crate::llm::error::LlmError;
+use crate::llm::loader;
+use crate::llm::lora::{self, AdapterBuilder};
+use crate::llm::prefix::{prefix_boundary, PrefixCache, DEFAULT_PREFIX_CACHE_ENTRIES};
+use crate::llm::repetition::{RepetitionConfig, RepetitionGuard};
+use crate::llm::sampling::{
+    apply_repetition_penalty, apply_top_k, find_stop, SamplingParams, StopMatcher, REPETITION_PENALTY_WINDOW,
+};
+use crate::llm::train::{self, AdamW, AdamWConfig, CausalLm};
+use crate::node::config::{LLMConfig, LoraConfig, ModelArch, Quantization};
+use crate::node::training::GradientTransport;
+
+pub use crate::node::message::Truncation;
+
+const STREAM_CHANNEL_CAPACITY: usize = 64;
+
+pub type TokenReceiver = mpsc::Receiver<Result<String, String>>;
+
+/// Adapts a token channel to a `Stream` that ends when the generator does.
+pub fn token_stream(rx: TokenReceiver) -> impl Stream<Item = Result<String, String>> + Send + Unpin {
+    Box::pin(stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeMode {
+    Strict,
+    #[default]
+    Lossy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FinishReason {
+    #[default]
+    EndOfSequence,
+    MaxTokens,
+    /// The running sequence reached the model context; the output is partial.
+    ContextExhausted,
+    /// The consumer stopped generation, e.g. a dropped stream or a repetition loop.
+    Interrupted,
+    /// The output reached one of the request's stop sequences, which is cut off.
+    StopSequence,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerationOutput {
+    pub text: String,
+    pub decode_mode: DecodeMode,
+    pub replaced_invalid_utf8: bool,
+    pub finish_reason: FinishReason,
+}
+
+pub fn token_piece_bytes(piece: &str, out: &mut Vec<u8>) {
+    if piece.len() == 6 && piece.starts_with("<0x") && piece.ends_with('>') {
+        if let Ok(byte) = u8::from_str_radix(&piece[3..5], 16) {
+            out.push(byte);
+            return;
+        }
+    }
+    out.extend_from_slice(piece.replace('\u{2581}', " ").as_bytes());
+}
+
+pub fn decode_bytes(bytes: &[u8], mode: DecodeMode) -> Result<GenerationOutput, LlmError> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok(GenerationOutput {
+            text: text.to_string(),
+            decode_mode: mode,
+            replaced_invalid_utf8: false,
+            finish_reason: FinishReason::default(),
+        }),
+        Err(e) => match mode {
+            DecodeMode::Strict => Err(LlmError::InvalidUtf8 { valid_up_to: e.valid_up_to() }),
+            DecodeMode::Lossy => Ok(GenerationOutput {
+                text: String::from_utf8_lossy(bytes).into_owned(),
+                decode_mode: mode,
+                replaced_invalid_utf8: true,
+                finish_reason: FinishReason::default(),
+            }),
+        },
+    }
+}
+
+/// Number of tokens to generate for a `prompt_len`-token prompt. Zero is
+/// rejected; requests that would run past `context_length` are clamped to
+/// the space left in the window.
+pub fn token_budget(prompt_len: usize, max_tokens: usize, context_length: usize) -> Result<usize, String> {
+    if max_tokens == 0 {
+        return Err("max_tokens must be at least 1".to_string());
+    }
+
+    let available = context_length.saturating_sub(prompt_len);
+    if available == 0 {
+        return Err(format!(
+            "Prompt of {} tokens leaves no room in the {} token context window",
+            prompt_len, context_length
+        ));
+    }
+    if max_tokens > available {
+        warn!("max_tokens {} exceeds the {} tokens left in the context window, clamping", max_tokens, available);
+    }
+    Ok(max_tokens.min(available))
+}
+
+/// Fits a tokenized prompt and `max_tokens` of output into `context_length`,
+/// returning the prompt to run and the number of tokens to generate. Unless
+/// `truncation` is `Clamp`, an over-long prompt is cut so that
+/// `tokens.len() + max_tokens <= context_length`. A leading `bos` token is
+/// never cut.
+pub fn fit_prompt(
+    mut tokens: Vec<u32>,
+    max_tokens: usize,
+    context_length: usize,
+    truncation: Truncation,
+    bos: Option<u32>,
+) -> Result<(Vec<u32>, usize), String> {
+    if truncation == Truncation::Clamp || tokens.len().saturating_add(max_tokens) <= context_length {
+        let budget = token_budget(tokens.len(), max_tokens, context_length)?;
+        return Ok((tokens, budget));
+    }
+    if max_tokens == 0 {
+        return Err("max_tokens must be at least 1".to_string());
+    }
+
+    // At least one prompt token besides the BOS has to survive.
+    let pinned = usize::from(bos.is_some() && tokens.first() == bos.as_ref());
+    let budget = max_tokens.min(context_length.saturating_sub(1 + pinned));
+    if budget == 0 {
+        return Err(format!("A {} token context window leaves no room to generate", context_length));
+    }
+    let keep = context_length - budget;
+    let excess = tokens.len().saturating_sub(keep);
+    match truncation {
+        Truncation::Head => {
+            tokens.drain(pinned..pinned + excess);
+        }
+        Truncation::Tail => tokens.truncate(keep),
+        Truncation::Middle => {
+            let start = (keep / 2).max(pinned);
+            tokens.drain(start..start + excess);
+        }
+        Truncation::Clamp => unreachable!("handled above"),
+    }
+    warn!("Dropped {} prompt tokens ({:?}) to leave room for {} output tokens", excess, truncation, budget);
+    Ok((tokens, budget))
+}
+
+/// Autoregressive loop for a single sequence, as streamed by
+/// `generate_stream`. Stops before the running sequence would exceed
+/// `context_length` instead of handing the model an over-length input.
+/// `emit` returns false to stop early.
+pub fn decode_loop<S, E>(
+    mut tokens: Vec<u32>,
+    max_tokens: usize,
+    context_length: usize,
+    eos_token: Option<u32>,
+    mut sample: S,
+    mut emit: E,
+) -> Result<FinishReason, LlmError>
+where
+    S: FnMut(&[u32]) -> Result<u32, LlmError>,
+    E: FnMut(u32) -> bool,
+{
+    for _ in 0..max_tokens {
+        if tokens.len() >= context_length {
+            return Ok(FinishReason::ContextExhausted);
+        }
+
+        let next = sample(&tokens)?;
+        if Some(next) == eos_token {
+            return Ok(FinishReason::EndOfSequence);
+        }
+        tokens.push(next);
+
+        if !emit(next) {
+            return Ok(FinishReason::Interrupted);
+        }
+    }
+
+    Ok(FinishReason::MaxTokens)
+}
+
+/// `decode_loop` over several sequences of equal length. Each step hands every
+/// unfinished sequence to `sample` at once, along with its row index, so the
+/// model runs one forward pass per step for the whole batch. A sequence that
+/// finishes drops out; `emit` receives the row and its new token.
+pub fn decode_batch_loop<S, E>(
+    mut sequences: Vec<Vec<u32>>,
+    budgets: &[usize],
+    context_length: usize,
+    eos_token: Option<u32>,
+    mut sample: S,
+    mut emit: E,
+) -> Result<Vec<FinishReason>, LlmError>
+where
+    S: FnMut(&[usize], &[&[u32]]) -> Result<Vec<u32>, LlmError>,
+    E: FnMut(usize, u32) -> bool,
+{
+    let mut reasons: Vec<Option<FinishReason>> = vec![None; sequences.len()];
+    for step in 0.. {
+        for (row, reason) in reasons.iter_mut().enumerate() {
+            if reason.is_none() {
+                if step >= budgets[row] {
+                    *reason = Some(FinishReason::MaxTokens);
+                } else if sequences[row].len() >= context_length {
+                    *reason = Some(FinishReason::ContextExhausted);
+                }
+            }
+        }
+
+        let active: Vec<usize> = (0..sequences.len()).filter(|&row| reasons[row].is_none()).collect();
+        if active.is_empty() {
+            break;
+        }
+        let inputs: Vec<&[u32]> = active.iter().map(|&row| sequences[row].as_slice()).collect();
+        let next = sample(&active, &inputs)?;
+
+        for (&row, next) in active.iter().zip(next) {
+            if Some(next) == eos_token {
+                reasons[row] = Some(FinishReason::EndOfSequence);
+                continue;
+            }
+            sequences[row].push(next);
+            if !emit(row, next) {
+                reasons[row] = Some(FinishReason::Interrupted);
+            }
+        }
+    }
+
+    Ok(reasons.into_iter().map(|reason| reason.expect("every sequence finishes")).collect())
+}
+
+/// Exclusive access to the model. Each generation, or each group of a batch,
+/// holds the lock for its whole decode loop, so requests do not compete for
+/// device memory with their KV caches. Anything not decoded in the same
+/// group queues behind it.
+#[derive(Debug)]
+pub struct ModelLock<S> {
+    state: Mutex<S>,
+}
+
+impl<S> ModelLock<S> {
+    pub fn new(state: S) -> Self {
+        ModelLock { state: Mutex::new(state) }
+    }
+
+    pub async fn run<T>(&self, f: impl FnOnce(&mut S) -> T) -> T {
+        let mut state = self.state.lock().await;
+        f(&mut state)
+    }
+
+    /// For the blocking pool. Panics if called from an async context.
+    pub fn run_blocking<T>(&self, f: impl FnOnce(&mut S) -> T) -> T {
+        let mut state = self.state.blocking_lock();
+        f(&mut state)
+    }
+}
+
+/// A tokenized prompt fitted to the context window, ready to decode.
+struct PreparedPrompt<'a> {
+    tokens: Vec<u32>,
+    budget: usize,
+    max_tokens: usize,
+    sampling: &'a SamplingParams,
+}
+
+/// KV cache for a group decoding in lockstep, and how many positions it
+/// holds. After the prompt each step feeds one token per row. Rows that have
+/// finished keep feeding their last token so the cache's batch dimension
+/// stays intact; their logits are discarded.
+struct GroupCache {
+    /// `None` for quantized weights, which keep their cache internally.
+    cache: Option<Cache>,
+    cached: usize,
+    last: Vec<u32>,
+}
+
+/// Loaded model weights. GGUF models hold their own KV cache and restart it
+/// whenever a forward pass begins at position 0.
+#[derive(Debug)]
+enum Weights {
+    Full(Llama),
+    Quantized(ModelWeights),
+}
+
+impl Weights {
+    /// Next-token logits after `input`, whose first position is `index_pos`.
+    /// candle's causal mask only spans the new positions and ignores
+    /// `index_pos`, so a multi-token input on top of a cache would attend
+    /// wrongly; it goes through one position at a time instead.
+    fn forward(&mut self, input: &Tensor, index_pos: usize, cache: &mut Option<Cache>) -> candle_core::Result<Tensor> {
+        let (_, width) = input.dims2()?;
+        if index_pos == 0 || width == 1 {
+            return self.forward_positions(input, index_pos, cache);
+        }
+        let mut logits = None;
+        for offset in 0..width {
+            logits = Some(self.forward_positions(&input.narrow(1, offset, 1)?, index_pos + offset, cache)?);
+        }
+        logits.ok_or_else(|| candle_core::Error::Msg("empty forward input".to_string()))
+    }
+
+    fn forward_positions(&mut self, input: &Tensor, index_pos: usize, cache: &mut Option<Cache>) -> candle_core::Result<Tensor> {
+        match (self, cache) {
+            (Weights::Full(model), Some(cache)) => model.forward(input, index_pos, cache),
+            (Weights::Quantized(model), None) => model.forward(input, index_pos),
+            _ => Err(candle_core::Error::Msg("KV cache does not match the loaded weights".to_string())),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct LightLLM {
-    model: Llama,
+    model: ModelLock<Weights>,
     tokenizer: Tokenizer,
     device: Device,
+    /// Activation dtype for full-precision weights: f16 on GPU, f32 on CPU.
+    dtype: DType,
+    format: Quantization,
+    /// Short digest of the merged LoRA adapter, if any.
+    adapter: Option<String>,
+    spec: ModelSpec,
+    prefixes: parking_lot::Mutex<PrefixCache<Cache>>,
     version: String,
+    repetition: Option<RepetitionConfig>,
 }
 
 impl LightLLM {
-    pub fn new(model_path: &Path, tokenizer_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
-        
-        let config = Config::config_7b_v2()?;
-        
-
-        let device = Device::cuda_if_available(0)?;
-        
-        let vb = VarBuilder::from_safetensors(model_path, &device)?;
-        let model = Llama::load(vb, &config)?;
-        
-       
+    /// Loads the model `config` describes, on the GPU only if `use_gpu` is
+    /// set and one is available.
+    pub fn from_config(config: &LLMConfig) -> Result<Self, LlmError> {
+        let device = if config.use_gpu { Device::cuda_if_available(0)? } else { Device::Cpu };
+        Self::new(
+            config.model_arch,
+            config.weights_format(),
+            Path::new(&config.model_path),
+            Path::new(&config.tokenizer_path),
+            config.lora.as_ref(),
+            device,
+        )
+    }
+
+    /// `format` should already be resolved; `Auto` is read as fp16. A LoRA
+    /// config with an `adapter_path` has its adapter merged into the weights.
+    pub fn new(
+        arch: ModelArch,
+        format: Quantization,
+        model_path: &Path,
+        tokenizer_path: &Path,
+        lora: Option<&LoraConfig>,
+        device: Device,
+    ) -> Result<Self, LlmError> {
+        let spec = ModelSpec::new(arch);
+        let dtype = if device.is_cpu() { DType::F32 } else { DType::F16 };
+        let adapter = lora.and_then(|lora| lora.adapter_path.as_ref().map(|path| (lora, Path::new(path))));
+
+        let model = match format {
+            Quantization::Gguf if adapter.is_some() => {
+                return Err(LlmError::Weights("LoRA adapters can only be merged into fp16 weights, not GGUF".to_string()));
+            }
+            Quantization::Gguf => Weights::Quantized(loader::load_gguf(model_path, &device)?),
+            Quantization::Auto | Quantization::Fp16 => {
+                let vb = match adapter {
+                    Some((lora, adapter_path)) => {
+                        let mut tensors = loader::load_tensors(model_path, &device, true)?;
+                        let adapter_tensors = candle_core::safetensors::load(adapter_path, &device)?;
+                        let merged = lora::merge_adapter(&mut tensors, &adapter_tensors, lora.scale()).map_err(LlmError::Weights)?;
+                        info!("Merged LoRA adapter {} into {} projections", adapter_path.display(), merged);
+                        VarBuilder::from_tensors(tensors, dtype, &device)
+                    }
+                    None => loader::var_builder(model_path, dtype, &device)?,
+                };
+                let model = Llama::load(vb, &spec.config).map_err(|e| {
+                    LlmError::Weights(format!("{} does not hold {} weights: {}", model_path.display(), arch.name(), e))
+                })?;
+                Weights::Full(model)
+            }
+        };
+
         let tokenizer = Tokenizer::from_file(tokenizer_path)?;
+        let adapter_digest = match adapter {
+            Some((_, path)) => Some(hash(&std::fs::read(path)?).to_string()[..8].to_string()),
+            None => None,
+        };
 
         Ok(Self {
-            model,
+            model: ModelLock::new(model),
             tokenizer,
             device,
-            version: MODEL_VERSION.to_string(),
+            dtype,
+            format: if matches!(format, Quantization::Gguf) { Quantization::Gguf } else { Quantization::Fp16 },
+            adapter: adapter_digest,
+            version: spec.version.to_string(),
+            spec,
+            prefixes: parking_lot::Mutex::new(PrefixCache::new(DEFAULT_PREFIX_CACHE_ENTRIES)),
+            repetition: None,
         })
     }
 
+    /// Stops streamed generation early once the output falls into an n-gram loop.
+    pub fn with_repetition_guard(mut self, config: RepetitionConfig) -> Self {
+        self.repetition = Some(config);
+        self
+    }
+
     pub fn version(&self) -> &str {
         &self.version
     }
 
+    pub fn arch(&self) -> ModelArch {
+        self.spec.arch
+    }
+
     pub fn context_length(&self) -> usize {
-        MODEL_CONTEXT_LENGTH
+        self.spec.context_length
+    }
+
+    /// Architecture, release and weight format, e.g. `llama2-7b-2.0.1-gguf`,
+    /// plus `-lora-<digest>` with a merged adapter. Nodes reporting the same
+    /// ID should produce the same outputs.
+    pub fn model_id(&self) -> String {
+        let format = match self.format {
+            Quantization::Gguf => "gguf",
+            _ => "fp16",
+        };
+        let id = format!("{}-{}-{}", self.arch().name(), self.version, format);
+        match &self.adapter {
+            Some(digest) => format!("{}-lora-{}", id, digest),
+            None => id,
+        }
     }
 
     pub async fn generate(
         &self,
         prompt: &str,
         max_tokens: usize,
-        temperature: f32,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        
-        if prompt.len() > MODEL_CONTEXT_LENGTH {
-            return Err("Prompt too long for model context window".into());
-        }
-
-       
-        let tokens = self.tokenizer.encode(prompt, true)?;
-        let input_ids = tokens.get_ids();
-        
-       
-        let input_tensor = Tensor::new(input_ids, &self.device)?;
-        
-       
-        let output = self.model.generate(
-            &input_tensor,
-            None,
+        sampling: &SamplingParams,
+        decode_mode: DecodeMode,
+        truncation: Truncation,
+    ) -> Result<GenerationOutput, LlmError> {
+        self.model
+            .run(|model| self.generate_with(model, prompt, max_tokens, sampling, decode_mode, truncation))
+            .await
+    }
+
+    fn generate_with(
+        &self,
+        model: &mut Weights,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        decode_mode: DecodeMode,
+        truncation: Truncation,
+    ) -> Result<GenerationOutput, LlmError> {
+        let prepared = self.prepare(prompt, max_tokens, sampling, truncation)?;
+        let mut outputs = self.generate_group(model, vec![prepared], decode_mode)?;
+        Ok(outputs.remove(0))
+    }
+
+    fn prepare<'a>(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: &'a SamplingParams,
+        truncation: Truncation,
+    ) -> Result<PreparedPrompt<'a>, LlmError> {
+        let (tokens, budget) = self.fit_request(prompt, max_tokens, sampling, truncation)?;
+        Ok(PreparedPrompt { tokens, budget, max_tokens, sampling })
+    }
+
+    /// Decodes prompts of equal token length together, one batched forward
+    /// pass per step. Each keeps its own sampler, budget and stop sequences.
+    fn generate_group(
+        &self,
+        model: &mut Weights,
+        group: Vec<PreparedPrompt>,
+        decode_mode: DecodeMode,
+    ) -> Result<Vec<GenerationOutput>, LlmError> {
+        let eos_token = self.tokenizer.token_to_id(self.spec.eos_token);
+        let mut processors: Vec<_> = group.iter().map(|prompt| logits_processor(prompt.sampling)).collect();
+        let mut stops: Vec<_> = group.iter().map(|prompt| StopMatcher::new(&prompt.sampling.stop)).collect();
+        let mut pending = vec![Vec::new(); group.len()];
+        let mut stopped = vec![false; group.len()];
+        let mut generated = vec![Vec::new(); group.len()];
+        let budgets: Vec<usize> = group.iter().map(|prompt| prompt.budget).collect();
+        let prompts: Vec<&[u32]> = group.iter().map(|prompt| prompt.tokens.as_slice()).collect();
+        let mut cache = self.group_cache(model, &prompts)?;
+
+        let reasons = decode_batch_loop(
+            group.iter().map(|prompt| prompt.tokens.clone()).collect(),
+            &budgets,
+            self.context_length(),
+            eos_token,
+            |rows, sequences| {
+                let logits = self.forward_group(model, &mut cache, rows, sequences)?;
+                rows.iter()
+                    .zip(sequences)
+                    .zip(logits)
+                    .map(|((&row, sequence), logits)| {
+                        pick_token(sequence, logits, group[row].sampling, &mut processors[row])
+                    })
+                    .collect()
+            },
+            |row, next| {
+                generated[row].push(next);
+                stopped[row] = self.push_token(&mut stops[row], &mut pending[row], next).1;
+                !stopped[row]
+            },
+        )?;
+
+        group
+            .iter()
+            .zip(reasons)
+            .enumerate()
+            .map(|(row, (prompt, mut finish_reason))| {
+                if stopped[row] {
+                    finish_reason = FinishReason::StopSequence;
+                }
+                // A clamped budget that runs out means the window filled up first.
+                if finish_reason == FinishReason::MaxTokens && prompt.budget < prompt.max_tokens {
+                    finish_reason = FinishReason::ContextExhausted;
+                }
+                if finish_reason == FinishReason::ContextExhausted {
+                    warn!("Context window exhausted after {} generated tokens", generated[row].len());
+                }
+
+                let mut output = self.decode_tokens(&generated[row], decode_mode)?;
+                if let Some(pos) = find_stop(&output.text, &prompt.sampling.stop) {
+                    output.text.truncate(pos);
+                }
+                output.finish_reason = finish_reason;
+                Ok(output)
+            })
+            .collect()
+    }
+
+    pub fn generate_stream(
+        self: Arc<Self>,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: SamplingParams,
+        truncation: Truncation,
+    ) -> Result<TokenReceiver, LlmError> {
+        let (tokens, budget) = self.fit_request(prompt, max_tokens, &sampling, truncation)?;
+        let (tx, rx) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+
+        tokio::task::spawn_blocking(move || {
+            let streamed = self
+                .model
+                .run_blocking(|model| self.stream_tokens(model, tokens, budget, &sampling, &tx));
+            if let Err(e) = streamed {
+                let _ = tx.blocking_send(Err(e.to_string()));
+            }
+        });
+
+        Ok(rx)
+    }
+
+    /// Checks `sampling` and fits the tokenized prompt to the context window,
+    /// returning the tokens to run and how many to generate.
+    fn fit_request(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        truncation: Truncation,
+    ) -> Result<(Vec<u32>, usize), LlmError> {
+        sampling.validate().map_err(LlmError::Request)?;
+        let bos = self.tokenizer.token_to_id(self.spec.bos_token);
+        fit_prompt(self.encode_prompt(prompt)?, max_tokens, self.context_length(), truncation, bos).map_err(LlmError::Request)
+    }
+
+    fn encode_prompt(&self, prompt: &str) -> Result<Vec<u32>, LlmError> {
+        Ok(self.tokenizer.encode(prompt, true)?.get_ids().to_vec())
+    }
+
+    /// A fresh KV cache for `prompts`. A lone prompt instead resumes from the
+    /// longest prefix cached for an earlier one, then prefills up to its own
+    /// block boundary and leaves a snapshot there for the next prompt that
+    /// starts the same way, such as one sharing a system prompt. Quantized
+    /// weights cannot hand out their cache, so they always start fresh.
+    fn group_cache(&self, model: &Weights, prompts: &[&[u32]]) -> Result<GroupCache, LlmError> {
+        let last = prompts.iter().map(|prompt| prompt.last().copied().unwrap_or_default()).collect();
+        let (Weights::Full(model), [tokens]) = (model, prompts) else {
+            return Ok(GroupCache { cache: self.fresh_cache(model)?, cached: 0, last });
+        };
+
+        let (mut cached, mut cache) = match self.prefixes.lock().lookup(tokens) {
+            Some(found) => found,
+            None => (0, Cache::new(true, self.dtype, &self.spec.config, &self.device)?),
+        };
+        let boundary = prefix_boundary(tokens.len());
+        if boundary > cached {
+            let input = Tensor::new(&tokens[cached..boundary], &self.device)?.unsqueeze(0)?;
+            model.forward(&input, cached, &mut cache)?;
+            cached = boundary;
+            self.prefixes.lock().insert(tokens[..boundary].to_vec(), cache.clone());
+        }
+        Ok(GroupCache { cache: Some(cache), cached, last })
+    }
+
+    fn fresh_cache(&self, model: &Weights) -> Result<Option<Cache>, LlmError> {
+        match model {
+            Weights::Full(_) => Ok(Some(Cache::new(true, self.dtype, &self.spec.config, &self.device)?)),
+            Weights::Quantized(_) => Ok(None),
+        }
+    }
+
+    /// Next-token logits for `rows`, whose `sequences` share a length. Only
+    /// the positions the cache does not hold yet go through the model.
+    fn forward_group(
+        &self,
+        model: &mut Weights,
+        group: &mut GroupCache,
+        rows: &[usize],
+        sequences: &[&[u32]],
+    ) -> Result<Vec<Vec<f32>>, LlmError> {
+        let width = sequences[0].len() - group.cached;
+        let mut inputs: Vec<Vec<u32>> = group.last.iter().map(|&token| vec![token; width]).collect();
+        for (&row, sequence) in rows.iter().zip(sequences) {
+            inputs[row] = sequence[group.cached..].to_vec();
+            group.last[row] = sequence.last().copied().unwrap_or_default();
+        }
+
+        let input = Tensor::new(inputs.concat(), &self.device)?.reshape((inputs.len(), width))?;
+        let mut logits = model
+            .forward(&input, group.cached, &mut group.cache)?
+            .to_dtype(DType::F32)?
+            .to_vec2::<f32>()?;
+        group.cached += width;
+        Ok(rows.iter().map(|&row| std::mem::take(&mut logits[row])).collect())
+    }
+
+    /// Feeds a generated token to `stops`, returning the text it releases and
+    /// whether a stop sequence was reached.
+    fn push_token(&self, stops: &mut StopMatcher, pending: &mut Vec<u8>, next: u32) -> (String, bool) {
+        if let Some(piece) = self.tokenizer.id_to_token(next) {
+            self.spec.encoding.piece_bytes(&piece, pending);
+        }
+        match std::str::from_utf8(pending) {
+            Ok(text) if !text.is_empty() => {
+                let released = stops.push(text);
+                pending.clear();
+                released
+            }
+            _ => (String::new(), false),
+        }
+    }
+
+    fn stream_tokens(
+        &self,
+        model: &mut Weights,
+        tokens: Vec<u32>,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        tx: &mpsc::Sender<Result<String, String>>,
+    ) -> Result<(), LlmError> {
+        let eos_token = self.tokenizer.token_to_id(self.spec.eos_token);
+        let mut logits_processor = logits_processor(sampling);
+        let mut stops = StopMatcher::new(&sampling.stop);
+        let mut stopped = false;
+        let mut pending = Vec::new();
+        let mut guard = self.repetition.map(RepetitionGuard::new);
+        let mut generated = 0;
+        let mut cache = self.group_cache(model, &[tokens.as_slice()])?;
+
+        let finish_reason = decode_loop(
+            tokens,
             max_tokens,
-            Some(temperature),
-            None,
+            self.context_length(),
+            eos_token,
+            |sequence| {
+                let logits = self.forward_group(model, &mut cache, &[0], &[sequence])?.remove(0);
+                pick_token(sequence, logits, sampling, &mut logits_processor)
+            },
+            |next| {
+                generated += 1;
+                if let Some(ngram) = guard.as_mut().and_then(|g| g.push(next)) {
+                    warn!("Stopping generation, output is repeating a {}-token sequence", ngram);
+                    let _ = tx.blocking_send(Err(format!("Generation stopped: repeated {}-token loop detected", ngram)));
+                    return false;
+                }
+
+                let (released, reached_stop) = self.push_token(&mut stops, &mut pending, next);
+                stopped = reached_stop;
+                if !released.is_empty() && tx.blocking_send(Ok(released)).is_err() {
+                    return false;
+                }
+                !stopped
+            },
         )?;
-        
-      
-        let output_ids: Vec<u32> = output.to_vec1()?;
-        let decoded = self.tokenizer.decode(&output_ids, true)?;
-        
-        Ok(decoded)
+
+        // Text held back as a possible stop sequence turned out not to be one.
+        if !stopped && finish_reason != FinishReason::Interrupted {
+            let held = stops.finish();
+            if !held.is_empty() {
+                let _ = tx.blocking_send(Ok(held));
+            }
+        }
+        if finish_reason == FinishReason::ContextExhausted {
+            warn!("Context window exhausted after {} streamed tokens", generated);
+            let _ = tx.blocking_send(Err(format!(
+                "Context exhausted: output truncated after {} tokens",
+                generated
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn decode_tokens(
+        &self,
+        ids: &[u32],
+        mode: DecodeMode,
+    ) -> Result<GenerationOutput, LlmError> {
+        let mut bytes = Vec::new();
+        for id in ids {
+            if let Some(piece) = self.tokenizer.id_to_token(*id) {
+                if self.tokenizer.get_added_vocabulary().is_special_token(&piece) {
+                    continue;
+                }
+                self.spec.encoding.piece_bytes(&piece, &mut bytes);
+            }
+        }
+
+        decode_bytes(&bytes, mode)
+    }
+}
+
+
+fn logits_processor(sampling: &SamplingParams) -> LogitsProcessor {
+    LogitsProcessor::new(sampling.seed, Some(sampling.temperature as f64), sampling.top_p)
+}
+
+/// Applies the repetition penalty and top-k to `logits`, then samples;
+/// temperature and top-p are left to the processor.
+fn pick_token(
+    sequence: &[u32],
+    mut logits: Vec<f32>,
+    sampling: &SamplingParams,
+    logits_processor: &mut LogitsProcessor,
+) -> Result<u32, LlmError> {
+    let recent = &sequence[sequence.len().saturating_sub(REPETITION_PENALTY_WINDOW)..];
+    apply_repetition_penalty(&mut logits, recent, sampling.repetition_penalty);
+    if let Some(top_k) = sampling.top_k {
+        apply_top_k(&mut logits, top_k);
     }
+    Ok(logits_processor.sample(&Tensor::new(logits.as_slice(), &Device::Cpu)?)?)
 }
 
+impl BatchGenerator for LightLLM {
+    // Length bucketing only: prompts that tokenize to exactly the same length
+    // decode together, one forward pass per step, and groups of different
+    // lengths take the model lock in turn. Nothing is padded or masked.
+    fn generate_batch(&self, requests: &[BatchRequest]) -> Vec<Result<String, String>> {
+        let mut results = vec![None; requests.len()];
+        let mut groups: BTreeMap<usize, Vec<(usize, PreparedPrompt)>> = BTreeMap::new();
+        for (index, request) in requests.iter().enumerate() {
+            match self.prepare(&request.prompt, request.max_tokens, &request.sampling, request.truncation) {
+                Ok(prepared) => groups.entry(prepared.tokens.len()).or_default().push((index, prepared)),
+                Err(e) => results[index] = Some(Err(e.to_string())),
+            }
+        }
+
+        for group in groups.into_values() {
+            let (indices, prompts): (Vec<_>, Vec<_>) = group.into_iter().unzip();
+            let outputs = self
+                .model
+                .run_blocking(|model| self.generate_group(model, prompts, DecodeMode::Lossy))
+                .map_err(|e| e.to_string());
+            match outputs {
+                Ok(outputs) => {
+                    for (index, output) in indices.into_iter().zip(outputs) {
+                        results[index] = Some(Ok(output.text));
+                    }
+                }
+                Err(e) => {
+                    for index in indices {
+                        results[index] = Some(Err(e.clone()));
+                    }
+                }
+            }
+        }
 
+        results
+            .into_iter()
+            .map(|result| result.expect("every request is answered"))
+            .collect()
+    }
+}
+
+/// Fine-tunes full-precision weights on text batches. Trained weights are
+/// kept as f32 variables on `device` so gradients and optimizer updates stay
+/// exact; quantized GGUF weights cannot be trained. With a LoRA config only
+/// the adapters are trained and the base weights stay frozen.
+///
+/// With a transport attached, each step's gradients are averaged with those
+/// of `peers`, weighted by stake, before the optimizer runs. A peer whose
+/// gradients miss a round's deadline is left out of that round, so replicas
+/// that start from the same weights can still drift apart.
 pub struct DistributedTrainer {
-    model: LightLLM,
-    peers: Vec<String>,
+    model: CausalLm,
+    weights: VarMap,
+    /// Shared with the blocking task that applies each update.
+    optimizer: Arc<parking_lot::Mutex<AdamW>>,
+    tokenizer: Tokenizer,
+    spec: ModelSpec,
+    device: Device,
+    lora: Option<LoraConfig>,
+    peers: Vec<Pubkey>,
+    transport: Option<Arc<dyn GradientTransport>>,
     batch_size: usize,
 }
 
+/// Runs a stretch of training work, which ties up a thread for seconds, off
+/// the async runtime.
+async fn on_blocking_pool<T, F>(work: F) -> Result<T, LlmError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, LlmError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+}
+
 impl DistributedTrainer {
+    /// `peers` are the base58 pubkeys of the other trainers. A LoRA config
+    /// with an `adapter_path` resumes training that adapter.
     pub fn new(
-        model: LightLLM,
+        arch: ModelArch,
+        model_path: &Path,
+        tokenizer_path: &Path,
+        lora: Option<LoraConfig>,
+        device: Device,
         peers: Vec<String>,
         batch_size: usize,
-    ) -> Self {
-        Self {
+    ) -> Result<Self, LlmError> {
+        let peers = peers
+            .iter()
+            .map(|peer| Pubkey::from_str(peer).map_err(|e| LlmError::TrainerPubkey(peer.clone(), e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let spec = ModelSpec::new(arch);
+        let mut weights = VarMap::new();
+        let model = match &lora {
+            Some(lora) => {
+                let base = loader::var_builder(model_path, DType::F32, &device)?;
+                let adapters = AdapterBuilder::new(lora, VarBuilder::from_varmap(&weights, DType::F32, &device));
+                CausalLm::load_with_lora(base, adapters, &spec.config, spec.context_length)
+            }
+            None => {
+                {
+                    let mut vars = weights.data().lock().map_err(|_| LlmError::Poisoned)?;
+                    for (name, tensor) in loader::load_tensors(model_path, &device, true)? {
+                        vars.insert(name, Var::from_tensor(&tensor.to_dtype(DType::F32)?)?);
+                    }
+                }
+                CausalLm::load(VarBuilder::from_varmap(&weights, DType::F32, &device), &spec.config, spec.context_length)
+            }
+        }
+        .map_err(|e| LlmError::Weights(format!("{} does not hold {} weights: {}", model_path.display(), arch.name(), e)))?;
+        if let Some(adapter_path) = lora.as_ref().and_then(|lora| lora.adapter_path.as_ref()) {
+            weights.load(adapter_path)?;
+        }
+        let optimizer = AdamW::new(&weights, AdamWConfig::default())?;
+
+        Ok(Self {
             model,
+            weights,
+            optimizer: Arc::new(parking_lot::Mutex::new(optimizer)),
+            tokenizer: Tokenizer::from_file(tokenizer_path)?,
+            spec,
+            device,
+            lora,
             peers,
-            batch_size,
-        }
+            transport: None,
+            batch_size: batch_size.max(1),
+        })
+    }
+
+    /// Replaces the optimizer, dropping any state it had built up.
+    pub fn with_optimizer(mut self, config: AdamWConfig) -> Result<Self, LlmError> {
+        self.optimizer = Arc::new(parking_lot::Mutex::new(AdamW::new(&self.weights, config)?));
+        Ok(self)
     }
 
     pub fn model_info(&self) -> String {
         format!(
-            "LLaMA-2 7B v{} (Released: {})\nContext Length: {} tokens",
-            MODEL_VERSION,
-            MODEL_RELEASE_DATE,
-            MODEL_CONTEXT_LENGTH
+            "{} v{} (Released: {})\nContext Length: {} tokens",
+            self.spec.display_name,
+            self.spec.version,
+            self.spec.release_date,
+            self.spec.context_length
         )
     }
 
+    /// Averages every step's gradients with `peers` over `transport`,
+    /// usually the node, which from then on takes gradients from `peers`
+    /// alone.
+    pub fn with_transport(mut self, transport: Arc<dyn GradientTransport>) -> Self {
+        transport.register_trainers(&self.peers);
+        self.transport = Some(transport);
+        self
+    }
+
+    pub fn peers(&self) -> &[Pubkey] {
+        &self.peers
+    }
+
+    /// Optimizer steps taken, including those restored from a checkpoint.
+    pub fn steps(&self) -> u64 {
+        self.optimizer.lock().steps()
+    }
+
+    /// Runs one optimizer step on `batch` and returns its mean next-token
+    /// cross-entropy. Texts are split into micro-batches of `batch_size`
+    /// whose gradients are summed, weighted by their token counts. The loss
+    /// is this node's own; the update is the round's average. The passes
+    /// and the update run on the blocking pool.
     pub async fn train_step(
         &mut self,
         batch: Vec<String>,
-    ) -> Result<f32, Box<dyn std::error::Error>> {
-       
-        
-        Ok(0.0) 
+    ) -> Result<f32, LlmError> {
+        let eos_token = self.tokenizer.token_to_id(self.spec.eos_token);
+        let mut sequences = Vec::with_capacity(batch.len());
+        for text in &batch {
+            let mut tokens = self.tokenizer.encode(text.as_str(), true)?.get_ids().to_vec();
+            tokens.extend(eos_token);
+            tokens.truncate(self.spec.context_length + 1);
+            if tokens.len() >= 2 {
+                sequences.push(tokens);
+            }
+        }
+        let total_tokens: usize = sequences.iter().map(|s| s.len() - 1).sum();
+        if total_tokens == 0 {
+            return Err(LlmError::EmptyBatch);
+        }
+
+        let (model, weights, device) = (self.model.clone(), self.weights.clone(), self.device.clone());
+        let (batch_size, shared) = (self.batch_size, self.transport.is_some());
+        let (grads, loss_sum, local) = on_blocking_pool(move || {
+            let vars = weights.all_vars();
+            let mut grads: Option<GradStore> = None;
+            let mut loss_sum = 0.0;
+            for chunk in sequences.chunks(batch_size) {
+                let (inputs, targets, mask) = train::next_token_batch(chunk, &device)?;
+                let logits = model.forward(&inputs)?;
+                let weight = chunk.iter().map(|s| s.len() - 1).sum::<usize>() as f64 / total_tokens as f64;
+                let loss = (train::next_token_loss(&logits, &targets, &mask)? * weight)?;
+                loss_sum += loss.to_scalar::<f32>()?;
+
+                let chunk_grads = loss.backward()?;
+                match grads.as_mut() {
+                    Some(total) => train::accumulate_grads(total, &chunk_grads, &vars)?,
+                    None => grads = Some(chunk_grads),
+                }
+            }
+            let grads = grads.expect("at least one micro-batch");
+            let local = if shared { Some(train::gradients_of(&weights, &grads)?) } else { None };
+            Ok((grads, loss_sum, local))
+        })
+        .await?;
+
+        let averaged = match (&self.transport, local) {
+            (Some(transport), Some(local)) => Some(transport.all_reduce(self.steps() + 1, local, &self.peers).await?),
+            _ => None,
+        };
+        let (weights, device, optimizer) = (self.weights.clone(), self.device.clone(), Arc::clone(&self.optimizer));
+        on_blocking_pool(move || {
+            let mut grads = grads;
+            if let Some(averaged) = averaged {
+                train::replace_grads(&weights, &mut grads, averaged, &device)?;
+            }
+            optimizer.lock().step(&grads)?;
+            Ok(())
+        })
+        .await?;
+        Ok(loss_sum)
+    }
+
+    /// Trained weights file within a checkpoint: the adapter alone when
+    /// training LoRA, ready to set as `llm.lora.adapter_path`.
+    fn weights_file(&self) -> &'static str {
+        if self.lora.is_some() {
+            train::ADAPTER_CHECKPOINT_FILE
+        } else {
+            train::WEIGHTS_CHECKPOINT_FILE
+        }
+    }
+
+    /// Writes the trained weights and optimizer state into `dir`.
+    pub fn save_checkpoint(&self, dir: &Path) -> Result<(), LlmError> {
+        std::fs::create_dir_all(dir)?;
+        self.weights.save(dir.join(self.weights_file()))?;
+        self.optimizer.lock().save(&dir.join(train::OPTIMIZER_CHECKPOINT_FILE))?;
+        Ok(())
+    }
+
+    /// Resumes from a checkpoint written by `save_checkpoint` for the same model.
+    pub fn load_checkpoint(&mut self, dir: &Path) -> Result<(), LlmError> {
+        self.weights.load(dir.join(self.weights_file()))?;
+        self.optimizer.lock().load(&dir.join(train::OPTIMIZER_CHECKPOINT_FILE))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pieces_to_bytes(pieces: &[&str]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for piece in pieces {
+            token_piece_bytes(piece, &mut bytes);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_byte_fallback_pieces() {
+        let bytes = pieces_to_bytes(&["\u{2581}price", "<0xE2>", "<0x82>", "<0xAC>"]);
+        let output = decode_bytes(&bytes, DecodeMode::Strict).unwrap();
+        assert_eq!(output.text, " price\u{20AC}");
+        assert!(!output.replaced_invalid_utf8);
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_invalid_utf8() {
+        let bytes = pieces_to_bytes(&["\u{2581}price", "<0xE2>", "<0x82>"]);
+        assert!(matches!(decode_bytes(&bytes, DecodeMode::Strict), Err(LlmError::InvalidUtf8 { valid_up_to: 6 })));
+    }
+
+    #[test]
+    fn test_lossy_mode_replaces_invalid_utf8() {
+        let bytes = pieces_to_bytes(&["\u{2581}price", "<0xE2>", "<0x82>"]);
+        let output = decode_bytes(&bytes, DecodeMode::Lossy).unwrap();
+        assert_eq!(output.text, " price\u{FFFD}");
+        assert_eq!(output.decode_mode, DecodeMode::Lossy);
+        assert!(output.replaced_invalid_utf8);
+    }
+
+    #[test]
+    fn test_context_exhausted_stops_cleanly_with_partial_output() {
+        let context_length = 32;
+        let prompt: Vec<u32> = (0..context_length as u32 - 3).collect();
+        let mut emitted = Vec::new();
+        let mut longest_input = 0;
+
+        let reason = decode_loop(
+            prompt,
+            1_000,
+            context_length,
+            None,
+            |sequence| {
+                longest_input = longest_input.max(sequence.len());
+                Ok(7)
+            },
+            |next| {
+                emitted.push(next);
+                true
+            },
+        )
+        .unwrap();
+
+        assert_eq!(reason, FinishReason::ContextExhausted);
+        assert_eq!(emitted, vec![7, 7, 7]);
+        assert!(longest_input < context_length);
+    }
+
+    #[test]
+    fn test_decode_loop_finish_reasons() {
+        let eos = decode_loop(vec![1], 10, 32, Some(2), |_| Ok(2), |_| true).unwrap();
+        assert_eq!(eos, FinishReason::EndOfSequence);
+
+        let length = decode_loop(vec![1], 4, 32, Some(2), |_| Ok(5), |_| true).unwrap();
+        assert_eq!(length, FinishReason::MaxTokens);
+
+        let interrupted = decode_loop(vec![1], 4, 32, None, |_| Ok(5), |_| false).unwrap();
+        assert_eq!(interrupted, FinishReason::Interrupted);
+    }
+
+    #[tokio::test]
+    async fn test_token_stream_yields_until_sender_drops() {
+        use futures::StreamExt;
+
+        let (tx, rx) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            for piece in ["Hel", "lo"] {
+                tx.send(Ok(piece.to_string())).await.unwrap();
+            }
+            tx.send(Err("Context exhausted".to_string())).await.unwrap();
+        });
+
+        let items: Vec<_> = token_stream(rx).collect().await;
+        assert_eq!(
+            items,
+            vec![Ok("Hel".to_string()), Ok("lo".to_string()), Err("Context exhausted".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_batch_loop_runs_active_rows_together() {
+        let mut steps = Vec::new();
+        let mut emitted = vec![Vec::new(); 3];
+        let reasons = decode_batch_loop(
+            vec![vec![1, 2], vec![3, 4], vec![5, 6]],
+            &[1, 4, 4],
+            32,
+            Some(0),
+            |rows, sequences| {
+                assert!(sequences.iter().all(|sequence| sequence.len() == sequences[0].len()));
+                steps.push(rows.to_vec());
+                // Row 2 hits end of sequence on its second token.
+                Ok(rows.iter().map(|&row| if row == 2 && sequences[0].len() == 3 { 0 } else { 9 }).collect())
+            },
+            |row, next| {
+                emitted[row].push(next);
+                true
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            reasons,
+            vec![FinishReason::MaxTokens, FinishReason::MaxTokens, FinishReason::EndOfSequence]
+        );
+        assert_eq!(steps, vec![vec![0, 1, 2], vec![1, 2], vec![1], vec![1]]);
+        assert_eq!(emitted, vec![vec![9], vec![9; 4], vec![9]]);
+    }
+
+    #[test]
+    fn test_default_decode_mode_is_lossy() {
+        assert_eq!(DecodeMode::default(), DecodeMode::Lossy);
+    }
+
+    #[test]
+    fn test_token_budget_rejects_zero() {
+        assert!(token_budget(10, 0, 32).unwrap_err().contains("max_tokens"));
+    }
+
+    #[test]
+    fn test_token_budget_keeps_reasonable_value() {
+        assert_eq!(token_budget(10, 16, 32).unwrap(), 16);
+        assert_eq!(token_budget(10, 22, 32).unwrap(), 22);
+    }
+
+    #[test]
+    fn test_token_budget_clamps_oversized_value() {
+        assert_eq!(token_budget(10, 1_000_000, 32).unwrap(), 22);
+        assert!(token_budget(32, 1, 32).is_err());
+    }
+
+    #[test]
+    fn test_fit_prompt_checks_prompt_plus_output() {
+        let prompt: Vec<u32> = (0..10).collect();
+        assert_eq!(fit_prompt(prompt.clone(), 22, 32, Truncation::Head, None).unwrap(), (prompt.clone(), 22));
+        // Without truncation the prompt is kept and the output clamped.
+        assert_eq!(fit_prompt(prompt.clone(), 30, 32, Truncation::Clamp, None).unwrap(), (prompt, 22));
+        assert!(fit_prompt((0..40).collect(), 4, 32, Truncation::Clamp, None).is_err());
+    }
+
+    #[test]
+    fn test_fit_prompt_truncation_strategies() {
+        let prompt: Vec<u32> = (0..10).collect();
+        let fit = |truncation, bos| fit_prompt(prompt.clone(), 4, 10, truncation, bos).unwrap();
+        assert_eq!(fit(Truncation::Head, None), (vec![4, 5, 6, 7, 8, 9], 4));
+        assert_eq!(fit(Truncation::Tail, None), (vec![0, 1, 2, 3, 4, 5], 4));
+        assert_eq!(fit(Truncation::Middle, None), (vec![0, 1, 2, 7, 8, 9], 4));
+        // Token 0 stands in for the BOS, which stays in front.
+        assert_eq!(fit(Truncation::Head, Some(0)), (vec![0, 5, 6, 7, 8, 9], 4));
+        assert_eq!(fit(Truncation::Middle, Some(0)), (vec![0, 1, 2, 7, 8, 9], 4));
+        assert_eq!(fit(Truncation::Head, Some(1)), (vec![4, 5, 6, 7, 8, 9], 4));
+
+        // An output budget as large as the window still leaves one prompt token.
+        assert_eq!(fit_prompt(prompt.clone(), 100, 10, Truncation::Head, None).unwrap(), (vec![9], 9));
+        assert_eq!(fit_prompt(prompt, 100, 10, Truncation::Head, Some(0)).unwrap(), (vec![0, 9], 8));
+        assert!(fit_prompt(vec![1; 40], 0, 32, Truncation::Head, None).is_err());
+        assert!(fit_prompt(vec![0; 40], 4, 1, Truncation::Head, Some(0)).is_err());
+    }
+
+    #[test]
+    fn test_prompt_resumed_from_cached_prefix_matches_one_pass() {
+        let config = candle_transformers::models::llama::Config {
+            hidden_size: 32,
+            intermediate_size: 64,
+            vocab_size: 128,
+            num_hidden_layers: 2,
+            num_attention_heads: 4,
+            num_key_value_heads: 2,
+            ..candle_transformers::models::llama::Config::config_7b_v2(false)
+        };
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let mut weights = Weights::Full(Llama::load(vb, &config).unwrap());
+        let prompt: Vec<u32> = (0..100).map(|t| t * 7 % 128).collect();
+
+        // Logits after feeding the prompt in chunks ending at `splits`.
+        let mut logits = |splits: &[usize]| {
+            let mut cache = Some(Cache::new(true, DType::F32, &config, &Device::Cpu).unwrap());
+            let mut start = 0;
+            let mut last = None;
+            for &end in splits {
+                let input = Tensor::new(&prompt[start..end], &Device::Cpu).unwrap().unsqueeze(0).unwrap();
+                last = Some(weights.forward(&input, start, &mut cache).unwrap());
+                start = end;
+            }
+            last.unwrap().flatten_all().unwrap().to_vec1::<f32>().unwrap()
+        };
+
+        let whole = logits(&[prompt.len()]);
+        let boundary = prefix_boundary(prompt.len());
+        assert_eq!(boundary, 64);
+        let resumed = logits(&[boundary, prompt.len()]);
+        assert_eq!(whole.len(), resumed.len());
+        for (a, b) in whole.iter().zip(&resumed) {
+            assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+        }
+    }
+
+    /// Stand-in for a model with an internal KV cache: it only accepts a
+    /// sequence that extends what it has already cached.
+    #[derive(Default)]
+    struct CachedModel {
+        cache: Vec<u32>,
+    }
+
+    impl CachedModel {
+        fn forward(&mut self, sequence: &[u32]) -> Result<u32, LlmError> {
+            if !sequence.starts_with(&self.cache) {
+                return Err(candle_core::Error::Msg("KV cache belongs to another sequence".to_string()).into());
+            }
+            self.cache = sequence.to_vec();
+            std::thread::yield_now();
+            Ok(sequence.iter().fold(7u32, |acc, t| acc.wrapping_mul(31).wrapping_add(*t)) % 1000)
+        }
+    }
+
+    fn generate_cached(model: &mut CachedModel, prompt: Vec<u32>) -> Result<Vec<u32>, String> {
+        model.cache.clear();
+        let mut generated = Vec::new();
+        decode_loop(prompt, 32, 4096, None, |sequence| model.forward(sequence), |next| {
+            generated.push(next);
+            true
+        })
+        .map_err(|e| e.to_string())?;
+        Ok(generated)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_generations_are_serialized() {
+        let lock = Arc::new(ModelLock::new(CachedModel::default()));
+        let prompts: Vec<Vec<u32>> = (0..8).map(|i| vec![i, i + 1, i + 2]).collect();
+        let expected: Vec<Vec<u32>> = prompts
+            .iter()
+            .map(|prompt| generate_cached(&mut CachedModel::default(), prompt.clone()).unwrap())
+            .collect();
+
+        let handles: Vec<_> = prompts
+            .into_iter()
+            .enumerate()
+            .map(|(i, prompt)| {
+                let lock = Arc::clone(&lock);
+                if i % 2 == 0 {
+                    tokio::task::spawn_blocking(move || lock.run_blocking(|model| generate_cached(model, prompt)))
+                } else {
+                    tokio::spawn(async move { lock.run(|model| generate_cached(model, prompt)).await })
+                }
+            })
+            .collect();
+
+        for (handle, expected) in handles.into_iter().zip(expected) {
+            assert_eq!(handle.await.unwrap().unwrap(), expected);
+        }
     }
 }
@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+
+/// Prompts are prefilled up to a multiple of this many tokens before the
+/// KV cache is snapshotted, so prompts sharing a long system prompt land on
+/// the same boundary.
+pub const PREFIX_BLOCK_TOKENS: usize = 64;
+pub const DEFAULT_PREFIX_CACHE_ENTRIES: usize = 8;
+
+/// Where a prompt of `len` tokens is snapshotted: the last block boundary
+/// that still leaves a token to run for the next-token logits.
+pub fn prefix_boundary(len: usize) -> usize {
+    len.saturating_sub(1) / PREFIX_BLOCK_TOKENS * PREFIX_BLOCK_TOKENS
+}
+
+/// Snapshots of KV caches keyed by the prompt tokens they hold. A new prompt
+/// that starts with a stored prefix resumes from the snapshot instead of
+/// recomputing it. Least recently used entries are evicted first.
+#[derive(Debug)]
+pub struct PrefixCache<C> {
+    entries: VecDeque<(Vec<u32>, C)>,
+    capacity: usize,
+}
+
+impl<C: Clone> PrefixCache<C> {
+    pub fn new(capacity: usize) -> Self {
+        PrefixCache {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// The longest stored prefix of `tokens`, shorter than `tokens` itself,
+    /// and a copy of its cache.
+    pub fn lookup(&mut self, tokens: &[u32]) -> Option<(usize, C)> {
+        let position = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, (prefix, _))| prefix.len() < tokens.len() && tokens.starts_with(prefix))
+            .max_by_key(|(_, (prefix, _))| prefix.len())
+            .map(|(position, _)| position)?;
+        let entry = self.entries.remove(position)?;
+        let found = (entry.0.len(), entry.1.clone());
+        self.entries.push_front(entry);
+        Some(found)
+    }
+
+    pub fn insert(&mut self, prefix: Vec<u32>, cache: C) {
+        if self.capacity == 0 || prefix.is_empty() {
+            return;
+        }
+        self.entries.retain(|(stored, _)| *stored != prefix);
+        if self.entries.len() == self.capacity {
+            self.entries.pop_back();
+        }
+        self.entries.push_front((prefix, cache));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_stored_prefix_wins() {
+        let mut cache = PrefixCache::new(4);
+        cache.insert(vec![1, 2], "short");
+        cache.insert(vec![1, 2, 3, 4], "long");
+        cache.insert(vec![9], "other");
+
+        assert_eq!(cache.lookup(&[1, 2, 3, 4, 5]), Some((4, "long")));
+        assert_eq!(cache.lookup(&[1, 2, 3]), Some((2, "short")));
+        // An exact match leaves nothing to run, so it does not count.
+        assert_eq!(cache.lookup(&[9]), None);
+    }
+
+    #[test]
+    fn test_least_recently_used_evicted() {
+        let mut cache = PrefixCache::new(2);
+        cache.insert(vec![1], 1);
+        cache.insert(vec![2], 2);
+        assert!(cache.lookup(&[1, 0]).is_some());
+        cache.insert(vec![3], 3);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.lookup(&[2, 0]).is_none());
+        assert!(cache.lookup(&[1, 0]).is_some());
+    }
+
+    #[test]
+    fn test_boundary_leaves_a_token_to_run() {
+        assert_eq!(prefix_boundary(200), 192);
+        assert_eq!(prefix_boundary(64), 0);
+        assert_eq!(prefix_boundary(65), 64);
+        assert_eq!(prefix_boundary(0), 0);
+    }
+}
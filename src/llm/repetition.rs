@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+pub const DEFAULT_REPETITION_WINDOW: usize = 64;
+pub const DEFAULT_REPETITION_THRESHOLD: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepetitionConfig {
+    /// Number of most recent tokens inspected for loops.
+    pub window: usize,
+    /// How many back-to-back copies of an n-gram count as a loop.
+    pub threshold: usize,
+}
+
+impl Default for RepetitionConfig {
+    fn default() -> Self {
+        RepetitionConfig {
+            window: DEFAULT_REPETITION_WINDOW,
+            threshold: DEFAULT_REPETITION_THRESHOLD,
+        }
+    }
+}
+
+/// Detects generation loops: the tail of the token stream consisting of the
+/// same n-gram repeated `threshold` times, for any n that fits in the window.
+#[derive(Debug, Clone)]
+pub struct RepetitionGuard {
+    config: RepetitionConfig,
+    recent: VecDeque<u32>,
+}
+
+impl RepetitionGuard {
+    pub fn new(config: RepetitionConfig) -> Self {
+        RepetitionGuard {
+            recent: VecDeque::with_capacity(config.window),
+            config,
+        }
+    }
+
+    /// Records `token` and returns the looping n-gram length, if any.
+    pub fn push(&mut self, token: u32) -> Option<usize> {
+        if self.recent.len() == self.config.window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(token);
+
+        let threshold = self.config.threshold.max(2);
+        let max_ngram = self.recent.len() / threshold;
+        (1..=max_ngram).find(|&n| self.tail_repeats(n, threshold))
+    }
+
+    fn tail_repeats(&self, n: usize, copies: usize) -> bool {
+        let len = self.recent.len();
+        let start = len - n * copies;
+        (start..len - n).all(|i| self.recent[i] == self.recent[i + n])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_looping_stream_stops_early() {
+        let mut guard = RepetitionGuard::new(RepetitionConfig { window: 32, threshold: 4 });
+        let prefix = [11, 12, 13, 14, 15];
+        let stream = prefix.iter().copied().chain([7u32, 8, 9].iter().copied().cycle()).take(512);
+
+        let mut stopped_at = None;
+        for (i, token) in stream.enumerate() {
+            if let Some(n) = guard.push(token) {
+                stopped_at = Some((i, n));
+                break;
+            }
+        }
+
+        let (index, ngram) = stopped_at.expect("loop not detected");
+        assert_eq!(ngram, 3);
+        assert_eq!(index, prefix.len() + 3 * 4 - 1);
+    }
+
+    #[test]
+    fn test_varied_stream_is_not_flagged() {
+        let mut guard = RepetitionGuard::new(RepetitionConfig::default());
+        assert!((0..1_000u32).all(|token| guard.push(token % 97).is_none()));
+    }
+
+    #[test]
+    fn test_repeats_below_threshold_allowed() {
+        let mut guard = RepetitionGuard::new(RepetitionConfig { window: 32, threshold: 4 });
+        for token in [1, 2, 1, 2, 1, 2] {
+            assert!(guard.push(token).is_none());
+        }
+        assert_eq!(guard.push(1), None);
+        assert_eq!(guard.push(2), Some(2));
+    }
+}
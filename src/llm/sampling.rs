@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_TEMPERATURE: f32 = crate::node::message::DEFAULT_GENERATION_TEMPERATURE;
+pub const DEFAULT_SAMPLING_SEED: u64 = crate::node::message::DEFAULT_GENERATION_SEED;
+/// Most recent tokens a repetition penalty looks back over.
+pub const REPETITION_PENALTY_WINDOW: usize = 64;
+
+/// How the next token is picked from the model's logits. Two nodes given the
+/// same prompt and the same params produce the same output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplingParams {
+    /// Zero is greedy decoding.
+    pub temperature: f32,
+    /// Sample only among the `top_k` most likely tokens.
+    pub top_k: Option<usize>,
+    /// Sample only among the most likely tokens whose probabilities add up to `top_p`.
+    pub top_p: Option<f64>,
+    /// Above 1.0 discourages tokens seen in the last
+    /// `REPETITION_PENALTY_WINDOW` tokens; 1.0 turns it off.
+    pub repetition_penalty: f32,
+    /// Generation ends before any of these; the match is not returned.
+    pub stop: Vec<String>,
+    pub seed: u64,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        SamplingParams {
+            temperature: DEFAULT_TEMPERATURE,
+            top_k: None,
+            top_p: None,
+            repetition_penalty: 1.0,
+            stop: Vec::new(),
+            seed: DEFAULT_SAMPLING_SEED,
+        }
+    }
+}
+
+impl SamplingParams {
+    pub fn with_temperature(temperature: f32) -> Self {
+        SamplingParams {
+            temperature,
+            ..SamplingParams::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.temperature.is_nan() || self.temperature < 0.0 {
+            return Err(format!("temperature must be zero or more, got {}", self.temperature));
+        }
+        if self.top_k == Some(0) {
+            return Err("top_k must be at least 1".to_string());
+        }
+        if let Some(top_p) = self.top_p {
+            if top_p.is_nan() || top_p <= 0.0 || top_p > 1.0 {
+                return Err(format!("top_p must be in (0, 1], got {}", top_p));
+            }
+        }
+        if self.repetition_penalty.is_nan() || self.repetition_penalty <= 0.0 {
+            return Err(format!("repetition_penalty must be positive, got {}", self.repetition_penalty));
+        }
+        Ok(())
+    }
+}
+
+/// Scales down the logits of tokens in `context` so they are less likely to
+/// be picked again.
+pub fn apply_repetition_penalty(logits: &mut [f32], context: &[u32], penalty: f32) {
+    if penalty == 1.0 {
+        return;
+    }
+    let mut seen = std::collections::HashSet::new();
+    for &token in context {
+        if !seen.insert(token) {
+            continue;
+        }
+        if let Some(logit) = logits.get_mut(token as usize) {
+            if *logit >= 0.0 {
+                *logit /= penalty;
+            } else {
+                *logit *= penalty;
+            }
+        }
+    }
+}
+
+/// Masks every logit below the `k` largest. Ties at the cutoff all survive.
+pub fn apply_top_k(logits: &mut [f32], k: usize) {
+    if k == 0 || k >= logits.len() {
+        return;
+    }
+    let mut sorted = logits.to_vec();
+    let (_, threshold, _) = sorted.select_nth_unstable_by(k - 1, |a, b| b.total_cmp(a));
+    let threshold = *threshold;
+    for logit in logits.iter_mut() {
+        if *logit < threshold {
+            *logit = f32::NEG_INFINITY;
+        }
+    }
+}
+
+/// Byte offset of the earliest stop sequence in `text`.
+pub fn find_stop(text: &str, stops: &[String]) -> Option<usize> {
+    stops
+        .iter()
+        .filter(|stop| !stop.is_empty())
+        .filter_map(|stop| text.find(stop.as_str()))
+        .min()
+}
+
+/// Watches streamed text for stop sequences. Text is released only once it
+/// can no longer turn out to be the start of one.
+#[derive(Debug, Clone, Default)]
+pub struct StopMatcher {
+    stops: Vec<String>,
+    held: String,
+}
+
+impl StopMatcher {
+    pub fn new(stops: &[String]) -> Self {
+        StopMatcher {
+            stops: stops.iter().filter(|stop| !stop.is_empty()).cloned().collect(),
+            held: String::new(),
+        }
+    }
+
+    /// Adds decoded text. Returns what is safe to emit and whether a stop
+    /// sequence was reached, in which case nothing more should be generated.
+    pub fn push(&mut self, text: &str) -> (String, bool) {
+        self.held.push_str(text);
+        if let Some(pos) = find_stop(&self.held, &self.stops) {
+            let released = self.held[..pos].to_string();
+            self.held.clear();
+            return (released, true);
+        }
+
+        let split = self.held.len() - self.partial_match_len();
+        (self.held.drain(..split).collect(), false)
+    }
+
+    /// Text still held back when generation ends without a stop.
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.held)
+    }
+
+    /// Longest tail of the held text that begins some stop sequence.
+    fn partial_match_len(&self) -> usize {
+        self.stops
+            .iter()
+            .filter_map(|stop| {
+                (1..stop.len().min(self.held.len() + 1))
+                    .rev()
+                    .find(|&n| stop.is_char_boundary(n) && self.held.ends_with(&stop[..n]))
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repetition_penalty_scales_seen_tokens_once() {
+        let mut logits = vec![2.0, -2.0, 2.0];
+        apply_repetition_penalty(&mut logits, &[0, 1, 0, 0], 2.0);
+        assert_eq!(logits, vec![1.0, -4.0, 2.0]);
+    }
+
+    #[test]
+    fn test_top_k_masks_the_rest() {
+        let mut logits = vec![0.1, 3.0, -1.0, 2.0, 0.5];
+        apply_top_k(&mut logits, 2);
+        assert_eq!(logits[1], 3.0);
+        assert_eq!(logits[3], 2.0);
+        assert_eq!(logits.iter().filter(|l| l.is_infinite()).count(), 3);
+    }
+
+    #[test]
+    fn test_invalid_params_rejected() {
+        assert!(SamplingParams::default().validate().is_ok());
+        assert!(SamplingParams { top_k: Some(0), ..Default::default() }.validate().is_err());
+        assert!(SamplingParams { top_p: Some(1.5), ..Default::default() }.validate().is_err());
+        assert!(SamplingParams::with_temperature(-1.0).validate().is_err());
+        assert!(SamplingParams { repetition_penalty: 0.0, ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_params_deserialize_with_defaults() {
+        let params: SamplingParams = serde_json::from_str(r#"{"top_k": 40, "stop": ["\n\n"]}"#).unwrap();
+        assert_eq!(params.top_k, Some(40));
+        assert_eq!(params.temperature, DEFAULT_TEMPERATURE);
+        assert_eq!(params.seed, DEFAULT_SAMPLING_SEED);
+    }
+
+    #[test]
+    fn test_stop_matcher_holds_back_partial_matches() {
+        let mut matcher = StopMatcher::new(&["###".to_string()]);
+        assert_eq!(matcher.push("Hello #"), ("Hello ".to_string(), false));
+        assert_eq!(matcher.push("# world"), ("## world".to_string(), false));
+        assert_eq!(matcher.push("!#"), ("!".to_string(), false));
+        assert_eq!(matcher.push("##tail"), (String::new(), true));
+    }
+
+    #[test]
+    fn test_stop_matcher_flushes_at_end() {
+        let mut matcher = StopMatcher::new(&["END".to_string(), String::new()]);
+        assert_eq!(matcher.push("the EN"), ("the ".to_string(), false));
+        assert_eq!(matcher.finish(), "EN");
+        assert_eq!(find_stop("a END b END", &["END".to_string()]), Some(2));
+    }
+}
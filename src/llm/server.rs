@@ -0,0 +1,241 @@
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+    routing::post,
+    Json, Router,
+};
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::llm::error::LlmError;
+use crate::llm::model::{LightLLM, TokenReceiver, Truncation};
+use crate::llm::sampling::SamplingParams;
+
+pub trait StreamingGenerator: Send + Sync {
+    fn generate_stream(
+        self: Arc<Self>,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: SamplingParams,
+        truncation: Truncation,
+    ) -> Result<TokenReceiver, LlmError>;
+}
+
+impl StreamingGenerator for LightLLM {
+    fn generate_stream(
+        self: Arc<Self>,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: SamplingParams,
+        truncation: Truncation,
+    ) -> Result<TokenReceiver, LlmError> {
+        LightLLM::generate_stream(self, prompt, max_tokens, sampling, truncation)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GenerateRequest {
+    pub prompt: String,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    /// Temperature, top-k/top-p, stop sequences and seed, given inline.
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+    /// How to shorten a prompt that leaves no room for `max_tokens`.
+    #[serde(default)]
+    pub truncation: Truncation,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenChunk {
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerateStats {
+    pub tokens: usize,
+    pub elapsed_ms: u64,
+    pub max_tokens: usize,
+}
+
+#[derive(Clone)]
+struct ServerState {
+    generator: Arc<dyn StreamingGenerator>,
+    max_tokens_cap: usize,
+}
+
+pub fn router(generator: Arc<dyn StreamingGenerator>, max_tokens_cap: usize) -> Router {
+    Router::new()
+        .route("/generate", post(generate_sse))
+        .with_state(ServerState {
+            generator,
+            max_tokens_cap,
+        })
+}
+
+struct StreamState {
+    rx: Option<TokenReceiver>,
+    tokens: usize,
+    max_tokens: usize,
+    started: Instant,
+}
+
+async fn generate_sse(
+    State(state): State<ServerState>,
+    Json(request): Json<GenerateRequest>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let max_tokens = request
+        .max_tokens
+        .unwrap_or(state.max_tokens_cap)
+        .min(state.max_tokens_cap);
+
+    let rx = Arc::clone(&state.generator).generate_stream(&request.prompt, max_tokens, request.sampling, request.truncation);
+    let (rx, initial_error) = match rx {
+        Ok(rx) => (Some(rx), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+
+    let initial = stream::iter(initial_error.map(|e| Ok(Event::default().event("error").data(e))));
+
+    // Dropping the receiver when the client disconnects stops the generator on its next send.
+    let tokens = stream::unfold(
+        StreamState {
+            rx,
+            tokens: 0,
+            max_tokens,
+            started: Instant::now(),
+        },
+        |mut state| async move {
+            let rx = state.rx.as_mut()?;
+            let event = match rx.recv().await {
+                Some(Ok(text)) => {
+                    state.tokens += 1;
+                    Event::default()
+                        .event("token")
+                        .json_data(TokenChunk { text })
+                        .unwrap_or_else(|e| Event::default().event("error").data(e.to_string()))
+                }
+                Some(Err(e)) => {
+                    state.rx = None;
+                    Event::default().event("error").data(e)
+                }
+                None => {
+                    state.rx = None;
+                    let stats = GenerateStats {
+                        tokens: state.tokens,
+                        elapsed_ms: state.started.elapsed().as_millis() as u64,
+                        max_tokens: state.max_tokens,
+                    };
+                    Event::default()
+                        .event("done")
+                        .json_data(stats)
+                        .unwrap_or_else(|e| Event::default().event("error").data(e.to_string()))
+                }
+            };
+            Some((Ok(event), state))
+        },
+    );
+
+    Sse::new(initial.chain(tokens)).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    struct ScriptedGenerator {
+        pieces: Vec<&'static str>,
+    }
+
+    impl StreamingGenerator for ScriptedGenerator {
+        fn generate_stream(
+            self: Arc<Self>,
+            _prompt: &str,
+            max_tokens: usize,
+            _sampling: SamplingParams,
+            _truncation: Truncation,
+        ) -> Result<TokenReceiver, LlmError> {
+            let (tx, rx) = mpsc::channel(8);
+            tokio::spawn(async move {
+                for piece in self.pieces.iter().take(max_tokens) {
+                    if tx.send(Ok(piece.to_string())).await.is_err() {
+                        return;
+                    }
+                }
+            });
+            Ok(rx)
+        }
+    }
+
+    fn parse_events(body: &str) -> Vec<(String, String)> {
+        body.split("\n\n")
+            .filter(|block| !block.trim().is_empty())
+            .filter_map(|block| {
+                let mut event = None;
+                let mut data = None;
+                for line in block.lines() {
+                    if let Some(value) = line.strip_prefix("event: ") {
+                        event = Some(value.to_string());
+                    } else if let Some(value) = line.strip_prefix("data: ") {
+                        data = Some(value.to_string());
+                    }
+                }
+                Some((event?, data.unwrap_or_default()))
+            })
+            .collect()
+    }
+
+    async fn post_generate(app: Router, body: &str) -> Vec<(String, String)> {
+        let response = app
+            .oneshot(
+                Request::post("/generate")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        parse_events(&String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_sse_chunks_reassemble_output() {
+        let generator = Arc::new(ScriptedGenerator {
+            pieces: vec!["Hello", ",", " distributed", "\nworld"],
+        });
+        let events = post_generate(router(generator, 64), r#"{"prompt": "hi"}"#).await;
+
+        let text: String = events
+            .iter()
+            .filter(|(event, _)| event == "token")
+            .map(|(_, data)| serde_json::from_str::<TokenChunk>(data).unwrap().text)
+            .collect();
+        assert_eq!(text, "Hello, distributed\nworld");
+
+        let (event, data) = events.last().unwrap();
+        assert_eq!(event, "done");
+        let stats: GenerateStats = serde_json::from_str(data).unwrap();
+        assert_eq!(stats.tokens, 4);
+    }
+
+    #[tokio::test]
+    async fn test_sse_respects_max_tokens_cap() {
+        let generator = Arc::new(ScriptedGenerator {
+            pieces: vec!["a", "b", "c", "d"],
+        });
+        let events = post_generate(router(generator, 2), r#"{"prompt": "hi", "max_tokens": 100}"#).await;
+
+        let (_, data) = events.last().unwrap();
+        let stats: GenerateStats = serde_json::from_str(data).unwrap();
+        assert_eq!(stats.tokens, 2);
+        assert_eq!(stats.max_tokens, 2);
+    }
+}
@@ -0,0 +1,577 @@
+use candle_core::backprop::GradStore;
+use candle_core::{DType, Device, Tensor, Var, D};
+use candle_nn::{embedding, linear_no_bias, Embedding, Init, Linear, Module, VarBuilder, VarMap};
+use candle_transformers::models::llama::Config;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::llm::error::LlmError;
+use crate::llm::lora::{AdapterBuilder, LoraLinear};
+use crate::node::training::Gradient;
+
+/// Checkpoint file holding the trained weights.
+pub const WEIGHTS_CHECKPOINT_FILE: &str = "model.safetensors";
+/// Checkpoint file holding trained LoRA adapters.
+pub const ADAPTER_CHECKPOINT_FILE: &str = "adapter.safetensors";
+/// Checkpoint file holding the optimizer moments and step count.
+pub const OPTIMIZER_CHECKPOINT_FILE: &str = "optimizer.safetensors";
+const STEP_TENSOR: &str = "step";
+
+/// RMS norm spelled out in tensor ops; the fused kernel has no backward pass.
+#[derive(Debug, Clone)]
+struct RmsNorm {
+    weight: Tensor,
+    eps: f64,
+}
+
+impl RmsNorm {
+    fn load(size: usize, eps: f64, vb: VarBuilder) -> candle_core::Result<Self> {
+        let weight = vb.get_with_hints(size, "weight", Init::Const(1.0))?;
+        Ok(RmsNorm { weight, eps })
+    }
+
+    fn forward(&self, xs: &Tensor) -> candle_core::Result<Tensor> {
+        let norm = (xs.sqr()?.mean_keepdim(D::Minus1)? + self.eps)?.sqrt()?;
+        xs.broadcast_div(&norm)?.broadcast_mul(&self.weight)
+    }
+}
+
+/// Rotates the two halves of the head dimension, as the Llama checkpoints expect.
+fn apply_rope(xs: &Tensor, cos: &Tensor, sin: &Tensor) -> candle_core::Result<Tensor> {
+    let half = xs.dim(D::Minus1)? / 2;
+    let rotated = Tensor::cat(&[&xs.narrow(D::Minus1, half, half)?.neg()?, &xs.narrow(D::Minus1, 0, half)?], D::Minus1)?;
+    xs.broadcast_mul(cos)? + rotated.broadcast_mul(sin)?
+}
+
+/// Shares each key/value head across `n_rep` query heads.
+fn repeat_kv(xs: Tensor, n_rep: usize) -> candle_core::Result<Tensor> {
+    if n_rep == 1 {
+        return Ok(xs);
+    }
+    let (b, heads, t, d) = xs.dims4()?;
+    xs.unsqueeze(2)?.broadcast_as((b, heads, n_rep, t, d))?.reshape((b, heads * n_rep, t, d))
+}
+
+#[derive(Debug, Clone)]
+struct Attention {
+    q_proj: LoraLinear,
+    k_proj: LoraLinear,
+    v_proj: LoraLinear,
+    o_proj: LoraLinear,
+    num_heads: usize,
+    num_kv_heads: usize,
+    head_dim: usize,
+}
+
+impl Attention {
+    fn load(config: &Config, vb: VarBuilder, adapters: Option<&AdapterBuilder>) -> candle_core::Result<Self> {
+        let head_dim = config.hidden_size / config.num_attention_heads;
+        let kv_size = head_dim * config.num_key_value_heads;
+        let hidden = config.hidden_size;
+        Ok(Attention {
+            q_proj: LoraLinear::load(hidden, hidden, "q_proj", vb.clone(), adapters)?,
+            k_proj: LoraLinear::load(hidden, kv_size, "k_proj", vb.clone(), adapters)?,
+            v_proj: LoraLinear::load(hidden, kv_size, "v_proj", vb.clone(), adapters)?,
+            o_proj: LoraLinear::load(hidden, hidden, "o_proj", vb, adapters)?,
+            num_heads: config.num_attention_heads,
+            num_kv_heads: config.num_key_value_heads,
+            head_dim,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, cos: &Tensor, sin: &Tensor, mask: &Tensor) -> candle_core::Result<Tensor> {
+        let (b, t, hidden) = xs.dims3()?;
+        let heads = |proj: &LoraLinear, n: usize| -> candle_core::Result<Tensor> {
+            proj.forward(xs)?.reshape((b, t, n, self.head_dim))?.transpose(1, 2)?.contiguous()
+        };
+        let q = apply_rope(&heads(&self.q_proj, self.num_heads)?, cos, sin)?;
+        let k = apply_rope(&heads(&self.k_proj, self.num_kv_heads)?, cos, sin)?;
+        let v = heads(&self.v_proj, self.num_kv_heads)?;
+
+        let n_rep = self.num_heads / self.num_kv_heads;
+        let k = repeat_kv(k, n_rep)?.contiguous()?;
+        let v = repeat_kv(v, n_rep)?.contiguous()?;
+
+        let scores = (q.matmul(&k.t()?)? / (self.head_dim as f64).sqrt())?.broadcast_add(mask)?;
+        let probs = candle_nn::ops::softmax(&scores, D::Minus1)?;
+        let out = probs.matmul(&v)?.transpose(1, 2)?.reshape((b, t, hidden))?;
+        self.o_proj.forward(&out)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Mlp {
+    gate_proj: LoraLinear,
+    up_proj: LoraLinear,
+    down_proj: LoraLinear,
+}
+
+impl Mlp {
+    fn load(config: &Config, vb: VarBuilder, adapters: Option<&AdapterBuilder>) -> candle_core::Result<Self> {
+        let (hidden, intermediate) = (config.hidden_size, config.intermediate_size);
+        Ok(Mlp {
+            gate_proj: LoraLinear::load(hidden, intermediate, "gate_proj", vb.clone(), adapters)?,
+            up_proj: LoraLinear::load(hidden, intermediate, "up_proj", vb.clone(), adapters)?,
+            down_proj: LoraLinear::load(intermediate, hidden, "down_proj", vb, adapters)?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> candle_core::Result<Tensor> {
+        let gate = self.gate_proj.forward(xs)?;
+        let gate = (&gate / (gate.neg()?.exp()? + 1.0)?)?;
+        self.down_proj.forward(&(gate * self.up_proj.forward(xs)?)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Block {
+    input_layernorm: RmsNorm,
+    attention: Attention,
+    post_attention_layernorm: RmsNorm,
+    mlp: Mlp,
+}
+
+impl Block {
+    fn load(config: &Config, vb: VarBuilder, adapters: Option<&AdapterBuilder>) -> candle_core::Result<Self> {
+        Ok(Block {
+            input_layernorm: RmsNorm::load(config.hidden_size, config.rms_norm_eps, vb.pp("input_layernorm"))?,
+            attention: Attention::load(config, vb.pp("self_attn"), adapters.map(|a| a.pp("self_attn")).as_ref())?,
+            post_attention_layernorm: RmsNorm::load(
+                config.hidden_size,
+                config.rms_norm_eps,
+                vb.pp("post_attention_layernorm"),
+            )?,
+            mlp: Mlp::load(config, vb.pp("mlp"), adapters.map(|a| a.pp("mlp")).as_ref())?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, cos: &Tensor, sin: &Tensor, mask: &Tensor) -> candle_core::Result<Tensor> {
+        let xs = (xs + self.attention.forward(&self.input_layernorm.forward(xs)?, cos, sin, mask)?)?;
+        &xs + self.mlp.forward(&self.post_attention_layernorm.forward(&xs)?)?
+    }
+}
+
+/// Llama-family decoder that returns logits for every position, which the
+/// inference model does not, built only from ops with a backward pass.
+/// Reads the same tensor names as the inference weights, and can carry LoRA
+/// adapters on its projections.
+#[derive(Debug, Clone)]
+pub struct CausalLm {
+    embed_tokens: Embedding,
+    blocks: Vec<Block>,
+    norm: RmsNorm,
+    lm_head: Linear,
+    cos: Tensor,
+    sin: Tensor,
+}
+
+impl CausalLm {
+    /// Positions past `max_len` cannot be run.
+    pub fn load(vb: VarBuilder, config: &Config, max_len: usize) -> candle_core::Result<Self> {
+        Self::load_inner(vb, None, config, max_len)
+    }
+
+    /// Loads `vb` as the base model with adapters from `adapters` on the
+    /// targeted projections. Only the adapters are meant to be trained.
+    pub fn load_with_lora(
+        vb: VarBuilder,
+        adapters: AdapterBuilder,
+        config: &Config,
+        max_len: usize,
+    ) -> candle_core::Result<Self> {
+        Self::load_inner(vb, Some(&adapters), config, max_len)
+    }
+
+    fn load_inner(
+        vb: VarBuilder,
+        adapters: Option<&AdapterBuilder>,
+        config: &Config,
+        max_len: usize,
+    ) -> candle_core::Result<Self> {
+        let head_dim = config.hidden_size / config.num_attention_heads;
+        let inv_freq: Vec<f32> = (0..head_dim / 2)
+            .map(|i| 1.0 / config.rope_theta.powf(2.0 * i as f32 / head_dim as f32))
+            .collect();
+        let inv_freq = Tensor::from_vec(inv_freq, (1, head_dim / 2), vb.device())?;
+        let positions = Tensor::arange(0u32, max_len as u32, vb.device())?
+            .to_dtype(DType::F32)?
+            .reshape((max_len, 1))?;
+        let freqs = positions.matmul(&inv_freq)?;
+        let freqs = Tensor::cat(&[&freqs, &freqs], D::Minus1)?;
+
+        let blocks = (0..config.num_hidden_layers)
+            .map(|i| {
+                let path = format!("model.layers.{}", i);
+                Block::load(config, vb.pp(&path), adapters.map(|a| a.pp(&path)).as_ref())
+            })
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        Ok(CausalLm {
+            embed_tokens: embedding(config.vocab_size, config.hidden_size, vb.pp("model.embed_tokens"))?,
+            blocks,
+            norm: RmsNorm::load(config.hidden_size, config.rms_norm_eps, vb.pp("model.norm"))?,
+            lm_head: linear_no_bias(config.hidden_size, config.vocab_size, vb.pp("lm_head"))?,
+            cos: freqs.cos()?,
+            sin: freqs.sin()?,
+        })
+    }
+
+    /// Logits of shape `(batch, seq_len, vocab)` for `tokens` of shape
+    /// `(batch, seq_len)`, each position seeing only those before it.
+    pub fn forward(&self, tokens: &Tensor) -> candle_core::Result<Tensor> {
+        let (_, t) = tokens.dims2()?;
+        let cos = self.cos.narrow(0, 0, t)?;
+        let sin = self.sin.narrow(0, 0, t)?;
+        let mask: Vec<f32> = (0..t)
+            .flat_map(|i| (0..t).map(move |j| if j > i { f32::NEG_INFINITY } else { 0.0 }))
+            .collect();
+        let mask = Tensor::from_vec(mask, (t, t), tokens.device())?;
+
+        let mut xs = self.embed_tokens.forward(tokens)?;
+        for block in &self.blocks {
+            xs = block.forward(&xs, &cos, &sin, &mask)?;
+        }
+        self.lm_head.forward(&self.norm.forward(&xs)?)
+    }
+}
+
+/// Inputs, shifted targets and a loss mask for a batch of token sequences,
+/// right-padded to the longest. Sequences need at least two tokens.
+pub fn next_token_batch(sequences: &[Vec<u32>], device: &Device) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
+    let len = sequences.iter().map(|s| s.len().saturating_sub(1)).max().unwrap_or(0);
+    let (mut inputs, mut targets, mut mask) = (Vec::new(), Vec::new(), Vec::new());
+    for sequence in sequences {
+        let n = sequence.len().saturating_sub(1);
+        inputs.extend(sequence.iter().take(n).copied().chain(std::iter::repeat(0).take(len - n)));
+        targets.extend(sequence.iter().skip(1).copied().chain(std::iter::repeat(0).take(len - n)));
+        mask.extend(std::iter::repeat(1f32).take(n).chain(std::iter::repeat(0.0).take(len - n)));
+    }
+    let shape = (sequences.len(), len);
+    Ok((
+        Tensor::from_vec(inputs, shape, device)?,
+        Tensor::from_vec(targets, shape, device)?,
+        Tensor::from_vec(mask, shape, device)?,
+    ))
+}
+
+/// Mean cross-entropy of `targets` under `logits` over the positions where
+/// `mask` is 1.
+pub fn next_token_loss(logits: &Tensor, targets: &Tensor, mask: &Tensor) -> candle_core::Result<Tensor> {
+    let log_probs = candle_nn::ops::log_softmax(&logits.to_dtype(DType::F32)?, D::Minus1)?;
+    let picked = log_probs.gather(&targets.unsqueeze(D::Minus1)?.contiguous()?, D::Minus1)?.squeeze(D::Minus1)?;
+    (picked * mask)?.sum_all()?.neg()?.broadcast_div(&mask.sum_all()?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdamWConfig {
+    pub learning_rate: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub eps: f64,
+    pub weight_decay: f64,
+}
+
+impl Default for AdamWConfig {
+    fn default() -> Self {
+        AdamWConfig {
+            learning_rate: 1e-5,
+            beta1: 0.9,
+            beta2: 0.999,
+            eps: 1e-8,
+            weight_decay: 0.01,
+        }
+    }
+}
+
+/// AdamW with decoupled weight decay. Unlike candle's optimizer its moments
+/// are reachable, so they can be checkpointed and training resumed exactly.
+#[derive(Debug)]
+pub struct AdamW {
+    config: AdamWConfig,
+    params: Vec<(String, Var)>,
+    first_moments: Vec<Tensor>,
+    second_moments: Vec<Tensor>,
+    step: u64,
+}
+
+impl AdamW {
+    /// Optimizes every variable in `vars`.
+    pub fn new(vars: &VarMap, config: AdamWConfig) -> Result<Self, LlmError> {
+        let mut params: Vec<(String, Var)> = vars
+            .data()
+            .lock()
+            .map_err(|_| LlmError::Poisoned)?
+            .iter()
+            .map(|(name, var)| (name.clone(), var.clone()))
+            .collect();
+        params.sort_by(|a, b| a.0.cmp(&b.0));
+        let zeros = params.iter().map(|(_, var)| var.zeros_like()).collect::<candle_core::Result<Vec<_>>>()?;
+        Ok(AdamW {
+            config,
+            params,
+            first_moments: zeros.clone(),
+            second_moments: zeros,
+            step: 0,
+        })
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.step
+    }
+
+    /// Applies one update from `grads`. Variables without a gradient are left alone.
+    pub fn step(&mut self, grads: &GradStore) -> candle_core::Result<()> {
+        let AdamWConfig { learning_rate, beta1, beta2, eps, weight_decay } = self.config;
+        self.step += 1;
+        let correction1 = 1.0 - beta1.powi(self.step as i32);
+        let correction2 = 1.0 - beta2.powi(self.step as i32);
+
+        for (((_, var), m), v) in self.params.iter().zip(&mut self.first_moments).zip(&mut self.second_moments) {
+            let Some(grad) = grads.get(var.as_tensor()) else {
+                continue;
+            };
+            *m = ((&*m * beta1)? + (grad * (1.0 - beta1))?)?;
+            *v = ((&*v * beta2)? + (grad.sqr()? * (1.0 - beta2))?)?;
+            let m_hat = (&*m / correction1)?;
+            let v_hat = (&*v / correction2)?;
+            let update = ((m_hat / (v_hat.sqrt()? + eps)?)? * learning_rate)?;
+            let decayed = (var.as_tensor() * (1.0 - learning_rate * weight_decay))?;
+            var.set(&(decayed - update)?)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the moments and step count to a safetensors file.
+    pub fn save(&self, path: &Path) -> candle_core::Result<()> {
+        let mut tensors = HashMap::new();
+        for (((name, _), m), v) in self.params.iter().zip(&self.first_moments).zip(&self.second_moments) {
+            tensors.insert(format!("{}.m", name), m.clone());
+            tensors.insert(format!("{}.v", name), v.clone());
+        }
+        tensors.insert(STEP_TENSOR.to_string(), Tensor::new(&[self.step as i64], &Device::Cpu)?);
+        candle_core::safetensors::save(&tensors, path)
+    }
+
+    /// Restores state written by `save` for the same set of variables.
+    pub fn load(&mut self, path: &Path) -> candle_core::Result<()> {
+        let device = self.params.first().map_or(Device::Cpu, |(_, var)| var.device().clone());
+        let mut tensors = candle_core::safetensors::load(path, &device)?;
+        let moment = |tensors: &mut HashMap<String, Tensor>, name: String, like: &Var| {
+            let tensor = tensors
+                .remove(&name)
+                .ok_or_else(|| candle_core::Error::Msg(format!("optimizer checkpoint is missing {}", name)))?;
+            if tensor.shape() != like.shape() {
+                return Err(candle_core::Error::Msg(format!("optimizer checkpoint has the wrong shape for {}", name)));
+            }
+            tensor.to_dtype(like.dtype())
+        };
+
+        let mut first_moments = Vec::with_capacity(self.params.len());
+        let mut second_moments = Vec::with_capacity(self.params.len());
+        for (name, var) in &self.params {
+            first_moments.push(moment(&mut tensors, format!("{}.m", name), var)?);
+            second_moments.push(moment(&mut tensors, format!("{}.v", name), var)?);
+        }
+        let step = tensors
+            .remove(STEP_TENSOR)
+            .ok_or_else(|| candle_core::Error::Msg("optimizer checkpoint is missing its step count".to_string()))?;
+
+        self.step = step.to_vec1::<i64>()?.first().copied().unwrap_or(0) as u64;
+        self.first_moments = first_moments;
+        self.second_moments = second_moments;
+        Ok(())
+    }
+}
+
+/// Adds the gradients in `grads` to `total` for every variable in `vars`.
+pub fn accumulate_grads(total: &mut GradStore, grads: &GradStore, vars: &[Var]) -> candle_core::Result<()> {
+    for var in vars {
+        let Some(grad) = grads.get(var.as_tensor()) else {
+            continue;
+        };
+        let sum = match total.remove(var.as_tensor()) {
+            Some(previous) => (previous + grad)?,
+            None => grad.clone(),
+        };
+        total.insert(var.as_tensor(), sum);
+    }
+    Ok(())
+}
+
+/// The gradient of every variable in `vars` that has one, sorted by name.
+pub fn gradients_of(vars: &VarMap, grads: &GradStore) -> Result<Vec<Gradient>, LlmError> {
+    let vars = vars.data().lock().map_err(|_| LlmError::Poisoned)?;
+    let mut names: Vec<&String> = vars.keys().collect();
+    names.sort();
+    let mut gradients = Vec::new();
+    for name in names {
+        if let Some(grad) = grads.get(vars[name].as_tensor()) {
+            gradients.push(Gradient {
+                name: name.clone(),
+                shape: grad.dims().to_vec(),
+                values: grad.to_dtype(DType::F32)?.flatten_all()?.to_vec1()?,
+            });
+        }
+    }
+    Ok(gradients)
+}
+
+/// Swaps the gradients in `grads` for `gradients`, matched to `vars` by name.
+pub fn replace_grads(
+    vars: &VarMap,
+    grads: &mut GradStore,
+    gradients: Vec<Gradient>,
+    device: &Device,
+) -> Result<(), LlmError> {
+    let vars = vars.data().lock().map_err(|_| LlmError::Poisoned)?;
+    for gradient in gradients {
+        let var = vars
+            .get(&gradient.name)
+            .ok_or_else(|| candle_core::Error::Msg(format!("no variable named {}", gradient.name)))?;
+        let tensor = Tensor::from_vec(gradient.values, gradient.shape, device)?.to_dtype(var.dtype())?;
+        grads.insert(var.as_tensor(), tensor);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::config::LoraConfig;
+
+    fn tiny_config() -> Config {
+        Config {
+            hidden_size: 16,
+            intermediate_size: 32,
+            vocab_size: 24,
+            num_hidden_layers: 2,
+            num_attention_heads: 4,
+            num_key_value_heads: 2,
+            ..Config::config_7b_v2(false)
+        }
+    }
+
+    fn tiny_model(vars: &VarMap) -> CausalLm {
+        let vb = VarBuilder::from_varmap(vars, DType::F32, &Device::Cpu);
+        CausalLm::load(vb, &tiny_config(), 16).unwrap()
+    }
+
+    fn batch_loss(model: &CausalLm, sequences: &[Vec<u32>]) -> Tensor {
+        let (inputs, targets, mask) = next_token_batch(sequences, &Device::Cpu).unwrap();
+        next_token_loss(&model.forward(&inputs).unwrap(), &targets, &mask).unwrap()
+    }
+
+    #[test]
+    fn test_uniform_logits_cost_log_vocab() {
+        let logits = Tensor::zeros((1, 3, 8), DType::F32, &Device::Cpu).unwrap();
+        let (_, targets, mask) = next_token_batch(&[vec![1, 2, 3, 4]], &Device::Cpu).unwrap();
+        let loss = next_token_loss(&logits, &targets, &mask).unwrap().to_scalar::<f32>().unwrap();
+        assert!((loss - (8f32).ln()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_padding_is_masked_out() {
+        let (inputs, targets, mask) = next_token_batch(&[vec![5, 6, 7], vec![8, 9]], &Device::Cpu).unwrap();
+        assert_eq!(inputs.to_vec2::<u32>().unwrap(), vec![vec![5, 6], vec![8, 0]]);
+        assert_eq!(targets.to_vec2::<u32>().unwrap(), vec![vec![6, 7], vec![9, 0]]);
+        assert_eq!(mask.to_vec2::<f32>().unwrap(), vec![vec![1.0, 1.0], vec![1.0, 0.0]]);
+    }
+
+    #[test]
+    fn test_positions_do_not_see_later_tokens() {
+        let vars = VarMap::new();
+        let model = tiny_model(&vars);
+        let a = model.forward(&Tensor::new(&[[1u32, 2, 3]], &Device::Cpu).unwrap()).unwrap();
+        let b = model.forward(&Tensor::new(&[[1u32, 2, 9]], &Device::Cpu).unwrap()).unwrap();
+        let first_two = |t: &Tensor| t.narrow(1, 0, 2).unwrap().flatten_all().unwrap().to_vec1::<f32>().unwrap();
+        for (x, y) in first_two(&a).into_iter().zip(first_two(&b)) {
+            assert!((x - y).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_adamw_steps_reduce_loss() {
+        let vars = VarMap::new();
+        let model = tiny_model(&vars);
+        let config = AdamWConfig { learning_rate: 1e-2, ..AdamWConfig::default() };
+        let mut optimizer = AdamW::new(&vars, config).unwrap();
+        let sequences = vec![vec![1, 2, 3, 4, 5, 6], vec![7, 8, 9]];
+
+        let initial = batch_loss(&model, &sequences).to_scalar::<f32>().unwrap();
+        for _ in 0..20 {
+            let loss = batch_loss(&model, &sequences);
+            optimizer.step(&loss.backward().unwrap()).unwrap();
+        }
+        let trained = batch_loss(&model, &sequences).to_scalar::<f32>().unwrap();
+
+        assert!(trained < initial, "loss went from {} to {}", initial, trained);
+        assert_eq!(optimizer.steps(), 20);
+    }
+
+    #[test]
+    fn test_lora_trains_only_adapters() {
+        let (base, adapters) = (VarMap::new(), VarMap::new());
+        let lora = LoraConfig { rank: 2, ..LoraConfig::default() };
+        let model = CausalLm::load_with_lora(
+            VarBuilder::from_varmap(&base, DType::F32, &Device::Cpu),
+            AdapterBuilder::new(&lora, VarBuilder::from_varmap(&adapters, DType::F32, &Device::Cpu)),
+            &tiny_config(),
+            16,
+        )
+        .unwrap();
+        // q_proj and v_proj in each of the two layers, two factors each.
+        assert_eq!(adapters.all_vars().len(), 8);
+
+        let config = AdamWConfig { learning_rate: 1e-2, ..AdamWConfig::default() };
+        let mut optimizer = AdamW::new(&adapters, config).unwrap();
+        let q_proj = || {
+            let vars = base.data().lock().unwrap();
+            vars["model.layers.0.self_attn.q_proj.weight"].flatten_all().unwrap().to_vec1::<f32>().unwrap()
+        };
+        let frozen = q_proj();
+        let sequences = vec![vec![1, 2, 3, 4, 5, 6]];
+        let initial = batch_loss(&model, &sequences).to_scalar::<f32>().unwrap();
+        for _ in 0..20 {
+            optimizer.step(&batch_loss(&model, &sequences).backward().unwrap()).unwrap();
+        }
+
+        assert!(batch_loss(&model, &sequences).to_scalar::<f32>().unwrap() < initial);
+        assert_eq!(q_proj(), frozen);
+    }
+
+    #[test]
+    fn test_averaged_gradients_replace_local_ones() {
+        let vars = VarMap::new();
+        let model = tiny_model(&vars);
+        let mut grads = batch_loss(&model, &[vec![1, 2, 3]]).backward().unwrap();
+        let mut gradients = gradients_of(&vars, &grads).unwrap();
+        assert_eq!(gradients.len(), vars.all_vars().len());
+
+        for gradient in &mut gradients {
+            gradient.values.iter_mut().for_each(|v| *v = 1.0);
+        }
+        replace_grads(&vars, &mut grads, gradients, &Device::Cpu).unwrap();
+        let replaced = gradients_of(&vars, &grads).unwrap();
+        assert!(replaced.iter().all(|g| g.values.iter().all(|v| *v == 1.0)));
+    }
+
+    #[test]
+    fn test_optimizer_checkpoint_round_trip() {
+        let vars = VarMap::new();
+        let model = tiny_model(&vars);
+        let mut optimizer = AdamW::new(&vars, AdamWConfig::default()).unwrap();
+        let loss = batch_loss(&model, &[vec![1, 2, 3]]);
+        optimizer.step(&loss.backward().unwrap()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(OPTIMIZER_CHECKPOINT_FILE);
+        optimizer.save(&path).unwrap();
+        let mut restored = AdamW::new(&vars, AdamWConfig::default()).unwrap();
+        restored.load(&path).unwrap();
+
+        assert_eq!(restored.steps(), 1);
+        for (saved, loaded) in optimizer.second_moments.iter().zip(&restored.second_moments) {
+            let saved = saved.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+            assert_eq!(saved, loaded.flatten_all().unwrap().to_vec1::<f32>().unwrap());
+        }
+    }
+}
@@ -0,0 +1,524 @@
+use clap::{Args, Parser, Subcommand};
+use log::{error, info, LevelFilter};
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use fractis_node::client::StakeClient;
+use fractis_node::node::identity::{load_keypair, save_keypair};
+use fractis_node::node::config::DEFAULT_LOG_LEVEL;
+use fractis_node::node::message::DEFAULT_GENERATION_TEMPERATURE;
+use fractis_node::node::{rpc, ConfigError, Node, NodeConfig};
+use fractis_node::utils::FRACTISAddress;
+
+type CliResult = Result<(), Box<dyn Error>>;
+
+/// Runs and operates a FRACTIS TestNet node.
+#[derive(Debug, Parser)]
+#[command(name = "fractis", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Where the node config comes from. Settings are layered: defaults, then
+/// the file, then `FRACTIS_*` environment variables, then these flags.
+#[derive(Debug, Args)]
+struct ConfigArgs {
+    /// Node configuration file.
+    #[arg(short, long, default_value = "config/node.toml")]
+    config: PathBuf,
+    /// Overrides `host`.
+    #[arg(long)]
+    host: Option<String>,
+    /// Overrides `port`.
+    #[arg(long)]
+    port: Option<u16>,
+    /// Overrides `rpc_url`.
+    #[arg(long)]
+    rpc_url: Option<String>,
+    /// Overrides any setting, e.g. `--set max_connections=20` or
+    /// `--set rpc.port=9000`. Repeatable.
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_override)]
+    overrides: Vec<(String, String)>,
+}
+
+impl ConfigArgs {
+    fn overrides(&self) -> Vec<(String, String)> {
+        let mut overrides = self.overrides.clone();
+        overrides.extend(self.host.clone().map(|host| ("host".to_string(), host)));
+        overrides.extend(self.port.map(|port| ("port".to_string(), port.to_string())));
+        overrides.extend(self.rpc_url.clone().map(|url| ("rpc_url".to_string(), url)));
+        overrides
+    }
+
+    fn load(&self) -> Result<NodeConfig, ConfigError> {
+        NodeConfig::load_layered(&self.config, std::env::vars(), &self.overrides())
+    }
+}
+
+fn parse_override(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", arg))
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the node.
+    #[command(subcommand)]
+    Node(NodeCommand),
+    /// Manage the node configuration file.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Generate a new identity keypair.
+    Keygen {
+        /// Where to write the keypair, in the `solana-keygen` JSON format.
+        #[arg(short, long, default_value = "./data/identity.json")]
+        out: PathBuf,
+        /// Replace an existing keypair file.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Stake or withdraw with the node's keypair.
+    #[command(subcommand)]
+    Stake(StakeCommand),
+    /// Work with FRACTIS addresses.
+    #[command(subcommand)]
+    Address(AddressCommand),
+    /// Inspect a running node's peers.
+    #[command(subcommand)]
+    Peer(PeerCommand),
+    /// Run the local model.
+    #[command(subcommand)]
+    Inference(InferenceCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum NodeCommand {
+    /// Start the node and run until interrupted.
+    Start(ConfigArgs),
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Write a default configuration file.
+    Init {
+        #[command(flatten)]
+        config: ConfigArgs,
+        /// Replace an existing configuration file.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum StakeCommand {
+    /// Create the stake account and deposit `amount` lamports.
+    Create {
+        #[command(flatten)]
+        config: ConfigArgs,
+        amount: u64,
+        /// Seconds the stake stays locked.
+        #[arg(long, default_value_t = 0)]
+        lock_period: i64,
+    },
+    /// Withdraw `amount` lamports once the lock has expired.
+    Withdraw {
+        #[command(flatten)]
+        config: ConfigArgs,
+        amount: u64,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AddressCommand {
+    /// Convert a base58 Solana public key to its FRACTIS address, or a
+    /// FRACTIS address back to the Solana key.
+    Convert { address: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum PeerCommand {
+    /// List the peers of the node serving the configured JSON-RPC API.
+    List(ConfigArgs),
+}
+
+#[derive(Debug, Subcommand)]
+enum InferenceCommand {
+    /// Generate a completion for `prompt` with the configured model.
+    Run {
+        #[command(flatten)]
+        config: ConfigArgs,
+        prompt: String,
+        #[arg(long, default_value_t = 256)]
+        max_tokens: usize,
+        #[command(flatten)]
+        sampling: SamplingArgs,
+    },
+}
+
+#[derive(Debug, Args)]
+#[cfg_attr(not(feature = "llm"), allow(dead_code))]
+struct SamplingArgs {
+    #[arg(long, default_value_t = DEFAULT_GENERATION_TEMPERATURE)]
+    temperature: f32,
+    #[arg(long)]
+    top_k: Option<usize>,
+    #[arg(long)]
+    top_p: Option<f64>,
+    #[arg(long, default_value_t = 1.0)]
+    repetition_penalty: f32,
+    /// Stop before this text. Repeatable.
+    #[arg(long)]
+    stop: Vec<String>,
+    /// Fixed RNG seed; the same seed and prompt give the same output.
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() {
+    init_logging();
+
+    let result = match Cli::parse().command {
+        Command::Node(NodeCommand::Start(config)) => start_node(&config).await,
+        Command::Config(ConfigCommand::Init { config, force }) => init_config(&config, force),
+        Command::Keygen { out, force } => keygen(&out, force),
+        Command::Stake(command) => stake(command).await,
+        Command::Address(AddressCommand::Convert { address }) => convert_address(&address),
+        Command::Peer(PeerCommand::List(config)) => list_peers(&config).await,
+        Command::Inference(InferenceCommand::Run { config, prompt, max_tokens, sampling }) => {
+            run_inference(&config, &prompt, max_tokens, sampling).await
+        }
+    };
+
+    if let Err(e) = result {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Without `RUST_LOG`, env_logger lets everything through and the global
+/// max level does the filtering, so `log_level` can be raised or lowered by
+/// a config reload.
+fn init_logging() {
+    if std::env::var_os("RUST_LOG").is_some() {
+        env_logger::init();
+    } else {
+        env_logger::Builder::new().filter_level(LevelFilter::Trace).init();
+        log::set_max_level(DEFAULT_LOG_LEVEL);
+    }
+}
+
+async fn start_node(args: &ConfigArgs) -> CliResult {
+    let config = args.load()?;
+    if let Some(level) = config.log_level() {
+        log::set_max_level(level);
+    }
+    let node = Arc::new(Node::new(config.clone()).await?);
+
+    #[cfg(feature = "llm")]
+    #[cfg_attr(not(feature = "api"), allow(unused_variables))]
+    let model = attach_llm(&node, &config)?;
+
+    #[cfg(feature = "api")]
+    {
+        #[cfg(feature = "llm")]
+        let generate = model
+            .zip(config.llm.as_ref())
+            .map(|(model, llm)| fractis_node::llm::server::router(model, llm.max_tokens));
+        #[cfg(not(feature = "llm"))]
+        let generate = None;
+        spawn_api_servers(&node, &config, generate);
+    }
+
+    let running = node.start();
+    tokio::pin!(running);
+    tokio::select! {
+        result = &mut running => return Ok(result?),
+        _ = tokio::signal::ctrl_c() => info!("Interrupted, shutting down"),
+        _ = reload_on_hangup(&node, args) => {}
+    }
+
+    for outcome in node.shutdown().await? {
+        info!("{:?}", outcome);
+    }
+    Ok(running.await?)
+}
+
+/// Reloads the config file into `node` on every SIGHUP for as long as the
+/// node runs; it never completes. A file that fails to load or validate is
+/// reported and the running config kept.
+async fn reload_on_hangup(node: &Node, args: &ConfigArgs) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangups) => {
+                while hangups.recv().await.is_some() {
+                    info!("SIGHUP received, reloading {}", args.config.display());
+                    match args.load() {
+                        Ok(config) => {
+                            node.reload_config(config).await;
+                        }
+                        Err(e) => error!("Keeping the running config, {} is invalid: {}", args.config.display(), e),
+                    }
+                }
+            }
+            Err(e) => error!("Config reload on SIGHUP unavailable: {}", e),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (node, args);
+    std::future::pending::<()>().await
+}
+
+/// Loads the configured model and serves peers' generation requests from it
+/// in batches.
+#[cfg(feature = "llm")]
+fn attach_llm(node: &Node, config: &NodeConfig) -> Result<Option<Arc<fractis_node::llm::LightLLM>>, Box<dyn Error>> {
+    use fractis_node::llm::{BatchQueue, LightLLM, RepetitionConfig};
+
+    if let Some(llm) = config.llm.as_ref().filter(|llm| llm.enabled) {
+        let mut model = LightLLM::from_config(llm)?;
+        if llm.repetition_guard {
+            model = model.with_repetition_guard(RepetitionConfig {
+                window: llm.repetition_window,
+                threshold: llm.repetition_threshold,
+            });
+        }
+        info!("Loaded {} model {} ({:?} weights)", model.arch().name(), model.version(), llm.weights_format());
+        let model = Arc::new(model);
+        node.attach_model(model.clone());
+        let queue = BatchQueue::spawn(model.clone(), Duration::from_millis(llm.batch_window_ms), llm.max_batch_size)
+            .with_model_version(model.model_id());
+        node.attach_generator(Arc::new(queue));
+        return Ok(Some(model));
+    }
+    Ok(None)
+}
+
+/// `generate` holds the streaming `/generate` route when a model is loaded;
+/// it is served next to JSON-RPC.
+#[cfg(feature = "api")]
+fn spawn_api_servers(node: &Arc<Node>, config: &NodeConfig, generate: Option<axum::Router>) {
+    use fractis_node::node::{control, json_rpc, metrics_endpoint};
+    use log::warn;
+
+    if let Some(api) = config.control_api.clone() {
+        let node = node.clone();
+        tokio::spawn(async move {
+            if let Err(e) = control::serve(node, &api).await {
+                warn!("Control API stopped: {}", e);
+            }
+        });
+    }
+    if let Some(rpc) = config.rpc.clone() {
+        let mut app = json_rpc::router(node.clone());
+        if let Some(generate) = generate {
+            app = app.merge(generate);
+        }
+        tokio::spawn(async move {
+            if let Err(e) = json_rpc::serve_router(app, &rpc).await {
+                warn!("JSON-RPC server stopped: {}", e);
+            }
+        });
+    }
+    if let Some(metrics) = config.metrics.clone() {
+        let node = node.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics_endpoint::serve(node, &metrics).await {
+                warn!("Metrics endpoint stopped: {}", e);
+            }
+        });
+    }
+}
+
+fn init_config(args: &ConfigArgs, force: bool) -> CliResult {
+    let path = &args.config;
+    if path.exists() && !force {
+        return Err(format!("{} already exists, pass --force to replace it", path.display()).into());
+    }
+    let config = NodeConfig::from_layers(
+        "keypair_path = \"./data/identity.json\"",
+        std::env::vars(),
+        &args.overrides(),
+    )?;
+    config.save(path)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+fn keygen(out: &Path, force: bool) -> CliResult {
+    if out.exists() && !force {
+        return Err(format!("{} already exists, pass --force to replace it", out.display()).into());
+    }
+    let keypair = Keypair::new();
+    save_keypair(&keypair, out)?;
+    println!("{}", keypair.pubkey());
+    Ok(())
+}
+
+async fn stake(command: StakeCommand) -> CliResult {
+    let (config, create) = match &command {
+        StakeCommand::Create { config, .. } => (config, true),
+        StakeCommand::Withdraw { config, .. } => (config, false),
+    };
+    let client = stake_client(&config.load()?)?;
+    info!(
+        "{} stake account {} for {}",
+        if create { "Funding" } else { "Withdrawing from" },
+        client.stake_address(),
+        client.staker()
+    );
+
+    let signature = match command {
+        StakeCommand::Create { amount, lock_period, .. } => client.create_stake(amount, lock_period).await?,
+        StakeCommand::Withdraw { amount, .. } => client.withdraw(amount).await?,
+    };
+    println!("{}", signature);
+    Ok(())
+}
+
+fn stake_client(config: &NodeConfig) -> Result<StakeClient, Box<dyn Error>> {
+    let keypair_path = config.keypair_path.as_deref().ok_or("staking requires keypair_path")?;
+    let program_id: Pubkey = config.stake_program_id
+        .as_deref()
+        .ok_or("staking requires stake_program_id")?
+        .parse()?;
+    let rpc_client = rpc::build_rpc_client(
+        config.rpc_endpoint(),
+        config.commitment,
+        Duration::from_millis(config.rpc_timeout_ms),
+        config.rpc_max_response_bytes,
+        None,
+    );
+    Ok(StakeClient::new(
+        Arc::new(rpc_client),
+        Arc::new(load_keypair(Path::new(keypair_path))?),
+        program_id,
+    ))
+}
+
+fn convert_address(address: &str) -> CliResult {
+    match address.parse::<Pubkey>() {
+        Ok(pubkey) => println!("{}", FRACTISAddress::from_pubkey(&pubkey)),
+        Err(_) => println!("{}", FRACTISAddress::from_string(address)?.to_solana()?),
+    }
+    Ok(())
+}
+
+async fn list_peers(args: &ConfigArgs) -> CliResult {
+    let config = args.load()?;
+    let server = config.rpc.as_ref().ok_or("peer list requires an [rpc] section in the node configuration")?;
+    let url = format!("http://{}:{}/", server.host, server.port);
+
+    let response: Value = reqwest::Client::new()
+        .post(&url)
+        .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": "getPeers" }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    if let Some(error) = response.get("error") {
+        return Err(format!("getPeers failed: {}", error).into());
+    }
+
+    let peers = response["result"].as_array().cloned().unwrap_or_default();
+    for peer in &peers {
+        println!(
+            "{}\t{}\t{}\tscore {}",
+            peer["addr"].as_str().unwrap_or("?"),
+            peer["node_id"].as_str().unwrap_or("-"),
+            if peer["connected"].as_bool() == Some(true) { "connected" } else { "disconnected" },
+            peer["score"],
+        );
+    }
+    println!("{} peers", peers.len());
+    Ok(())
+}
+
+#[cfg(feature = "llm")]
+async fn run_inference(args: &ConfigArgs, prompt: &str, max_tokens: usize, sampling: SamplingArgs) -> CliResult {
+    use fractis_node::llm::sampling::DEFAULT_SAMPLING_SEED;
+    use fractis_node::llm::{DecodeMode, LightLLM, SamplingParams, Truncation};
+
+    let config = args.load()?;
+    let llm = config.llm.as_ref().ok_or("inference requires an [llm] section in the node configuration")?;
+    let model = LightLLM::from_config(llm)?;
+    let sampling = SamplingParams {
+        temperature: sampling.temperature,
+        top_k: sampling.top_k,
+        top_p: sampling.top_p,
+        repetition_penalty: sampling.repetition_penalty,
+        stop: sampling.stop,
+        seed: sampling.seed.unwrap_or(DEFAULT_SAMPLING_SEED),
+    };
+    let output = model.generate(prompt, max_tokens, &sampling, DecodeMode::Lossy, Truncation::Clamp).await?;
+    println!("{}", output.text);
+    Ok(())
+}
+
+#[cfg(not(feature = "llm"))]
+async fn run_inference(_args: &ConfigArgs, _prompt: &str, _max_tokens: usize, _sampling: SamplingArgs) -> CliResult {
+    Err("this binary was built without the `llm` feature".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_subcommands_parse() {
+        let cli = Cli::parse_from(["fractis", "node", "start", "--config", "node.toml"]);
+        assert!(matches!(cli.command, Command::Node(NodeCommand::Start(ConfigArgs { config, .. })) if config == Path::new("node.toml")));
+
+        let cli = Cli::parse_from(["fractis", "stake", "create", "1000", "--lock-period", "60"]);
+        assert!(matches!(cli.command, Command::Stake(StakeCommand::Create { amount: 1000, lock_period: 60, .. })));
+
+        let cli = Cli::parse_from(["fractis", "peer", "list"]);
+        assert!(matches!(cli.command, Command::Peer(PeerCommand::List(ConfigArgs { config, .. })) if config == Path::new("config/node.toml")));
+    }
+
+    #[test]
+    fn test_config_flags_become_overrides() {
+        let cli = Cli::parse_from([
+            "fractis", "peer", "list", "--port", "9100", "--set", "rpc.port=9001", "--rpc-url", "http://rpc:8899",
+        ]);
+        let Command::Peer(PeerCommand::List(args)) = cli.command else { panic!("unexpected command") };
+        assert_eq!(
+            args.overrides(),
+            vec![
+                ("rpc.port".to_string(), "9001".to_string()),
+                ("port".to_string(), "9100".to_string()),
+                ("rpc_url".to_string(), "http://rpc:8899".to_string()),
+            ]
+        );
+        assert!(Cli::try_parse_from(["fractis", "peer", "list", "--set", "port"]).is_err());
+    }
+
+    #[test]
+    fn test_config_init_refuses_to_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let cli = Cli::parse_from(["fractis", "config", "init", "--config", dir.path().join("node.toml").to_str().unwrap()]);
+        let Command::Config(ConfigCommand::Init { config: args, .. }) = cli.command else { panic!("unexpected command") };
+        let path = args.config.clone();
+        init_config(&args, false).unwrap();
+        assert!(init_config(&args, false).is_err());
+        init_config(&args, true).unwrap();
+        let written: NodeConfig = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written.keypair_path.as_deref(), Some("./data/identity.json"));
+    }
+}
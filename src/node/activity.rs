@@ -0,0 +1,38 @@
+use serde::Serialize;
+use std::net::SocketAddr;
+
+pub const ACTIVITY_CHANNEL_CAPACITY: usize = 256;
+
+/// Something that happened on the node, published for outside observers
+/// such as WebSocket subscribers. Sending never blocks and is dropped when
+/// nobody is listening.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum Activity {
+    NewBlock { height: u64, hash: String, transactions: usize },
+    NewTransaction { signature: String },
+    PeerConnected { addr: SocketAddr },
+    PeerDisconnected { addr: SocketAddr },
+    InferenceCompleted { request_id: u64, succeeded: bool },
+}
+
+impl Activity {
+    /// The subscription topic this event is delivered under.
+    pub fn topic(&self) -> &'static str {
+        match self {
+            Activity::NewBlock { .. } => "newBlocks",
+            Activity::NewTransaction { .. } => "newTransactions",
+            Activity::PeerConnected { .. } => "peerConnected",
+            Activity::PeerDisconnected { .. } => "peerDisconnected",
+            Activity::InferenceCompleted { .. } => "inferenceCompleted",
+        }
+    }
+}
+
+pub const TOPICS: [&str; 5] = [
+    "newBlocks",
+    "newTransactions",
+    "peerConnected",
+    "peerDisconnected",
+    "inferenceCompleted",
+];
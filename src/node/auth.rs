@@ -0,0 +1,236 @@
+use async_trait::async_trait;
+use log::debug;
+use parking_lot::Mutex;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::node::framing::{read_message, write_message, FrameError};
+use crate::node::message::Message;
+use crate::node::stake_check::{verify_stake_account, AccountFetcher};
+use crate::node::transport::Connection;
+
+const AUTH_DOMAIN: &[u8] = b"fractis-peer-auth-v1";
+pub const DEFAULT_ADMISSION_CACHE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("Framing error: {0}")]
+    Frame(#[from] FrameError),
+    #[error("Peer closed the connection during authentication")]
+    Closed,
+    #[error("Expected an auth message, got {0}")]
+    Unexpected(String),
+    #[error("Malformed peer identity: {0}")]
+    Malformed(String),
+    #[error("Peer {0} presented an invalid signature")]
+    BadSignature(Pubkey),
+    #[error("Peer {0} refused: {1}")]
+    Refused(Pubkey, String),
+}
+
+/// Decides whether an authenticated peer identity may join.
+#[async_trait]
+pub trait PeerAuthenticator: Send + Sync {
+    async fn admit(&self, pubkey: &Pubkey) -> Result<(), String>;
+}
+
+/// Admits any peer that proves ownership of its key.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenAdmission;
+
+#[async_trait]
+impl PeerAuthenticator for OpenAdmission {
+    async fn admit(&self, _pubkey: &Pubkey) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Admits peers holding an active on-chain stake of at least `min_stake`.
+/// Verdicts are cached for `ttl` so reconnect storms don't hammer the RPC node.
+pub struct StakeGate {
+    fetcher: Arc<dyn AccountFetcher>,
+    program_id: Pubkey,
+    min_stake: u64,
+    ttl: Duration,
+    cache: Mutex<HashMap<Pubkey, (Result<(), String>, Instant)>>,
+}
+
+impl StakeGate {
+    pub fn new(fetcher: Arc<dyn AccountFetcher>, program_id: Pubkey, min_stake: u64) -> Self {
+        StakeGate {
+            fetcher,
+            program_id,
+            min_stake,
+            ttl: DEFAULT_ADMISSION_CACHE_TTL,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+}
+
+#[async_trait]
+impl PeerAuthenticator for StakeGate {
+    async fn admit(&self, pubkey: &Pubkey) -> Result<(), String> {
+        if let Some((verdict, checked_at)) = self.cache.lock().get(pubkey) {
+            if checked_at.elapsed() < self.ttl {
+                return verdict.clone();
+            }
+        }
+
+        let verdict = verify_stake_account(self.fetcher.as_ref(), &self.program_id, pubkey, self.min_stake)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string());
+        debug!("Stake admission for {}: {:?}", pubkey, verdict);
+
+        self.cache.lock().insert(*pubkey, (verdict.clone(), Instant::now()));
+        verdict
+    }
+}
+
+fn auth_payload(challenge: u64, binding: &[u8]) -> Vec<u8> {
+    let mut payload = AUTH_DOMAIN.to_vec();
+    payload.extend_from_slice(&challenge.to_le_bytes());
+    payload.extend_from_slice(binding);
+    payload
+}
+
+/// Proves our identity by signing the peer's handshake challenge, then checks
+/// the peer's proof against our own challenge and asks `authenticator` whether
+/// to admit it.
+///
+/// `binding` is the encrypted channel's handshake hash (empty for plain
+/// connections). Both sides sign it, so a proof relayed by a man in the
+/// middle, who necessarily holds a different session, fails to verify.
+pub async fn authenticate<C: Connection>(
+    conn: &mut C,
+    keypair: &Keypair,
+    local_challenge: u64,
+    remote_challenge: u64,
+    binding: &[u8],
+    authenticator: &dyn PeerAuthenticator,
+) -> Result<Pubkey, AuthError> {
+    let signature = keypair.sign_message(&auth_payload(remote_challenge, binding));
+    write_message(conn, &Message::Auth {
+        pubkey: keypair.pubkey().to_bytes(),
+        signature: signature.as_ref().to_vec(),
+    }).await?;
+
+    let (pubkey, signature) = match read_message(conn).await? {
+        Some(Message::Auth { pubkey, signature }) => (pubkey, signature),
+        Some(other) => return Err(AuthError::Unexpected(format!("{:?}", other))),
+        None => return Err(AuthError::Closed),
+    };
+
+    let pubkey = Pubkey::new_from_array(pubkey);
+    let signature = Signature::try_from(signature.as_slice())
+        .map_err(|_| AuthError::Malformed(format!("signature from {} has the wrong length", pubkey)))?;
+    if !signature.verify(pubkey.as_ref(), &auth_payload(local_challenge, binding)) {
+        return Err(AuthError::BadSignature(pubkey));
+    }
+
+    authenticator
+        .admit(&pubkey)
+        .await
+        .map_err(|reason| AuthError::Refused(pubkey, reason))?;
+    Ok(pubkey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::stake_check::testing::StakeLedger;
+    use crate::node::transport::MemoryConnection;
+    use std::net::SocketAddr;
+    use std::sync::atomic::Ordering;
+
+    fn ledger_with(staked: &Keypair) -> Arc<StakeLedger> {
+        Arc::new(StakeLedger::new(Pubkey::new_unique()).with_stake(staked.pubkey(), 20_000_000_000))
+    }
+
+    async fn connect(
+        node: &Keypair,
+        peer: &Keypair,
+        gate: &dyn PeerAuthenticator,
+    ) -> Result<Pubkey, AuthError> {
+        let (mut a, mut b) = MemoryConnection::pair(
+            SocketAddr::from(([10, 0, 0, 1], 8000)),
+            SocketAddr::from(([10, 0, 0, 2], 8001)),
+        );
+        let (node_side, _) = tokio::join!(
+            authenticate(&mut a, node, 11, 22, &[], gate),
+            authenticate(&mut b, peer, 22, 11, &[], &OpenAdmission),
+        );
+        node_side
+    }
+
+    #[tokio::test]
+    async fn test_stake_gate_admits_staked_and_refuses_unstaked() {
+        let node = Keypair::new();
+        let staked = Keypair::new();
+        let unstaked = Keypair::new();
+        let ledger = ledger_with(&staked);
+        let gate = StakeGate::new(ledger.clone(), ledger.program_id, 10_000_000_000);
+
+        assert_eq!(connect(&node, &staked, &gate).await.unwrap(), staked.pubkey());
+        assert!(matches!(
+            connect(&node, &unstaked, &gate).await,
+            Err(AuthError::Refused(pubkey, _)) if pubkey == unstaked.pubkey()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stake_verdicts_are_cached() {
+        let node = Keypair::new();
+        let staked = Keypair::new();
+        let ledger = ledger_with(&staked);
+        let gate = StakeGate::new(ledger.clone(), ledger.program_id, 1);
+
+        for _ in 0..3 {
+            connect(&node, &staked, &gate).await.unwrap();
+        }
+        assert_eq!(ledger.fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_signature_over_wrong_challenge_rejected() {
+        let node = Keypair::new();
+        let peer = Keypair::new();
+        let (mut a, mut b) = MemoryConnection::pair(
+            SocketAddr::from(([10, 0, 0, 1], 8000)),
+            SocketAddr::from(([10, 0, 0, 2], 8001)),
+        );
+
+        // The peer signs a stale challenge instead of the one we issued.
+        let (node_side, _) = tokio::join!(
+            authenticate(&mut a, &node, 11, 22, &[], &OpenAdmission),
+            authenticate(&mut b, &peer, 22, 99, &[], &OpenAdmission),
+        );
+        assert!(matches!(node_side, Err(AuthError::BadSignature(_))));
+    }
+
+    #[tokio::test]
+    async fn test_signature_bound_to_other_channel_rejected() {
+        let node = Keypair::new();
+        let peer = Keypair::new();
+        let (mut a, mut b) = MemoryConnection::pair(
+            SocketAddr::from(([10, 0, 0, 1], 8000)),
+            SocketAddr::from(([10, 0, 0, 2], 8001)),
+        );
+
+        // A relayed proof carries the binding of the relay's own session.
+        let (node_side, _) = tokio::join!(
+            authenticate(&mut a, &node, 11, 22, b"session-a", &OpenAdmission),
+            authenticate(&mut b, &peer, 22, 11, b"session-b", &OpenAdmission),
+        );
+        assert!(matches!(node_side, Err(AuthError::BadSignature(_))));
+    }
+}
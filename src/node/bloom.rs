@@ -0,0 +1,170 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+pub const DEFAULT_SEEN_CAPACITY: usize = 100_000;
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.001;
+
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    bit_count: usize,
+    hash_count: u32,
+    items: usize,
+}
+
+impl BloomFilter {
+    pub fn with_rate(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+
+        let bit_count = (-(capacity * rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hash_count = ((bit_count as f64 / capacity) * ln2).round().max(1.0) as u32;
+
+        BloomFilter {
+            bits: vec![0; (bit_count + 63) / 64],
+            bit_count,
+            hash_count,
+            items: 0,
+        }
+    }
+
+    fn hashes<T: Hash + ?Sized>(item: &T) -> (u64, u64) {
+        let mut first = DefaultHasher::new();
+        item.hash(&mut first);
+        let h1 = first.finish();
+
+        let mut second = DefaultHasher::new();
+        h1.hash(&mut second);
+        item.hash(&mut second);
+        (h1, second.finish() | 1)
+    }
+
+    fn positions<T: Hash + ?Sized>(&self, item: &T) -> impl Iterator<Item = usize> {
+        let (h1, h2) = Self::hashes(item);
+        let bit_count = self.bit_count as u64;
+        (0..self.hash_count as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bit_count) as usize)
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        let positions: Vec<usize> = self.positions(item).collect();
+        for pos in positions {
+            self.bits[pos / 64] |= 1 << (pos % 64);
+        }
+        self.items += 1;
+    }
+
+    pub fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        self.positions(item).all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        self.bits.len() * std::mem::size_of::<u64>()
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.iter_mut().for_each(|word| *word = 0);
+        self.items = 0;
+    }
+}
+
+/// Two-generation bloom filter: once the current generation reaches its
+/// capacity it becomes the previous one, so memory stays fixed while recent
+/// announcements are still recognised.
+#[derive(Debug, Clone)]
+pub struct SeenFilter {
+    current: BloomFilter,
+    previous: BloomFilter,
+    generation_capacity: usize,
+}
+
+impl SeenFilter {
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let generation_capacity = (capacity / 2).max(1);
+        SeenFilter {
+            current: BloomFilter::with_rate(generation_capacity, false_positive_rate),
+            previous: BloomFilter::with_rate(generation_capacity, false_positive_rate),
+            generation_capacity,
+        }
+    }
+
+    /// Records the item and returns `true` if it had not been seen before.
+    pub fn check_and_insert<T: Hash + ?Sized>(&mut self, item: &T) -> bool {
+        if self.current.contains(item) || self.previous.contains(item) {
+            return false;
+        }
+
+        if self.current.len() >= self.generation_capacity {
+            std::mem::swap(&mut self.current, &mut self.previous);
+            self.current.clear();
+        }
+        self.current.insert(item);
+        true
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        self.current.memory_bytes() + self.previous.memory_bytes()
+    }
+}
+
+impl Default for SeenFilter {
+    fn default() -> Self {
+        SeenFilter::new(DEFAULT_SEEN_CAPACITY, DEFAULT_FALSE_POSITIVE_RATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_false_negatives() {
+        let mut filter = BloomFilter::with_rate(10_000, 0.01);
+        for i in 0..10_000 {
+            filter.insert(&format!("node-{}", i));
+        }
+        assert!((0..10_000).all(|i| filter.contains(&format!("node-{}", i))));
+    }
+
+    #[test]
+    fn test_false_positive_rate_within_bounds() {
+        let mut filter = BloomFilter::with_rate(10_000, 0.01);
+        for i in 0..10_000 {
+            filter.insert(&format!("node-{}", i));
+        }
+
+        let false_positives = (10_000..110_000)
+            .filter(|i| filter.contains(&format!("node-{}", i)))
+            .count();
+        assert!(false_positives < 2_000, "false positive rate too high: {}", false_positives);
+    }
+
+    #[test]
+    fn test_seen_filter_dedups_announcements() {
+        let mut seen = SeenFilter::new(1_000, 0.001);
+        assert!(seen.check_and_insert("node-a@10.0.0.1:8000"));
+        assert!(!seen.check_and_insert("node-a@10.0.0.1:8000"));
+        assert!(seen.check_and_insert("node-b@10.0.0.2:8000"));
+    }
+
+    #[test]
+    fn test_seen_filter_memory_stays_bounded() {
+        let mut seen = SeenFilter::new(1_000, 0.01);
+        let initial = seen.memory_bytes();
+
+        for i in 0..100_000 {
+            seen.check_and_insert(&i);
+        }
+
+        assert_eq!(seen.memory_bytes(), initial);
+        assert!(!seen.check_and_insert(&99_999));
+    }
+}
@@ -0,0 +1,144 @@
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockStatus {
+    /// Not enough peer samples to judge yet.
+    Unknown,
+    InSync { offset_ms: i64 },
+    Skewed { offset_ms: i64 },
+}
+
+/// Estimates the local clock offset from the median of peer clocks, using
+/// timestamps carried in `Pong` replies.
+#[derive(Debug)]
+pub struct ClockSkewMonitor {
+    max_offset_ms: i64,
+    abstain_when_skewed: bool,
+    min_samples: usize,
+    offsets: HashMap<SocketAddr, i64>,
+    status: ClockStatus,
+}
+
+impl ClockSkewMonitor {
+    pub fn new(max_offset: Duration, abstain_when_skewed: bool) -> Self {
+        ClockSkewMonitor {
+            max_offset_ms: max_offset.as_millis() as i64,
+            abstain_when_skewed,
+            min_samples: 3,
+            offsets: HashMap::new(),
+            status: ClockStatus::Unknown,
+        }
+    }
+
+    pub fn with_min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = min_samples.max(1);
+        self
+    }
+
+    /// Records a pong from `peer`. `peer_unix_ms` is the peer's clock when it
+    /// replied; the reply is assumed to have been sent half an RTT ago.
+    pub fn record(&mut self, peer: SocketAddr, peer_unix_ms: i64, local_unix_ms: i64, rtt: Duration) {
+        let offset = peer_unix_ms + (rtt.as_millis() / 2) as i64 - local_unix_ms;
+        debug!("Peer {} clock offset {}ms", peer, offset);
+        self.offsets.insert(peer, offset);
+    }
+
+    pub fn forget(&mut self, peer: &SocketAddr) {
+        self.offsets.remove(peer);
+    }
+
+    /// Median of peer clocks relative to ours. Positive means we are behind.
+    pub fn median_offset_ms(&self) -> Option<i64> {
+        if self.offsets.len() < self.min_samples {
+            return None;
+        }
+        let mut offsets: Vec<i64> = self.offsets.values().copied().collect();
+        offsets.sort_unstable();
+        let mid = offsets.len() / 2;
+        Some(if offsets.len() % 2 == 0 {
+            (offsets[mid - 1] + offsets[mid]) / 2
+        } else {
+            offsets[mid]
+        })
+    }
+
+    pub fn evaluate(&mut self) -> ClockStatus {
+        self.status = match self.median_offset_ms() {
+            None => ClockStatus::Unknown,
+            Some(offset_ms) if offset_ms.abs() > self.max_offset_ms => {
+                warn!(
+                    "Local clock is {}ms off the peer median (limit {}ms){}",
+                    offset_ms,
+                    self.max_offset_ms,
+                    if self.abstain_when_skewed { ", abstaining from consensus" } else { "" }
+                );
+                ClockStatus::Skewed { offset_ms }
+            }
+            Some(offset_ms) => ClockStatus::InSync { offset_ms },
+        };
+        self.status
+    }
+
+    pub fn status(&self) -> ClockStatus {
+        self.status
+    }
+
+    pub fn may_participate(&self) -> bool {
+        !(self.abstain_when_skewed && matches!(self.status, ClockStatus::Skewed { .. }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(i: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, i], 8000))
+    }
+
+    const LOCAL_NOW: i64 = 1_700_000_000_000;
+
+    #[test]
+    fn test_skewed_local_clock_triggers_abstention() {
+        let mut monitor = ClockSkewMonitor::new(Duration::from_millis(500), true);
+        // Every peer is ~5s ahead, so our own clock is the skewed one.
+        for i in 1..=5 {
+            monitor.record(peer(i), LOCAL_NOW + 5_000 + i as i64 * 10, LOCAL_NOW, Duration::from_millis(40));
+        }
+
+        assert!(matches!(monitor.evaluate(), ClockStatus::Skewed { offset_ms } if offset_ms > 5_000));
+        assert!(!monitor.may_participate());
+    }
+
+    #[test]
+    fn test_single_skewed_peer_does_not_move_median() {
+        let mut monitor = ClockSkewMonitor::new(Duration::from_millis(500), true);
+        monitor.record(peer(1), LOCAL_NOW + 30, LOCAL_NOW, Duration::from_millis(20));
+        monitor.record(peer(2), LOCAL_NOW - 20, LOCAL_NOW, Duration::from_millis(20));
+        monitor.record(peer(3), LOCAL_NOW + 60_000, LOCAL_NOW, Duration::from_millis(20));
+
+        assert!(matches!(monitor.evaluate(), ClockStatus::InSync { .. }));
+        assert!(monitor.may_participate());
+    }
+
+    #[test]
+    fn test_warn_only_mode_keeps_participating() {
+        let mut monitor = ClockSkewMonitor::new(Duration::from_millis(500), false).with_min_samples(1);
+        monitor.record(peer(1), LOCAL_NOW - 2_000, LOCAL_NOW, Duration::ZERO);
+
+        assert_eq!(monitor.evaluate(), ClockStatus::Skewed { offset_ms: -2_000 });
+        assert!(monitor.may_participate());
+    }
+
+    #[test]
+    fn test_too_few_samples_is_unknown() {
+        let mut monitor = ClockSkewMonitor::new(Duration::from_millis(500), true);
+        monitor.record(peer(1), LOCAL_NOW + 9_000, LOCAL_NOW, Duration::ZERO);
+
+        assert_eq!(monitor.evaluate(), ClockStatus::Unknown);
+        assert!(monitor.may_participate());
+    }
+}
@@ -1,10 +1,44 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
-use std::net::ToSocketAddrs;
-use log::{warn, error};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use log::{warn, error, LevelFilter};
 use thiserror::Error;
 
+use crate::node::auth::DEFAULT_ADMISSION_CACHE_TTL;
+use crate::node::bloom::{DEFAULT_FALSE_POSITIVE_RATE, DEFAULT_SEEN_CAPACITY};
+use crate::node::consensus::{DEFAULT_FUTURE_TOLERANCE, DEFAULT_MAX_TRANSACTION_BYTES, DEFAULT_PROPOSER_FALLBACK_TIMEOUT};
+use crate::node::dedup::{DEFAULT_DEDUP_CAPACITY, DEFAULT_DEDUP_TTL};
+use crate::node::epoch::RewardWeights;
+use crate::node::inbound::InboundLimits;
+use crate::node::mempool::DEFAULT_MEMPOOL_CAPACITY;
+use crate::node::replay::DEFAULT_REPLAY_WINDOW;
+use crate::node::reputation::{BanPolicy, MIN_SCORE};
+use crate::node::throttle::SendLimit;
+
+pub const MAINNET_RPC_URL: &str = "https://api.mainnet-beta.solana.com";
+pub const TESTNET_RPC_URL: &str = "https://api.testnet.solana.com";
+pub const DEVNET_RPC_URL: &str = "https://api.devnet.solana.com";
+
+/// Prefix of environment variables overriding config settings, such as
+/// `FRACTIS_PORT=9000`. A double underscore reaches into a section:
+/// `FRACTIS_RPC__PORT` sets `rpc.port`.
+pub const ENV_PREFIX: &str = "FRACTIS_";
+
+/// Log level without `log_level` in the config or `RUST_LOG` in the
+/// environment.
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// Settings a running node picks up from a reloaded config file. Changes to
+/// anything else are reported and wait for a restart.
+pub const RELOADABLE_FIELDS: [&str; 5] = [
+    "log_level",
+    "max_connections",
+    "bootstrap_nodes",
+    "disconnect_removed_bootstrap",
+    "consensus_timeout",
+];
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("IO error: {0}")]
@@ -17,6 +51,132 @@ pub enum ConfigError {
     InvalidBootstrapNode(String),
     #[error("Storage path error: {0}")]
     StoragePath(String),
+    #[error("Invalid configuration value: {0}")]
+    InvalidValue(String),
+    #[error("Conflicting configuration: {0}")]
+    Conflict(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    #[default]
+    Validator,
+    Observer,
+}
+
+/// Solana cluster the node reads stake and slot data from. `Custom` takes
+/// its endpoint from `rpc_url`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Cluster {
+    #[default]
+    Mainnet,
+    Testnet,
+    Devnet,
+    Custom,
+}
+
+impl Cluster {
+    pub fn default_rpc_url(&self) -> Option<&'static str> {
+        match self {
+            Cluster::Mainnet => Some(MAINNET_RPC_URL),
+            Cluster::Testnet => Some(TESTNET_RPC_URL),
+            Cluster::Devnet => Some(DEVNET_RPC_URL),
+            Cluster::Custom => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Commitment {
+    Processed,
+    #[default]
+    Confirmed,
+    Finalized,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    #[default]
+    Plain,
+    Noise,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FramingVersion {
+    V1,
+    #[default]
+    V2,
+}
+
+/// How quickly a quiet peer is probed, declared idle and swept away.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct LivenessTimeouts {
+    /// Idle time before the OS starts TCP keepalive probes.
+    pub keepalive_secs: u64,
+    /// Interval between latency pings to each peer.
+    pub heartbeat_secs: u64,
+    /// Silence after which a peer is considered gone.
+    pub idle_secs: u64,
+    /// Interval between sweeps that drop disconnected and idle peers.
+    pub reap_secs: u64,
+    /// Consecutive unanswered heartbeats after which a peer is marked
+    /// disconnected.
+    #[serde(default = "default_max_missed_heartbeats")]
+    pub max_missed_heartbeats: u32,
+}
+
+/// Named liveness presets. `Lenient` suits peers on flaky or mobile links,
+/// `Aggressive` frees slots held by dead datacenter peers quickly.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LivenessProfile {
+    Lenient,
+    #[default]
+    Balanced,
+    Aggressive,
+    Custom(LivenessTimeouts),
+}
+
+impl LivenessProfile {
+    pub fn timeouts(&self) -> LivenessTimeouts {
+        match self {
+            LivenessProfile::Lenient => LivenessTimeouts {
+                keepalive_secs: 120,
+                heartbeat_secs: 30,
+                idle_secs: 300,
+                reap_secs: 120,
+                max_missed_heartbeats: 5,
+            },
+            LivenessProfile::Balanced => LivenessTimeouts {
+                keepalive_secs: 60,
+                heartbeat_secs: 15,
+                idle_secs: 90,
+                reap_secs: 60,
+                max_missed_heartbeats: 3,
+            },
+            LivenessProfile::Aggressive => LivenessTimeouts {
+                keepalive_secs: 20,
+                heartbeat_secs: 5,
+                idle_secs: 20,
+                reap_secs: 10,
+                max_missed_heartbeats: 2,
+            },
+            LivenessProfile::Custom(timeouts) => *timeouts,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -29,16 +189,604 @@ pub struct NodeConfig {
     pub consensus_timeout: u64,   
     pub bootstrap_nodes: Vec<String>, 
     #[serde(default)]
+    pub disconnect_removed_bootstrap: bool,
+    #[serde(default = "default_min_stake")]
+    pub min_stake: u64,
+    #[serde(default)]
+    pub stake_program_id: Option<String>,
+    #[serde(default)]
+    pub keypair_path: Option<String>,
+    #[serde(default)]
+    pub stake_gated_peers: bool,
+    #[serde(default = "default_peer_admission_cache_secs")]
+    pub peer_admission_cache_secs: u64,
+    #[serde(default = "default_min_validator_lock_secs")]
+    pub min_validator_lock_secs: u64,
+    #[serde(default = "default_max_transaction_bytes")]
+    pub max_transaction_bytes: usize,
+    #[serde(default = "default_timestamp_future_tolerance_ms")]
+    pub timestamp_future_tolerance_ms: u64,
+    #[serde(default = "default_mempool_capacity")]
+    pub mempool_capacity: usize,
+    #[serde(default = "default_mempool_backpressure_high")]
+    pub mempool_backpressure_high: f64,
+    #[serde(default = "default_mempool_backpressure_low")]
+    pub mempool_backpressure_low: f64,
+    #[serde(default = "default_mempool_max_age_secs")]
+    pub mempool_max_age_secs: u64,
+    #[serde(default = "default_mempool_persist_interval_secs")]
+    pub mempool_persist_interval_secs: u64,
+    #[serde(default = "default_broadcast_replay_window")]
+    pub broadcast_replay_window: usize,
+    #[serde(default = "default_slot_duration_ms")]
+    pub slot_duration_ms: u64,
+    #[serde(default = "default_proposer_fallback_timeout_ms")]
+    pub proposer_fallback_timeout_ms: u64,
+    #[serde(default = "default_epoch_slots")]
+    pub epoch_slots: u64,
+    #[serde(default = "default_accept_concurrency")]
+    pub accept_concurrency: usize,
+    #[serde(default = "default_max_inbound_per_ip")]
+    pub max_inbound_per_ip: usize,
+    #[serde(default = "default_inbound_attempts_per_ip_per_min")]
+    pub inbound_attempts_per_ip_per_min: u32,
+    #[serde(default = "default_gossip_seen_capacity")]
+    pub gossip_seen_capacity: usize,
+    #[serde(default = "default_gossip_seen_fp_rate")]
+    pub gossip_seen_fp_rate: f64,
+    #[serde(default = "default_gossip_dedup_capacity")]
+    pub gossip_dedup_capacity: usize,
+    #[serde(default = "default_gossip_dedup_ttl_secs")]
+    pub gossip_dedup_ttl_secs: u64,
+    #[serde(default = "default_max_clock_offset_ms")]
+    pub max_clock_offset_ms: u64,
+    #[serde(default)]
+    pub abstain_on_clock_skew: bool,
+    #[serde(default = "default_min_ready_peers")]
+    pub min_ready_peers: usize,
+    #[serde(default = "default_peer_discovery")]
+    pub peer_discovery: bool,
+    #[serde(default = "default_peer_exchange_interval_secs")]
+    pub peer_exchange_interval_secs: u64,
+    #[serde(default = "default_peer_ban_score")]
+    pub peer_ban_score: i32,
+    #[serde(default = "default_peer_ban_secs")]
+    pub peer_ban_secs: u64,
+    #[serde(default)]
+    pub peer_send_bytes_per_sec: Option<u64>,
+    #[serde(default = "default_peer_send_burst_bytes")]
+    pub peer_send_burst_bytes: u64,
+    #[serde(default = "default_shutdown_phase_timeout_ms")]
+    pub shutdown_phase_timeout_ms: u64,
+    /// Catch up from a peer snapshot on startup when the local chain is
+    /// behind the network.
+    #[serde(default = "default_state_sync")]
+    pub state_sync: bool,
+    /// Blocks between the snapshots this node serves to joining peers.
+    #[serde(default = "default_snapshot_interval_blocks")]
+    pub snapshot_interval_blocks: u64,
+    #[serde(default = "default_state_sync_timeout_secs")]
+    pub state_sync_timeout_secs: u64,
+    #[serde(default)]
+    pub cluster: Cluster,
+    #[serde(default)]
+    pub rpc_url: Option<String>,
+    #[serde(default)]
+    pub commitment: Commitment,
+    #[serde(default = "default_rpc_timeout_ms")]
+    pub rpc_timeout_ms: u64,
+    #[serde(default = "default_rpc_max_response_bytes")]
+    pub rpc_max_response_bytes: usize,
+    #[serde(default)]
+    pub role: NodeRole,
+    #[serde(default)]
+    pub transport: TransportKind,
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default)]
+    pub framing: FramingVersion,
+    #[serde(default)]
+    pub liveness: LivenessProfile,
+    #[serde(default)]
+    pub propose_blocks: Option<bool>,
+    #[serde(default)]
     pub llm: Option<LLMConfig>,
+    /// Fraction of submitted inference jobs re-run on a second validator
+    /// to catch executors returning wrong output.
+    #[serde(default = "default_inference_verification_rate")]
+    pub inference_verification_rate: f64,
+    #[serde(default)]
+    pub control_api: Option<ControlApiConfig>,
+    #[serde(default)]
+    pub rpc: Option<RpcServerConfig>,
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
+    #[serde(default)]
+    pub nat: Option<NatConfig>,
+    #[serde(default)]
+    pub epoch: Option<EpochConfig>,
+    /// Caps logging at `error`, `warn`, `info`, `debug` or `trace`.
+    #[serde(default)]
+    pub log_level: Option<String>,
+}
+
+fn top_level(key: &str) -> &str {
+    key.split('.').next().unwrap_or(key)
+}
+
+/// Overlays `overlay` onto `base`, merging tables key by key and replacing
+/// everything else.
+fn merge_toml(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_toml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Sets the dotted `key` under `root`, creating sections as needed. A
+/// setting that currently holds a string keeps `raw` as a string, so a
+/// `node_id` of `123` stays text.
+fn set_toml_path(root: &mut toml::Value, key: &str, raw: &str) {
+    let mut segments: Vec<&str> = key.split('.').collect();
+    let Some(last) = segments.pop() else { return };
+    let mut table = root;
+    for segment in segments {
+        let toml::Value::Table(current) = table else { return };
+        table = current
+            .entry(segment.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    }
+    let toml::Value::Table(current) = table else { return };
+
+    let value = match current.get(last) {
+        Some(toml::Value::String(_)) => toml::Value::String(raw.to_string()),
+        _ => toml::from_str::<toml::Table>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+            .unwrap_or_else(|| toml::Value::String(raw.to_string())),
+    };
+    current.insert(last.to_string(), value);
+}
+
+fn default_max_missed_heartbeats() -> u32 {
+    3
+}
+
+fn default_min_stake() -> u64 {
+    10_000_000_000
+}
+
+fn default_peer_admission_cache_secs() -> u64 {
+    DEFAULT_ADMISSION_CACHE_TTL.as_secs()
+}
+
+fn default_min_validator_lock_secs() -> u64 {
+    86_400
+}
+
+fn default_max_transaction_bytes() -> usize {
+    DEFAULT_MAX_TRANSACTION_BYTES
+}
+
+fn default_timestamp_future_tolerance_ms() -> u64 {
+    DEFAULT_FUTURE_TOLERANCE.as_millis() as u64
+}
+
+fn default_mempool_capacity() -> usize {
+    DEFAULT_MEMPOOL_CAPACITY
+}
+
+fn default_mempool_backpressure_high() -> f64 {
+    0.9
+}
+
+fn default_mempool_backpressure_low() -> f64 {
+    0.7
+}
+
+fn default_peer_send_burst_bytes() -> u64 {
+    64 * 1024
+}
+
+fn default_mempool_max_age_secs() -> u64 {
+    300
+}
+
+fn default_mempool_persist_interval_secs() -> u64 {
+    30
+}
+
+fn default_broadcast_replay_window() -> usize {
+    DEFAULT_REPLAY_WINDOW
+}
+
+fn default_slot_duration_ms() -> u64 {
+    1000
+}
+
+fn default_proposer_fallback_timeout_ms() -> u64 {
+    DEFAULT_PROPOSER_FALLBACK_TIMEOUT.as_millis() as u64
+}
+
+fn default_epoch_slots() -> u64 {
+    3600
+}
+
+fn default_max_inbound_per_ip() -> usize {
+    4
+}
+
+fn default_inbound_attempts_per_ip_per_min() -> u32 {
+    30
+}
+
+fn default_accept_concurrency() -> usize {
+    64
+}
+
+fn default_gossip_seen_capacity() -> usize {
+    DEFAULT_SEEN_CAPACITY
+}
+
+fn default_gossip_seen_fp_rate() -> f64 {
+    DEFAULT_FALSE_POSITIVE_RATE
+}
+
+fn default_gossip_dedup_capacity() -> usize {
+    DEFAULT_DEDUP_CAPACITY
+}
+
+fn default_gossip_dedup_ttl_secs() -> u64 {
+    DEFAULT_DEDUP_TTL.as_secs()
+}
+
+fn default_max_clock_offset_ms() -> u64 {
+    1000
+}
+
+fn default_peer_ban_score() -> i32 {
+    -50
+}
+
+fn default_peer_ban_secs() -> u64 {
+    3600
+}
+
+fn default_peer_discovery() -> bool {
+    true
+}
+
+fn default_peer_exchange_interval_secs() -> u64 {
+    30
+}
+
+fn default_min_ready_peers() -> usize {
+    1
+}
+
+fn default_shutdown_phase_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_state_sync() -> bool {
+    true
+}
+
+fn default_snapshot_interval_blocks() -> u64 {
+    1000
+}
+
+fn default_state_sync_timeout_secs() -> u64 {
+    10
+}
+
+fn default_inference_verification_rate() -> f64 {
+    0.05
+}
+
+fn default_rpc_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_rpc_max_response_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+/// Model family and size of the weights at `llm.model_path`. An unknown
+/// name fails to parse with the list of supported ones.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ModelArch {
+    #[default]
+    #[serde(rename = "llama2-7b")]
+    Llama2_7B,
+    #[serde(rename = "llama3-8b")]
+    Llama3_8B,
+    #[serde(rename = "mistral-7b")]
+    Mistral7B,
+    #[serde(rename = "tinyllama-1.1b")]
+    TinyLlama1_1B,
+}
+
+impl ModelArch {
+    pub const ALL: [ModelArch; 4] = [
+        ModelArch::Llama2_7B,
+        ModelArch::Llama3_8B,
+        ModelArch::Mistral7B,
+        ModelArch::TinyLlama1_1B,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModelArch::Llama2_7B => "llama2-7b",
+            ModelArch::Llama3_8B => "llama3-8b",
+            ModelArch::Mistral7B => "mistral-7b",
+            ModelArch::TinyLlama1_1B => "tinyllama-1.1b",
+        }
+    }
+}
+
+/// How the weights at `llm.model_path` are stored. `Auto` goes by the file
+/// extension: `.gguf` is quantized, anything else fp16 safetensors.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    #[default]
+    Auto,
+    Fp16,
+    /// GGUF weights in whatever block format they were quantized to, e.g. 4-bit Q4_K_M.
+    Gguf,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LLMConfig {
     pub enabled: bool,
+    #[serde(default)]
+    pub model_arch: ModelArch,
+    #[serde(default)]
+    pub quantization: Quantization,
     pub model_path: String,
     pub tokenizer_path: String,
     pub max_batch_size: usize,
     pub use_gpu: bool,
+    #[serde(default = "default_llm_max_tokens")]
+    pub max_tokens: usize,
+    #[serde(default = "default_batch_window_ms")]
+    pub batch_window_ms: u64,
+    #[serde(default)]
+    pub repetition_guard: bool,
+    #[serde(default = "default_repetition_window")]
+    pub repetition_window: usize,
+    #[serde(default = "default_repetition_threshold")]
+    pub repetition_threshold: usize,
+    #[serde(default)]
+    pub lora: Option<LoraConfig>,
+}
+
+impl LLMConfig {
+    /// `quantization` with `Auto` resolved against `model_path`.
+    pub fn weights_format(&self) -> Quantization {
+        match self.quantization {
+            Quantization::Auto if Path::new(&self.model_path).extension().map_or(false, |ext| ext == "gguf") => {
+                Quantization::Gguf
+            }
+            Quantization::Auto => Quantization::Fp16,
+            explicit => explicit,
+        }
+    }
+}
+
+/// Projections a LoRA adapter can attach to.
+pub const LORA_TARGET_MODULES: [&str; 7] = ["q_proj", "k_proj", "v_proj", "o_proj", "gate_proj", "up_proj", "down_proj"];
+
+/// Low-rank adapters on the projections in `target_modules`, added to the
+/// frozen weights scaled by `alpha / rank`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LoraConfig {
+    #[serde(default = "default_lora_rank")]
+    pub rank: usize,
+    #[serde(default = "default_lora_alpha")]
+    pub alpha: f64,
+    #[serde(default = "default_lora_target_modules")]
+    pub target_modules: Vec<String>,
+    /// Trained adapter merged into the weights when the model loads.
+    #[serde(default)]
+    pub adapter_path: Option<String>,
+}
+
+impl Default for LoraConfig {
+    fn default() -> Self {
+        LoraConfig {
+            rank: default_lora_rank(),
+            alpha: default_lora_alpha(),
+            target_modules: default_lora_target_modules(),
+            adapter_path: None,
+        }
+    }
+}
+
+impl LoraConfig {
+    pub fn scale(&self) -> f64 {
+        self.alpha / self.rank as f64
+    }
+
+    pub fn targets(&self, module: &str) -> bool {
+        self.target_modules.iter().any(|target| target == module)
+    }
+}
+
+fn default_lora_rank() -> usize {
+    8
+}
+
+fn default_lora_alpha() -> f64 {
+    16.0
+}
+
+fn default_lora_target_modules() -> Vec<String> {
+    vec!["q_proj".to_string(), "v_proj".to_string()]
+}
+
+fn default_llm_max_tokens() -> usize {
+    512
+}
+
+fn default_batch_window_ms() -> u64 {
+    20
+}
+
+fn default_repetition_window() -> usize {
+    64
+}
+
+fn default_repetition_threshold() -> usize {
+    4
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ControlApiConfig {
+    #[serde(default = "default_control_api_host")]
+    pub host: String,
+    pub port: u16,
+    pub auth_token: String,
+}
+
+fn default_control_api_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+/// The `[rpc]` section: where the public JSON-RPC server listens.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RpcServerConfig {
+    #[serde(default = "default_rpc_server_host")]
+    pub host: String,
+    #[serde(default = "default_rpc_server_port")]
+    pub port: u16,
+}
+
+fn default_rpc_server_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_rpc_server_port() -> u16 {
+    8899
+}
+
+/// The `[metrics]` section: where Prometheus scrapes `/metrics`. Without
+/// it the endpoint isn't served.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MetricsConfig {
+    #[serde(default = "default_metrics_host")]
+    pub host: String,
+    #[serde(default = "default_metrics_port")]
+    pub port: u16,
+}
+
+fn default_metrics_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_metrics_port() -> u16 {
+    9100
+}
+
+/// How the listen port is opened on the home router. `Auto` tries UPnP,
+/// then NAT-PMP.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PortMapping {
+    #[default]
+    Auto,
+    Upnp,
+    NatPmp,
+    None,
+}
+
+/// The `[nat]` section, for nodes behind a router. Without it peers dial
+/// back at the IP they see this node connect from, on its listen port.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NatConfig {
+    #[serde(default)]
+    pub mapping: PortMapping,
+    /// Advertised as-is instead of mapping a port, for routers forwarded
+    /// by hand.
+    #[serde(default)]
+    pub external_addr: Option<SocketAddr>,
+    /// NAT-PMP gateway; defaults to the default route's.
+    #[serde(default)]
+    pub gateway: Option<IpAddr>,
+    #[serde(default = "default_nat_lease_secs")]
+    pub lease_secs: u32,
+}
+
+fn default_nat_lease_secs() -> u32 {
+    3600
+}
+
+/// How long an epoch lasts. Epochs are counted from genesis or from the
+/// Unix epoch, so every node agrees where one ends.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EpochLength {
+    Blocks(u64),
+    Seconds(u64),
+}
+
+impl Default for EpochLength {
+    fn default() -> Self {
+        EpochLength::Blocks(1000)
+    }
+}
+
+/// The `[epoch]` section. Without it the node keeps no epochs and pays no
+/// rewards.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EpochConfig {
+    #[serde(default)]
+    pub length: EpochLength,
+    /// Lamports shared among validators at the end of each epoch, in
+    /// proportion to their weighted participation.
+    pub rewards_per_epoch: u64,
+    #[serde(default = "default_block_reward_weight")]
+    pub block_weight: u64,
+    #[serde(default = "default_vote_reward_weight")]
+    pub vote_weight: u64,
+    #[serde(default = "default_inference_reward_weight")]
+    pub inference_weight: u64,
+    /// Submit each epoch's rewards to the stake program. Only works if the
+    /// node's keypair is the program's reward authority.
+    #[serde(default)]
+    pub distribute: bool,
+}
+
+fn default_block_reward_weight() -> u64 {
+    10
+}
+
+fn default_vote_reward_weight() -> u64 {
+    1
+}
+
+fn default_inference_reward_weight() -> u64 {
+    5
+}
+
+impl EpochConfig {
+    pub fn reward_weights(&self) -> RewardWeights {
+        RewardWeights {
+            blocks: self.block_weight,
+            votes: self.vote_weight,
+            inference: self.inference_weight,
+        }
+    }
 }
 
 impl Default for NodeConfig {
@@ -54,15 +802,81 @@ impl Default for NodeConfig {
                 "testnet.fractis.io:8000".to_string(),
                 "testnet2.fractis.io:8000".to_string(),
             ],
+            disconnect_removed_bootstrap: false,
+            min_stake: default_min_stake(),
+            stake_program_id: None,
+            keypair_path: None,
+            stake_gated_peers: false,
+            peer_admission_cache_secs: default_peer_admission_cache_secs(),
+            min_validator_lock_secs: default_min_validator_lock_secs(),
+            max_transaction_bytes: default_max_transaction_bytes(),
+            timestamp_future_tolerance_ms: default_timestamp_future_tolerance_ms(),
+            mempool_capacity: default_mempool_capacity(),
+            mempool_backpressure_high: default_mempool_backpressure_high(),
+            mempool_backpressure_low: default_mempool_backpressure_low(),
+            mempool_max_age_secs: default_mempool_max_age_secs(),
+            mempool_persist_interval_secs: default_mempool_persist_interval_secs(),
+            broadcast_replay_window: default_broadcast_replay_window(),
+            slot_duration_ms: default_slot_duration_ms(),
+            proposer_fallback_timeout_ms: default_proposer_fallback_timeout_ms(),
+            epoch_slots: default_epoch_slots(),
+            accept_concurrency: default_accept_concurrency(),
+            max_inbound_per_ip: default_max_inbound_per_ip(),
+            inbound_attempts_per_ip_per_min: default_inbound_attempts_per_ip_per_min(),
+            gossip_seen_capacity: default_gossip_seen_capacity(),
+            gossip_seen_fp_rate: default_gossip_seen_fp_rate(),
+            gossip_dedup_capacity: default_gossip_dedup_capacity(),
+            gossip_dedup_ttl_secs: default_gossip_dedup_ttl_secs(),
+            max_clock_offset_ms: default_max_clock_offset_ms(),
+            abstain_on_clock_skew: false,
+            min_ready_peers: default_min_ready_peers(),
+            peer_discovery: default_peer_discovery(),
+            peer_exchange_interval_secs: default_peer_exchange_interval_secs(),
+            peer_ban_score: default_peer_ban_score(),
+            peer_ban_secs: default_peer_ban_secs(),
+            peer_send_bytes_per_sec: None,
+            peer_send_burst_bytes: default_peer_send_burst_bytes(),
+            shutdown_phase_timeout_ms: default_shutdown_phase_timeout_ms(),
+            state_sync: default_state_sync(),
+            snapshot_interval_blocks: default_snapshot_interval_blocks(),
+            state_sync_timeout_secs: default_state_sync_timeout_secs(),
+            cluster: Cluster::default(),
+            rpc_url: None,
+            commitment: Commitment::default(),
+            rpc_timeout_ms: default_rpc_timeout_ms(),
+            rpc_max_response_bytes: default_rpc_max_response_bytes(),
+            role: NodeRole::default(),
+            transport: TransportKind::default(),
+            compression: Compression::default(),
+            framing: FramingVersion::default(),
+            liveness: LivenessProfile::default(),
+            propose_blocks: None,
             llm: None,
+            inference_verification_rate: default_inference_verification_rate(),
+            control_api: None,
+            rpc: None,
+            metrics: None,
+            nat: None,
+            epoch: None,
+            log_level: None,
         }
     }
 }
 
 impl NodeConfig {
     pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        Self::load_layered(path, std::iter::empty(), &[])
+    }
+
+    /// Loads `path` with `FRACTIS_*` variables from `env` and then
+    /// `overrides` layered on top, each winning over the layers before it.
+    pub fn load_layered(
+        path: &Path,
+        env: impl IntoIterator<Item = (String, String)>,
+        overrides: &[(String, String)],
+    ) -> Result<Self, ConfigError> {
         let config_str = fs::read_to_string(path)?;
-        let mut config: NodeConfig = toml::from_str(&config_str)?;
+        let config = Self::from_layers(&config_str, env, overrides)?;
         
        
         config.validate()?;
@@ -92,12 +906,70 @@ impl NodeConfig {
                         format!("LLM tokenizer file not found: {}", tokenizer_path.display())
                     ));
                 }
+
+                if let Some(adapter_path) = llm_config.lora.as_ref().and_then(|lora| lora.adapter_path.as_ref()) {
+                    if !Path::new(adapter_path).exists() {
+                        return Err(ConfigError::StoragePath(
+                            format!("LoRA adapter file not found: {}", adapter_path)
+                        ));
+                    }
+                }
             }
         }
 
         Ok(config)
     }
 
+    /// Builds a config from the defaults, overlaid by the TOML in `file`,
+    /// then by `FRACTIS_*` variables in `env`, then by `overrides`. Keys are
+    /// dotted setting names such as `port` or `rpc.port`; values are read as
+    /// TOML, falling back to a plain string. A variable or override naming
+    /// no setting is an error rather than a silently ignored typo.
+    pub fn from_layers(
+        file: &str,
+        env: impl IntoIterator<Item = (String, String)>,
+        overrides: &[(String, String)],
+    ) -> Result<Self, ConfigError> {
+        let mut layered = toml::Value::try_from(NodeConfig::default())
+            .map_err(|e| ConfigError::InvalidValue(format!("default config does not serialize: {}", e)))?;
+        merge_toml(&mut layered, toml::Value::Table(toml::from_str(file)?));
+
+        let settings = Self::setting_names();
+        let is_setting = |key: &str| settings.iter().any(|setting| top_level(key) == setting);
+        let mut from_env: Vec<(String, String)> = Vec::new();
+        for (name, value) in env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_ascii_lowercase().replace("__", ".");
+            if !is_setting(&key) {
+                return Err(ConfigError::InvalidValue(format!("unknown setting {} in {}", key, name)));
+            }
+            from_env.push((key, value));
+        }
+        from_env.sort();
+        for (key, value) in &from_env {
+            set_toml_path(&mut layered, key, value);
+        }
+
+        for (key, value) in overrides {
+            if !is_setting(key) {
+                return Err(ConfigError::InvalidValue(format!("unknown setting {}", key)));
+            }
+            set_toml_path(&mut layered, key, value);
+        }
+
+        Ok(layered.try_into()?)
+    }
+
+    /// Top-level setting names, including optional sections left unset.
+    fn setting_names() -> Vec<String> {
+        match serde_json::to_value(NodeConfig::default()) {
+            Ok(serde_json::Value::Object(fields)) => fields.keys().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+
     pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
        
         self.validate()?;
@@ -138,6 +1010,285 @@ impl NodeConfig {
         }
 
         
+        if let Some(program_id) = &self.stake_program_id {
+            program_id.parse::<solana_sdk::pubkey::Pubkey>().map_err(|_| {
+                ConfigError::InvalidValue(format!("stake_program_id is not a valid pubkey: {}", program_id))
+            })?;
+        }
+
+        if self.max_transaction_bytes == 0 {
+            return Err(ConfigError::InvalidValue(
+                "max_transaction_bytes must be greater than zero".to_string()
+            ));
+        }
+
+        if self.timestamp_future_tolerance_ms > self.consensus_timeout {
+            warn!("timestamp_future_tolerance_ms ({}) exceeds consensus_timeout ({}ms), future-dated transactions may outlive their expiry", self.timestamp_future_tolerance_ms, self.consensus_timeout);
+        }
+
+        if self.accept_concurrency == 0 {
+            return Err(ConfigError::InvalidValue(
+                "accept_concurrency must be greater than zero".to_string()
+            ));
+        }
+
+        if !(self.gossip_seen_fp_rate > 0.0 && self.gossip_seen_fp_rate < 1.0) {
+            return Err(ConfigError::InvalidValue(
+                "gossip_seen_fp_rate must be between 0 and 1".to_string()
+            ));
+        }
+
+        if !(0.0..=1.0).contains(&self.inference_verification_rate) {
+            return Err(ConfigError::InvalidValue(
+                "inference_verification_rate must be between 0 and 1".to_string()
+            ));
+        }
+
+        if self.gossip_dedup_capacity == 0 || self.gossip_dedup_ttl_secs == 0 {
+            return Err(ConfigError::InvalidValue(
+                "gossip_dedup_capacity and gossip_dedup_ttl_secs must be greater than zero".to_string()
+            ));
+        }
+
+        if self.slot_duration_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "slot_duration_ms must be greater than zero".to_string()
+            ));
+        }
+
+        if self.proposer_fallback_timeout_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "proposer_fallback_timeout_ms must be greater than zero".to_string()
+            ));
+        }
+
+        if self.epoch_slots == 0 {
+            return Err(ConfigError::InvalidValue(
+                "epoch_slots must be greater than zero".to_string()
+            ));
+        }
+
+        if let Some(level) = &self.log_level {
+            level.parse::<LevelFilter>().map_err(|_| {
+                ConfigError::InvalidValue(format!("log_level must be one of off, error, warn, info, debug or trace, got {}", level))
+            })?;
+        }
+
+        if self.slot_duration_ms > self.consensus_timeout {
+            warn!("slot_duration_ms ({}) exceeds consensus_timeout ({}ms), slots will time out before they end", self.slot_duration_ms, self.consensus_timeout);
+        }
+
+        if self.proposer_fallback_timeout_ms >= self.slot_duration_ms {
+            warn!("proposer_fallback_timeout_ms ({}) is not below slot_duration_ms ({}), fallback proposers never get a turn", self.proposer_fallback_timeout_ms, self.slot_duration_ms);
+        }
+
+        if let Some(llm) = &self.llm {
+            if llm.max_batch_size == 0 {
+                return Err(ConfigError::InvalidValue(
+                    "llm.max_batch_size must be greater than zero".to_string()
+                ));
+            }
+            if llm.batch_window_ms > 1000 {
+                warn!("Long llm.batch_window_ms ({}), every request waits up to this long before generation starts", llm.batch_window_ms);
+            }
+        }
+
+        if let Some((llm, lora)) = self.llm.as_ref().and_then(|llm| llm.lora.as_ref().map(|lora| (llm, lora))) {
+            if lora.rank == 0 || !(lora.alpha > 0.0) {
+                return Err(ConfigError::InvalidValue(
+                    "llm.lora.rank and llm.lora.alpha must be greater than zero".to_string()
+                ));
+            }
+            if lora.target_modules.is_empty() {
+                return Err(ConfigError::InvalidValue(
+                    "llm.lora.target_modules must name at least one module".to_string()
+                ));
+            }
+            if let Some(unknown) = lora.target_modules.iter().find(|m| !LORA_TARGET_MODULES.contains(&m.as_str())) {
+                return Err(ConfigError::InvalidValue(format!(
+                    "Unknown llm.lora target module '{}', expected one of {}",
+                    unknown,
+                    LORA_TARGET_MODULES.join(", ")
+                )));
+            }
+            if lora.adapter_path.is_some() && llm.weights_format() == Quantization::Gguf {
+                return Err(ConfigError::Conflict(
+                    "llm.lora adapters can only be merged into fp16 weights, not GGUF".to_string()
+                ));
+            }
+        }
+
+        if let Some(llm) = self.llm.as_ref().filter(|llm| llm.repetition_guard) {
+            if llm.repetition_threshold < 2 || llm.repetition_window < llm.repetition_threshold {
+                return Err(ConfigError::InvalidValue(
+                    "repetition_threshold must be at least 2 and no larger than repetition_window".to_string()
+                ));
+            }
+        }
+
+        if let Some(api) = &self.control_api {
+            if api.auth_token.trim().is_empty() {
+                return Err(ConfigError::InvalidValue(
+                    "control_api.auth_token must not be empty".to_string()
+                ));
+            }
+            if api.host == self.host && api.port == self.port {
+                return Err(ConfigError::Conflict(
+                    "control_api cannot listen on the peer port".to_string()
+                ));
+            }
+        }
+
+        if let Some(rpc) = &self.rpc {
+            if rpc.host == self.host && rpc.port == self.port {
+                return Err(ConfigError::Conflict(
+                    "rpc cannot listen on the peer port".to_string()
+                ));
+            }
+            if self.control_api.as_ref().is_some_and(|api| api.host == rpc.host && api.port == rpc.port) {
+                return Err(ConfigError::Conflict(
+                    "rpc and control_api cannot share an address".to_string()
+                ));
+            }
+        }
+
+        if let Some(metrics) = &self.metrics {
+            let taken = [
+                Some((self.host.as_str(), self.port)),
+                self.rpc.as_ref().map(|rpc| (rpc.host.as_str(), rpc.port)),
+                self.control_api.as_ref().map(|api| (api.host.as_str(), api.port)),
+            ];
+            if taken.into_iter().flatten().any(|addr| addr == (metrics.host.as_str(), metrics.port)) {
+                return Err(ConfigError::Conflict(
+                    "metrics must listen on its own address".to_string()
+                ));
+            }
+        }
+
+        if let Some(nat) = &self.nat {
+            if nat.lease_secs < 60 {
+                return Err(ConfigError::InvalidValue(
+                    format!("nat lease_secs ({}) must be at least 60", nat.lease_secs)
+                ));
+            }
+            if nat.external_addr.map_or(false, |addr| addr.port() == 0 || addr.ip().is_unspecified()) {
+                return Err(ConfigError::InvalidValue(
+                    "nat external_addr must be a routable address and port".to_string()
+                ));
+            }
+        }
+
+        if let Some(epoch) = &self.epoch {
+            if matches!(epoch.length, EpochLength::Blocks(0) | EpochLength::Seconds(0)) {
+                return Err(ConfigError::InvalidValue(
+                    "epoch length must be greater than zero".to_string()
+                ));
+            }
+            if epoch.distribute && self.stake_program_id.is_none() {
+                return Err(ConfigError::Conflict(
+                    "epoch distribute requires stake_program_id".to_string()
+                ));
+            }
+        }
+
+        match (&self.rpc_url, self.cluster) {
+            (None, Cluster::Custom) => {
+                return Err(ConfigError::Conflict(
+                    "cluster = \"custom\" requires rpc_url".to_string()
+                ));
+            }
+            (Some(url), _) if !(url.starts_with("http://") || url.starts_with("https://")) => {
+                return Err(ConfigError::InvalidValue(
+                    format!("rpc_url must be an http:// or https:// URL, got {}", url)
+                ));
+            }
+            _ => {}
+        }
+
+        if self.rpc_timeout_ms == 0 || self.rpc_max_response_bytes == 0 {
+            return Err(ConfigError::InvalidValue(
+                "rpc_timeout_ms and rpc_max_response_bytes must be greater than zero".to_string()
+            ));
+        }
+
+        if self.rpc_timeout_ms > self.consensus_timeout {
+            warn!("rpc_timeout_ms ({}) exceeds consensus_timeout ({}ms), a slow RPC endpoint can stall consensus", self.rpc_timeout_ms, self.consensus_timeout);
+        }
+
+        if !(0.0 < self.mempool_backpressure_low
+            && self.mempool_backpressure_low < self.mempool_backpressure_high
+            && self.mempool_backpressure_high <= 1.0)
+        {
+            return Err(ConfigError::InvalidValue(
+                "mempool backpressure watermarks must satisfy 0 < low < high <= 1".to_string()
+            ));
+        }
+
+        if self.mempool_max_age_secs == 0 || self.mempool_persist_interval_secs == 0 {
+            return Err(ConfigError::InvalidValue(
+                "mempool_max_age_secs and mempool_persist_interval_secs must be greater than zero".to_string()
+            ));
+        }
+
+        if self.min_ready_peers as u64 > self.max_connections as u64 {
+            return Err(ConfigError::InvalidValue(
+                format!("min_ready_peers ({}) exceeds max_connections ({})", self.min_ready_peers, self.max_connections)
+            ));
+        }
+
+        if self.max_inbound_per_ip == 0 || self.inbound_attempts_per_ip_per_min == 0 {
+            return Err(ConfigError::InvalidValue(
+                "max_inbound_per_ip and inbound_attempts_per_ip_per_min must be greater than zero".to_string()
+            ));
+        }
+
+        if !(MIN_SCORE..0).contains(&self.peer_ban_score) {
+            return Err(ConfigError::InvalidValue(
+                format!("peer_ban_score must be negative and at least {}", MIN_SCORE)
+            ));
+        }
+
+        if self.peer_ban_secs == 0 {
+            return Err(ConfigError::InvalidValue(
+                "peer_ban_secs must be greater than zero".to_string()
+            ));
+        }
+
+        if self.peer_discovery && self.peer_exchange_interval_secs == 0 {
+            return Err(ConfigError::InvalidValue(
+                "peer_exchange_interval_secs must be greater than zero".to_string()
+            ));
+        }
+
+        if self.snapshot_interval_blocks == 0 || self.state_sync_timeout_secs == 0 {
+            return Err(ConfigError::InvalidValue(
+                "snapshot_interval_blocks and state_sync_timeout_secs must be greater than zero".to_string()
+            ));
+        }
+
+        if self.peer_send_bytes_per_sec == Some(0) || self.peer_send_burst_bytes == 0 {
+            return Err(ConfigError::InvalidValue(
+                "peer_send_bytes_per_sec and peer_send_burst_bytes must be greater than zero".to_string()
+            ));
+        }
+
+        let liveness = self.liveness.timeouts();
+        if liveness.keepalive_secs == 0 || liveness.heartbeat_secs == 0 || liveness.reap_secs == 0 {
+            return Err(ConfigError::InvalidValue(
+                "liveness keepalive, heartbeat and reap timeouts must be greater than zero".to_string()
+            ));
+        }
+        if liveness.max_missed_heartbeats == 0 {
+            return Err(ConfigError::InvalidValue(
+                "liveness max_missed_heartbeats must be greater than zero".to_string()
+            ));
+        }
+        if liveness.idle_secs <= liveness.heartbeat_secs {
+            return Err(ConfigError::InvalidValue(
+                format!("liveness idle_secs ({}) must exceed heartbeat_secs ({})", liveness.idle_secs, liveness.heartbeat_secs)
+            ));
+        }
+
         if self.consensus_timeout < 1000 {
             warn!("Very low consensus_timeout ({}ms), this might cause consensus issues", self.consensus_timeout);
         }
@@ -150,6 +1301,502 @@ impl NodeConfig {
             ));
         }
 
+        if let Some(keypair_path) = &self.keypair_path {
+            if Path::new(keypair_path).is_dir() {
+                return Err(ConfigError::InvalidValue(
+                    format!("keypair_path {} is a directory", keypair_path)
+                ));
+            }
+        }
+
+        self.validate_feature_combinations()
+    }
+
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        self.log_level.as_deref().and_then(|level| level.parse().ok())
+    }
+
+    /// This config with the reloadable fields taken from `reloaded`, plus
+    /// the names of any other fields `reloaded` changes.
+    pub fn with_reloadable(&self, reloaded: &NodeConfig) -> (NodeConfig, Vec<String>) {
+        let applied = NodeConfig {
+            log_level: reloaded.log_level.clone(),
+            max_connections: reloaded.max_connections,
+            bootstrap_nodes: reloaded.bootstrap_nodes.clone(),
+            disconnect_removed_bootstrap: reloaded.disconnect_removed_bootstrap,
+            consensus_timeout: reloaded.consensus_timeout,
+            ..self.clone()
+        };
+
+        let mut restart_required: Vec<String> = match (serde_json::to_value(&applied), serde_json::to_value(reloaded)) {
+            (Ok(serde_json::Value::Object(applied)), Ok(serde_json::Value::Object(reloaded))) => reloaded
+                .iter()
+                .filter(|(field, value)| applied.get(*field) != Some(*value))
+                .map(|(field, _)| field.clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        restart_required.sort();
+        (applied, restart_required)
+    }
+
+    pub fn proposes_blocks(&self) -> bool {
+        self.propose_blocks.unwrap_or(self.role == NodeRole::Validator)
+    }
+
+    /// `rpc_url` if set, otherwise the cluster's public endpoint.
+    pub fn rpc_endpoint(&self) -> &str {
+        self.rpc_url
+            .as_deref()
+            .or_else(|| self.cluster.default_rpc_url())
+            .unwrap_or(MAINNET_RPC_URL)
+    }
+
+    pub fn inbound_limits(&self) -> InboundLimits {
+        InboundLimits {
+            max_per_ip: self.max_inbound_per_ip,
+            attempts_per_minute: self.inbound_attempts_per_ip_per_min,
+        }
+    }
+
+    pub fn ban_policy(&self) -> BanPolicy {
+        BanPolicy {
+            threshold: self.peer_ban_score,
+            duration: std::time::Duration::from_secs(self.peer_ban_secs),
+        }
+    }
+
+    pub fn peer_send_limit(&self) -> Option<SendLimit> {
+        self.peer_send_bytes_per_sec.map(|bytes_per_sec| SendLimit {
+            bytes_per_sec,
+            burst_bytes: self.peer_send_burst_bytes,
+        })
+    }
+
+    fn validate_feature_combinations(&self) -> Result<(), ConfigError> {
+        if self.stake_gated_peers && self.stake_program_id.is_none() {
+            return Err(ConfigError::Conflict(
+                "stake_gated_peers requires stake_program_id to look up peer stake accounts".to_string()
+            ));
+        }
+
+        if self.role == NodeRole::Observer && self.propose_blocks == Some(true) {
+            return Err(ConfigError::Conflict(
+                "observer nodes cannot propose blocks; remove propose_blocks or set role = \"validator\"".to_string()
+            ));
+        }
+
+        if self.compression != Compression::None && self.framing == FramingVersion::V1 {
+            return Err(ConfigError::Conflict(
+                "compression requires framing = \"v2\", v1 frames have no compression flag".to_string()
+            ));
+        }
+
+        if self.compression != Compression::None && self.transport == TransportKind::Noise {
+            return Err(ConfigError::Conflict(
+                "compression cannot be combined with the noise transport, compressed ciphertext leaks plaintext length patterns".to_string()
+            ));
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_config() -> NodeConfig {
+        NodeConfig {
+            bootstrap_nodes: vec!["127.0.0.1:8001".to_string()],
+            storage_path: std::env::temp_dir().to_string_lossy().into_owned(),
+            ..NodeConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_default_combination_is_valid() {
+        assert!(local_config().validate().is_ok());
+    }
+
+    #[test]
+    fn test_reload_takes_only_reloadable_fields() {
+        let running = local_config();
+        let reloaded = NodeConfig {
+            max_connections: 7,
+            consensus_timeout: 900,
+            bootstrap_nodes: vec!["127.0.0.1:8002".to_string()],
+            log_level: Some("debug".to_string()),
+            port: running.port + 1,
+            min_stake: running.min_stake + 1,
+            ..running.clone()
+        };
+
+        let (applied, restart_required) = running.with_reloadable(&reloaded);
+        assert_eq!(applied.max_connections, 7);
+        assert_eq!(applied.consensus_timeout, 900);
+        assert_eq!(applied.bootstrap_nodes, reloaded.bootstrap_nodes);
+        assert_eq!(applied.log_level(), Some(LevelFilter::Debug));
+        assert_eq!(applied.port, running.port);
+        assert_eq!(applied.min_stake, running.min_stake);
+        assert_eq!(restart_required, vec!["min_stake".to_string(), "port".to_string()]);
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_layers_override_in_order() {
+        let file = r#"
+            node_id = "123"
+            host = "127.0.0.1"
+            port = 8000
+            rpc_url = "http://file:8899"
+        "#;
+        let env = vars(&[
+            ("FRACTIS_PORT", "8100"),
+            ("FRACTIS_RPC_URL", "http://env:8899"),
+            ("FRACTIS_RPC__PORT", "9900"),
+            ("PORT", "1"),
+        ]);
+        let cli = vars(&[("port", "8200"), ("bootstrap_nodes", r#"["10.0.0.1:8000"]"#)]);
+
+        let config = NodeConfig::from_layers(file, env.clone(), &cli).unwrap();
+        assert_eq!(config.node_id, "123");
+        assert_eq!(config.port, 8200);
+        assert_eq!(config.rpc_url.as_deref(), Some("http://env:8899"));
+        assert_eq!(config.rpc.as_ref().map(|rpc| (rpc.host.as_str(), rpc.port)), Some(("127.0.0.1", 9900)));
+        assert_eq!(config.bootstrap_nodes, vec!["10.0.0.1:8000".to_string()]);
+        // Unset in every layer, so the default stands.
+        assert_eq!(config.max_connections, NodeConfig::default().max_connections);
+
+        let config = NodeConfig::from_layers(file, env, &[]).unwrap();
+        assert_eq!(config.port, 8100);
+        let config = NodeConfig::from_layers(file, Vec::new(), &[]).unwrap();
+        assert_eq!((config.port, config.rpc.is_none()), (8000, true));
+    }
+
+    #[test]
+    fn test_unknown_override_rejected() {
+        let result = NodeConfig::from_layers("", Vec::new(), &vars(&[("prot", "8000")]));
+        assert!(matches!(result, Err(ConfigError::InvalidValue(msg)) if msg.contains("prot")));
+
+        let result = NodeConfig::from_layers("", Vec::new(), &vars(&[("port", "eighty")]));
+        assert!(matches!(result, Err(ConfigError::Toml(_))));
+
+        let result = NodeConfig::from_layers("", vars(&[("FRACTIS_PROT", "8000")]), &[]);
+        assert!(matches!(result, Err(ConfigError::InvalidValue(msg)) if msg.contains("FRACTIS_PROT")));
+    }
+
+    #[test]
+    fn test_unknown_log_level_rejected() {
+        let config = NodeConfig { log_level: Some("loud".to_string()), ..local_config() };
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(msg)) if msg.contains("log_level")));
+        let config = NodeConfig { log_level: Some("warn".to_string()), ..local_config() };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_observer_cannot_propose_blocks() {
+        let config = NodeConfig {
+            role: NodeRole::Observer,
+            propose_blocks: Some(true),
+            ..local_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Conflict(msg)) if msg.contains("observer")));
+
+        let config = NodeConfig {
+            role: NodeRole::Observer,
+            ..local_config()
+        };
+        assert!(config.validate().is_ok());
+        assert!(!config.proposes_blocks());
+    }
+
+    #[test]
+    fn test_compression_requires_v2_framing() {
+        let config = NodeConfig {
+            compression: Compression::Lz4,
+            framing: FramingVersion::V1,
+            ..local_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Conflict(msg)) if msg.contains("framing")));
+
+        let config = NodeConfig {
+            compression: Compression::Lz4,
+            ..local_config()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_compression_rejected_over_noise() {
+        let config = NodeConfig {
+            compression: Compression::Lz4,
+            transport: TransportKind::Noise,
+            ..local_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Conflict(msg)) if msg.contains("noise")));
+    }
+
+    #[test]
+    fn test_control_api_requires_token() {
+        let api = ControlApiConfig {
+            host: default_control_api_host(),
+            port: 8899,
+            auth_token: " ".to_string(),
+        };
+        let config = NodeConfig {
+            control_api: Some(api.clone()),
+            ..local_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(msg)) if msg.contains("auth_token")));
+
+        let config = NodeConfig {
+            control_api: Some(ControlApiConfig {
+                auth_token: "token".to_string(),
+                ..api
+            }),
+            ..local_config()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_nat_section_parsed_and_validated() {
+        #[derive(Deserialize)]
+        struct Section {
+            nat: NatConfig,
+        }
+        let parsed: Section = toml::from_str("nat = { mapping = \"natpmp\", gateway = \"192.168.1.1\" }").unwrap();
+        assert_eq!(parsed.nat.mapping, PortMapping::NatPmp);
+        assert_eq!(parsed.nat.gateway, Some(IpAddr::from([192, 168, 1, 1])));
+        assert_eq!(parsed.nat.lease_secs, 3600);
+        assert!(NodeConfig { nat: Some(parsed.nat.clone()), ..local_config() }.validate().is_ok());
+
+        let short_lease = NatConfig { lease_secs: 10, ..parsed.nat.clone() };
+        let config = NodeConfig { nat: Some(short_lease), ..local_config() };
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(msg)) if msg.contains("lease_secs")));
+
+        let no_port = NatConfig { external_addr: Some(SocketAddr::from(([198, 51, 100, 7], 0))), ..parsed.nat };
+        let config = NodeConfig { nat: Some(no_port), ..local_config() };
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(msg)) if msg.contains("external_addr")));
+    }
+
+    #[test]
+    fn test_epoch_section_parsed_and_validated() {
+        #[derive(Deserialize)]
+        struct Section {
+            epoch: EpochConfig,
+        }
+        let parsed: Section = toml::from_str("epoch = { length = { seconds = 3600 }, rewards_per_epoch = 5000 }").unwrap();
+        assert_eq!(parsed.epoch.length, EpochLength::Seconds(3600));
+        assert_eq!(parsed.epoch.reward_weights(), RewardWeights { blocks: 10, votes: 1, inference: 5 });
+        assert!(NodeConfig { epoch: Some(parsed.epoch.clone()), ..local_config() }.validate().is_ok());
+
+        let empty = EpochConfig { length: EpochLength::Blocks(0), ..parsed.epoch.clone() };
+        assert!(NodeConfig { epoch: Some(empty), ..local_config() }.validate().is_err());
+
+        let distributing = EpochConfig { distribute: true, ..parsed.epoch };
+        let config = NodeConfig { epoch: Some(distributing), ..local_config() };
+        assert!(matches!(config.validate(), Err(ConfigError::Conflict(_))));
+    }
+
+    #[test]
+    fn test_rpc_section_parsed_and_checked_for_clashes() {
+        let config: NodeConfig = toml::from_str(r#"
+            node_id = "node-a"
+            host = "127.0.0.1"
+            port = 8000
+            storage_path = "./data"
+            max_connections = 50
+            consensus_timeout = 5000
+            bootstrap_nodes = []
+
+            [rpc]
+            port = 9000
+
+            [metrics]
+        "#)
+        .unwrap();
+        let rpc = config.rpc.clone().unwrap();
+        assert_eq!((rpc.host.as_str(), rpc.port), ("127.0.0.1", 9000));
+        assert_eq!(config.metrics.as_ref().map(|metrics| metrics.port), Some(9100));
+
+        let clashing = NodeConfig {
+            rpc: Some(rpc.clone()),
+            metrics: Some(MetricsConfig { host: rpc.host.clone(), port: rpc.port }),
+            ..local_config()
+        };
+        assert!(matches!(clashing.validate(), Err(ConfigError::Conflict(msg)) if msg.contains("metrics")));
+
+        let config = NodeConfig { rpc: Some(rpc.clone()), ..local_config() };
+        assert!(config.validate().is_ok());
+
+        let config = NodeConfig {
+            rpc: Some(rpc.clone()),
+            control_api: Some(ControlApiConfig {
+                host: rpc.host.clone(),
+                port: rpc.port,
+                auth_token: "token".to_string(),
+            }),
+            ..local_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Conflict(msg)) if msg.contains("control_api")));
+    }
+
+    #[test]
+    fn test_liveness_profiles_set_documented_timeouts() {
+        let expected = [
+            (LivenessProfile::Lenient, (120, 30, 300, 120, 5)),
+            (LivenessProfile::Balanced, (60, 15, 90, 60, 3)),
+            (LivenessProfile::Aggressive, (20, 5, 20, 10, 2)),
+        ];
+        for (profile, (keepalive_secs, heartbeat_secs, idle_secs, reap_secs, max_missed_heartbeats)) in expected {
+            assert_eq!(
+                profile.timeouts(),
+                LivenessTimeouts { keepalive_secs, heartbeat_secs, idle_secs, reap_secs, max_missed_heartbeats }
+            );
+            let config = NodeConfig { liveness: profile, ..local_config() };
+            assert!(config.validate().is_ok());
+        }
+        assert_eq!(NodeConfig::default().liveness, LivenessProfile::Balanced);
+    }
+
+    #[test]
+    fn test_custom_liveness_passes_through_manual_values() {
+        let manual = LivenessTimeouts {
+            keepalive_secs: 45,
+            heartbeat_secs: 7,
+            idle_secs: 33,
+            reap_secs: 11,
+            max_missed_heartbeats: 3,
+        };
+        assert_eq!(LivenessProfile::Custom(manual).timeouts(), manual);
+
+        #[derive(Deserialize)]
+        struct Section {
+            liveness: LivenessProfile,
+        }
+        let parsed: Section = toml::from_str(
+            "liveness = { custom = { keepalive_secs = 45, heartbeat_secs = 7, idle_secs = 33, reap_secs = 11 } }",
+        )
+        .unwrap();
+        assert_eq!(parsed.liveness, LivenessProfile::Custom(manual));
+        let parsed: Section = toml::from_str("liveness = \"aggressive\"").unwrap();
+        assert_eq!(parsed.liveness, LivenessProfile::Aggressive);
+
+        let config = NodeConfig {
+            liveness: LivenessProfile::Custom(LivenessTimeouts { idle_secs: 7, ..manual }),
+            ..local_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(msg)) if msg.contains("idle_secs")));
+        let config = NodeConfig {
+            liveness: LivenessProfile::Custom(LivenessTimeouts { max_missed_heartbeats: 0, ..manual }),
+            ..local_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(msg)) if msg.contains("max_missed_heartbeats")));
+    }
+
+    #[test]
+    fn test_cluster_selects_rpc_endpoint() {
+        assert_eq!(local_config().rpc_endpoint(), MAINNET_RPC_URL);
+        let devnet = NodeConfig { cluster: Cluster::Devnet, ..local_config() };
+        assert_eq!(devnet.rpc_endpoint(), DEVNET_RPC_URL);
+
+        let custom = NodeConfig {
+            cluster: Cluster::Custom,
+            rpc_url: Some("http://127.0.0.1:8899".to_string()),
+            commitment: Commitment::Finalized,
+            ..local_config()
+        };
+        assert!(custom.validate().is_ok());
+        assert_eq!(custom.rpc_endpoint(), "http://127.0.0.1:8899");
+
+        let parsed: NodeConfig = toml::from_str(r#"
+            node_id = "node-a"
+            host = "127.0.0.1"
+            port = 8000
+            storage_path = "./data"
+            max_connections = 50
+            consensus_timeout = 5000
+            bootstrap_nodes = []
+            cluster = "testnet"
+            commitment = "processed"
+        "#)
+        .unwrap();
+        assert_eq!(parsed.rpc_endpoint(), TESTNET_RPC_URL);
+        assert_eq!(parsed.commitment, Commitment::Processed);
+    }
+
+    #[test]
+    fn test_rpc_url_validated() {
+        let missing = NodeConfig { cluster: Cluster::Custom, ..local_config() };
+        assert!(matches!(missing.validate(), Err(ConfigError::Conflict(_))));
+
+        let bad_scheme = NodeConfig { rpc_url: Some("ws://127.0.0.1:8900".to_string()), ..local_config() };
+        assert!(matches!(bad_scheme.validate(), Err(ConfigError::InvalidValue(msg)) if msg.contains("rpc_url")));
+    }
+
+    #[test]
+    fn test_model_arch_parsed_by_name() {
+        let section = |arch: &str| {
+            toml::from_str::<LLMConfig>(&format!(
+                "enabled = true\nmodel_path = \"m\"\ntokenizer_path = \"t\"\nmax_batch_size = 1\nuse_gpu = false\n{}",
+                arch
+            ))
+        };
+        assert_eq!(section("").unwrap().model_arch, ModelArch::Llama2_7B);
+        for arch in ModelArch::ALL {
+            let parsed = section(&format!("model_arch = \"{}\"", arch.name())).unwrap();
+            assert_eq!(parsed.model_arch, arch);
+        }
+
+        let err = section("model_arch = \"gpt-4\"").unwrap_err().to_string();
+        assert!(ModelArch::ALL.iter().all(|arch| err.contains(arch.name())), "{}", err);
+    }
+
+    #[test]
+    fn test_weights_format_follows_extension_unless_set() {
+        let llm = |model_path: &str, quantization| LLMConfig {
+            enabled: true,
+            model_arch: ModelArch::default(),
+            quantization,
+            model_path: model_path.to_string(),
+            tokenizer_path: "tokenizer.json".to_string(),
+            max_batch_size: 1,
+            use_gpu: false,
+            max_tokens: 16,
+            batch_window_ms: 20,
+            repetition_guard: false,
+            repetition_window: 64,
+            repetition_threshold: 4,
+            lora: None,
+        };
+        assert_eq!(llm("llama-2-7b.Q4_K_M.gguf", Quantization::Auto).weights_format(), Quantization::Gguf);
+        assert_eq!(llm("llama-2-7b.safetensors", Quantization::Auto).weights_format(), Quantization::Fp16);
+        assert_eq!(llm("weights.bin", Quantization::Gguf).weights_format(), Quantization::Gguf);
+    }
+
+    #[test]
+    fn test_lora_section_validated() {
+        let section: LLMConfig = toml::from_str(
+            "enabled = true\nmodel_path = \"m.safetensors\"\ntokenizer_path = \"t\"\nmax_batch_size = 1\nuse_gpu = false\n[lora]\nrank = 4",
+        )
+        .unwrap();
+        let lora = section.lora.clone().unwrap();
+        assert_eq!((lora.rank, lora.scale()), (4, 4.0));
+        assert!(lora.targets("q_proj") && !lora.targets("o_proj"));
+
+        let with_lora = |lora: LoraConfig, model_path: &str| NodeConfig {
+            llm: Some(LLMConfig { lora: Some(lora), model_path: model_path.to_string(), ..section.clone() }),
+            ..local_config()
+        };
+        assert!(with_lora(LoraConfig::default(), "m.safetensors").validate().is_ok());
+        let unknown = LoraConfig { target_modules: vec!["wq".to_string()], ..LoraConfig::default() };
+        assert!(matches!(with_lora(unknown, "m.safetensors").validate(), Err(ConfigError::InvalidValue(msg)) if msg.contains("wq")));
+        let merged = LoraConfig { adapter_path: Some("adapter.safetensors".to_string()), ..LoraConfig::default() };
+        assert!(matches!(with_lora(merged, "m.gguf").validate(), Err(ConfigError::Conflict(_))));
+    }
+}
@@ -1,62 +1,1418 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use solana_sdk::{
-    hash::Hash,
-    signature::Signature,
+    hash::{hashv, Hash},
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
 };
-use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::node::mempool::Mempool;
+
+#[derive(Error, Debug)]
+pub enum ChainError {
+    #[error("Header at height {0} does not extend the local chain")]
+    BrokenLinkage(u64),
+    #[error("Invalid quorum certificate for block at height {0}")]
+    InvalidQuorum(u64),
+    #[error("Block at height {0} is not signed by its proposer")]
+    InvalidProposerSignature(u64),
+    #[error("Block at height {0} was proposed by a non-validator")]
+    UnknownProposer(u64),
+    #[error("Transaction root of block at height {0} does not match its transactions")]
+    TransactionRootMismatch(u64),
+    #[error("Block at height {0} contains a transaction with an invalid signature")]
+    InvalidTransaction(u64),
+    #[error("View change for height {0} is not signed by a validator")]
+    InvalidViewChange(u64),
+    #[error("Block at height {0} was not proposed by the leader of its slot")]
+    WrongLeader(u64),
+    #[error("Vote for block at height {0} is not signed by a validator")]
+    InvalidVote(u64),
+}
+
+impl ChainError {
+    /// Whether the error proves the sender relayed something no honest node
+    /// would, rather than a block that just doesn't fit the local chain or
+    /// view yet.
+    pub fn is_forgery(&self) -> bool {
+        matches!(
+            self,
+            ChainError::InvalidProposerSignature(_)
+                | ChainError::UnknownProposer(_)
+                | ChainError::TransactionRootMismatch(_)
+                | ChainError::InvalidTransaction(_)
+                | ChainError::InvalidVote(_)
+        )
+    }
+}
+
+pub const DEFAULT_MAX_TRANSACTION_BYTES: usize = 1232;
+pub const DEFAULT_FUTURE_TOLERANCE: Duration = Duration::from_secs(2);
+pub const DEFAULT_PROPOSER_FALLBACK_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsensusResult {
+    Accepted,
+    InvalidSignature,
+    Expired,
+    FutureTimestamp { ahead_ms: u64 },
+    TooLarge { size: usize, limit: usize },
+    /// The validators that confirmed hold `confirmed_stake` of the
+    /// `required_stake` needed for a two-thirds supermajority.
+    InsufficientConfirmations { confirmed_stake: u64, required_stake: u64 },
+}
+
+impl ConsensusResult {
+    pub fn is_accepted(&self) -> bool {
+        matches!(self, ConsensusResult::Accepted)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TimestampedTransaction {
+    pub transaction: Transaction,
+    /// When this node received the transaction, for mempool aging.
+    pub timestamp: Instant,
+    /// When the transaction entered the network, on the submitting node's
+    /// wall clock. Checked against ours for skew and expiry.
+    pub sent_at_ms: i64,
+}
+
+impl TimestampedTransaction {
+    pub fn new(transaction: Transaction) -> Self {
+        TimestampedTransaction {
+            transaction,
+            timestamp: Instant::now(),
+            sent_at_ms: unix_now_ms(),
+        }
+    }
+
+    /// A transaction gossiped by a peer, stamped `sent_at_ms` by the node
+    /// it was submitted to.
+    pub fn received(transaction: Transaction, sent_at_ms: i64) -> Self {
+        TimestampedTransaction { sent_at_ms, ..TimestampedTransaction::new(transaction) }
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        self.transaction.verify().is_ok()
+    }
+
+    pub fn serialized_size(&self) -> usize {
+        bincode::serialized_size(&self.transaction)
+            .map(|size| size as usize)
+            .unwrap_or(usize::MAX)
+    }
+}
+
+#[async_trait]
+pub trait ValidatorClient: Send + Sync {
+    async fn verify_transaction(&self, validator: &Pubkey, transaction: &TimestampedTransaction) -> bool;
+
+    async fn verify_batch(&self, validator: &Pubkey, transactions: &[&TimestampedTransaction]) -> Vec<bool> {
+        let mut results = Vec::with_capacity(transactions.len());
+        for transaction in transactions {
+            results.push(self.verify_transaction(validator, transaction).await);
+        }
+        results
+    }
+}
+
+pub struct LocalValidatorClient;
+
+#[async_trait]
+impl ValidatorClient for LocalValidatorClient {
+    async fn verify_transaction(&self, _validator: &Pubkey, transaction: &TimestampedTransaction) -> bool {
+        transaction.verify_signature()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Validator {
+    pub pubkey: Pubkey,
+    pub stake: u64,
+    pub locked_until: i64,
+}
+
+impl Validator {
+    /// Whether the stake stays locked for at least `min_remaining_lock`
+    /// after `now`. Without a minimum every validator counts, as before the
+    /// minimum existed, even one whose lock has already run out.
+    pub fn is_eligible(&self, now: i64, min_remaining_lock: Duration) -> bool {
+        min_remaining_lock.is_zero() || self.locked_until.saturating_sub(now) >= min_remaining_lock.as_secs() as i64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub height: u64,
+    /// Slot the block was proposed in; its leader must be the proposer.
+    pub slot: u64,
+    pub parent_hash: Hash,
+    pub timestamp: i64,
+    pub tx_root: Hash,
+    pub proposer: Pubkey,
+}
+
+impl BlockHeader {
+    pub fn hash(&self) -> Hash {
+        hashv(&[
+            &self.height.to_le_bytes(),
+            &self.slot.to_le_bytes(),
+            self.parent_hash.as_ref(),
+            &self.timestamp.to_le_bytes(),
+            self.tx_root.as_ref(),
+            self.proposer.as_ref(),
+        ])
+    }
+}
+
+/// A header together with the transactions it commits to, signed by the
+/// proposer over the header hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+    pub signature: Signature,
+}
+
+impl Block {
+    /// Builds a block from `transactions`, filling in the transaction root
+    /// and signing it as `proposer`.
+    pub fn new(
+        height: u64,
+        slot: u64,
+        parent_hash: Hash,
+        timestamp: i64,
+        transactions: Vec<Transaction>,
+        proposer: &Keypair,
+    ) -> Self {
+        let header = BlockHeader {
+            height,
+            slot,
+            parent_hash,
+            timestamp,
+            tx_root: transaction_root(&transactions),
+            proposer: proposer.pubkey(),
+        };
+        let signature = proposer.sign_message(header.hash().as_ref());
+        Block { header, transactions, signature }
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        self.signature.verify(self.header.proposer.as_ref(), self.hash().as_ref())
+    }
+}
+
+/// Commits to the block's transactions, in order, by their first signature.
+pub fn transaction_root(transactions: &[Transaction]) -> Hash {
+    let signatures: Vec<&[u8]> = transactions
+        .iter()
+        .filter_map(|tx| tx.signatures.first().map(|s| s.as_ref()))
+        .collect();
+    hashv(&signatures)
+}
+
+/// A validator's vote to give up on the leaders before `view` at `height`
+/// because no block was committed in time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewChange {
+    pub height: u64,
+    pub view: u64,
+    pub validator: Pubkey,
+    pub signature: Signature,
+}
+
+impl ViewChange {
+    pub fn new(height: u64, view: u64, validator: &Keypair) -> Self {
+        ViewChange {
+            height,
+            view,
+            validator: validator.pubkey(),
+            signature: validator.sign_message(Self::signed_digest(height, view).as_ref()),
+        }
+    }
+
+    fn signed_digest(height: u64, view: u64) -> Hash {
+        hashv(&[b"view-change", &height.to_le_bytes(), &view.to_le_bytes()])
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        self.signature.verify(
+            self.validator.as_ref(),
+            Self::signed_digest(self.height, self.view).as_ref(),
+        )
+    }
+}
+
+/// A validator's vote to commit the block with `block_hash` at `height`. It
+/// signs the bare block hash, so votes collect straight into a
+/// `QuorumCertificate`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockVote {
+    pub block_hash: Hash,
+    pub height: u64,
+    pub validator: Pubkey,
+    pub signature: Signature,
+}
+
+impl BlockVote {
+    pub fn new(block: &Block, validator: &Keypair) -> Self {
+        let block_hash = block.hash();
+        BlockVote {
+            block_hash,
+            height: block.header.height,
+            validator: validator.pubkey(),
+            signature: validator.sign_message(block_hash.as_ref()),
+        }
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        self.signature.verify(self.validator.as_ref(), self.block_hash.as_ref())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuorumCertificate {
+    pub block_hash: Hash,
+    pub votes: Vec<(Pubkey, Signature)>,
+}
+
+impl QuorumCertificate {
+    /// Checks every vote and that the signers hold more than two thirds of
+    /// the voting weight of `validators`, the set eligible to vote.
+    pub fn verify<'a>(&self, validators: impl IntoIterator<Item = &'a Validator>) -> bool {
+        let validators: Vec<&Validator> = validators.into_iter().collect();
+        let mut signers = HashSet::new();
+        for (pubkey, signature) in &self.votes {
+            if !validators.iter().any(|v| v.pubkey == *pubkey) {
+                return false;
+            }
+            if !signature.verify(pubkey.as_ref(), self.block_hash.as_ref()) {
+                return false;
+            }
+            signers.insert(*pubkey);
+        }
+
+        let weights = vote_weights(validators.iter().copied());
+        let total = weights.iter().fold(0u64, |sum, w| sum.saturating_add(*w));
+        let signed = validators
+            .iter()
+            .zip(&weights)
+            .filter(|(v, _)| signers.contains(&v.pubkey))
+            .fold(0u64, |sum, (_, w)| sum.saturating_add(*w));
+        signed >= supermajority(total)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertifiedHeader {
+    pub header: BlockHeader,
+    pub qc: QuorumCertificate,
+}
 
 pub struct ConsensusManager {
     last_block_hash: Hash,
+    height: u64,
+    /// Slot of the tip block; the next block must come from a later one.
+    last_slot: u64,
     validators: Vec<Validator>,
     consensus_timeout: Duration,
     last_consensus: Instant,
+    /// Leader rotations agreed at the next height. Reset when a block commits.
+    view: u64,
+    /// Highest view each validator has asked for at the next height.
+    view_requests: HashMap<Pubkey, u64>,
+    /// Blocks proposed for the next height, one per proposer.
+    proposals: HashMap<Pubkey, Block>,
+    /// The first vote each validator cast at the next height.
+    block_votes: HashMap<Pubkey, BlockVote>,
+    min_validator_lock: Duration,
+    max_transaction_bytes: usize,
+    future_tolerance: Duration,
+    proposer_fallback_timeout: Duration,
+    validator_client: Arc<dyn ValidatorClient>,
+}
+
+impl std::fmt::Debug for ConsensusManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConsensusManager")
+            .field("height", &self.height)
+            .field("last_block_hash", &self.last_block_hash)
+            .field("validators", &self.validators)
+            .field("view", &self.view)
+            .finish_non_exhaustive()
+    }
 }
 
 impl ConsensusManager {
     pub fn new(timeout: Duration) -> Self {
         ConsensusManager {
             last_block_hash: Hash::default(),
+            height: 0,
+            last_slot: 0,
             validators: Vec::new(),
             consensus_timeout: timeout,
             last_consensus: Instant::now(),
+            view: 0,
+            view_requests: HashMap::new(),
+            proposals: HashMap::new(),
+            block_votes: HashMap::new(),
+            min_validator_lock: Duration::ZERO,
+            max_transaction_bytes: DEFAULT_MAX_TRANSACTION_BYTES,
+            future_tolerance: DEFAULT_FUTURE_TOLERANCE,
+            proposer_fallback_timeout: DEFAULT_PROPOSER_FALLBACK_TIMEOUT,
+            validator_client: Arc::new(LocalValidatorClient),
         }
     }
 
-    pub async fn validate_transaction(&self, transaction: &Transaction) -> bool {
-       
+    pub fn with_validator_client(mut self, client: Arc<dyn ValidatorClient>) -> Self {
+        self.validator_client = client;
+        self
+    }
+
+    pub fn with_max_transaction_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_transaction_bytes = max_bytes;
+        self
+    }
+
+    /// Accept transactions stamped up to `tolerance` ahead of the local clock
+    /// to absorb clock skew between nodes.
+    pub fn with_future_tolerance(mut self, tolerance: Duration) -> Self {
+        self.future_tolerance = tolerance;
+        self
+    }
+
+    pub fn with_proposer_fallback_timeout(mut self, timeout: Duration) -> Self {
+        self.proposer_fallback_timeout = timeout.max(Duration::from_millis(1));
+        self
+    }
+
+    pub fn with_min_validator_lock(mut self, min_lock: Duration) -> Self {
+        self.min_validator_lock = min_lock;
+        self
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn last_block_hash(&self) -> Hash {
+        self.last_block_hash
+    }
+
+    pub fn validators(&self) -> &[Validator] {
+        &self.validators
+    }
+
+    /// Validators whose stake stays locked for the minimum lock after `now`.
+    /// Only they vote, propose and carry weight in certificates and view
+    /// changes.
+    pub fn eligible_validators(&self, now: i64) -> Vec<&Validator> {
+        self.validators
+            .iter()
+            .filter(|v| v.is_eligible(now, self.min_validator_lock))
+            .collect()
+    }
+
+    pub fn quorum_weight(&self, now: i64) -> u64 {
+        self.eligible_validators(now).iter().map(|v| v.stake).sum()
+    }
+
+    fn voting_validators(&self) -> Vec<&Validator> {
+        self.eligible_validators(unix_now())
+    }
+
+    pub fn select_proposer(&self, slot: u64) -> Option<&Validator> {
+        self.proposer_order(slot).into_iter().next()
+    }
+
+    /// Proposer for `slot` in the current view: each agreed view change
+    /// passes the slot one place further down `proposer_order`.
+    pub fn leader(&self, slot: u64) -> Option<&Validator> {
+        self.proposer_after(slot, Duration::ZERO)
+    }
+
+    pub fn view(&self) -> u64 {
+        self.view
+    }
+
+    pub fn is_validator(&self, pubkey: &Pubkey) -> bool {
+        self.validators.iter().any(|v| v.pubkey == *pubkey)
+    }
+
+    /// Whether `consensus_timeout` has passed without a block committing or
+    /// the view changing.
+    pub fn view_timed_out(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_consensus) >= self.consensus_timeout
+    }
+
+    /// Applies a reloaded `consensus_timeout` to the view currently running.
+    pub fn set_consensus_timeout(&mut self, timeout: Duration) {
+        self.consensus_timeout = timeout;
+    }
+
+    /// Restarts the view timeout, e.g. while there is nothing to commit.
+    pub fn reset_view_timer(&mut self) {
+        self.last_consensus = Instant::now();
+    }
+
+    /// This validator's vote to move past the view it is stuck in. Restarts
+    /// the timeout so a further vote waits another `consensus_timeout`.
+    pub fn request_view_change(&mut self, validator: &Keypair) -> ViewChange {
+        let asked = self.requested_view(&validator.pubkey());
+        self.last_consensus = Instant::now();
+        ViewChange::new(self.height + 1, self.view.max(asked) + 1, validator)
+    }
+
+    /// Counts a view change vote. Votes for other heights or for views
+    /// already passed are ignored. Returns the new view once eligible
+    /// validators holding a two-thirds supermajority of stake have asked
+    /// for it.
+    pub fn record_view_change(&mut self, vote: &ViewChange) -> Result<Option<u64>, ChainError> {
+        if !self.is_validator(&vote.validator) || !vote.verify_signature() {
+            return Err(ChainError::InvalidViewChange(vote.height));
+        }
+        if vote.height != self.height + 1 || vote.view <= self.view {
+            return Ok(None);
+        }
+        let asked = self.view_requests.entry(vote.validator).or_insert(0);
+        *asked = (*asked).max(vote.view);
+
+        let total = vote_weights(self.voting_validators()).iter().fold(0u64, |sum, w| sum.saturating_add(*w));
+        let Some(view) = self.view_backed_by(supermajority(total)) else {
+            return Ok(None);
+        };
+        self.view = view;
+        self.last_consensus = Instant::now();
+        Ok(Some(view))
+    }
+
+    /// Highest view `validator` has asked for at the next height, 0 if none.
+    pub fn requested_view(&self, validator: &Pubkey) -> u64 {
+        self.view_requests.get(validator).copied().unwrap_or(0)
+    }
+
+    /// A view ahead of the current one that validators holding more than a
+    /// third of stake have asked for but `validator` hasn't. At least one of
+    /// the askers is honest, so a validator that hasn't timed out yet should
+    /// vote for it too.
+    pub fn view_to_join(&self, validator: &Pubkey) -> Option<u64> {
+        if !self.is_validator(validator) {
+            return None;
+        }
+        let total = vote_weights(self.voting_validators()).iter().fold(0u64, |sum, w| sum.saturating_add(*w));
+        let asked = self.requested_view(validator);
+        self.view_backed_by((total as u128 / 3 + 1) as u64).filter(|view| *view > asked)
+    }
+
+    /// Highest view past the current one whose supporters, counting
+    /// everyone who asked for it or a later view, weigh at least `required`.
+    fn view_backed_by(&self, required: u64) -> Option<u64> {
+        let voting = self.voting_validators();
+        let weights = vote_weights(voting.iter().copied());
+        let mut views: Vec<u64> = self.view_requests.values().copied().filter(|v| *v > self.view).collect();
+        views.sort_unstable_by(|a, b| b.cmp(a));
+        views.dedup();
+        views.into_iter().find(|view| {
+            let backing = voting
+                .iter()
+                .zip(&weights)
+                .filter(|(v, _)| self.view_requests.get(&v.pubkey).map_or(false, |asked| asked >= view))
+                .fold(0u64, |sum, (_, w)| sum.saturating_add(*w));
+            backing >= required
+        })
+    }
+
+    /// Ranked proposers for `slot` among the eligible validators: the
+    /// primary first, then fallbacks ordered by a hash of the slot and
+    /// pubkey. The primary is drawn with probability proportional to stake.
+    /// Depends only on the validator set and the minimum lock, so every
+    /// honest node derives the same order.
+    pub fn proposer_order(&self, slot: u64) -> Vec<&Validator> {
+        let mut ordered = self.voting_validators();
+        if ordered.is_empty() {
+            return Vec::new();
+        }
+        ordered.sort_by_key(|v| v.pubkey);
+        let primary = ordered.remove(leader_index(&ordered, slot));
+
+        let slot_bytes = slot.to_le_bytes();
+
+        ordered.sort_by_cached_key(|v| (hashv(&[&slot_bytes, v.pubkey.as_ref()]), v.pubkey));
+        ordered.insert(0, primary);
+        ordered
+    }
+
+    /// Proposer expected to be producing `slot` once `elapsed` has passed
+    /// since the slot started. Each fallback timeout without a block hands the
+    /// slot to the next validator in `proposer_order` after the leader.
+    pub fn proposer_after(&self, slot: u64, elapsed: Duration) -> Option<&Validator> {
+        let order = self.proposer_order(slot);
+        if order.is_empty() {
+            return None;
+        }
+        let len = order.len() as u64;
+        let rank = (self.view % len + self.fallback_rank(elapsed) % len) % len;
+        Some(order[rank as usize])
+    }
+
+    /// Fallback timeouts that fit in `elapsed`.
+    fn fallback_rank(&self, elapsed: Duration) -> u64 {
+        (elapsed.as_millis() / self.proposer_fallback_timeout.as_millis()).min(u64::MAX as u128) as u64
+    }
+
+    pub fn add_validator(&mut self, validator: Validator) {
+        if !self.validators.iter().any(|v| v.pubkey == validator.pubkey) {
+            self.validators.push(validator);
+        }
+    }
+
+    /// Replaces the validator set, as when it is rebuilt from on-chain stake
+    /// at an epoch boundary.
+    pub fn set_validators(&mut self, validators: Vec<Validator>) {
+        self.validators = validators;
+    }
+
+    pub fn verify_header_chain(&self, headers: &[CertifiedHeader]) -> Result<(), ChainError> {
+        let mut parent_hash = self.last_block_hash;
+        let mut height = self.height;
+
+        for certified in headers {
+            let header = &certified.header;
+            if header.height != height + 1 || header.parent_hash != parent_hash {
+                return Err(ChainError::BrokenLinkage(header.height));
+            }
+
+            let hash = header.hash();
+            if certified.qc.block_hash != hash || !certified.qc.verify(self.voting_validators()) {
+                return Err(ChainError::InvalidQuorum(header.height));
+            }
+
+            parent_hash = hash;
+            height = header.height;
+        }
+
+        Ok(())
+    }
+
+    pub fn fast_forward(&mut self, headers: &[CertifiedHeader]) -> Result<(), ChainError> {
+        self.verify_header_chain(headers)?;
+
+        if let Some(tip) = headers.last() {
+            self.set_tip(&tip.header);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `header` is the next block on the local chain.
+    pub fn check_extends(&self, header: &BlockHeader) -> Result<(), ChainError> {
+        if header.height != self.height + 1 || header.parent_hash != self.last_block_hash {
+            return Err(ChainError::BrokenLinkage(header.height));
+        }
+        Ok(())
+    }
+
+    /// Moves the local tip to `header`, for a block just committed or one
+    /// restored from storage on startup.
+    pub fn set_tip(&mut self, header: &BlockHeader) {
+        self.last_block_hash = header.hash();
+        self.height = header.height;
+        self.last_slot = header.slot;
+        self.last_consensus = Instant::now();
+        self.view = 0;
+        self.view_requests.clear();
+        self.proposals.clear();
+        self.block_votes.clear();
+    }
+
+    /// Builds the next block on the local tip from up to `max_transactions`
+    /// of the highest-fee pending transactions. Those failing `pre_validate`
+    /// are dropped rather than returned to the mempool.
+    pub fn form_block(&self, mempool: &mut Mempool, slot: u64, max_transactions: usize, proposer: &Keypair) -> Block {
+        let transactions: Vec<Transaction> = mempool
+            .take(max_transactions)
+            .into_iter()
+            .filter(|pending| self.pre_validate(pending).is_ok())
+            .map(|pending| pending.transaction)
+            .collect();
+        Block::new(self.height + 1, slot, self.last_block_hash, unix_now(), transactions, proposer)
+    }
+
+    /// Checks that `block` extends the local tip, is signed by a known
+    /// validator and carries only validly signed transactions matching its
+    /// transaction root. Any proposer is accepted while the validator set is
+    /// still empty.
+    ///
+    /// This is all a block already committed elsewhere needs, as when
+    /// catching up from a peer. A fresh proposal must also pass
+    /// `add_proposal`.
+    pub fn verify_block(&self, block: &Block) -> Result<(), ChainError> {
+        let header = &block.header;
+        self.check_extends(header)?;
+        if !block.verify_signature() {
+            return Err(ChainError::InvalidProposerSignature(header.height));
+        }
+        if !self.validators.is_empty() && !self.validators.iter().any(|v| v.pubkey == header.proposer) {
+            return Err(ChainError::UnknownProposer(header.height));
+        }
+        if header.tx_root != transaction_root(&block.transactions) {
+            return Err(ChainError::TransactionRootMismatch(header.height));
+        }
+        if block.transactions.iter().any(|tx| tx.verify().is_err()) {
+            return Err(ChainError::InvalidTransaction(header.height));
+        }
+        Ok(())
+    }
+
+    /// Verifies `block` and makes it the local tip.
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), ChainError> {
+        self.verify_block(block)?;
+        self.set_tip(&block.header);
+        Ok(())
+    }
+
+    /// Whether blocks need a quorum certificate before they commit. A chain
+    /// without validators has nobody to vote, so blocks commit as proposed.
+    pub fn requires_quorum(&self) -> bool {
+        !self.validators.is_empty()
+    }
+
+    /// Checks that `header` comes from a slot after the tip's, no later than
+    /// `current_slot`, and that its proposer leads that slot in the current
+    /// view or is a fallback whose turn has come `elapsed` into the slot.
+    pub fn check_leader(&self, header: &BlockHeader, current_slot: u64, elapsed: Duration) -> Result<(), ChainError> {
+        let after_tip = self.height == 0 || header.slot > self.last_slot;
+        if !after_tip || header.slot > current_slot {
+            return Err(ChainError::WrongLeader(header.height));
+        }
+        let order = self.proposer_order(header.slot);
+        let len = order.len() as u64;
+        let turns = self.fallback_rank(elapsed).saturating_add(1).min(len);
+        let has_turn = (0..turns).any(|rank| order[((self.view % len + rank) % len) as usize].pubkey == header.proposer);
+        if !has_turn {
+            return Err(ChainError::WrongLeader(header.height));
+        }
+        Ok(())
+    }
+
+    /// Holds `block` as a candidate for the next height until validators
+    /// vote it through. Each proposer gets one candidate per height. Returns
+    /// whether the block was new.
+    pub fn add_proposal(&mut self, block: &Block, current_slot: u64, elapsed: Duration) -> Result<bool, ChainError> {
+        self.verify_block(block)?;
+        self.check_leader(&block.header, current_slot, elapsed)?;
+        if self.proposals.contains_key(&block.header.proposer) {
+            return Ok(false);
+        }
+        self.proposals.insert(block.header.proposer, block.clone());
+        Ok(true)
+    }
+
+    /// Whether `validator` has already proposed a block for the next height.
+    pub fn has_proposed(&self, validator: &Pubkey) -> bool {
+        self.proposals.contains_key(validator)
+    }
+
+    /// Whether any validator has proposed a block for the next height.
+    pub fn has_proposals(&self) -> bool {
+        !self.proposals.is_empty()
+    }
+
+    /// Whether `validator` has already voted at the next height.
+    pub fn has_voted(&self, validator: &Pubkey) -> bool {
+        self.block_votes.contains_key(validator)
+    }
+
+    /// Counts a vote for a block at the next height. A validator's first
+    /// vote at a height is the one that counts. Returns the block and its
+    /// certificate once it has votes from a two-thirds supermajority of
+    /// stake.
+    pub fn record_block_vote(&mut self, vote: &BlockVote) -> Result<Option<(Block, QuorumCertificate)>, ChainError> {
+        if !self.is_validator(&vote.validator) || !vote.verify_signature() {
+            return Err(ChainError::InvalidVote(vote.height));
+        }
+        if vote.height != self.height + 1 {
+            return Ok(None);
+        }
+        self.block_votes.entry(vote.validator).or_insert_with(|| vote.clone());
+        Ok(self.certified())
+    }
+
+    /// A proposal for the next height together with a certificate from the
+    /// votes cast for it, if the eligible validators among the voters
+    /// already reach a supermajority.
+    pub fn certified(&self) -> Option<(Block, QuorumCertificate)> {
+        let voting = self.voting_validators();
+        self.proposals.values().find_map(|block| {
+            let block_hash = block.hash();
+            let votes = self
+                .block_votes
+                .values()
+                .filter(|vote| vote.block_hash == block_hash && voting.iter().any(|v| v.pubkey == vote.validator))
+                .map(|vote| (vote.validator, vote.signature))
+                .collect();
+            let qc = QuorumCertificate { block_hash, votes };
+            qc.verify(voting.iter().copied()).then(|| (block.clone(), qc))
+        })
+    }
+
+    pub fn pre_validate(&self, transaction: &TimestampedTransaction) -> Result<(), ConsensusResult> {
+        let size = transaction.serialized_size();
+        if size > self.max_transaction_bytes {
+            return Err(ConsensusResult::TooLarge {
+                size,
+                limit: self.max_transaction_bytes,
+            });
+        }
+
         if !self.verify_signature(transaction) {
-            return false;
+            return Err(ConsensusResult::InvalidSignature);
         }
 
-        
-        if !self.verify_timestamp(transaction) {
-            return false;
+        self.verify_timestamp(transaction, unix_now_ms())
+    }
+
+    pub async fn validate_transaction(&self, transaction: &TimestampedTransaction) -> ConsensusResult {
+        if let Err(result) = self.pre_validate(transaction) {
+            return result;
         }
 
        
-        let confirmations = self.get_validator_confirmations(transaction).await;
-        
-        
-        confirmations > (self.validators.len() * 2 / 3)
+        let now = unix_now();
+        let (confirmed_stake, total_stake) = self.get_validator_confirmations(transaction, now).await;
+        let required_stake = supermajority(total_stake);
+
+        if confirmed_stake >= required_stake {
+            ConsensusResult::Accepted
+        } else {
+            ConsensusResult::InsufficientConfirmations { confirmed_stake, required_stake }
+        }
+    }
+
+    pub async fn validate_batch(&self, transactions: &[TimestampedTransaction]) -> Vec<ConsensusResult> {
+        let mut results: Vec<Option<ConsensusResult>> = transactions
+            .iter()
+            .map(|tx| self.pre_validate(tx).err())
+            .collect();
+
+        let mut unique: Vec<&TimestampedTransaction> = Vec::new();
+        let mut slot_of: Vec<Option<usize>> = vec![None; transactions.len()];
+        let mut index_by_signature: HashMap<Signature, usize> = HashMap::new();
+        for (i, tx) in transactions.iter().enumerate() {
+            if results[i].is_some() {
+                continue;
+            }
+            let signature = tx.transaction.signatures.first().copied().unwrap_or_default();
+            let slot = *index_by_signature.entry(signature).or_insert_with(|| {
+                unique.push(tx);
+                unique.len() - 1
+            });
+            slot_of[i] = Some(slot);
+        }
+
+        let now = unix_now();
+        let eligible = self.eligible_validators(now);
+        let weights = vote_weights(eligible.iter().copied());
+        let required_stake = supermajority(weights.iter().fold(0u64, |sum, w| sum.saturating_add(*w)));
+        let mut confirmations = vec![0u64; unique.len()];
+
+        if !unique.is_empty() {
+            for (validator, weight) in eligible.iter().zip(&weights) {
+                let verdicts = self.validator_client.verify_batch(&validator.pubkey, &unique).await;
+                for (confirmed, approved) in confirmations.iter_mut().zip(verdicts) {
+                    if approved {
+                        *confirmed = confirmed.saturating_add(*weight);
+                    }
+                }
+            }
+        }
+
+        for (i, result) in results.iter_mut().enumerate() {
+            if let Some(slot) = slot_of[i] {
+                let confirmed_stake = confirmations[slot];
+                *result = Some(if confirmed_stake >= required_stake {
+                    ConsensusResult::Accepted
+                } else {
+                    ConsensusResult::InsufficientConfirmations { confirmed_stake, required_stake }
+                });
+            }
+        }
+
+        results.into_iter().map(|r| r.unwrap_or(ConsensusResult::InvalidSignature)).collect()
     }
 
-    fn verify_signature(&self, transaction: &Transaction) -> bool {
+    fn verify_signature(&self, transaction: &TimestampedTransaction) -> bool {
         transaction.verify_signature()
     }
 
-    fn verify_timestamp(&self, transaction: &Transaction) -> bool {
-       
-        let now = Instant::now();
-        let transaction_age = now.duration_since(transaction.timestamp);
-        transaction_age < self.consensus_timeout
+    /// Compares the submitter's wall-clock stamp with `now_ms`: up to
+    /// `future_tolerance` ahead is clock skew, further ahead is rejected, and
+    /// older than `consensus_timeout` has expired.
+    fn verify_timestamp(&self, transaction: &TimestampedTransaction, now_ms: i64) -> Result<(), ConsensusResult> {
+        let ahead_ms = transaction.sent_at_ms.saturating_sub(now_ms);
+        if ahead_ms > 0 {
+            if ahead_ms as u128 > self.future_tolerance.as_millis() {
+                return Err(ConsensusResult::FutureTimestamp { ahead_ms: ahead_ms as u64 });
+            }
+            return Ok(());
+        }
+
+        let age_ms = now_ms.saturating_sub(transaction.sent_at_ms);
+        if (age_ms as u128) < self.consensus_timeout.as_millis() {
+            Ok(())
+        } else {
+            Err(ConsensusResult::Expired)
+        }
     }
 
-    async fn get_validator_confirmations(&self, transaction: &Transaction) -> usize {
-        let mut confirmations = 0;
-        for validator in &self.validators {
-            if validator.verify_transaction(transaction).await {
-                confirmations += 1;
+    /// Stake of the eligible validators that confirm `transaction`, and the
+    /// stake of all eligible validators.
+    async fn get_validator_confirmations(&self, transaction: &TimestampedTransaction, now: i64) -> (u64, u64) {
+        let eligible = self.eligible_validators(now);
+        let weights = vote_weights(eligible.iter().copied());
+        let mut confirmed = 0u64;
+        let mut total = 0u64;
+        for (validator, weight) in eligible.iter().zip(weights) {
+            total = total.saturating_add(weight);
+            if self.validator_client.verify_transaction(&validator.pubkey, transaction).await {
+                confirmed = confirmed.saturating_add(weight);
             }
         }
-        confirmations
+        (confirmed, total)
+    }
+}
+
+/// Weight each validator votes with: its staked lamports. When nobody in the
+/// set has stake, as on a network without a stake program, each validator
+/// gets one vote instead.
+fn vote_weights<'a>(validators: impl IntoIterator<Item = &'a Validator>) -> Vec<u64> {
+    let stakes: Vec<u64> = validators.into_iter().map(|v| v.stake).collect();
+    if stakes.iter().all(|stake| *stake == 0) {
+        return vec![1; stakes.len()];
+    }
+    stakes
+}
+
+/// Smallest weight strictly greater than two thirds of `total`.
+fn supermajority(total: u64) -> u64 {
+    (total as u128 * 2 / 3 + 1) as u64
+}
+
+/// Index into `validators` (sorted by pubkey) of the stake-weighted leader
+/// for the slot. Falls back to round robin when nobody has stake.
+fn leader_index(validators: &[&Validator], slot: u64) -> usize {
+    let total: u64 = validators.iter().fold(0u64, |sum, v| sum.saturating_add(v.stake));
+    if total == 0 {
+        return (slot % validators.len() as u64) as usize;
+    }
+
+    let draw = hashv(&[b"leader", &slot.to_le_bytes()]);
+    let mut point = u64::from_le_bytes(draw.as_ref()[..8].try_into().unwrap()) % total;
+    for (i, validator) in validators.iter().enumerate() {
+        if point < validator.stake {
+            return i;
+        }
+        point -= validator.stake;
+    }
+    validators.len() - 1
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn unix_now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::system_transaction;
+
+    fn signed_transfer() -> TimestampedTransaction {
+        let payer = Keypair::new();
+        TimestampedTransaction::new(system_transaction::transfer(
+            &payer,
+            &Pubkey::new_unique(),
+            1_000,
+            Hash::default(),
+        ))
+    }
+
+    fn validator(stake: u64, locked_until: i64) -> Validator {
+        Validator {
+            pubkey: Pubkey::new_unique(),
+            stake,
+            locked_until,
+        }
+    }
+
+    #[test]
+    fn test_form_block_extends_tip_with_valid_mempool_transactions() {
+        let manager = ConsensusManager::new(Duration::from_secs(5));
+        let mut mempool = Mempool::new(16);
+        let valid = signed_transfer();
+        let mut tampered = signed_transfer();
+        tampered.transaction.message.recent_blockhash = Hash::new_unique();
+        mempool.add(valid.clone());
+        mempool.add(tampered);
+        mempool.add(signed_transfer());
+
+        let proposer = Keypair::new();
+        let block = manager.form_block(&mut mempool, 4, 2, &proposer);
+
+        assert_eq!(block.transactions, vec![valid.transaction]);
+        assert_eq!(block.header.height, 1);
+        assert_eq!(block.header.slot, 4);
+        assert_eq!(block.header.parent_hash, manager.last_block_hash());
+        assert_eq!(block.header.tx_root, transaction_root(&block.transactions));
+        assert_eq!(block.header.proposer, proposer.pubkey());
+        assert_eq!(mempool.len(), 1);
+        assert!(manager.verify_block(&block).is_ok());
+    }
+
+    #[test]
+    fn test_apply_block_checks_proposer_and_contents() {
+        let proposer = Keypair::new();
+        let mut manager = ConsensusManager::new(Duration::from_secs(5));
+        manager.add_validator(Validator { pubkey: proposer.pubkey(), stake: 1_000, locked_until: 0 });
+        let block = Block::new(1, 1, Hash::default(), 0, vec![signed_transfer().transaction], &proposer);
+
+        let outsider = Block::new(1, 1, Hash::default(), 0, Vec::new(), &Keypair::new());
+        assert!(matches!(manager.apply_block(&outsider), Err(ChainError::UnknownProposer(1))));
+
+        let mut forged = block.clone();
+        forged.header.timestamp = 1;
+        assert!(matches!(manager.apply_block(&forged), Err(ChainError::InvalidProposerSignature(1))));
+
+        let mut swapped = block.clone();
+        swapped.transactions = vec![signed_transfer().transaction];
+        assert!(matches!(manager.apply_block(&swapped), Err(ChainError::TransactionRootMismatch(1))));
+
+        manager.apply_block(&block).unwrap();
+        assert_eq!(manager.height(), 1);
+        assert_eq!(manager.last_block_hash(), block.hash());
+        assert!(matches!(manager.apply_block(&block), Err(ChainError::BrokenLinkage(1))));
+    }
+
+    #[test]
+    fn test_leader_schedule_follows_stake() {
+        let mut manager = ConsensusManager::new(Duration::from_secs(5));
+        let whale = validator(9_000, 0);
+        manager.add_validator(whale.clone());
+        manager.add_validator(validator(1_000, 0));
+
+        let led = (0..1_000u64)
+            .filter(|slot| manager.select_proposer(*slot).unwrap().pubkey == whale.pubkey)
+            .count();
+        assert!((850..=950).contains(&led), "whale led {} of 1000 slots", led);
+    }
+
+    #[test]
+    fn test_quorum_certificate_weighs_votes_by_stake() {
+        let whale = Keypair::new();
+        let minnows = [Keypair::new(), Keypair::new(), Keypair::new()];
+        let mut validators = vec![Validator { pubkey: whale.pubkey(), stake: 7_000, locked_until: 0 }];
+        validators.extend(minnows.iter().map(|k| Validator { pubkey: k.pubkey(), stake: 1_000, locked_until: 0 }));
+
+        let block_hash = Hash::new_unique();
+        let vote = |key: &Keypair| (key.pubkey(), key.sign_message(block_hash.as_ref()));
+        let minority = QuorumCertificate { block_hash, votes: minnows.iter().map(vote).collect() };
+        let majority = QuorumCertificate { block_hash, votes: vec![vote(&whale)] };
+
+        assert!(!minority.verify(&validators));
+        assert!(majority.verify(&validators));
+    }
+
+    #[test]
+    fn test_proposal_commits_only_from_slot_leader_with_quorum() {
+        let keys: Vec<Keypair> = (0..4).map(|_| Keypair::new()).collect();
+        let mut manager = ConsensusManager::new(Duration::from_secs(5));
+        for key in &keys {
+            manager.add_validator(Validator { pubkey: key.pubkey(), stake: 1_000, locked_until: 0 });
+        }
+        assert!(manager.requires_quorum());
+        let signer = |pubkey: Pubkey| keys.iter().find(|k| k.pubkey() == pubkey).unwrap();
+        let leader = signer(manager.leader(5).unwrap().pubkey);
+        let other = keys.iter().find(|k| k.pubkey() != leader.pubkey()).unwrap();
+
+        let usurped = Block::new(1, 5, Hash::default(), 0, Vec::new(), other);
+        assert!(matches!(manager.add_proposal(&usurped, 5, Duration::ZERO), Err(ChainError::WrongLeader(1))));
+        let early = Block::new(1, 5, Hash::default(), 0, Vec::new(), leader);
+        assert!(matches!(manager.add_proposal(&early, 4, Duration::ZERO), Err(ChainError::WrongLeader(1))));
+
+        let block = Block::new(1, 5, Hash::default(), 0, Vec::new(), leader);
+        assert!(manager.add_proposal(&block, 5, Duration::ZERO).unwrap());
+        assert!(!manager.add_proposal(&block, 5, Duration::ZERO).unwrap());
+        assert!(manager.has_proposed(&leader.pubkey()));
+
+        let outsider = BlockVote::new(&block, &Keypair::new());
+        assert!(matches!(manager.record_block_vote(&outsider), Err(ChainError::InvalidVote(1))));
+        let voters: Vec<&Keypair> = keys.iter().filter(|k| k.pubkey() != other.pubkey()).collect();
+        assert_eq!(manager.record_block_vote(&BlockVote::new(&block, voters[0])).unwrap(), None);
+        assert_eq!(manager.record_block_vote(&BlockVote::new(&block, voters[1])).unwrap(), None);
+        // A second vote from the same validator doesn't count twice.
+        assert_eq!(manager.record_block_vote(&BlockVote::new(&block, voters[1])).unwrap(), None);
+        let (certified, qc) = manager.record_block_vote(&BlockVote::new(&block, voters[2])).unwrap().unwrap();
+        assert_eq!(certified, block);
+        assert!(qc.verify(manager.validators()));
+
+        manager.apply_block(&block).unwrap();
+        assert!(!manager.has_proposed(&leader.pubkey()));
+        let replayed_slot = Block::new(2, 5, block.hash(), 0, Vec::new(), signer(manager.leader(5).unwrap().pubkey));
+        assert!(matches!(manager.add_proposal(&replayed_slot, 9, Duration::ZERO), Err(ChainError::WrongLeader(2))));
+    }
+
+    #[test]
+    fn test_view_change_rotates_leader_once_supermajority_agrees() {
+        let keys: Vec<Keypair> = (0..4).map(|_| Keypair::new()).collect();
+        let mut manager = ConsensusManager::new(Duration::from_secs(5));
+        for key in &keys {
+            manager.add_validator(Validator { pubkey: key.pubkey(), stake: 1_000, locked_until: 0 });
+        }
+        let stalled = manager.leader(7).unwrap().pubkey;
+
+        assert_eq!(manager.record_view_change(&ViewChange::new(1, 1, &keys[0])).unwrap(), None);
+        assert_eq!(manager.view_to_join(&keys[3].pubkey()), None);
+        assert_eq!(manager.record_view_change(&ViewChange::new(1, 1, &keys[1])).unwrap(), None);
+        assert_eq!(manager.view_to_join(&keys[3].pubkey()), Some(1));
+        assert_eq!(manager.view_to_join(&keys[0].pubkey()), None);
+        assert_eq!(manager.record_view_change(&ViewChange::new(2, 1, &keys[2])).unwrap(), None);
+        assert_eq!(manager.record_view_change(&ViewChange::new(1, 1, &keys[2])).unwrap(), Some(1));
+
+        assert_eq!(manager.view(), 1);
+        assert_eq!(manager.leader(7).unwrap().pubkey, manager.proposer_order(7)[1].pubkey);
+        assert_ne!(manager.leader(7).unwrap().pubkey, stalled);
+        assert_eq!(manager.record_view_change(&ViewChange::new(1, 1, &keys[3])).unwrap(), None);
+
+        let outsider = ViewChange::new(1, 2, &Keypair::new());
+        assert!(matches!(manager.record_view_change(&outsider), Err(ChainError::InvalidViewChange(1))));
+        let mut forged = ViewChange::new(1, 2, &keys[0]);
+        forged.view = 3;
+        assert!(matches!(manager.record_view_change(&forged), Err(ChainError::InvalidViewChange(1))));
+
+        let block = Block::new(1, 7, Hash::default(), 0, Vec::new(), &keys[0]);
+        manager.apply_block(&block).unwrap();
+        assert_eq!(manager.view(), 0);
+        assert_eq!(manager.request_view_change(&keys[0]), ViewChange::new(2, 1, &keys[0]));
+    }
+
+    #[test]
+    fn test_near_expiry_vote_does_not_complete_certificate() {
+        let now = unix_now();
+        let keys: Vec<Keypair> = (0..4).map(|_| Keypair::new()).collect();
+        let mut manager = ConsensusManager::new(Duration::from_secs(5)).with_min_validator_lock(Duration::from_secs(86_400));
+        for (i, key) in keys.iter().enumerate() {
+            let locked_until = if i == 0 { now + 60 } else { now + 30 * 86_400 };
+            manager.add_validator(Validator { pubkey: key.pubkey(), stake: 1_000, locked_until });
+        }
+        let expiring = &keys[0];
+        let long_locked: Vec<&Keypair> = keys[1..].iter().collect();
+        assert!(manager.proposer_order(3).iter().all(|v| v.pubkey != expiring.pubkey()));
+
+        let leader = *long_locked.iter().find(|k| k.pubkey() == manager.leader(3).unwrap().pubkey).unwrap();
+        let block = Block::new(1, 3, Hash::default(), 0, Vec::new(), leader);
+        assert!(manager.add_proposal(&block, 3, Duration::ZERO).unwrap());
+
+        assert_eq!(manager.record_block_vote(&BlockVote::new(&block, expiring)).unwrap(), None);
+        assert_eq!(manager.record_block_vote(&BlockVote::new(&block, long_locked[0])).unwrap(), None);
+        // Three of four validators by stake, but only two of the three whose
+        // stake stays locked long enough.
+        assert_eq!(manager.record_block_vote(&BlockVote::new(&block, long_locked[1])).unwrap(), None);
+        let (_, qc) = manager.record_block_vote(&BlockVote::new(&block, long_locked[2])).unwrap().unwrap();
+        assert!(qc.votes.iter().all(|(pubkey, _)| *pubkey != expiring.pubkey()));
+        assert!(qc.verify(manager.eligible_validators(now)));
+    }
+
+    #[test]
+    fn test_near_expiry_lock_excluded_from_quorum() {
+        let now = 1_700_000_000;
+        let mut manager = ConsensusManager::new(Duration::from_secs(5))
+            .with_min_validator_lock(Duration::from_secs(86_400));
+
+        let expiring = validator(500, now + 60);
+        let long_locked = validator(300, now + 30 * 86_400);
+        manager.add_validator(expiring.clone());
+        manager.add_validator(long_locked.clone());
+
+        let eligible = manager.eligible_validators(now);
+        assert_eq!(eligible.len(), 1);
+        assert_eq!(eligible[0].pubkey, long_locked.pubkey);
+        assert_eq!(manager.quorum_weight(now), 300);
+    }
+
+    #[test]
+    fn test_no_minimum_lock_counts_all_validators() {
+        let now = 1_700_000_000;
+        let mut manager = ConsensusManager::new(Duration::from_secs(5));
+        manager.add_validator(validator(500, now));
+        manager.add_validator(validator(300, now + 86_400));
+
+        assert_eq!(manager.quorum_weight(now), 800);
+    }
+
+    #[test]
+    fn test_expired_lock_counts_without_minimum() {
+        let now = 1_700_000_000;
+        let mut manager = ConsensusManager::new(Duration::from_secs(5));
+        manager.add_validator(validator(500, now - 86_400));
+        manager.add_validator(validator(300, 0));
+
+        assert_eq!(manager.eligible_validators(now).len(), 2);
+        assert_eq!(manager.quorum_weight(now), 800);
+    }
+
+    #[test]
+    fn test_cluster_agrees_on_fallback_when_primary_offline() {
+        let timeout = Duration::from_millis(500);
+        let validators: Vec<Validator> = (0..5).map(|_| validator(1_000, 0)).collect();
+
+        let nodes: Vec<ConsensusManager> = (0..4)
+            .map(|shift| {
+                let mut manager = ConsensusManager::new(Duration::from_secs(5))
+                    .with_proposer_fallback_timeout(timeout);
+                let mut local = validators.clone();
+                local.rotate_left(shift);
+                for v in local {
+                    manager.add_validator(v);
+                }
+                manager
+            })
+            .collect();
+
+        for slot in 0..20 {
+            let primary = nodes[0].select_proposer(slot).unwrap().pubkey;
+            let before_timeout: HashSet<Pubkey> = nodes
+                .iter()
+                .map(|n| n.proposer_after(slot, timeout - Duration::from_millis(1)).unwrap().pubkey)
+                .collect();
+            assert_eq!(before_timeout, HashSet::from([primary]));
+
+            let fallback: HashSet<Pubkey> = nodes
+                .iter()
+                .map(|n| n.proposer_after(slot, timeout).unwrap().pubkey)
+                .collect();
+            assert_eq!(fallback.len(), 1);
+            assert_ne!(fallback.into_iter().next().unwrap(), primary);
+        }
+    }
+
+    #[test]
+    fn test_fallback_proposal_accepted_once_its_turn_comes() {
+        let timeout = Duration::from_millis(500);
+        let keys: Vec<Keypair> = (0..4).map(|_| Keypair::new()).collect();
+        let mut manager = ConsensusManager::new(Duration::from_secs(5)).with_proposer_fallback_timeout(timeout);
+        for key in &keys {
+            manager.add_validator(Validator { pubkey: key.pubkey(), stake: 1_000, locked_until: 0 });
+        }
+        let signer = |pubkey: Pubkey| keys.iter().find(|k| k.pubkey() == pubkey).unwrap();
+        let order: Vec<Pubkey> = manager.proposer_order(5).iter().map(|v| v.pubkey).collect();
+
+        let second = Block::new(1, 5, Hash::default(), 0, Vec::new(), signer(order[1]));
+        assert!(matches!(manager.add_proposal(&second, 5, timeout / 2), Err(ChainError::WrongLeader(1))));
+        let third = Block::new(1, 5, Hash::default(), 0, Vec::new(), signer(order[2]));
+        assert!(matches!(manager.add_proposal(&third, 5, timeout), Err(ChainError::WrongLeader(1))));
+        assert!(manager.add_proposal(&second, 5, timeout).unwrap());
+        // Earlier ranks keep their turn once later ones have theirs.
+        let primary = Block::new(1, 5, Hash::default(), 0, Vec::new(), signer(order[0]));
+        assert!(manager.add_proposal(&primary, 5, timeout * 2).unwrap());
+    }
+
+    #[test]
+    fn test_proposer_order_covers_every_validator_once() {
+        let mut manager = ConsensusManager::new(Duration::from_secs(5));
+        for _ in 0..6 {
+            manager.add_validator(validator(1_000, 0));
+        }
+
+        let order: HashSet<Pubkey> = manager.proposer_order(42).iter().map(|v| v.pubkey).collect();
+        assert_eq!(order.len(), 6);
+    }
+
+    #[test]
+    fn test_slightly_future_timestamp_within_tolerance_accepted() {
+        let manager = ConsensusManager::new(Duration::from_secs(5))
+            .with_future_tolerance(Duration::from_secs(2));
+        let now_ms = 1_700_000_000_000;
+        let mut transaction = signed_transfer();
+
+        transaction.sent_at_ms = now_ms + 500;
+        assert_eq!(manager.verify_timestamp(&transaction, now_ms), Ok(()));
+
+        transaction.sent_at_ms = now_ms + 2_000;
+        assert_eq!(manager.verify_timestamp(&transaction, now_ms), Ok(()));
+
+        transaction.sent_at_ms = now_ms - 5_000;
+        assert_eq!(manager.verify_timestamp(&transaction, now_ms), Err(ConsensusResult::Expired));
+    }
+
+    #[test]
+    fn test_far_future_timestamp_rejected() {
+        let manager = ConsensusManager::new(Duration::from_secs(5))
+            .with_future_tolerance(Duration::from_secs(2));
+        let now_ms = 1_700_000_000_000;
+        let mut transaction = signed_transfer();
+
+        transaction.sent_at_ms = now_ms + 2_001;
+        assert_eq!(
+            manager.verify_timestamp(&transaction, now_ms),
+            Err(ConsensusResult::FutureTimestamp { ahead_ms: 2_001 })
+        );
+
+        // A peer's clock a minute fast shows on the wire, whenever it arrives.
+        let gossiped = TimestampedTransaction::received(transaction.transaction.clone(), unix_now_ms() + 60_000);
+        assert!(matches!(
+            manager.pre_validate(&gossiped),
+            Err(ConsensusResult::FutureTimestamp { .. })
+        ));
+    }
+
+    #[test]
+    fn test_transaction_at_size_limit_accepted() {
+        let transaction = signed_transfer();
+        let size = transaction.serialized_size();
+        let manager = ConsensusManager::new(Duration::from_secs(5))
+            .with_max_transaction_bytes(size);
+
+        assert!(manager.pre_validate(&transaction).is_ok());
+    }
+
+    #[test]
+    fn test_transaction_over_size_limit_rejected() {
+        let transaction = signed_transfer();
+        let size = transaction.serialized_size();
+        let manager = ConsensusManager::new(Duration::from_secs(5))
+            .with_max_transaction_bytes(size - 1);
+
+        assert_eq!(
+            manager.pre_validate(&transaction),
+            Err(ConsensusResult::TooLarge { size, limit: size - 1 })
+        );
+    }
+
+    struct CountingClient {
+        batch_calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ValidatorClient for CountingClient {
+        async fn verify_transaction(&self, _validator: &Pubkey, transaction: &TimestampedTransaction) -> bool {
+            transaction.verify_signature()
+        }
+
+        async fn verify_batch(&self, _validator: &Pubkey, transactions: &[&TimestampedTransaction]) -> Vec<bool> {
+            self.batch_calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            transactions.iter().map(|tx| tx.verify_signature()).collect()
+        }
+    }
+
+    struct ApprovingClient {
+        approvers: HashSet<Pubkey>,
+    }
+
+    #[async_trait]
+    impl ValidatorClient for ApprovingClient {
+        async fn verify_transaction(&self, validator: &Pubkey, _transaction: &TimestampedTransaction) -> bool {
+            self.approvers.contains(validator)
+        }
+    }
+
+    fn voting_manager(validators: &[Validator], approvers: impl IntoIterator<Item = Pubkey>) -> ConsensusManager {
+        let mut manager = ConsensusManager::new(Duration::from_secs(5))
+            .with_validator_client(Arc::new(ApprovingClient { approvers: approvers.into_iter().collect() }));
+        for validator in validators {
+            manager.add_validator(validator.clone());
+        }
+        manager
+    }
+
+    #[tokio::test]
+    async fn test_single_whale_decides_the_vote() {
+        let whale = validator(7_000_000, i64::MAX);
+        let mut validators: Vec<Validator> = (0..3).map(|_| validator(1_000_000, i64::MAX)).collect();
+        validators.push(whale.clone());
+        let transaction = signed_transfer();
+
+        let manager = voting_manager(&validators, [whale.pubkey]);
+        assert_eq!(manager.validate_transaction(&transaction).await, ConsensusResult::Accepted);
+
+        let everyone_else = validators.iter().filter(|v| v.pubkey != whale.pubkey).map(|v| v.pubkey);
+        let manager = voting_manager(&validators, everyone_else);
+        assert_eq!(
+            manager.validate_transaction(&transaction).await,
+            ConsensusResult::InsufficientConfirmations { confirmed_stake: 3_000_000, required_stake: 6_666_667 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_many_tiny_validators_need_two_thirds_of_stake() {
+        let validators: Vec<Validator> = (0..99).map(|_| validator(1, i64::MAX)).collect();
+        let transaction = signed_transfer();
+
+        let manager = voting_manager(&validators, validators[..66].iter().map(|v| v.pubkey));
+        assert_eq!(
+            manager.validate_transaction(&transaction).await,
+            ConsensusResult::InsufficientConfirmations { confirmed_stake: 66, required_stake: 67 }
+        );
+
+        let manager = voting_manager(&validators, validators[..67].iter().map(|v| v.pubkey));
+        assert_eq!(manager.validate_batch(&[transaction]).await, vec![ConsensusResult::Accepted]);
+    }
+
+    #[test]
+    fn test_unstaked_validators_vote_one_each() {
+        let validators: Vec<Validator> = (0..3).map(|_| validator(0, 0)).collect();
+        assert_eq!(vote_weights(&validators), vec![1, 1, 1]);
+        assert_eq!(supermajority(3), 3);
+        assert_eq!(supermajority(0), 1);
+    }
+
+    #[tokio::test]
+    async fn test_validate_batch_aligns_results_and_amortizes_queries() {
+        let client = Arc::new(CountingClient {
+            batch_calls: std::sync::atomic::AtomicUsize::new(0),
+        });
+        let mut manager = ConsensusManager::new(Duration::from_secs(5))
+            .with_validator_client(client.clone());
+        for _ in 0..3 {
+            manager.add_validator(validator(100, i64::MAX));
+        }
+
+        let valid = signed_transfer();
+        let mut forged = signed_transfer();
+        forged.transaction.signatures[0] = Signature::default();
+        let batch = vec![valid.clone(), forged, signed_transfer(), valid];
+
+        let results = manager.validate_batch(&batch).await;
+
+        assert_eq!(results, vec![
+            ConsensusResult::Accepted,
+            ConsensusResult::InvalidSignature,
+            ConsensusResult::Accepted,
+            ConsensusResult::Accepted,
+        ]);
+
+        let naive_calls = 3 * 3;
+        let batch_calls = client.batch_calls.load(std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(batch_calls, 3);
+        assert!(batch_calls < naive_calls);
     }
 }
@@ -0,0 +1,448 @@
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::node::config::ControlApiConfig;
+use crate::node::network::{Node, NodeStats};
+use crate::node::peer::PeerSnapshot;
+use crate::node::reputation::BanEntry;
+
+pub(crate) const JSONRPC_VERSION: &str = "2.0";
+pub(crate) const PARSE_ERROR: i64 = -32700;
+pub(crate) const INVALID_REQUEST: i64 = -32600;
+pub(crate) const METHOD_NOT_FOUND: i64 = -32601;
+pub(crate) const INVALID_PARAMS: i64 = -32602;
+pub(crate) const INTERNAL_ERROR: i64 = -32603;
+
+/// Most entries a listing returns, and what it returns without a
+/// `params.limit`.
+pub(crate) const MAX_LIST_LIMIT: usize = 1000;
+
+/// The node operations reachable over the control API.
+pub trait ControlTarget: Send + Sync {
+    fn peers(&self) -> Vec<PeerSnapshot>;
+    fn stats(&self) -> NodeStats;
+    fn disconnect(&self, addr: SocketAddr, reason: &str) -> bool;
+    fn bans(&self) -> Vec<BanEntry>;
+    fn unban(&self, ip: IpAddr) -> bool;
+    fn pause(&self);
+    fn resume(&self);
+    fn is_paused(&self) -> bool;
+}
+
+impl ControlTarget for Node {
+    fn peers(&self) -> Vec<PeerSnapshot> {
+        Node::peers(self)
+    }
+
+    fn stats(&self) -> NodeStats {
+        Node::stats(self)
+    }
+
+    fn disconnect(&self, addr: SocketAddr, reason: &str) -> bool {
+        Node::disconnect(self, addr, reason)
+    }
+
+    fn bans(&self) -> Vec<BanEntry> {
+        Node::bans(self)
+    }
+
+    fn unban(&self, ip: IpAddr) -> bool {
+        Node::unban(self, ip)
+    }
+
+    fn pause(&self) {
+        Node::pause(self)
+    }
+
+    fn resume(&self) {
+        Node::resume(self)
+    }
+
+    fn is_paused(&self) -> bool {
+        Node::is_paused(self)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RpcRequest {
+    #[serde(default)]
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    #[serde(default)]
+    pub id: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcResponse {
+    pub(crate) fn ok(id: Value, result: Value) -> Self {
+        RpcResponse {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub(crate) fn err(id: Value, code: i64, message: impl Into<String>) -> Self {
+        RpcResponse {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result: None,
+            error: Some(RpcError {
+                code,
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ControlState {
+    target: Arc<dyn ControlTarget>,
+    auth_token: Arc<str>,
+}
+
+pub fn router(target: Arc<dyn ControlTarget>, auth_token: &str) -> Router {
+    Router::new()
+        .route("/", post(handle_rpc))
+        .with_state(ControlState {
+            target,
+            auth_token: Arc::from(auth_token),
+        })
+}
+
+pub async fn serve(target: Arc<dyn ControlTarget>, config: &ControlApiConfig) -> std::io::Result<()> {
+    let listener = TcpListener::bind((config.host.as_str(), config.port)).await?;
+    info!("Control API listening on {}", listener.local_addr()?);
+    axum::serve(listener, router(target, &config.auth_token)).await
+}
+
+fn authorized(headers: &HeaderMap, expected: &str) -> bool {
+    let presented = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    // Compare every byte so response timing doesn't reveal the matching prefix.
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn handle_rpc(
+    State(state): State<ControlState>,
+    headers: HeaderMap,
+    Json(request): Json<RpcRequest>,
+) -> Result<Json<RpcResponse>, StatusCode> {
+    if !authorized(&headers, &state.auth_token) {
+        warn!("Rejected control API call to '{}' with a missing or invalid token", request.method);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(Json(dispatch(state.target.as_ref(), request)))
+}
+
+/// `params.limit` capped at `MAX_LIST_LIMIT`, or `None` if it is given but
+/// isn't a positive integer.
+pub(crate) fn list_limit(params: &Value) -> Option<usize> {
+    match params.get("limit") {
+        None | Some(Value::Null) => Some(MAX_LIST_LIMIT),
+        Some(limit) => limit
+            .as_u64()
+            .filter(|limit| *limit > 0)
+            .map(|limit| (limit as usize).min(MAX_LIST_LIMIT)),
+    }
+}
+
+fn dispatch(target: &dyn ControlTarget, request: RpcRequest) -> RpcResponse {
+    let id = request.id;
+    if request.jsonrpc != JSONRPC_VERSION {
+        return RpcResponse::err(id, INVALID_REQUEST, "jsonrpc must be \"2.0\"");
+    }
+
+    match request.method.as_str() {
+        "get_peers" => match list_limit(&request.params) {
+            Some(limit) => RpcResponse::ok(id, json!(target.peers().into_iter().take(limit).collect::<Vec<_>>())),
+            None => RpcResponse::err(id, INVALID_PARAMS, "expected params.limit as a positive integer"),
+        },
+        "get_stats" => RpcResponse::ok(id, json!(target.stats())),
+        "disconnect_peer" => {
+            let addr = request
+                .params
+                .get("addr")
+                .and_then(Value::as_str)
+                .and_then(|addr| addr.parse::<SocketAddr>().ok());
+            match addr {
+                Some(addr) => {
+                    let disconnected = target.disconnect(addr, "requested over control API");
+                    RpcResponse::ok(id, json!({ "disconnected": disconnected }))
+                }
+                None => RpcResponse::err(id, INVALID_PARAMS, "expected params.addr as \"ip:port\""),
+            }
+        }
+        "get_bans" => RpcResponse::ok(id, json!(target.bans())),
+        "unban_peer" => {
+            let ip = request
+                .params
+                .get("ip")
+                .and_then(Value::as_str)
+                .and_then(|ip| ip.parse::<IpAddr>().ok());
+            match ip {
+                Some(ip) => RpcResponse::ok(id, json!({ "unbanned": target.unban(ip) })),
+                None => RpcResponse::err(id, INVALID_PARAMS, "expected params.ip as an IP address"),
+            }
+        }
+        "pause" => {
+            target.pause();
+            RpcResponse::ok(id, json!({ "paused": target.is_paused() }))
+        }
+        "resume" => {
+            target.resume();
+            RpcResponse::ok(id, json!({ "paused": target.is_paused() }))
+        }
+        other => RpcResponse::err(id, METHOD_NOT_FOUND, format!("unknown method '{}'", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::config::NodeConfig;
+    use crate::node::peer::PeerInfo;
+    use crate::node::reputation::{BanList, PeerEvent};
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use parking_lot::Mutex;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "s3cret-token";
+
+    struct FakeNode {
+        peers: Mutex<HashMap<SocketAddr, PeerInfo>>,
+        bans: Mutex<BanList>,
+        paused: AtomicBool,
+    }
+
+    impl FakeNode {
+        fn with_peers(addrs: &[SocketAddr]) -> Arc<Self> {
+            Arc::new(FakeNode {
+                peers: Mutex::new(addrs.iter().map(|addr| (*addr, PeerInfo::new(*addr))).collect()),
+                bans: Mutex::new(BanList::new()),
+                paused: AtomicBool::new(false),
+            })
+        }
+    }
+
+    impl ControlTarget for FakeNode {
+        fn peers(&self) -> Vec<PeerSnapshot> {
+            self.peers.lock().values().map(PeerInfo::snapshot).collect()
+        }
+
+        fn stats(&self) -> NodeStats {
+            let peers = self.peers.lock();
+            NodeStats {
+                peer_count: peers.len(),
+                connected_peers: peers.len(),
+                avg_rtt_ms: None,
+                min_rtt_ms: None,
+                max_rtt_ms: None,
+                self_connections_rejected: 0,
+                inbound_refused: 0,
+                external_addr: None,
+            }
+        }
+
+        fn disconnect(&self, addr: SocketAddr, _reason: &str) -> bool {
+            self.peers.lock().remove(&addr).is_some()
+        }
+
+        fn bans(&self) -> Vec<BanEntry> {
+            self.bans.lock().active(Instant::now())
+        }
+
+        fn unban(&self, ip: IpAddr) -> bool {
+            self.bans.lock().unban(ip)
+        }
+
+        fn pause(&self) {
+            self.paused.store(true, Ordering::SeqCst);
+        }
+
+        fn resume(&self) {
+            self.paused.store(false, Ordering::SeqCst);
+        }
+
+        fn is_paused(&self) -> bool {
+            self.paused.load(Ordering::SeqCst)
+        }
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    async fn call(app: &Router, token: Option<&str>, method: &str, params: Value) -> (StatusCode, Option<RpcResponse>) {
+        let mut request = Request::post("/").header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).ok())
+    }
+
+    async fn result(app: &Router, method: &str, params: Value) -> Value {
+        let (status, response) = call(app, Some(TOKEN), method, params).await;
+        assert_eq!(status, StatusCode::OK);
+        let response = response.unwrap();
+        assert!(response.error.is_none(), "{:?}", response.error);
+        response.result.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_and_stats() {
+        let node = FakeNode::with_peers(&[addr(8001), addr(8002)]);
+        let app = router(node, TOKEN);
+
+        let peers = result(&app, "get_peers", Value::Null).await;
+        let mut listed: Vec<String> = peers
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|peer| peer["addr"].as_str().unwrap().to_string())
+            .collect();
+        listed.sort();
+        assert_eq!(listed, vec!["10.0.0.1:8001", "10.0.0.1:8002"]);
+
+        let stats = result(&app, "get_stats", Value::Null).await;
+        assert_eq!(stats["peer_count"], 2);
+
+        let limited = result(&app, "get_peers", json!({ "limit": 1 })).await;
+        assert_eq!(limited.as_array().unwrap().len(), 1);
+        let (_, response) = call(&app, Some(TOKEN), "get_peers", json!({ "limit": 0 })).await;
+        assert_eq!(response.unwrap().error.unwrap().code, INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn test_real_node_served() {
+        let node = Arc::new(Node::new(NodeConfig::default()).await.unwrap());
+        let app = router(node.clone(), TOKEN);
+
+        assert_eq!(result(&app, "get_stats", Value::Null).await["peer_count"], 0);
+        assert_eq!(result(&app, "get_peers", json!({ "limit": 10 })).await, json!([]));
+        assert_eq!(result(&app, "get_bans", Value::Null).await, json!([]));
+        assert_eq!(result(&app, "pause", Value::Null).await["paused"], true);
+        assert!(node.is_paused());
+        assert_eq!(result(&app, "resume", Value::Null).await["paused"], false);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_peer_removes_it() {
+        let node = FakeNode::with_peers(&[addr(8001), addr(8002)]);
+        let app = router(node.clone(), TOKEN);
+
+        let outcome = result(&app, "disconnect_peer", json!({ "addr": "10.0.0.1:8001" })).await;
+        assert_eq!(outcome["disconnected"], true);
+        assert!(!node.peers.lock().contains_key(&addr(8001)));
+
+        let outcome = result(&app, "disconnect_peer", json!({ "addr": "10.0.0.1:8001" })).await;
+        assert_eq!(outcome["disconnected"], false);
+
+        let (_, response) = call(&app, Some(TOKEN), "disconnect_peer", json!({ "addr": "nope" })).await;
+        assert_eq!(response.unwrap().error.unwrap().code, INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume_toggle_node() {
+        let node = FakeNode::with_peers(&[]);
+        let app = router(node.clone(), TOKEN);
+
+        assert_eq!(result(&app, "pause", Value::Null).await["paused"], true);
+        assert!(node.is_paused());
+
+        assert_eq!(result(&app, "resume", Value::Null).await["paused"], false);
+        assert!(!node.is_paused());
+    }
+
+    #[tokio::test]
+    async fn test_calls_without_valid_token_are_rejected() {
+        let node = FakeNode::with_peers(&[addr(8001)]);
+        let app = router(node.clone(), TOKEN);
+
+        let (status, _) = call(&app, None, "pause", Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = call(&app, Some("s3cret-tokeN"), "disconnect_peer", json!({ "addr": "10.0.0.1:8001" })).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        assert!(!node.is_paused());
+        assert!(node.peers.lock().contains_key(&addr(8001)));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_method_reports_error() {
+        let app = router(FakeNode::with_peers(&[]), TOKEN);
+        let (status, response) = call(&app, Some(TOKEN), "format_disk", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.unwrap().error.unwrap().code, METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_scores_and_bans_exposed() {
+        let node = FakeNode::with_peers(&[addr(8001)]);
+        node.peers.lock().get_mut(&addr(8001)).unwrap().record_event(PeerEvent::Timeout);
+        node.bans.lock().ban("10.0.0.9".parse().unwrap(), Duration::from_secs(600), Instant::now());
+        let app = router(node.clone(), TOKEN);
+
+        let peers = result(&app, "get_peers", Value::Null).await;
+        assert_eq!(peers[0]["score"], -5);
+
+        let bans = result(&app, "get_bans", Value::Null).await;
+        assert_eq!(bans[0]["ip"], "10.0.0.9");
+
+        assert_eq!(result(&app, "unban_peer", json!({ "ip": "10.0.0.9" })).await["unbanned"], true);
+        assert_eq!(result(&app, "get_bans", Value::Null).await, json!([]));
+        let (_, response) = call(&app, Some(TOKEN), "unban_peer", json!({ "ip": "10.0.0" })).await;
+        assert_eq!(response.unwrap().error.unwrap().code, INVALID_PARAMS);
+    }
+}
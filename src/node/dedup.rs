@@ -0,0 +1,134 @@
+use solana_sdk::hash::{hash, Hash};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::node::message::Message;
+
+pub const DEFAULT_DEDUP_CAPACITY: usize = 50_000;
+pub const DEFAULT_DEDUP_TTL: Duration = Duration::from_secs(120);
+
+/// Content hash used to recognise the same gossip arriving from several peers.
+/// Point-to-point messages (pings, acks, handshakes) have none.
+pub fn gossip_digest(message: &Message) -> Option<Hash> {
+    match message {
+        Message::NewTransaction { .. }
+        | Message::Block { .. }
+        | Message::ViewChange { .. }
+        | Message::BlockVote { .. }
+        | Message::ValidatorAnnounce { .. } => {
+            bincode::serialize(message).ok().map(|bytes| hash(&bytes))
+        }
+        _ => None,
+    }
+}
+
+/// Exact recently-seen set bounded by both age and size. Unlike the bloom
+/// filter used for announcements, it never drops a message it hasn't seen.
+#[derive(Debug)]
+pub struct RecentMessages {
+    seen: HashMap<Hash, Instant>,
+    order: VecDeque<(Hash, Instant)>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl RecentMessages {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        RecentMessages {
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            capacity: capacity.max(1),
+            ttl,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    pub fn contains(&self, digest: &Hash, now: Instant) -> bool {
+        self.seen
+            .get(digest)
+            .map_or(false, |seen_at| now.saturating_duration_since(*seen_at) < self.ttl)
+    }
+
+    /// Records `digest` and returns `true` if it was not seen within the TTL.
+    pub fn first_seen(&mut self, digest: Hash, now: Instant) -> bool {
+        self.expire(now);
+        if self.seen.contains_key(&digest) {
+            return false;
+        }
+
+        if self.seen.len() >= self.capacity {
+            if let Some((oldest, _)) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(digest, now);
+        self.order.push_back((digest, now));
+        true
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((digest, seen_at)) = self.order.front().copied() {
+            if now.saturating_duration_since(seen_at) < self.ttl {
+                break;
+            }
+            self.order.pop_front();
+            self.seen.remove(&digest);
+        }
+    }
+}
+
+impl Default for RecentMessages {
+    fn default() -> Self {
+        RecentMessages::new(DEFAULT_DEDUP_CAPACITY, DEFAULT_DEDUP_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(n: u8) -> Hash {
+        hash(&[n])
+    }
+
+    #[test]
+    fn test_duplicate_suppressed_until_ttl() {
+        let mut recent = RecentMessages::new(16, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(recent.first_seen(digest(1), now));
+        assert!(!recent.first_seen(digest(1), now + Duration::from_secs(5)));
+        assert!(recent.first_seen(digest(1), now + Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let mut recent = RecentMessages::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        for n in 0..3 {
+            assert!(recent.first_seen(digest(n), now));
+        }
+
+        assert_eq!(recent.len(), 2);
+        assert!(recent.first_seen(digest(0), now));
+        assert!(!recent.first_seen(digest(2), now));
+    }
+
+    #[test]
+    fn test_only_gossip_has_a_digest() {
+        let block = Message::Block { slot: 7, payload: vec![1, 2, 3] };
+        assert_eq!(gossip_digest(&block), gossip_digest(&block.clone()));
+        assert_ne!(
+            gossip_digest(&block),
+            gossip_digest(&Message::Block { slot: 8, payload: vec![1, 2, 3] })
+        );
+        assert!(gossip_digest(&Message::Ping { nonce: 1 }).is_none());
+    }
+}
@@ -0,0 +1,174 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Delays between attempts to reach an outbound peer: doubling from `base`
+/// up to `max`, each spread by up to `jitter` either way so nodes restarted
+/// together don't redial in lockstep.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackoffPolicy {
+    pub base: Duration,
+    pub max: Duration,
+    pub jitter: f64,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        BackoffPolicy {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(300),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// Wait before the next attempt after `failures` consecutive failures.
+    pub fn delay(&self, failures: u32, rng: &mut impl Rng) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31);
+        let delay = self.base.saturating_mul(1 << doublings).min(self.max);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 + rng.gen_range(-jitter..=jitter)).min(self.max)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DialState {
+    Waiting { failures: u32, next_attempt: Instant },
+    Dialing { failures: u32 },
+    Connected,
+    /// The target turned out to be this node; never dialed again.
+    Abandoned,
+}
+
+/// Outbound targets the node keeps trying to reach, such as the bootstrap
+/// list. Failed dials back off without limit instead of giving up, and a
+/// target whose connection drops is dialed again straight away.
+#[derive(Debug)]
+pub struct Dialer {
+    policy: BackoffPolicy,
+    targets: HashMap<String, DialState>,
+}
+
+impl Dialer {
+    pub fn new(policy: BackoffPolicy) -> Self {
+        Dialer { policy, targets: HashMap::new() }
+    }
+
+    /// Tracks exactly `targets`: new ones are due immediately, known ones
+    /// keep their backoff and the rest are forgotten.
+    pub fn set_targets(&mut self, targets: &[String], now: Instant) {
+        self.targets.retain(|target, _| targets.contains(target));
+        for target in targets {
+            self.targets
+                .entry(target.clone())
+                .or_insert(DialState::Waiting { failures: 0, next_attempt: now });
+        }
+    }
+
+    /// Targets whose backoff has elapsed, marked as being dialed so the next
+    /// call doesn't hand them out again before the attempt finishes.
+    pub fn due(&mut self, now: Instant) -> Vec<String> {
+        let mut due = Vec::new();
+        for (target, state) in self.targets.iter_mut() {
+            if let DialState::Waiting { failures, next_attempt } = *state {
+                if next_attempt <= now {
+                    *state = DialState::Dialing { failures };
+                    due.push(target.clone());
+                }
+            }
+        }
+        due.sort();
+        due
+    }
+
+    pub fn record_success(&mut self, target: &str) {
+        if let Some(state) = self.targets.get_mut(target) {
+            *state = DialState::Connected;
+        }
+    }
+
+    /// Schedules the next attempt and returns how long it is away.
+    pub fn record_failure(&mut self, target: &str, now: Instant) -> Option<Duration> {
+        let state = self.targets.get_mut(target)?;
+        let failures = match *state {
+            DialState::Waiting { failures, .. } | DialState::Dialing { failures } => failures + 1,
+            DialState::Connected => 1,
+            DialState::Abandoned => return None,
+        };
+        let delay = self.policy.delay(failures, &mut rand::thread_rng());
+        *state = DialState::Waiting { failures, next_attempt: now + delay };
+        Some(delay)
+    }
+
+    /// A connected target dropped; it is due again at once.
+    pub fn record_disconnect(&mut self, target: &str, now: Instant) {
+        if let Some(state) = self.targets.get_mut(target) {
+            if *state == DialState::Connected {
+                *state = DialState::Waiting { failures: 0, next_attempt: now };
+            }
+        }
+    }
+
+    pub fn abandon(&mut self, target: &str) {
+        if let Some(state) = self.targets.get_mut(target) {
+            *state = DialState::Abandoned;
+        }
+    }
+
+    pub fn is_connected(&self, target: &str) -> bool {
+        self.targets.get(target) == Some(&DialState::Connected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn policy(jitter: f64) -> BackoffPolicy {
+        BackoffPolicy { base: Duration::from_secs(1), max: Duration::from_secs(60), jitter }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap_within_jitter() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let exact = policy(0.0);
+        let delays: Vec<u64> = (1..=8).map(|n| exact.delay(n, &mut rng).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(exact.delay(u32::MAX, &mut rng), Duration::from_secs(60));
+
+        let jittered = policy(0.25);
+        for _ in 0..100 {
+            let delay = jittered.delay(3, &mut rng).as_secs_f64();
+            assert!((3.0..=5.0).contains(&delay));
+        }
+    }
+
+    #[test]
+    fn test_failed_targets_retry_after_backoff_and_redial_on_disconnect() {
+        let mut dialer = Dialer::new(policy(0.0));
+        let start = Instant::now();
+        let targets = vec!["a:8000".to_string(), "b:8000".to_string()];
+        dialer.set_targets(&targets, start);
+
+        assert_eq!(dialer.due(start), targets);
+        assert!(dialer.due(start).is_empty());
+
+        dialer.record_success("a:8000");
+        assert_eq!(dialer.record_failure("b:8000", start), Some(Duration::from_secs(1)));
+        assert!(dialer.due(start).is_empty());
+        assert_eq!(dialer.due(start + Duration::from_secs(1)), vec!["b:8000".to_string()]);
+        assert_eq!(dialer.record_failure("b:8000", start), Some(Duration::from_secs(2)));
+
+        dialer.record_disconnect("a:8000", start);
+        assert!(!dialer.is_connected("a:8000"));
+        assert_eq!(dialer.due(start), vec!["a:8000".to_string()]);
+
+        dialer.abandon("a:8000");
+        assert!(dialer.record_failure("a:8000", start).is_none());
+        dialer.set_targets(&["b:8000".to_string()], start);
+        assert_eq!(dialer.due(start + Duration::from_secs(600)), vec!["b:8000".to_string()]);
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Tracks in-flight work (connection handlers, consensus rounds, generation
+/// requests) so the node can stop taking new work and wait for the rest.
+#[derive(Debug)]
+pub struct InFlight {
+    accepting: AtomicBool,
+    count: watch::Sender<usize>,
+}
+
+/// Held for the lifetime of one unit of work; dropping it marks the work done.
+#[derive(Debug)]
+pub struct WorkGuard {
+    tracker: Arc<InFlight>,
+}
+
+impl InFlight {
+    pub fn new() -> Arc<Self> {
+        let (count, _) = watch::channel(0);
+        Arc::new(InFlight {
+            accepting: AtomicBool::new(true),
+            count,
+        })
+    }
+
+    /// Registers new work, or returns `None` once the node is draining.
+    pub fn try_begin(self: &Arc<Self>) -> Option<WorkGuard> {
+        if !self.is_accepting() {
+            return None;
+        }
+        self.count.send_modify(|count| *count += 1);
+        Some(WorkGuard {
+            tracker: Arc::clone(self),
+        })
+    }
+
+    pub fn stop_accepting(&self) {
+        self.accepting.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::SeqCst)
+    }
+
+    pub fn count(&self) -> usize {
+        *self.count.borrow()
+    }
+
+    pub async fn wait_idle(&self) {
+        let mut rx = self.count.subscribe();
+        let _ = rx.wait_for(|count| *count == 0).await;
+    }
+}
+
+impl Drop for WorkGuard {
+    fn drop(&mut self) {
+        self.tracker.count.send_modify(|count| *count -= 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_wait_idle_returns_after_last_guard_drops() {
+        let tracker = InFlight::new();
+        let first = tracker.try_begin().unwrap();
+        let second = tracker.try_begin().unwrap();
+        tracker.stop_accepting();
+
+        assert!(tracker.try_begin().is_none());
+        assert_eq!(tracker.count(), 2);
+
+        drop(first);
+        let waiter = tokio::time::timeout(Duration::from_millis(50), tracker.wait_idle()).await;
+        assert!(waiter.is_err());
+
+        drop(second);
+        tracker.wait_idle().await;
+        assert_eq!(tracker.count(), 0);
+    }
+}
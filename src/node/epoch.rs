@@ -0,0 +1,223 @@
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+
+use crate::node::config::EpochLength;
+
+/// What a validator did for the network during one epoch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Participation {
+    pub blocks_proposed: u64,
+    pub votes_cast: u64,
+    pub inference_jobs: u64,
+}
+
+impl Participation {
+    pub fn score(&self, weights: &RewardWeights) -> u128 {
+        self.blocks_proposed as u128 * weights.blocks as u128
+            + self.votes_cast as u128 * weights.votes as u128
+            + self.inference_jobs as u128 * weights.inference as u128
+    }
+}
+
+/// How much each kind of participation counts towards a validator's share
+/// of an epoch's rewards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewardWeights {
+    pub blocks: u64,
+    pub votes: u64,
+    pub inference: u64,
+}
+
+/// The tally of an epoch that has ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpochSummary {
+    pub epoch: u64,
+    pub participation: BTreeMap<Pubkey, Participation>,
+}
+
+/// Follows the chain across epoch boundaries, tallying participation for the
+/// current epoch and handing over each one as it ends.
+///
+/// The epoch the tracker first observes is usually joined partway, so its
+/// tally is dropped instead of paid out short. Restarting a distributing
+/// node therefore never pays an epoch twice.
+#[derive(Debug)]
+pub struct EpochTracker {
+    length: EpochLength,
+    current: Option<u64>,
+    /// Whether the tracker saw `current` from its first block.
+    complete: bool,
+    tally: BTreeMap<Pubkey, Participation>,
+    ended: Vec<EpochSummary>,
+}
+
+impl EpochTracker {
+    pub fn new(length: EpochLength) -> Self {
+        EpochTracker {
+            length,
+            current: None,
+            complete: false,
+            tally: BTreeMap::new(),
+            ended: Vec::new(),
+        }
+    }
+
+    /// The epoch holding the block at `height`, or the moment `unix_secs`
+    /// for wall-clock epochs.
+    pub fn epoch_at(&self, height: u64, unix_secs: i64) -> u64 {
+        match self.length {
+            EpochLength::Blocks(blocks) => height / blocks.max(1),
+            EpochLength::Seconds(secs) => unix_secs.max(0) as u64 / secs.max(1),
+        }
+    }
+
+    /// Moves to the epoch of `height` and `unix_secs`, closing the current
+    /// one if that is later. Observations from an earlier epoch are ignored.
+    pub fn observe(&mut self, height: u64, unix_secs: i64) {
+        let epoch = self.epoch_at(height, unix_secs);
+        let Some(current) = self.current else {
+            self.current = Some(epoch);
+            self.complete = self.starts_epoch(height);
+            return;
+        };
+        if epoch <= current {
+            return;
+        }
+        let participation = std::mem::take(&mut self.tally);
+        if self.complete {
+            self.ended.push(EpochSummary { epoch: current, participation });
+        }
+        self.current = Some(epoch);
+        // The clock is watched without gaps, so only the block count can
+        // have jumped past the start of the new epoch.
+        self.complete = epoch == current + 1
+            && (matches!(self.length, EpochLength::Seconds(_)) || self.starts_epoch(height));
+    }
+
+    /// Whether `height` is the first block of its epoch. Height 0 is genesis,
+    /// so epoch 0 starts at block 1. A wall-clock epoch is never known to
+    /// have been joined at its start.
+    fn starts_epoch(&self, height: u64) -> bool {
+        match self.length {
+            EpochLength::Blocks(blocks) => {
+                let blocks = blocks.max(1);
+                height <= (height / blocks * blocks).max(1)
+            }
+            EpochLength::Seconds(_) => false,
+        }
+    }
+
+    pub fn record_block(&mut self, proposer: Pubkey) {
+        self.tally.entry(proposer).or_default().blocks_proposed += 1;
+    }
+
+    pub fn record_vote(&mut self, validator: Pubkey) {
+        self.tally.entry(validator).or_default().votes_cast += 1;
+    }
+
+    pub fn record_inference(&mut self, executor: Pubkey) {
+        self.tally.entry(executor).or_default().inference_jobs += 1;
+    }
+
+    pub fn current(&self) -> Option<u64> {
+        self.current
+    }
+
+    /// Hands over the epochs that ended since the last call, oldest first.
+    pub fn take_ended(&mut self) -> Vec<EpochSummary> {
+        std::mem::take(&mut self.ended)
+    }
+}
+
+/// Splits `pool` lamports between validators in proportion to their weighted
+/// participation. Shares round down, so a little of the pool may be left
+/// over; validators whose share rounds to nothing are left out.
+pub fn compute_rewards(
+    participation: &BTreeMap<Pubkey, Participation>,
+    weights: &RewardWeights,
+    pool: u64,
+) -> Vec<(Pubkey, u64)> {
+    let total: u128 = participation.values().map(|p| p.score(weights)).sum();
+    if total == 0 {
+        return Vec::new();
+    }
+    participation
+        .iter()
+        .map(|(validator, p)| (*validator, (pool as u128 * p.score(weights) / total) as u64))
+        .filter(|(_, share)| *share > 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const WEIGHTS: RewardWeights = RewardWeights { blocks: 10, votes: 1, inference: 5 };
+
+    #[test]
+    fn test_epochs_close_on_boundaries_and_skip_the_one_joined_late() {
+        let validator = Pubkey::new_unique();
+        let mut tracker = EpochTracker::new(EpochLength::Blocks(10));
+
+        // Joined at height 5: epoch 0 is only partly seen.
+        tracker.observe(5, 0);
+        tracker.record_block(validator);
+        tracker.observe(10, 0);
+        assert_eq!(tracker.current(), Some(1));
+        assert!(tracker.take_ended().is_empty());
+
+        tracker.record_block(validator);
+        tracker.record_vote(validator);
+        tracker.observe(19, 0);
+        tracker.record_inference(validator);
+        tracker.observe(4, 0);
+        tracker.observe(20, 0);
+        let ended = tracker.take_ended();
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].epoch, 1);
+        assert_eq!(
+            ended[0].participation[&validator],
+            Participation { blocks_proposed: 1, votes_cast: 1, inference_jobs: 1 }
+        );
+
+        // Skipping past epoch 3 means epoch 4 was joined partway too.
+        tracker.observe(45, 0);
+        tracker.observe(50, 0);
+        assert_eq!(tracker.take_ended().iter().map(|s| s.epoch).collect::<Vec<_>>(), vec![2]);
+
+        let mut from_genesis = EpochTracker::new(EpochLength::Blocks(10));
+        from_genesis.observe(1, 0);
+        from_genesis.observe(10, 0);
+        assert_eq!(from_genesis.take_ended().len(), 1);
+
+        let mut wall_clock = EpochTracker::new(EpochLength::Seconds(60));
+        wall_clock.observe(7, 1_700_000_030);
+        assert_eq!(wall_clock.current(), Some(1_700_000_030 / 60));
+        wall_clock.observe(7, 1_700_000_070);
+        wall_clock.record_vote(validator);
+        wall_clock.observe(8, 1_700_000_130);
+        let ended = wall_clock.take_ended();
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].epoch, 1_700_000_070 / 60);
+        assert_eq!(ended[0].participation[&validator].votes_cast, 1);
+    }
+
+    #[test]
+    fn test_rewards_split_by_weighted_participation() {
+        let (proposer, voter, idle) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let participation = BTreeMap::from([
+            (proposer, Participation { blocks_proposed: 2, votes_cast: 0, inference_jobs: 2 }),
+            (voter, Participation { blocks_proposed: 0, votes_cast: 10, inference_jobs: 0 }),
+            (idle, Participation::default()),
+        ]);
+
+        // Scores of 30 and 10; the odd lamport rounds away.
+        let rewards: HashMap<Pubkey, u64> = compute_rewards(&participation, &WEIGHTS, 4_001).into_iter().collect();
+        assert_eq!(rewards, HashMap::from([(proposer, 3_000), (voter, 1_000)]));
+
+        let shares = compute_rewards(&participation, &WEIGHTS, u64::MAX);
+        assert_eq!(shares.iter().map(|(_, share)| *share as u128).sum::<u128>(), u64::MAX as u128 - 1);
+        assert!(compute_rewards(&BTreeMap::from([(idle, Participation::default())]), &WEIGHTS, 100).is_empty());
+    }
+}
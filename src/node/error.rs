@@ -0,0 +1,112 @@
+use solana_client::client_error::ClientError;
+use solana_sdk::pubkey::ParsePubkeyError;
+use std::net::SocketAddr;
+use thiserror::Error;
+
+use crate::node::auth::AuthError;
+use crate::node::config::ConfigError;
+use crate::node::consensus::ChainError;
+use crate::node::handshake::HandshakeError;
+use crate::node::identity::IdentityError;
+use crate::node::noise::NoiseError;
+use crate::node::snapshot::SnapshotError;
+use crate::node::stake_check::StakeCheckError;
+use crate::node::storage::StorageError;
+use crate::node::submit::SubmitError;
+
+/// Why a connection to a peer could not be set up.
+#[derive(Error, Debug)]
+pub enum NetworkError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Connect timed out")]
+    ConnectTimeout,
+    #[error("Refusing banned peer {0}")]
+    Banned(SocketAddr),
+    #[error("At peer capacity, refusing lower-quality peer {0}")]
+    AtCapacity(SocketAddr),
+    #[error(transparent)]
+    Handshake(#[from] HandshakeError),
+    #[error(transparent)]
+    Noise(#[from] NoiseError),
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+}
+
+impl NetworkError {
+    /// The dialed address turned out to be this node.
+    pub fn is_self_connection(&self) -> bool {
+        matches!(self, NetworkError::Handshake(HandshakeError::SelfConnection(_)))
+    }
+}
+
+/// Failures of the node itself: starting up, talking to the Solana cluster
+/// and maintaining the local chain.
+#[derive(Error, Debug)]
+pub enum NodeError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Identity(#[from] IdentityError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error("No storage attached")]
+    NoStorage,
+    #[error(transparent)]
+    Network(#[from] NetworkError),
+    #[error("RPC error: {0}")]
+    Rpc(String),
+    #[error("RPC client has been closed")]
+    RpcClosed,
+    #[error("Invalid pubkey: {0}")]
+    Pubkey(#[from] ParsePubkeyError),
+    #[error(transparent)]
+    Stake(#[from] StakeCheckError),
+    #[error("Wallet balance of {balance} lamports is below the minimum stake of {min_stake}")]
+    InsufficientStake { balance: u64, min_stake: u64 },
+    #[error(transparent)]
+    Submit(#[from] SubmitError),
+    #[error(transparent)]
+    Chain(#[from] ChainError),
+    #[error("State sync failed: {0}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("Encoding error: {0}")]
+    Encoding(#[from] bincode::Error),
+}
+
+impl From<ClientError> for NodeError {
+    fn from(e: ClientError) -> Self {
+        NodeError::Rpc(e.to_string())
+    }
+}
+
+impl From<NoiseError> for NodeError {
+    fn from(e: NoiseError) -> Self {
+        NodeError::Network(e.into())
+    }
+}
+
+impl From<std::io::Error> for NodeError {
+    fn from(e: std::io::Error) -> Self {
+        NodeError::Network(e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_keep_their_kind_through_conversion() {
+        let refused: NodeError = std::io::Error::from(std::io::ErrorKind::AddrInUse).into();
+        assert!(matches!(refused, NodeError::Network(NetworkError::Io(_))));
+
+        let err: NetworkError = HandshakeError::SelfConnection("node-a".to_string()).into();
+        assert!(err.is_self_connection());
+        assert_eq!(err.to_string(), "Peer presented our own node ID node-a, this is a self-connection");
+        assert!(!NetworkError::ConnectTimeout.is_self_connection());
+
+        let err: NodeError = ChainError::BrokenLinkage(7).into();
+        assert!(matches!(err, NodeError::Chain(ChainError::BrokenLinkage(7))));
+    }
+}
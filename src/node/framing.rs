@@ -0,0 +1,156 @@
+use log::debug;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::node::message::Message;
+
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+#[derive(Error, Debug)]
+pub enum FrameError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Frame of {size} bytes exceeds limit of {limit} bytes")]
+    TooLarge { size: usize, limit: usize },
+    #[error("Malformed message: {0}")]
+    Decode(#[from] bincode::Error),
+}
+
+async fn read_full<R>(reader: &mut R, buf: &mut [u8]) -> Result<usize, FrameError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Reads one length-prefixed frame, returning `None` once the peer has disconnected.
+pub async fn read_frame<R>(reader: &mut R, max_size: usize) -> Result<Option<Vec<u8>>, FrameError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; LENGTH_PREFIX_SIZE];
+    let read = read_full(reader, &mut header).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read < LENGTH_PREFIX_SIZE {
+        debug!("Peer disconnected after {} bytes of a frame header", read);
+        return Ok(None);
+    }
+
+    let size = u32::from_be_bytes(header) as usize;
+    if size > max_size {
+        return Err(FrameError::TooLarge { size, limit: max_size });
+    }
+
+    let mut payload = vec![0u8; size];
+    let read = read_full(reader, &mut payload).await?;
+    if read < size {
+        debug!("Peer disconnected after {} of {} frame bytes", read, size);
+        return Ok(None);
+    }
+
+    Ok(Some(payload))
+}
+
+pub async fn write_frame<W>(writer: &mut W, payload: &[u8]) -> Result<(), FrameError>
+where
+    W: AsyncWrite + Unpin,
+{
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(FrameError::TooLarge {
+            size: payload.len(),
+            limit: MAX_FRAME_SIZE,
+        });
+    }
+
+    writer.write_all(&(payload.len() as u32).to_be_bytes()).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub async fn read_message<R>(reader: &mut R) -> Result<Option<Message>, FrameError>
+where
+    R: AsyncRead + Unpin,
+{
+    match read_frame(reader, MAX_FRAME_SIZE).await? {
+        Some(payload) => Ok(Some(bincode::deserialize(&payload)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn write_message<W>(writer: &mut W, message: &Message) -> Result<(), FrameError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let payload = bincode::serialize(message)?;
+    write_frame(writer, &payload).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_test::io::Builder;
+
+    fn encoded(message: &Message) -> Vec<u8> {
+        let payload = bincode::serialize(message).unwrap();
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_frame_split_across_reads() {
+        let message = Message::Ping { nonce: 0xDEAD_BEEF };
+        let frame = encoded(&message);
+
+        let mut builder = Builder::new();
+        for chunk in frame.chunks(3) {
+            builder.read(chunk);
+        }
+        let mut reader = builder.build();
+
+        assert_eq!(read_message(&mut reader).await.unwrap(), Some(message));
+        assert_eq!(read_message(&mut reader).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_eof_mid_frame_is_clean_disconnect() {
+        let frame = encoded(&Message::Pong { nonce: 7, timestamp_ms: 1_700_000_000_000 });
+        let mut reader = Builder::new().read(&frame[..frame.len() - 2]).build();
+
+        assert!(read_message(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_rejected() {
+        let mut reader = Builder::new().read(&(1024u32).to_be_bytes()).build();
+
+        assert!(matches!(
+            read_frame(&mut reader, 512).await,
+            Err(FrameError::TooLarge { size: 1024, limit: 512 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_write_then_read_round_trip() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let message = Message::Ack { seq: 42 };
+
+        write_message(&mut client, &message).await.unwrap();
+        drop(client);
+
+        assert_eq!(read_message(&mut server).await.unwrap(), Some(message));
+        assert_eq!(read_message(&mut server).await.unwrap(), None);
+    }
+}
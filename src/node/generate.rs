@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use std::fmt::Debug;
+use thiserror::Error;
+
+use crate::node::framing::FrameError;
+use crate::node::message::GenerateParams;
+
+#[derive(Error, Debug)]
+pub enum GenerateError {
+    #[error("Framing error: {0}")]
+    Frame(#[from] FrameError),
+    #[error("Peer closed the connection before responding")]
+    Closed,
+    #[error("Generation failed on peer: {0}")]
+    Remote(String),
+}
+
+/// Whatever serves generation requests on this node, typically the LLM
+/// batch queue.
+#[async_trait]
+pub trait GenerationService: Send + Sync + Debug {
+    async fn generate(&self, prompt: String, max_tokens: usize, params: GenerateParams) -> Result<String, String>;
+
+    /// Identifies the weights outputs come from; only results from the same
+    /// model version are expected to match.
+    fn model_version(&self) -> String;
+}
+
+#[cfg(feature = "llm")]
+#[async_trait]
+impl GenerationService for crate::llm::BatchQueue {
+    async fn generate(&self, prompt: String, max_tokens: usize, params: GenerateParams) -> Result<String, String> {
+        self.submit(crate::llm::BatchRequest {
+            prompt,
+            max_tokens,
+            sampling: crate::llm::SamplingParams {
+                temperature: params.temperature,
+                top_k: params.top_k,
+                top_p: params.top_p,
+                repetition_penalty: params.repetition_penalty,
+                stop: params.stop,
+                seed: params.seed,
+            },
+            truncation: params.truncation,
+        })
+        .await
+    }
+
+    fn model_version(&self) -> String {
+        crate::llm::BatchQueue::model_version(self).to_string()
+    }
+}
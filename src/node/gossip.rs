@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::node::message::PeerRecord;
+use crate::node::peer::PeerInfo;
+
+pub const DEFAULT_KNOWN_PEERS_CAPACITY: usize = 1024;
+/// Most records sent in one `Peers` reply.
+pub const MAX_SHARED_PEERS: usize = 32;
+/// Records not re-announced by anyone for this long are dropped.
+const KNOWN_PEER_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Connected peers to share with `requester`: those whose node ID and
+/// listening address are known, excluding the requester itself.
+pub fn shareable<'a>(peers: impl IntoIterator<Item = &'a PeerInfo>, requester: SocketAddr) -> Vec<PeerRecord> {
+    peers
+        .into_iter()
+        .filter(|p| p.is_connected() && p.addr != requester)
+        .filter_map(|p| {
+            Some(PeerRecord {
+                node_id: p.node_id.clone()?,
+                addr: p.listen_addr?,
+            })
+        })
+        .take(MAX_SHARED_PEERS)
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct KnownPeer {
+    addr: SocketAddr,
+    last_heard: Instant,
+    failed_dials: u32,
+}
+
+/// Peers learned through gossip, keyed by node ID so the same node reached
+/// through several addresses is only dialed once.
+#[derive(Debug)]
+pub struct KnownPeers {
+    local_node_id: String,
+    peers: HashMap<String, KnownPeer>,
+    capacity: usize,
+}
+
+impl KnownPeers {
+    pub fn new(local_node_id: &str, capacity: usize) -> Self {
+        KnownPeers {
+            local_node_id: local_node_id.to_string(),
+            peers: HashMap::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Adds or refreshes `records`, ignoring this node. Returns how many node
+    /// IDs were new.
+    pub fn merge(&mut self, records: impl IntoIterator<Item = PeerRecord>, now: Instant) -> usize {
+        self.expire(now);
+        let mut learned = 0;
+        for record in records {
+            if record.node_id == self.local_node_id || record.addr.port() == 0 {
+                continue;
+            }
+            if let Some(known) = self.peers.get_mut(&record.node_id) {
+                if known.addr != record.addr {
+                    known.failed_dials = 0;
+                }
+                known.addr = record.addr;
+                known.last_heard = now;
+            } else if self.peers.len() < self.capacity {
+                self.peers.insert(record.node_id, KnownPeer {
+                    addr: record.addr,
+                    last_heard: now,
+                    failed_dials: 0,
+                });
+                learned += 1;
+            }
+        }
+        learned
+    }
+
+    /// Up to `limit` peers worth dialing: not already connected under any
+    /// address, and fewest failed dials first.
+    pub fn candidates(&self, connected: &HashSet<String>, limit: usize) -> Vec<PeerRecord> {
+        let mut candidates: Vec<(&String, &KnownPeer)> = self
+            .peers
+            .iter()
+            .filter(|(node_id, _)| !connected.contains(*node_id))
+            .collect();
+        candidates.sort_by(|a, b| a.1.failed_dials.cmp(&b.1.failed_dials).then_with(|| a.0.cmp(b.0)));
+        candidates
+            .into_iter()
+            .take(limit)
+            .map(|(node_id, known)| PeerRecord {
+                node_id: node_id.clone(),
+                addr: known.addr,
+            })
+            .collect()
+    }
+
+    pub fn record_dial_failure(&mut self, node_id: &str) {
+        if let Some(known) = self.peers.get_mut(node_id) {
+            known.failed_dials = known.failed_dials.saturating_add(1);
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.peers
+            .retain(|_, known| now.saturating_duration_since(known.last_heard) < KNOWN_PEER_TTL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(node_id: &str, port: u16) -> PeerRecord {
+        PeerRecord {
+            node_id: node_id.to_string(),
+            addr: SocketAddr::from(([10, 0, 0, 1], port)),
+        }
+    }
+
+    #[test]
+    fn test_merge_dedupes_by_node_id_and_skips_self() {
+        let mut known = KnownPeers::new("node-local", 16);
+        let now = Instant::now();
+
+        let learned = known.merge(
+            vec![record("node-a", 8001), record("node-a", 9001), record("node-local", 8000), record("node-b", 8002)],
+            now,
+        );
+
+        assert_eq!(learned, 2);
+        assert_eq!(known.len(), 2);
+        let candidates = known.candidates(&HashSet::new(), 10);
+        assert_eq!(candidates, vec![record("node-a", 9001), record("node-b", 8002)]);
+    }
+
+    #[test]
+    fn test_candidates_skip_connected_and_prefer_reachable() {
+        let mut known = KnownPeers::new("node-local", 16);
+        known.merge(vec![record("node-a", 8001), record("node-b", 8002), record("node-c", 8003)], Instant::now());
+        known.record_dial_failure("node-a");
+
+        let connected = HashSet::from(["node-b".to_string()]);
+        assert_eq!(known.candidates(&connected, 10), vec![record("node-c", 8003), record("node-a", 8001)]);
+        assert_eq!(known.candidates(&connected, 1), vec![record("node-c", 8003)]);
+    }
+
+    #[test]
+    fn test_capacity_and_expiry_bound_the_directory() {
+        let mut known = KnownPeers::new("node-local", 2);
+        let now = Instant::now();
+        assert_eq!(known.merge(vec![record("node-a", 8001), record("node-b", 8002), record("node-c", 8003)], now), 2);
+
+        known.merge(Vec::new(), now + KNOWN_PEER_TTL);
+        assert!(known.is_empty());
+    }
+}
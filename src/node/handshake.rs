@@ -0,0 +1,225 @@
+use std::net::SocketAddr;
+use thiserror::Error;
+use tokio::time::{timeout, Duration};
+
+use crate::node::config::TransportKind;
+use crate::node::framing::{read_message, write_message, FrameError};
+use crate::node::message::Message;
+use crate::node::transport::Connection;
+
+pub const PROTOCOL_VERSION: u16 = 3;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum HandshakeError {
+    #[error("Framing error: {0}")]
+    Frame(#[from] FrameError),
+    #[error("Peer closed the connection during handshake")]
+    Closed,
+    #[error("Handshake timed out")]
+    Timeout,
+    #[error("Expected a handshake message, got {0}")]
+    Unexpected(String),
+    #[error("Unsupported protocol version {0}")]
+    Version(u16),
+    #[error("Peer presented our own node ID {0}, this is a self-connection")]
+    SelfConnection(String),
+    #[error("Peer wants the {remote:?} transport, we require {local:?}")]
+    TransportMismatch { local: TransportKind, remote: TransportKind },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeInfo {
+    pub protocol_version: u16,
+    pub node_id: String,
+    pub listen_port: u16,
+    /// Fresh per connection; the peer signs it during authentication.
+    pub challenge: u64,
+    pub transport: TransportKind,
+    /// Inference jobs the node runs at once, zero without a model.
+    pub inference_capacity: u32,
+    /// The other side's address as this node sees it.
+    pub observed_addr: Option<SocketAddr>,
+    /// Where this node accepts connections from outside its NAT.
+    pub external_addr: Option<SocketAddr>,
+}
+
+impl HandshakeInfo {
+    pub fn local(node_id: &str, listen_port: u16) -> Self {
+        HandshakeInfo {
+            protocol_version: PROTOCOL_VERSION,
+            node_id: node_id.to_string(),
+            listen_port,
+            challenge: uuid::Uuid::new_v4().as_u128() as u64,
+            transport: TransportKind::default(),
+            inference_capacity: 0,
+            observed_addr: None,
+            external_addr: None,
+        }
+    }
+
+    pub fn with_transport(mut self, transport: TransportKind) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn with_inference_capacity(mut self, capacity: u32) -> Self {
+        self.inference_capacity = capacity;
+        self
+    }
+
+    pub fn with_observed_addr(mut self, addr: SocketAddr) -> Self {
+        self.observed_addr = Some(addr);
+        self
+    }
+
+    pub fn with_external_addr(mut self, addr: Option<SocketAddr>) -> Self {
+        self.external_addr = addr;
+        self
+    }
+
+    /// Which side starts the Noise handshake. Both sides compute this from
+    /// the exchanged challenges, so the roles never depend on who dialed.
+    pub fn is_noise_initiator(&self, remote: &HandshakeInfo) -> bool {
+        self.challenge > remote.challenge
+    }
+
+    fn to_message(&self) -> Message {
+        Message::Handshake {
+            protocol_version: self.protocol_version,
+            node_id: self.node_id.clone(),
+            listen_port: self.listen_port,
+            challenge: self.challenge,
+            transport: self.transport,
+            inference_capacity: self.inference_capacity,
+            observed_addr: self.observed_addr,
+            external_addr: self.external_addr,
+        }
+    }
+}
+
+/// Exchanges `Handshake` messages with the remote side. Both sides send first,
+/// so the same call works for inbound and outbound connections.
+pub async fn perform<C: Connection>(conn: &mut C, local: &HandshakeInfo) -> Result<HandshakeInfo, HandshakeError> {
+    timeout(HANDSHAKE_TIMEOUT, exchange(conn, local))
+        .await
+        .map_err(|_| HandshakeError::Timeout)?
+}
+
+async fn exchange<C: Connection>(conn: &mut C, local: &HandshakeInfo) -> Result<HandshakeInfo, HandshakeError> {
+    write_message(conn, &local.to_message()).await?;
+
+    match read_message(conn).await? {
+        Some(Message::Handshake {
+            protocol_version,
+            node_id,
+            listen_port,
+            challenge,
+            transport,
+            inference_capacity,
+            observed_addr,
+            external_addr,
+        }) => {
+            if protocol_version != PROTOCOL_VERSION {
+                return Err(HandshakeError::Version(protocol_version));
+            }
+            // Reaching ourselves through gossip or a NAT hairpin.
+            if node_id == local.node_id {
+                return Err(HandshakeError::SelfConnection(node_id));
+            }
+            // Never fall back to plaintext: a node configured for noise only
+            // talks to peers that are too.
+            if transport != local.transport {
+                return Err(HandshakeError::TransportMismatch { local: local.transport, remote: transport });
+            }
+            // Equal challenges would leave both sides in the same Noise role.
+            if transport == TransportKind::Noise && challenge == local.challenge {
+                return Err(HandshakeError::Unexpected("peer echoed our challenge".to_string()));
+            }
+            Ok(HandshakeInfo {
+                protocol_version,
+                node_id,
+                listen_port,
+                challenge,
+                transport,
+                inference_capacity,
+                observed_addr,
+                external_addr,
+            })
+        }
+        Some(other) => Err(HandshakeError::Unexpected(format!("{:?}", other))),
+        None => Err(HandshakeError::Closed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::transport::MemoryConnection;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    #[tokio::test]
+    async fn test_handshake_over_memory_transport() {
+        let (mut a, mut b) = MemoryConnection::pair(addr(8000), addr(8001));
+        let local_a = HandshakeInfo::local("node-a", 8000).with_observed_addr(addr(8001));
+        let local_b = HandshakeInfo::local("node-b", 8001)
+            .with_inference_capacity(4)
+            .with_observed_addr(addr(8000))
+            .with_external_addr(Some(SocketAddr::from(([198, 51, 100, 7], 18001))));
+
+        let (seen_by_a, seen_by_b) = tokio::join!(perform(&mut a, &local_a), perform(&mut b, &local_b));
+
+        assert_eq!(seen_by_a.unwrap(), local_b);
+        assert_eq!(seen_by_b.unwrap(), local_a);
+        assert_eq!(a.peer_addr().unwrap(), addr(8001));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_version_mismatch() {
+        let (mut a, mut b) = MemoryConnection::pair(addr(8000), addr(8001));
+        let local_a = HandshakeInfo::local("node-a", 8000);
+        let future_b = HandshakeInfo {
+            protocol_version: PROTOCOL_VERSION + 1,
+            ..HandshakeInfo::local("node-b", 8001)
+        };
+
+        let (seen_by_a, _) = tokio::join!(perform(&mut a, &local_a), perform(&mut b, &future_b));
+        assert!(matches!(seen_by_a, Err(HandshakeError::Version(v)) if v == PROTOCOL_VERSION + 1));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_detects_closed_peer() {
+        let (mut a, b) = MemoryConnection::pair(addr(8000), addr(8001));
+        drop(b);
+
+        let result = perform(&mut a, &HandshakeInfo::local("node-a", 8000)).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_own_node_id() {
+        let (mut a, mut b) = MemoryConnection::pair(addr(8000), addr(8000));
+        let local = HandshakeInfo::local("node-a", 8000);
+        let echo = HandshakeInfo::local("node-a", 8000);
+
+        let (seen_by_a, _) = tokio::join!(perform(&mut a, &local), perform(&mut b, &echo));
+        assert!(matches!(seen_by_a, Err(HandshakeError::SelfConnection(id)) if id == "node-a"));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_transport_mismatch() {
+        let (mut a, mut b) = MemoryConnection::pair(addr(8000), addr(8001));
+        let noise = HandshakeInfo::local("node-a", 8000).with_transport(TransportKind::Noise);
+        let plain = HandshakeInfo::local("node-b", 8001);
+
+        let (seen_by_a, seen_by_b) = tokio::join!(perform(&mut a, &noise), perform(&mut b, &plain));
+        assert!(matches!(
+            seen_by_a,
+            Err(HandshakeError::TransportMismatch { local: TransportKind::Noise, remote: TransportKind::Plain })
+        ));
+        assert!(matches!(seen_by_b, Err(HandshakeError::TransportMismatch { .. })));
+    }
+}
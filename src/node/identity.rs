@@ -0,0 +1,90 @@
+use log::info;
+use solana_sdk::signature::Keypair;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum IdentityError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Encoding error: {0}")]
+    Encoding(#[from] serde_json::Error),
+    #[error("Invalid keypair file {0}: {1}")]
+    InvalidKeypair(String, String),
+}
+
+/// Reads a keypair stored as a JSON array of 64 bytes, the format
+/// `solana-keygen` writes.
+pub fn load_keypair(path: &Path) -> Result<Keypair, IdentityError> {
+    let bytes: Vec<u8> = serde_json::from_slice(&fs::read(path)?)?;
+    Keypair::from_bytes(&bytes).map_err(|e| IdentityError::InvalidKeypair(path.display().to_string(), e.to_string()))
+}
+
+pub fn save_keypair(keypair: &Keypair, path: &Path) -> Result<(), IdentityError> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec(&keypair.to_bytes().to_vec())?)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Loads the node keypair from `path`, generating and saving a new one the
+/// first time the node starts.
+pub fn load_or_create_keypair(path: &Path) -> Result<Keypair, IdentityError> {
+    match load_keypair(path) {
+        Err(IdentityError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            let keypair = Keypair::new();
+            save_keypair(&keypair, path)?;
+            info!("Generated new node keypair at {}", path.display());
+            Ok(keypair)
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::Signer;
+
+    #[test]
+    fn test_keypair_created_once_and_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys").join("identity.json");
+
+        let first = load_or_create_keypair(&path).unwrap();
+        let second = load_or_create_keypair(&path).unwrap();
+
+        assert_eq!(first.pubkey(), second.pubkey());
+        assert_eq!(load_keypair(&path).unwrap().to_bytes(), first.to_bytes());
+    }
+
+    #[test]
+    fn test_reads_solana_keygen_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("id.json");
+        let keypair = Keypair::new();
+        let array = keypair.to_bytes().iter().map(|b| b.to_string()).collect::<Vec<_>>().join(",");
+        fs::write(&path, format!("[{}]", array)).unwrap();
+
+        assert_eq!(load_or_create_keypair(&path).unwrap().pubkey(), keypair.pubkey());
+    }
+
+    #[test]
+    fn test_corrupt_keypair_is_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("id.json");
+        fs::write(&path, "[1, 2, 3]").unwrap();
+
+        assert!(matches!(load_or_create_keypair(&path), Err(IdentityError::InvalidKeypair(..))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[1, 2, 3]");
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const ATTEMPT_WINDOW: Duration = Duration::from_secs(60);
+// Sweep idle IPs once the table grows past this, so a scan from many
+// addresses can't grow it without bound.
+const SWEEP_THRESHOLD: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InboundLimits {
+    /// Connections one IP may hold open at once.
+    pub max_per_ip: usize,
+    /// Connection attempts one IP may make per minute.
+    pub attempts_per_minute: u32,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InboundRefusal {
+    #[error("{0} connections already open from this address")]
+    TooManyFromIp(usize),
+    #[error("More than {0} connection attempts in the last minute")]
+    RateLimited(u32),
+}
+
+/// Screens inbound connections by source IP before any handshake work is
+/// spent on them.
+#[derive(Debug)]
+pub struct InboundLimiter {
+    limits: InboundLimits,
+    attempts: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl InboundLimiter {
+    pub fn new(limits: InboundLimits) -> Self {
+        InboundLimiter {
+            limits,
+            attempts: HashMap::new(),
+        }
+    }
+
+    /// Records an attempt from `ip`, which already has `open_from_ip`
+    /// connections, and decides whether to accept it.
+    pub fn check(&mut self, ip: IpAddr, open_from_ip: usize, now: Instant) -> Result<(), InboundRefusal> {
+        if self.attempts.len() >= SWEEP_THRESHOLD {
+            self.sweep(now);
+        }
+
+        let attempts = self.attempts.entry(ip).or_default();
+        while attempts.front().is_some_and(|at| now.saturating_duration_since(*at) >= ATTEMPT_WINDOW) {
+            attempts.pop_front();
+        }
+        if attempts.len() >= self.limits.attempts_per_minute as usize {
+            return Err(InboundRefusal::RateLimited(self.limits.attempts_per_minute));
+        }
+        attempts.push_back(now);
+
+        if open_from_ip >= self.limits.max_per_ip {
+            return Err(InboundRefusal::TooManyFromIp(open_from_ip));
+        }
+        Ok(())
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        self.attempts.retain(|_, attempts| {
+            attempts.back().is_some_and(|at| now.saturating_duration_since(*at) < ATTEMPT_WINDOW)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: InboundLimits = InboundLimits {
+        max_per_ip: 2,
+        attempts_per_minute: 3,
+    };
+
+    #[test]
+    fn test_attempts_rate_limited_per_ip() {
+        let mut limiter = InboundLimiter::new(LIMITS);
+        let flooder: IpAddr = "10.0.0.2".parse().unwrap();
+        let other: IpAddr = "10.0.0.3".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check(flooder, 0, now), Ok(()));
+        }
+        assert_eq!(limiter.check(flooder, 0, now), Err(InboundRefusal::RateLimited(3)));
+        assert_eq!(limiter.check(other, 0, now), Ok(()));
+
+        assert_eq!(limiter.check(flooder, 0, now + ATTEMPT_WINDOW), Ok(()));
+    }
+
+    #[test]
+    fn test_open_connections_capped_per_ip() {
+        let mut limiter = InboundLimiter::new(LIMITS);
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limiter.check(ip, 1, now), Ok(()));
+        assert_eq!(limiter.check(ip, 2, now), Err(InboundRefusal::TooManyFromIp(2)));
+    }
+}
@@ -0,0 +1,854 @@
+use log::{debug, warn};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use solana_sdk::hash::hashv;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+use crate::node::consensus::Validator;
+use crate::node::generate::GenerationService;
+use crate::node::message::{GenerateParams, Message};
+use crate::node::outbox::Outboxes;
+
+const RECEIPT_DOMAIN: &[u8] = b"fractis-inference-receipt-v2";
+const ACCEPTANCE_DOMAIN: &[u8] = b"fractis-inference-acceptance-v1";
+pub const DEFAULT_INFERENCE_TIMEOUT: Duration = Duration::from_secs(120);
+/// Disputes kept until taken; the oldest give way beyond this.
+pub const MAX_RECORDED_DISPUTES: usize = 1024;
+/// Receipts one requester may have waiting to settle. Later ones are
+/// ignored, so no single validator can crowd out everyone else's.
+pub const MAX_UNSETTLED_RECEIPTS_PER_REQUESTER: usize = 4096;
+
+/// Requester, job and executor: what makes a receipt the same job.
+type ReceiptKey = ([u8; 32], u64, [u8; 32]);
+
+#[derive(Error, Debug)]
+pub enum InferenceError {
+    #[error("No connected peer is staked and has free inference capacity")]
+    NoCapablePeer,
+    #[error("Executor {0} did not answer in time")]
+    Timeout(Pubkey),
+    #[error("No open session with executor {0}")]
+    Unreachable(Pubkey),
+    #[error("Inference failed on {executor}: {message}")]
+    Remote { executor: Pubkey, message: String },
+    #[error("Receipt for job {0} does not match the job or its executor")]
+    BadReceipt(u64),
+    #[error("Node is shutting down")]
+    ShuttingDown,
+}
+
+/// What a result is bound to. Honest executors running the same model on
+/// the same inputs arrive at the same `output_hash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InferenceCommitment {
+    /// Prompt, every sampling param including the seed and stop sequences,
+    /// truncation, token limit and model version.
+    pub input_hash: [u8; 32],
+    pub output_hash: [u8; 32],
+}
+
+impl InferenceCommitment {
+    pub fn new(prompt: &str, params: &GenerateParams, max_tokens: u32, model_version: &str, output: &str) -> Self {
+        // Optional values are prefixed with a presence byte and strings with
+        // their length, so no two inputs hash the same bytes.
+        let mut sampling = params.temperature.to_bits().to_le_bytes().to_vec();
+        for value in [params.top_k.map(|k| k as u64), params.top_p.map(f64::to_bits)] {
+            sampling.push(u8::from(value.is_some()));
+            sampling.extend_from_slice(&value.unwrap_or_default().to_le_bytes());
+        }
+        sampling.extend_from_slice(&params.repetition_penalty.to_bits().to_le_bytes());
+        sampling.extend_from_slice(&(params.stop.len() as u64).to_le_bytes());
+        for stop in &params.stop {
+            sampling.extend_from_slice(&(stop.len() as u64).to_le_bytes());
+            sampling.extend_from_slice(stop.as_bytes());
+        }
+        InferenceCommitment {
+            input_hash: hashv(&[
+                &(prompt.len() as u64).to_le_bytes(),
+                prompt.as_bytes(),
+                &sampling,
+                &params.seed.to_le_bytes(),
+                &[params.truncation as u8],
+                &max_tokens.to_le_bytes(),
+                model_version.as_bytes(),
+            ])
+            .to_bytes(),
+            output_hash: hashv(&[output.as_bytes()]).to_bytes(),
+        }
+    }
+}
+
+/// The executor's signature over a job: who asked, who ran it, and the
+/// commitment to its inputs and output. Rewards are paid against these.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InferenceReceipt {
+    pub job_id: u64,
+    pub requester: [u8; 32],
+    pub executor: [u8; 32],
+    /// The token limit applied, which the executor may have lowered.
+    pub max_tokens: u32,
+    pub model_version: String,
+    pub commitment: InferenceCommitment,
+    pub signature: Vec<u8>,
+}
+
+impl InferenceReceipt {
+    pub fn sign(
+        keypair: &Keypair,
+        job_id: u64,
+        requester: &Pubkey,
+        max_tokens: u32,
+        model_version: String,
+        commitment: InferenceCommitment,
+    ) -> Self {
+        let mut receipt = InferenceReceipt {
+            job_id,
+            requester: requester.to_bytes(),
+            executor: keypair.pubkey().to_bytes(),
+            max_tokens,
+            model_version,
+            commitment,
+            signature: Vec::new(),
+        };
+        receipt.signature = keypair.sign_message(&receipt.payload()).as_ref().to_vec();
+        receipt
+    }
+
+    pub fn executor(&self) -> Pubkey {
+        Pubkey::new_from_array(self.executor)
+    }
+
+    fn key(&self) -> ReceiptKey {
+        (self.requester, self.job_id, self.executor)
+    }
+
+    /// Whether the executor signed this receipt for exactly these inputs and `output`.
+    pub fn verify(&self, prompt: &str, params: &GenerateParams, output: &str) -> bool {
+        self.commitment == InferenceCommitment::new(prompt, params, self.max_tokens, &self.model_version, output)
+            && self.verify_signature()
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        Signature::try_from(self.signature.as_slice())
+            .map_or(false, |signature| signature.verify(&self.executor, &self.payload()))
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        let mut payload = RECEIPT_DOMAIN.to_vec();
+        payload.extend_from_slice(&self.job_id.to_le_bytes());
+        payload.extend_from_slice(&self.requester);
+        payload.extend_from_slice(&self.executor);
+        payload.extend_from_slice(&self.max_tokens.to_le_bytes());
+        payload.extend_from_slice(&(self.model_version.len() as u64).to_le_bytes());
+        payload.extend_from_slice(self.model_version.as_bytes());
+        payload.extend_from_slice(&self.commitment.input_hash);
+        payload.extend_from_slice(&self.commitment.output_hash);
+        payload
+    }
+}
+
+/// A receipt the requester checked against the job and countersigned, so
+/// an executor can't claim work for jobs nobody gave it. Gossiped for
+/// every node to count towards the executor's rewards.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcceptedReceipt {
+    pub receipt: InferenceReceipt,
+    pub signature: Vec<u8>,
+}
+
+impl AcceptedReceipt {
+    pub fn sign(requester: &Keypair, receipt: InferenceReceipt) -> Self {
+        let signature = requester.sign_message(&Self::payload(&receipt)).as_ref().to_vec();
+        AcceptedReceipt { receipt, signature }
+    }
+
+    /// Whether the executor signed the receipt and the requester it names
+    /// countersigned it.
+    pub fn verify_signatures(&self) -> bool {
+        self.receipt.verify_signature()
+            && Signature::try_from(self.signature.as_slice())
+                .map_or(false, |signature| signature.verify(&self.receipt.requester, &Self::payload(&self.receipt)))
+    }
+
+    fn payload(receipt: &InferenceReceipt) -> Vec<u8> {
+        let mut payload = ACCEPTANCE_DOMAIN.to_vec();
+        payload.extend_from_slice(&receipt.payload());
+        payload.extend_from_slice(&receipt.signature);
+        payload
+    }
+}
+
+/// Signed results from three executors that took the same inputs: the two
+/// `witnesses` agree and the `faulty` one does not. Two receipts that
+/// disagree don't say which is wrong, so a third run decides.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InferenceDispute {
+    pub faulty: InferenceReceipt,
+    pub witnesses: [InferenceReceipt; 2],
+}
+
+impl InferenceDispute {
+    /// Settles a mismatch between `first` and `second` by siding with the
+    /// one `tiebreak` agrees with. `None` unless it agrees with exactly one.
+    pub fn resolve(first: InferenceReceipt, second: InferenceReceipt, tiebreak: InferenceReceipt) -> Option<Self> {
+        let (faulty, agreeing) = if tiebreak.commitment.output_hash == first.commitment.output_hash {
+            (second, first)
+        } else {
+            (first, second)
+        };
+        let dispute = InferenceDispute { faulty, witnesses: [agreeing, tiebreak] };
+        dispute.is_valid().then_some(dispute)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let [first, second] = &self.witnesses;
+        let faulty = &self.faulty;
+        faulty.executor != first.executor
+            && faulty.executor != second.executor
+            && first.executor != second.executor
+            && first.commitment.input_hash == faulty.commitment.input_hash
+            && second.commitment.input_hash == faulty.commitment.input_hash
+            && first.commitment.output_hash == second.commitment.output_hash
+            && first.commitment.output_hash != faulty.commitment.output_hash
+            && faulty.verify_signature()
+            && first.verify_signature()
+            && second.verify_signature()
+    }
+}
+
+/// A finished job as returned to whoever submitted it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InferenceOutput {
+    pub text: String,
+    pub receipt: InferenceReceipt,
+}
+
+/// A connected peer that might run a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub pubkey: Pubkey,
+    pub stake: u64,
+    /// Concurrent jobs the peer advertised in its handshake.
+    pub capacity: u32,
+    /// Jobs this node is still waiting on from the peer.
+    pub in_flight: u32,
+}
+
+/// Picks a staked candidate with free capacity, weighted by stake. The draw
+/// is derived from the job, so the choice is reproducible.
+pub fn select_executor(candidates: &[Candidate], job_id: u64, requester: &Pubkey) -> Option<Pubkey> {
+    let mut eligible: Vec<&Candidate> = candidates
+        .iter()
+        .filter(|c| c.stake > 0 && c.in_flight < c.capacity)
+        .collect();
+    eligible.sort_by_key(|c| c.pubkey);
+    eligible.dedup_by_key(|c| c.pubkey);
+    let total: u64 = eligible.iter().fold(0u64, |sum, c| sum.saturating_add(c.stake));
+    if total == 0 {
+        return None;
+    }
+
+    let draw = hashv(&[b"executor", &job_id.to_le_bytes(), requester.as_ref()]);
+    let mut point = u64::from_le_bytes(draw.as_ref()[..8].try_into().unwrap()) % total;
+    for candidate in &eligible {
+        if point < candidate.stake {
+            return Some(candidate.pubkey);
+        }
+        point -= candidate.stake;
+    }
+    eligible.last().map(|c| c.pubkey)
+}
+
+/// Completed jobs by executor, and the receipts not yet settled into rewards.
+#[derive(Debug, Default)]
+pub struct JobLedger {
+    completed: HashMap<Pubkey, u64>,
+    unsettled: Vec<InferenceReceipt>,
+    /// Receipts handed over at the last settlement, still recognised as
+    /// repeats until the next one.
+    settled: HashSet<ReceiptKey>,
+}
+
+impl JobLedger {
+    /// Returns `false` for a receipt already recorded, or once its
+    /// requester has `MAX_UNSETTLED_RECEIPTS_PER_REQUESTER` waiting.
+    pub fn record(&mut self, receipt: InferenceReceipt) -> bool {
+        let key = receipt.key();
+        if self.settled.contains(&key) || self.unsettled.iter().any(|r| r.key() == key) {
+            return false;
+        }
+        let waiting = self.unsettled.iter().filter(|r| r.requester == receipt.requester).count();
+        if waiting >= MAX_UNSETTLED_RECEIPTS_PER_REQUESTER {
+            return false;
+        }
+        *self.completed.entry(receipt.executor()).or_default() += 1;
+        self.unsettled.push(receipt);
+        true
+    }
+
+    /// Drops an unsettled receipt that turned out to be wrong. Returns
+    /// whether it was still unsettled.
+    pub fn revoke(&mut self, receipt: &InferenceReceipt) -> bool {
+        let before = self.unsettled.len();
+        self.unsettled.retain(|r| r != receipt);
+        if self.unsettled.len() == before {
+            return false;
+        }
+        if let Some(count) = self.completed.get_mut(&receipt.executor()) {
+            *count = count.saturating_sub(1);
+        }
+        true
+    }
+
+    pub fn completed_by(&self, executor: &Pubkey) -> u64 {
+        self.completed.get(executor).copied().unwrap_or(0)
+    }
+
+    pub fn completed(&self) -> HashMap<Pubkey, u64> {
+        self.completed.clone()
+    }
+
+    /// Hands over the receipts recorded since the last settlement. They
+    /// can't be revoked any more, but still count as repeats until the
+    /// next one.
+    pub fn settle(&mut self) -> Vec<InferenceReceipt> {
+        let receipts = std::mem::take(&mut self.unsettled);
+        self.settled = receipts.iter().map(InferenceReceipt::key).collect();
+        receipts
+    }
+}
+
+#[derive(Debug)]
+struct PendingJob {
+    executor: Pubkey,
+    reply: oneshot::Sender<Result<InferenceOutput, String>>,
+}
+
+/// Both sides of the inference job market: runs jobs routed to this node and
+/// tracks the ones this node routed to peers until their results come back.
+#[derive(Debug)]
+pub struct InferenceMarket {
+    keypair: Arc<Keypair>,
+    generator: RwLock<Option<Arc<dyn GenerationService>>>,
+    max_tokens: usize,
+    timeout: Duration,
+    verification_rate: f64,
+    job_ids: AtomicU64,
+    pending: Mutex<HashMap<u64, PendingJob>>,
+    /// Jobs running here, by requester.
+    running: Mutex<HashMap<Pubkey, u32>>,
+    /// Jobs one requester may have running here at once.
+    capacity: u32,
+    ledger: Mutex<JobLedger>,
+    disputes: Mutex<Vec<InferenceDispute>>,
+}
+
+/// A requester's job counted in `running` until dropped.
+struct RunningJob<'a> {
+    running: &'a Mutex<HashMap<Pubkey, u32>>,
+    requester: Pubkey,
+}
+
+impl Drop for RunningJob<'_> {
+    fn drop(&mut self) {
+        let mut running = self.running.lock();
+        if let Some(count) = running.get_mut(&self.requester) {
+            *count -= 1;
+            if *count == 0 {
+                running.remove(&self.requester);
+            }
+        }
+    }
+}
+
+impl InferenceMarket {
+    /// Jobs run here generate at most `max_tokens` tokens.
+    pub fn new(keypair: Arc<Keypair>, max_tokens: usize) -> Self {
+        InferenceMarket {
+            keypair,
+            generator: RwLock::new(None),
+            max_tokens,
+            timeout: DEFAULT_INFERENCE_TIMEOUT,
+            verification_rate: 0.0,
+            job_ids: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+            running: Mutex::new(HashMap::new()),
+            capacity: u32::MAX,
+            ledger: Mutex::new(JobLedger::default()),
+            disputes: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Caps the jobs one requester has running here at once, normally at
+    /// the capacity advertised in the handshake. Routers that respect the
+    /// advertisement never hit it.
+    pub fn with_capacity(mut self, capacity: u32) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Fraction of completed jobs re-executed by a second validator.
+    pub fn with_verification_rate(mut self, rate: f64) -> Self {
+        self.verification_rate = rate;
+        self
+    }
+
+    pub fn pubkey(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+
+    pub fn attach_generator(&self, generator: Arc<dyn GenerationService>) {
+        *self.generator.write() = Some(generator);
+    }
+
+    pub fn generator(&self) -> Option<Arc<dyn GenerationService>> {
+        self.generator.read().clone()
+    }
+
+    /// The generator and the token budget a request for `max_tokens` gets.
+    fn prepare(&self, max_tokens: u32) -> Result<(Arc<dyn GenerationService>, u32), String> {
+        let generator = self.generator().ok_or_else(|| "LLM is disabled on this node".to_string())?;
+        if max_tokens == 0 {
+            return Err("max_tokens must be at least 1".to_string());
+        }
+        Ok((generator, max_tokens.min(u32::try_from(self.max_tokens).unwrap_or(u32::MAX))))
+    }
+
+    /// Runs a peer's unsigned `GenerateRequest`, capped like a job.
+    pub async fn generate(&self, prompt: String, max_tokens: u32, params: GenerateParams) -> Result<String, String> {
+        let (generator, max_tokens) = self.prepare(max_tokens)?;
+        generator.generate(prompt, max_tokens as usize, params).await
+    }
+
+    /// Runs a job routed here by `requester` and signs the result. Refused
+    /// while the requester already has `capacity` jobs running here.
+    pub async fn execute(
+        &self,
+        requester: &Pubkey,
+        job_id: u64,
+        prompt: String,
+        max_tokens: u32,
+        params: GenerateParams,
+    ) -> Result<InferenceOutput, String> {
+        let (generator, max_tokens) = self.prepare(max_tokens)?;
+        let _running = self.begin_job(requester)?;
+        let text = generator.generate(prompt.clone(), max_tokens as usize, params.clone()).await?;
+        let model_version = generator.model_version();
+        let commitment = InferenceCommitment::new(&prompt, &params, max_tokens, &model_version, &text);
+        let receipt = InferenceReceipt::sign(&self.keypair, job_id, requester, max_tokens, model_version, commitment);
+        Ok(InferenceOutput { text, receipt })
+    }
+
+    fn begin_job(&self, requester: &Pubkey) -> Result<RunningJob<'_>, String> {
+        let mut running = self.running.lock();
+        let count = running.entry(*requester).or_default();
+        if *count >= self.capacity {
+            return Err(format!("{} already has {} jobs running here", requester, count));
+        }
+        *count += 1;
+        Ok(RunningJob { running: &self.running, requester: *requester })
+    }
+
+    pub fn next_job_id(&self) -> u64 {
+        self.job_ids.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn in_flight(&self, executor: &Pubkey) -> u32 {
+        self.pending.lock().values().filter(|job| job.executor == *executor).count() as u32
+    }
+
+    /// Starts waiting for `executor` to answer `job_id`.
+    pub fn register(&self, job_id: u64, executor: Pubkey) -> oneshot::Receiver<Result<InferenceOutput, String>> {
+        let (reply, rx) = oneshot::channel();
+        self.pending.lock().insert(job_id, PendingJob { executor, reply });
+        rx
+    }
+
+    /// Delivers a result sent by `from`. Returns `false` if no job of ours
+    /// was waiting on `from` under that ID.
+    pub fn complete(&self, from: &Pubkey, job_id: u64, result: Result<InferenceOutput, String>) -> bool {
+        let mut pending = self.pending.lock();
+        if pending.get(&job_id).map_or(true, |job| job.executor != *from) {
+            return false;
+        }
+        let job = pending.remove(&job_id).expect("checked above");
+        let _ = job.reply.send(result);
+        true
+    }
+
+    /// Sends a job to a candidate picked by `select_executor`, over its
+    /// session alone, and waits for its result.
+    pub async fn route(
+        &self,
+        outboxes: &Outboxes,
+        candidates: &[Candidate],
+        prompt: &str,
+        max_tokens: u32,
+        params: GenerateParams,
+    ) -> Result<InferenceOutput, InferenceError> {
+        let job_id = self.next_job_id();
+        let executor = select_executor(candidates, job_id, &self.pubkey()).ok_or(InferenceError::NoCapablePeer)?;
+        let reply = self.register(job_id, executor);
+        debug!("Routing inference job {} to {}", job_id, executor);
+        let request = Message::InferenceRequest {
+            job_id,
+            executor: Some(executor.to_bytes()),
+            prompt: prompt.to_string(),
+            max_tokens,
+            params: params.clone(),
+        };
+        if !outboxes.send(&executor, request).await {
+            self.pending.lock().remove(&job_id);
+            return Err(InferenceError::Unreachable(executor));
+        }
+        self.await_result(job_id, executor, prompt, max_tokens, params, reply).await
+    }
+
+    /// Waits for the executor's answer, checks its receipt and records the
+    /// job as completed.
+    pub async fn await_result(
+        &self,
+        job_id: u64,
+        executor: Pubkey,
+        prompt: &str,
+        max_tokens: u32,
+        params: GenerateParams,
+        rx: oneshot::Receiver<Result<InferenceOutput, String>>,
+    ) -> Result<InferenceOutput, InferenceError> {
+        let result = tokio::time::timeout(self.timeout, rx).await;
+        self.pending.lock().remove(&job_id);
+        let output = match result {
+            Ok(Ok(Ok(output))) => output,
+            Ok(Ok(Err(message))) => return Err(InferenceError::Remote { executor, message }),
+            Ok(Err(_)) | Err(_) => return Err(InferenceError::Timeout(executor)),
+        };
+
+        let receipt = &output.receipt;
+        if receipt.job_id != job_id
+            || receipt.executor() != executor
+            || receipt.requester != self.pubkey().to_bytes()
+            || receipt.max_tokens > max_tokens
+            || !receipt.verify(prompt, &params, &output.text)
+        {
+            return Err(InferenceError::BadReceipt(job_id));
+        }
+        if !self.ledger.lock().record(receipt.clone()) {
+            debug!("Job {} from {} was already recorded", job_id, executor);
+        }
+        Ok(output)
+    }
+
+    /// Whether to re-execute a completed job, drawn at `verification_rate`.
+    /// The draw is random so an executor cannot tell which jobs get checked.
+    pub fn should_verify(&self) -> bool {
+        let draw = uuid::Uuid::new_v4().as_u128() as u64;
+        self.verification_rate >= 1.0 || (draw as f64) < self.verification_rate * u64::MAX as f64
+    }
+
+    /// Re-runs a completed job on a second validator and, if the outputs
+    /// differ, on a third to decide between the two. Returns the dispute
+    /// against whichever executor was outvoted; `None` if the outputs agree
+    /// or no third validator settles it.
+    pub async fn verify(
+        &self,
+        outboxes: &Outboxes,
+        candidates: &[Candidate],
+        prompt: &str,
+        params: GenerateParams,
+        original: InferenceReceipt,
+    ) -> Option<InferenceDispute> {
+        let others = |ran: &[&InferenceReceipt]| -> Vec<Candidate> {
+            candidates.iter().filter(|c| ran.iter().all(|r| r.executor() != c.pubkey)).copied().collect()
+        };
+        let max_tokens = original.max_tokens;
+        let check = match self.route(outboxes, &others(&[&original]), prompt, max_tokens, params.clone()).await {
+            Ok(check) => check.receipt,
+            Err(e) => {
+                debug!("Could not re-run inference job {}: {}", original.job_id, e);
+                return None;
+            }
+        };
+        // A lower token cap or another model version changes the inputs,
+        // and then the outputs can't be compared.
+        if check.commitment.input_hash != original.commitment.input_hash
+            || check.commitment.output_hash == original.commitment.output_hash
+        {
+            return None;
+        }
+        let tiebreak = match self.route(outboxes, &others(&[&original, &check]), prompt, max_tokens, params).await {
+            Ok(tiebreak) => tiebreak.receipt,
+            Err(e) => {
+                warn!(
+                    "Validators {} and {} disagree on inference job {} and no third settled it: {}",
+                    original.executor(),
+                    check.executor(),
+                    original.job_id,
+                    e
+                );
+                return None;
+            }
+        };
+        InferenceDispute::resolve(original, check, tiebreak)
+    }
+
+    /// Keeps a valid dispute for the operator and withholds the reward for
+    /// the outvoted job. Returns `false` if it was already known.
+    pub fn record_dispute(&self, dispute: InferenceDispute) -> bool {
+        let mut disputes = self.disputes.lock();
+        let faulty = &dispute.faulty;
+        if disputes.iter().any(|d| {
+            d.faulty.job_id == faulty.job_id
+                && d.faulty.requester == faulty.requester
+                && d.faulty.executor == faulty.executor
+        }) {
+            return false;
+        }
+        self.ledger.lock().revoke(faulty);
+        if disputes.len() >= MAX_RECORDED_DISPUTES {
+            disputes.remove(0);
+        }
+        disputes.push(dispute);
+        true
+    }
+
+    pub fn take_disputes(&self) -> Vec<InferenceDispute> {
+        std::mem::take(&mut *self.disputes.lock())
+    }
+
+    pub fn completed_jobs(&self) -> HashMap<Pubkey, u64> {
+        self.ledger.lock().completed()
+    }
+
+    /// Countersigns a receipt this node checked, for gossiping.
+    pub fn accept(&self, receipt: InferenceReceipt) -> AcceptedReceipt {
+        AcceptedReceipt::sign(&self.keypair, receipt)
+    }
+
+    /// Records a job another node gossiped. Requester and executor must be
+    /// different staked `validators`, so nobody is paid for jobs they hand
+    /// themselves. Returns whether it was new.
+    pub fn record_accepted(&self, accepted: &AcceptedReceipt, validators: &[Validator]) -> bool {
+        let receipt = &accepted.receipt;
+        let staked = |key: &[u8; 32]| validators.iter().any(|v| v.pubkey.to_bytes() == *key);
+        if receipt.requester == receipt.executor
+            || !staked(&receipt.requester)
+            || !staked(&receipt.executor)
+            || !accepted.verify_signatures()
+        {
+            return false;
+        }
+        self.ledger.lock().record(receipt.clone())
+    }
+
+    /// Hands over the receipts recorded since the last settlement, once
+    /// the epoch they count towards ends. Only one caller should settle.
+    pub fn settle_receipts(&self) -> Vec<InferenceReceipt> {
+        self.ledger.lock().settle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::message::Truncation;
+
+    fn candidate(stake: u64, capacity: u32, in_flight: u32) -> Candidate {
+        Candidate { pubkey: Pubkey::new_unique(), stake, capacity, in_flight }
+    }
+
+    #[test]
+    fn test_selection_skips_unstaked_and_busy_peers() {
+        let requester = Pubkey::new_unique();
+        let ready = candidate(10, 2, 1);
+        let candidates = [candidate(0, 4, 0), candidate(50, 2, 2), ready, candidate(30, 0, 0)];
+        for job_id in 0..20 {
+            assert_eq!(select_executor(&candidates, job_id, &requester), Some(ready.pubkey));
+        }
+        assert_eq!(select_executor(&candidates[..2], 1, &requester), None);
+    }
+
+    #[test]
+    fn test_selection_follows_stake() {
+        let requester = Pubkey::new_unique();
+        let heavy = candidate(900, 8, 0);
+        let light = candidate(100, 8, 0);
+        let picked_heavy = (0..1000)
+            .filter(|&job_id| select_executor(&[heavy, light], job_id, &requester) == Some(heavy.pubkey))
+            .count();
+        assert!((850..950).contains(&picked_heavy), "{}", picked_heavy);
+    }
+
+    fn receipt(executor: &Keypair, job_id: u64, requester: &Pubkey, output: &str) -> InferenceReceipt {
+        let params = GenerateParams::default();
+        let commitment = InferenceCommitment::new("prompt", &params, 16, "test-model", output);
+        InferenceReceipt::sign(executor, job_id, requester, 16, "test-model".to_string(), commitment)
+    }
+
+    #[test]
+    fn test_receipt_binds_inputs_and_output() {
+        let executor = Keypair::new();
+        let params = GenerateParams::default();
+        let receipt = receipt(&executor, 7, &Pubkey::new_unique(), "output");
+
+        assert!(receipt.verify("prompt", &params, "output"));
+        assert!(!receipt.verify("prompt", &params, "forged output"));
+        assert!(!receipt.verify("prompt", &GenerateParams { seed: 1, ..params.clone() }, "output"));
+        assert!(!receipt.verify("prompt", &GenerateParams { truncation: Truncation::Head, ..params.clone() }, "output"));
+        assert!(!receipt.verify("prompt", &GenerateParams { top_k: Some(40), ..params.clone() }, "output"));
+        assert!(!receipt.verify("prompt", &GenerateParams { top_p: Some(0.9), ..params.clone() }, "output"));
+        let stop = vec!["\n".to_string()];
+        assert!(!receipt.verify("prompt", &GenerateParams { stop, ..params.clone() }, "output"));
+        let mut reassigned = receipt.clone();
+        reassigned.executor = Keypair::new().pubkey().to_bytes();
+        assert!(!reassigned.verify("prompt", &params, "output"));
+    }
+
+    #[test]
+    fn test_tiebreak_decides_which_executor_is_faulty() {
+        let requester = Pubkey::new_unique();
+        let (first, second, third) = (Keypair::new(), Keypair::new(), Keypair::new());
+        let original = receipt(&first, 1, &requester, "yes");
+        let check = receipt(&second, 2, &requester, "no");
+        let resolve = |tiebreak| InferenceDispute::resolve(original.clone(), check.clone(), tiebreak);
+
+        // The check was the one that got it wrong.
+        assert_eq!(resolve(receipt(&third, 3, &requester, "yes")).unwrap().faulty, check);
+        assert_eq!(resolve(receipt(&third, 3, &requester, "no")).unwrap().faulty, original);
+
+        assert!(resolve(receipt(&third, 3, &requester, "maybe")).is_none());
+        assert!(resolve(receipt(&second, 3, &requester, "no")).is_none());
+        let mut forged = receipt(&third, 3, &requester, "no");
+        forged.signature[0] ^= 1;
+        assert!(resolve(forged).is_none());
+        let params = GenerateParams { seed: 1, ..GenerateParams::default() };
+        let commitment = InferenceCommitment::new("prompt", &params, 16, "test-model", "no");
+        let other_inputs = InferenceReceipt::sign(&third, 3, &requester, 16, "test-model".to_string(), commitment);
+        assert!(resolve(other_inputs).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_only_the_executor_completes_a_job() {
+        let market = InferenceMarket::new(Arc::new(Keypair::new()), 16);
+        let executor = Keypair::new();
+        let job_id = market.next_job_id();
+        let rx = market.register(job_id, executor.pubkey());
+        assert_eq!(market.in_flight(&executor.pubkey()), 1);
+
+        let output = || InferenceOutput {
+            text: "done".to_string(),
+            receipt: receipt(&executor, job_id, &market.pubkey(), "done"),
+        };
+        assert!(!market.complete(&Pubkey::new_unique(), job_id, Ok(output())));
+        assert!(market.complete(&executor.pubkey(), job_id, Ok(output())));
+
+        let params = GenerateParams::default();
+        let finished = market.await_result(job_id, executor.pubkey(), "prompt", 64, params, rx).await.unwrap();
+        assert_eq!(finished.text, "done");
+        assert_eq!(market.in_flight(&executor.pubkey()), 0);
+        assert_eq!(market.completed_jobs()[&executor.pubkey()], 1);
+
+        let check = receipt(&Keypair::new(), 9, &market.pubkey(), "not done");
+        let tiebreak = receipt(&Keypair::new(), 10, &market.pubkey(), "not done");
+        let dispute = InferenceDispute::resolve(finished.receipt, check, tiebreak).unwrap();
+        assert!(market.record_dispute(dispute.clone()));
+        assert!(!market.record_dispute(dispute));
+        assert_eq!(market.completed_jobs()[&executor.pubkey()], 0);
+        assert!(market.settle_receipts().is_empty());
+        assert_eq!(market.take_disputes().len(), 1);
+    }
+
+    #[test]
+    fn test_gossiped_jobs_counted_once_between_validators() {
+        let market = InferenceMarket::new(Arc::new(Keypair::new()), 16);
+        let (requester, executor, outsider) = (Keypair::new(), Keypair::new(), Keypair::new());
+        let validators: Vec<Validator> = [&requester, &executor]
+            .iter()
+            .map(|k| Validator { pubkey: k.pubkey(), stake: 10, locked_until: i64::MAX })
+            .collect();
+        let accepted = AcceptedReceipt::sign(&requester, receipt(&executor, 1, &requester.pubkey(), "out"));
+
+        assert!(market.record_accepted(&accepted, &validators));
+        assert!(!market.record_accepted(&accepted, &validators));
+        // Executors can't hand themselves jobs, nor claim one the requester
+        // never countersigned or one from outside the validator set.
+        let own = AcceptedReceipt::sign(&executor, receipt(&executor, 2, &executor.pubkey(), "out"));
+        assert!(!market.record_accepted(&own, &validators));
+        let unsigned = AcceptedReceipt::sign(&outsider, receipt(&executor, 3, &requester.pubkey(), "out"));
+        assert!(!market.record_accepted(&unsigned, &validators));
+        let unstaked = AcceptedReceipt::sign(&outsider, receipt(&executor, 4, &outsider.pubkey(), "out"));
+        assert!(!market.record_accepted(&unstaked, &validators));
+
+        assert_eq!(market.settle_receipts(), vec![accepted.receipt.clone()]);
+        // Still a repeat after settling, so it isn't paid again.
+        assert!(!market.record_accepted(&accepted, &validators));
+        assert!(market.settle_receipts().is_empty());
+        assert_eq!(market.completed_jobs()[&executor.pubkey()], 1);
+    }
+
+    #[test]
+    fn test_recorded_disputes_capped() {
+        let market = InferenceMarket::new(Arc::new(Keypair::new()), 16);
+        let (faulty, first, second) = (Keypair::new(), Keypair::new(), Keypair::new());
+        let requester = market.pubkey();
+        for job_id in 0..MAX_RECORDED_DISPUTES as u64 + 1 {
+            let dispute = InferenceDispute::resolve(
+                receipt(&faulty, job_id, &requester, "wrong"),
+                receipt(&first, job_id, &requester, "right"),
+                receipt(&second, job_id, &requester, "right"),
+            )
+            .unwrap();
+            assert!(market.record_dispute(dispute));
+        }
+
+        let disputes = market.take_disputes();
+        assert_eq!(disputes.len(), MAX_RECORDED_DISPUTES);
+        assert_eq!(disputes[0].faulty.job_id, 1);
+    }
+
+    #[derive(Debug)]
+    struct SlowGenerator;
+
+    #[async_trait::async_trait]
+    impl GenerationService for SlowGenerator {
+        async fn generate(&self, prompt: String, _max_tokens: usize, _params: GenerateParams) -> Result<String, String> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(prompt)
+        }
+
+        fn model_version(&self) -> String {
+            "slow".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requester_over_capacity_refused() {
+        let market = Arc::new(InferenceMarket::new(Arc::new(Keypair::new()), 16).with_capacity(1));
+        market.attach_generator(Arc::new(SlowGenerator));
+        let (busy, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let params = GenerateParams::default();
+
+        let first = {
+            let (market, params) = (Arc::clone(&market), params.clone());
+            tokio::spawn(async move { market.execute(&busy, 1, "first".to_string(), 8, params).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let refused = market.execute(&busy, 2, "second".to_string(), 8, params.clone()).await;
+        assert!(matches!(refused, Err(message) if message.contains("already has 1 jobs")));
+        assert!(market.execute(&other, 3, "other".to_string(), 8, params.clone()).await.is_ok());
+
+        assert!(first.await.unwrap().is_ok());
+        assert!(market.execute(&busy, 4, "again".to_string(), 8, params).await.is_ok());
+    }
+}
@@ -0,0 +1,449 @@
+use axum::{
+    extract::{ws::WebSocketUpgrade, State},
+    response::Response,
+    routing::{get, post},
+    Json, Router,
+};
+use log::info;
+use serde::Serialize;
+use serde_json::{json, Value};
+use solana_sdk::{pubkey::Pubkey, signature::Signature, transaction::Transaction};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+
+use crate::node::activity::Activity;
+use crate::node::config::RpcServerConfig;
+use crate::node::consensus::{Block, Validator};
+use crate::node::control::{
+    list_limit, RpcRequest, RpcResponse, INTERNAL_ERROR, INVALID_PARAMS, INVALID_REQUEST, JSONRPC_VERSION,
+    METHOD_NOT_FOUND,
+};
+use crate::node::error::NodeError;
+use crate::node::handshake::PROTOCOL_VERSION;
+use crate::node::network::Node;
+use crate::node::peer::PeerSnapshot;
+use crate::node::subscriptions;
+use crate::program::stake::find_stake_address;
+
+/// Implementation-defined server error: the node cannot serve chain data,
+/// e.g. because no storage is attached yet.
+const NODE_UNAVAILABLE: i64 = -32001;
+
+/// The node state readable, and the one write, over the public JSON-RPC API.
+pub trait RpcTarget: Send + Sync {
+    fn peers(&self) -> Vec<PeerSnapshot>;
+    fn block_height(&self) -> u64;
+    fn block(&self, height: u64) -> Result<Option<Block>, NodeError>;
+    fn pending_transactions(&self) -> Vec<Signature>;
+    fn submit_transaction(&self, transaction: Transaction) -> bool;
+    fn validators(&self) -> Vec<Validator>;
+    fn stake_program_id(&self) -> Option<Pubkey>;
+    fn pubkey(&self) -> Pubkey;
+    fn subscribe_activity(&self) -> broadcast::Receiver<Activity>;
+}
+
+impl RpcTarget for Node {
+    fn peers(&self) -> Vec<PeerSnapshot> {
+        Node::peers(self)
+    }
+
+    fn block_height(&self) -> u64 {
+        Node::height(self)
+    }
+
+    fn block(&self, height: u64) -> Result<Option<Block>, NodeError> {
+        Node::stored_block_at(self, height)
+    }
+
+    fn pending_transactions(&self) -> Vec<Signature> {
+        Node::pending_transactions(self)
+    }
+
+    fn submit_transaction(&self, transaction: Transaction) -> bool {
+        Node::queue_transaction(self, transaction)
+    }
+
+    fn validators(&self) -> Vec<Validator> {
+        Node::validators(self)
+    }
+
+    fn stake_program_id(&self) -> Option<Pubkey> {
+        Node::stake_program_id(self)
+    }
+
+    fn pubkey(&self) -> Pubkey {
+        Node::pubkey(self)
+    }
+
+    fn subscribe_activity(&self) -> broadcast::Receiver<Activity> {
+        Node::subscribe_activity(self)
+    }
+}
+
+/// A block with hashes, keys and transaction signatures in their usual
+/// base58 form.
+#[derive(Debug, Serialize)]
+struct BlockView {
+    height: u64,
+    hash: String,
+    parent_hash: String,
+    timestamp: i64,
+    proposer: String,
+    transactions: Vec<String>,
+}
+
+impl From<&Block> for BlockView {
+    fn from(block: &Block) -> Self {
+        BlockView {
+            height: block.header.height,
+            hash: block.hash().to_string(),
+            parent_hash: block.header.parent_hash.to_string(),
+            timestamp: block.header.timestamp,
+            proposer: block.header.proposer.to_string(),
+            transactions: block
+                .transactions
+                .iter()
+                .filter_map(|transaction| transaction.signatures.first().map(Signature::to_string))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ValidatorView {
+    pubkey: String,
+    stake: u64,
+    locked_until: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct StakeInfo {
+    pubkey: String,
+    stake_account: Option<String>,
+    is_validator: bool,
+    stake: u64,
+    locked_until: i64,
+}
+
+/// JSON-RPC calls are posted to `/`; `/ws` upgrades to a WebSocket that
+/// streams node activity for the topics a client subscribes to.
+pub fn router(target: Arc<dyn RpcTarget>) -> Router {
+    Router::new()
+        .route("/", post(handle_rpc))
+        .route("/ws", get(handle_ws))
+        .with_state(target)
+}
+
+pub async fn serve(target: Arc<dyn RpcTarget>, config: &RpcServerConfig) -> std::io::Result<()> {
+    serve_router(router(target), config).await
+}
+
+/// Serves `app`, typically `router` merged with extra routes such as the
+/// LLM's `/generate` stream, on the configured RPC address.
+pub async fn serve_router(app: Router, config: &RpcServerConfig) -> std::io::Result<()> {
+    let listener = TcpListener::bind((config.host.as_str(), config.port)).await?;
+    info!("JSON-RPC server listening on {}", listener.local_addr()?);
+    axum::serve(listener, app).await
+}
+
+async fn handle_rpc(State(target): State<Arc<dyn RpcTarget>>, Json(request): Json<RpcRequest>) -> Json<RpcResponse> {
+    Json(dispatch(target.as_ref(), request))
+}
+
+async fn handle_ws(State(target): State<Arc<dyn RpcTarget>>, upgrade: WebSocketUpgrade) -> Response {
+    // Subscribe before the upgrade completes so no event falls in between.
+    let activity = target.subscribe_activity();
+    upgrade.on_upgrade(move |socket| subscriptions::run(socket, activity))
+}
+
+fn dispatch(target: &dyn RpcTarget, request: RpcRequest) -> RpcResponse {
+    let id = request.id;
+    if request.jsonrpc != JSONRPC_VERSION {
+        return RpcResponse::err(id, INVALID_REQUEST, "jsonrpc must be \"2.0\"");
+    }
+
+    // Listings return at most `params.limit` entries.
+    let limit = match request.method.as_str() {
+        "getPeers" | "getMempool" | "getValidators" => match list_limit(&request.params) {
+            Some(limit) => limit,
+            None => return RpcResponse::err(id, INVALID_PARAMS, "expected params.limit as a positive integer"),
+        },
+        _ => 0,
+    };
+
+    match request.method.as_str() {
+        "getPeers" => RpcResponse::ok(id, json!(target.peers().into_iter().take(limit).collect::<Vec<_>>())),
+        "getBlockHeight" => RpcResponse::ok(id, json!(target.block_height())),
+        "getBlock" => {
+            let Some(height) = request.params.get("height").and_then(Value::as_u64) else {
+                return RpcResponse::err(id, INVALID_PARAMS, "expected params.height as an integer");
+            };
+            match target.block(height) {
+                Ok(block) => RpcResponse::ok(id, json!(block.as_ref().map(BlockView::from))),
+                Err(e @ NodeError::NoStorage) => RpcResponse::err(id, NODE_UNAVAILABLE, e.to_string()),
+                Err(e) => RpcResponse::err(id, INTERNAL_ERROR, e.to_string()),
+            }
+        }
+        "getMempool" => {
+            let pending: Vec<String> =
+                target.pending_transactions().iter().take(limit).map(Signature::to_string).collect();
+            RpcResponse::ok(id, json!(pending))
+        }
+        "getValidators" => {
+            // Largest stake first, so a short listing keeps the validators
+            // that matter most for consensus.
+            let mut validators = target.validators();
+            validators.sort_by(|a, b| b.stake.cmp(&a.stake).then(a.pubkey.cmp(&b.pubkey)));
+            let views: Vec<ValidatorView> = validators
+                .into_iter()
+                .take(limit)
+                .map(|v| ValidatorView { pubkey: v.pubkey.to_string(), stake: v.stake, locked_until: v.locked_until })
+                .collect();
+            RpcResponse::ok(id, json!(views))
+        }
+        "submitTransaction" => {
+            let transaction = request
+                .params
+                .get("transaction")
+                .and_then(Value::as_str)
+                .and_then(|encoded| hex::decode(encoded).ok())
+                .and_then(|bytes| bincode::deserialize::<Transaction>(&bytes).ok())
+                .filter(|transaction| !transaction.signatures.is_empty() && transaction.verify().is_ok());
+            let Some(transaction) = transaction else {
+                return RpcResponse::err(id, INVALID_PARAMS, "expected params.transaction as a hex-encoded signed transaction");
+            };
+            let signature = transaction.signatures[0];
+            let queued = target.submit_transaction(transaction);
+            RpcResponse::ok(id, json!({ "signature": signature.to_string(), "queued": queued }))
+        }
+        "getStakeInfo" => {
+            let pubkey = match request.params.get("pubkey").and_then(Value::as_str) {
+                Some(pubkey) => match pubkey.parse::<Pubkey>() {
+                    Ok(pubkey) => pubkey,
+                    Err(_) => return RpcResponse::err(id, INVALID_PARAMS, "expected params.pubkey as a base58 public key"),
+                },
+                None => target.pubkey(),
+            };
+            let validator = target.validators().into_iter().find(|v| v.pubkey == pubkey);
+            RpcResponse::ok(id, json!(StakeInfo {
+                pubkey: pubkey.to_string(),
+                stake_account: target
+                    .stake_program_id()
+                    .map(|program_id| find_stake_address(&program_id, &pubkey).0.to_string()),
+                is_validator: validator.is_some(),
+                stake: validator.as_ref().map_or(0, |v| v.stake),
+                locked_until: validator.as_ref().map_or(0, |v| v.locked_until),
+            }))
+        }
+        "getNodeVersion" => RpcResponse::ok(id, json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "protocol_version": PROTOCOL_VERSION,
+        })),
+        other => RpcResponse::err(id, METHOD_NOT_FOUND, format!("unknown method '{}'", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::config::NodeConfig;
+    use crate::node::peer::PeerInfo;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use parking_lot::Mutex;
+    use solana_sdk::{hash::Hash, signature::{Keypair, Signer}, system_transaction};
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    struct FakeNode {
+        keypair: Keypair,
+        program_id: Pubkey,
+        blocks: Vec<Block>,
+        mempool: Mutex<Vec<Transaction>>,
+        validators: Vec<Validator>,
+        activity: broadcast::Sender<Activity>,
+    }
+
+    impl FakeNode {
+        fn new() -> Arc<Self> {
+            let keypair = Keypair::new();
+            let genesis = Block::new(0, 0, Hash::default(), 1_700_000_000, Vec::new(), &keypair);
+            let tip = Block::new(1, 1, genesis.hash(), 1_700_000_001, vec![transfer()], &keypair);
+            let validators = vec![Validator { pubkey: keypair.pubkey(), stake: 42, locked_until: 100 }];
+            Arc::new(FakeNode {
+                keypair,
+                program_id: Pubkey::new_unique(),
+                blocks: vec![genesis, tip],
+                mempool: Mutex::new(Vec::new()),
+                validators,
+                activity: broadcast::channel(8).0,
+            })
+        }
+    }
+
+    impl RpcTarget for FakeNode {
+        fn peers(&self) -> Vec<PeerSnapshot> {
+            vec![PeerInfo::new(SocketAddr::from(([10, 0, 0, 1], 8001))).snapshot()]
+        }
+
+        fn block_height(&self) -> u64 {
+            self.blocks.len() as u64 - 1
+        }
+
+        fn block(&self, height: u64) -> Result<Option<Block>, NodeError> {
+            if self.blocks.is_empty() {
+                return Err(NodeError::NoStorage);
+            }
+            Ok(self.blocks.get(height as usize).cloned())
+        }
+
+        fn pending_transactions(&self) -> Vec<Signature> {
+            self.mempool.lock().iter().map(|transaction| transaction.signatures[0]).collect()
+        }
+
+        fn submit_transaction(&self, transaction: Transaction) -> bool {
+            self.mempool.lock().push(transaction);
+            true
+        }
+
+        fn validators(&self) -> Vec<Validator> {
+            self.validators.clone()
+        }
+
+        fn stake_program_id(&self) -> Option<Pubkey> {
+            Some(self.program_id)
+        }
+
+        fn pubkey(&self) -> Pubkey {
+            self.keypair.pubkey()
+        }
+
+        fn subscribe_activity(&self) -> broadcast::Receiver<Activity> {
+            self.activity.subscribe()
+        }
+    }
+
+    fn transfer() -> Transaction {
+        system_transaction::transfer(&Keypair::new(), &Pubkey::new_unique(), 1, Hash::default())
+    }
+
+    async fn call(app: &Router, method: &str, params: Value) -> RpcResponse {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let request = Request::post("/")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    async fn result(app: &Router, method: &str, params: Value) -> Value {
+        let response = call(app, method, params).await;
+        assert!(response.error.is_none(), "{:?}", response.error);
+        response.result.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chain_state_exposed() {
+        let node = FakeNode::new();
+        let app = router(node.clone());
+
+        assert_eq!(result(&app, "getBlockHeight", Value::Null).await, 1);
+        let block = result(&app, "getBlock", json!({ "height": 1 })).await;
+        assert_eq!(block["hash"], node.blocks[1].hash().to_string());
+        assert_eq!(block["parent_hash"], node.blocks[0].hash().to_string());
+        assert_eq!(block["transactions"][0], node.blocks[1].transactions[0].signatures[0].to_string());
+        assert_eq!(result(&app, "getBlock", json!({ "height": 9 })).await, Value::Null);
+        assert_eq!(call(&app, "getBlock", json!({ "height": "one" })).await.error.unwrap().code, INVALID_PARAMS);
+
+        assert_eq!(result(&app, "getPeers", Value::Null).await[0]["addr"], "10.0.0.1:8001");
+        assert_eq!(result(&app, "getNodeVersion", Value::Null).await["protocol_version"], PROTOCOL_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_block_without_storage_reported_as_unavailable() {
+        let mut node = FakeNode::new();
+        Arc::get_mut(&mut node).unwrap().blocks.clear();
+        let app = router(node);
+
+        let error = call(&app, "getBlock", json!({ "height": 0 })).await.error.unwrap();
+        assert_eq!((error.code, error.message.as_str()), (NODE_UNAVAILABLE, "No storage attached"));
+    }
+
+    #[tokio::test]
+    async fn test_submitted_transaction_lands_in_mempool() {
+        let node = FakeNode::new();
+        let app = router(node.clone());
+        let transaction = transfer();
+        let encoded = hex::encode(bincode::serialize(&transaction).unwrap());
+
+        let outcome = result(&app, "submitTransaction", json!({ "transaction": encoded })).await;
+        assert_eq!(outcome["signature"], transaction.signatures[0].to_string());
+        assert_eq!(result(&app, "getMempool", Value::Null).await, json!([transaction.signatures[0].to_string()]));
+        result(&app, "submitTransaction", json!({ "transaction": hex::encode(bincode::serialize(&transfer()).unwrap()) })).await;
+        assert_eq!(result(&app, "getMempool", json!({ "limit": 1 })).await.as_array().unwrap().len(), 1);
+        assert_eq!(call(&app, "getMempool", json!({ "limit": -1 })).await.error.unwrap().code, INVALID_PARAMS);
+
+        let mut forged = transaction.clone();
+        forged.signatures[0] = Signature::default();
+        let encoded = hex::encode(bincode::serialize(&forged).unwrap());
+        let response = call(&app, "submitTransaction", json!({ "transaction": encoded })).await;
+        assert_eq!(response.error.unwrap().code, INVALID_PARAMS);
+        assert_eq!(node.mempool.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stake_info_for_node_and_other_keys() {
+        let node = FakeNode::new();
+        let app = router(node.clone());
+
+        let own = result(&app, "getStakeInfo", Value::Null).await;
+        assert_eq!(own["is_validator"], true);
+        assert_eq!(own["stake"], 42);
+        assert_eq!(own["stake_account"], find_stake_address(&node.program_id, &node.pubkey()).0.to_string());
+
+        let stranger = Pubkey::new_unique();
+        let other = result(&app, "getStakeInfo", json!({ "pubkey": stranger.to_string() })).await;
+        assert_eq!((other["is_validator"].clone(), other["stake"].clone()), (json!(false), json!(0)));
+
+        let response = call(&app, "getStakeInfo", json!({ "pubkey": "not-a-key" })).await;
+        assert_eq!(response.error.unwrap().code, INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn test_validators_listed_by_stake() {
+        let mut node = FakeNode::new();
+        let small = Validator { pubkey: Pubkey::new_unique(), stake: 7, locked_until: 0 };
+        Arc::get_mut(&mut node).unwrap().validators.push(small);
+        let app = router(node.clone());
+
+        let listed = result(&app, "getValidators", Value::Null).await;
+        assert_eq!(listed[0]["pubkey"], node.pubkey().to_string());
+        assert_eq!(listed[1]["stake"], 7);
+        let top = result(&app, "getValidators", json!({ "limit": 1 })).await;
+        assert_eq!(top, json!([listed[0]]));
+    }
+
+    #[tokio::test]
+    async fn test_real_node_served() {
+        let node = Arc::new(Node::new(NodeConfig::default()).await.unwrap());
+        let app = router(node.clone());
+
+        assert_eq!(result(&app, "getBlockHeight", Value::Null).await, 0);
+        assert_eq!(call(&app, "getBlock", json!({ "height": 0 })).await.error.unwrap().code, NODE_UNAVAILABLE);
+        assert_eq!(result(&app, "getValidators", Value::Null).await, json!([]));
+
+        let transactions = [transfer(), transfer()];
+        for transaction in &transactions {
+            let encoded = hex::encode(bincode::serialize(transaction).unwrap());
+            assert_eq!(result(&app, "submitTransaction", json!({ "transaction": encoded })).await["queued"], true);
+        }
+        assert_eq!(node.pending_transactions().len(), 2);
+        let listed = result(&app, "getMempool", json!({ "limit": 1 })).await;
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+        assert!(node.is_transaction_pending(&listed[0].as_str().unwrap().parse().unwrap()));
+    }
+}
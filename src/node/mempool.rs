@@ -0,0 +1,447 @@
+use log::{debug, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use solana_sdk::compute_budget;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::Transaction;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::node::consensus::TimestampedTransaction;
+
+pub const DEFAULT_MEMPOOL_CAPACITY: usize = 10_000;
+pub const MEMPOOL_FILE: &str = "mempool.bin";
+
+#[derive(Error, Debug)]
+pub enum MempoolError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Encoding error: {0}")]
+    Encoding(#[from] bincode::Error),
+}
+
+/// On-disk form of a pending transaction. `Instant` has no meaning across
+/// restarts, so the queue time is stored as wall-clock milliseconds.
+#[derive(Serialize, Deserialize)]
+struct PersistedTransaction {
+    transaction: Transaction,
+    queued_at_ms: u64,
+}
+
+/// Pending transactions copied out of a `Mempool` for writing to disk.
+pub struct MempoolSnapshot {
+    entries: Vec<PersistedTransaction>,
+}
+
+impl MempoolSnapshot {
+    /// Writes the snapshot to `path`. The file is replaced atomically so a
+    /// crash mid-write keeps the previous snapshot.
+    pub fn save(&self, path: &Path) -> Result<usize, MempoolError> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, bincode::serialize(&self.entries)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(self.entries.len())
+    }
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+// `SetComputeUnitPrice` tag in the compute budget program's instruction
+// encoding, followed by the price as a little-endian u64.
+const SET_COMPUTE_UNIT_PRICE: u8 = 3;
+
+/// Priority fee a transaction offers, in micro-lamports per compute unit.
+/// Transactions that don't set a compute unit price offer zero.
+pub fn priority_fee(transaction: &Transaction) -> u64 {
+    let message = &transaction.message;
+    message
+        .instructions
+        .iter()
+        .filter(|ix| message.account_keys.get(ix.program_id_index as usize) == Some(&compute_budget::id()))
+        .find_map(|ix| match ix.data.split_first() {
+            Some((&SET_COMPUTE_UNIT_PRICE, price)) if price.len() >= 8 => {
+                Some(u64::from_le_bytes(price[..8].try_into().unwrap()))
+            }
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// Highest fee first, then arrival order.
+type Priority = (Reverse<u64>, u64);
+
+/// Pending transactions ordered by priority fee, with a signature index so
+/// membership checks don't scan the queue.
+#[derive(Debug)]
+pub struct Mempool {
+    queue: BTreeMap<Priority, TimestampedTransaction>,
+    index: HashMap<Signature, Priority>,
+    capacity: usize,
+    next_seq: u64,
+}
+
+fn signature_of(transaction: &TimestampedTransaction) -> Signature {
+    transaction.transaction.signatures.first().copied().unwrap_or_default()
+}
+
+impl Mempool {
+    pub fn new(capacity: usize) -> Self {
+        Mempool {
+            queue: BTreeMap::new(),
+            index: HashMap::new(),
+            capacity: capacity.max(1),
+            next_seq: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn fill_ratio(&self) -> f64 {
+        self.queue.len() as f64 / self.capacity as f64
+    }
+
+    pub fn contains(&self, signature: &Signature) -> bool {
+        self.index.contains_key(signature)
+    }
+
+    /// Signatures of the pending transactions, highest fee first.
+    pub fn signatures(&self) -> Vec<Signature> {
+        self.queue.values().map(signature_of).collect()
+    }
+
+    /// Queues `transaction` unless it is already pending. When full, the
+    /// oldest of the lowest-fee transactions is evicted to make room, and a
+    /// transaction paying less than all of them is refused.
+    pub fn add(&mut self, transaction: TimestampedTransaction) -> bool {
+        let signature = signature_of(&transaction);
+        if self.index.contains_key(&signature) {
+            return false;
+        }
+
+        let fee = priority_fee(&transaction.transaction);
+        if self.queue.len() >= self.capacity {
+            let Some(&(Reverse(lowest), _)) = self.queue.keys().next_back() else {
+                return false;
+            };
+            if fee < lowest {
+                debug!("Mempool full, refusing {} paying below {}", signature, lowest);
+                return false;
+            }
+            let victim = self.queue.range((Reverse(lowest), 0)..).next().map(|(key, _)| *key);
+            if let Some(evicted) = victim.and_then(|key| self.queue.remove(&key)) {
+                debug!("Mempool full, evicting {}", signature_of(&evicted));
+                self.index.remove(&signature_of(&evicted));
+            }
+        }
+
+        let key = (Reverse(fee), self.next_seq);
+        self.next_seq += 1;
+        self.index.insert(signature, key);
+        self.queue.insert(key, transaction);
+        true
+    }
+
+    /// Removes and returns up to `max` transactions, highest fee first and
+    /// oldest first among equal fees.
+    pub fn take(&mut self, max: usize) -> Vec<TimestampedTransaction> {
+        let mut batch = Vec::with_capacity(max.min(self.queue.len()));
+        while batch.len() < max {
+            let Some((_, transaction)) = self.queue.pop_first() else {
+                break;
+            };
+            self.index.remove(&signature_of(&transaction));
+            batch.push(transaction);
+        }
+        batch
+    }
+
+    /// Drops the pending copies of `transactions`, as once a block carrying
+    /// them has been committed. Returns how many were pending.
+    pub fn remove_committed(&mut self, transactions: &[Transaction]) -> usize {
+        let mut removed = 0;
+        for transaction in transactions {
+            let Some(signature) = transaction.signatures.first() else {
+                continue;
+            };
+            if let Some(key) = self.index.remove(signature) {
+                self.queue.remove(&key);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Copies the pending transactions in priority order, so they can be
+    /// written out after the lock on the pool is released.
+    pub fn snapshot(&self, now: SystemTime) -> MempoolSnapshot {
+        let now_ms = unix_ms(now);
+        MempoolSnapshot {
+            entries: self
+                .queue
+                .values()
+                .map(|pending| PersistedTransaction {
+                    transaction: pending.transaction.clone(),
+                    queued_at_ms: now_ms.saturating_sub(pending.timestamp.elapsed().as_millis() as u64),
+                })
+                .collect(),
+        }
+    }
+
+    /// Queues the transactions saved at `path`, dropping entries older than
+    /// `max_age` or whose signatures no longer verify. A missing file
+    /// restores nothing. Returns how many were queued.
+    pub fn restore(&mut self, path: &Path, max_age: Duration, now: SystemTime) -> Result<usize, MempoolError> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let persisted: Vec<PersistedTransaction> = bincode::deserialize(&bytes)?;
+        let now_ms = unix_ms(now);
+        let loaded_at = Instant::now();
+        let total = persisted.len();
+        let mut queued = 0;
+
+        for entry in persisted {
+            let age = Duration::from_millis(now_ms.saturating_sub(entry.queued_at_ms));
+            if age >= max_age {
+                continue;
+            }
+            let restored = TimestampedTransaction {
+                transaction: entry.transaction,
+                timestamp: loaded_at.checked_sub(age).unwrap_or(loaded_at),
+                sent_at_ms: entry.queued_at_ms as i64,
+            };
+            if !restored.verify_signature() {
+                warn!("Dropping persisted transaction {} with an invalid signature", signature_of(&restored));
+                continue;
+            }
+            if self.add(restored) {
+                queued += 1;
+            }
+        }
+
+        debug!("Restored {} of {} persisted transactions", queued, total);
+        Ok(queued)
+    }
+
+    /// Drops transactions that have waited longer than `max_age`.
+    pub fn evict_expired(&mut self, max_age: Duration, now: Instant) -> usize {
+        let before = self.queue.len();
+        let index = &mut self.index;
+        self.queue.retain(|_, transaction| {
+            let fresh = now.saturating_duration_since(transaction.timestamp) < max_age;
+            if !fresh {
+                index.remove(&signature_of(transaction));
+            }
+            fresh
+        });
+        before - self.queue.len()
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool::new(DEFAULT_MEMPOOL_CAPACITY)
+    }
+}
+
+/// The peers asked to slow transaction forwarding once the mempool filled
+/// to the `high` watermark, held until it drains to `low`.
+#[derive(Debug)]
+pub struct Backpressure {
+    high: f64,
+    low: f64,
+    slowed: Mutex<HashSet<Pubkey>>,
+}
+
+impl Backpressure {
+    pub fn new(high: f64, low: f64) -> Self {
+        Backpressure { high, low, slowed: Mutex::new(HashSet::new()) }
+    }
+
+    /// Whether `peer`, which just forwarded a transaction into a mempool
+    /// now `fill` full, should be told to slow down. Each peer is told once
+    /// until released.
+    pub fn slow(&self, peer: Pubkey, fill: f64) -> bool {
+        fill >= self.high && self.slowed.lock().insert(peer)
+    }
+
+    /// The peers to tell to resume, once `fill` is down to the low watermark.
+    pub fn release(&self, fill: f64) -> Vec<Pubkey> {
+        if fill > self.low {
+            return Vec::new();
+        }
+        self.slowed.lock().drain().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::hash::Hash;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::compute_budget::ComputeBudgetInstruction;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::{system_instruction, system_transaction};
+
+    fn transfer() -> TimestampedTransaction {
+        TimestampedTransaction::new(system_transaction::transfer(
+            &Keypair::new(),
+            &Pubkey::new_unique(),
+            1_000,
+            Hash::default(),
+        ))
+    }
+
+    #[test]
+    fn test_contains_tracks_capacity_eviction() {
+        let mut mempool = Mempool::new(2);
+        let txs: Vec<TimestampedTransaction> = (0..3).map(|_| transfer()).collect();
+        let sigs: Vec<Signature> = txs.iter().map(signature_of).collect();
+
+        for tx in txs.iter().cloned() {
+            assert!(mempool.add(tx));
+        }
+
+        assert!(!mempool.contains(&sigs[0]));
+        assert!(mempool.contains(&sigs[1]));
+        assert!(mempool.contains(&sigs[2]));
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn test_contains_after_take_and_expiry() {
+        let mut mempool = Mempool::new(16);
+        let now = Instant::now();
+        let mut txs: Vec<TimestampedTransaction> = (0..5).map(|_| transfer()).collect();
+        txs[4].timestamp = now - Duration::from_secs(60);
+        let sigs: Vec<Signature> = txs.iter().map(signature_of).collect();
+        for tx in txs {
+            mempool.add(tx);
+        }
+
+        let taken = mempool.take(2);
+        assert_eq!(taken.iter().map(signature_of).collect::<Vec<_>>(), sigs[..2]);
+        assert!(!mempool.contains(&sigs[0]) && !mempool.contains(&sigs[1]));
+
+        assert_eq!(mempool.evict_expired(Duration::from_secs(30), now), 1);
+        assert!(!mempool.contains(&sigs[4]));
+        assert!(mempool.contains(&sigs[2]) && mempool.contains(&sigs[3]));
+        assert_eq!(mempool.len(), 2);
+
+        let committed: Vec<Transaction> = taken.into_iter().map(|tx| tx.transaction).collect();
+        assert_eq!(mempool.remove_committed(&committed), 0);
+        let pending = mempool.take(1).remove(0);
+        mempool.add(pending.clone());
+        assert_eq!(mempool.remove_committed(&[pending.transaction]), 1);
+        assert!(!mempool.contains(&signature_of(&pending)));
+        assert_eq!(mempool.len(), 1);
+    }
+
+    fn paying(fee: u64) -> TimestampedTransaction {
+        let payer = Keypair::new();
+        let instructions = [
+            ComputeBudgetInstruction::set_compute_unit_price(fee),
+            system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1_000),
+        ];
+        TimestampedTransaction::new(Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&payer.pubkey()),
+            &[&payer],
+            Hash::default(),
+        ))
+    }
+
+    #[test]
+    fn test_take_orders_by_fee_then_arrival() {
+        let mut mempool = Mempool::new(16);
+        let txs = [paying(5), transfer(), paying(50), paying(5)];
+        let sigs: Vec<Signature> = txs.iter().map(signature_of).collect();
+        for tx in txs {
+            assert!(mempool.add(tx));
+        }
+
+        let taken: Vec<Signature> = mempool.take(16).iter().map(signature_of).collect();
+        assert_eq!(taken, vec![sigs[2], sigs[0], sigs[3], sigs[1]]);
+        assert_eq!(priority_fee(&paying(7).transaction), 7);
+    }
+
+    #[test]
+    fn test_full_mempool_evicts_cheapest_and_refuses_cheaper() {
+        let mut mempool = Mempool::new(2);
+        let cheap = paying(1);
+        let rich = paying(100);
+        assert!(mempool.add(cheap.clone()));
+        assert!(mempool.add(rich.clone()));
+
+        assert!(!mempool.add(transfer()));
+        let mid = paying(10);
+        assert!(mempool.add(mid.clone()));
+
+        assert!(!mempool.contains(&signature_of(&cheap)));
+        assert!(mempool.contains(&signature_of(&rich)) && mempool.contains(&signature_of(&mid)));
+    }
+
+    #[test]
+    fn test_duplicate_signature_rejected() {
+        let mut mempool = Mempool::new(4);
+        let tx = transfer();
+        assert!(mempool.add(tx.clone()));
+        assert!(!mempool.add(tx));
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_and_restore_skip_expired_and_tampered() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(MEMPOOL_FILE);
+        let max_age = Duration::from_secs(60);
+
+        let mut mempool = Mempool::new(16);
+        let fresh = transfer();
+        let mut expired = transfer();
+        expired.timestamp = Instant::now() - Duration::from_secs(120);
+        let mut tampered = transfer();
+        tampered.transaction.message.recent_blockhash = Hash::new_unique();
+        for tx in [fresh.clone(), expired.clone(), tampered.clone()] {
+            mempool.add(tx);
+        }
+
+        assert_eq!(mempool.snapshot(SystemTime::now()).save(&path).unwrap(), 3);
+        let mut restored = Mempool::new(16);
+        assert_eq!(restored.restore(&path, max_age, SystemTime::now()).unwrap(), 1);
+
+        assert_eq!(restored.len(), 1);
+        assert!(restored.contains(&signature_of(&fresh)));
+        assert!(!restored.contains(&signature_of(&expired)));
+        assert!(!restored.contains(&signature_of(&tampered)));
+    }
+
+    #[test]
+    fn test_restore_without_snapshot_queues_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mempool = Mempool::new(16);
+        let restored = mempool.restore(&dir.path().join(MEMPOOL_FILE), Duration::from_secs(60), SystemTime::now());
+        assert_eq!(restored.unwrap(), 0);
+        assert!(mempool.is_empty());
+    }
+}
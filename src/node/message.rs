@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::node::config::TransportKind;
+use crate::node::consensus::CertifiedHeader;
+use crate::node::inference::{AcceptedReceipt, InferenceDispute, InferenceOutput};
+use crate::node::snapshot::SnapshotManifest;
+use crate::node::training::GradientShard;
+
+/// Same as the LLM's default sampling seed.
+pub const DEFAULT_GENERATION_SEED: u64 = 299792458;
+/// Same as the LLM's default sampling temperature.
+pub const DEFAULT_GENERATION_TEMPERATURE: f32 = 0.7;
+
+/// What to do with a prompt that leaves no room for the requested output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Truncation {
+    /// Keep the prompt whole; only prompts longer than the context window
+    /// are rejected and the output budget shrinks to what is left.
+    #[default]
+    #[serde(alias = "reject")]
+    Clamp,
+    /// Drop tokens from the start, keeping the most recent text and the
+    /// leading BOS token.
+    Head,
+    /// Drop tokens from the end.
+    Tail,
+    /// Drop tokens from the middle, keeping both ends.
+    Middle,
+}
+
+/// Sampling options carried by a generation request, mirroring the LLM's
+/// `SamplingParams`. The same params and seed reproduce the same output on
+/// the same model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerateParams {
+    pub temperature: f32,
+    pub top_k: Option<usize>,
+    pub top_p: Option<f64>,
+    pub repetition_penalty: f32,
+    /// Generation ends before any of these; the match is not returned.
+    pub stop: Vec<String>,
+    pub seed: u64,
+    /// How the executor fits an over-long prompt, which changes the output.
+    pub truncation: Truncation,
+}
+
+impl Default for GenerateParams {
+    fn default() -> Self {
+        GenerateParams {
+            temperature: DEFAULT_GENERATION_TEMPERATURE,
+            top_k: None,
+            top_p: None,
+            repetition_penalty: 1.0,
+            stop: Vec::new(),
+            seed: DEFAULT_GENERATION_SEED,
+            truncation: Truncation::default(),
+        }
+    }
+}
+
+// Compared bitwise so `Message` can stay `Eq`.
+impl PartialEq for GenerateParams {
+    fn eq(&self, other: &Self) -> bool {
+        self.temperature.to_bits() == other.temperature.to_bits()
+            && self.top_k == other.top_k
+            && self.top_p.map(f64::to_bits) == other.top_p.map(f64::to_bits)
+            && self.repetition_penalty.to_bits() == other.repetition_penalty.to_bits()
+            && self.stop == other.stop
+            && self.seed == other.seed
+            && self.truncation == other.truncation
+    }
+}
+
+impl Eq for GenerateParams {}
+
+/// A peer as shared in discovery gossip: its node ID and listening address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub node_id: String,
+    pub addr: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Message {
+    Ping { nonce: u64 },
+    Pong { nonce: u64, timestamp_ms: i64 },
+    Ack { seq: u64 },
+    /// Everything the sender broadcast up to `seq` was sent before this.
+    /// Answered with an `Ack`, so after a reconnect only later messages
+    /// are replayed.
+    Checkpoint { seq: u64 },
+    /// `inference_capacity` is how many inference jobs the sender runs at
+    /// once; zero if it runs none. `observed_addr` is where the sender sees
+    /// the receiver connecting from, `external_addr` where the sender can be
+    /// dialed from outside its NAT, if it knows.
+    Handshake {
+        protocol_version: u16,
+        node_id: String,
+        listen_port: u16,
+        challenge: u64,
+        transport: TransportKind,
+        inference_capacity: u32,
+        observed_addr: Option<SocketAddr>,
+        external_addr: Option<SocketAddr>,
+    },
+    Auth { pubkey: [u8; 32], signature: Vec<u8> },
+    /// A bincode-encoded transaction gossiped between peers. `sent_at_ms`
+    /// is the wall clock of the node it was submitted to.
+    NewTransaction { transaction: Vec<u8>, sent_at_ms: i64 },
+    Block { slot: u64, payload: Vec<u8> },
+    /// A bincode-encoded `ViewChange` vote to rotate past a stalled leader.
+    ViewChange { payload: Vec<u8> },
+    /// A bincode-encoded `BlockVote` for a proposed block.
+    BlockVote { payload: Vec<u8> },
+    /// Asks the receiver to slow (`active`) or resume transaction forwarding.
+    Backpressure { active: bool },
+    GenerateRequest { request_id: u64, prompt: String, max_tokens: u32, params: GenerateParams },
+    GenerateResponse { request_id: u64, result: Result<String, String> },
+    /// A job for the inference market. Without an `executor` it is a
+    /// client's request on the request port for the receiver to route to a
+    /// staked peer; with one, it is sent only to that node, which runs it
+    /// for staked requesters.
+    InferenceRequest {
+        job_id: u64,
+        executor: Option<[u8; 32]>,
+        prompt: String,
+        max_tokens: u32,
+        params: GenerateParams,
+    },
+    /// The answer to an `InferenceRequest`, signed by the node that ran it.
+    InferenceResult { job_id: u64, result: Result<InferenceOutput, String> },
+    /// A finished job, countersigned by the requester that checked it.
+    /// Gossiped so every node counts it towards the executor's rewards.
+    InferenceReceipt { accepted: AcceptedReceipt },
+    /// Evidence that two validators agreed on a job's output and a third
+    /// signed a different one for the same inputs. Gossiped so every node
+    /// can penalize the outvoted executor.
+    InferenceDispute { dispute: InferenceDispute },
+    /// Part of a trainer's gradients for a round of distributed training,
+    /// sent only to the other trainers and never relayed.
+    GradientShard { shard: GradientShard },
+    /// Announces that `pubkey` has staked in `stake_account` and should join
+    /// the validator set. Receivers check the account on-chain first.
+    ValidatorAnnounce { pubkey: [u8; 32], stake_account: [u8; 32] },
+    /// Asks a peer for the peers it is connected to.
+    GetPeers,
+    Peers { peers: Vec<PeerRecord> },
+    /// Asks peers for the manifest of their latest state snapshot.
+    GetSnapshotManifest,
+    /// `None` if the sender has no chain to share yet.
+    SnapshotManifest { manifest: Option<SnapshotManifest> },
+    /// Asks `server` for one chunk of its snapshot at `height`. Sent only
+    /// to its session, and ignored by any other receiver.
+    GetSnapshotChunk { server: [u8; 32], height: u64, index: u32 },
+    SnapshotChunk { height: u64, index: u32, data: Vec<u8> },
+    /// Asks `server` for the blocks it holds from `from_height` on. Sent
+    /// only to its session, and ignored by any other receiver.
+    GetBlocks { server: [u8; 32], from_height: u64 },
+    /// Bincode-encoded blocks in height order, empty past the sender's tip.
+    Blocks { blocks: Vec<Vec<u8>> },
+    /// Asks `server` for up to `limit` certified headers from `start_height`
+    /// on. Sent only to its session, and ignored by any other receiver.
+    GetHeaders { server: [u8; 32], start_height: u64, limit: u32 },
+    /// Certified headers in height order, and the height of the newest
+    /// block the sender holds a certificate for.
+    Headers { tip_height: u64, headers: Vec<CertifiedHeader> },
+    /// Last message on a connection the sender is closing on purpose.
+    Goodbye { reason: String },
+}
+
+impl Message {
+    /// Reply to `Ping { nonce }`, stamped with the local wall clock so the
+    /// sender can estimate clock offset.
+    pub fn pong(nonce: u64) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        Message::Pong { nonce, timestamp_ms }
+    }
+}
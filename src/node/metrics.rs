@@ -0,0 +1,142 @@
+use parking_lot::Mutex;
+use prometheus::{
+    Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const INFERENCE_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Prometheus metrics for one node, all named under `fractis_`. Counters
+/// and histograms are updated where the events happen; gauges describing
+/// current state are filled in by whoever renders them.
+pub struct NodeMetrics {
+    registry: Registry,
+    pub connected_peers: IntGauge,
+    pub known_peers: IntGauge,
+    pub connections_opened: IntCounter,
+    pub connections_closed: IntCounter,
+    pub mempool_depth: IntGauge,
+    pub consensus_round_seconds: Histogram,
+    pub inference_seconds: Histogram,
+    pub inference_requests: IntCounterVec,
+    pub inference_output_bytes: IntCounter,
+    pub rpc_failures: IntCounterVec,
+    last_block_at: Mutex<Option<Instant>>,
+}
+
+impl fmt::Debug for NodeMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeMetrics").finish_non_exhaustive()
+    }
+}
+
+impl Default for NodeMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NodeMetrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("fractis".to_string()), None).expect("valid metric prefix");
+        let metrics = NodeMetrics {
+            connected_peers: IntGauge::new("connected_peers", "Peers with an open session").unwrap(),
+            known_peers: IntGauge::new("known_peers", "Peers in the peer table, connected or not").unwrap(),
+            connections_opened: IntCounter::new("peer_connections_opened_total", "Peer sessions opened").unwrap(),
+            connections_closed: IntCounter::new("peer_connections_closed_total", "Peer sessions closed").unwrap(),
+            mempool_depth: IntGauge::new("mempool_depth", "Transactions waiting in the mempool").unwrap(),
+            consensus_round_seconds: Histogram::with_opts(HistogramOpts::new(
+                "consensus_round_seconds",
+                "Time between consecutive blocks applied to the local chain",
+            ))
+            .unwrap(),
+            inference_seconds: Histogram::with_opts(
+                HistogramOpts::new("inference_seconds", "Time to serve a peer's generation request")
+                    .buckets(INFERENCE_BUCKETS.to_vec()),
+            )
+            .unwrap(),
+            inference_requests: IntCounterVec::new(
+                Opts::new("inference_requests_total", "Generation requests served for peers"),
+                &["outcome"],
+            )
+            .unwrap(),
+            inference_output_bytes: IntCounter::new("inference_output_bytes_total", "Bytes of generated text returned to peers").unwrap(),
+            rpc_failures: IntCounterVec::new(
+                Opts::new("rpc_failures_total", "Failed calls to the Solana RPC endpoint"),
+                &["method"],
+            )
+            .unwrap(),
+            last_block_at: Mutex::new(None),
+            registry,
+        };
+
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.connected_peers.clone()),
+            Box::new(metrics.known_peers.clone()),
+            Box::new(metrics.connections_opened.clone()),
+            Box::new(metrics.connections_closed.clone()),
+            Box::new(metrics.mempool_depth.clone()),
+            Box::new(metrics.consensus_round_seconds.clone()),
+            Box::new(metrics.inference_seconds.clone()),
+            Box::new(metrics.inference_requests.clone()),
+            Box::new(metrics.inference_output_bytes.clone()),
+            Box::new(metrics.rpc_failures.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).expect("metric names are unique");
+        }
+        metrics
+    }
+
+    /// Records a block joining the local chain; the time since the previous
+    /// one is the round's latency.
+    pub fn record_block_applied(&self) {
+        let now = Instant::now();
+        if let Some(previous) = self.last_block_at.lock().replace(now) {
+            self.consensus_round_seconds.observe(now.duration_since(previous).as_secs_f64());
+        }
+    }
+
+    pub fn record_inference(&self, elapsed: Duration, result: &Result<String, String>) {
+        self.inference_seconds.observe(elapsed.as_secs_f64());
+        match result {
+            Ok(text) => {
+                self.inference_requests.with_label_values(&["ok"]).inc();
+                self.inference_output_bytes.inc_by(text.len() as u64);
+            }
+            Err(_) => self.inference_requests.with_label_values(&["error"]).inc(),
+        }
+    }
+
+    /// Everything registered, in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorded_events_exported() {
+        let metrics = NodeMetrics::new();
+        metrics.record_block_applied();
+        metrics.record_block_applied();
+        metrics.record_inference(Duration::from_millis(300), &Ok("hello".to_string()));
+        metrics.record_inference(Duration::from_millis(10), &Err("LLM is disabled".to_string()));
+        metrics.rpc_failures.with_label_values(&["getSlot"]).inc();
+        metrics.mempool_depth.set(7);
+
+        let text = metrics.encode();
+        assert!(text.contains("fractis_consensus_round_seconds_count 1"), "{}", text);
+        assert!(text.contains("fractis_inference_requests_total{outcome=\"ok\"} 1"));
+        assert!(text.contains("fractis_inference_requests_total{outcome=\"error\"} 1"));
+        assert!(text.contains("fractis_inference_output_bytes_total 5"));
+        assert!(text.contains("fractis_rpc_failures_total{method=\"getSlot\"} 1"));
+        assert!(text.contains("fractis_mempool_depth 7"));
+    }
+}
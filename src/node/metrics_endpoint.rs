@@ -0,0 +1,69 @@
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use log::info;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::node::config::MetricsConfig;
+use crate::node::network::Node;
+
+/// Anything that can render its metrics for a Prometheus scrape.
+pub trait MetricsTarget: Send + Sync {
+    fn render_metrics(&self) -> String;
+}
+
+impl MetricsTarget for Node {
+    fn render_metrics(&self) -> String {
+        Node::render_metrics(self)
+    }
+}
+
+pub fn router(target: Arc<dyn MetricsTarget>) -> Router {
+    Router::new().route("/metrics", get(handle_metrics)).with_state(target)
+}
+
+pub async fn serve(target: Arc<dyn MetricsTarget>, config: &MetricsConfig) -> std::io::Result<()> {
+    let listener = TcpListener::bind((config.host.as_str(), config.port)).await?;
+    info!("Metrics endpoint listening on {}", listener.local_addr()?);
+    axum::serve(listener, router(target)).await
+}
+
+async fn handle_metrics(State(target): State<Arc<dyn MetricsTarget>>) -> impl IntoResponse {
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], target.render_metrics())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::metrics::NodeMetrics;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    impl MetricsTarget for NodeMetrics {
+        fn render_metrics(&self) -> String {
+            self.encode()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics_served_as_prometheus_text() {
+        let metrics = Arc::new(NodeMetrics::new());
+        metrics.connected_peers.set(3);
+        let app = router(metrics);
+
+        let response = app
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/plain; version=0.0.4");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("fractis_connected_peers 3"));
+    }
+}
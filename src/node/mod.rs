@@ -0,0 +1,76 @@
+pub mod activity;
+pub mod auth;
+pub mod bloom;
+pub mod clock_sync;
+pub mod config;
+pub mod consensus;
+#[cfg(feature = "api")]
+pub mod control;
+pub mod dedup;
+pub mod dialer;
+pub mod drain;
+pub mod epoch;
+pub mod error;
+pub mod framing;
+pub mod generate;
+pub mod gossip;
+pub mod handshake;
+pub mod identity;
+pub mod inbound;
+pub mod inference;
+#[cfg(feature = "api")]
+pub mod json_rpc;
+pub mod mempool;
+pub mod message;
+pub mod metrics;
+#[cfg(feature = "api")]
+pub mod metrics_endpoint;
+pub mod nat;
+pub mod network;
+pub mod noise;
+pub mod outbox;
+pub mod peer;
+pub mod readiness;
+pub mod replay;
+pub mod reputation;
+pub mod rpc;
+pub mod session;
+pub mod shutdown;
+pub mod slot;
+pub mod snapshot;
+pub mod stake_check;
+pub mod storage;
+pub mod submit;
+#[cfg(feature = "api")]
+pub mod subscriptions;
+pub mod sync;
+pub mod throttle;
+pub mod training;
+pub mod transport;
+pub mod validator_registry;
+
+pub use activity::Activity;
+pub use config::{NodeConfig, ConfigError};
+pub use epoch::{EpochSummary, EpochTracker, Participation, RewardWeights};
+pub use consensus::ConsensusManager;
+pub use error::{NetworkError, NodeError};
+pub use generate::{GenerateError, GenerationService};
+pub use inference::{
+    AcceptedReceipt, InferenceCommitment, InferenceDispute, InferenceError, InferenceMarket, InferenceOutput,
+    InferenceReceipt,
+};
+pub use message::{GenerateParams, Message, PeerRecord};
+pub use network::{BootstrapDiff, ConfigReload, Node, NodeStats};
+pub use peer::{PeerInfo, PeerSnapshot};
+pub use readiness::{NodeEvent, ReadinessCondition};
+pub use mempool::Mempool;
+pub use replay::{Replay, ReplayBuffer};
+pub use reputation::{BanEntry, PeerEvent};
+pub use slot::SlotClock;
+pub use snapshot::{SnapshotError, SnapshotManifest, StateSync};
+pub use storage::{Storage, StorageError};
+pub use sync::{CatchUp, HeaderSource, SessionHeaderSource, SyncError, SyncTarget};
+pub use throttle::{SendLimit, Throttled};
+pub use training::{Gradient, GradientExchange, GradientShard, GradientTransport, TrainingError};
+pub use transport::{Connection, MemoryConnection, TcpTransport, Transport};
+pub use validator_registry::ValidatorRegistry;
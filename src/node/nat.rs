@@ -0,0 +1,352 @@
+use igd_next::aio::tokio::search_gateway;
+use igd_next::{PortMappingProtocol, SearchOptions};
+use log::debug;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout, Duration};
+
+use crate::node::config::{NatConfig, PortMapping};
+
+const NATPMP_PORT: u16 = 5351;
+const NATPMP_OP_EXTERNAL_ADDR: u8 = 0;
+const NATPMP_OP_MAP_TCP: u8 = 2;
+/// RFC 6886 starts at 250ms and doubles; four tries give up after ~4s.
+const NATPMP_FIRST_TIMEOUT: Duration = Duration::from_millis(250);
+const NATPMP_ATTEMPTS: u32 = 4;
+const UPNP_SEARCH_TIMEOUT: Duration = Duration::from_secs(3);
+const MAPPING_DESCRIPTION: &str = "fractis-node";
+/// Distinct peers that must agree before their view of our IP is trusted.
+const MIN_OBSERVERS: usize = 2;
+const MAX_OBSERVERS: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum NatError {
+    #[error("Port mapping is disabled")]
+    Disabled,
+    #[error("No default gateway found")]
+    NoGateway,
+    #[error("Gateway did not answer")]
+    Timeout,
+    #[error("NAT-PMP request failed with result code {0}")]
+    NatPmp(u16),
+    #[error("Malformed NAT-PMP response")]
+    Malformed,
+    #[error("UPnP error: {0}")]
+    Upnp(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A port opened on the router, valid for `lease` unless renewed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub method: PortMapping,
+    pub gateway: SocketAddr,
+    pub internal_port: u16,
+    pub external: SocketAddr,
+    pub lease: Duration,
+}
+
+/// Opens `port` on the gateway with the configured method, asking for the
+/// same port outside.
+pub async fn map_port(config: &NatConfig, port: u16) -> Result<Mapping, NatError> {
+    match config.mapping {
+        PortMapping::None => Err(NatError::Disabled),
+        PortMapping::Upnp => upnp_map(port, config.lease_secs).await,
+        PortMapping::NatPmp => natpmp_map(natpmp_gateway(config)?, port, config.lease_secs).await,
+        PortMapping::Auto => match upnp_map(port, config.lease_secs).await {
+            Ok(mapping) => Ok(mapping),
+            Err(e) => {
+                debug!("UPnP unavailable ({}), trying NAT-PMP", e);
+                natpmp_map(natpmp_gateway(config)?, port, config.lease_secs).await
+            }
+        },
+    }
+}
+
+/// Closes a mapping before its lease runs out.
+pub async fn release(mapping: &Mapping) -> Result<(), NatError> {
+    match mapping.method {
+        PortMapping::Upnp => {
+            let gateway = upnp_gateway().await?;
+            gateway
+                .remove_port(PortMappingProtocol::TCP, mapping.external.port())
+                .await
+                .map_err(|e| NatError::Upnp(e.to_string()))
+        }
+        _ => {
+            let request = natpmp_map_request(mapping.internal_port, 0, 0);
+            natpmp_request(mapping.gateway, &request, NATPMP_OP_MAP_TCP, 16).await.map(|_| ())
+        }
+    }
+}
+
+async fn upnp_gateway() -> Result<igd_next::aio::Gateway<igd_next::aio::tokio::Tokio>, NatError> {
+    let options = SearchOptions { timeout: Some(UPNP_SEARCH_TIMEOUT), ..Default::default() };
+    search_gateway(options).await.map_err(|e| NatError::Upnp(e.to_string()))
+}
+
+async fn upnp_map(port: u16, lease_secs: u32) -> Result<Mapping, NatError> {
+    let gateway = upnp_gateway().await?;
+    let local_ip = local_ip_towards(gateway.addr).await?;
+    let external_ip = gateway.get_external_ip().await.map_err(|e| NatError::Upnp(e.to_string()))?;
+    gateway
+        .add_port(PortMappingProtocol::TCP, port, SocketAddr::new(local_ip, port), lease_secs, MAPPING_DESCRIPTION)
+        .await
+        .map_err(|e| NatError::Upnp(e.to_string()))?;
+    Ok(Mapping {
+        method: PortMapping::Upnp,
+        gateway: gateway.addr,
+        internal_port: port,
+        external: SocketAddr::new(external_ip, port),
+        lease: Duration::from_secs(lease_secs as u64),
+    })
+}
+
+/// The local address the OS would use to reach `remote`, which is what the
+/// router sees as the mapping's internal client.
+async fn local_ip_towards(remote: SocketAddr) -> Result<IpAddr, NatError> {
+    let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)).await?;
+    socket.connect(remote).await?;
+    Ok(socket.local_addr()?.ip())
+}
+
+fn natpmp_gateway(config: &NatConfig) -> Result<SocketAddr, NatError> {
+    let ip = match config.gateway {
+        Some(ip) => ip,
+        None => std::fs::read_to_string("/proc/net/route")
+            .ok()
+            .and_then(|table| parse_default_gateway(&table))
+            .ok_or(NatError::NoGateway)?
+            .into(),
+    };
+    Ok(SocketAddr::new(ip, NATPMP_PORT))
+}
+
+/// The gateway of the default IPv4 route in a Linux `/proc/net/route`
+/// table, whose addresses are little-endian hex.
+fn parse_default_gateway(table: &str) -> Option<Ipv4Addr> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        (gateway != 0).then(|| Ipv4Addr::from(gateway.to_le_bytes()))
+    })
+}
+
+async fn natpmp_map(gateway: SocketAddr, port: u16, lease_secs: u32) -> Result<Mapping, NatError> {
+    let response = natpmp_request(gateway, &[0, NATPMP_OP_EXTERNAL_ADDR], NATPMP_OP_EXTERNAL_ADDR, 12).await?;
+    let external_ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+
+    let request = natpmp_map_request(port, port, lease_secs);
+    let response = natpmp_request(gateway, &request, NATPMP_OP_MAP_TCP, 16).await?;
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+    let lifetime = u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+    Ok(Mapping {
+        method: PortMapping::NatPmp,
+        gateway,
+        internal_port: port,
+        external: SocketAddr::new(external_ip.into(), external_port),
+        lease: Duration::from_secs(lifetime as u64),
+    })
+}
+
+/// A lifetime of zero deletes the mapping.
+fn natpmp_map_request(internal_port: u16, external_port: u16, lifetime_secs: u32) -> [u8; 12] {
+    let mut request = [0u8; 12];
+    request[1] = NATPMP_OP_MAP_TCP;
+    request[4..6].copy_from_slice(&internal_port.to_be_bytes());
+    request[6..8].copy_from_slice(&external_port.to_be_bytes());
+    request[8..12].copy_from_slice(&lifetime_secs.to_be_bytes());
+    request
+}
+
+/// Sends `request` until the gateway answers, doubling the wait each time,
+/// and returns a response checked to be at least `len` bytes of success.
+async fn natpmp_request(gateway: SocketAddr, request: &[u8], op: u8, len: usize) -> Result<Vec<u8>, NatError> {
+    let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)).await?;
+    socket.connect(gateway).await?;
+    let mut wait = NATPMP_FIRST_TIMEOUT;
+    let mut buf = [0u8; 16];
+    for _ in 0..NATPMP_ATTEMPTS {
+        socket.send(request).await?;
+        if let Ok(received) = timeout(wait, socket.recv(&mut buf)).await {
+            let response = &buf[..received?];
+            if response.len() < len || response[0] != 0 || response[1] != op | 0x80 {
+                return Err(NatError::Malformed);
+            }
+            return match u16::from_be_bytes([response[2], response[3]]) {
+                0 => Ok(response.to_vec()),
+                code => Err(NatError::NatPmp(code)),
+            };
+        }
+        wait *= 2;
+    }
+    Err(NatError::Timeout)
+}
+
+/// Addresses on the public internet, as opposed to private, loopback,
+/// link-local, carrier-grade NAT or documentation ranges.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            !(ip.is_loopback() || ip.is_unspecified() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+/// Where other nodes should dial a peer connected from `conn_addr`. The
+/// peer's own `advertised` address is taken when it names the IP the peer
+/// connected from, or when the peer is on a private network and can't be
+/// reached at that IP anyway; otherwise anyone could point gossip at a host
+/// they don't control.
+pub fn dial_back_addr(conn_addr: SocketAddr, listen_port: u16, advertised: Option<SocketAddr>) -> SocketAddr {
+    match advertised {
+        Some(advertised) if advertised.ip() == conn_addr.ip() || !is_public(conn_addr.ip()) => advertised,
+        _ => SocketAddr::new(conn_addr.ip(), listen_port),
+    }
+}
+
+/// This node's IP as peers report seeing it in their handshakes, one vote
+/// per peer key.
+#[derive(Debug, Default)]
+pub struct ObservedAddrs {
+    votes: VecDeque<(Pubkey, IpAddr)>,
+}
+
+impl ObservedAddrs {
+    pub fn record(&mut self, observer: Pubkey, ip: IpAddr) {
+        if !is_public(ip) {
+            return;
+        }
+        self.votes.retain(|(key, _)| *key != observer);
+        self.votes.push_back((observer, ip));
+        if self.votes.len() > MAX_OBSERVERS {
+            self.votes.pop_front();
+        }
+    }
+
+    /// The IP most peers see, once at least `MIN_OBSERVERS` agree on it and
+    /// they are a majority.
+    pub fn confirmed(&self) -> Option<IpAddr> {
+        let mut counts: HashMap<IpAddr, usize> = HashMap::new();
+        for (_, ip) in &self.votes {
+            *counts.entry(*ip).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .filter(|(_, count)| *count >= MIN_OBSERVERS && *count * 2 > self.votes.len())
+            .map(|(ip, _)| ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(last: u8) -> IpAddr {
+        IpAddr::from([8, 8, 4, last])
+    }
+
+    #[test]
+    fn test_external_ip_confirmed_by_majority_of_peers() {
+        let mut observed = ObservedAddrs::default();
+        let (a, b, c) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+        observed.record(a, public(1));
+        assert_eq!(observed.confirmed(), None);
+        observed.record(a, public(1));
+        assert_eq!(observed.confirmed(), None);
+        observed.record(b, IpAddr::from([192, 168, 1, 20]));
+        assert_eq!(observed.confirmed(), None);
+
+        observed.record(b, public(1));
+        assert_eq!(observed.confirmed(), Some(public(1)));
+        observed.record(c, public(2));
+        assert_eq!(observed.confirmed(), Some(public(1)));
+        observed.record(b, public(2));
+        assert_eq!(observed.confirmed(), Some(public(2)));
+    }
+
+    #[test]
+    fn test_dial_back_addr_trusts_advertised_address_only_from_its_own_ip() {
+        let conn = SocketAddr::new(public(1), 53211);
+        let mapped = SocketAddr::new(public(1), 18000);
+        let elsewhere = SocketAddr::new(public(9), 18000);
+
+        assert_eq!(dial_back_addr(conn, 8000, None), SocketAddr::new(public(1), 8000));
+        assert_eq!(dial_back_addr(conn, 8000, Some(mapped)), mapped);
+        assert_eq!(dial_back_addr(conn, 8000, Some(elsewhere)), SocketAddr::new(public(1), 8000));
+
+        let lan = SocketAddr::from(([10, 0, 0, 4], 53211));
+        assert_eq!(dial_back_addr(lan, 8000, Some(elsewhere)), elsewhere);
+    }
+
+    #[test]
+    fn test_default_gateway_parsed_from_route_table() {
+        let table = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                     eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\n\
+                     eth0\t00000000\t0101A8C0\t0003\t0\t0\t0\t00000000\n";
+        assert_eq!(parse_default_gateway(table), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(parse_default_gateway("Iface\tDestination\tGateway\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_natpmp_mapping_against_fake_gateway() {
+        let gateway = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let gateway_addr = gateway.local_addr().unwrap();
+        let answering = tokio::spawn(async move {
+            let mut buf = [0u8; 12];
+            for _ in 0..2 {
+                let (len, from) = gateway.recv_from(&mut buf).await.unwrap();
+                let mut response = vec![0, buf[1] | 0x80, 0, 0, 0, 0, 0, 1];
+                match buf[1] {
+                    NATPMP_OP_EXTERNAL_ADDR => response.extend_from_slice(&[8, 8, 4, 1]),
+                    _ => {
+                        assert_eq!(len, 12);
+                        response.extend_from_slice(&buf[4..6]);
+                        response.extend_from_slice(&18000u16.to_be_bytes());
+                        response.extend_from_slice(&buf[8..12]);
+                    }
+                }
+                gateway.send_to(&response, from).await.unwrap();
+            }
+        });
+
+        let mapping = natpmp_map(gateway_addr, 8000, 3600).await.unwrap();
+        answering.await.unwrap();
+        assert_eq!(mapping.external, SocketAddr::new(public(1), 18000));
+        assert_eq!(mapping.internal_port, 8000);
+        assert_eq!(mapping.lease, Duration::from_secs(3600));
+    }
+
+    #[tokio::test]
+    async fn test_natpmp_error_code_surfaced() {
+        let gateway = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let gateway_addr = gateway.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 12];
+            let (_, from) = gateway.recv_from(&mut buf).await.unwrap();
+            gateway.send_to(&[0, 128, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0], from).await.unwrap();
+        });
+
+        assert!(matches!(natpmp_map(gateway_addr, 8000, 3600).await, Err(NatError::NatPmp(2))));
+    }
+}
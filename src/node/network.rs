@@ -1,58 +1,679 @@
-use solana_client::rpc_client::RpcClient;
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
-    commitment_config::CommitmentConfig,
-    signature::{Keypair, Signer},
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
     transaction::Transaction,
 };
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{broadcast, mpsc};
-use tokio::time::{sleep, Duration, timeout};
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use tokio::sync::{broadcast, mpsc, watch, Semaphore};
+use tokio::task::{JoinError, JoinSet};
+use tokio::time::{interval_at, sleep, Duration, timeout};
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::FutureExt;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 use std::sync::Arc;
-use parking_lot::RwLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime};
+use parking_lot::{Mutex, RwLock};
 use log::{info, error, warn, debug};
+use serde::Serialize;
+
+use crate::client::StakeClient;
+use crate::node::activity::{Activity, ACTIVITY_CHANNEL_CAPACITY};
+use crate::node::auth::{self, OpenAdmission, PeerAuthenticator, StakeGate};
+use crate::node::bloom::SeenFilter;
+use crate::node::clock_sync::{ClockSkewMonitor, ClockStatus};
+use crate::node::config::{ConfigError, NodeConfig, PortMapping, TransportKind, DEFAULT_LOG_LEVEL};
+use crate::node::drain::{InFlight, WorkGuard};
+use crate::node::epoch::{self, EpochSummary, EpochTracker};
+use crate::node::error::{NetworkError, NodeError};
+use crate::node::framing::{read_message, write_message, FrameError};
+use crate::node::generate::{GenerateError, GenerationService};
+use crate::node::gossip::{KnownPeers, DEFAULT_KNOWN_PEERS_CAPACITY};
+use crate::node::handshake::{self, HandshakeError, HandshakeInfo};
+use crate::node::identity::load_or_create_keypair;
+use crate::node::inbound::{InboundLimiter, InboundRefusal};
+use crate::node::inference::{Candidate, InferenceDispute, InferenceError, InferenceMarket, InferenceOutput};
+use crate::node::dialer::{BackoffPolicy, Dialer};
+use crate::node::consensus::{
+    Block, BlockVote, CertifiedHeader, ChainError, ConsensusManager, QuorumCertificate, TimestampedTransaction,
+    Validator, ViewChange,
+};
+use crate::node::dedup::{self, RecentMessages};
+use crate::node::mempool::{Backpressure, Mempool, MempoolError, MEMPOOL_FILE};
+use crate::node::message::{GenerateParams, Message, PeerRecord};
+use crate::node::metrics::NodeMetrics;
+use crate::node::nat::{self, Mapping, ObservedAddrs};
+use crate::node::noise::{self, Secured};
+use crate::node::outbox::Outboxes;
+use crate::node::peer::{self, PeerInfo, PeerSnapshot};
+use crate::node::readiness::{NodeEvent, Readiness, ReadinessTracker};
+use crate::node::replay::{Replay, ReplayBuffer};
+use crate::node::reputation::{self, BanEntry, BanList, PeerEvent};
+use crate::node::rpc;
+use crate::node::session::{self, Announcement, ReceivedBlock, SessionContext};
+use crate::node::shutdown::{PhaseOutcome, ShutdownPhase, ShutdownSequence};
+use crate::node::slot::SlotClock;
+use crate::node::snapshot::{self, SnapshotState, StateSync, MAX_BLOCKS_PER_REQUEST};
+use crate::node::stake_check::{verify_stake_account, AccountFetcher, StakeCheckError};
+use crate::node::storage::{Storage, StorageError};
+use crate::node::submit::{submit_with_retry, RetryPolicy};
+use crate::node::sync::{CatchUp, HeaderSource, SessionHeaderSource, SyncError, SyncTarget};
+use crate::node::throttle::{SendLimit, Throttled};
+use crate::node::training::{Gradient, GradientExchange, GradientTransport, TrainingError};
+use crate::node::transport::Connection;
+use crate::node::validator_registry::ValidatorRegistry;
+use crate::program::stake::find_stake_address;
 
 const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
-const RECONNECT_DELAY: Duration = Duration::from_secs(5);
-const MAX_RECONNECT_ATTEMPTS: u32 = 3;
+/// How often bootstrap nodes are checked for a due redial.
+const REDIAL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const KEEPALIVE_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_DISCOVERY_DIALS: usize = 8;
+const MAX_BLOCK_TRANSACTIONS: usize = 1024;
+const PORT_MAPPING_RETRY: Duration = Duration::from_secs(300);
+const MIN_PORT_MAPPING_RENEWAL: Duration = Duration::from_secs(30);
+/// How often ended epochs are settled, and wall-clock epochs checked for
+/// their end.
+const EPOCH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Validator announcements sessions may queue before further ones are
+/// dropped, and how many are checked on chain at once.
+const ANNOUNCEMENT_QUEUE_CAPACITY: usize = 64;
+const MAX_PENDING_ANNOUNCEMENTS: usize = 8;
+/// Blocks sessions may queue for verification before further ones are
+/// dropped.
+const BLOCK_QUEUE_CAPACITY: usize = 64;
+/// Evictions sessions may fall behind on before rechecking the peer table.
+const EVICTION_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStats {
+    pub peer_count: usize,
+    pub connected_peers: usize,
+    pub avg_rtt_ms: Option<f64>,
+    pub min_rtt_ms: Option<f64>,
+    pub max_rtt_ms: Option<f64>,
+    pub self_connections_rejected: u64,
+    pub inbound_refused: u64,
+    pub external_addr: Option<SocketAddr>,
+}
 
 #[derive(Debug)]
 pub struct Node {
-    config: Arc<NodeConfig>,
-    keypair: Keypair,
-    rpc_client: RpcClient,
+    config: RwLock<Arc<NodeConfig>>,
+    keypair: Arc<Keypair>,
+    rpc_client: RwLock<Option<Arc<RpcClient>>>,
+    account_fetcher: RwLock<Option<Arc<dyn AccountFetcher>>>,
+    storage: RwLock<Option<Storage>>,
+    /// Shared with sessions, which check job requesters against it.
+    consensus: Arc<RwLock<ConsensusManager>>,
+    validator_registry: Option<ValidatorRegistry>,
+    model: Mutex<Option<Arc<dyn Any + Send + Sync>>>,
+    inference: Arc<InferenceMarket>,
+    /// Shared with sessions, which run peer generation requests under it.
+    generation_permits: Arc<Semaphore>,
+    training: Arc<GradientExchange>,
+    state_sync: Arc<StateSync>,
+    /// `None` without an `[epoch]` section.
+    epochs: Option<Mutex<EpochTracker>>,
+    request_ids: AtomicU64,
+    announcements: mpsc::Sender<Announcement>,
+    /// Taken by `start` while it runs.
+    announcement_queue: Mutex<Option<mpsc::Receiver<Announcement>>>,
+    blocks: mpsc::Sender<ReceivedBlock>,
+    /// Taken by `start` while it runs.
+    block_queue: Mutex<Option<mpsc::Receiver<ReceivedBlock>>>,
     peers: Arc<RwLock<HashMap<SocketAddr, PeerInfo>>>,
     tx: broadcast::Sender<Message>,
-    shutdown: mpsc::Sender<()>,
+    /// Queues for messages meant for one peer's session.
+    outboxes: Arc<Outboxes>,
+    activity: broadcast::Sender<Activity>,
+    /// Tells sessions which peers were disconnected.
+    evictions: broadcast::Sender<SocketAddr>,
+    metrics: Arc<NodeMetrics>,
+    shutdown: watch::Sender<bool>,
+    paused: watch::Sender<bool>,
+    in_flight: Arc<InFlight>,
+    tasks: Arc<Mutex<JoinSet<()>>>,
+    sessions: Arc<Mutex<JoinSet<()>>>,
+    stopped: watch::Sender<bool>,
+    ping_nonce: Arc<AtomicU64>,
+    replay: Arc<Mutex<ReplayBuffer>>,
+    mempool: Arc<Mutex<Mempool>>,
+    /// Shared with sessions, which ask peers to slow down as it fills.
+    backpressure: Arc<Backpressure>,
+    inbound: Mutex<InboundLimiter>,
+    inbound_refused: AtomicU64,
+    slot_clock: Arc<SlotClock>,
+    seen_announcements: Arc<Mutex<SeenFilter>>,
+    recent_messages: Arc<Mutex<RecentMessages>>,
+    known_peers: Arc<Mutex<KnownPeers>>,
+    clock_skew: Arc<Mutex<ClockSkewMonitor>>,
+    readiness: Arc<ReadinessTracker>,
+    local_peer: Arc<LocalPeer>,
+    bootstrap_peers: Arc<RwLock<HashMap<String, SocketAddr>>>,
+    dialer: Mutex<Dialer>,
+}
+
+/// What a connection handler needs to introduce and authenticate this node.
+struct LocalPeer {
+    node_id: String,
+    listen_port: u16,
+    keypair: Arc<Keypair>,
+    authenticator: Arc<dyn PeerAuthenticator>,
+    transport: TransportKind,
+    /// X25519 static key for Noise sessions. It is bound to `keypair` by the
+    /// auth signature over the handshake hash, so it needn't persist.
+    noise_key: Vec<u8>,
+    /// `max_connections`, which a config reload can change.
+    max_peers: AtomicUsize,
+    send_limit: Option<SendLimit>,
+    self_connections: AtomicU64,
+    bans: Arc<Mutex<BanList>>,
+    /// Advertised in the handshake: `max_batch_size` when the LLM is enabled.
+    inference_capacity: u32,
+    /// Advertised in the handshake: configured or mapped on the router.
+    external_addr: RwLock<Option<SocketAddr>>,
+    /// Held on the router until shutdown.
+    mapping: Mutex<Option<Mapping>>,
+    /// Our IP as peers report seeing it.
+    observed: Mutex<ObservedAddrs>,
+}
+
+impl std::fmt::Debug for LocalPeer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalPeer")
+            .field("node_id", &self.node_id)
+            .field("listen_port", &self.listen_port)
+            .field("pubkey", &self.keypair.pubkey())
+            .field("transport", &self.transport)
+            .field("max_peers", &self.max_peers)
+            .field("send_limit", &self.send_limit)
+            .field("self_connections", &self.self_connections)
+            .field("bans", &self.bans)
+            .field("inference_capacity", &self.inference_capacity)
+            .field("external_addr", &self.external_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What a config reload changed on the running node.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigReload {
+    pub bootstrap: BootstrapDiff,
+    /// Changed fields outside `RELOADABLE_FIELDS`, left as they were until
+    /// the node restarts.
+    pub restart_required: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootstrapDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl BootstrapDiff {
+    pub fn between(old: &[String], new: &[String]) -> Self {
+        BootstrapDiff {
+            added: new.iter().filter(|n| !old.contains(n)).cloned().collect(),
+            removed: old.iter().filter(|o| !new.contains(o)).cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
 }
 
 impl Node {
-    pub async fn new(config: NodeConfig) -> Result<Self, Box<dyn std::error::Error>> {
-        let keypair = Keypair::new();
-        let rpc_client = RpcClient::new_with_commitment(
-            "https://api.mainnet-beta.solana.com".to_string(),
-            CommitmentConfig::confirmed(),
-        );
+    pub async fn new(config: NodeConfig) -> Result<Self, NodeError> {
+        let keypair = match &config.keypair_path {
+            Some(path) => Arc::new(load_or_create_keypair(Path::new(path))?),
+            None => {
+                warn!("No keypair_path configured, using an ephemeral identity that changes on restart");
+                Arc::new(Keypair::new())
+            }
+        };
+        info!("Node identity {}", keypair.pubkey());
+        let metrics = Arc::new(NodeMetrics::new());
+        let rpc_client = Arc::new(rpc::build_rpc_client(
+            config.rpc_endpoint(),
+            config.commitment,
+            Duration::from_millis(config.rpc_timeout_ms),
+            config.rpc_max_response_bytes,
+            Some(metrics.rpc_failures.clone()),
+        ));
+
+        let authenticator: Arc<dyn PeerAuthenticator> = if config.stake_gated_peers {
+            let program_id: Pubkey = config.stake_program_id
+                .as_deref()
+                .ok_or_else(|| ConfigError::Conflict("stake_gated_peers requires stake_program_id".to_string()))?
+                .parse()?;
+            Arc::new(
+                StakeGate::new(rpc_client.clone(), program_id, config.min_stake)
+                    .with_cache_ttl(Duration::from_secs(config.peer_admission_cache_secs)),
+            )
+        } else {
+            Arc::new(OpenAdmission)
+        };
+        let local_peer = LocalPeer {
+            node_id: config.node_id.clone(),
+            listen_port: config.port,
+            keypair: Arc::clone(&keypair),
+            authenticator,
+            transport: config.transport,
+            noise_key: noise::generate_static_key()?,
+            max_peers: AtomicUsize::new(config.max_connections as usize),
+            send_limit: config.peer_send_limit(),
+            self_connections: AtomicU64::new(0),
+            bans: Arc::new(Mutex::new(BanList::new())),
+            inference_capacity: config.llm.as_ref().filter(|llm| llm.enabled).map_or(0, |llm| llm.max_batch_size as u32),
+            external_addr: RwLock::new(config.nat.as_ref().and_then(|nat| nat.external_addr)),
+            mapping: Mutex::new(None),
+            observed: Mutex::new(ObservedAddrs::default()),
+        };
 
         let (tx, _) = broadcast::channel(100);
-        let (shutdown_tx, _) = mpsc::channel(1);
+        let (activity, _) = broadcast::channel(ACTIVITY_CHANNEL_CAPACITY);
+        let (evictions, _) = broadcast::channel(EVICTION_CHANNEL_CAPACITY);
+        let (shutdown_tx, _) = watch::channel(false);
+        let (paused_tx, _) = watch::channel(false);
+        let (stopped_tx, _) = watch::channel(false);
+        let (announcements, announcement_queue) = mpsc::channel(ANNOUNCEMENT_QUEUE_CAPACITY);
+        let (blocks, block_queue) = mpsc::channel(BLOCK_QUEUE_CAPACITY);
+        let consensus = ConsensusManager::new(Duration::from_millis(config.consensus_timeout))
+            .with_max_transaction_bytes(config.max_transaction_bytes)
+            .with_future_tolerance(Duration::from_millis(config.timestamp_future_tolerance_ms))
+            .with_min_validator_lock(Duration::from_secs(config.min_validator_lock_secs))
+            .with_proposer_fallback_timeout(Duration::from_millis(config.proposer_fallback_timeout_ms));
+        let validator_registry = config.stake_program_id
+            .as_deref()
+            .map(str::parse::<Pubkey>)
+            .transpose()?
+            .map(|program_id| ValidatorRegistry::new(program_id, config.min_stake, config.epoch_slots));
+        let require_model = config.llm.as_ref().map_or(false, |llm| llm.enabled);
+        let readiness = ReadinessTracker::new(Readiness::new(config.min_ready_peers, require_model));
+        let replay = ReplayBuffer::new(config.broadcast_replay_window);
+        let slot_clock = SlotClock::new(Duration::from_millis(config.slot_duration_ms));
+        let seen_announcements = SeenFilter::new(config.gossip_seen_capacity, config.gossip_seen_fp_rate);
+        let recent_messages = RecentMessages::new(
+            config.gossip_dedup_capacity,
+            Duration::from_secs(config.gossip_dedup_ttl_secs),
+        );
+        let clock_skew = ClockSkewMonitor::new(
+            Duration::from_millis(config.max_clock_offset_ms),
+            config.abstain_on_clock_skew,
+        );
+        let known_peers = KnownPeers::new(&config.node_id, DEFAULT_KNOWN_PEERS_CAPACITY);
+        let inbound = InboundLimiter::new(config.inbound_limits());
+        let inference = InferenceMarket::new(
+            Arc::clone(&keypair),
+            config.llm.as_ref().map_or(usize::MAX, |llm| llm.max_tokens),
+        )
+        .with_capacity(local_peer.inference_capacity)
+        .with_verification_rate(config.inference_verification_rate);
+        let state_sync = StateSync::new(keypair.pubkey(), config.snapshot_interval_blocks)
+            .with_timeout(Duration::from_secs(config.state_sync_timeout_secs));
+        let epochs = config.epoch.as_ref().map(|epoch| Mutex::new(EpochTracker::new(epoch.length)));
         
         Ok(Node {
-            config: Arc::new(config),
+            config: RwLock::new(Arc::new(config)),
             keypair,
-            rpc_client,
+            rpc_client: RwLock::new(Some(rpc_client)),
+            account_fetcher: RwLock::new(None),
+            storage: RwLock::new(None),
+            consensus: Arc::new(RwLock::new(consensus)),
+            validator_registry,
+            model: Mutex::new(None),
+            inference: Arc::new(inference),
+            generation_permits: Arc::new(Semaphore::new(local_peer.inference_capacity as usize)),
+            training: Arc::new(GradientExchange::new(Arc::clone(&keypair))),
+            state_sync: Arc::new(state_sync),
+            epochs,
+            request_ids: AtomicU64::new(1),
+            announcements,
+            announcement_queue: Mutex::new(Some(announcement_queue)),
+            blocks,
+            block_queue: Mutex::new(Some(block_queue)),
             peers: Arc::new(RwLock::new(HashMap::new())),
             tx,
+            outboxes: Arc::new(Outboxes::new()),
+            activity,
+            evictions,
+            metrics,
             shutdown: shutdown_tx,
+            paused: paused_tx,
+            in_flight: InFlight::new(),
+            tasks: Arc::new(Mutex::new(JoinSet::new())),
+            sessions: Arc::new(Mutex::new(JoinSet::new())),
+            stopped: stopped_tx,
+            ping_nonce: Arc::new(AtomicU64::new(0)),
+            replay: Arc::new(Mutex::new(replay)),
+            mempool: Arc::new(Mutex::new(Mempool::new(config.mempool_capacity))),
+            backpressure: Arc::new(Backpressure::new(config.mempool_backpressure_high, config.mempool_backpressure_low)),
+            inbound: Mutex::new(inbound),
+            inbound_refused: AtomicU64::new(0),
+            slot_clock: Arc::new(slot_clock),
+            seen_announcements: Arc::new(Mutex::new(seen_announcements)),
+            recent_messages: Arc::new(Mutex::new(recent_messages)),
+            known_peers: Arc::new(Mutex::new(known_peers)),
+            clock_skew: Arc::new(Mutex::new(clock_skew)),
+            readiness: Arc::new(readiness),
+            local_peer: Arc::new(local_peer),
+            bootstrap_peers: Arc::new(RwLock::new(HashMap::new())),
+            dialer: Mutex::new(Dialer::new(BackoffPolicy::default())),
+        })
+    }
+
+    /// Registers a unit of in-flight work. Returns `None` while draining.
+    pub fn begin_work(&self) -> Option<WorkGuard> {
+        self.in_flight.try_begin()
+    }
+
+    /// Stops accepting inbound peers and new transactions until `resume`.
+    /// Existing peers stay connected.
+    pub fn pause(&self) {
+        if !self.paused.send_replace(true) {
+            info!("Node paused");
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.paused.send_replace(false) {
+            info!("Node resumed");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Queues a locally submitted transaction and gossips it to peers.
+    pub fn queue_transaction(&self, transaction: Transaction) -> bool {
+        if self.is_paused() {
+            debug!("Paused, not queueing transaction");
+            return false;
+        }
+        let Ok(encoded) = bincode::serialize(&transaction) else {
+            return false;
+        };
+        let signature = transaction.signatures.first().copied().unwrap_or_default();
+        let pending = TimestampedTransaction::new(transaction);
+        let sent_at_ms = pending.sent_at_ms;
+        if !self.mempool.lock().add(pending) {
+            return false;
+        }
+        let _ = self.activity.send(Activity::NewTransaction { signature: signature.to_string() });
+        self.gossip(Message::NewTransaction { transaction: encoded, sent_at_ms });
+        true
+    }
+
+    pub fn is_transaction_pending(&self, signature: &Signature) -> bool {
+        self.mempool.lock().contains(signature)
+    }
+
+    pub fn pending_transactions(&self) -> Vec<Signature> {
+        self.mempool.lock().signatures()
+    }
+
+    /// Removes up to `max` pending transactions, highest fee first.
+    pub fn take_transactions(&self, max: usize) -> Vec<TimestampedTransaction> {
+        let (taken, fill) = {
+            let mut mempool = self.mempool.lock();
+            let taken = mempool.take(max);
+            (taken, mempool.fill_ratio())
+        };
+        self.release_backpressure(fill);
+        taken
+    }
+
+    /// Tells the peers asked to slow down to resume once the mempool drains
+    /// to the low watermark, each on its own session.
+    fn release_backpressure(&self, fill: f64) {
+        let released = self.backpressure.release(fill);
+        if released.is_empty() {
+            return;
+        }
+        info!("Mempool drained to {:.0}%, releasing backpressure on {} peers", fill * 100.0, released.len());
+        let outboxes = Arc::clone(&self.outboxes);
+        self.spawn_task(async move {
+            for peer in released {
+                outboxes.send(&peer, Message::Backpressure { active: false }).await;
+            }
+        });
+    }
+
+    /// Expires stale transactions and snapshots the rest next to the attached
+    /// storage. Without storage there is nowhere to write, so nothing is saved.
+    pub async fn persist_mempool(&self) -> Result<usize, MempoolError> {
+        let Some(storage) = self.storage.read().clone() else {
+            return Ok(0);
+        };
+        persist_mempool(&self.mempool, &storage, Duration::from_secs(self.config().mempool_max_age_secs)).await
+    }
+
+    /// Sends an already-signed transaction, retrying with the same bytes and
+    /// checking whether it landed before each re-send.
+    pub async fn submit_transaction(&self, transaction: &Transaction) -> Result<Signature, NodeError> {
+        let policy = RetryPolicy {
+            attempt_timeout: Duration::from_millis(self.config().rpc_timeout_ms),
+            ..RetryPolicy::default()
+        };
+        Ok(submit_with_retry(self.rpc()?.as_ref(), transaction, policy).await?)
+    }
+
+    pub fn record_peer_announcement(&self, node_id: &str, addr: SocketAddr) -> bool {
+        self.seen_announcements
+            .lock()
+            .check_and_insert(&(node_id, addr))
+    }
+
+    pub fn pubkey(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+
+    pub fn current_slot(&self) -> u64 {
+        self.slot_clock.current_slot()
+    }
+
+    pub fn subscribe_slots(&self) -> tokio::sync::watch::Receiver<u64> {
+        self.slot_clock.subscribe()
+    }
+
+    async fn align_slot_clock(&self) -> Result<(), NodeError> {
+        let rpc_client = self.rpc()?;
+        let slot = rpc_client.get_slot().await?;
+        let block_time = rpc_client.get_block_time(slot).await?;
+        self.slot_clock.align_to_reference(block_time * 1000);
+        Ok(())
+    }
+
+    /// Sends `message` to every session and keeps it for replay. It goes
+    /// out under the replay lock, so a session opening meanwhile gets it
+    /// either replayed or on its subscription, never both.
+    pub fn broadcast(&self, message: Message) -> u64 {
+        let mut replay = self.replay.lock();
+        let seq = replay.push(message.clone());
+        let _ = self.tx.send(message);
+        seq
+    }
+
+    /// Broadcasts gossip originating here, marking it seen so copies relayed
+    /// back by peers are dropped.
+    fn gossip(&self, message: Message) -> u64 {
+        if let Some(digest) = dedup::gossip_digest(&message) {
+            self.recent_messages.lock().first_seen(digest, Instant::now());
+        }
+        self.broadcast(message)
+    }
+
+    /// Rebroadcasts gossip received from `from`, unless the same message was
+    /// already relayed recently. Returns whether it was forwarded.
+    pub fn relay(&self, from: SocketAddr, message: Message) -> bool {
+        if let Some(digest) = dedup::gossip_digest(&message) {
+            if !self.recent_messages.lock().first_seen(digest, Instant::now()) {
+                debug!("Dropping duplicate gossip from {}", from);
+                return false;
+            }
+        }
+        self.broadcast(message);
+        true
+    }
+
+    pub fn handle_ack(&self, node_id: &str, seq: u64) {
+        self.replay.lock().ack(node_id, seq);
+    }
+
+    pub fn resync_peer(&self, node_id: &str) -> Replay {
+        self.replay.lock().resync(node_id)
+    }
+
+    pub fn peers(&self) -> Vec<PeerSnapshot> {
+        self.peers.read().values().map(PeerInfo::snapshot).collect()
+    }
+
+    /// Current connections grouped by the node ID each peer announced in its handshake.
+    pub fn peers_by_node_id(&self) -> HashMap<String, Vec<SocketAddr>> {
+        let mut by_id: HashMap<String, Vec<SocketAddr>> = HashMap::new();
+        for peer in self.peers.read().values() {
+            if let Some(node_id) = &peer.node_id {
+                by_id.entry(node_id.clone()).or_default().push(peer.addr);
+            }
+        }
+        by_id
+    }
+
+    pub fn disconnect(&self, addr: SocketAddr, reason: &str) -> bool {
+        let removed = self.peers.write().remove(&addr).is_some();
+        if removed {
+            let _ = self.evictions.send(addr);
+            info!("Disconnected peer {}: {}", addr, reason);
+        }
+        self.refresh_peer_readiness();
+        removed
+    }
+
+    /// Drops every connection from `node_id`, whichever addresses it is using.
+    pub fn disconnect_node_id(&self, node_id: &str, reason: &str) -> Vec<SocketAddr> {
+        let mut peers = self.peers.write();
+        let addrs: Vec<SocketAddr> = peers
+            .values()
+            .filter(|p| p.node_id.as_deref() == Some(node_id))
+            .map(|p| p.addr)
+            .collect();
+        for addr in &addrs {
+            peers.remove(addr);
+        }
+        drop(peers);
+        for addr in &addrs {
+            let _ = self.evictions.send(*addr);
+        }
+
+        if !addrs.is_empty() {
+            info!("Disconnected node {} ({} connections): {}", node_id, addrs.len(), reason);
+        }
+        self.refresh_peer_readiness();
+        addrs
+    }
+
+    pub fn stats(&self) -> NodeStats {
+        let peers = self.peers.read();
+        let rtts: Vec<f64> = peers
+            .values()
+            .filter_map(|p| p.rtt())
+            .map(|rtt| rtt.as_secs_f64() * 1000.0)
+            .collect();
+
+        NodeStats {
+            peer_count: peers.len(),
+            connected_peers: peers.values().filter(|p| p.is_connected()).count(),
+            avg_rtt_ms: if rtts.is_empty() {
+                None
+            } else {
+                Some(rtts.iter().sum::<f64>() / rtts.len() as f64)
+            },
+            min_rtt_ms: rtts.iter().cloned().reduce(f64::min),
+            max_rtt_ms: rtts.iter().cloned().reduce(f64::max),
+            self_connections_rejected: self.local_peer.self_connections.load(Ordering::Relaxed),
+            inbound_refused: self.inbound_refused.load(Ordering::Relaxed),
+            external_addr: self.external_addr(),
+        }
+    }
+
+    /// Where nodes outside our NAT can dial us: the configured or mapped
+    /// address, else the IP peers agree they see with the listen port.
+    pub fn external_addr(&self) -> Option<SocketAddr> {
+        let advertised = *self.local_peer.external_addr.read();
+        advertised.or_else(|| {
+            let ip = self.local_peer.observed.lock().confirmed()?;
+            Some(SocketAddr::new(ip, self.local_peer.listen_port))
         })
     }
 
-    pub async fn start(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn record_peer_violation(&self, addr: SocketAddr) {
+        self.record_peer_event(addr, PeerEvent::MalformedMessage);
+    }
+
+    /// Adjusts the score of the peer at `addr`, banning it if the score falls
+    /// to `peer_ban_score`. Returns whether it was banned.
+    pub fn record_peer_event(&self, addr: SocketAddr, event: PeerEvent) -> bool {
+        let banned = reputation::apply_event(&self.peers, &self.local_peer.bans, self.config().ban_policy(), addr, event);
+        if banned {
+            self.refresh_peer_readiness();
+        } else if let Some(peer) = self.peers.read().get(&addr) {
+            debug!("Peer {} score now {} after {:?}", addr, peer.score(), event);
+        }
+        banned
+    }
+
+    pub fn bans(&self) -> Vec<BanEntry> {
+        self.local_peer.bans.lock().active(Instant::now())
+    }
+
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        let lifted = self.local_peer.bans.lock().unban(ip);
+        if lifted {
+            info!("Lifted ban on {}", ip);
+        }
+        lifted
+    }
+
+    /// Marks `addr` as active so the idle sweep keeps it.
+    pub fn touch_peer(&self, addr: SocketAddr) {
+        if let Some(peer) = self.peers.write().get_mut(&addr) {
+            peer.touch();
+        }
+    }
+
+    pub fn handle_pong(&self, addr: SocketAddr, nonce: u64, timestamp_ms: i64) {
+        session::record_pong(&self.peers, &self.clock_skew, addr, nonce, timestamp_ms);
+    }
+
+    pub fn clock_status(&self) -> ClockStatus {
+        self.clock_skew.lock().status()
+    }
+
+    /// False while the local clock is too far from the peer median and the
+    /// node is configured to abstain.
+    pub fn may_participate_in_consensus(&self) -> bool {
+        self.clock_skew.lock().may_participate()
+    }
+
+    /// Runs the node until `shutdown` or `drain` completes.
+    pub async fn start(&self) -> Result<(), NodeError> {
+        if self.storage.read().is_none() {
+            self.attach_storage(Storage::open(Path::new(&self.config().storage_path))?)?;
+        }
        
         self.verify_stake().await?;
+        self.readiness.update(|r| r.set_stake_verified(true));
 
-        let addr = format!("{}:{}", self.config.host, self.config.port);
+        let addr = format!("{}:{}", self.config().host, self.config().port);
         let listener = TcpListener::bind(&addr).await
             .map_err(|e| {
                 error!("Failed to bind to {}: {}", addr, e);
@@ -60,41 +681,230 @@ impl Node {
             })?;
         
         info!("Node listening on {}", addr);
+        self.start_port_mapping();
+
+        if let Err(e) = self.align_slot_clock().await {
+            warn!("Could not align slot clock with the Solana cluster: {}", e);
+        }
+        self.spawn_task(Arc::clone(&self.slot_clock).run());
+        if let Err(e) = self.refresh_validators(self.current_slot()).await {
+            warn!("Could not load the validator set from the stake program: {}", e);
+        }
 
         
+        let liveness = self.config().liveness.timeouts();
         let peers = Arc::clone(&self.peers);
-        tokio::spawn(async move {
+        self.spawn_task(async move {
             loop {
-                sleep(Duration::from_secs(60)).await;
-                Self::cleanup_disconnected_peers(Arc::clone(&peers)).await;
+                sleep(Duration::from_secs(liveness.reap_secs)).await;
+                Self::cleanup_disconnected_peers(Arc::clone(&peers), Duration::from_secs(liveness.idle_secs)).await;
             }
         });
 
-       
-        self.connect_to_bootstrap_nodes().await?;
+        let mempool = Arc::clone(&self.mempool);
+        let config = self.config();
+        let peers = Arc::clone(&self.peers);
+        let storage = self.storage.read().clone();
+        self.spawn_task(async move {
+            loop {
+                sleep(Duration::from_secs(config.mempool_persist_interval_secs)).await;
+                if let Some(storage) = &storage {
+                    let max_age = Duration::from_secs(config.mempool_max_age_secs);
+                    if let Err(e) = persist_mempool(&mempool, storage, max_age).await {
+                        warn!("Failed to persist mempool: {}", e);
+                    }
+                    if let Err(e) = persist_peers(storage, &peers) {
+                        warn!("Failed to persist peers: {}", e);
+                    }
+                }
+            }
+        });
+
+        let peers = Arc::clone(&self.peers);
+        let tx = self.tx.clone();
+        let ping_nonce = Arc::clone(&self.ping_nonce);
+        let clock_skew = Arc::clone(&self.clock_skew);
+        let readiness = Arc::clone(&self.readiness);
+        let bans = Arc::clone(&self.local_peer.bans);
+        let ban_policy = self.config().ban_policy();
+        let replay = Arc::clone(&self.replay);
+        self.spawn_task(async move {
+            let ping_timeout = Duration::from_secs(liveness.heartbeat_secs * 2);
+            loop {
+                sleep(Duration::from_secs(liveness.heartbeat_secs)).await;
+                send_checkpoint(&replay, &tx);
+                let timed_out =
+                    Self::probe_latency(&peers, &tx, &ping_nonce, ping_timeout, liveness.max_missed_heartbeats);
+                for addr in timed_out {
+                    reputation::apply_event(&peers, &bans, ban_policy, addr, PeerEvent::Timeout);
+                }
+                clock_skew.lock().evaluate();
+                // Inbound handlers don't hold the node, so the peer count is
+                // folded into readiness here.
+                let connected = peers.read().values().filter(|p| p.is_connected()).count();
+                readiness.update(|r| r.set_connected_peers(connected));
+            }
+        });
+
+        // Blocks and votes gossiped while syncing wait in their queues and
+        // apply afterwards.
+        let mut gossip = self.tx.subscribe();
+        self.connect_to_bootstrap_nodes().await;
+        if self.config().state_sync {
+            if let Err(e) = self.sync_state().await {
+                warn!("State sync failed, following gossip from height {}: {}", self.height(), e);
+            }
+        }
+        match self.catch_up().await {
+            Ok(_) | Err(SyncError::NoPeers) => {}
+            Err(e) => warn!("Catch-up stopped, following gossip from height {}: {}", self.height(), e),
+        }
+        if self.announce_validator() {
+            info!("Announced validator stake to peers");
+        }
+
+        let handler_permits = Arc::new(Semaphore::new(self.config().accept_concurrency));
+        let mut shutdown_rx = self.shutdown.subscribe();
+        let mut paused_rx = self.paused.subscribe();
+        let keepalive = Duration::from_secs(liveness.keepalive_secs);
+        let exchange_every = Duration::from_secs(self.config().peer_exchange_interval_secs);
+        let mut discovery = interval_at(tokio::time::Instant::now() + exchange_every, exchange_every);
+        let mut view_check_every = self.view_check_every();
+        let mut view_checks = interval_at(tokio::time::Instant::now() + view_check_every, view_check_every);
+        let proposes = self.config().proposes_blocks();
+        let mut slots = self.slot_clock.subscribe();
+        let fallback_every = Duration::from_millis(self.config().proposer_fallback_timeout_ms);
+        let mut fallback_checks = interval_at(tokio::time::Instant::now() + fallback_every, fallback_every);
+        let mut redials = interval_at(tokio::time::Instant::now() + REDIAL_CHECK_INTERVAL, REDIAL_CHECK_INTERVAL);
+        let mut dials = FuturesUnordered::new();
+        let mut epoch_checks = interval_at(tokio::time::Instant::now() + EPOCH_CHECK_INTERVAL, EPOCH_CHECK_INTERVAL);
+        // A second concurrent `start` finds the queue taken and closed.
+        let mut announcements = self.announcement_queue.lock().take().unwrap_or_else(|| mpsc::channel(1).1);
+        let mut blocks = self.block_queue.lock().take().unwrap_or_else(|| mpsc::channel(1).1);
+        let mut admissions = FuturesUnordered::new();
+        // At most one discovery round at a time.
+        let mut discovering = FuturesUnordered::new();
+        // At most one catch-up at a time, started when gossip shows the
+        // chain has moved past the local tip.
+        let mut catching_up = FuturesUnordered::new();
 
         loop {
             tokio::select! {
-                result = listener.accept() => {
+                _ = shutdown_rx.changed() => {
+                    info!("Accept loop stopped");
+                    break;
+                }
+                // Wakes the loop so the accept guard below is re-evaluated.
+                _ = paused_rx.changed() => continue,
+                // Discovery dials and epoch settlement wait on peers and the
+                // cluster, so they run alongside the loop instead of in it.
+                _ = discovery.tick(), if self.config().peer_discovery && !*paused_rx.borrow() && discovering.is_empty() => {
+                    discovering.push(self.discover_peers());
+                }
+                Some(_) = discovering.next(), if !discovering.is_empty() => {}
+                // Dials run alongside the loop so a slow bootstrap node
+                // doesn't hold up accepting peers.
+                _ = redials.tick(), if !*paused_rx.borrow() => {
+                    for node in self.due_bootstrap_nodes() {
+                        dials.push(self.dial_and_record(node));
+                    }
+                }
+                Some(_) = dials.next(), if !dials.is_empty() => {}
+                // Stake checks go to the cluster, so they run alongside the
+                // loop too.
+                Some(announcement) = announcements.recv(), if admissions.len() < MAX_PENDING_ANNOUNCEMENTS => {
+                    admissions.push(self.admit_announcement(announcement));
+                }
+                Some(()) = admissions.next(), if !admissions.is_empty() => {}
+                Ok(()) = slots.changed(), if !*paused_rx.borrow() => {
+                    let slot = *slots.borrow_and_update();
+                    if let Err(e) = self.refresh_validators(slot).await {
+                        warn!("Could not refresh the validator set for slot {}: {}", slot, e);
+                    }
+                    if proposes {
+                        self.produce_block(slot);
+                    }
+                }
+                // Hands the slot down `proposer_order` while its leader
+                // stays silent.
+                _ = fallback_checks.tick(), if proposes && !*paused_rx.borrow() => {
+                    self.produce_block(self.current_slot());
+                }
+                _ = view_checks.tick(), if proposes && !*paused_rx.borrow() => {
+                    self.check_view_timeout();
+                    // Follows `consensus_timeout` across config reloads.
+                    if self.view_check_every() != view_check_every {
+                        view_check_every = self.view_check_every();
+                        view_checks = interval_at(tokio::time::Instant::now() + view_check_every, view_check_every);
+                    }
+                }
+                _ = epoch_checks.tick(), if self.epochs.is_some() => {
+                    self.end_epochs();
+                }
+                Some(received) = blocks.recv() => match self.receive_proposal(received) {
+                    Err(NodeError::Chain(ChainError::BrokenLinkage(height))) if height > self.height() + 1 => {
+                        if catching_up.is_empty() {
+                            info!("Gossip reached height {}, catching up from {}", height, self.height());
+                            catching_up.push(self.catch_up());
+                        }
+                    }
+                    Err(e) => warn!("Rejected gossiped block: {}", e),
+                    Ok(_) => {}
+                },
+                Some(result) = catching_up.next(), if !catching_up.is_empty() => {
+                    if let Err(e) = result {
+                        warn!("Catch-up stopped at height {}: {}", self.height(), e);
+                    }
+                }
+                received = gossip.recv() => match received {
+                    Ok(Message::BlockVote { payload }) => {
+                        if let Err(e) = self.receive_block_vote(&payload) {
+                            warn!("Rejected block vote: {}", e);
+                        }
+                    }
+                    Ok(Message::ViewChange { payload }) => {
+                        if let Err(e) = self.receive_view_change(&payload) {
+                            warn!("Rejected view change: {}", e);
+                        }
+                    }
+                    _ => {}
+                },
+                result = listener.accept(), if self.in_flight.is_accepting() && !*paused_rx.borrow() => {
                     match result {
                         Ok((socket, addr)) => {
+                            if let Err(refusal) = self.screen_inbound(addr) {
+                                debug!("Refusing connection from {}: {}", addr, refusal);
+                                continue;
+                            }
                             let tx = self.tx.clone();
                             let peers = Arc::clone(&self.peers);
+                            let local = Arc::clone(&self.local_peer);
+                            let session_ctx = self.session_context();
+                            let sessions = Arc::clone(&self.sessions);
+                            let Some(work) = self.in_flight.try_begin() else {
+                                debug!("Draining, refusing connection from {}", addr);
+                                continue;
+                            };
                             
                             debug!("New connection from {}", addr);
                             
-                            tokio::spawn(async move {
-                                match timeout(CONNECTION_TIMEOUT, Self::handle_connection(socket, addr, tx, peers)).await {
-                                    Ok(result) => {
-                                        if let Err(e) = result {
-                                            error!("Error handling connection from {}: {}", addr, e);
-                                        }
-                                    }
-                                    Err(_) => {
-                                        error!("Connection handling timeout for {}", addr);
-                                    }
+                            let spawned = spawn_bounded(&handler_permits, &mut self.tasks.lock(), async move {
+                                let _work = work;
+                                if let Err(e) = configure_tcp(&socket, keepalive) {
+                                    error!("Failed to configure socket for {}: {}", addr, e);
+                                    return;
+                                }
+                                match timeout(CONNECTION_TIMEOUT, Self::handle_connection(socket, addr, local, tx, peers)).await {
+                                    // The handler permit only bounds admissions; the
+                                    // session runs as its own task.
+                                    Ok(Ok(conn)) => spawn_session(&sessions, conn, addr, session_ctx),
+                                    Ok(Err(e)) => error!("Error handling connection from {}: {}", addr, e),
+                                    Err(_) => error!("Connection handling timeout for {}", addr),
                                 }
                             });
+                            if !spawned {
+                                warn!("Connection handlers saturated, shedding connection from {}", addr);
+                            }
                         }
                         Err(e) => {
                             error!("Error accepting connection: {}", e);
@@ -104,110 +914,2969 @@ impl Node {
                 }
             }
         }
-    }
 
-    pub async fn shutdown(&self) -> Result<(), Box<dyn std::error::Error>> {
-        info!("Shutting down node...");
-        let _ = self.shutdown.send(()).await;
+        drop(listener);
+        drop((admissions, discovering, catching_up));
+        *self.announcement_queue.lock() = Some(announcements);
+        *self.block_queue.lock() = Some(blocks);
+        let _ = self.stopped.subscribe().wait_for(|stopped| *stopped).await;
         Ok(())
     }
 
-    async fn verify_stake(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let balance = self.rpc_client
-            .get_balance(&self.keypair.pubkey())
-            .await?;
-
-        if balance < self.config.min_stake {
-            return Err("Insufficient stake amount".into());
+    /// Applies the per-IP inbound limits to a freshly accepted connection.
+    fn screen_inbound(&self, addr: SocketAddr) -> Result<(), InboundRefusal> {
+        let open_from_ip = self
+            .peers
+            .read()
+            .values()
+            .filter(|p| p.is_connected() && p.addr.ip() == addr.ip())
+            .count();
+        let screened = self.inbound.lock().check(addr.ip(), open_from_ip, Instant::now());
+        if screened.is_err() {
+            self.inbound_refused.fetch_add(1, Ordering::Relaxed);
         }
+        screened
+    }
 
-        Ok(())
+    /// Runs `task` in the node's task set so shutdown can abort and join it.
+    fn spawn_task<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock();
+        reap_finished(&mut tasks);
+        tasks.spawn(task);
     }
 
-    async fn connect_to_bootstrap_nodes(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for node in &self.config.bootstrap_nodes {
-            let mut attempts = 0;
-            while attempts < MAX_RECONNECT_ATTEMPTS {
-                match TcpStream::connect(node).await {
-                    Ok(stream) => {
-                        info!("Connected to bootstrap node: {}", node);
-                        let peers = Arc::clone(&self.peers);
-                        if let Err(e) = self.handle_outbound_connection(stream, peers).await {
-                            error!("Error handling connection to {}: {}", node, e);
-                            attempts += 1;
-                            sleep(RECONNECT_DELAY).await;
-                            continue;
+    /// Keeps the listen port mapped on the router, renewing at half the
+    /// lease and retrying while no gateway answers.
+    fn start_port_mapping(&self) {
+        let Some(nat) = self.config().nat.clone() else { return };
+        if nat.external_addr.is_some() || nat.mapping == PortMapping::None {
+            return;
+        }
+        let local = Arc::clone(&self.local_peer);
+        let port = self.config().port;
+        self.spawn_task(async move {
+            loop {
+                let renew_in = match nat::map_port(&nat, port).await {
+                    Ok(mapping) => {
+                        if *local.external_addr.read() != Some(mapping.external) {
+                            info!("Mapped port {} to {} via {:?}", port, mapping.external, mapping.method);
                         }
-                        break;
+                        *local.external_addr.write() = Some(mapping.external);
+                        *local.mapping.lock() = Some(mapping);
+                        mapping.lease / 2
                     }
                     Err(e) => {
-                        warn!("Failed to connect to bootstrap node {}: {}", node, e);
-                        attempts += 1;
-                        if attempts < MAX_RECONNECT_ATTEMPTS {
-                            sleep(RECONNECT_DELAY).await;
-                        }
+                        warn!("Could not map port {} on the router: {}", port, e);
+                        PORT_MAPPING_RETRY
                     }
+                };
+                sleep(renew_in.max(MIN_PORT_MAPPING_RENEWAL)).await;
+            }
+        });
+    }
+
+    /// Aborts every task still running and waits for all of them to finish.
+    async fn join_tasks(&self) {
+        let mut tasks = std::mem::take(&mut *self.tasks.lock());
+        tasks.abort_all();
+        while let Some(result) = tasks.join_next().await {
+            log_task_exit(result);
+        }
+    }
+
+    /// Waits up to `grace` for sessions to send their goodbyes, then aborts
+    /// any still running.
+    async fn close_sessions(&self, grace: Duration) {
+        let mut sessions = std::mem::take(&mut *self.sessions.lock());
+        let closing = async {
+            while let Some(result) = sessions.join_next().await {
+                log_task_exit(result);
+            }
+        };
+        if timeout(grace, closing).await.is_err() {
+            warn!("{} peer sessions did not close within {:?}, aborting", sessions.len(), grace);
+            sessions.abort_all();
+            while let Some(result) = sessions.join_next().await {
+                log_task_exit(result);
+            }
+        }
+    }
+
+    fn rpc(&self) -> Result<Arc<RpcClient>, NodeError> {
+        self.rpc_client.read().clone().ok_or(NodeError::RpcClosed)
+    }
+
+    /// Reads stake accounts through `fetcher` instead of the RPC client.
+    pub fn attach_account_fetcher(&self, fetcher: Arc<dyn AccountFetcher>) {
+        *self.account_fetcher.write() = Some(fetcher);
+    }
+
+    fn account_fetcher(&self) -> Result<Arc<dyn AccountFetcher>, NodeError> {
+        if let Some(fetcher) = self.account_fetcher.read().clone() {
+            return Ok(fetcher);
+        }
+        let rpc: Arc<dyn AccountFetcher> = self.rpc()?;
+        Ok(rpc)
+    }
+
+    /// Persists blocks, stake snapshots and peers to `storage`, resuming from
+    /// whatever it already holds: the chain tip, the last validator set,
+    /// previously seen peers and the pending transactions saved beside it.
+    /// `start` opens one under `storage_path` if none was attached.
+    pub fn attach_storage(&self, storage: Storage) -> Result<(), StorageError> {
+        {
+            let mut consensus = self.consensus.write();
+            if let Some(tip) = storage.latest_block()? {
+                info!("Resuming chain at height {}", tip.header.height);
+                consensus.set_tip(&tip.header);
+            }
+            if let Some((height, validators)) = storage.latest_stake_snapshot()? {
+                debug!("Restoring {} validators snapshotted at height {}", validators.len(), height);
+                for validator in validators {
+                    consensus.add_validator(validator);
                 }
             }
         }
+        let learned = self.known_peers.lock().merge(storage.peers()?, Instant::now());
+        if learned > 0 {
+            debug!("Restored {} known peers", learned);
+        }
+        let max_age = Duration::from_secs(self.config().mempool_max_age_secs);
+        let restored = self.mempool.lock().restore(&storage.root().join(MEMPOOL_FILE), max_age, SystemTime::now());
+        match restored {
+            Ok(0) => {}
+            Ok(restored) => info!("Restored {} pending transactions", restored),
+            Err(e) => warn!("Could not restore persisted mempool: {}", e),
+        }
+        self.state_sync.attach_storage(storage.clone());
+        *self.storage.write() = Some(storage);
         Ok(())
     }
 
-    async fn handle_connection(
-        socket: TcpStream,
-        addr: SocketAddr,
-        tx: broadcast::Sender<Message>,
-        peers: Arc<RwLock<HashMap<SocketAddr, PeerInfo>>>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        socket.set_nodelay(true)?;
-        
-        
-        let keepalive = socket2::TcpKeepalive::new()
-            .with_time(Duration::from_secs(60))
-            .with_interval(Duration::from_secs(10));
-        
-        let socket2 = socket2::SockRef::from(&socket);
-        socket2.set_tcp_keepalive(&keepalive)?;
-        
-        
-        peers.write().insert(addr, PeerInfo::new(addr));
-        
-        
-        Ok(())
+    fn storage(&self) -> Result<Storage, NodeError> {
+        self.storage.read().clone().ok_or(NodeError::NoStorage)
     }
 
-    async fn handle_outbound_connection(
-        &self,
-        stream: TcpStream,
-        peers: Arc<RwLock<HashMap<SocketAddr, PeerInfo>>>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let addr = stream.peer_addr()?;
-        stream.set_nodelay(true)?;
-        
-        
-        let keepalive = socket2::TcpKeepalive::new()
-            .with_time(Duration::from_secs(60))
-            .with_interval(Duration::from_secs(10));
-        
-        let socket2 = socket2::SockRef::from(&stream);
-        socket2.set_tcp_keepalive(&keepalive)?;
-        
-        
-        peers.write().insert(addr, PeerInfo::new(addr));
-        
-        
+    /// Appends `block` to the local chain once `ConsensusManager::verify_block`
+    /// accepts it. The tip only moves once the block is on disk, and its
+    /// transactions then leave the mempool.
+    pub fn apply_block(&self, block: &Block) -> Result<(), NodeError> {
+        let storage = self.storage()?;
+        let mut consensus = self.consensus.write();
+        consensus.verify_block(block)?;
+        storage.put_block(block)?;
+        consensus.set_tip(&block.header);
+        if let Some(epochs) = &self.epochs {
+            let mut epochs = epochs.lock();
+            self.advance_epochs(&mut epochs, block.header.height);
+            epochs.record_block(block.header.proposer);
+        }
+        // `propose_block` locks the mempool before consensus.
+        drop(consensus);
+        let fill = {
+            let mut mempool = self.mempool.lock();
+            mempool.remove_committed(&block.transactions);
+            mempool.fill_ratio()
+        };
+        self.release_backpressure(fill);
+        self.metrics.record_block_applied();
+        let _ = self.activity.send(Activity::NewBlock {
+            height: block.header.height,
+            hash: block.hash().to_string(),
+            transactions: block.transactions.len(),
+        });
         Ok(())
     }
 
-    async fn cleanup_disconnected_peers(peers: Arc<RwLock<HashMap<SocketAddr, PeerInfo>>>) {
-        let mut peers = peers.write();
-        peers.retain(|addr, peer| {
-            if !peer.is_connected() {
-                warn!("Removing disconnected peer: {}", addr);
-                false
-            } else {
-                true
+    /// Applies a block a peer has already committed, as when catching up.
+    /// Returns `false` for one already on the local chain.
+    pub fn receive_block(&self, payload: &[u8]) -> Result<bool, NodeError> {
+        let block: Block = bincode::deserialize(payload)?;
+        if self.storage()?.get_block_by_hash(&block.hash())?.is_some() {
+            return Ok(false);
+        }
+        self.apply_block(&block)?;
+        info!("Applied block {} from {}", block.header.height, block.header.proposer);
+        Ok(true)
+    }
+
+    /// Checks a block gossiped by a peer and relays it only once it holds
+    /// up. A peer relaying a forged block loses score. Returns whether the
+    /// block was new.
+    pub fn receive_proposal(&self, received: ReceivedBlock) -> Result<bool, NodeError> {
+        let ReceivedBlock { from, slot, payload } = received;
+        let block: Block = match bincode::deserialize(&payload) {
+            Ok(block) => block,
+            Err(e) => {
+                self.record_peer_violation(from);
+                return Err(e.into());
             }
-        });
+        };
+        match self.accept_proposal(&block) {
+            Ok(true) => {}
+            Ok(false) => return Ok(false),
+            Err(NodeError::Chain(e)) if e.is_forgery() => {
+                self.record_peer_event(from, PeerEvent::InvalidBlock);
+                return Err(e.into());
+            }
+            Err(e) => return Err(e),
+        }
+        self.relay(from, Message::Block { slot, payload });
+        Ok(true)
+    }
+
+    /// Commits `block` straight away while there are no validators to vote.
+    /// Otherwise holds it as a proposal, votes for it and commits it once
+    /// its votes reach a quorum.
+    fn accept_proposal(&self, block: &Block) -> Result<bool, NodeError> {
+        if self.storage()?.get_block_by_hash(&block.hash())?.is_some() {
+            return Ok(false);
+        }
+        if !self.consensus.read().requires_quorum() {
+            self.apply_block(block)?;
+            info!("Applied block {} from {}", block.header.height, block.header.proposer);
+            return Ok(true);
+        }
+        // One slot of slack for clocks that disagree at a slot boundary.
+        let latest_slot = self.current_slot().saturating_add(1);
+        let elapsed = self.slot_clock.since_slot_start(block.header.slot);
+        if !self.consensus.write().add_proposal(block, latest_slot, elapsed)? {
+            return Ok(false);
+        }
+        self.cast_block_vote(block);
+        self.commit_if_certified();
+        Ok(true)
+    }
+
+    /// Counts a block vote gossiped by a peer, committing the block it
+    /// completes a quorum for. Returns whether a block committed.
+    pub fn receive_block_vote(&self, payload: &[u8]) -> Result<bool, NodeError> {
+        let vote: BlockVote = bincode::deserialize(payload)?;
+        let certified = self.consensus.write().record_block_vote(&vote)?;
+        let Some((block, qc)) = certified else {
+            return Ok(false);
+        };
+        self.commit_block(&block, &qc);
+        Ok(true)
+    }
+
+    /// Votes for `block` and gossips the vote. A validator votes once per
+    /// height, for the first valid proposal it sees, and not at all while
+    /// its clock is skewed.
+    fn cast_block_vote(&self, block: &Block) {
+        if !self.may_participate_in_consensus() {
+            debug!("Clock skewed, abstaining from voting on block {}", block.header.height);
+            return;
+        }
+        let vote = BlockVote::new(block, &self.keypair);
+        let recorded = {
+            let mut consensus = self.consensus.write();
+            if !consensus.is_validator(&vote.validator) || consensus.has_voted(&vote.validator) {
+                return;
+            }
+            consensus.record_block_vote(&vote)
+        };
+        if let Err(e) = recorded {
+            warn!("Could not record own block vote: {}", e);
+            return;
+        }
+        match bincode::serialize(&vote) {
+            Ok(payload) => {
+                self.gossip(Message::BlockVote { payload });
+            }
+            Err(e) => warn!("Failed to encode block vote: {}", e),
+        }
+    }
+
+    fn commit_if_certified(&self) {
+        let certified = self.consensus.read().certified();
+        if let Some((block, qc)) = certified {
+            self.commit_block(&block, &qc);
+        }
+    }
+
+    fn commit_block(&self, block: &Block, qc: &QuorumCertificate) {
+        if let Err(e) = self.apply_certified(block, qc) {
+            warn!("Failed to commit certified block {}: {}", block.header.height, e);
+            return;
+        }
+        info!("Committed block {} from {}", block.header.height, block.header.proposer);
+    }
+
+    /// Applies a certified block, stores `qc` with it for peers catching up
+    /// and credits each validator whose vote is in `qc` for the epoch. A
+    /// height commits once, so nobody earns more than one vote per block.
+    fn apply_certified(&self, block: &Block, qc: &QuorumCertificate) -> Result<(), NodeError> {
+        self.apply_block(block)?;
+        self.storage()?.put_certificate(block.header.height, qc)?;
+        if let Some(epochs) = &self.epochs {
+            let mut epochs = epochs.lock();
+            for (validator, _) in &qc.votes {
+                epochs.record_vote(*validator);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches the blocks committed past the local tip from connected
+    /// peers, checking each batch of certified headers before downloading
+    /// the blocks behind it. Peers that serve a chain that doesn't verify
+    /// lose score. Returns the height reached.
+    pub async fn catch_up(&self) -> Result<u64, SyncError> {
+        let sources: Vec<Arc<dyn HeaderSource>> = self
+            .peers
+            .read()
+            .values()
+            .filter(|p| p.is_connected())
+            .filter_map(|p| p.pubkey)
+            .map(|server| {
+                Arc::new(SessionHeaderSource::new(server, Arc::clone(&self.state_sync), Arc::clone(&self.outboxes)))
+                    as Arc<dyn HeaderSource>
+            })
+            .collect();
+        let ban_policy = self.config().ban_policy();
+        let penalize = |id: &str| {
+            if let Ok(pubkey) = id.parse::<Pubkey>() {
+                reputation::apply_event_to_pubkey(&self.peers, &self.local_peer.bans, ban_policy, &pubkey, PeerEvent::MalformedMessage);
+            }
+        };
+        CatchUp::new(MAX_BLOCKS_PER_REQUEST).run(self, &sources, penalize).await
+    }
+
+    /// Brings a joining node up to the network before it follows gossip:
+    /// installs the snapshot backed by the most stake among connected peers
+    /// if it is ahead of the local chain, then fetches the blocks committed
+    /// since from a peer that offered it. Stake is weighed by the validator
+    /// set loaded from the stake program, not by what peers claim. Returns
+    /// the height reached.
+    pub async fn sync_state(&self) -> Result<u64, NodeError> {
+        let connected = self.peers.read().values().filter(|p| p.is_connected() && p.pubkey.is_some()).count();
+        if connected == 0 {
+            return Ok(self.height());
+        }
+        let offers = self.state_sync.collect_manifests(&self.tx, connected).await;
+        let stakes: HashMap<Pubkey, u64> =
+            self.consensus.read().validators().iter().map(|v| (v.pubkey, v.stake)).collect();
+        let Some((manifest, servers)) = snapshot::choose_manifest(&offers, &stakes) else {
+            debug!("No peer has a snapshot to share");
+            return Ok(self.height());
+        };
+
+        if manifest.height > self.height() {
+            info!("Fetching snapshot at height {} from {} peers", manifest.height, servers.len());
+            let ban_policy = self.config().ban_policy();
+            let penalize = |pubkey: &Pubkey| {
+                reputation::apply_event_to_pubkey(&self.peers, &self.local_peer.bans, ban_policy, pubkey, PeerEvent::MalformedMessage);
+            };
+            let state = self.state_sync.download(&self.outboxes, &manifest, &servers, penalize).await?;
+            let validators = self.checked_snapshot_validators(&state).await?;
+            self.install_snapshot(&state, validators)?;
+        }
+
+        let server = servers[0];
+        loop {
+            let from_height = self.height() + 1;
+            let Some(blocks) = self.state_sync.request_blocks(&self.outboxes, server, from_height).await else {
+                warn!("Peer {} stopped serving blocks at height {}", server, from_height);
+                break;
+            };
+            for payload in &blocks {
+                self.receive_block(payload)?;
+            }
+            if self.height() < from_height {
+                break;
+            }
+        }
+        info!("State sync reached height {}", self.height());
+        Ok(self.height())
+    }
+
+    /// The validator set to install along with `state`, or `None` to keep
+    /// the local one. With a stake program the set is rebuilt from its
+    /// accounts, and the one peers sent only counts for a warning if it
+    /// differs. Without one, the peers' set is taken only while this node
+    /// knows no validators.
+    async fn checked_snapshot_validators(&self, state: &SnapshotState) -> Result<Option<Vec<Validator>>, NodeError> {
+        let Some(registry) = &self.validator_registry else {
+            let unknown = self.consensus.read().validators().is_empty();
+            return Ok((unknown && !state.validators.is_empty()).then(|| state.validators.clone()));
+        };
+        let fetcher = self.account_fetcher()?;
+        let validators = registry.refresh(fetcher.as_ref(), self.current_slot()).await?;
+        let sent: HashMap<Pubkey, u64> = state.validators.iter().map(|v| (v.pubkey, v.stake)).collect();
+        let on_chain: HashMap<Pubkey, u64> = validators.iter().map(|v| (v.pubkey, v.stake)).collect();
+        if sent != on_chain {
+            warn!(
+                "Snapshot at height {} lists {} validators, the stake program {}; using the stake program's",
+                state.height(),
+                sent.len(),
+                on_chain.len(),
+            );
+        }
+        Ok(Some(validators))
+    }
+
+    /// Stores a verified snapshot and moves the chain tip to it, installing
+    /// `validators` if given. Blocks the local chain already holds must
+    /// match it.
+    fn install_snapshot(&self, state: &SnapshotState, validators: Option<Vec<Validator>>) -> Result<(), NodeError> {
+        let storage = self.storage()?;
+        for block in &state.blocks {
+            storage.put_block(block)?;
+        }
+        if let Some(tip) = state.blocks.last() {
+            let mut consensus = self.consensus.write();
+            consensus.set_tip(&tip.header);
+            if let Some(validators) = validators {
+                consensus.set_validators(validators);
+            }
+            self.snapshot_validators(&consensus);
+        }
+        let local_model = self.inference.generator().map(|generator| generator.model_version());
+        if let (Some(theirs), Some(ours)) = (&state.model_version, &local_model) {
+            if theirs != ours {
+                warn!("Peers serve model {} but this node loaded {}", theirs, ours);
+            }
+        }
+        info!("Installed snapshot at height {} with {} validators", state.height(), self.consensus.read().validators().len());
+        Ok(())
+    }
+
+    pub fn is_leader(&self, slot: u64) -> bool {
+        self.consensus.read().leader(slot).map(|v| v.pubkey) == Some(self.keypair.pubkey())
+    }
+
+    /// Whether this node's turn to propose for `slot` has come by now: the
+    /// leader's from the start of the slot, then each fallback's in turn
+    /// once the one before it has had `proposer_fallback_timeout_ms`.
+    fn is_proposer_now(&self, slot: u64) -> bool {
+        let elapsed = self.slot_clock.since_slot_start(slot);
+        self.consensus.read().proposer_after(slot, elapsed).map(|v| v.pubkey) == Some(self.keypair.pubkey())
+    }
+
+    /// Votes to rotate past the current leader once `consensus_timeout` has
+    /// passed with transactions pending but no block committed. An idle
+    /// chain is not a stalled one, so the timeout restarts while the mempool
+    /// is empty. A node whose clock is skewed leaves this to the others.
+    pub fn check_view_timeout(&self) {
+        if !self.may_participate_in_consensus() {
+            return;
+        }
+        let idle = self.mempool.lock().is_empty();
+        let vote = {
+            let mut consensus = self.consensus.write();
+            if !consensus.is_validator(&self.keypair.pubkey()) || !consensus.view_timed_out(Instant::now()) {
+                return;
+            }
+            if idle {
+                consensus.reset_view_timer();
+                return;
+            }
+            consensus.request_view_change(&self.keypair)
+        };
+        warn!("No block at height {} within the consensus timeout, voting for view {}", vote.height, vote.view);
+        self.cast_view_change(vote);
+    }
+
+    /// Counts a view change vote gossiped by a peer, joining the change once
+    /// more than a third of stake backs it. Returns whether the view moved.
+    pub fn receive_view_change(&self, payload: &[u8]) -> Result<bool, NodeError> {
+        let vote: ViewChange = bincode::deserialize(payload)?;
+        let (view_before, advanced, join) = {
+            let mut consensus = self.consensus.write();
+            let view_before = consensus.view();
+            let advanced = consensus.record_view_change(&vote)?;
+            let join = consensus
+                .view_to_join(&self.keypair.pubkey())
+                .map(|view| ViewChange::new(consensus.height() + 1, view, &self.keypair));
+            (view_before, advanced, join)
+        };
+        if let Some(view) = advanced {
+            self.enter_view(view);
+        }
+        if let Some(own) = join {
+            self.cast_view_change(own);
+        }
+        Ok(self.consensus.read().view() != view_before)
+    }
+
+    fn cast_view_change(&self, vote: ViewChange) {
+        if !self.may_participate_in_consensus() {
+            debug!("Clock skewed, abstaining from view change at height {}", vote.height);
+            return;
+        }
+        let recorded = self.consensus.write().record_view_change(&vote);
+        let advanced = match recorded {
+            Ok(advanced) => advanced,
+            Err(e) => {
+                warn!("Could not record own view change: {}", e);
+                return;
+            }
+        };
+        match bincode::serialize(&vote) {
+            Ok(payload) => {
+                self.gossip(Message::ViewChange { payload });
+            }
+            Err(e) => warn!("Failed to encode view change: {}", e),
+        }
+        if let Some(view) = advanced {
+            self.enter_view(view);
+        }
+    }
+
+    /// Re-proposes under the new view's leader straight away instead of
+    /// waiting for the next slot.
+    fn enter_view(&self, view: u64) {
+        info!("Moved to view {} at height {}", view, self.consensus.read().height() + 1);
+        if self.config().proposes_blocks() {
+            self.produce_block(self.current_slot());
+        }
+    }
+
+    /// Proposes a block for `slot` if it is this node's turn, it has
+    /// transactions pending, hasn't proposed at this height yet and its
+    /// clock isn't skewed. A fallback only steps in while no block has been
+    /// proposed for the height.
+    fn produce_block(&self, slot: u64) {
+        if !self.is_proposer_now(slot) || self.mempool.lock().is_empty() {
+            return;
+        }
+        if !self.may_participate_in_consensus() {
+            debug!("Clock skewed, abstaining from proposing for slot {}", slot);
+            return;
+        }
+        {
+            let consensus = self.consensus.read();
+            if consensus.has_proposed(&self.keypair.pubkey()) {
+                return;
+            }
+            let leads = consensus.leader(slot).map(|v| v.pubkey) == Some(self.keypair.pubkey());
+            if !leads && consensus.has_proposals() {
+                return;
+            }
+        }
+        if let Err(e) = self.propose_block(slot, MAX_BLOCK_TRANSACTIONS) {
+            warn!("Failed to propose block for slot {}: {}", slot, e);
+        }
+    }
+
+    /// Forms the block for `slot` from the highest-fee pending transactions
+    /// and gossips it. Without validators it commits straight away;
+    /// otherwise it is voted on like any other proposal, and its
+    /// transactions stay in the mempool until it commits.
+    pub fn propose_block(&self, slot: u64, max_transactions: usize) -> Result<Block, NodeError> {
+        let (block, fill) = {
+            let mut mempool = self.mempool.lock();
+            let block = self.consensus.read().form_block(&mut mempool, slot, max_transactions, &self.keypair);
+            (block, mempool.fill_ratio())
+        };
+        self.release_backpressure(fill);
+
+        let requeue = || {
+            let mut mempool = self.mempool.lock();
+            for transaction in &block.transactions {
+                mempool.add(TimestampedTransaction::new(transaction.clone()));
+            }
+        };
+        if self.consensus.read().requires_quorum() {
+            requeue();
+            let elapsed = self.slot_clock.since_slot_start(slot);
+            self.consensus.write().add_proposal(&block, slot, elapsed)?;
+        } else if let Err(e) = self.apply_block(&block) {
+            requeue();
+            return Err(e);
+        }
+        info!("Proposed block {} with {} transactions", block.header.height, block.transactions.len());
+        self.gossip(Message::Block { slot, payload: bincode::serialize(&block)? });
+        self.cast_block_vote(&block);
+        self.commit_if_certified();
+        Ok(block)
+    }
+
+    pub fn stored_block(&self, hash: &Hash) -> Result<Option<Block>, NodeError> {
+        Ok(self.storage()?.get_block_by_hash(hash)?)
+    }
+
+    pub fn stored_block_at(&self, height: u64) -> Result<Option<Block>, NodeError> {
+        Ok(self.storage()?.get_block_by_height(height)?)
+    }
+
+    /// Height of the chain tip this node has applied.
+    pub fn height(&self) -> u64 {
+        self.consensus.read().height()
+    }
+
+    /// The config in effect, including any reloaded settings.
+    pub fn config(&self) -> Arc<NodeConfig> {
+        self.config.read().clone()
+    }
+
+    pub fn stake_program_id(&self) -> Option<Pubkey> {
+        self.config().stake_program_id.as_deref()?.parse().ok()
+    }
+
+    /// Stakes and delegates with the node's own keypair, or `None` without a
+    /// configured stake program or once the RPC client has been closed.
+    pub fn stake_client(&self) -> Option<StakeClient> {
+        let program_id = self.stake_program_id()?;
+        let rpc = self.rpc_client.read().clone()?;
+        Some(StakeClient::new(rpc, self.keypair.clone(), program_id))
+    }
+
+    pub fn validators(&self) -> Vec<Validator> {
+        self.consensus.read().validators().to_vec()
+    }
+
+    /// Settles the epochs that have ended. Each ended epoch's rewards are
+    /// split among the staked validators and, when `distribute` is set,
+    /// submitted to the stake program in the background. Returns the epochs
+    /// settled.
+    pub fn end_epochs(&self) -> Vec<EpochSummary> {
+        let (Some(epochs), Some(config)) = (&self.epochs, self.config().epoch.clone()) else {
+            return Vec::new();
+        };
+        // Read before taking the tracker: `apply_block` locks them the other way round.
+        let height = self.height();
+        let ended = {
+            let mut epochs = epochs.lock();
+            self.advance_epochs(&mut epochs, height);
+            epochs.take_ended()
+        };
+
+        // Only staked validators have an account to credit, so nothing is
+        // paid while the set is unknown.
+        let validators: HashSet<Pubkey> = self.validators().iter().map(|v| v.pubkey).collect();
+        let weights = config.reward_weights();
+        for summary in &ended {
+            let mut participation = summary.participation.clone();
+            participation.retain(|validator, _| validators.contains(validator));
+            let rewards = epoch::compute_rewards(&participation, &weights, config.rewards_per_epoch);
+            info!("Epoch {} ended, {} validators earned rewards", summary.epoch, rewards.len());
+            if !config.distribute || rewards.is_empty() {
+                continue;
+            }
+            let Some(client) = self.stake_client() else {
+                warn!("No stake client, epoch {} rewards not distributed", summary.epoch);
+                continue;
+            };
+            let epoch = summary.epoch;
+            self.spawn_task(async move {
+                let (signatures, unpaid) = client.distribute_rewards(epoch, &rewards).await;
+                info!("Distributed epoch {} rewards in {} transactions", epoch, signatures.len());
+                for (validator, e) in unpaid {
+                    warn!("Could not credit {} with epoch {} rewards: {}", validator, epoch, e);
+                }
+            });
+        }
+        ended
+    }
+
+    /// Moves `epochs` to `height` and the local clock, the only clock
+    /// wall-clock epochs follow. Jobs recorded since the last epoch ended
+    /// count towards the one closing.
+    fn advance_epochs(&self, epochs: &mut EpochTracker, height: u64) {
+        let now = unix_now_ms() / 1000;
+        let closing = epochs.current().map_or(false, |current| epochs.epoch_at(height, now) > current);
+        if closing {
+            for receipt in self.inference.settle_receipts() {
+                epochs.record_inference(receipt.executor());
+            }
+        }
+        epochs.observe(height, now);
+    }
+
+    /// Tells peers this node has staked so they add it to their validator
+    /// sets. `None` without a configured stake program.
+    pub fn validator_announcement(&self) -> Option<Message> {
+        let program_id = self.stake_program_id()?;
+        let pubkey = self.keypair.pubkey();
+        let (stake_account, _) = find_stake_address(&program_id, &pubkey);
+        Some(Message::ValidatorAnnounce {
+            pubkey: pubkey.to_bytes(),
+            stake_account: stake_account.to_bytes(),
+        })
+    }
+
+    pub fn announce_validator(&self) -> bool {
+        match self.validator_announcement() {
+            Some(announcement) => {
+                self.broadcast(announcement);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Checks an announced stake on-chain and, if it holds, adds `pubkey` to
+    /// the validator set. Returns whether the set changed.
+    pub async fn handle_validator_announce(&self, pubkey: Pubkey, stake_account: Pubkey) -> Result<bool, StakeCheckError> {
+        let Some(program_id) = self.stake_program_id() else {
+            debug!("No stake_program_id configured, ignoring validator announcement from {}", pubkey);
+            return Ok(false);
+        };
+        let (expected, _) = find_stake_address(&program_id, &pubkey);
+        if stake_account != expected {
+            return Err(StakeCheckError::NotStakeAddress { account: stake_account, staker: pubkey });
+        }
+
+        let fetcher = self.account_fetcher().map_err(|e| StakeCheckError::Rpc(e.to_string()))?;
+        let stake = verify_stake_account(fetcher.as_ref(), &program_id, &pubkey, self.config().min_stake).await?;
+
+        let mut consensus = self.consensus.write();
+        if consensus.validators().iter().any(|v| v.pubkey == pubkey) {
+            return Ok(false);
+        }
+        consensus.add_validator(Validator {
+            pubkey,
+            stake: stake.amount,
+            locked_until: stake.locked_until,
+        });
+        info!("Added validator {} with {} lamports staked", pubkey, stake.amount);
+        self.snapshot_validators(&consensus);
+        Ok(true)
+    }
+
+    /// Rebuilds the validator set from the stake program's accounts when
+    /// `slot` falls in an epoch it hasn't been rebuilt for. Returns whether
+    /// the set was replaced; always `false` without a stake program.
+    pub async fn refresh_validators(&self, slot: u64) -> Result<bool, StakeCheckError> {
+        let Some(registry) = &self.validator_registry else {
+            return Ok(false);
+        };
+        if !registry.is_due(slot) {
+            return Ok(false);
+        }
+
+        let fetcher = self.account_fetcher().map_err(|e| StakeCheckError::Rpc(e.to_string()))?;
+        let validators = registry.refresh(fetcher.as_ref(), slot).await?;
+        let total_stake: u64 = validators.iter().map(|v| v.stake).sum();
+        info!(
+            "Epoch {} validator set: {} validators holding {} lamports",
+            registry.epoch(slot),
+            validators.len(),
+            total_stake,
+        );
+
+        let mut consensus = self.consensus.write();
+        consensus.set_validators(validators);
+        self.snapshot_validators(&consensus);
+        Ok(true)
+    }
+
+    fn snapshot_validators(&self, consensus: &ConsensusManager) {
+        if let Some(storage) = self.storage.read().as_ref() {
+            if let Err(e) = storage.put_stake_snapshot(consensus.height(), consensus.validators()) {
+                warn!("Failed to snapshot validator set: {}", e);
+            }
+        }
+    }
+
+    /// Hands the node ownership of the loaded model so shutdown releases it
+    /// only after peers and RPC are done.
+    pub fn attach_model(&self, model: Arc<dyn Any + Send + Sync>) {
+        *self.model.lock() = Some(model);
+        self.readiness.update(|r| r.set_model_loaded(true));
+    }
+
+    /// Routes peer generation requests to `generator`, usually the LLM batch queue.
+    pub fn attach_generator(&self, generator: Arc<dyn GenerationService>) {
+        self.state_sync.set_model_version(generator.model_version());
+        self.inference.attach_generator(generator);
+    }
+
+    async fn handle_generate_request(&self, prompt: String, max_tokens: u32, params: GenerateParams) -> Result<String, String> {
+        let _work = self.begin_work().ok_or_else(|| "Node is shutting down".to_string())?;
+        self.inference.generate(prompt, max_tokens, params).await
+    }
+
+    /// Checks an announcement a peer sent and relays it when it added a
+    /// validator. One that doesn't hold up counts against the sender.
+    async fn admit_announcement(&self, announcement: Announcement) {
+        let Announcement { from, pubkey, stake_account } = announcement;
+        match self.handle_validator_announce(pubkey, stake_account).await {
+            Ok(true) => {
+                self.relay(from, Message::ValidatorAnnounce {
+                    pubkey: pubkey.to_bytes(),
+                    stake_account: stake_account.to_bytes(),
+                });
+            }
+            Ok(false) => {}
+            Err(StakeCheckError::Rpc(e)) => warn!("Could not verify validator {}: {}", pubkey, e),
+            Err(e) => {
+                warn!("Rejected validator announcement for {}: {}", pubkey, e);
+                self.record_peer_violation(from);
+            }
+        }
+    }
+
+    /// Answers request/response messages from one peer until it disconnects.
+    pub async fn serve_requests<C: Connection>(&self, conn: &mut C) -> Result<(), FrameError> {
+        while let Some(message) = read_message(conn).await? {
+            if let Ok(addr) = conn.peer_addr() {
+                self.touch_peer(addr);
+            }
+            match message {
+                Message::Ping { nonce } => write_message(conn, &Message::pong(nonce)).await?,
+                Message::GenerateRequest { request_id, prompt, max_tokens, params } => {
+                    let started = Instant::now();
+                    let result = self.handle_generate_request(prompt, max_tokens, params).await;
+                    self.metrics.record_inference(started.elapsed(), &result);
+                    let _ = self.activity.send(Activity::InferenceCompleted { request_id, succeeded: result.is_ok() });
+                    write_message(conn, &Message::GenerateResponse { request_id, result }).await?;
+                }
+                Message::InferenceRequest { job_id, executor: None, prompt, max_tokens, params } => {
+                    let result = self
+                        .submit_inference(&prompt, max_tokens, params)
+                        .await
+                        .map_err(|e| e.to_string());
+                    write_message(conn, &Message::InferenceResult { job_id, result }).await?;
+                }
+                Message::ValidatorAnnounce { pubkey, stake_account } => {
+                    if let Ok(from) = conn.peer_addr() {
+                        self.admit_announcement(Announcement {
+                            from,
+                            pubkey: Pubkey::new_from_array(pubkey),
+                            stake_account: Pubkey::new_from_array(stake_account),
+                        }).await;
+                    }
+                }
+                other => debug!("Ignoring unsolicited {:?}", other),
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks the peer on `conn` to run a generation and waits for its answer.
+    pub async fn request_generation<C: Connection>(
+        &self,
+        conn: &mut C,
+        prompt: &str,
+        max_tokens: u32,
+        params: GenerateParams,
+    ) -> Result<String, GenerateError> {
+        let request_id = self.request_ids.fetch_add(1, Ordering::Relaxed);
+        write_message(conn, &Message::GenerateRequest {
+            request_id,
+            prompt: prompt.to_string(),
+            max_tokens,
+            params,
+        }).await?;
+
+        loop {
+            match read_message(conn).await? {
+                Some(Message::GenerateResponse { request_id: id, result }) if id == request_id => {
+                    return result.map_err(GenerateError::Remote);
+                }
+                Some(other) => debug!("Skipping {:?} while awaiting generation {}", other, request_id),
+                None => return Err(GenerateError::Closed),
+            }
+        }
+    }
+
+    /// Routes a job to a connected validator that advertised inference
+    /// capacity, chosen by stake, and waits for its signed result. The job
+    /// is recorded as completed by that validator for rewards. A sampled
+    /// fraction of jobs is then re-run on a second validator in the
+    /// background, and on a third if the outputs differ; a dispute against
+    /// the outvoted executor is gossiped.
+    pub async fn submit_inference(
+        &self,
+        prompt: &str,
+        max_tokens: u32,
+        params: GenerateParams,
+    ) -> Result<InferenceOutput, InferenceError> {
+        let _work = self.begin_work().ok_or(InferenceError::ShuttingDown)?;
+        let candidates = self.inference_candidates();
+        let output = self.inference.route(&self.outboxes, &candidates, prompt, max_tokens, params.clone()).await?;
+        // Other nodes count the job towards the executor's rewards too.
+        self.gossip(Message::InferenceReceipt { accepted: self.inference.accept(output.receipt.clone()) });
+
+        if self.inference.should_verify() {
+            let original = output.receipt.clone();
+            let (inference, tx, peers) = (Arc::clone(&self.inference), self.tx.clone(), Arc::clone(&self.peers));
+            let (bans, policy) = (Arc::clone(&self.local_peer.bans), self.config().ban_policy());
+            let (outboxes, prompt) = (Arc::clone(&self.outboxes), prompt.to_string());
+            self.spawn_task(async move {
+                let Some(dispute) = inference.verify(&outboxes, &candidates, &prompt, params, original).await else {
+                    return;
+                };
+                if inference.record_dispute(dispute.clone()) {
+                    let executor = dispute.faulty.executor();
+                    warn!("Validator {} outvoted on inference job {}", executor, dispute.faulty.job_id);
+                    reputation::apply_event_to_pubkey(&peers, &bans, policy, &executor, PeerEvent::FaultyInference);
+                    let _ = tx.send(Message::InferenceDispute { dispute });
+                }
+            });
+        }
+        Ok(output)
+    }
+
+    /// Connected peers that advertised inference capacity, with their stake.
+    fn inference_candidates(&self) -> Vec<Candidate> {
+        let stakes: HashMap<Pubkey, u64> = self.validators().into_iter().map(|v| (v.pubkey, v.stake)).collect();
+        self.peers
+            .read()
+            .values()
+            .filter(|p| p.is_connected() && p.inference_capacity > 0)
+            .filter_map(|p| {
+                let pubkey = p.pubkey?;
+                Some(Candidate {
+                    pubkey,
+                    stake: stakes.get(&pubkey).copied().unwrap_or(0),
+                    capacity: p.inference_capacity,
+                    in_flight: self.inference.in_flight(&pubkey),
+                })
+            })
+            .collect()
+    }
+
+    /// Inference jobs each executor has completed, for this node or for
+    /// the requesters that gossiped them.
+    pub fn completed_inference_jobs(&self) -> HashMap<Pubkey, u64> {
+        self.inference.completed_jobs()
+    }
+
+    /// Disputes recorded since the last call, at most
+    /// `MAX_RECORDED_DISPUTES`, for the slash authority to review. Nodes
+    /// don't submit slashes themselves.
+    pub fn take_inference_disputes(&self) -> Vec<InferenceDispute> {
+        self.inference.take_disputes()
+    }
+
+    /// Emits `Ready` once stake is verified, `min_ready_peers` are connected
+    /// and any enabled model is attached, and `NotReady` if one regresses.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<NodeEvent> {
+        self.readiness.subscribe()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.readiness.is_ready()
+    }
+
+    /// Streams new blocks and transactions, peer sessions opening and
+    /// closing, and inference requests served for peers.
+    pub fn subscribe_activity(&self) -> broadcast::Receiver<Activity> {
+        self.activity.subscribe()
+    }
+
+    /// Current metrics in the Prometheus text format, with the peer and
+    /// mempool gauges read fresh.
+    pub fn render_metrics(&self) -> String {
+        {
+            let peers = self.peers.read();
+            self.metrics.known_peers.set(peers.len() as i64);
+            self.metrics.connected_peers.set(peers.values().filter(|p| p.is_connected()).count() as i64);
+        }
+        self.metrics.mempool_depth.set(self.mempool.lock().len() as i64);
+        self.metrics.encode()
+    }
+
+    fn refresh_peer_readiness(&self) {
+        let connected = self.peers.read().values().filter(|p| p.is_connected()).count();
+        self.readiness.update(|r| r.set_connected_peers(connected));
+    }
+
+    pub async fn shutdown(&self) -> Result<Vec<PhaseOutcome>, NodeError> {
+        let phase_timeout = Duration::from_millis(self.config().shutdown_phase_timeout_ms);
+        Ok(self.run_shutdown(phase_timeout).await)
+    }
+
+    /// Stops accepting peers and new work, waits up to `timeout_after` for
+    /// in-flight work to finish, then runs the remaining shutdown phases.
+    pub async fn drain(&self, timeout_after: Duration) -> Result<Vec<PhaseOutcome>, NodeError> {
+        info!("Draining node with {} tasks in flight", self.in_flight.count());
+        Ok(self.run_shutdown(timeout_after).await)
+    }
+
+    async fn run_shutdown(&self, drain_timeout: Duration) -> Vec<PhaseOutcome> {
+        let outcomes = self.shutdown_sequence(drain_timeout).run().await;
+        // Lets `start` return now that everything has been released.
+        self.stopped.send_replace(true);
+        outcomes
+    }
+
+    fn shutdown_sequence(&self, drain_timeout: Duration) -> ShutdownSequence<'_> {
+        let phase_timeout = Duration::from_millis(self.config().shutdown_phase_timeout_ms);
+
+        ShutdownSequence::new()
+            .phase(ShutdownPhase::StopAccepting, phase_timeout, async move {
+                self.in_flight.stop_accepting();
+                self.shutdown.send_replace(true);
+            }.boxed())
+            .phase(ShutdownPhase::DrainConsensus, drain_timeout, async move {
+                self.in_flight.wait_idle().await;
+            }.boxed())
+            .phase(ShutdownPhase::FlushPeers, phase_timeout, async move {
+                self.join_tasks().await;
+                self.close_sessions(phase_timeout / 2).await;
+                let mapping = self.local_peer.mapping.lock().take();
+                if let Some(mapping) = mapping {
+                    if let Err(e) = nat::release(&mapping).await {
+                        warn!("Could not release port mapping {}: {}", mapping.external, e);
+                    }
+                }
+                match self.persist_mempool().await {
+                    Ok(saved) => info!("Persisted {} pending transactions", saved),
+                    Err(e) => warn!("Failed to persist mempool: {}", e),
+                }
+                if let Some(storage) = self.storage.read().as_ref() {
+                    if let Err(e) = persist_peers(storage, &self.peers).and_then(|_| storage.flush()) {
+                        warn!("Failed to flush storage: {}", e);
+                    }
+                }
+                let flushed = self.peers.write().drain().count();
+                self.bootstrap_peers.write().clear();
+                info!("Dropped {} peer connections", flushed);
+            }.boxed())
+            .phase(ShutdownPhase::CloseRpc, phase_timeout, async move {
+                self.rpc_client.write().take();
+            }.boxed())
+            .phase(ShutdownPhase::ReleaseModel, phase_timeout, async move {
+                self.model.lock().take();
+                self.readiness.update(|r| r.set_model_loaded(false));
+            }.boxed())
+    }
+
+    async fn verify_stake(&self) -> Result<(), NodeError> {
+        if let Some(program_id) = &self.config().stake_program_id {
+            let program_id: Pubkey = program_id.parse()?;
+            let stake = verify_stake_account(
+                self.account_fetcher()?.as_ref(),
+                &program_id,
+                &self.keypair.pubkey(),
+                self.config().min_stake,
+            ).await?;
+            info!("Verified stake account holding {} lamports", stake.amount);
+            self.consensus.write().add_validator(Validator {
+                pubkey: self.keypair.pubkey(),
+                stake: stake.amount,
+                locked_until: stake.locked_until,
+            });
+            return Ok(());
+        }
+
+        warn!("No stake_program_id configured, falling back to wallet balance check");
+        let balance = self.rpc()?
+            .get_balance(&self.keypair.pubkey())
+            .await?;
+
+        let min_stake = self.config().min_stake;
+        if balance < min_stake {
+            return Err(NodeError::InsufficientStake { balance, min_stake });
+        }
+
+        Ok(())
+    }
+
+    /// Dials every bootstrap node once, in parallel. Nodes that can't be
+    /// reached are retried from the accept loop with exponential backoff.
+    async fn connect_to_bootstrap_nodes(&self) {
+        self.dialer.lock().set_targets(&self.config().bootstrap_nodes, Instant::now());
+        self.dial_due_bootstrap_nodes().await;
+    }
+
+    async fn dial_due_bootstrap_nodes(&self) {
+        let due = self.due_bootstrap_nodes();
+        futures::future::join_all(due.into_iter().map(|node| self.dial_and_record(node))).await;
+    }
+
+    /// Bootstrap nodes whose backoff has elapsed, including any whose
+    /// connection dropped since the last check.
+    fn due_bootstrap_nodes(&self) -> Vec<String> {
+        let now = Instant::now();
+        let bootstrap: Vec<(String, SocketAddr)> =
+            self.bootstrap_peers.read().iter().map(|(node, addr)| (node.clone(), *addr)).collect();
+        let lost: Vec<String> = {
+            let peers = self.peers.read();
+            bootstrap
+                .into_iter()
+                .filter(|(_, addr)| !peers.get(addr).map_or(false, |p| p.is_connected()))
+                .map(|(node, _)| node)
+                .collect()
+        };
+
+        let mut dialer = self.dialer.lock();
+        for node in lost {
+            info!("Lost connection to bootstrap node {}, redialing", node);
+            self.bootstrap_peers.write().remove(&node);
+            dialer.record_disconnect(&node, now);
+        }
+        dialer.due(now)
+    }
+
+    async fn dial_and_record(&self, node: String) -> bool {
+        match self.dial_bootstrap_node(&node).await {
+            Ok(addr) => {
+                info!("Connected to bootstrap node: {}", node);
+                self.bootstrap_peers.write().insert(node.clone(), addr);
+                self.dialer.lock().record_success(&node);
+                self.refresh_peer_readiness();
+                true
+            }
+            Err(e) if e.is_self_connection() => {
+                warn!("Bootstrap node {} is this node, not retrying", node);
+                self.dialer.lock().abandon(&node);
+                false
+            }
+            Err(e) => {
+                if let Some(delay) = self.dialer.lock().record_failure(&node, Instant::now()) {
+                    warn!("Failed to connect to bootstrap node {}: {}, retrying in {:?}", node, e, delay);
+                }
+                false
+            }
+        }
+    }
+
+    async fn dial_bootstrap_node(&self, node: &str) -> Result<SocketAddr, NetworkError> {
+        let stream = timeout(CONNECTION_TIMEOUT, TcpStream::connect(node))
+            .await
+            .map_err(|_| NetworkError::ConnectTimeout)??;
+        let addr = stream.peer_addr()?;
+        self.handle_outbound_connection(stream, Arc::clone(&self.peers)).await?;
+        Ok(addr)
+    }
+
+    /// Gossiped peers worth dialing, bounded by the free connection slots.
+    pub fn discovery_targets(&self) -> Vec<PeerRecord> {
+        let (connected, open_slots) = {
+            let peers = self.peers.read();
+            let connected: HashSet<String> = peers
+                .values()
+                .filter(|p| p.is_connected())
+                .filter_map(|p| p.node_id.clone())
+                .collect();
+            let live = peers.values().filter(|p| p.is_connected()).count();
+            (connected, (self.config().max_connections as usize).saturating_sub(live))
+        };
+        self.known_peers.lock().candidates(&connected, open_slots.min(MAX_DISCOVERY_DIALS))
+    }
+
+    /// Asks peers for the nodes they know, then dials gossiped peers while
+    /// connection slots are free. Returns how many new peers were connected.
+    pub async fn discover_peers(&self) -> usize {
+        let _ = self.tx.send(Message::GetPeers);
+
+        let targets = self.discovery_targets();
+        if targets.is_empty() {
+            return 0;
+        }
+        debug!("Dialing {} discovered peers", targets.len());
+        let dialed = futures::future::join_all(targets.into_iter().map(|target| self.dial_discovered(target))).await;
+        let connected = dialed.into_iter().filter(|ok| *ok).count();
+        if connected > 0 {
+            self.refresh_peer_readiness();
+        }
+        connected
+    }
+
+    async fn dial_discovered(&self, target: PeerRecord) -> bool {
+        let result = match timeout(CONNECTION_TIMEOUT, TcpStream::connect(target.addr)).await {
+            Ok(Ok(stream)) => self.handle_outbound_connection(stream, Arc::clone(&self.peers)).await,
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err(NetworkError::ConnectTimeout),
+        };
+        match result {
+            Ok(()) => {
+                info!("Connected to discovered peer {} at {}", target.node_id, target.addr);
+                true
+            }
+            Err(e) => {
+                debug!("Could not dial discovered peer {} at {}: {}", target.node_id, target.addr, e);
+                self.known_peers.lock().record_dial_failure(&target.node_id);
+                false
+            }
+        }
+    }
+
+    /// How often `start` checks for a stalled view: four times per
+    /// `consensus_timeout`.
+    fn view_check_every(&self) -> Duration {
+        Duration::from_millis((self.config().consensus_timeout / 4).max(1))
+    }
+
+    /// Applies the `RELOADABLE_FIELDS` of a reloaded config to the running
+    /// node. A removed `log_level` falls back to the default, and lowering
+    /// `max_connections` disconnects the lowest-quality peers over the limit.
+    /// Newly listed bootstrap nodes are dialed and, if
+    /// `disconnect_removed_bootstrap` is set, peers for dropped entries are
+    /// disconnected. Peers that did not come from the bootstrap list are left alone.
+    pub async fn reload_config(&self, new_config: NodeConfig) -> ConfigReload {
+        let current = self.config();
+        let (applied, restart_required) = current.with_reloadable(&new_config);
+        if !restart_required.is_empty() {
+            warn!("Config changes to {} take effect after a restart", restart_required.join(", "));
+        }
+        let diff = BootstrapDiff::between(&current.bootstrap_nodes, &applied.bootstrap_nodes);
+
+        // Left alone when unchanged, so a level from `RUST_LOG` survives.
+        if applied.log_level != current.log_level {
+            log::set_max_level(applied.log_level().unwrap_or(DEFAULT_LOG_LEVEL));
+        }
+        let max_peers = applied.max_connections as usize;
+        self.local_peer.max_peers.store(max_peers, Ordering::Relaxed);
+        self.consensus.write().set_consensus_timeout(Duration::from_millis(applied.consensus_timeout));
+        *self.config.write() = Arc::new(applied);
+        info!("Reloaded config: {} bootstrap nodes added, {} removed", diff.added.len(), diff.removed.len());
+
+        if self.config().disconnect_removed_bootstrap {
+            for node in &diff.removed {
+                let removed = self.bootstrap_peers.write().remove(node);
+                if let Some(addr) = removed {
+                    self.disconnect(addr, &format!("bootstrap node {} removed from config", node));
+                }
+            }
+        }
+
+        let excess: Vec<SocketAddr> = peer::rank_by_quality(self.peers.read().values(), Instant::now())
+            .into_iter()
+            .skip(max_peers)
+            .collect();
+        for addr in excess {
+            self.disconnect(addr, "over max_connections after a config reload");
+        }
+
+        self.dialer.lock().set_targets(&self.config().bootstrap_nodes, Instant::now());
+        self.dial_due_bootstrap_nodes().await;
+        for node in &diff.added {
+            if !self.dialer.lock().is_connected(node) {
+                warn!("Could not reach newly added bootstrap node {}, retrying in the background", node);
+            }
+        }
+
+        ConfigReload { bootstrap: diff, restart_required }
+    }
+
+    /// Handshakes, upgrades to the negotiated transport, authenticates and
+    /// registers a peer, returning the connection ready for
+    /// `session::run_session`.
+    async fn handle_connection<C: Connection>(
+        mut conn: C,
+        addr: SocketAddr,
+        local: Arc<LocalPeer>,
+        tx: broadcast::Sender<Message>,
+        peers: Arc<RwLock<HashMap<SocketAddr, PeerInfo>>>,
+    ) -> Result<Throttled<Secured<C>>, NetworkError> {
+        if local.bans.lock().is_banned(addr.ip(), Instant::now()) {
+            return Err(NetworkError::Banned(addr));
+        }
+        let hello = HandshakeInfo::local(&local.node_id, local.listen_port)
+            .with_transport(local.transport)
+            .with_inference_capacity(local.inference_capacity)
+            .with_observed_addr(addr)
+            .with_external_addr(*local.external_addr.read());
+        let remote = handshake::perform(&mut conn, &hello).await.map_err(|e| {
+            if matches!(e, HandshakeError::SelfConnection(_)) {
+                local.self_connections.fetch_add(1, Ordering::Relaxed);
+                warn!("Dropping self-connection via {}", addr);
+            }
+            e
+        })?;
+        let mut conn = match remote.transport {
+            TransportKind::Plain => Secured::plain(conn),
+            TransportKind::Noise => {
+                Secured::handshake(conn, hello.is_noise_initiator(&remote), &local.noise_key).await?
+            }
+        };
+        let binding = conn.handshake_hash().to_vec();
+        let pubkey = auth::authenticate(
+            &mut conn,
+            &local.keypair,
+            hello.challenge,
+            remote.challenge,
+            &binding,
+            local.authenticator.as_ref(),
+        ).await?;
+        debug!("Admitted peer {} as node {} ({})", addr, remote.node_id, pubkey);
+        if let Some(observed) = remote.observed_addr {
+            local.observed.lock().record(pubkey, observed.ip());
+        }
+
+        let mut peer = PeerInfo::new(addr);
+        peer.node_id = Some(remote.node_id);
+        peer.pubkey = Some(pubkey);
+        peer.listen_addr = Some(nat::dial_back_addr(addr, remote.listen_port, remote.external_addr));
+        peer.inference_capacity = remote.inference_capacity;
+        match peer::admit_with_eviction(&mut peers.write(), peer, local.max_peers.load(Ordering::Relaxed), Instant::now()) {
+            Ok(Some(evicted)) => info!("At peer capacity, evicted lowest-quality peer {}", evicted.addr),
+            Ok(None) => {}
+            Err(_) => return Err(NetworkError::AtCapacity(addr)),
+        }
+
+        Ok(Throttled::new(conn, local.send_limit))
+    }
+
+    async fn handle_outbound_connection(
+        &self,
+        stream: TcpStream,
+        peers: Arc<RwLock<HashMap<SocketAddr, PeerInfo>>>,
+    ) -> Result<(), NetworkError> {
+        let addr = stream.peer_addr()?;
+        configure_tcp(&stream, Duration::from_secs(self.config().liveness.timeouts().keepalive_secs))?;
+
+        let conn = Self::handle_connection(stream, addr, Arc::clone(&self.local_peer), self.tx.clone(), peers).await?;
+        spawn_session(&self.sessions, conn, addr, self.session_context());
+        Ok(())
+    }
+
+    fn session_context(&self) -> SessionContext {
+        SessionContext {
+            tx: self.tx.clone(),
+            activity: self.activity.clone(),
+            metrics: Arc::clone(&self.metrics),
+            peers: Arc::clone(&self.peers),
+            consensus: Arc::clone(&self.consensus),
+            outboxes: Arc::clone(&self.outboxes),
+            recent_messages: Arc::clone(&self.recent_messages),
+            clock_skew: Arc::clone(&self.clock_skew),
+            replay: Arc::clone(&self.replay),
+            mempool: Arc::clone(&self.mempool),
+            backpressure: Arc::clone(&self.backpressure),
+            known_peers: Arc::clone(&self.known_peers),
+            seen_announcements: Arc::clone(&self.seen_announcements),
+            bans: Arc::clone(&self.local_peer.bans),
+            ban_policy: self.config().ban_policy(),
+            inference: Arc::clone(&self.inference),
+            generation_permits: Arc::clone(&self.generation_permits),
+            training: Arc::clone(&self.training),
+            state_sync: Arc::clone(&self.state_sync),
+            announcements: self.announcements.clone(),
+            blocks: self.blocks.clone(),
+            evictions: self.evictions.clone(),
+            in_flight: Arc::clone(&self.in_flight),
+            tasks: Arc::clone(&self.tasks),
+            shutdown: self.shutdown.subscribe(),
+        }
+    }
+
+    /// Pings every connected peer and returns one entry per ping that went
+    /// unanswered. Peers that miss `max_missed` heartbeats in a row are marked
+    /// disconnected for the next sweep to drop.
+    fn probe_latency(
+        peers: &Arc<RwLock<HashMap<SocketAddr, PeerInfo>>>,
+        tx: &broadcast::Sender<Message>,
+        ping_nonce: &AtomicU64,
+        ping_timeout: Duration,
+        max_missed: u32,
+    ) -> Vec<SocketAddr> {
+        let nonce = ping_nonce.fetch_add(1, Ordering::Relaxed);
+        let sent_at = Instant::now();
+        let mut timed_out = Vec::new();
+        for peer in peers.write().values_mut().filter(|p| p.is_connected()) {
+            if let Some(deadline) = sent_at.checked_sub(ping_timeout) {
+                let expired = peer.expire_pings(deadline);
+                timed_out.extend(std::iter::repeat(peer.addr).take(expired));
+            }
+            if peer.missed_heartbeats() >= max_missed {
+                warn!("Peer {} missed {} heartbeats, marking it disconnected", peer.addr, peer.missed_heartbeats());
+                peer.mark_disconnected();
+                continue;
+            }
+            peer.record_ping_sent(nonce, sent_at);
+        }
+        let _ = tx.send(Message::Ping { nonce });
+        timed_out
+    }
+
+    async fn cleanup_disconnected_peers(peers: Arc<RwLock<HashMap<SocketAddr, PeerInfo>>>, idle: Duration) {
+        let mut peers = peers.write();
+        peers.retain(|addr, peer| {
+            if !peer.is_connected() {
+                warn!("Removing disconnected peer: {}", addr);
+                false
+            } else if peer.last_seen.elapsed() >= idle {
+                warn!("Removing peer {} idle for {:?}", addr, peer.last_seen.elapsed());
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl GradientTransport for Node {
+    fn register_trainers(&self, trainers: &[Pubkey]) {
+        self.training.set_trainers(trainers.iter().copied());
+    }
+
+    /// Participants without stake, including this node, do not count.
+    async fn all_reduce(
+        &self,
+        round: u64,
+        gradients: Vec<Gradient>,
+        participants: &[Pubkey],
+    ) -> Result<Vec<Gradient>, TrainingError> {
+        let _work = self.begin_work().ok_or(TrainingError::ShuttingDown)?;
+        let validators: HashMap<Pubkey, u64> = self.validators().into_iter().map(|v| (v.pubkey, v.stake)).collect();
+        let local = self.pubkey();
+        let stakes: HashMap<Pubkey, u64> = participants
+            .iter()
+            .chain(std::iter::once(&local))
+            .filter_map(|p| validators.get(p).map(|stake| (*p, *stake)))
+            .collect();
+        self.training.all_reduce(&self.outboxes, round, gradients, &stakes).await
+    }
+}
+
+impl SyncTarget for Node {
+    fn height(&self) -> u64 {
+        Node::height(self)
+    }
+
+    fn stake_of(&self, pubkey: &Pubkey) -> u64 {
+        self.consensus.read().validators().iter().find(|v| v.pubkey == *pubkey).map_or(0, |v| v.stake)
+    }
+
+    fn verify_headers(&self, headers: &[CertifiedHeader]) -> Result<(), ChainError> {
+        self.consensus.read().verify_header_chain(headers)
+    }
+
+    /// Applies each block like a committed one. Gossip may have moved the
+    /// tip since the headers were checked, so they are checked again.
+    fn append(&self, headers: &[CertifiedHeader], blocks: &[Block]) -> Result<(), SyncError> {
+        self.verify_headers(headers)?;
+        for (certified, block) in headers.iter().zip(blocks) {
+            self.apply_certified(block, &certified.qc)?;
+        }
+        Ok(())
+    }
+}
+
+/// The pool is only locked while it is copied; the file is written on a
+/// blocking thread so gossip and block production don't wait on the disk.
+async fn persist_mempool(mempool: &Mutex<Mempool>, storage: &Storage, max_age: Duration) -> Result<usize, MempoolError> {
+    let snapshot = {
+        let mut mempool = mempool.lock();
+        let expired = mempool.evict_expired(max_age, Instant::now());
+        if expired > 0 {
+            debug!("Expired {} stale mempool transactions", expired);
+        }
+        mempool.snapshot(SystemTime::now())
+    };
+    let path = storage.root().join(MEMPOOL_FILE);
+    tokio::task::spawn_blocking(move || snapshot.save(&path))
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+}
+
+/// Records every connected peer whose node ID and listening address are known.
+fn persist_peers(storage: &Storage, peers: &RwLock<HashMap<SocketAddr, PeerInfo>>) -> Result<(), StorageError> {
+    let records: Vec<PeerRecord> = peers
+        .read()
+        .values()
+        .filter(|p| p.is_connected())
+        .filter_map(|p| Some(PeerRecord { node_id: p.node_id.clone()?, addr: p.listen_addr? }))
+        .collect();
+    for record in &records {
+        storage.put_peer(record)?;
+    }
+    Ok(())
+}
+
+/// Broadcasts a checkpoint at the newest replayable message, under the
+/// replay lock so every session writes it after all the messages it covers.
+fn send_checkpoint(replay: &Mutex<ReplayBuffer>, tx: &broadcast::Sender<Message>) {
+    let replay = replay.lock();
+    let _ = tx.send(Message::Checkpoint { seq: replay.latest_seq() });
+}
+
+pub(crate) fn unix_now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+fn configure_tcp(stream: &TcpStream, keepalive: Duration) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+
+    let keepalive = socket2::TcpKeepalive::new()
+        .with_time(keepalive)
+        .with_interval(KEEPALIVE_PROBE_INTERVAL.min(keepalive));
+
+    socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive)
+}
+
+fn spawn_session<C: Connection>(tasks: &Mutex<JoinSet<()>>, conn: C, addr: SocketAddr, ctx: SessionContext) {
+    let mut tasks = tasks.lock();
+    reap_finished(&mut tasks);
+    let session = session::run_session(conn, addr, ctx);
+    tasks.spawn(async move {
+        if let Err(e) = session.await {
+            warn!("Session with {} failed: {}", addr, e);
+        }
+    });
+}
+
+/// Spawns `task` only if a handler permit is free. When saturated the task is
+/// dropped unpolled, which closes any socket it owns.
+fn spawn_bounded<F>(permits: &Arc<Semaphore>, tasks: &mut JoinSet<()>, task: F) -> bool
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    match Arc::clone(permits).try_acquire_owned() {
+        Ok(permit) => {
+            reap_finished(tasks);
+            tasks.spawn(async move {
+                task.await;
+                drop(permit);
+            });
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Collects tasks that already exited so the set doesn't grow unbounded and
+/// handler panics get logged promptly.
+pub(crate) fn reap_finished(tasks: &mut JoinSet<()>) {
+    while let Some(result) = tasks.try_join_next() {
+        log_task_exit(result);
+    }
+}
+
+fn log_task_exit(result: Result<(), JoinError>) {
+    if let Err(e) = result {
+        if e.is_panic() {
+            error!("Node task panicked: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::config::{EpochConfig, EpochLength, LLMConfig};
+    use crate::node::stake_check::testing::StakeLedger;
+    use crate::node::transport::MemoryConnection;
+    use solana_sdk::{hash::Hash, system_transaction};
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+    /// Plays the remote end of `handle_connection`: handshake, then auth.
+    async fn remote_peer<C: Connection>(
+        conn: &mut C,
+        node_id: &str,
+        port: u16,
+        keypair: &Keypair,
+    ) -> Result<HandshakeInfo, NetworkError> {
+        let hello = HandshakeInfo::local(node_id, port);
+        let remote = handshake::perform(conn, &hello).await?;
+        auth::authenticate(conn, keypair, hello.challenge, remote.challenge, &[], &OpenAdmission).await?;
+        Ok(remote)
+    }
+
+    /// Like `remote_peer`, but upgrades to Noise before authenticating.
+    async fn noise_remote_peer<C: Connection>(
+        mut conn: C,
+        node_id: &str,
+        port: u16,
+        keypair: &Keypair,
+    ) -> Result<(HandshakeInfo, Secured<C>), NetworkError> {
+        let hello = HandshakeInfo::local(node_id, port).with_transport(TransportKind::Noise);
+        let remote = handshake::perform(&mut conn, &hello).await?;
+        let static_key = noise::generate_static_key()?;
+        let mut conn = Secured::handshake(conn, hello.is_noise_initiator(&remote), &static_key).await?;
+        let binding = conn.handshake_hash().to_vec();
+        auth::authenticate(&mut conn, keypair, hello.challenge, remote.challenge, &binding, &OpenAdmission).await?;
+        Ok((remote, conn))
+    }
+
+    fn open_local_peer(node_id: &str, port: u16) -> Arc<LocalPeer> {
+        Arc::new(LocalPeer {
+            node_id: node_id.to_string(),
+            listen_port: port,
+            keypair: Arc::new(Keypair::new()),
+            authenticator: Arc::new(OpenAdmission),
+            transport: TransportKind::Plain,
+            noise_key: noise::generate_static_key().unwrap(),
+            max_peers: AtomicUsize::new(50),
+            send_limit: None,
+            self_connections: AtomicU64::new(0),
+            bans: Arc::new(Mutex::new(BanList::new())),
+            inference_capacity: 0,
+            external_addr: RwLock::new(None),
+            mapping: Mutex::new(None),
+            observed: Mutex::new(ObservedAddrs::default()),
+        })
+    }
+
+    async fn handshaking_listener(node_id: &'static str) -> (SocketAddr, tokio::task::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let keypair = Keypair::new();
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let _ = remote_peer(&mut socket, node_id, addr.port(), &keypair).await;
+            }
+        });
+        (addr, task)
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_bootstrap_node_retried_without_blocking() {
+        let (live_addr, _live) = handshaking_listener("live").await;
+        let down = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let down_addr = down.local_addr().unwrap();
+        drop(down);
+
+        let config = NodeConfig {
+            bootstrap_nodes: vec![down_addr.to_string(), live_addr.to_string()],
+            ..NodeConfig::default()
+        };
+        let node = Node::new(config).await.unwrap();
+        node.connect_to_bootstrap_nodes().await.unwrap();
+
+        assert!(node.dialer.lock().is_connected(&live_addr.to_string()));
+        assert!(!node.dialer.lock().is_connected(&down_addr.to_string()));
+        assert!(node.due_bootstrap_nodes().is_empty());
+
+        node.peers.write().remove(&live_addr);
+        assert_eq!(node.due_bootstrap_nodes(), vec![live_addr.to_string()]);
+        assert!(node.dial_and_record(live_addr.to_string()).await);
+        assert!(node.peers.read().contains_key(&live_addr));
+    }
+
+    #[tokio::test]
+    async fn test_reload_dials_added_bootstrap_node() {
+        let (existing_addr, _existing) = handshaking_listener("existing").await;
+        let (added_addr, _added) = handshaking_listener("added").await;
+
+        let config = NodeConfig {
+            bootstrap_nodes: vec![existing_addr.to_string()],
+            ..NodeConfig::default()
+        };
+        let node = Node::new(config.clone()).await.unwrap();
+        node.connect_to_bootstrap_nodes().await.unwrap();
+        let gossip_peer = SocketAddr::from(([10, 0, 0, 9], 8000));
+        node.peers.write().insert(gossip_peer, PeerInfo::new(gossip_peer));
+
+        let reloaded = NodeConfig {
+            bootstrap_nodes: vec![added_addr.to_string()],
+            disconnect_removed_bootstrap: true,
+            ..config
+        };
+        let reload = node.reload_config(reloaded).await;
+
+        assert_eq!(reload.bootstrap.added, vec![added_addr.to_string()]);
+        assert_eq!(reload.bootstrap.removed, vec![existing_addr.to_string()]);
+        assert!(reload.restart_required.is_empty());
+        let peers = node.peers.read();
+        assert_eq!(peers[&added_addr].node_id.as_deref(), Some("added"));
+        assert!(!peers.contains_key(&existing_addr));
+        assert!(peers.contains_key(&gossip_peer));
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_runtime_fields_only() {
+        let config = NodeConfig { max_connections: 10, consensus_timeout: 5_000, ..NodeConfig::default() };
+        let node = Node::new(config.clone()).await.unwrap();
+        let mut evictions = node.evictions.subscribe();
+        for i in 1..=5 {
+            let addr = SocketAddr::from(([10, 0, 0, i], 8000));
+            node.peers.write().insert(addr, PeerInfo::new(addr));
+        }
+
+        let reloaded = NodeConfig {
+            max_connections: 3,
+            consensus_timeout: 50,
+            port: config.port + 1,
+            ..config.clone()
+        };
+        let reload = node.reload_config(reloaded).await;
+
+        assert_eq!(reload.restart_required, vec!["port".to_string()]);
+        assert_eq!(node.config().port, config.port);
+        assert_eq!(node.config().max_connections, 3);
+        assert_eq!(node.local_peer.max_peers.load(Ordering::Relaxed), 3);
+        assert_eq!(node.peers.read().len(), 3);
+        for _ in 0..2 {
+            assert!(!node.peers.read().contains_key(&evictions.try_recv().unwrap()));
+        }
+        assert!(node.consensus.read().view_timed_out(Instant::now() + Duration::from_millis(60)));
+        assert_eq!(node.view_check_every(), Duration::from_millis(12));
+    }
+
+    #[tokio::test]
+    async fn test_skewed_peer_clocks_cause_abstention() {
+        let config = NodeConfig {
+            max_clock_offset_ms: 500,
+            abstain_on_clock_skew: true,
+            ..NodeConfig::default()
+        };
+        let node = Node::new(config).await.unwrap();
+        let addrs: Vec<SocketAddr> = (1..=3).map(|i| SocketAddr::from(([10, 0, 0, i], 8000))).collect();
+        for addr in &addrs {
+            node.peers.write().insert(*addr, PeerInfo::new(*addr));
+        }
+
+        Node::probe_latency(&node.peers, &node.tx, &node.ping_nonce, Duration::from_secs(30), 3);
+        for addr in &addrs {
+            node.handle_pong(*addr, 0, unix_now_ms() + 10_000);
+        }
+        node.clock_skew.lock().evaluate();
+
+        assert!(matches!(node.clock_status(), ClockStatus::Skewed { .. }));
+        assert!(!node.may_participate_in_consensus());
+    }
+
+    #[tokio::test]
+    async fn test_skewed_validator_sends_no_votes() {
+        let config = NodeConfig {
+            max_clock_offset_ms: 500,
+            abstain_on_clock_skew: true,
+            consensus_timeout: 50,
+            // Leaves every past slot with its leader.
+            proposer_fallback_timeout_ms: u64::MAX,
+            ..NodeConfig::default()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let node = Node::new(config).await.unwrap();
+        node.attach_storage(Storage::open(dir.path()).unwrap()).unwrap();
+        let leader = Keypair::new();
+        for pubkey in [node.pubkey(), leader.pubkey()] {
+            node.consensus.write().add_validator(Validator { pubkey, stake: 1_000, locked_until: i64::MAX });
+        }
+        let addrs: Vec<SocketAddr> = (1..=3).map(|i| SocketAddr::from(([10, 0, 0, i], 8000))).collect();
+        for addr in &addrs {
+            node.peers.write().insert(*addr, PeerInfo::new(*addr));
+        }
+        Node::probe_latency(&node.peers, &node.tx, &node.ping_nonce, Duration::from_secs(30), 3);
+        for addr in &addrs {
+            node.handle_pong(*addr, 0, unix_now_ms() + 10_000);
+        }
+        node.clock_skew.lock().evaluate();
+        assert!(!node.may_participate_in_consensus());
+        assert!(node.queue_transaction(system_transaction::transfer(&Keypair::new(), &Pubkey::new_unique(), 1, Hash::default())));
+        let mut gossip = node.tx.subscribe();
+
+        let slot = (1..).find(|slot| node.consensus.read().leader(*slot).unwrap().pubkey == leader.pubkey()).unwrap();
+        let block = Block::new(1, slot, node.consensus.read().last_block_hash(), 0, Vec::new(), &leader);
+        let payload = bincode::serialize(&block).unwrap();
+        assert!(node.receive_proposal(ReceivedBlock { from: addrs[0], slot, payload: payload.clone() }).unwrap());
+        node.produce_block((1..).find(|slot| node.is_leader(*slot)).unwrap());
+        sleep(Duration::from_millis(60)).await;
+        node.check_view_timeout();
+
+        // The block is relayed, but neither voted on nor answered with a
+        // proposal or a view change.
+        assert_eq!(gossip.try_recv().unwrap(), Message::Block { slot, payload });
+        assert!(gossip.try_recv().is_err());
+        assert!(!node.consensus.read().has_voted(&node.pubkey()));
+    }
+
+    #[tokio::test]
+    async fn test_silent_peer_disconnected_after_missed_heartbeats() {
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        let silent = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let responsive = SocketAddr::from(([10, 0, 0, 2], 8000));
+        for addr in [silent, responsive] {
+            node.peers.write().insert(addr, PeerInfo::new(addr));
+        }
+
+        for round in 0..3 {
+            let timed_out = Node::probe_latency(&node.peers, &node.tx, &node.ping_nonce, Duration::ZERO, 2);
+            node.handle_pong(responsive, round, unix_now_ms());
+            assert!(!timed_out.contains(&responsive));
+        }
+
+        let peers = node.peers();
+        let silent = peers.iter().find(|p| p.addr == silent).unwrap();
+        assert!(!silent.connected);
+        assert_eq!(silent.missed_heartbeats, 2);
+        let responsive = peers.iter().find(|p| p.addr == responsive).unwrap();
+        assert!(responsive.connected && responsive.rtt_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_drain_finishes_in_flight_work_before_shutdown() {
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        let finished = Arc::new(AtomicBool::new(false));
+
+        let work = node.begin_work().unwrap();
+        let done = Arc::clone(&finished);
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(100)).await;
+            done.store(true, Ordering::SeqCst);
+            drop(work);
+        });
+
+        node.drain(Duration::from_secs(5)).await.unwrap();
+
+        assert!(finished.load(Ordering::SeqCst));
+        assert!(node.begin_work().is_none());
+        assert!(*node.shutdown.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_phases_run_in_order_and_release_resources() {
+        let config = NodeConfig {
+            shutdown_phase_timeout_ms: 200,
+            ..NodeConfig::default()
+        };
+        let node = Node::new(config).await.unwrap();
+        let model: Arc<dyn Any + Send + Sync> = Arc::new(String::from("weights"));
+        node.attach_model(Arc::clone(&model));
+        let peer = SocketAddr::from(([10, 0, 0, 2], 8001));
+        node.peers.write().insert(peer, PeerInfo::new(peer));
+
+        let started = Instant::now();
+        let outcomes = node.shutdown().await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_millis(200 * 5));
+        let phases: Vec<ShutdownPhase> = outcomes.iter().map(|o| o.phase).collect();
+        assert_eq!(
+            phases,
+            vec![
+                ShutdownPhase::StopAccepting,
+                ShutdownPhase::DrainConsensus,
+                ShutdownPhase::FlushPeers,
+                ShutdownPhase::CloseRpc,
+                ShutdownPhase::ReleaseModel,
+            ]
+        );
+        assert!(outcomes.iter().all(|o| !o.timed_out));
+        assert!(node.peers.read().is_empty());
+        assert!(node.rpc().is_err());
+        assert_eq!(Arc::strong_count(&model), 1);
+    }
+
+    #[tokio::test]
+    async fn test_drain_gives_up_after_timeout() {
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        let _stuck = node.begin_work().unwrap();
+
+        let started = Instant::now();
+        node.drain(Duration::from_millis(50)).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(*node.shutdown.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_external_address_learned_and_advertised_through_handshakes() {
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        let local_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let seen_as = SocketAddr::from(([8, 8, 8, 9], 40000));
+        assert_eq!(node.external_addr(), None);
+
+        for (i, addr) in [SocketAddr::from(([8, 8, 4, 1], 53211)), SocketAddr::from(([8, 8, 4, 2], 53212))]
+            .into_iter()
+            .enumerate()
+        {
+            let (local_conn, mut remote_conn) = MemoryConnection::pair(local_addr, addr);
+            tokio::spawn(async move {
+                let keypair = Keypair::new();
+                let hello = HandshakeInfo::local(&format!("node-{}", i), 8000)
+                    .with_observed_addr(seen_as)
+                    .with_external_addr(Some(SocketAddr::new(addr.ip(), 18000)));
+                let remote = handshake::perform(&mut remote_conn, &hello).await.unwrap();
+                assert_eq!(remote.observed_addr, Some(addr));
+                auth::authenticate(&mut remote_conn, &keypair, hello.challenge, remote.challenge, &[], &OpenAdmission)
+                    .await
+                    .unwrap();
+            });
+            Node::handle_connection(local_conn, addr, Arc::clone(&node.local_peer), node.tx.clone(), Arc::clone(&node.peers))
+                .await
+                .unwrap();
+            assert_eq!(node.peers.read()[&addr].listen_addr, Some(SocketAddr::new(addr.ip(), 18000)));
+        }
+
+        assert_eq!(node.external_addr(), Some(SocketAddr::new(seen_as.ip(), 8000)));
+        assert_eq!(node.stats().external_addr, node.external_addr());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_node_id_drops_every_connection() {
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        let remote_keypair = Arc::new(Keypair::new());
+        let local_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let bad_addrs = [
+            SocketAddr::from(([10, 0, 0, 2], 8001)),
+            SocketAddr::from(([10, 0, 0, 2], 9001)),
+        ];
+
+        for addr in bad_addrs {
+            let (local_conn, mut remote_conn) = MemoryConnection::pair(local_addr, addr);
+            let keypair = Arc::clone(&remote_keypair);
+            tokio::spawn(async move {
+                let _ = remote_peer(&mut remote_conn, "node-bad", addr.port(), &keypair).await;
+            });
+            Node::handle_connection(local_conn, addr, Arc::clone(&node.local_peer), node.tx.clone(), Arc::clone(&node.peers))
+                .await
+                .unwrap();
+        }
+        let good_addr = SocketAddr::from(([10, 0, 0, 3], 8001));
+        let mut good = PeerInfo::new(good_addr);
+        good.node_id = Some("node-good".to_string());
+        node.peers.write().insert(good_addr, good);
+
+        assert_eq!(node.peers_by_node_id()["node-bad"].len(), 2);
+
+        let mut dropped = node.disconnect_node_id("node-bad", "sending invalid blocks");
+        dropped.sort();
+        assert_eq!(dropped, bad_addrs.to_vec());
+        assert!(!node.peers_by_node_id().contains_key("node-bad"));
+        assert_eq!(node.peers_by_node_id()["node-good"], vec![good_addr]);
+        assert!(node.disconnect_node_id("node-bad", "again").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_connection_registers_peer_after_handshake() {
+        let local_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let remote_addr = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let (local_conn, mut remote_conn) = MemoryConnection::pair(local_addr, remote_addr);
+        let peers = Arc::new(RwLock::new(HashMap::new()));
+        let (tx, _) = broadcast::channel(8);
+
+        let remote_keypair = Keypair::new();
+        let remote_pubkey = remote_keypair.pubkey();
+
+        let remote = tokio::spawn(async move {
+            remote_peer(&mut remote_conn, "node-b", 8001, &remote_keypair)
+                .await
+                .map(|info| info.node_id)
+                .map_err(|e| e.to_string())
+        });
+        Node::handle_connection(
+            local_conn,
+            remote_addr,
+            open_local_peer("node-a", 8000),
+            tx,
+            Arc::clone(&peers),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(remote.await.unwrap().unwrap(), "node-a");
+        let peers = peers.read();
+        assert_eq!(peers[&remote_addr].node_id.as_deref(), Some("node-b"));
+        assert_eq!(peers[&remote_addr].pubkey, Some(remote_pubkey));
+    }
+
+    #[tokio::test]
+    async fn test_noise_connection_authenticates_and_carries_messages() {
+        let local_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let remote_addr = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let (local_conn, remote_conn) = MemoryConnection::pair(local_addr, remote_addr);
+        let peers = Arc::new(RwLock::new(HashMap::new()));
+        let (tx, _) = broadcast::channel(8);
+        let mut local = open_local_peer("node-a", 8000);
+        Arc::get_mut(&mut local).unwrap().transport = TransportKind::Noise;
+
+        let remote_keypair = Keypair::new();
+        let remote_pubkey = remote_keypair.pubkey();
+        let remote = tokio::spawn(async move {
+            let (_, mut conn) = noise_remote_peer(remote_conn, "node-b", 8001, &remote_keypair)
+                .await
+                .map_err(|e| e.to_string())?;
+            assert!(conn.is_encrypted());
+            read_message(&mut conn).await.map_err(|e| e.to_string())
+        });
+        let mut conn = Node::handle_connection(local_conn, remote_addr, local, tx, Arc::clone(&peers))
+            .await
+            .unwrap();
+        write_message(&mut conn, &Message::Ack { seq: 9 }).await.unwrap();
+
+        assert_eq!(remote.await.unwrap().unwrap(), Some(Message::Ack { seq: 9 }));
+        assert_eq!(peers.read()[&remote_addr].pubkey, Some(remote_pubkey));
+    }
+
+    #[tokio::test]
+    async fn test_plain_peer_refused_by_noise_node() {
+        let local_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let remote_addr = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let (local_conn, mut remote_conn) = MemoryConnection::pair(local_addr, remote_addr);
+        let peers = Arc::new(RwLock::new(HashMap::new()));
+        let (tx, _) = broadcast::channel(8);
+        let mut local = open_local_peer("node-a", 8000);
+        Arc::get_mut(&mut local).unwrap().transport = TransportKind::Noise;
+
+        let remote = tokio::spawn(async move {
+            remote_peer(&mut remote_conn, "node-b", 8001, &Keypair::new()).await.is_ok()
+        });
+        let result = Node::handle_connection(local_conn, remote_addr, local, tx, Arc::clone(&peers)).await;
+
+        let err = result.err().expect("plain peer admitted over noise");
+        assert!(matches!(err, NetworkError::Handshake(HandshakeError::TransportMismatch { .. })));
+        assert!(!remote.await.unwrap());
+        assert!(peers.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_burst_respects_handler_bound() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = listener.local_addr().unwrap();
+        let permits = Arc::new(Semaphore::new(4));
+        let mut tasks = JoinSet::new();
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let burst = 32;
+        let clients: Vec<_> = (0..burst)
+            .map(|_| tokio::spawn(TcpStream::connect(local_addr)))
+            .collect();
+
+        let mut handled = 0;
+        let mut shed = 0;
+        for _ in 0..burst {
+            let (socket, _) = listener.accept().await.unwrap();
+            let active = Arc::clone(&active);
+            let peak = Arc::clone(&peak);
+            let spawned = spawn_bounded(&permits, &mut tasks, async move {
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                sleep(Duration::from_millis(50)).await;
+                active.fetch_sub(1, Ordering::SeqCst);
+                drop(socket);
+            });
+            if spawned {
+                handled += 1;
+            } else {
+                shed += 1;
+            }
+        }
+
+        for client in clients {
+            let _ = client.await;
+        }
+        sleep(Duration::from_millis(100)).await;
+
+        assert!(peak.load(Ordering::SeqCst) <= 4);
+        assert!(handled >= 4);
+        assert_eq!(handled + shed, burst);
+        assert_eq!(permits.available_permits(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_gossip_from_two_peers_is_relayed_once() {
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        let mut outbound = node.tx.subscribe();
+        let block = Message::Block { slot: 42, payload: vec![9; 64] };
+
+        assert!(node.relay(SocketAddr::from(([10, 0, 0, 1], 8000)), block.clone()));
+        assert!(!node.relay(SocketAddr::from(([10, 0, 0, 2], 8000)), block.clone()));
+
+        assert_eq!(outbound.recv().await.unwrap(), block);
+        assert!(matches!(outbound.try_recv(), Err(broadcast::error::TryRecvError::Empty)));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_aborts_and_joins_handler_tasks() {
+        struct SetOnDrop(Arc<AtomicBool>);
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        let permits = Arc::new(Semaphore::new(8));
+        let dropped: Vec<Arc<AtomicBool>> = (0..4).map(|_| Arc::new(AtomicBool::new(false))).collect();
+
+        for flag in &dropped {
+            let guard = SetOnDrop(Arc::clone(flag));
+            assert!(spawn_bounded(&permits, &mut node.tasks.lock(), async move {
+                let _guard = guard;
+                futures::future::pending::<()>().await;
+            }));
+        }
+        node.spawn_task(async { panic!("handler bug") });
+        sleep(Duration::from_millis(20)).await;
+
+        node.shutdown().await.unwrap();
+
+        assert!(dropped.iter().all(|flag| flag.load(Ordering::SeqCst)));
+        assert!(node.tasks.lock().is_empty());
+        assert_eq!(permits.available_permits(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_mempool_restored_across_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig { mempool_max_age_secs: 60, ..NodeConfig::default() };
+        let transfer = || system_transaction::transfer(&Keypair::new(), &Pubkey::new_unique(), 1_000, Hash::default());
+
+        let node = Node::new(config.clone()).await.unwrap();
+        assert_eq!(node.persist_mempool().await.unwrap(), 0);
+        node.attach_storage(Storage::open(dir.path()).unwrap()).unwrap();
+        let valid = transfer();
+        assert!(node.queue_transaction(valid.clone()));
+        let mut stale = TimestampedTransaction::new(transfer());
+        stale.timestamp = Instant::now() - Duration::from_secs(120);
+        let stale_signature = stale.transaction.signatures[0];
+        node.mempool.lock().add(stale);
+        assert_eq!(node.persist_mempool().await.unwrap(), 1);
+        drop(node);
+
+        let restarted = Node::new(config).await.unwrap();
+        assert!(restarted.pending_transactions().is_empty());
+        restarted.attach_storage(Storage::open(dir.path()).unwrap()).unwrap();
+        assert!(restarted.is_transaction_pending(&valid.signatures[0]));
+        assert!(!restarted.is_transaction_pending(&stale_signature));
+    }
+
+    #[tokio::test]
+    async fn test_proposed_block_drains_mempool_and_is_gossiped() {
+        let dir = tempfile::tempdir().unwrap();
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        node.attach_storage(Storage::open(dir.path()).unwrap()).unwrap();
+        let mut gossip = node.tx.subscribe();
+
+        let transfer = system_transaction::transfer(&Keypair::new(), &Pubkey::new_unique(), 1_000, Hash::default());
+        assert!(node.queue_transaction(transfer.clone()));
+        assert!(!node.queue_transaction(transfer.clone()));
+        assert!(matches!(gossip.recv().await.unwrap(), Message::NewTransaction { .. }));
+
+        let slot = node.current_slot();
+        let block = node.propose_block(slot, 16).unwrap();
+        assert_eq!(block.transactions, vec![transfer.clone()]);
+        assert!(!node.is_transaction_pending(&transfer.signatures[0]));
+        assert_eq!(node.stored_block(&block.hash()).unwrap(), Some(block.clone()));
+        let payload = match gossip.recv().await.unwrap() {
+            Message::Block { payload, .. } => payload,
+            other => panic!("expected a block, got {:?}", other),
+        };
+        assert_eq!(bincode::deserialize::<Block>(&payload).unwrap(), block);
+
+        // A peer following along applies the block, drops its own copy of
+        // the transaction and relays it; the proposer ignores its echo.
+        let follower_dir = tempfile::tempdir().unwrap();
+        let follower = Node::new(NodeConfig::default()).await.unwrap();
+        follower.attach_storage(Storage::open(follower_dir.path()).unwrap()).unwrap();
+        assert!(follower.queue_transaction(transfer.clone()));
+        let mut relayed = follower.tx.subscribe();
+        let from = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let received = ReceivedBlock { from, slot, payload: payload.clone() };
+        assert!(follower.receive_proposal(received.clone()).unwrap());
+        assert_eq!(follower.consensus.read().last_block_hash(), block.hash());
+        assert!(!follower.is_transaction_pending(&transfer.signatures[0]));
+        assert_eq!(relayed.recv().await.unwrap(), Message::Block { slot, payload });
+        assert!(!node.receive_proposal(received).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_proposal_commits_once_validators_vote_and_forgeries_are_penalised() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (a, b) = (Node::new(NodeConfig::default()).await.unwrap(), Node::new(NodeConfig::default()).await.unwrap());
+        a.attach_storage(Storage::open(dir_a.path()).unwrap()).unwrap();
+        b.attach_storage(Storage::open(dir_b.path()).unwrap()).unwrap();
+        for node in [&a, &b] {
+            for pubkey in [a.pubkey(), b.pubkey()] {
+                node.consensus.write().add_validator(Validator { pubkey, stake: 1_000, locked_until: i64::MAX });
+            }
+        }
+        let slot = (1..).find(|slot| a.is_leader(*slot)).unwrap();
+        let transfer = system_transaction::transfer(&Keypair::new(), &Pubkey::new_unique(), 1_000, Hash::default());
+        assert!(a.queue_transaction(transfer.clone()) && b.queue_transaction(transfer.clone()));
+
+        let mut from_a = a.tx.subscribe();
+        let block = a.propose_block(slot, 16).unwrap();
+        // One of two equal votes is no quorum, so nothing commits yet.
+        assert_eq!(a.height(), 0);
+        assert!(a.is_transaction_pending(&transfer.signatures[0]));
+        let Message::Block { payload, .. } = from_a.recv().await.unwrap() else { panic!("expected the block") };
+        let Message::BlockVote { payload: a_vote } = from_a.recv().await.unwrap() else { panic!("expected a vote") };
+
+        let forger = SocketAddr::from(([10, 0, 0, 9], 8000));
+        b.peers.write().insert(forger, PeerInfo::new(forger));
+        let mut forged = block.clone();
+        forged.header.timestamp += 1;
+        let forged = ReceivedBlock { from: forger, slot, payload: bincode::serialize(&forged).unwrap() };
+        assert!(matches!(b.receive_proposal(forged), Err(NodeError::Chain(ChainError::InvalidProposerSignature(1)))));
+        assert_eq!(b.bans().iter().map(|ban| ban.ip).collect::<Vec<_>>(), vec![forger.ip()]);
+
+        let mut from_b = b.tx.subscribe();
+        let from = SocketAddr::from(([10, 0, 0, 1], 8000));
+        assert!(b.receive_proposal(ReceivedBlock { from, slot, payload: payload.clone() }).unwrap());
+        let Message::BlockVote { payload: b_vote } = from_b.recv().await.unwrap() else { panic!("expected a vote") };
+        assert_eq!(from_b.recv().await.unwrap(), Message::Block { slot, payload });
+        assert_eq!(b.height(), 0);
+
+        assert!(b.receive_block_vote(&a_vote).unwrap());
+        assert!(a.receive_block_vote(&b_vote).unwrap());
+        for node in [&a, &b] {
+            assert_eq!(node.height(), 1);
+            assert_eq!(node.stored_block(&block.hash()).unwrap(), Some(block.clone()));
+            assert!(!node.is_transaction_pending(&transfer.signatures[0]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_joining_node_installs_snapshot_then_fetches_newer_blocks() {
+        let config = || NodeConfig { snapshot_interval_blocks: 3, ..NodeConfig::default() };
+        let server_dir = tempfile::tempdir().unwrap();
+        let server = Node::new(config()).await.unwrap();
+        server.attach_storage(Storage::open(server_dir.path()).unwrap()).unwrap();
+        for _ in 0..2 {
+            server.propose_block(server.current_slot(), 16).unwrap();
+        }
+        // Snapshots are only taken at multiples of the interval.
+        assert!(server.state_sync.latest_manifest().is_none());
+        server.propose_block(server.current_slot(), 16).unwrap();
+        // The snapshot stops at height 3; the blocks after it are fetched one by one.
+        assert_eq!(server.state_sync.latest_manifest().unwrap().height, 3);
+        for _ in 0..2 {
+            server.propose_block(server.current_slot(), 16).unwrap();
+        }
+
+        let joiner_dir = tempfile::tempdir().unwrap();
+        let joiner = Node::new(config()).await.unwrap();
+        joiner.attach_storage(Storage::open(joiner_dir.path()).unwrap()).unwrap();
+        assert_eq!(joiner.sync_state().await.unwrap(), 0);
+
+        let server_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let joiner_addr = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let mut peer = PeerInfo::new(server_addr);
+        peer.pubkey = Some(server.pubkey());
+        joiner.peers.write().insert(server_addr, peer);
+        let mut peer = PeerInfo::new(joiner_addr);
+        peer.pubkey = Some(joiner.pubkey());
+        server.peers.write().insert(joiner_addr, peer);
+        let (conn_s, conn_j) = MemoryConnection::pair(server_addr, joiner_addr);
+        spawn_session(&server.sessions, conn_s, joiner_addr, server.session_context());
+        spawn_session(&joiner.sessions, conn_j, server_addr, joiner.session_context());
+
+        assert_eq!(joiner.sync_state().await.unwrap(), 5);
+        assert_eq!(joiner.consensus.read().last_block_hash(), server.consensus.read().last_block_hash());
+        for height in 1..=5 {
+            assert_eq!(joiner.stored_block_at(height).unwrap(), server.stored_block_at(height).unwrap());
+        }
+        // Already caught up: nothing left to fetch.
+        assert_eq!(joiner.sync_state().await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_lagging_node_catches_up_on_certified_headers() {
+        let (server_dir, joiner_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (server, joiner) = (Node::new(NodeConfig::default()).await.unwrap(), Node::new(NodeConfig::default()).await.unwrap());
+        server.attach_storage(Storage::open(server_dir.path()).unwrap()).unwrap();
+        joiner.attach_storage(Storage::open(joiner_dir.path()).unwrap()).unwrap();
+        for node in [&server, &joiner] {
+            node.consensus.write().add_validator(Validator { pubkey: server.pubkey(), stake: 1_000, locked_until: i64::MAX });
+        }
+        // The server is the only validator, so its own vote commits each block.
+        for slot in 1..=5 {
+            server.propose_block(slot, 16).unwrap();
+        }
+        assert_eq!(server.height(), 5);
+        assert!(matches!(joiner.catch_up().await, Err(SyncError::NoPeers)));
+
+        let server_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let joiner_addr = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let mut peer = PeerInfo::new(server_addr);
+        peer.pubkey = Some(server.pubkey());
+        joiner.peers.write().insert(server_addr, peer);
+        let mut peer = PeerInfo::new(joiner_addr);
+        peer.pubkey = Some(joiner.pubkey());
+        server.peers.write().insert(joiner_addr, peer);
+        let (conn_s, conn_j) = MemoryConnection::pair(server_addr, joiner_addr);
+        spawn_session(&server.sessions, conn_s, joiner_addr, server.session_context());
+        spawn_session(&joiner.sessions, conn_j, server_addr, joiner.session_context());
+
+        assert_eq!(joiner.catch_up().await.unwrap(), 5);
+        assert_eq!(joiner.consensus.read().last_block_hash(), server.consensus.read().last_block_hash());
+        for height in 1..=5 {
+            assert_eq!(joiner.stored_block_at(height).unwrap(), server.stored_block_at(height).unwrap());
+        }
+        // The joiner keeps the certificates, so it can serve the chain on.
+        assert_eq!(joiner.state_sync.certified_headers(1, 16), server.state_sync.certified_headers(1, 16));
+        assert_eq!(joiner.catch_up().await.unwrap(), 5);
+    }
+
+    async fn next_view_change(gossip: &mut broadcast::Receiver<Message>) -> Vec<u8> {
+        loop {
+            if let Message::ViewChange { payload } = gossip.recv().await.unwrap() {
+                return payload;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_leader_replaced_by_view_change() {
+        let config = || NodeConfig { consensus_timeout: 50, ..NodeConfig::default() };
+        let dir = tempfile::tempdir().unwrap();
+        let a = Node::new(config()).await.unwrap();
+        a.attach_storage(Storage::open(dir.path()).unwrap()).unwrap();
+        let b = Node::new(config()).await.unwrap();
+        let offline = Pubkey::new_unique();
+        for node in [&a, &b] {
+            let mut consensus = node.consensus.write();
+            consensus.add_validator(Validator { pubkey: a.pubkey(), stake: 2_000, locked_until: i64::MAX });
+            consensus.add_validator(Validator { pubkey: b.pubkey(), stake: 1_000, locked_until: i64::MAX });
+            consensus.add_validator(Validator { pubkey: offline, stake: 1_000, locked_until: i64::MAX });
+        }
+        let stalled_slot = (0..)
+            .find(|slot| a.consensus.read().leader(*slot).unwrap().pubkey == offline)
+            .unwrap();
+        let mut a_gossip = a.tx.subscribe();
+        let mut b_gossip = b.tx.subscribe();
+
+        // Nothing pending: an idle chain doesn't time out.
+        sleep(Duration::from_millis(60)).await;
+        a.check_view_timeout();
+        assert!(a_gossip.try_recv().is_err());
+
+        assert!(a.queue_transaction(system_transaction::transfer(&Keypair::new(), &Pubkey::new_unique(), 1, Hash::default())));
+        sleep(Duration::from_millis(60)).await;
+        a.check_view_timeout();
+        assert_eq!(a.consensus.read().view(), 0);
+
+        // A's vote alone is over a third of stake, so B joins and tips it
+        // past two thirds; B's vote then moves A along.
+        assert!(b.receive_view_change(&next_view_change(&mut a_gossip).await).unwrap());
+        assert!(a.receive_view_change(&next_view_change(&mut b_gossip).await).unwrap());
+
+        for node in [&a, &b] {
+            assert_eq!(node.consensus.read().view(), 1);
+            assert_ne!(node.consensus.read().leader(stalled_slot).unwrap().pubkey, offline);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_proposer_advances_chain_while_leader_offline() {
+        let config = || NodeConfig { slot_duration_ms: 60_000, proposer_fallback_timeout_ms: 10_000, ..NodeConfig::default() };
+        let dirs: Vec<_> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+        let mut nodes = Vec::new();
+        for dir in &dirs {
+            let node = Node::new(config()).await.unwrap();
+            node.attach_storage(Storage::open(dir.path()).unwrap()).unwrap();
+            nodes.push(node);
+        }
+        let pubkeys: Vec<Pubkey> = nodes.iter().map(Node::pubkey).collect();
+        for node in &nodes {
+            for pubkey in &pubkeys {
+                node.consensus.write().add_validator(Validator { pubkey: *pubkey, stake: 1_000, locked_until: i64::MAX });
+            }
+        }
+        let slot = nodes[0].current_slot();
+        let order: Vec<Pubkey> = nodes[0].consensus.read().proposer_order(slot).iter().map(|v| v.pubkey).collect();
+        // The slot's leader never comes online.
+        let online: Vec<&Node> = order[1..].iter().map(|pubkey| nodes.iter().find(|n| n.pubkey() == *pubkey).unwrap()).collect();
+        let fallback = online[0];
+        let transfer = system_transaction::transfer(&Keypair::new(), &Pubkey::new_unique(), 1, Hash::default());
+        for node in &online {
+            assert!(node.queue_transaction(transfer.clone()));
+        }
+        let mut from_fallback = fallback.tx.subscribe();
+
+        // Still the leader's turn: the fallback holds off.
+        let slot_start_ms = slot as i64 * 60_000;
+        for node in &online {
+            node.slot_clock.align_to_reference(slot_start_ms + 5_000);
+        }
+        fallback.produce_block(slot);
+        assert!(from_fallback.try_recv().is_err());
+
+        for node in &online {
+            node.slot_clock.align_to_reference(slot_start_ms + 15_000);
+        }
+        fallback.produce_block(slot);
+        let Message::Block { payload, .. } = from_fallback.recv().await.unwrap() else { panic!("expected the block") };
+        let Message::BlockVote { payload: vote } = from_fallback.recv().await.unwrap() else { panic!("expected a vote") };
+        let mut votes = vec![vote];
+        let from = SocketAddr::from(([10, 0, 0, 1], 8000));
+        for follower in &online[1..] {
+            let mut gossip = follower.tx.subscribe();
+            assert!(follower.receive_proposal(ReceivedBlock { from, slot, payload: payload.clone() }).unwrap());
+            let Message::BlockVote { payload: vote } = gossip.recv().await.unwrap() else { panic!("expected a vote") };
+            votes.push(vote);
+        }
+
+        // Three of four equal stakes make a quorum without the leader.
+        for (i, node) in online.iter().enumerate() {
+            for (j, vote) in votes.iter().enumerate() {
+                if i != j {
+                    node.receive_block_vote(vote).unwrap();
+                }
+            }
+            assert_eq!(node.height(), 1);
+            assert!(!node.is_transaction_pending(&transfer.signatures[0]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_saturated_mempool_signals_backpressure_to_sender() {
+        let config = NodeConfig {
+            mempool_capacity: 4,
+            mempool_backpressure_high: 0.75,
+            mempool_backpressure_low: 0.5,
+            ..NodeConfig::default()
+        };
+        let node = Node::new(config).await.unwrap();
+        let local = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let sender = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let mut peer = PeerInfo::new(sender);
+        peer.pubkey = Some(Pubkey::new_unique());
+        node.peers.write().insert(sender, peer);
+        let (conn, mut remote) = MemoryConnection::pair(local, sender);
+        spawn_session(&node.sessions, conn, sender, node.session_context());
+
+        let gossip = |transaction: Transaction| Message::NewTransaction {
+            transaction: bincode::serialize(&transaction).unwrap(),
+            sent_at_ms: unix_now_ms(),
+        };
+        for _ in 0..4 {
+            let transfer = system_transaction::transfer(&Keypair::new(), &Pubkey::new_unique(), 1_000, Hash::default());
+            write_message(&mut remote, &gossip(transfer)).await.unwrap();
+        }
+        // Asked once, when the third transaction fills the pool to 75%.
+        let reply = timeout(Duration::from_secs(1), read_message(&mut remote)).await.expect("no backpressure");
+        assert_eq!(reply.unwrap(), Some(Message::Backpressure { active: true }));
+        sleep(Duration::from_millis(20)).await;
+        assert_eq!(node.pending_transactions().len(), 4);
+
+        node.take_transactions(3);
+        let reply = timeout(Duration::from_secs(1), read_message(&mut remote)).await.expect("no release");
+        assert_eq!(reply.unwrap(), Some(Message::Backpressure { active: false }));
+    }
+
+    #[tokio::test]
+    async fn test_reconnecting_peer_replayed_only_unacknowledged_broadcasts() {
+        let (a, b) = (Node::new(NodeConfig::default()).await.unwrap(), Node::new(NodeConfig::default()).await.unwrap());
+        let (addr_a, addr_b) = (SocketAddr::from(([10, 0, 0, 1], 8000)), SocketAddr::from(([10, 0, 0, 2], 8001)));
+        let connect = || {
+            let mut peer = PeerInfo::new(addr_b);
+            peer.node_id = Some("node-b".to_string());
+            peer.pubkey = Some(b.pubkey());
+            a.peers.write().insert(addr_b, peer);
+            let mut peer = PeerInfo::new(addr_a);
+            peer.node_id = Some("node-a".to_string());
+            peer.pubkey = Some(a.pubkey());
+            b.peers.write().insert(addr_a, peer);
+            let (conn_a, conn_b) = MemoryConnection::pair(addr_a, addr_b);
+            spawn_session(&a.sessions, conn_a, addr_b, a.session_context());
+            spawn_session(&b.sessions, conn_b, addr_a, b.session_context());
+        };
+        let vote = |n: u8| Message::BlockVote { payload: vec![n] };
+        let mut at_b = b.tx.subscribe();
+
+        connect();
+        a.broadcast(vote(1));
+        assert_eq!(timeout(Duration::from_secs(1), at_b.recv()).await.unwrap().unwrap(), vote(1));
+        send_checkpoint(&a.replay, &a.tx);
+        timeout(Duration::from_secs(1), async {
+            while a.replay.lock().replay_for("node-b") != Replay::UpToDate {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("B never acknowledged the checkpoint");
+
+        a.disconnect(addr_b, "test");
+        b.disconnect(addr_a, "test");
+        timeout(Duration::from_secs(1), async {
+            while a.outboxes.is_open(&b.pubkey()) || b.outboxes.is_open(&a.pubkey()) {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("sessions outlived the disconnect");
+        a.broadcast(vote(2));
+        a.broadcast(vote(3));
+        assert_eq!(a.replay.lock().replay_for("node-b"), Replay::Messages(vec![(2, vote(2)), (3, vote(3))]));
+
+        connect();
+        for expected in [vote(2), vote(3)] {
+            assert_eq!(timeout(Duration::from_secs(1), at_b.recv()).await.unwrap().unwrap(), expected);
+        }
+    }
+
+    #[derive(Debug)]
+    struct EchoGenerator;
+
+    #[async_trait::async_trait]
+    impl GenerationService for EchoGenerator {
+        async fn generate(&self, prompt: String, max_tokens: usize, _params: GenerateParams) -> Result<String, String> {
+            Ok(prompt.split_whitespace().take(max_tokens).collect::<Vec<_>>().join(" ").to_uppercase())
+        }
+
+        fn model_version(&self) -> String {
+            "echo".to_string()
+        }
+    }
+
+    async fn request_between(requester: &Node, server: &Node, prompt: &str, max_tokens: u32) -> Result<String, GenerateError> {
+        let (mut a, mut b) = MemoryConnection::pair(
+            SocketAddr::from(([10, 0, 0, 1], 8000)),
+            SocketAddr::from(([10, 0, 0, 2], 8001)),
+        );
+        let client = async move {
+            let result = requester.request_generation(&mut a, prompt, max_tokens, GenerateParams::default()).await;
+            drop(a);
+            result
+        };
+        let (result, served) = tokio::join!(client, server.serve_requests(&mut b));
+        served.unwrap();
+        result
+    }
+
+    fn llm_config() -> NodeConfig {
+        NodeConfig {
+            llm: Some(LLMConfig {
+                enabled: true,
+                model_arch: Default::default(),
+                quantization: Default::default(),
+                model_path: String::new(),
+                tokenizer_path: String::new(),
+                max_batch_size: 4,
+                use_gpu: false,
+                max_tokens: 3,
+                batch_window_ms: 20,
+                repetition_guard: false,
+                repetition_window: 64,
+                repetition_threshold: 4,
+                lora: None,
+            }),
+            ..NodeConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_peer_generation_round_trip() {
+        let requester = Node::new(NodeConfig::default()).await.unwrap();
+        let server = Node::new(llm_config()).await.unwrap();
+        server.attach_generator(Arc::new(EchoGenerator));
+
+        let output = request_between(&requester, &server, "hello from a distant peer", 100).await.unwrap();
+        assert_eq!(output, "HELLO FROM A");
+    }
+
+    #[tokio::test]
+    async fn test_inference_job_routed_to_staked_peer() {
+        let router = Node::new(NodeConfig::default()).await.unwrap();
+        let executor = Node::new(llm_config()).await.unwrap();
+        executor.attach_generator(Arc::new(EchoGenerator));
+        assert!(matches!(
+            router.submit_inference("hello", 8, GenerateParams::default()).await,
+            Err(InferenceError::NoCapablePeer)
+        ));
+
+        let router_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let executor_addr = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let mut peer = PeerInfo::new(executor_addr);
+        peer.pubkey = Some(executor.pubkey());
+        peer.inference_capacity = 4;
+        router.peers.write().insert(executor_addr, peer);
+        let mut peer = PeerInfo::new(router_addr);
+        peer.pubkey = Some(router.pubkey());
+        executor.peers.write().insert(router_addr, peer);
+        let validators = vec![
+            Validator { pubkey: executor.pubkey(), stake: 1_000, locked_until: i64::MAX },
+            Validator { pubkey: router.pubkey(), stake: 1_000, locked_until: i64::MAX },
+        ];
+        router.consensus.write().set_validators(validators.clone());
+
+        let (conn_r, conn_e) = MemoryConnection::pair(router_addr, executor_addr);
+        spawn_session(&router.sessions, conn_r, executor_addr, router.session_context());
+        spawn_session(&executor.sessions, conn_e, router_addr, executor.session_context());
+        sleep(Duration::from_millis(20)).await;
+
+        // The executor only runs jobs for staked requesters.
+        let refused = router.submit_inference("from an unknown router", 100, GenerateParams::default()).await;
+        assert!(matches!(refused, Err(InferenceError::Remote { message, .. }) if message.contains("not a staked")));
+        executor.consensus.write().set_validators(validators);
+
+        let output = router.submit_inference("routed to a staked peer", 100, GenerateParams::default()).await.unwrap();
+        assert_eq!(output.text, "ROUTED TO A");
+        assert_eq!(output.receipt.executor(), executor.pubkey());
+        assert!(output.receipt.verify("routed to a staked peer", &GenerateParams::default(), &output.text));
+        assert_eq!(router.completed_inference_jobs()[&executor.pubkey()], 1);
+        // The countersigned receipt reaches the executor's peers as well.
+        sleep(Duration::from_millis(20)).await;
+        assert_eq!(executor.completed_inference_jobs()[&executor.pubkey()], 1);
+    }
+
+    #[derive(Debug, Default)]
+    struct SlowGenerator {
+        finished: AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl GenerationService for SlowGenerator {
+        async fn generate(&self, prompt: String, _max_tokens: usize, _params: GenerateParams) -> Result<String, String> {
+            sleep(Duration::from_millis(100)).await;
+            self.finished.store(true, Ordering::SeqCst);
+            Ok(prompt)
+        }
+
+        fn model_version(&self) -> String {
+            "slow".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_job_run_for_a_peer() {
+        let router = Node::new(NodeConfig::default()).await.unwrap();
+        let executor = Node::new(llm_config()).await.unwrap();
+        let generator = Arc::new(SlowGenerator::default());
+        executor.attach_generator(Arc::clone(&generator) as Arc<dyn GenerationService>);
+
+        let router_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let executor_addr = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let mut peer = PeerInfo::new(executor_addr);
+        peer.pubkey = Some(executor.pubkey());
+        peer.inference_capacity = 4;
+        router.peers.write().insert(executor_addr, peer);
+        let mut peer = PeerInfo::new(router_addr);
+        peer.pubkey = Some(router.pubkey());
+        executor.peers.write().insert(router_addr, peer);
+        let validators = vec![
+            Validator { pubkey: executor.pubkey(), stake: 1_000, locked_until: i64::MAX },
+            Validator { pubkey: router.pubkey(), stake: 1_000, locked_until: i64::MAX },
+        ];
+        router.consensus.write().set_validators(validators.clone());
+        executor.consensus.write().set_validators(validators);
+
+        let (conn_r, conn_e) = MemoryConnection::pair(router_addr, executor_addr);
+        spawn_session(&router.sessions, conn_r, executor_addr, router.session_context());
+        spawn_session(&executor.sessions, conn_e, router_addr, executor.session_context());
+        sleep(Duration::from_millis(20)).await;
+
+        let submitted = timeout(Duration::from_secs(1), router.submit_inference("hello", 8, GenerateParams::default()));
+        let drained = async {
+            timeout(Duration::from_secs(1), async {
+                while executor.in_flight.count() == 0 {
+                    sleep(Duration::from_millis(5)).await;
+                }
+            })
+            .await
+            .expect("job never started");
+            executor.drain(Duration::from_secs(5)).await.unwrap();
+            // The job finished before the model was released.
+            assert!(generator.finished.load(Ordering::SeqCst));
+        };
+        let _ = tokio::join!(submitted, drained);
+    }
+
+    #[tokio::test]
+    async fn test_generation_refused_without_llm() {
+        let requester = Node::new(NodeConfig::default()).await.unwrap();
+        let server = Node::new(NodeConfig::default()).await.unwrap();
+
+        let result = request_between(&requester, &server, "hello", 8).await;
+        assert!(matches!(result, Err(GenerateError::Remote(msg)) if msg.contains("disabled")));
+    }
+
+    async fn deliver(node: &Node, message: Message) {
+        let (mut a, mut b) = MemoryConnection::pair(
+            SocketAddr::from(([10, 0, 0, 1], 8000)),
+            SocketAddr::from(([10, 0, 0, 2], 8001)),
+        );
+        let send = async move {
+            write_message(&mut a, &message).await.unwrap();
+        };
+        let (_, served) = tokio::join!(send, node.serve_requests(&mut b));
+        served.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_announced_validator_joins_peer_validator_sets() {
+        let program_id = Pubkey::new_unique();
+        let config = || NodeConfig {
+            stake_program_id: Some(program_id.to_string()),
+            ..NodeConfig::default()
+        };
+        let newcomer = Node::new(config()).await.unwrap();
+        let impostor = Node::new(config()).await.unwrap();
+        let peers = [Node::new(config()).await.unwrap(), Node::new(config()).await.unwrap()];
+
+        let staker = newcomer.keypair.pubkey();
+        let ledger = Arc::new(StakeLedger::new(program_id).with_stake(staker, 20_000_000_000));
+        for peer in &peers {
+            peer.attach_account_fetcher(ledger.clone());
+        }
+
+        let announcement = newcomer.validator_announcement().unwrap();
+        let mut relayed = peers[0].tx.subscribe();
+        for peer in &peers {
+            assert!(peer.validators().is_empty());
+            deliver(peer, announcement.clone()).await;
+            let validators: Vec<Pubkey> = peer.validators().iter().map(|v| v.pubkey).collect();
+            assert_eq!(validators, vec![staker]);
+        }
+        assert_eq!(relayed.recv().await.unwrap(), announcement);
+
+        deliver(&peers[0], impostor.validator_announcement().unwrap()).await;
+        assert_eq!(peers[0].validators().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_validator_set_rebuilt_from_stake_accounts_each_epoch() {
+        let program_id = Pubkey::new_unique();
+        let node = Node::new(NodeConfig {
+            stake_program_id: Some(program_id.to_string()),
+            epoch_slots: 10,
+            ..NodeConfig::default()
+        }).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        node.attach_storage(Storage::open(dir.path()).unwrap()).unwrap();
+
+        let stakers = [Pubkey::new_unique(), Pubkey::new_unique()];
+        node.attach_account_fetcher(Arc::new(
+            StakeLedger::new(program_id)
+                .with_stake(stakers[0], 30_000_000_000)
+                .with_stake(stakers[1], 10_000_000_000),
+        ));
+
+        assert!(node.refresh_validators(3).await.unwrap());
+        let mut validators: Vec<(Pubkey, u64)> = node.validators().iter().map(|v| (v.pubkey, v.stake)).collect();
+        validators.sort();
+        let mut expected = vec![(stakers[0], 30_000_000_000), (stakers[1], 10_000_000_000)];
+        expected.sort();
+        assert_eq!(validators, expected);
+        assert!(!node.refresh_validators(9).await.unwrap());
+
+        node.attach_account_fetcher(Arc::new(StakeLedger::new(program_id).with_stake(stakers[1], 10_000_000_000)));
+        assert!(node.refresh_validators(10).await.unwrap());
+        let validators: Vec<Pubkey> = node.validators().iter().map(|v| v.pubkey).collect();
+        assert_eq!(validators, vec![stakers[1]]);
+        let (_, snapshot) = node.storage().unwrap().latest_stake_snapshot().unwrap().unwrap();
+        assert_eq!(snapshot.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_self_connection_dropped_without_registering_peer() {
+        let local_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let hairpin_addr = SocketAddr::from(([203, 0, 113, 7], 8000));
+        let (local_conn, mut remote_conn) = MemoryConnection::pair(local_addr, hairpin_addr);
+        let peers = Arc::new(RwLock::new(HashMap::new()));
+        let (tx, _) = broadcast::channel(8);
+        let local = open_local_peer("node-a", 8000);
+
+        let remote = tokio::spawn(async move {
+            let keypair = Keypair::new();
+            remote_peer(&mut remote_conn, "node-a", 8000, &keypair).await.is_ok()
+        });
+        let result = Node::handle_connection(local_conn, hairpin_addr, Arc::clone(&local), tx, Arc::clone(&peers)).await;
+
+        assert!(result.unwrap_err().to_string().contains("self-connection"));
+        assert!(!remote.await.unwrap());
+        assert!(peers.read().is_empty());
+        assert_eq!(local.self_connections.load(Ordering::Relaxed), 1);
+    }
+
+    fn known(node_id: &str, addr: SocketAddr) -> PeerRecord {
+        PeerRecord { node_id: node_id.to_string(), addr }
+    }
+
+    #[tokio::test]
+    async fn test_discovery_targets_skip_connected_and_respect_max_connections() {
+        let config = NodeConfig { node_id: "node-local".to_string(), max_connections: 3, ..NodeConfig::default() };
+        let node = Node::new(config).await.unwrap();
+        let connected_addr = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let mut connected = PeerInfo::new(connected_addr);
+        connected.node_id = Some("node-a".to_string());
+        node.peers.write().insert(connected_addr, connected);
+
+        node.known_peers.lock().merge(
+            vec![
+                known("node-a", SocketAddr::from(([10, 0, 0, 9], 9001))),
+                known("node-local", SocketAddr::from(([10, 0, 0, 1], 8000))),
+                known("node-b", SocketAddr::from(([10, 0, 0, 3], 8001))),
+                known("node-c", SocketAddr::from(([10, 0, 0, 4], 8001))),
+                known("node-d", SocketAddr::from(([10, 0, 0, 5], 8001))),
+            ],
+            Instant::now(),
+        );
+
+        let targets: Vec<String> = node.discovery_targets().into_iter().map(|t| t.node_id).collect();
+        assert_eq!(targets, vec!["node-b".to_string(), "node-c".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_discover_peers_dials_gossiped_nodes() {
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        let (remote_addr, _listener) = handshaking_listener("node-gossiped").await;
+        let unreachable = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        node.known_peers.lock().merge(
+            vec![known("node-gossiped", remote_addr), known("node-gone", unreachable)],
+            Instant::now(),
+        );
+
+        assert_eq!(node.discover_peers().await, 1);
+        assert!(node.peers_by_node_id().contains_key("node-gossiped"));
+        // The failed dial is tried last next round.
+        assert_eq!(node.discovery_targets().last(), Some(&known("node-gone", unreachable)));
+    }
+
+    #[tokio::test]
+    async fn test_keypair_persists_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig {
+            keypair_path: Some(dir.path().join("identity.json").to_string_lossy().into_owned()),
+            ..NodeConfig::default()
+        };
+
+        let first = Node::new(config.clone()).await.unwrap().pubkey();
+        let restarted = Node::new(config).await.unwrap().pubkey();
+        assert_eq!(first, restarted);
+        assert_ne!(Node::new(NodeConfig::default()).await.unwrap().pubkey(), first);
+    }
+
+    #[tokio::test]
+    async fn test_start_returns_after_shutdown_and_peers_get_goodbye() {
+        let program_id = Pubkey::new_unique();
+        let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let dir = tempfile::tempdir().unwrap();
+        let node = Node::new(NodeConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            storage_path: dir.path().to_string_lossy().into_owned(),
+            bootstrap_nodes: Vec::new(),
+            stake_program_id: Some(program_id.to_string()),
+            rpc_url: Some("http://127.0.0.1:1".to_string()),
+            shutdown_phase_timeout_ms: 1_000,
+            ..NodeConfig::default()
+        }).await.unwrap();
+        node.attach_account_fetcher(Arc::new(StakeLedger::new(program_id).with_stake(node.pubkey(), 20_000_000_000)));
+
+        let peer = async {
+            let mut socket = loop {
+                match TcpStream::connect(("127.0.0.1", port)).await {
+                    Ok(socket) => break socket,
+                    Err(_) => sleep(Duration::from_millis(10)).await,
+                }
+            };
+            remote_peer(&mut socket, "node-remote", 9000, &Keypair::new()).await.unwrap();
+            while node.peers_by_node_id().is_empty() {
+                sleep(Duration::from_millis(10)).await;
+            }
+
+            node.shutdown().await.unwrap();
+            let mut goodbye = None;
+            while let Ok(Some(message)) = read_message(&mut socket).await {
+                if let Message::Goodbye { reason } = message {
+                    goodbye = Some(reason);
+                }
+            }
+            goodbye
+        };
+
+        let (started, goodbye) = timeout(Duration::from_secs(10), async { tokio::join!(node.start(), peer) })
+            .await
+            .expect("start did not return after shutdown");
+        started.unwrap();
+        assert_eq!(goodbye.as_deref(), Some("node shutting down"));
+        assert!(node.peers.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_committed_blocks_resume_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let proposer = Keypair::new();
+        let block = |height: u64, parent_hash: Hash| Block::new(height, height, parent_hash, 1_700_000_000, Vec::new(), &proposer);
+        let first = block(1, Hash::default());
+        let second = block(2, first.hash());
+
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        assert!(node.apply_block(&first).is_err(), "applied without storage");
+        node.attach_storage(Storage::open(dir.path()).unwrap()).unwrap();
+        node.apply_block(&first).unwrap();
+        assert!(node.apply_block(&block(3, second.hash())).is_err());
+        node.apply_block(&second).unwrap();
+        let peer = SocketAddr::from(([10, 0, 0, 2], 51000));
+        let mut info = PeerInfo::new(peer);
+        info.node_id = Some("node-b".to_string());
+        info.listen_addr = Some(SocketAddr::from(([10, 0, 0, 2], 8001)));
+        node.peers.write().insert(peer, info);
+        node.shutdown().await.unwrap();
+        drop(node);
+
+        let restarted = Node::new(NodeConfig::default()).await.unwrap();
+        restarted.attach_storage(Storage::open(dir.path()).unwrap()).unwrap();
+        assert_eq!(restarted.consensus.read().height(), 2);
+        assert_eq!(restarted.consensus.read().last_block_hash(), second.hash());
+        assert_eq!(restarted.stored_block(&first.hash()).unwrap(), Some(first));
+        assert_eq!(restarted.discovery_targets(), vec![PeerRecord {
+            node_id: "node-b".to_string(),
+            addr: SocketAddr::from(([10, 0, 0, 2], 8001)),
+        }]);
+    }
+
+    #[tokio::test]
+    async fn test_ended_epochs_tally_block_proposers() {
+        let dir = tempfile::tempdir().unwrap();
+        let proposer = Keypair::new();
+        let config = NodeConfig {
+            epoch: Some(EpochConfig {
+                length: EpochLength::Blocks(2),
+                rewards_per_epoch: 1_000,
+                block_weight: 10,
+                vote_weight: 1,
+                inference_weight: 5,
+                distribute: false,
+            }),
+            ..NodeConfig::default()
+        };
+        let node = Node::new(config).await.unwrap();
+        node.attach_storage(Storage::open(dir.path()).unwrap()).unwrap();
+
+        let mut parent_hash = Hash::default();
+        for height in 1..=4 {
+            let block = Block::new(height, height, parent_hash, 1_700_000_000, Vec::new(), &proposer);
+            node.apply_block(&block).unwrap();
+            parent_hash = block.hash();
+        }
+
+        let ended = node.end_epochs();
+        assert_eq!(ended.iter().map(|summary| summary.epoch).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(ended[0].participation[&proposer.pubkey()].blocks_proposed, 1);
+        assert_eq!(ended[1].participation[&proposer.pubkey()].blocks_proposed, 2);
+        assert!(node.end_epochs().is_empty());
+        assert!(Node::new(NodeConfig::default()).await.unwrap().end_epochs().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_banned_peer_refused_on_reconnect() {
+        let node = Node::new(NodeConfig::default()).await.unwrap();
+        let local_addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let bad = SocketAddr::from(([10, 0, 0, 2], 8001));
+        node.peers.write().insert(bad, PeerInfo::new(bad));
+
+        assert!(!node.record_peer_event(bad, PeerEvent::MalformedMessage));
+        assert_eq!(node.peers()[0].score, -10);
+        assert!(node.record_peer_event(bad, PeerEvent::InvalidBlock));
+        assert!(node.peers().is_empty());
+        assert_eq!(node.bans().iter().map(|b| b.ip).collect::<Vec<_>>(), vec![bad.ip()]);
+
+        let reconnect = SocketAddr::from(([10, 0, 0, 2], 9001));
+        let connect = |addr| {
+            let (local_conn, mut remote_conn) = MemoryConnection::pair(local_addr, addr);
+            tokio::spawn(async move {
+                let _ = remote_peer(&mut remote_conn, "node-bad", 9001, &Keypair::new()).await;
+            });
+            Node::handle_connection(local_conn, addr, Arc::clone(&node.local_peer), node.tx.clone(), Arc::clone(&node.peers))
+        };
+        assert!(connect(reconnect).await.is_err());
+        assert!(node.peers().is_empty());
+
+        assert!(node.unban(bad.ip()));
+        assert!(connect(reconnect).await.is_ok());
+        assert_eq!(node.peers()[0].score, 0);
+    }
+
+    #[tokio::test]
+    async fn test_inbound_connections_limited_per_ip() {
+        let node = Node::new(NodeConfig {
+            max_inbound_per_ip: 1,
+            inbound_attempts_per_ip_per_min: 2,
+            ..NodeConfig::default()
+        }).await.unwrap();
+        let connected = SocketAddr::from(([10, 0, 0, 2], 8001));
+        node.peers.write().insert(connected, PeerInfo::new(connected));
+
+        let second = SocketAddr::from(([10, 0, 0, 2], 9001));
+        assert_eq!(node.screen_inbound(second), Err(InboundRefusal::TooManyFromIp(1)));
+
+        let flooder = SocketAddr::from(([10, 0, 0, 3], 9001));
+        assert!(node.screen_inbound(flooder).is_ok());
+        assert!(node.screen_inbound(flooder).is_ok());
+        assert_eq!(node.screen_inbound(flooder), Err(InboundRefusal::RateLimited(2)));
+        assert!(node.screen_inbound(SocketAddr::from(([10, 0, 0, 4], 9001))).is_ok());
+
+        assert_eq!(node.stats().inbound_refused, 2);
     }
 }
@@ -0,0 +1,271 @@
+use snow::{Builder, HandshakeState, TransportState};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::{timeout, Duration};
+
+use crate::node::transport::Connection;
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+const NOISE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_NOISE_MESSAGE: usize = 65535;
+const TAG_LEN: usize = 16;
+const MAX_PLAINTEXT: usize = MAX_NOISE_MESSAGE - TAG_LEN;
+const READ_CHUNK: usize = 8 * 1024;
+
+#[derive(Error, Debug)]
+pub enum NoiseError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Noise error: {0}")]
+    Noise(#[from] snow::Error),
+    #[error("Noise handshake timed out")]
+    Timeout,
+}
+
+fn builder() -> Builder<'static> {
+    Builder::new(NOISE_PARAMS.parse().expect("valid noise parameters"))
+}
+
+/// Generates the X25519 static private key a node uses for Noise sessions.
+pub fn generate_static_key() -> Result<Vec<u8>, NoiseError> {
+    Ok(builder().generate_keypair()?.private)
+}
+
+/// A connection that is either plain or encrypted with a Noise session.
+/// Encrypted bytes travel as frames of a 2-byte big-endian length followed
+/// by one Noise message.
+pub struct Secured<C> {
+    inner: C,
+    session: Option<Box<TransportState>>,
+    handshake_hash: Vec<u8>,
+    // Ciphertext read from `inner` that doesn't yet form a whole frame.
+    read_raw: Vec<u8>,
+    read_plain: Vec<u8>,
+    read_pos: usize,
+    // One encrypted frame being written, and the plaintext length it carries.
+    write_frame: Vec<u8>,
+    write_pos: usize,
+    write_len: usize,
+}
+
+impl<C> Secured<C> {
+    pub fn plain(inner: C) -> Self {
+        Secured {
+            inner,
+            session: None,
+            handshake_hash: Vec::new(),
+            read_raw: Vec::new(),
+            read_plain: Vec::new(),
+            read_pos: 0,
+            write_frame: Vec::new(),
+            write_pos: 0,
+            write_len: 0,
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Hash of the Noise handshake, unique to this session; empty when plain.
+    /// Signing it ties a node identity to this very channel.
+    pub fn handshake_hash(&self) -> &[u8] {
+        &self.handshake_hash
+    }
+}
+
+impl<C: Connection> Secured<C> {
+    /// Runs a Noise XX handshake over `inner`. Exactly one side must be the
+    /// `initiator`.
+    pub async fn handshake(inner: C, initiator: bool, static_key: &[u8]) -> Result<Self, NoiseError> {
+        let builder = builder().local_private_key(static_key);
+        let state = if initiator { builder.build_initiator()? } else { builder.build_responder()? };
+        timeout(NOISE_HANDSHAKE_TIMEOUT, Self::exchange(inner, state))
+            .await
+            .map_err(|_| NoiseError::Timeout)?
+    }
+
+    async fn exchange(mut inner: C, mut state: HandshakeState) -> Result<Self, NoiseError> {
+        let mut buf = vec![0u8; MAX_NOISE_MESSAGE];
+        let mut payload = vec![0u8; MAX_NOISE_MESSAGE];
+        while !state.is_handshake_finished() {
+            if state.is_my_turn() {
+                let len = state.write_message(&[], &mut buf)?;
+                inner.write_all(&(len as u16).to_be_bytes()).await?;
+                inner.write_all(&buf[..len]).await?;
+                inner.flush().await?;
+            } else {
+                let mut len = [0u8; 2];
+                inner.read_exact(&mut len).await?;
+                let len = u16::from_be_bytes(len) as usize;
+                inner.read_exact(&mut buf[..len]).await?;
+                state.read_message(&buf[..len], &mut payload)?;
+            }
+        }
+
+        let handshake_hash = state.get_handshake_hash().to_vec();
+        let mut secured = Secured::plain(inner);
+        secured.session = Some(Box::new(state.into_transport_mode()?));
+        secured.handshake_hash = handshake_hash;
+        Ok(secured)
+    }
+}
+
+impl<C: Connection> Connection for Secured<C> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+}
+
+fn invalid_data(e: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl<C: AsyncRead + Unpin> AsyncRead for Secured<C> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let Some(session) = this.session.as_mut() else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+
+        loop {
+            if this.read_pos < this.read_plain.len() {
+                let n = buf.remaining().min(this.read_plain.len() - this.read_pos);
+                buf.put_slice(&this.read_plain[this.read_pos..this.read_pos + n]);
+                this.read_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.read_raw.len() >= 2 {
+                let len = u16::from_be_bytes([this.read_raw[0], this.read_raw[1]]) as usize;
+                if this.read_raw.len() >= 2 + len {
+                    this.read_plain.resize(MAX_NOISE_MESSAGE, 0);
+                    let n = session
+                        .read_message(&this.read_raw[2..2 + len], &mut this.read_plain)
+                        .map_err(invalid_data)?;
+                    this.read_plain.truncate(n);
+                    this.read_pos = 0;
+                    this.read_raw.drain(..2 + len);
+                    continue;
+                }
+            }
+
+            let mut chunk = [0u8; READ_CHUNK];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                if this.read_raw.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            this.read_raw.extend_from_slice(chunk_buf.filled());
+        }
+    }
+}
+
+impl<C: AsyncWrite + Unpin> AsyncWrite for Secured<C> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let Some(session) = this.session.as_mut() else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+
+        // A frame left over from a write that returned Pending holds the
+        // caller's retried bytes, so finish it rather than encrypting again.
+        if this.write_pos == this.write_frame.len() {
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            let len = buf.len().min(MAX_PLAINTEXT);
+            this.write_frame.resize(2 + len + TAG_LEN, 0);
+            let n = session
+                .write_message(&buf[..len], &mut this.write_frame[2..])
+                .map_err(invalid_data)?;
+            this.write_frame[..2].copy_from_slice(&(n as u16).to_be_bytes());
+            this.write_frame.truncate(2 + n);
+            this.write_pos = 0;
+            this.write_len = len;
+        }
+
+        while this.write_pos < this.write_frame.len() {
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &this.write_frame[this.write_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            this.write_pos += n;
+        }
+        Poll::Ready(Ok(this.write_len))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::framing::{read_message, write_message};
+    use crate::node::message::Message;
+    use crate::node::transport::MemoryConnection;
+
+    fn pair() -> (MemoryConnection, MemoryConnection) {
+        MemoryConnection::pair(SocketAddr::from(([10, 0, 0, 1], 8000)), SocketAddr::from(([10, 0, 0, 2], 8001)))
+    }
+
+    async fn secured_pair() -> (Secured<MemoryConnection>, Secured<MemoryConnection>) {
+        let (a, b) = pair();
+        let (key_a, key_b) = (generate_static_key().unwrap(), generate_static_key().unwrap());
+        let (a, b) = tokio::join!(Secured::handshake(a, true, &key_a), Secured::handshake(b, false, &key_b));
+        (a.unwrap(), b.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_noise_round_trip() {
+        let (mut a, mut b) = secured_pair().await;
+        assert!(a.is_encrypted());
+        assert!(!a.handshake_hash().is_empty());
+        assert_eq!(a.handshake_hash(), b.handshake_hash());
+
+        // Larger than one Noise message, so it spans several frames.
+        let block = Message::Block { slot: 7, payload: vec![0xab; 200_000] };
+        let (sent, received) = tokio::join!(write_message(&mut a, &block), read_message(&mut b));
+        sent.unwrap();
+        assert_eq!(received.unwrap(), Some(block));
+
+        drop(a);
+        assert_eq!(read_message(&mut b).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_noise_rejects_tampered_frame() {
+        let (mut a, mut b) = secured_pair().await;
+
+        let mut frame = Vec::new();
+        write_message(&mut frame, &Message::Ack { seq: 1 }).await.unwrap();
+        let mut cipher = vec![0u8; frame.len() + TAG_LEN];
+        let n = a.session.as_mut().unwrap().write_message(&frame, &mut cipher).unwrap();
+        cipher[0] ^= 1;
+        a.inner.write_all(&(n as u16).to_be_bytes()).await.unwrap();
+        a.inner.write_all(&cipher[..n]).await.unwrap();
+
+        assert!(read_message(&mut b).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_noise_fails_when_both_sides_initiate() {
+        let (a, b) = pair();
+        let (key_a, key_b) = (generate_static_key().unwrap(), generate_static_key().unwrap());
+        let (a, b) = tokio::join!(Secured::handshake(a, true, &key_a), Secured::handshake(b, true, &key_b));
+        assert!(a.is_err() || b.is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use parking_lot::RwLock;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+
+use crate::node::message::Message;
+
+/// The queue into each open session, by the peer's authenticated key, for
+/// messages meant for that peer alone. Everything else goes to every
+/// session over the broadcast channel.
+#[derive(Debug, Default)]
+pub struct Outboxes {
+    sessions: RwLock<HashMap<Pubkey, (SocketAddr, mpsc::Sender<Message>)>>,
+}
+
+impl Outboxes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces any earlier session with the same peer.
+    pub fn open(&self, pubkey: Pubkey, addr: SocketAddr, queue: mpsc::Sender<Message>) {
+        self.sessions.write().insert(pubkey, (addr, queue));
+    }
+
+    /// Leaves a newer session with the same peer open.
+    pub fn close(&self, pubkey: &Pubkey, addr: SocketAddr) {
+        let mut sessions = self.sessions.write();
+        if sessions.get(pubkey).map_or(false, |(open, _)| *open == addr) {
+            sessions.remove(pubkey);
+        }
+    }
+
+    /// Queues `message` for the session with `pubkey`. Returns `false` if
+    /// there is none or it ended.
+    pub async fn send(&self, pubkey: &Pubkey, message: Message) -> bool {
+        let Some(queue) = self.sessions.read().get(pubkey).map(|(_, queue)| queue.clone()) else {
+            return false;
+        };
+        queue.send(message).await.is_ok()
+    }
+
+    pub fn is_open(&self, pubkey: &Pubkey) -> bool {
+        self.sessions.read().contains_key(pubkey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_message_reaches_only_the_addressed_session() {
+        let outboxes = Outboxes::new();
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (queue_a, mut received_a) = mpsc::channel(4);
+        let (queue_b, mut received_b) = mpsc::channel(4);
+        outboxes.open(a, SocketAddr::from(([10, 0, 0, 1], 8000)), queue_a);
+        outboxes.open(b, SocketAddr::from(([10, 0, 0, 2], 8000)), queue_b);
+
+        assert!(outboxes.send(&a, Message::GetPeers).await);
+        assert_eq!(received_a.try_recv().unwrap(), Message::GetPeers);
+        assert!(received_b.try_recv().is_err());
+        assert!(!outboxes.send(&Pubkey::new_unique(), Message::GetPeers).await);
+    }
+
+    #[tokio::test]
+    async fn test_closing_a_replaced_session_keeps_the_newer_one() {
+        let outboxes = Outboxes::new();
+        let peer = Pubkey::new_unique();
+        let (old, new) = (SocketAddr::from(([10, 0, 0, 1], 8000)), SocketAddr::from(([10, 0, 0, 1], 8001)));
+        outboxes.open(peer, old, mpsc::channel(4).0);
+        let (queue, mut received) = mpsc::channel(4);
+        outboxes.open(peer, new, queue);
+
+        outboxes.close(&peer, old);
+        assert!(outboxes.send(&peer, Message::GetPeers).await);
+        assert_eq!(received.try_recv().unwrap(), Message::GetPeers);
+        outboxes.close(&peer, new);
+        assert!(!outboxes.is_open(&peer));
+    }
+}
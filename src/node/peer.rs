@@ -0,0 +1,357 @@
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::node::reputation::{PeerEvent, PeerScore};
+
+const RTT_EWMA_ALPHA: f64 = 0.2;
+const MAX_PENDING_PINGS: usize = 16;
+/// RTT, connection age and violation count at which each quality component
+/// drops to one half.
+const QUALITY_RTT_MIDPOINT_MS: f64 = 100.0;
+const QUALITY_UPTIME_MIDPOINT_SECS: f64 = 300.0;
+const QUALITY_VIOLATION_MIDPOINT: f64 = 1.0;
+const QUALITY_WEIGHTS: (f64, f64, f64) = (0.4, 0.3, 0.3);
+
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub addr: SocketAddr,
+    pub node_id: Option<String>,
+    pub pubkey: Option<Pubkey>,
+    /// Where the peer accepts connections, from its handshake.
+    pub listen_addr: Option<SocketAddr>,
+    /// Inference jobs the peer runs at once, from its handshake.
+    pub inference_capacity: u32,
+    pub connected_at: Instant,
+    pub last_seen: Instant,
+    connected: bool,
+    rtt_ewma: Option<Duration>,
+    pending_pings: HashMap<u64, Instant>,
+    missed_heartbeats: u32,
+    violations: u32,
+    score: PeerScore,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerSnapshot {
+    pub addr: SocketAddr,
+    pub node_id: Option<String>,
+    pub connected: bool,
+    pub connected_secs: u64,
+    pub last_seen_secs: u64,
+    pub rtt_ms: Option<f64>,
+    pub missed_heartbeats: u32,
+    pub violations: u32,
+    pub score: i32,
+    pub quality: f64,
+}
+
+impl PeerInfo {
+    pub fn new(addr: SocketAddr) -> Self {
+        let now = Instant::now();
+        PeerInfo {
+            addr,
+            node_id: None,
+            pubkey: None,
+            listen_addr: None,
+            inference_capacity: 0,
+            connected_at: now,
+            last_seen: now,
+            connected: true,
+            rtt_ewma: None,
+            pending_pings: HashMap::new(),
+            missed_heartbeats: 0,
+            violations: 0,
+            score: PeerScore::default(),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    pub fn mark_disconnected(&mut self) {
+        self.connected = false;
+    }
+
+    pub fn touch(&mut self) {
+        self.last_seen = Instant::now();
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt_ewma
+    }
+
+    pub fn violations(&self) -> u32 {
+        self.violations
+    }
+
+    /// Heartbeats that went unanswered since the last pong.
+    pub fn missed_heartbeats(&self) -> u32 {
+        self.missed_heartbeats
+    }
+
+    pub fn record_violation(&mut self) {
+        self.record_event(PeerEvent::MalformedMessage);
+    }
+
+    pub fn score(&self) -> i32 {
+        self.score.value()
+    }
+
+    /// Adjusts the peer's score, counting violations towards its quality too.
+    /// Returns the new score.
+    pub fn record_event(&mut self, event: PeerEvent) -> i32 {
+        if event.is_violation() {
+            self.violations = self.violations.saturating_add(1);
+        }
+        self.score.record(event)
+    }
+
+    /// Normalized score in `[0, 1]` combining latency, connection age and
+    /// protocol violations. Peers without an RTT sample get a neutral latency
+    /// component; disconnected peers score zero.
+    pub fn quality_score(&self, now: Instant) -> f64 {
+        if !self.connected {
+            return 0.0;
+        }
+
+        let latency = match self.rtt_ewma {
+            Some(rtt) => 1.0 / (1.0 + rtt.as_secs_f64() * 1000.0 / QUALITY_RTT_MIDPOINT_MS),
+            None => 0.5,
+        };
+        let age = now.saturating_duration_since(self.connected_at).as_secs_f64();
+        let uptime = age / (age + QUALITY_UPTIME_MIDPOINT_SECS);
+        let reliability = 1.0 / (1.0 + self.violations as f64 / QUALITY_VIOLATION_MIDPOINT);
+
+        let (w_latency, w_uptime, w_reliability) = QUALITY_WEIGHTS;
+        w_latency * latency + w_uptime * uptime + w_reliability * reliability
+    }
+
+    pub fn record_ping_sent(&mut self, nonce: u64, sent_at: Instant) {
+        if self.pending_pings.len() >= MAX_PENDING_PINGS {
+            if let Some(oldest) = self.pending_pings
+                .iter()
+                .min_by_key(|(_, sent)| **sent)
+                .map(|(nonce, _)| *nonce)
+            {
+                self.pending_pings.remove(&oldest);
+            }
+        }
+        self.pending_pings.insert(nonce, sent_at);
+    }
+
+    /// Forgets pings sent before `deadline`, counting them as missed
+    /// heartbeats, and returns how many went unanswered.
+    pub fn expire_pings(&mut self, deadline: Instant) -> usize {
+        let before = self.pending_pings.len();
+        self.pending_pings.retain(|_, sent| *sent >= deadline);
+        let expired = before - self.pending_pings.len();
+        self.missed_heartbeats = self.missed_heartbeats.saturating_add(expired as u32);
+        expired
+    }
+
+    pub fn record_pong(&mut self, nonce: u64, received_at: Instant) -> Option<Duration> {
+        let sent_at = self.pending_pings.remove(&nonce)?;
+        let sample = received_at.saturating_duration_since(sent_at);
+
+        self.rtt_ewma = Some(match self.rtt_ewma {
+            Some(current) => current.mul_f64(1.0 - RTT_EWMA_ALPHA) + sample.mul_f64(RTT_EWMA_ALPHA),
+            None => sample,
+        });
+        self.last_seen = received_at;
+        self.missed_heartbeats = 0;
+
+        self.rtt_ewma
+    }
+
+    pub fn snapshot(&self) -> PeerSnapshot {
+        PeerSnapshot {
+            addr: self.addr,
+            node_id: self.node_id.clone(),
+            connected: self.connected,
+            connected_secs: self.connected_at.elapsed().as_secs(),
+            last_seen_secs: self.last_seen.elapsed().as_secs(),
+            rtt_ms: self.rtt_ewma.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            missed_heartbeats: self.missed_heartbeats,
+            violations: self.violations,
+            score: self.score.value(),
+            quality: self.quality_score(Instant::now()),
+        }
+    }
+}
+
+pub fn rank_by_latency<'a, I>(peers: I) -> Vec<SocketAddr>
+where
+    I: IntoIterator<Item = &'a PeerInfo>,
+{
+    let mut ranked: Vec<&PeerInfo> = peers
+        .into_iter()
+        .filter(|p| p.is_connected())
+        .collect();
+    ranked.sort_by_key(|p| p.rtt().unwrap_or(Duration::MAX));
+    ranked.into_iter().map(|p| p.addr).collect()
+}
+
+pub fn rank_by_quality<'a, I>(peers: I, now: Instant) -> Vec<SocketAddr>
+where
+    I: IntoIterator<Item = &'a PeerInfo>,
+{
+    let mut ranked: Vec<(f64, SocketAddr)> = peers
+        .into_iter()
+        .filter(|p| p.is_connected())
+        .map(|p| (p.quality_score(now), p.addr))
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranked.into_iter().map(|(_, addr)| addr).collect()
+}
+
+/// Inserts `candidate`, evicting the lowest-quality peer if the table is at
+/// `capacity` and the candidate scores higher. Returns the evicted peer, or
+/// the candidate back if it was refused.
+pub fn admit_with_eviction(
+    peers: &mut HashMap<SocketAddr, PeerInfo>,
+    candidate: PeerInfo,
+    capacity: usize,
+    now: Instant,
+) -> Result<Option<PeerInfo>, PeerInfo> {
+    if peers.len() < capacity || peers.contains_key(&candidate.addr) {
+        peers.insert(candidate.addr, candidate);
+        return Ok(None);
+    }
+
+    let worst = peers
+        .values()
+        .map(|p| (p.quality_score(now), p.addr))
+        .min_by(|a, b| a.0.total_cmp(&b.0));
+    match worst {
+        Some((score, addr)) if score < candidate.quality_score(now) => {
+            let evicted = peers.remove(&addr);
+            peers.insert(candidate.addr, candidate);
+            Ok(evicted)
+        }
+        _ => Err(candidate),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_rtt_reflects_pong_delay() {
+        let mut peer = PeerInfo::new(addr(9000));
+        let start = Instant::now();
+
+        peer.record_ping_sent(1, start);
+        let rtt = peer.record_pong(1, start + Duration::from_millis(120)).unwrap();
+        assert_eq!(rtt, Duration::from_millis(120));
+
+        peer.record_ping_sent(2, start);
+        let rtt = peer.record_pong(2, start + Duration::from_millis(220)).unwrap();
+        assert!((rtt.as_secs_f64() * 1000.0 - 140.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_unknown_pong_ignored() {
+        let mut peer = PeerInfo::new(addr(9000));
+        assert!(peer.record_pong(42, Instant::now()).is_none());
+        assert!(peer.rtt().is_none());
+    }
+
+    #[test]
+    fn test_missed_heartbeats_reset_by_pong() {
+        let mut peer = PeerInfo::new(addr(9000));
+        let start = Instant::now();
+
+        peer.record_ping_sent(1, start);
+        peer.record_ping_sent(2, start + Duration::from_secs(10));
+        peer.record_ping_sent(3, start + Duration::from_secs(20));
+        assert_eq!(peer.expire_pings(start + Duration::from_secs(15)), 2);
+        assert_eq!(peer.missed_heartbeats(), 2);
+
+        peer.record_pong(3, start + Duration::from_secs(21));
+        assert_eq!(peer.missed_heartbeats(), 0);
+        assert_eq!(peer.snapshot().missed_heartbeats, 0);
+    }
+
+    #[test]
+    fn test_rank_prefers_low_latency() {
+        let start = Instant::now();
+        let mut slow = PeerInfo::new(addr(9001));
+        slow.record_ping_sent(1, start);
+        slow.record_pong(1, start + Duration::from_millis(300));
+
+        let mut fast = PeerInfo::new(addr(9002));
+        fast.record_ping_sent(1, start);
+        fast.record_pong(1, start + Duration::from_millis(20));
+
+        let unmeasured = PeerInfo::new(addr(9003));
+
+        let ranked = rank_by_latency(vec![&unmeasured, &slow, &fast]);
+        assert_eq!(ranked, vec![addr(9002), addr(9001), addr(9003)]);
+    }
+
+    fn measured(port: u16, rtt_ms: u64, age: Duration, violations: u32, now: Instant) -> PeerInfo {
+        let mut peer = PeerInfo::new(addr(port));
+        peer.connected_at = now - age;
+        peer.record_ping_sent(1, now);
+        peer.record_pong(1, now + Duration::from_millis(rtt_ms));
+        for _ in 0..violations {
+            peer.record_violation();
+        }
+        peer
+    }
+
+    #[test]
+    fn test_quality_ranks_fast_stable_clean_peers_first() {
+        let now = Instant::now();
+        let good = measured(9001, 20, Duration::from_secs(3600), 0, now);
+        let laggy = measured(9002, 400, Duration::from_secs(3600), 0, now);
+        let misbehaving = measured(9003, 20, Duration::from_secs(3600), 5, now);
+        let fresh = measured(9004, 20, Duration::ZERO, 0, now);
+        let worst = measured(9005, 900, Duration::ZERO, 3, now);
+
+        for peer in [&good, &laggy, &misbehaving, &fresh, &worst] {
+            let score = peer.quality_score(now);
+            assert!((0.0..=1.0).contains(&score));
+        }
+        for peer in [&laggy, &misbehaving, &fresh] {
+            assert!(good.quality_score(now) > peer.quality_score(now));
+            assert!(peer.quality_score(now) > worst.quality_score(now));
+        }
+
+        let ranked = rank_by_quality(vec![&laggy, &worst, &misbehaving, &fresh, &good], now);
+        assert_eq!(ranked[0], addr(9001));
+        assert_eq!(ranked.last(), Some(&addr(9005)));
+    }
+
+    #[test]
+    fn test_lowest_quality_peer_evicted_at_capacity() {
+        let now = Instant::now();
+        let mut peers = HashMap::new();
+        for peer in [
+            measured(9001, 20, Duration::from_secs(3600), 0, now),
+            measured(9002, 900, Duration::from_secs(60), 3, now),
+            measured(9003, 50, Duration::from_secs(1800), 0, now),
+        ] {
+            peers.insert(peer.addr, peer);
+        }
+
+        let better = measured(9004, 30, Duration::from_secs(3600), 0, now);
+        let evicted = admit_with_eviction(&mut peers, better, 3, now).unwrap();
+        assert_eq!(evicted.map(|p| p.addr), Some(addr(9002)));
+        assert!(peers.contains_key(&addr(9004)));
+        assert_eq!(peers.len(), 3);
+
+        let worse = measured(9005, 2000, Duration::ZERO, 10, now);
+        assert!(admit_with_eviction(&mut peers, worse, 3, now).is_err());
+        assert!(!peers.contains_key(&addr(9005)));
+    }
+}
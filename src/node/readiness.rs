@@ -0,0 +1,180 @@
+use log::info;
+use parking_lot::Mutex;
+use tokio::sync::broadcast;
+
+const EVENT_CHANNEL_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadinessCondition {
+    StakeVerified,
+    MinPeers { connected: usize, required: usize },
+    ModelLoaded,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeEvent {
+    Ready,
+    NotReady { missing: Vec<ReadinessCondition> },
+}
+
+/// Combines the node's startup conditions into a single ready flag. Each
+/// setter returns the event to emit when the combined state flips.
+#[derive(Debug, Clone)]
+pub struct Readiness {
+    min_peers: usize,
+    require_model: bool,
+    stake_verified: bool,
+    connected_peers: usize,
+    model_loaded: bool,
+    ready: bool,
+}
+
+impl Readiness {
+    pub fn new(min_peers: usize, require_model: bool) -> Self {
+        Readiness {
+            min_peers,
+            require_model,
+            stake_verified: false,
+            connected_peers: 0,
+            model_loaded: false,
+            ready: false,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    pub fn missing(&self) -> Vec<ReadinessCondition> {
+        let mut missing = Vec::new();
+        if !self.stake_verified {
+            missing.push(ReadinessCondition::StakeVerified);
+        }
+        if self.connected_peers < self.min_peers {
+            missing.push(ReadinessCondition::MinPeers {
+                connected: self.connected_peers,
+                required: self.min_peers,
+            });
+        }
+        if self.require_model && !self.model_loaded {
+            missing.push(ReadinessCondition::ModelLoaded);
+        }
+        missing
+    }
+
+    pub fn set_stake_verified(&mut self, verified: bool) -> Option<NodeEvent> {
+        self.stake_verified = verified;
+        self.transition()
+    }
+
+    pub fn set_connected_peers(&mut self, connected: usize) -> Option<NodeEvent> {
+        self.connected_peers = connected;
+        self.transition()
+    }
+
+    pub fn set_model_loaded(&mut self, loaded: bool) -> Option<NodeEvent> {
+        self.model_loaded = loaded;
+        self.transition()
+    }
+
+    fn transition(&mut self) -> Option<NodeEvent> {
+        let missing = self.missing();
+        match (self.ready, missing.is_empty()) {
+            (false, true) => {
+                self.ready = true;
+                Some(NodeEvent::Ready)
+            }
+            (true, false) => {
+                self.ready = false;
+                Some(NodeEvent::NotReady { missing })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Shared `Readiness` that broadcasts its transitions to subscribers.
+#[derive(Debug)]
+pub struct ReadinessTracker {
+    state: Mutex<Readiness>,
+    events: broadcast::Sender<NodeEvent>,
+}
+
+impl ReadinessTracker {
+    pub fn new(readiness: Readiness) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        ReadinessTracker {
+            state: Mutex::new(readiness),
+            events,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state.lock().is_ready()
+    }
+
+    pub fn update<F>(&self, change: F)
+    where
+        F: FnOnce(&mut Readiness) -> Option<NodeEvent>,
+    {
+        let event = change(&mut self.state.lock());
+        if let Some(event) = event {
+            info!("Node readiness changed: {:?}", event);
+            let _ = self.events.send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ready_fires_once_when_all_conditions_hold() {
+        let tracker = ReadinessTracker::new(Readiness::new(2, true));
+        let mut events = tracker.subscribe();
+
+        tracker.update(|r| r.set_stake_verified(true));
+        tracker.update(|r| r.set_connected_peers(1));
+        tracker.update(|r| r.set_model_loaded(true));
+        assert!(events.try_recv().is_err());
+
+        tracker.update(|r| r.set_connected_peers(2));
+        tracker.update(|r| r.set_connected_peers(3));
+        tracker.update(|r| r.set_stake_verified(true));
+
+        assert_eq!(events.try_recv().unwrap(), NodeEvent::Ready);
+        assert!(events.try_recv().is_err());
+        assert!(tracker.is_ready());
+    }
+
+    #[test]
+    fn test_regression_emits_not_ready_then_ready_again() {
+        let mut readiness = Readiness::new(1, false);
+        assert_eq!(readiness.set_stake_verified(true), None);
+        assert_eq!(readiness.set_connected_peers(1), Some(NodeEvent::Ready));
+
+        assert_eq!(
+            readiness.set_connected_peers(0),
+            Some(NodeEvent::NotReady {
+                missing: vec![ReadinessCondition::MinPeers { connected: 0, required: 1 }],
+            })
+        );
+        assert_eq!(readiness.set_connected_peers(0), None);
+        assert_eq!(readiness.set_connected_peers(4), Some(NodeEvent::Ready));
+    }
+
+    #[test]
+    fn test_model_only_required_when_enabled() {
+        let mut readiness = Readiness::new(0, false);
+        assert_eq!(readiness.set_stake_verified(true), Some(NodeEvent::Ready));
+
+        let mut readiness = Readiness::new(0, true);
+        assert_eq!(readiness.set_stake_verified(true), None);
+        assert_eq!(readiness.missing(), vec![ReadinessCondition::ModelLoaded]);
+    }
+}
@@ -0,0 +1,172 @@
+use log::{debug, warn};
+use std::collections::{HashMap, VecDeque};
+
+use crate::node::message::Message;
+
+pub const DEFAULT_REPLAY_WINDOW: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Replay {
+    UpToDate,
+    Messages(Vec<(u64, Message)>),
+    Gap { last_acked: u64, oldest_available: u64 },
+    UnknownPeer,
+}
+
+#[derive(Debug)]
+pub struct ReplayBuffer {
+    history: VecDeque<(u64, Message)>,
+    window: usize,
+    next_seq: u64,
+    acked: HashMap<String, u64>,
+}
+
+impl ReplayBuffer {
+    pub fn new(window: usize) -> Self {
+        ReplayBuffer {
+            history: VecDeque::with_capacity(window),
+            window: window.max(1),
+            next_seq: 1,
+            acked: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, message: Message) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        if self.history.len() == self.window {
+            self.history.pop_front();
+        }
+        self.history.push_back((seq, message));
+        seq
+    }
+
+    pub fn latest_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    pub fn register_peer(&mut self, node_id: &str) {
+        let latest = self.latest_seq();
+        self.acked.entry(node_id.to_string()).or_insert(latest);
+    }
+
+    pub fn ack(&mut self, node_id: &str, seq: u64) {
+        let entry = self.acked.entry(node_id.to_string()).or_insert(0);
+        if seq > *entry {
+            *entry = seq.min(self.latest_seq());
+        }
+    }
+
+    pub fn forget_peer(&mut self, node_id: &str) {
+        self.acked.remove(node_id);
+    }
+
+    pub fn replay_for(&self, node_id: &str) -> Replay {
+        let last_acked = match self.acked.get(node_id) {
+            Some(seq) => *seq,
+            None => return Replay::UnknownPeer,
+        };
+
+        if last_acked >= self.latest_seq() {
+            return Replay::UpToDate;
+        }
+
+        let oldest_available = match self.history.front() {
+            Some((seq, _)) => *seq,
+            None => return Replay::UpToDate,
+        };
+        if last_acked + 1 < oldest_available {
+            return Replay::Gap { last_acked, oldest_available };
+        }
+
+        Replay::Messages(
+            self.history
+                .iter()
+                .filter(|(seq, _)| *seq > last_acked)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// What to send `node_id` as its session opens. A peer seen for the
+    /// first time, or one that fell out of the window, starts from now.
+    pub fn resync(&mut self, node_id: &str) -> Replay {
+        let plan = self.replay_for(node_id);
+        match &plan {
+            Replay::UnknownPeer => self.register_peer(node_id),
+            Replay::Gap { last_acked, oldest_available } => {
+                warn!(
+                    "Peer {} missed messages beyond the replay window (acked {}, oldest {})",
+                    node_id, last_acked, oldest_available
+                );
+                self.forget_peer(node_id);
+                self.register_peer(node_id);
+            }
+            Replay::Messages(messages) => {
+                debug!("Replaying {} missed messages to {}", messages.len(), node_id);
+            }
+            Replay::UpToDate => {}
+        }
+        plan
+    }
+}
+
+impl Default for ReplayBuffer {
+    fn default() -> Self {
+        ReplayBuffer::new(DEFAULT_REPLAY_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ping(nonce: u64) -> Message {
+        Message::Ping { nonce }
+    }
+
+    #[test]
+    fn test_reconnecting_peer_receives_only_missed_messages() {
+        let mut buffer = ReplayBuffer::new(16);
+        buffer.register_peer("node-b");
+
+        buffer.push(ping(1));
+        let second = buffer.push(ping(2));
+        buffer.ack("node-b", second);
+
+        // node-b drops here and misses the next two broadcasts.
+        let third = buffer.push(ping(3));
+        let fourth = buffer.push(ping(4));
+
+        assert_eq!(
+            buffer.replay_for("node-b"),
+            Replay::Messages(vec![(third, ping(3)), (fourth, ping(4))])
+        );
+
+        buffer.ack("node-b", fourth);
+        assert_eq!(buffer.replay_for("node-b"), Replay::UpToDate);
+    }
+
+    #[test]
+    fn test_replay_window_is_bounded() {
+        let mut buffer = ReplayBuffer::new(4);
+        buffer.register_peer("node-b");
+
+        for nonce in 0..10 {
+            buffer.push(ping(nonce));
+        }
+
+        assert_eq!(
+            buffer.replay_for("node-b"),
+            Replay::Gap { last_acked: 0, oldest_available: 7 }
+        );
+    }
+
+    #[test]
+    fn test_unknown_peer_gets_no_history() {
+        let mut buffer = ReplayBuffer::new(4);
+        buffer.push(ping(1));
+        assert_eq!(buffer.replay_for("stranger"), Replay::UnknownPeer);
+    }
+}
@@ -0,0 +1,208 @@
+use log::warn;
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::node::peer::PeerInfo;
+
+pub const MIN_SCORE: i32 = -100;
+pub const MAX_SCORE: i32 = 100;
+
+/// Peer behaviour that moves its score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerEvent {
+    MalformedMessage,
+    Timeout,
+    InvalidBlock,
+    /// Signed an inference result a second validator contradicted.
+    FaultyInference,
+    UsefulResponse,
+}
+
+impl PeerEvent {
+    pub fn delta(self) -> i32 {
+        match self {
+            PeerEvent::MalformedMessage => -10,
+            PeerEvent::Timeout => -5,
+            PeerEvent::InvalidBlock => -50,
+            PeerEvent::FaultyInference => -50,
+            PeerEvent::UsefulResponse => 1,
+        }
+    }
+
+    pub fn is_violation(self) -> bool {
+        matches!(self, PeerEvent::MalformedMessage | PeerEvent::InvalidBlock | PeerEvent::FaultyInference)
+    }
+}
+
+/// Running reputation of one connection, starting at zero and clamped to
+/// `[MIN_SCORE, MAX_SCORE]` so old good behaviour can't shield a peer forever.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerScore(i32);
+
+impl PeerScore {
+    pub fn value(self) -> i32 {
+        self.0
+    }
+
+    pub fn record(&mut self, event: PeerEvent) -> i32 {
+        self.0 = (self.0 + event.delta()).clamp(MIN_SCORE, MAX_SCORE);
+        self.0
+    }
+}
+
+/// Score at or below which a peer is banned, and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BanPolicy {
+    pub threshold: i32,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BanEntry {
+    pub ip: IpAddr,
+    pub remaining_secs: u64,
+}
+
+/// Banned peer IPs. Bans are by address rather than node ID, which a peer
+/// can change at will.
+#[derive(Debug, Default)]
+pub struct BanList {
+    bans: HashMap<IpAddr, Instant>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        BanList::default()
+    }
+
+    pub fn ban(&mut self, ip: IpAddr, duration: Duration, now: Instant) {
+        let until = now + duration;
+        let entry = self.bans.entry(ip).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+
+    pub fn unban(&mut self, ip: IpAddr) -> bool {
+        self.bans.remove(&ip).is_some()
+    }
+
+    pub fn is_banned(&mut self, ip: IpAddr, now: Instant) -> bool {
+        match self.bans.get(&ip) {
+            Some(until) if *until > now => true,
+            Some(_) => {
+                self.bans.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn active(&self, now: Instant) -> Vec<BanEntry> {
+        let mut entries: Vec<BanEntry> = self
+            .bans
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(ip, until)| BanEntry {
+                ip: *ip,
+                remaining_secs: until.saturating_duration_since(now).as_secs(),
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.ip);
+        entries
+    }
+}
+
+/// Applies `event` to the peer at `addr`. A peer whose score falls to the
+/// policy threshold is removed and its IP banned. Returns whether it was.
+pub(crate) fn apply_event(
+    peers: &RwLock<HashMap<SocketAddr, PeerInfo>>,
+    bans: &Mutex<BanList>,
+    policy: BanPolicy,
+    addr: SocketAddr,
+    event: PeerEvent,
+) -> bool {
+    let mut peers = peers.write();
+    let Some(peer) = peers.get_mut(&addr) else {
+        return false;
+    };
+    let score = peer.record_event(event);
+    if score > policy.threshold {
+        return false;
+    }
+
+    peers.remove(&addr);
+    bans.lock().ban(addr.ip(), policy.duration, Instant::now());
+    warn!("Banned {} for {:?}: score {} after {:?}", addr.ip(), policy.duration, score, event);
+    true
+}
+
+/// `apply_event` for every connection authenticated as `pubkey`. Returns
+/// how many were penalized.
+pub(crate) fn apply_event_to_pubkey(
+    peers: &RwLock<HashMap<SocketAddr, PeerInfo>>,
+    bans: &Mutex<BanList>,
+    policy: BanPolicy,
+    pubkey: &Pubkey,
+    event: PeerEvent,
+) -> usize {
+    let addrs: Vec<SocketAddr> = peers
+        .read()
+        .values()
+        .filter(|peer| peer.pubkey.as_ref() == Some(pubkey))
+        .map(|peer| peer.addr)
+        .collect();
+    for &addr in &addrs {
+        apply_event(peers, bans, policy, addr, event);
+    }
+    addrs.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: BanPolicy = BanPolicy {
+        threshold: -30,
+        duration: Duration::from_secs(60),
+    };
+
+    #[test]
+    fn test_score_is_clamped() {
+        let mut score = PeerScore::default();
+        for _ in 0..500 {
+            score.record(PeerEvent::UsefulResponse);
+        }
+        assert_eq!(score.value(), MAX_SCORE);
+        for _ in 0..10 {
+            score.record(PeerEvent::InvalidBlock);
+        }
+        assert_eq!(score.value(), MIN_SCORE);
+    }
+
+    #[test]
+    fn test_misbehaving_peer_banned_until_expiry() {
+        let bad = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let good = SocketAddr::from(([10, 0, 0, 3], 8001));
+        let peers = RwLock::new(HashMap::from([(bad, PeerInfo::new(bad)), (good, PeerInfo::new(good))]));
+        let bans = Mutex::new(BanList::new());
+
+        assert!(!apply_event(&peers, &bans, POLICY, bad, PeerEvent::MalformedMessage));
+        assert!(!apply_event(&peers, &bans, POLICY, bad, PeerEvent::Timeout));
+        assert!(!apply_event(&peers, &bans, POLICY, good, PeerEvent::Timeout));
+        assert!(apply_event(&peers, &bans, POLICY, bad, PeerEvent::InvalidBlock));
+
+        assert!(!peers.read().contains_key(&bad));
+        assert!(peers.read().contains_key(&good));
+
+        let now = Instant::now();
+        let mut bans = bans.into_inner();
+        assert!(bans.is_banned(bad.ip(), now));
+        assert!(!bans.is_banned(good.ip(), now));
+        assert_eq!(bans.active(now).len(), 1);
+        assert!(!bans.is_banned(bad.ip(), now + POLICY.duration));
+        assert!(bans.active(now + POLICY.duration).is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+use async_trait::async_trait;
+use prometheus::IntCounterVec;
+use solana_client::client_error::{ClientErrorKind, Result as ClientResult};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::RpcClientConfig;
+use solana_client::rpc_request::{RpcError, RpcRequest};
+use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::node::config::Commitment;
+
+/// HTTP sender that bounds both the request time and the response body size,
+/// so a stalled or misbehaving RPC endpoint fails fast instead of hanging the node.
+pub struct BoundedHttpSender {
+    client: reqwest::Client,
+    url: String,
+    max_response_bytes: usize,
+    request_id: AtomicU64,
+    failures: Option<IntCounterVec>,
+}
+
+impl BoundedHttpSender {
+    pub fn new(url: String, timeout: Duration, max_response_bytes: usize) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .connect_timeout(timeout)
+            .build()
+            .expect("reqwest client");
+
+        BoundedHttpSender {
+            client,
+            url,
+            max_response_bytes,
+            request_id: AtomicU64::new(0),
+            failures: None,
+        }
+    }
+
+    /// Counts failed calls in `failures`, labelled by RPC method.
+    pub fn with_failure_counter(mut self, failures: IntCounterVec) -> Self {
+        self.failures = Some(failures);
+        self
+    }
+
+    fn too_large(&self, size: u64) -> ClientErrorKind {
+        ClientErrorKind::Custom(format!(
+            "RPC response of {} bytes exceeds limit of {} bytes",
+            size, self.max_response_bytes
+        ))
+    }
+}
+
+#[async_trait]
+impl RpcSender for BoundedHttpSender {
+    async fn send(&self, request: RpcRequest, params: serde_json::Value) -> ClientResult<serde_json::Value> {
+        let result = self.send_bounded(request, params).await;
+        if let (Err(_), Some(failures)) = (&result, &self.failures) {
+            failures.with_label_values(&[&request.to_string()]).inc();
+        }
+        result
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+}
+
+impl BoundedHttpSender {
+    async fn send_bounded(&self, request: RpcRequest, params: serde_json::Value) -> ClientResult<serde_json::Value> {
+        let id = self.request_id.fetch_add(1, Ordering::Relaxed);
+        let body = request.build_request_json(id, params);
+
+        let mut response = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        if let Some(length) = response.content_length() {
+            if length > self.max_response_bytes as u64 {
+                return Err(self.too_large(length).into());
+            }
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if bytes.len() + chunk.len() > self.max_response_bytes {
+                return Err(self.too_large((bytes.len() + chunk.len()) as u64).into());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        let mut json: serde_json::Value = serde_json::from_slice(&bytes)?;
+        if let Some(error) = json.get("error") {
+            return Err(RpcError::RpcRequestError(format!("{} failed: {}", request, error)).into());
+        }
+        Ok(json["result"].take())
+    }
+}
+
+impl From<Commitment> for CommitmentConfig {
+    fn from(commitment: Commitment) -> Self {
+        match commitment {
+            Commitment::Processed => CommitmentConfig::processed(),
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+}
+
+pub fn build_rpc_client(
+    url: &str,
+    commitment: Commitment,
+    timeout: Duration,
+    max_response_bytes: usize,
+    failures: Option<IntCounterVec>,
+) -> RpcClient {
+    let mut sender = BoundedHttpSender::new(url.to_string(), timeout, max_response_bytes);
+    if let Some(failures) = failures {
+        sender = sender.with_failure_counter(failures);
+    }
+    RpcClient::new_sender(sender, RpcClientConfig::with_commitment(commitment.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::metrics::NodeMetrics;
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    async fn mock_server(delay: Duration, body: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let body = body.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    let _ = socket.read(&mut buf).await;
+                    tokio::time::sleep(delay).await;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_rpc_call_times_out_at_configured_bound() {
+        let url = mock_server(Duration::from_secs(30), r#"{"jsonrpc":"2.0","result":1,"id":0}"#.to_string()).await;
+        let metrics = NodeMetrics::new();
+        let client = build_rpc_client(
+            &url,
+            Commitment::Confirmed,
+            Duration::from_millis(200),
+            1024,
+            Some(metrics.rpc_failures.clone()),
+        );
+
+        let started = Instant::now();
+        let result = client.get_slot().await;
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+        assert_eq!(metrics.rpc_failures.with_label_values(&["getSlot"]).get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rpc_response_over_size_limit_rejected() {
+        let padding = "x".repeat(4096);
+        let body = format!(r#"{{"jsonrpc":"2.0","result":42,"id":0,"pad":"{}"}}"#, padding);
+        let url = mock_server(Duration::ZERO, body).await;
+
+        let bounded = build_rpc_client(&url, Commitment::Confirmed, Duration::from_secs(5), 1024, None);
+        assert!(bounded.get_slot().await.is_err());
+
+        let roomy = build_rpc_client(&url, Commitment::Confirmed, Duration::from_secs(5), 64 * 1024, None);
+        assert_eq!(roomy.get_slot().await.unwrap(), 42);
+    }
+}
@@ -0,0 +1,985 @@
+use log::{debug, warn};
+use parking_lot::{Mutex, RwLock};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::Transaction;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{split, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc, watch, Semaphore};
+use tokio::task::JoinSet;
+
+use crate::node::activity::Activity;
+use crate::node::bloom::SeenFilter;
+use crate::node::clock_sync::ClockSkewMonitor;
+use crate::node::consensus::{ConsensusManager, TimestampedTransaction};
+use crate::node::dedup::{self, RecentMessages};
+use crate::node::drain::InFlight;
+use crate::node::framing::{read_message, write_message, FrameError};
+use crate::node::gossip::{self, KnownPeers, MAX_SHARED_PEERS};
+use crate::node::inference::InferenceMarket;
+use crate::node::mempool::{Backpressure, Mempool};
+use crate::node::metrics::NodeMetrics;
+use crate::node::message::Message;
+use crate::node::network::{reap_finished, unix_now_ms};
+use crate::node::outbox::Outboxes;
+use crate::node::peer::PeerInfo;
+use crate::node::replay::{Replay, ReplayBuffer};
+use crate::node::reputation::{self, BanList, BanPolicy, PeerEvent};
+use crate::node::snapshot::StateSync;
+use crate::node::training::GradientExchange;
+use crate::node::transport::Connection;
+
+const REPLY_QUEUE_CAPACITY: usize = 32;
+// Remembers what a peer sent so it isn't echoed straight back to it.
+const ECHO_WINDOW: usize = 4096;
+const ECHO_TTL: Duration = Duration::from_secs(60);
+/// While a peer has asked to slow down, at most one transaction per interval
+/// is forwarded to it; it hears of the rest from its other peers.
+const SLOWED_FORWARD_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A validator announcement read from the peer at `from`. Sessions can't
+/// check stake on chain, so they hand announcements to the node, which
+/// relays the ones that hold up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Announcement {
+    pub from: SocketAddr,
+    pub pubkey: Pubkey,
+    pub stake_account: Pubkey,
+}
+
+/// A block read from the peer at `from`. Only the node can check it against
+/// the chain, so sessions hand blocks over and the node relays the ones
+/// that verify.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedBlock {
+    pub from: SocketAddr,
+    pub slot: u64,
+    pub payload: Vec<u8>,
+}
+
+/// Paces the transactions forwarded to a peer that sent `Backpressure`.
+#[derive(Debug, Default)]
+struct ForwardPacing {
+    slowed: bool,
+    last_forwarded: Option<Instant>,
+}
+
+impl ForwardPacing {
+    fn set(&mut self, slowed: bool) {
+        self.slowed = slowed;
+    }
+
+    /// Whether a transaction may be forwarded at `now`.
+    fn allow(&mut self, now: Instant) -> bool {
+        if !self.slowed {
+            return true;
+        }
+        if self.last_forwarded.map_or(false, |last| now.saturating_duration_since(last) < SLOWED_FORWARD_INTERVAL) {
+            return false;
+        }
+        self.last_forwarded = Some(now);
+        true
+    }
+}
+
+/// Node state every peer session reads and updates.
+#[derive(Debug, Clone)]
+pub struct SessionContext {
+    pub tx: broadcast::Sender<Message>,
+    pub activity: broadcast::Sender<Activity>,
+    pub metrics: Arc<NodeMetrics>,
+    pub peers: Arc<RwLock<HashMap<SocketAddr, PeerInfo>>>,
+    /// Read for the validator set, to check who may route jobs here.
+    pub consensus: Arc<RwLock<ConsensusManager>>,
+    /// Where messages for a single peer are queued.
+    pub outboxes: Arc<Outboxes>,
+    pub recent_messages: Arc<Mutex<RecentMessages>>,
+    pub clock_skew: Arc<Mutex<ClockSkewMonitor>>,
+    pub replay: Arc<Mutex<ReplayBuffer>>,
+    pub mempool: Arc<Mutex<Mempool>>,
+    /// Peers told to slow down while the mempool is near full.
+    pub backpressure: Arc<Backpressure>,
+    pub known_peers: Arc<Mutex<KnownPeers>>,
+    /// Peer announcements already merged, so a list relayed by many peers
+    /// is merged once.
+    pub seen_announcements: Arc<Mutex<SeenFilter>>,
+    pub bans: Arc<Mutex<BanList>>,
+    pub ban_policy: BanPolicy,
+    pub inference: Arc<InferenceMarket>,
+    /// Bounds peer generation requests running at once to the inference
+    /// capacity advertised in the handshake.
+    pub generation_permits: Arc<Semaphore>,
+    pub training: Arc<GradientExchange>,
+    pub state_sync: Arc<StateSync>,
+    pub announcements: mpsc::Sender<Announcement>,
+    pub blocks: mpsc::Sender<ReceivedBlock>,
+    /// Addresses the node dropped from `peers`, so their sessions close
+    /// without waiting for the peer to speak.
+    pub evictions: broadcast::Sender<SocketAddr>,
+    pub in_flight: Arc<InFlight>,
+    /// The node's task set, so shutdown joins work a session started.
+    pub tasks: Arc<Mutex<JoinSet<()>>>,
+    pub shutdown: watch::Receiver<bool>,
+}
+
+impl SessionContext {
+    /// Runs `task` in the node's task set so shutdown can abort and join it.
+    fn spawn_task<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock();
+        reap_finished(&mut tasks);
+        tasks.spawn(task);
+    }
+}
+
+/// Exchanges messages with an admitted peer until either side disconnects or
+/// the node shuts down. Everything on the broadcast channel is written to the
+/// peer; gossip read from it is deduplicated across peers and dispatched back
+/// into the channel for every other session.
+///
+/// The session subscribes to the channel and opens its outbox when called
+/// rather than when first polled, so nothing sent after the peer is admitted
+/// is missed. Messages in the outbox go to this peer alone. A peer whose
+/// node ID was seen before first gets the broadcasts it hasn't acknowledged.
+pub fn run_session<C: Connection>(
+    conn: C,
+    addr: SocketAddr,
+    ctx: SessionContext,
+) -> impl Future<Output = Result<(), FrameError>> {
+    let node_id = ctx.peers.read().get(&addr).and_then(|p| p.node_id.clone());
+    // Broadcasts go out under the replay lock, so what the replay leaves out
+    // arrives on the new subscription and nothing arrives twice.
+    let (outbound, missed) = {
+        let mut replay = ctx.replay.lock();
+        let missed = match node_id.as_deref().map(|node_id| replay.resync(node_id)) {
+            Some(Replay::Messages(messages)) => messages.into_iter().map(|(_, message)| message).collect(),
+            _ => Vec::new(),
+        };
+        (ctx.tx.subscribe(), missed)
+    };
+    let evictions = ctx.evictions.subscribe();
+    let (reply_tx, replies) = mpsc::channel(REPLY_QUEUE_CAPACITY);
+    let pubkey = ctx.peers.read().get(&addr).and_then(|p| p.pubkey);
+    if let Some(pubkey) = pubkey {
+        ctx.outboxes.open(pubkey, addr, reply_tx.clone());
+    }
+    session_loop(conn, addr, ctx, (outbound, missed), evictions, (reply_tx, replies), (pubkey, node_id))
+}
+
+async fn session_loop<C: Connection>(
+    conn: C,
+    addr: SocketAddr,
+    ctx: SessionContext,
+    (mut outbound, missed): (broadcast::Receiver<Message>, Vec<Message>),
+    mut evictions: broadcast::Receiver<SocketAddr>,
+    (reply_tx, mut replies): (mpsc::Sender<Message>, mpsc::Receiver<Message>),
+    (pubkey, node_id): (Option<Pubkey>, Option<String>),
+) -> Result<(), FrameError> {
+    let (mut reader, mut writer) = split(conn);
+    let mut shutdown = ctx.shutdown.clone();
+    let from_peer = Mutex::new(RecentMessages::new(ECHO_WINDOW, ECHO_TTL));
+    let pacing = Mutex::new(ForwardPacing::default());
+    ctx.metrics.connections_opened.inc();
+    let _ = ctx.activity.send(Activity::PeerConnected { addr });
+
+    // Reads and writes run as separate loops because reading a frame is not
+    // cancel-safe; replies cross over through a queue.
+    let reading = async {
+        loop {
+            let message = match read_message(&mut reader).await {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(e) => {
+                    if !matches!(e, FrameError::Io(_)) {
+                        reputation::apply_event(&ctx.peers, &ctx.bans, ctx.ban_policy, addr, PeerEvent::MalformedMessage);
+                    }
+                    return Err(e);
+                }
+            };
+            if let Message::Goodbye { reason } = &message {
+                debug!("Peer {} closed the session: {}", addr, reason);
+                break;
+            }
+            if let Some(reply) = dispatch(&ctx, addr, node_id.as_deref(), &from_peer, &pacing, &reply_tx, message) {
+                if reply_tx.send(reply).await.is_err() {
+                    break;
+                }
+            }
+            // Banned or disconnected by the operator.
+            if !ctx.peers.read().contains_key(&addr) {
+                debug!("Peer {} was removed, ending session", addr);
+                break;
+            }
+        }
+        Ok::<(), FrameError>(())
+    };
+
+    let writing = async {
+        for message in missed {
+            write_message(&mut writer, &message).await?;
+        }
+        loop {
+            let message = tokio::select! {
+                Some(reply) = replies.recv() => reply,
+                received = outbound.recv() => match received {
+                    Ok(message) => {
+                        let echo = dedup::gossip_digest(&message)
+                            .map_or(false, |digest| from_peer.lock().contains(&digest, Instant::now()));
+                        if echo {
+                            continue;
+                        }
+                        if matches!(message, Message::NewTransaction { .. }) && !pacing.lock().allow(Instant::now()) {
+                            continue;
+                        }
+                        message
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Session with {} fell behind and skipped {} messages", addr, missed);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                // A lagged receiver may have missed this peer's eviction, so
+                // anything but another peer's address rechecks the table.
+                evicted = evictions.recv() => {
+                    if matches!(evicted, Ok(evicted) if evicted != addr) || ctx.peers.read().contains_key(&addr) {
+                        continue;
+                    }
+                    debug!("Peer {} was removed, ending session", addr);
+                    let goodbye = Message::Goodbye { reason: "disconnected".to_string() };
+                    write_message(&mut writer, &goodbye).await?;
+                    writer.shutdown().await?;
+                    break;
+                }
+                _ = shutdown.changed() => {
+                    let goodbye = Message::Goodbye { reason: "node shutting down".to_string() };
+                    write_message(&mut writer, &goodbye).await?;
+                    writer.shutdown().await?;
+                    break;
+                }
+            };
+            write_message(&mut writer, &message).await?;
+        }
+        Ok::<(), FrameError>(())
+    };
+
+    let result = tokio::select! {
+        result = reading => result,
+        result = writing => result,
+    };
+
+    if let Some(pubkey) = &pubkey {
+        ctx.outboxes.close(pubkey, addr);
+    }
+    if let Some(peer) = ctx.peers.write().get_mut(&addr) {
+        peer.mark_disconnected();
+    }
+    ctx.metrics.connections_closed.inc();
+    let _ = ctx.activity.send(Activity::PeerDisconnected { addr });
+    debug!("Session with {} ended", addr);
+    result
+}
+
+fn dispatch(
+    ctx: &SessionContext,
+    addr: SocketAddr,
+    node_id: Option<&str>,
+    from_peer: &Mutex<RecentMessages>,
+    pacing: &Mutex<ForwardPacing>,
+    replies: &mpsc::Sender<Message>,
+    message: Message,
+) -> Option<Message> {
+    let now = Instant::now();
+    if let Some(peer) = ctx.peers.write().get_mut(&addr) {
+        peer.touch();
+    }
+
+    match message {
+        Message::Ping { nonce } => Some(Message::pong(nonce)),
+        Message::Pong { nonce, timestamp_ms } => {
+            record_pong(&ctx.peers, &ctx.clock_skew, addr, nonce, timestamp_ms);
+            None
+        }
+        Message::Ack { seq } => {
+            if let Some(node_id) = node_id {
+                ctx.replay.lock().ack(node_id, seq);
+            }
+            None
+        }
+        Message::Checkpoint { seq } => Some(Message::Ack { seq }),
+        Message::Backpressure { active } => {
+            debug!("Peer {} asked to {} transaction forwarding", addr, if active { "slow" } else { "resume" });
+            pacing.lock().set(active);
+            None
+        }
+        Message::NewTransaction { .. } | Message::ViewChange { .. } | Message::BlockVote { .. } => {
+            let mut reply = None;
+            if let Some(digest) = dedup::gossip_digest(&message) {
+                from_peer.lock().first_seen(digest, now);
+                if ctx.recent_messages.lock().first_seen(digest, now) {
+                    if let Message::NewTransaction { transaction, sent_at_ms } = &message {
+                        let Some((signature, slow_down)) = admit_transaction(ctx, addr, transaction, *sent_at_ms) else {
+                            warn!("Peer {} gossiped an invalid transaction", addr);
+                            reputation::apply_event(&ctx.peers, &ctx.bans, ctx.ban_policy, addr, PeerEvent::MalformedMessage);
+                            return None;
+                        };
+                        let _ = ctx.activity.send(Activity::NewTransaction { signature: signature.to_string() });
+                        reply = slow_down;
+                    }
+                    // Under the replay lock, as `Node::broadcast` sends.
+                    let mut replay = ctx.replay.lock();
+                    replay.push(message.clone());
+                    let _ = ctx.tx.send(message);
+                } else {
+                    debug!("Dropping duplicate gossip from {}", addr);
+                }
+            }
+            reply
+        }
+        Message::GetPeers => Some(Message::Peers {
+            peers: gossip::shareable(ctx.peers.read().values(), addr),
+        }),
+        Message::Peers { peers } => {
+            let unseen: Vec<_> = {
+                let mut seen = ctx.seen_announcements.lock();
+                peers
+                    .into_iter()
+                    .take(MAX_SHARED_PEERS)
+                    .filter(|record| seen.check_and_insert(&(record.node_id.as_str(), record.addr)))
+                    .collect()
+            };
+            let learned = ctx.known_peers.lock().merge(unseen, now);
+            if learned > 0 {
+                debug!("Learned {} new peers from {}", learned, addr);
+            }
+            None
+        }
+        Message::Block { slot, ref payload } => {
+            if let Some(digest) = dedup::gossip_digest(&message) {
+                from_peer.lock().first_seen(digest, now);
+                // Already verified and relayed; the node marks it seen then.
+                if ctx.recent_messages.lock().contains(&digest, now) {
+                    return None;
+                }
+            }
+            if ctx.blocks.try_send(ReceivedBlock { from: addr, slot, payload: payload.clone() }).is_err() {
+                debug!("Block queue full, dropping block from {}", addr);
+            }
+            None
+        }
+        Message::ValidatorAnnounce { pubkey, stake_account } => {
+            if let Some(digest) = dedup::gossip_digest(&message) {
+                from_peer.lock().first_seen(digest, now);
+                // Already checked and relayed; the node marks it seen then.
+                if ctx.recent_messages.lock().contains(&digest, now) {
+                    return None;
+                }
+            }
+            let announcement = Announcement {
+                from: addr,
+                pubkey: Pubkey::new_from_array(pubkey),
+                stake_account: Pubkey::new_from_array(stake_account),
+            };
+            if ctx.announcements.try_send(announcement).is_err() {
+                debug!("Announcement queue full, dropping validator announcement from {}", addr);
+            }
+            None
+        }
+        Message::GenerateRequest { request_id, prompt, max_tokens, params } => {
+            let Some(work) = ctx.in_flight.try_begin() else {
+                let result = Err("Node is shutting down".to_string());
+                return Some(Message::GenerateResponse { request_id, result });
+            };
+            // Like routed jobs, only staked validators get to spend this
+            // node's capacity.
+            let requester = ctx.peers.read().get(&addr).and_then(|p| p.pubkey);
+            let staked = requester.map_or(false, |requester| {
+                ctx.consensus.read().validators().iter().any(|v| v.pubkey == requester && v.stake > 0)
+            });
+            if !staked {
+                let result = Err("Generation is only served to staked validators".to_string());
+                return Some(Message::GenerateResponse { request_id, result });
+            }
+            let Ok(permit) = Arc::clone(&ctx.generation_permits).try_acquire_owned() else {
+                let result = Err("No free inference capacity on this node".to_string());
+                return Some(Message::GenerateResponse { request_id, result });
+            };
+            let (inference, metrics, activity, replies) =
+                (Arc::clone(&ctx.inference), Arc::clone(&ctx.metrics), ctx.activity.clone(), replies.clone());
+            ctx.spawn_task(async move {
+                let (_work, _permit) = (work, permit);
+                let started = Instant::now();
+                let result = inference.generate(prompt, max_tokens, params).await;
+                metrics.record_inference(started.elapsed(), &result);
+                let _ = activity.send(Activity::InferenceCompleted { request_id, succeeded: result.is_ok() });
+                let _ = replies.send(Message::GenerateResponse { request_id, result }).await;
+            });
+            None
+        }
+        Message::InferenceRequest { job_id, executor: Some(executor), prompt, max_tokens, params } => {
+            if executor != ctx.inference.pubkey().to_bytes() {
+                return None;
+            }
+            let requester = ctx.peers.read().get(&addr).and_then(|p| p.pubkey)?;
+            // Drain waits on the job, so the model outlives it.
+            let Some(work) = ctx.in_flight.try_begin() else {
+                let result = Err("Node is shutting down".to_string());
+                return Some(Message::InferenceResult { job_id, result });
+            };
+            // Routers only pick staked executors, and only staked routers
+            // get to spend this node's capacity.
+            if !ctx.consensus.read().validators().iter().any(|v| v.pubkey == requester && v.stake > 0) {
+                let result = Err(format!("{} is not a staked validator", requester));
+                return Some(Message::InferenceResult { job_id, result });
+            }
+            let (inference, metrics, activity, replies) =
+                (Arc::clone(&ctx.inference), Arc::clone(&ctx.metrics), ctx.activity.clone(), replies.clone());
+            // Generation takes a while; the session keeps reading meanwhile.
+            ctx.spawn_task(async move {
+                let _work = work;
+                let started = Instant::now();
+                let result = inference.execute(&requester, job_id, prompt, max_tokens, params).await;
+                let text = result.as_ref().map(|output| output.text.clone()).map_err(String::clone);
+                metrics.record_inference(started.elapsed(), &text);
+                let _ = activity.send(Activity::InferenceCompleted { request_id: job_id, succeeded: result.is_ok() });
+                let _ = replies.send(Message::InferenceResult { job_id, result }).await;
+            });
+            None
+        }
+        // Routing on a client's behalf happens on the request port, where the
+        // node can re-run a sample of jobs.
+        Message::InferenceRequest { job_id, executor: None, .. } => {
+            let result = Err("Jobs without an executor are only routed on the request port".to_string());
+            Some(Message::InferenceResult { job_id, result })
+        }
+        Message::InferenceResult { job_id, result } => {
+            let from = ctx.peers.read().get(&addr).and_then(|p| p.pubkey);
+            if !from.map_or(false, |from| ctx.inference.complete(&from, job_id, result)) {
+                debug!("Dropping unsolicited result for inference job {} from {}", job_id, addr);
+            }
+            None
+        }
+        Message::InferenceReceipt { accepted } => {
+            let validators = ctx.consensus.read().validators().to_vec();
+            if ctx.inference.record_accepted(&accepted, &validators) {
+                let _ = ctx.tx.send(Message::InferenceReceipt { accepted });
+            }
+            None
+        }
+        Message::InferenceDispute { dispute } => {
+            if !dispute.is_valid() {
+                warn!("Peer {} sent an inference dispute that does not hold up", addr);
+                reputation::apply_event(&ctx.peers, &ctx.bans, ctx.ban_policy, addr, PeerEvent::MalformedMessage);
+                return None;
+            }
+            if ctx.inference.record_dispute(dispute.clone()) {
+                let executor = dispute.faulty.executor();
+                warn!("Validator {} outvoted on inference job {}", executor, dispute.faulty.job_id);
+                reputation::apply_event_to_pubkey(&ctx.peers, &ctx.bans, ctx.ban_policy, &executor, PeerEvent::FaultyInference);
+                let _ = ctx.tx.send(Message::InferenceDispute { dispute });
+            }
+            None
+        }
+        Message::GetSnapshotManifest => {
+            let (state_sync, replies) = (Arc::clone(&ctx.state_sync), replies.clone());
+            // Rebuilding the snapshot reads the blocks it carries.
+            tokio::spawn(async move {
+                let manifest = tokio::task::spawn_blocking(move || state_sync.latest_manifest()).await.ok().flatten();
+                let _ = replies.send(Message::SnapshotManifest { manifest }).await;
+            });
+            None
+        }
+        Message::SnapshotManifest { manifest } => {
+            let from = ctx.peers.read().get(&addr).and_then(|p| p.pubkey);
+            if !from.map_or(false, |from| ctx.state_sync.record_manifest(from, manifest)) {
+                debug!("Dropping unsolicited snapshot manifest from {}", addr);
+            }
+            None
+        }
+        Message::GetSnapshotChunk { server, height, index } => {
+            if server != ctx.state_sync.pubkey().to_bytes() {
+                return None;
+            }
+            let data = ctx.state_sync.chunk(height, index)?;
+            Some(Message::SnapshotChunk { height, index, data })
+        }
+        Message::SnapshotChunk { height, index, data } => {
+            let from = ctx.peers.read().get(&addr).and_then(|p| p.pubkey);
+            if !from.map_or(false, |from| ctx.state_sync.complete_chunk(&from, height, index, data)) {
+                debug!("Dropping unsolicited chunk {} of snapshot {} from {}", index, height, addr);
+            }
+            None
+        }
+        Message::GetBlocks { server, from_height } => {
+            if server != ctx.state_sync.pubkey().to_bytes() {
+                return None;
+            }
+            Some(Message::Blocks { blocks: ctx.state_sync.blocks_from(from_height) })
+        }
+        Message::Blocks { blocks } => {
+            let from = ctx.peers.read().get(&addr).and_then(|p| p.pubkey);
+            if !from.map_or(false, |from| ctx.state_sync.complete_blocks(&from, blocks)) {
+                debug!("Dropping unsolicited blocks from {}", addr);
+            }
+            None
+        }
+        Message::GetHeaders { server, start_height, limit } => {
+            if server != ctx.state_sync.pubkey().to_bytes() {
+                return None;
+            }
+            let (tip_height, headers) = ctx.state_sync.certified_headers(start_height, limit as usize);
+            Some(Message::Headers { tip_height, headers })
+        }
+        Message::Headers { tip_height, headers } => {
+            let from = ctx.peers.read().get(&addr).and_then(|p| p.pubkey);
+            if !from.map_or(false, |from| ctx.state_sync.complete_headers(&from, tip_height, headers)) {
+                debug!("Dropping unsolicited headers from {}", addr);
+            }
+            None
+        }
+        Message::GradientShard { shard } => {
+            let from = ctx.peers.read().get(&addr).and_then(|p| p.pubkey);
+            if from != Some(shard.trainer()) || !shard.verify_signature() {
+                warn!("Peer {} sent a gradient shard it did not sign", addr);
+                reputation::apply_event(&ctx.peers, &ctx.bans, ctx.ban_policy, addr, PeerEvent::MalformedMessage);
+                return None;
+            }
+            let round = shard.round;
+            if !ctx.training.receive(shard) {
+                debug!("Dropping gradient shard for round {} from {}", round, addr);
+            }
+            None
+        }
+        Message::Handshake { .. } | Message::Auth { .. } => {
+            warn!("Peer {} repeated its handshake mid-session", addr);
+            reputation::apply_event(&ctx.peers, &ctx.bans, ctx.ban_policy, addr, PeerEvent::MalformedMessage);
+            None
+        }
+        other => {
+            debug!("Ignoring {:?} from {}", other, addr);
+            None
+        }
+    }
+}
+
+/// Queues a transaction gossiped by the peer at `addr`. Returns `None` if it
+/// doesn't decode or its signature doesn't verify; one that is already
+/// pending still counts as valid. Otherwise returns its signature and, when
+/// the mempool has filled to the high watermark, the `Backpressure` asking
+/// the peer to slow down instead of having its transactions evicted.
+fn admit_transaction(
+    ctx: &SessionContext,
+    addr: SocketAddr,
+    encoded: &[u8],
+    sent_at_ms: i64,
+) -> Option<(Signature, Option<Message>)> {
+    let transaction: Transaction = bincode::deserialize(encoded).ok()?;
+    let pending = TimestampedTransaction::received(transaction, sent_at_ms);
+    if !pending.verify_signature() {
+        return None;
+    }
+    let signature = pending.transaction.signatures.first().copied().unwrap_or_default();
+    let fill = {
+        let mut mempool = ctx.mempool.lock();
+        mempool.add(pending);
+        mempool.fill_ratio()
+    };
+    let peer = ctx.peers.read().get(&addr).and_then(|p| p.pubkey);
+    let slow_down = peer.filter(|peer| ctx.backpressure.slow(*peer, fill)).map(|_| {
+        warn!("Mempool {:.0}% full, asking {} to slow down", fill * 100.0, addr);
+        Message::Backpressure { active: true }
+    });
+    Some((signature, slow_down))
+}
+
+pub(crate) fn record_pong(
+    peers: &RwLock<HashMap<SocketAddr, PeerInfo>>,
+    clock_skew: &Mutex<ClockSkewMonitor>,
+    addr: SocketAddr,
+    nonce: u64,
+    timestamp_ms: i64,
+) {
+    if let Some(peer) = peers.write().get_mut(&addr) {
+        if let Some(rtt) = peer.record_pong(nonce, Instant::now()) {
+            peer.record_event(PeerEvent::UsefulResponse);
+            debug!("Peer {} RTT now {:?}", addr, rtt);
+            clock_skew.lock().record(addr, timestamp_ms, unix_now_ms(), rtt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::config::TransportKind;
+    use crate::node::consensus::Validator;
+    use crate::node::transport::MemoryConnection;
+    use tokio::time::{sleep, timeout};
+
+    fn context(peer: SocketAddr) -> (SessionContext, watch::Sender<bool>) {
+        let (tx, _) = broadcast::channel(16);
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let ctx = SessionContext {
+            tx,
+            activity: broadcast::channel(16).0,
+            metrics: Arc::new(NodeMetrics::new()),
+            peers: Arc::new(RwLock::new(HashMap::from([(peer, PeerInfo::new(peer))]))),
+            consensus: Arc::new(RwLock::new(ConsensusManager::new(Duration::from_secs(1)))),
+            outboxes: Arc::new(Outboxes::new()),
+            recent_messages: Arc::new(Mutex::new(RecentMessages::default())),
+            clock_skew: Arc::new(Mutex::new(ClockSkewMonitor::new(Duration::from_secs(1), false))),
+            replay: Arc::new(Mutex::new(ReplayBuffer::new(16))),
+            mempool: Arc::new(Mutex::new(Mempool::new(16))),
+            backpressure: Arc::new(Backpressure::new(0.9, 0.7)),
+            known_peers: Arc::new(Mutex::new(KnownPeers::new("node-local", 16))),
+            seen_announcements: Arc::new(Mutex::new(SeenFilter::default())),
+            bans: Arc::new(Mutex::new(BanList::new())),
+            ban_policy: BanPolicy { threshold: -50, duration: Duration::from_secs(60) },
+            inference: Arc::new(InferenceMarket::new(Arc::new(solana_sdk::signature::Keypair::new()), 16)),
+            generation_permits: Arc::new(Semaphore::new(1)),
+            training: Arc::new(GradientExchange::new(Arc::new(solana_sdk::signature::Keypair::new()))),
+            state_sync: Arc::new(StateSync::new(Pubkey::new_unique(), 100)),
+            announcements: mpsc::channel(4).0,
+            blocks: mpsc::channel(4).0,
+            evictions: broadcast::channel(4).0,
+            in_flight: InFlight::new(),
+            tasks: Arc::new(Mutex::new(JoinSet::new())),
+            shutdown,
+        };
+        (ctx, shutdown_tx)
+    }
+
+    #[tokio::test]
+    async fn test_sessions_exchange_gossip_and_pings() {
+        let addr_a = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let addr_b = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let (conn_a, conn_b) = MemoryConnection::pair(addr_a, addr_b);
+        let (ctx_a, stop_a) = context(addr_b);
+        let (ctx_b, stop_b) = context(addr_a);
+        let mut seen_by_a = ctx_a.tx.subscribe();
+        let mut seen_by_b = ctx_b.tx.subscribe();
+
+        let session_a = tokio::spawn(run_session(conn_a, addr_b, ctx_a.clone()));
+        let session_b = tokio::spawn(run_session(conn_b, addr_a, ctx_b.clone()));
+        // Let both writers subscribe before anything is broadcast.
+        sleep(Duration::from_millis(20)).await;
+
+        let first = Message::ViewChange { payload: vec![1] };
+        ctx_a.tx.send(first.clone()).unwrap();
+        assert_eq!(seen_by_a.recv().await.unwrap(), first);
+        assert_eq!(seen_by_b.recv().await.unwrap(), first);
+
+        // B forwarded `first` into its own channel; it must not come back to A.
+        let second = Message::ViewChange { payload: vec![2] };
+        ctx_b.tx.send(second.clone()).unwrap();
+        assert_eq!(seen_by_a.recv().await.unwrap(), second);
+
+        ctx_a.peers.write().get_mut(&addr_b).unwrap().record_ping_sent(7, Instant::now());
+        ctx_a.tx.send(Message::Ping { nonce: 7 }).unwrap();
+        timeout(Duration::from_secs(1), async {
+            while ctx_a.peers.read()[&addr_b].rtt().is_none() {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("pong never arrived");
+
+        stop_a.send(true).unwrap();
+        session_a.await.unwrap().unwrap();
+        session_b.await.unwrap().unwrap();
+        drop(stop_b);
+        assert!(!ctx_a.peers.read()[&addr_b].is_connected());
+        assert!(!ctx_b.peers.read()[&addr_a].is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_fans_out_to_every_session() {
+        let local = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let addr_b = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let addr_c = SocketAddr::from(([10, 0, 0, 3], 8002));
+        let (ctx, stop) = context(addr_b);
+        ctx.peers.write().insert(addr_c, PeerInfo::new(addr_c));
+
+        let (conn_b, mut remote_b) = MemoryConnection::pair(local, addr_b);
+        let (conn_c, mut remote_c) = MemoryConnection::pair(local, addr_c);
+        let session_b = tokio::spawn(run_session(conn_b, addr_b, ctx.clone()));
+        let session_c = tokio::spawn(run_session(conn_c, addr_c, ctx.clone()));
+        sleep(Duration::from_millis(20)).await;
+
+        let block = Message::Block { slot: 3, payload: vec![3] };
+        ctx.tx.send(block.clone()).unwrap();
+        assert_eq!(read_message(&mut remote_b).await.unwrap(), Some(block.clone()));
+        assert_eq!(read_message(&mut remote_c).await.unwrap(), Some(block));
+
+        stop.send(true).unwrap();
+        session_b.await.unwrap().unwrap();
+        session_c.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gossiped_transactions_enter_mempool() {
+        let addr_a = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let addr_b = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let (mut conn_a, conn_b) = MemoryConnection::pair(addr_a, addr_b);
+        let (ctx_b, stop_b) = context(addr_a);
+        let session_b = tokio::spawn(run_session(conn_b, addr_a, ctx_b.clone()));
+
+        let transfer = solana_sdk::system_transaction::transfer(
+            &solana_sdk::signature::Keypair::new(),
+            &solana_sdk::pubkey::Pubkey::new_unique(),
+            1,
+            solana_sdk::hash::Hash::default(),
+        );
+        let mut forged = transfer.clone();
+        forged.message.recent_blockhash = solana_sdk::hash::Hash::new_unique();
+        for tx in [&forged, &transfer] {
+            let gossip = Message::NewTransaction { transaction: bincode::serialize(tx).unwrap(), sent_at_ms: 0 };
+            write_message(&mut conn_a, &gossip).await.unwrap();
+        }
+
+        timeout(Duration::from_secs(1), async {
+            while ctx_b.mempool.lock().is_empty() {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("transaction never queued");
+        // The forged copy reuses the signature but was refused before it.
+        assert!(ctx_b.mempool.lock().contains(&transfer.signatures[0]));
+        assert_eq!(ctx_b.mempool.lock().len(), 1);
+        assert_eq!(ctx_b.peers.read()[&addr_a].score(), -10);
+
+        stop_b.send(true).unwrap();
+        session_b.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transaction_forwarding_slowed_while_peer_backpressured() {
+        let local = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let addr_b = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let (ctx, stop) = context(addr_b);
+        let (conn_b, mut remote_b) = MemoryConnection::pair(local, addr_b);
+        let session_b = tokio::spawn(run_session(conn_b, addr_b, ctx.clone()));
+        sleep(Duration::from_millis(20)).await;
+
+        write_message(&mut remote_b, &Message::Backpressure { active: true }).await.unwrap();
+        sleep(Duration::from_millis(20)).await;
+        let transaction = |n: u8| Message::NewTransaction { transaction: vec![n], sent_at_ms: 0 };
+        for n in 0..3 {
+            ctx.tx.send(transaction(n)).unwrap();
+        }
+        ctx.tx.send(Message::Ping { nonce: 1 }).unwrap();
+        // Only the first transaction goes out within the interval.
+        assert_eq!(read_message(&mut remote_b).await.unwrap(), Some(transaction(0)));
+        assert_eq!(read_message(&mut remote_b).await.unwrap(), Some(Message::Ping { nonce: 1 }));
+
+        write_message(&mut remote_b, &Message::Backpressure { active: false }).await.unwrap();
+        sleep(Duration::from_millis(20)).await;
+        ctx.tx.send(transaction(3)).unwrap();
+        ctx.tx.send(transaction(4)).unwrap();
+        assert_eq!(read_message(&mut remote_b).await.unwrap(), Some(transaction(3)));
+        assert_eq!(read_message(&mut remote_b).await.unwrap(), Some(transaction(4)));
+
+        stop.send(true).unwrap();
+        session_b.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_announcements_handed_to_node_and_generation_answered() {
+        let addr_a = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let addr_b = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let (mut conn_a, conn_b) = MemoryConnection::pair(addr_a, addr_b);
+        let (mut ctx_b, stop_b) = context(addr_a);
+        let (announcements, mut queued) = mpsc::channel(4);
+        ctx_b.announcements = announcements;
+        let session_b = tokio::spawn(run_session(conn_b, addr_a, ctx_b.clone()));
+
+        let (pubkey, stake_account) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let announce = Message::ValidatorAnnounce { pubkey: pubkey.to_bytes(), stake_account: stake_account.to_bytes() };
+        write_message(&mut conn_a, &announce).await.unwrap();
+        let announcement = timeout(Duration::from_secs(1), queued.recv()).await.expect("announcement never queued");
+        assert_eq!(announcement, Some(Announcement { from: addr_a, pubkey, stake_account }));
+
+        async fn refused(conn: &mut MemoryConnection, request_id: u64) -> String {
+            let request = Message::GenerateRequest {
+                request_id,
+                prompt: "hello".to_string(),
+                max_tokens: 4,
+                params: crate::node::message::GenerateParams::default(),
+            };
+            write_message(conn, &request).await.unwrap();
+            match timeout(Duration::from_secs(1), read_message(conn)).await.expect("no response").unwrap() {
+                Some(Message::GenerateResponse { request_id: answered, result: Err(e) }) if answered == request_id => e,
+                other => panic!("expected a refused generation, got {:?}", other),
+            }
+        }
+
+        assert!(refused(&mut conn_a, 7).await.contains("staked"));
+        let requester = Pubkey::new_unique();
+        ctx_b.peers.write().get_mut(&addr_a).unwrap().pubkey = Some(requester);
+        ctx_b.consensus.write().add_validator(Validator { pubkey: requester, stake: 1_000, locked_until: i64::MAX });
+        let busy = Arc::clone(&ctx_b.generation_permits).try_acquire_owned().unwrap();
+        assert!(refused(&mut conn_a, 8).await.contains("capacity"));
+        drop(busy);
+        assert!(refused(&mut conn_a, 9).await.contains("disabled"));
+
+        stop_b.send(true).unwrap();
+        session_b.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_blocks_handed_to_node_instead_of_relayed() {
+        let addr_a = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let addr_b = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let (mut conn_a, conn_b) = MemoryConnection::pair(addr_a, addr_b);
+        let (mut ctx_b, stop_b) = context(addr_a);
+        let (blocks, mut queued) = mpsc::channel(4);
+        ctx_b.blocks = blocks;
+        let mut relayed = ctx_b.tx.subscribe();
+        let session_b = tokio::spawn(run_session(conn_b, addr_a, ctx_b.clone()));
+
+        write_message(&mut conn_a, &Message::Block { slot: 3, payload: vec![3] }).await.unwrap();
+        let received = timeout(Duration::from_secs(1), queued.recv()).await.expect("block never queued");
+        assert_eq!(received, Some(ReceivedBlock { from: addr_a, slot: 3, payload: vec![3] }));
+        assert!(relayed.try_recv().is_err());
+
+        stop_b.send(true).unwrap();
+        session_b.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_shares_connected_peers() {
+        let addr_a = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let addr_b = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let addr_c = SocketAddr::from(([10, 0, 0, 3], 51234));
+        let (conn_a, conn_b) = MemoryConnection::pair(addr_a, addr_b);
+        let (ctx_a, stop_a) = context(addr_b);
+        let (ctx_b, _stop_b) = context(addr_a);
+
+        let mut peer_c = PeerInfo::new(addr_c);
+        peer_c.node_id = Some("node-c".to_string());
+        peer_c.listen_addr = Some(SocketAddr::from(([10, 0, 0, 3], 8003)));
+        ctx_b.peers.write().insert(addr_c, peer_c);
+        ctx_b.peers.write().get_mut(&addr_a).unwrap().listen_addr = Some(addr_a);
+        ctx_b.peers.write().get_mut(&addr_a).unwrap().node_id = Some("node-a".to_string());
+
+        let session_a = tokio::spawn(run_session(conn_a, addr_b, ctx_a.clone()));
+        let session_b = tokio::spawn(run_session(conn_b, addr_a, ctx_b));
+        sleep(Duration::from_millis(20)).await;
+
+        ctx_a.tx.send(Message::GetPeers).unwrap();
+        timeout(Duration::from_secs(1), async {
+            while ctx_a.known_peers.lock().is_empty() {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("peer list never arrived");
+
+        let learned = ctx_a.known_peers.lock().candidates(&Default::default(), 10);
+        assert_eq!(learned, vec![crate::node::message::PeerRecord {
+            node_id: "node-c".to_string(),
+            addr: SocketAddr::from(([10, 0, 0, 3], 8003)),
+        }]);
+
+        stop_a.send(true).unwrap();
+        session_a.await.unwrap().unwrap();
+        session_b.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_peer_announcements_seen_before_are_dropped() {
+        use crate::node::message::PeerRecord;
+
+        let addr_a = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let addr_b = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let (conn_a, conn_b) = MemoryConnection::pair(addr_a, addr_b);
+        let (ctx_a, stop_a) = context(addr_b);
+        let (ctx_b, _stop_b) = context(addr_a);
+        let seen = PeerRecord { node_id: "node-c".to_string(), addr: SocketAddr::from(([10, 0, 0, 3], 8003)) };
+        let unseen = PeerRecord { node_id: "node-d".to_string(), addr: SocketAddr::from(([10, 0, 0, 4], 8004)) };
+        assert!(ctx_a.seen_announcements.lock().check_and_insert(&(seen.node_id.as_str(), seen.addr)));
+
+        let session_a = tokio::spawn(run_session(conn_a, addr_b, ctx_a.clone()));
+        let session_b = tokio::spawn(run_session(conn_b, addr_a, ctx_b.clone()));
+        sleep(Duration::from_millis(20)).await;
+
+        ctx_b.tx.send(Message::Peers { peers: vec![seen, unseen.clone()] }).unwrap();
+        timeout(Duration::from_secs(1), async {
+            while ctx_a.known_peers.lock().is_empty() {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("peer list never arrived");
+
+        assert_eq!(ctx_a.known_peers.lock().candidates(&Default::default(), 10), vec![unseen.clone()]);
+        assert!(!ctx_a.seen_announcements.lock().check_and_insert(&(unseen.node_id.as_str(), unseen.addr)));
+
+        stop_a.send(true).unwrap();
+        session_a.await.unwrap().unwrap();
+        session_b.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_misbehaving_peer_banned_and_session_ended() {
+        let addr_a = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let addr_b = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let (mut conn_a, conn_b) = MemoryConnection::pair(addr_a, addr_b);
+        let (ctx_b, _stop_b) = context(addr_a);
+        let session_b = tokio::spawn(run_session(conn_b, addr_a, ctx_b.clone()));
+
+        let repeat = Message::Handshake {
+            protocol_version: 1,
+            node_id: "node-a".to_string(),
+            listen_port: 8000,
+            challenge: 1,
+            transport: TransportKind::Plain,
+            inference_capacity: 0,
+            observed_addr: None,
+            external_addr: None,
+        };
+        for _ in 0..5 {
+            if write_message(&mut conn_a, &repeat).await.is_err() {
+                break;
+            }
+        }
+
+        timeout(Duration::from_secs(1), session_b).await.expect("session kept running").unwrap().unwrap();
+        assert!(!ctx_b.peers.read().contains_key(&addr_a));
+        assert!(ctx_b.bans.lock().is_banned(addr_a.ip(), Instant::now()));
+    }
+
+    #[tokio::test]
+    async fn test_evicted_quiet_peer_session_ended() {
+        let local = SocketAddr::from(([10, 0, 0, 1], 8000));
+        let addr_b = SocketAddr::from(([10, 0, 0, 2], 8001));
+        let addr_c = SocketAddr::from(([10, 0, 0, 3], 8002));
+        let (ctx, _stop) = context(addr_b);
+        ctx.peers.write().insert(addr_c, PeerInfo::new(addr_c));
+
+        let (conn_b, mut remote_b) = MemoryConnection::pair(local, addr_b);
+        let (conn_c, _remote_c) = MemoryConnection::pair(local, addr_c);
+        let session_b = tokio::spawn(run_session(conn_b, addr_b, ctx.clone()));
+        let session_c = tokio::spawn(run_session(conn_c, addr_c, ctx.clone()));
+        sleep(Duration::from_millis(20)).await;
+
+        ctx.peers.write().remove(&addr_b);
+        ctx.evictions.send(addr_b).unwrap();
+
+        assert_eq!(
+            read_message(&mut remote_b).await.unwrap(),
+            Some(Message::Goodbye { reason: "disconnected".to_string() })
+        );
+        timeout(Duration::from_secs(1), session_b).await.expect("session kept running").unwrap().unwrap();
+        sleep(Duration::from_millis(20)).await;
+        assert!(!session_c.is_finished());
+        session_c.abort();
+    }
+}
@@ -0,0 +1,116 @@
+use futures::future::BoxFuture;
+use log::{info, warn};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownPhase {
+    StopAccepting,
+    DrainConsensus,
+    FlushPeers,
+    CloseRpc,
+    ReleaseModel,
+}
+
+impl fmt::Display for ShutdownPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ShutdownPhase::StopAccepting => "stop accepting",
+            ShutdownPhase::DrainConsensus => "drain consensus",
+            ShutdownPhase::FlushPeers => "flush peers",
+            ShutdownPhase::CloseRpc => "close RPC",
+            ShutdownPhase::ReleaseModel => "release model",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhaseOutcome {
+    pub phase: ShutdownPhase,
+    pub elapsed: Duration,
+    pub timed_out: bool,
+}
+
+/// Ordered shutdown steps, each bounded by its own timeout. A phase that
+/// overruns is abandoned and the sequence moves on.
+#[derive(Default)]
+pub struct ShutdownSequence<'a> {
+    phases: Vec<(ShutdownPhase, Duration, BoxFuture<'a, ()>)>,
+}
+
+impl<'a> ShutdownSequence<'a> {
+    pub fn new() -> Self {
+        ShutdownSequence { phases: Vec::new() }
+    }
+
+    pub fn phase(mut self, phase: ShutdownPhase, limit: Duration, step: BoxFuture<'a, ()>) -> Self {
+        self.phases.push((phase, limit, step));
+        self
+    }
+
+    pub fn total_timeout(&self) -> Duration {
+        self.phases.iter().map(|(_, limit, _)| *limit).sum()
+    }
+
+    pub async fn run(self) -> Vec<PhaseOutcome> {
+        let total = self.phases.len();
+        let mut outcomes = Vec::with_capacity(total);
+
+        for (i, (phase, limit, step)) in self.phases.into_iter().enumerate() {
+            info!("Shutdown phase {}/{}: {}", i + 1, total, phase);
+            let started = Instant::now();
+            let timed_out = timeout(limit, step).await.is_err();
+            if timed_out {
+                warn!("Shutdown phase '{}' did not finish within {:?}, continuing", phase, limit);
+            }
+            outcomes.push(PhaseOutcome {
+                phase,
+                elapsed: started.elapsed(),
+                timed_out,
+            });
+        }
+
+        info!("Shutdown complete");
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use parking_lot::Mutex;
+
+    #[tokio::test]
+    async fn test_phases_run_in_order_and_overruns_are_bounded() {
+        let order = Mutex::new(Vec::new());
+        let record = |phase: ShutdownPhase| {
+            let order = &order;
+            async move { order.lock().push(phase) }.boxed()
+        };
+
+        let sequence = ShutdownSequence::new()
+            .phase(ShutdownPhase::StopAccepting, Duration::from_millis(50), record(ShutdownPhase::StopAccepting))
+            .phase(
+                ShutdownPhase::DrainConsensus,
+                Duration::from_millis(50),
+                futures::future::pending().boxed(),
+            )
+            .phase(ShutdownPhase::FlushPeers, Duration::from_millis(50), record(ShutdownPhase::FlushPeers))
+            .phase(ShutdownPhase::CloseRpc, Duration::from_millis(50), record(ShutdownPhase::CloseRpc));
+        let budget = sequence.total_timeout();
+
+        let started = Instant::now();
+        let outcomes = sequence.run().await;
+
+        assert!(started.elapsed() < budget + Duration::from_millis(100));
+        assert_eq!(
+            *order.lock(),
+            vec![ShutdownPhase::StopAccepting, ShutdownPhase::FlushPeers, ShutdownPhase::CloseRpc]
+        );
+        let timed_out: Vec<ShutdownPhase> = outcomes.iter().filter(|o| o.timed_out).map(|o| o.phase).collect();
+        assert_eq!(timed_out, vec![ShutdownPhase::DrainConsensus]);
+    }
+}
@@ -0,0 +1,136 @@
+use log::{debug, warn};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+
+const MAX_REFERENCE_CORRECTION_MS: i64 = 60_000;
+
+#[derive(Debug)]
+pub struct SlotClock {
+    slot_duration: Duration,
+    reference_offset_ms: AtomicI64,
+    slot_tx: watch::Sender<u64>,
+}
+
+impl SlotClock {
+    pub fn new(slot_duration: Duration) -> Self {
+        let slot_duration = slot_duration.max(Duration::from_millis(1));
+        let (slot_tx, _) = watch::channel(0);
+        let clock = SlotClock {
+            slot_duration,
+            reference_offset_ms: AtomicI64::new(0),
+            slot_tx,
+        };
+        clock.slot_tx.send_replace(clock.wall_clock_slot());
+        clock
+    }
+
+    pub fn slot_duration(&self) -> Duration {
+        self.slot_duration
+    }
+
+    pub fn slot_at(&self, unix_ms: i64) -> u64 {
+        (unix_ms.max(0) as u128 / self.slot_duration.as_millis()) as u64
+    }
+
+    pub fn current_slot(&self) -> u64 {
+        *self.slot_tx.borrow()
+    }
+
+    /// Time since `slot` began by the corrected clock, zero for a slot yet
+    /// to begin.
+    pub fn since_slot_start(&self, slot: u64) -> Duration {
+        let start_ms = (slot as u128 * self.slot_duration.as_millis()).min(i64::MAX as u128) as i64;
+        Duration::from_millis(self.adjusted_unix_ms().saturating_sub(start_ms).max(0) as u64)
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.slot_tx.subscribe()
+    }
+
+    /// Corrects the local clock against a reference timestamp, e.g. the
+    /// block time of the latest Solana slot.
+    pub fn align_to_reference(&self, reference_unix_ms: i64) {
+        let offset = reference_unix_ms - local_unix_ms();
+        if offset.abs() > MAX_REFERENCE_CORRECTION_MS {
+            warn!("Ignoring slot clock correction of {}ms, reference looks wrong", offset);
+            return;
+        }
+        debug!("Slot clock offset from reference: {}ms", offset);
+        self.reference_offset_ms.store(offset, Ordering::Relaxed);
+    }
+
+    fn adjusted_unix_ms(&self) -> i64 {
+        local_unix_ms() + self.reference_offset_ms.load(Ordering::Relaxed)
+    }
+
+    fn wall_clock_slot(&self) -> u64 {
+        self.slot_at(self.adjusted_unix_ms())
+    }
+
+    fn until_next_boundary(&self) -> Duration {
+        let slot_ms = self.slot_duration.as_millis() as i64;
+        let elapsed = self.adjusted_unix_ms().rem_euclid(slot_ms);
+        Duration::from_millis((slot_ms - elapsed) as u64)
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut ticker = interval_at(Instant::now() + self.until_next_boundary(), self.slot_duration);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            ticker.tick().await;
+            let next = (self.current_slot() + 1).max(self.wall_clock_slot());
+            self.slot_tx.send_replace(next);
+        }
+    }
+}
+
+fn local_unix_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_at_uses_configured_duration() {
+        let clock = SlotClock::new(Duration::from_millis(400));
+        assert_eq!(clock.slot_at(0), 0);
+        assert_eq!(clock.slot_at(399), 0);
+        assert_eq!(clock.slot_at(400), 1);
+        assert_eq!(clock.slot_at(4_000), 10);
+    }
+
+    #[test]
+    fn test_since_slot_start_measured_from_slot_boundary() {
+        let clock = SlotClock::new(Duration::from_millis(400));
+        let slot = clock.current_slot();
+        // Slack for a boundary passing mid-test.
+        assert!(clock.since_slot_start(slot) < Duration::from_millis(800));
+        assert!(clock.since_slot_start(slot - 3) >= Duration::from_millis(1_200));
+        assert_eq!(clock.since_slot_start(slot + 2), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slot_counter_advances_at_configured_rate() {
+        let clock = Arc::new(SlotClock::new(Duration::from_millis(500)));
+        let start = clock.current_slot();
+        let mut slots = clock.subscribe();
+
+        tokio::spawn(Arc::clone(&clock).run());
+
+        tokio::time::sleep(clock.until_next_boundary() + Duration::from_millis(10)).await;
+        let first_tick = *slots.borrow_and_update();
+        assert_eq!(first_tick, start + 1);
+
+        tokio::time::sleep(Duration::from_millis(500) * 10).await;
+        assert_eq!(clock.current_slot(), first_tick + 10);
+    }
+}
@@ -0,0 +1,647 @@
+use log::{debug, info, warn};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use solana_sdk::hash::{hash, Hash};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{broadcast, oneshot, Notify};
+use tokio::time::timeout;
+
+use crate::node::consensus::{Block, BlockHeader, CertifiedHeader, ChainError, ConsensusManager, Validator};
+use crate::node::message::Message;
+use crate::node::outbox::Outboxes;
+use crate::node::storage::{Storage, StorageError};
+
+/// Snapshot bytes per chunk, well under the frame limit.
+pub const SNAPSHOT_CHUNK_SIZE: usize = 256 * 1024;
+pub const DEFAULT_STATE_SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+/// Most blocks handed out per `GetBlocks` request.
+pub const MAX_BLOCKS_PER_REQUEST: usize = 128;
+const MAX_BLOCKS_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+/// Snapshots kept for serving, so a download in progress survives a rebuild.
+const RETAINED_SNAPSHOTS: usize = 2;
+/// Most recent blocks a snapshot carries, so its size stays bounded as the
+/// chain grows.
+pub const SNAPSHOT_BLOCKS: u64 = 1024;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Chunk {index} does not match the manifest")]
+    BadChunk { index: u32 },
+    #[error("No peer served chunk {index} of the snapshot at height {height}")]
+    ChunkUnavailable { height: u64, index: u32 },
+    #[error("Encoding error: {0}")]
+    Encoding(#[from] bincode::Error),
+    #[error("Snapshot chain does not verify: {0}")]
+    Chain(#[from] ChainError),
+    #[error("Snapshot ends at block {found}, manifest promised {expected}")]
+    TipMismatch { expected: Hash, found: Hash },
+}
+
+/// Describes a snapshot whose chain ends in block `tip_hash` at `height`.
+/// The snapshot itself is fetched in chunks, each checked against its hash
+/// here.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub height: u64,
+    pub tip_hash: Hash,
+    /// Model the serving node generates with, if it runs one.
+    pub model_version: Option<String>,
+    pub chunk_hashes: Vec<Hash>,
+}
+
+/// What a joining node needs to take part: the last `SNAPSHOT_BLOCKS`
+/// blocks up to the snapshot height, the validator set there and the model
+/// version peers run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotState {
+    /// Header of the block below the first one carried; `None` when the
+    /// snapshot starts at genesis.
+    pub base: Option<BlockHeader>,
+    pub blocks: Vec<Block>,
+    pub validators: Vec<Validator>,
+    pub model_version: Option<String>,
+}
+
+impl SnapshotState {
+    /// Reads the blocks up to `height` and the stake snapshot taken at or
+    /// below it from `storage`. Returns `None` if one of those blocks is
+    /// missing.
+    pub fn read(storage: &Storage, height: u64, model_version: Option<String>) -> Result<Option<Self>, StorageError> {
+        let first = height.saturating_sub(SNAPSHOT_BLOCKS - 1).max(1);
+        let base = match first {
+            1 => None,
+            first => match storage.get_block_by_height(first - 1)? {
+                Some(block) => Some(block.header),
+                None => return Ok(None),
+            },
+        };
+        let mut blocks = Vec::with_capacity((height + 1 - first) as usize);
+        for height in first..=height {
+            match storage.get_block_by_height(height)? {
+                Some(block) => blocks.push(block),
+                None => return Ok(None),
+            }
+        }
+        let validators = storage.stake_snapshot_at(height)?.map(|(_, validators)| validators).unwrap_or_default();
+        Ok(Some(SnapshotState { base, blocks, validators, model_version }))
+    }
+
+    pub fn height(&self) -> u64 {
+        self.blocks.last().map_or(0, |block| block.header.height)
+    }
+
+    pub fn tip_hash(&self) -> Hash {
+        self.blocks.last().map_or_else(Hash::default, Block::hash)
+    }
+
+    /// Checks that the blocks form one chain on `base` ending in `tip_hash`.
+    /// Proposers aren't checked against a validator set: the tip is trusted
+    /// because the stake serving the snapshot agrees on it.
+    pub fn verify(&self, tip_hash: &Hash) -> Result<(), SnapshotError> {
+        // Only replays the chain; the view timeout never comes into play.
+        let mut chain = ConsensusManager::new(Duration::from_secs(1));
+        if let Some(base) = &self.base {
+            chain.set_tip(base);
+        }
+        for block in &self.blocks {
+            chain.apply_block(block)?;
+        }
+        if chain.last_block_hash() != *tip_hash {
+            return Err(SnapshotError::TipMismatch { expected: *tip_hash, found: chain.last_block_hash() });
+        }
+        Ok(())
+    }
+}
+
+/// A `SnapshotState`, bincode-encoded and split into chunks for serving.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub manifest: SnapshotManifest,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl Snapshot {
+    pub fn build(state: &SnapshotState, chunk_size: usize) -> Result<Self, bincode::Error> {
+        let encoded = bincode::serialize(state)?;
+        let chunks: Vec<Vec<u8>> = encoded.chunks(chunk_size.max(1)).map(<[u8]>::to_vec).collect();
+        let manifest = SnapshotManifest {
+            height: state.height(),
+            tip_hash: state.tip_hash(),
+            model_version: state.model_version.clone(),
+            chunk_hashes: chunks.iter().map(|chunk| hash(chunk)).collect(),
+        };
+        Ok(Snapshot { manifest, chunks })
+    }
+
+    pub fn chunk(&self, index: u32) -> Option<&[u8]> {
+        self.chunks.get(index as usize).map(Vec::as_slice)
+    }
+}
+
+/// Collects the chunks of a snapshot, keeping only those matching its
+/// manifest.
+#[derive(Debug)]
+pub struct SnapshotAssembler {
+    manifest: SnapshotManifest,
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+impl SnapshotAssembler {
+    pub fn new(manifest: SnapshotManifest) -> Self {
+        let chunks = vec![None; manifest.chunk_hashes.len()];
+        SnapshotAssembler { manifest, chunks }
+    }
+
+    pub fn insert(&mut self, index: u32, data: Vec<u8>) -> Result<(), SnapshotError> {
+        match self.manifest.chunk_hashes.get(index as usize) {
+            Some(expected) if *expected == hash(&data) => {
+                self.chunks[index as usize] = Some(data);
+                Ok(())
+            }
+            _ => Err(SnapshotError::BadChunk { index }),
+        }
+    }
+
+    /// Decodes the complete snapshot and verifies its chain against the
+    /// manifest.
+    pub fn finish(self) -> Result<SnapshotState, SnapshotError> {
+        let mut encoded = Vec::new();
+        for (index, chunk) in self.chunks.into_iter().enumerate() {
+            let chunk = chunk.ok_or(SnapshotError::ChunkUnavailable {
+                height: self.manifest.height,
+                index: index as u32,
+            })?;
+            encoded.extend_from_slice(&chunk);
+        }
+        let state: SnapshotState = bincode::deserialize(&encoded)?;
+        state.verify(&self.manifest.tip_hash)?;
+        Ok(state)
+    }
+}
+
+/// The manifest backed by the most stake, preferring the higher height on
+/// a tie, together with the peers offering it. Peers missing from `stakes`
+/// add no weight, and a manifest only they offer is never chosen. While no
+/// stake is known at all, each peer counts once.
+pub fn choose_manifest(
+    offers: &HashMap<Pubkey, Option<SnapshotManifest>>,
+    stakes: &HashMap<Pubkey, u64>,
+) -> Option<(SnapshotManifest, Vec<Pubkey>)> {
+    let weight = |peer: &Pubkey| if stakes.is_empty() { 1 } else { stakes.get(peer).copied().unwrap_or(0) };
+    let mut candidates: Vec<(SnapshotManifest, Vec<Pubkey>, u64)> = Vec::new();
+    for (peer, manifest) in offers {
+        let Some(manifest) = manifest else { continue };
+        match candidates.iter_mut().find(|(offered, _, _)| offered == manifest) {
+            Some((_, servers, backing)) => {
+                servers.push(*peer);
+                *backing = backing.saturating_add(weight(peer));
+            }
+            None => candidates.push((manifest.clone(), vec![*peer], weight(peer))),
+        }
+    }
+    let (manifest, mut servers, _) = candidates
+        .into_iter()
+        .filter(|(_, _, backing)| *backing > 0)
+        .max_by_key(|(manifest, _, backing)| (*backing, manifest.height))?;
+    servers.sort();
+    Some((manifest, servers))
+}
+
+/// Both sides of state sync: serves snapshots of the local chain to joining
+/// peers, and collects the manifests, chunks and blocks peers send back
+/// while this node catches up.
+#[derive(Debug)]
+pub struct StateSync {
+    pubkey: Pubkey,
+    /// Blocks between snapshot rebuilds.
+    interval: u64,
+    timeout: Duration,
+    storage: RwLock<Option<Storage>>,
+    model_version: RwLock<Option<String>>,
+    served: Mutex<VecDeque<Arc<Snapshot>>>,
+    /// Replies by peer while a sync is asking for manifests.
+    manifests: Mutex<Option<HashMap<Pubkey, Option<SnapshotManifest>>>>,
+    manifest_arrived: Notify,
+    chunks: Mutex<HashMap<(Pubkey, u64, u32), oneshot::Sender<Vec<u8>>>>,
+    blocks: Mutex<HashMap<Pubkey, oneshot::Sender<Vec<Vec<u8>>>>>,
+    headers: Mutex<HashMap<Pubkey, oneshot::Sender<(u64, Vec<CertifiedHeader>)>>>,
+}
+
+impl StateSync {
+    /// Snapshots are taken at multiples of `interval`, so peers that agree
+    /// on the chain offer the same one.
+    pub fn new(pubkey: Pubkey, interval: u64) -> Self {
+        StateSync {
+            pubkey,
+            interval: interval.max(1),
+            timeout: DEFAULT_STATE_SYNC_TIMEOUT,
+            storage: RwLock::new(None),
+            model_version: RwLock::new(None),
+            served: Mutex::new(VecDeque::new()),
+            manifests: Mutex::new(None),
+            manifest_arrived: Notify::new(),
+            chunks: Mutex::new(HashMap::new()),
+            blocks: Mutex::new(HashMap::new()),
+            headers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long to wait for manifests, and for each chunk or batch of blocks.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    pub fn attach_storage(&self, storage: Storage) {
+        *self.storage.write() = Some(storage);
+    }
+
+    pub fn set_model_version(&self, model_version: String) {
+        *self.model_version.write() = Some(model_version);
+    }
+
+    /// Manifest of the newest snapshot served, rebuilding it first at the
+    /// highest multiple of `interval` the chain has reached. Reads up to
+    /// `SNAPSHOT_BLOCKS` blocks when it rebuilds, so call it off the async
+    /// runtime.
+    pub fn latest_manifest(&self) -> Option<SnapshotManifest> {
+        let storage = self.storage.read().clone()?;
+        let height = match storage.latest_height() {
+            Ok(height) => height?,
+            Err(e) => {
+                warn!("Could not read the chain height for a snapshot: {}", e);
+                return self.newest_manifest();
+            }
+        };
+        let height = height - height % self.interval;
+        let stale = self.newest_manifest().map_or(true, |manifest| height > manifest.height);
+        if height > 0 && stale {
+            let model_version = self.model_version.read().clone();
+            match SnapshotState::read(&storage, height, model_version) {
+                Ok(Some(state)) => match Snapshot::build(&state, SNAPSHOT_CHUNK_SIZE) {
+                    Ok(snapshot) => self.publish(snapshot),
+                    Err(e) => warn!("Could not encode a snapshot at height {}: {}", height, e),
+                },
+                Ok(None) => debug!("Chain has gaps below height {}, not snapshotting", height),
+                Err(e) => warn!("Could not read a snapshot at height {}: {}", height, e),
+            }
+        }
+        self.newest_manifest()
+    }
+
+    fn newest_manifest(&self) -> Option<SnapshotManifest> {
+        self.served.lock().back().map(|snapshot| snapshot.manifest.clone())
+    }
+
+    /// Serves `snapshot`, dropping the oldest one kept beyond
+    /// `RETAINED_SNAPSHOTS`. One no newer than the latest is ignored.
+    pub fn publish(&self, snapshot: Snapshot) {
+        let mut served = self.served.lock();
+        if served.back().map_or(false, |newest| newest.manifest.height >= snapshot.manifest.height) {
+            return;
+        }
+        info!("Serving snapshot at height {} in {} chunks", snapshot.manifest.height, snapshot.chunks.len());
+        served.push_back(Arc::new(snapshot));
+        while served.len() > RETAINED_SNAPSHOTS {
+            served.pop_front();
+        }
+    }
+
+    pub fn chunk(&self, height: u64, index: u32) -> Option<Vec<u8>> {
+        let served = self.served.lock();
+        let snapshot = served.iter().find(|snapshot| snapshot.manifest.height == height)?;
+        snapshot.chunk(index).map(<[u8]>::to_vec)
+    }
+
+    /// Encoded blocks from `from_height` on, as many as fit one response.
+    pub fn blocks_from(&self, from_height: u64) -> Vec<Vec<u8>> {
+        let Some(storage) = self.storage.read().clone() else {
+            return Vec::new();
+        };
+        let mut blocks = Vec::new();
+        let mut size = 0;
+        for height in from_height.max(1)..from_height.max(1) + MAX_BLOCKS_PER_REQUEST as u64 {
+            let block = match storage.get_block_by_height(height) {
+                Ok(Some(block)) => block,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Could not read block {} for a peer: {}", height, e);
+                    break;
+                }
+            };
+            let Ok(payload) = bincode::serialize(&block) else { break };
+            size += payload.len();
+            if size > MAX_BLOCKS_RESPONSE_BYTES && !blocks.is_empty() {
+                break;
+            }
+            blocks.push(payload);
+        }
+        blocks
+    }
+
+    /// The height of the newest block stored with its certificate, and up to
+    /// `limit` certified headers from `start_height` on, as many as fit one
+    /// response.
+    pub fn certified_headers(&self, start_height: u64, limit: usize) -> (u64, Vec<CertifiedHeader>) {
+        let Some(storage) = self.storage.read().clone() else {
+            return (0, Vec::new());
+        };
+        let tip = match storage.latest_certified_height() {
+            Ok(tip) => tip.unwrap_or(0),
+            Err(e) => {
+                warn!("Could not read the certified height for a peer: {}", e);
+                return (0, Vec::new());
+            }
+        };
+        let mut headers = Vec::new();
+        let limit = limit.min(MAX_BLOCKS_PER_REQUEST);
+        for height in start_height.max(1)..=tip {
+            if headers.len() >= limit {
+                break;
+            }
+            match (storage.get_block_by_height(height), storage.certificate_at(height)) {
+                (Ok(Some(block)), Ok(Some(qc))) => headers.push(CertifiedHeader { header: block.header, qc }),
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!("Could not read header {} for a peer: {}", height, e);
+                    break;
+                }
+                _ => break,
+            }
+        }
+        (tip, headers)
+    }
+
+    /// Records a manifest sent by `from`. Returns `false` unless a sync is
+    /// asking for manifests.
+    pub fn record_manifest(&self, from: Pubkey, manifest: Option<SnapshotManifest>) -> bool {
+        let mut manifests = self.manifests.lock();
+        let Some(offers) = manifests.as_mut() else {
+            return false;
+        };
+        offers.insert(from, manifest);
+        self.manifest_arrived.notify_one();
+        true
+    }
+
+    /// Asks every peer for its latest manifest and waits until `expected`
+    /// peers have answered or the timeout passes.
+    pub async fn collect_manifests(
+        &self,
+        tx: &broadcast::Sender<Message>,
+        expected: usize,
+    ) -> HashMap<Pubkey, Option<SnapshotManifest>> {
+        *self.manifests.lock() = Some(HashMap::new());
+        let _ = tx.send(Message::GetSnapshotManifest);
+        let answered = || self.manifests.lock().as_ref().map_or(0, HashMap::len);
+        let _ = timeout(self.timeout, async {
+            while answered() < expected {
+                self.manifest_arrived.notified().await;
+            }
+        })
+        .await;
+        self.manifests.lock().take().unwrap_or_default()
+    }
+
+    /// Delivers a chunk sent by `from`. Returns `false` if we weren't
+    /// waiting on `from` for it.
+    pub fn complete_chunk(&self, from: &Pubkey, height: u64, index: u32, data: Vec<u8>) -> bool {
+        match self.chunks.lock().remove(&(*from, height, index)) {
+            Some(reply) => reply.send(data).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Downloads the snapshot described by `manifest`, spreading chunks over
+    /// `servers` and asking each through its own session. Moves on to the
+    /// next server when one is gone, times out or sends a chunk that doesn't
+    /// match; those that sent a bad chunk are passed to `penalize`.
+    pub async fn download(
+        &self,
+        outboxes: &Outboxes,
+        manifest: &SnapshotManifest,
+        servers: &[Pubkey],
+        penalize: impl Fn(&Pubkey),
+    ) -> Result<SnapshotState, SnapshotError> {
+        let height = manifest.height;
+        let mut assembler = SnapshotAssembler::new(manifest.clone());
+        for index in 0..manifest.chunk_hashes.len() as u32 {
+            let mut fetched = false;
+            for attempt in 0..servers.len() {
+                let server = servers[(index as usize + attempt) % servers.len()];
+                let (reply, rx) = oneshot::channel();
+                self.chunks.lock().insert((server, height, index), reply);
+                let request = Message::GetSnapshotChunk { server: server.to_bytes(), height, index };
+                let result = if outboxes.send(&server, request).await {
+                    timeout(self.timeout, rx).await.ok()
+                } else {
+                    None
+                };
+                self.chunks.lock().remove(&(server, height, index));
+                let Some(Ok(data)) = result else {
+                    debug!("Peer {} did not serve chunk {} of snapshot {}", server, index, height);
+                    continue;
+                };
+                match assembler.insert(index, data) {
+                    Ok(()) => {
+                        fetched = true;
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("Peer {} sent a bad snapshot chunk: {}", server, e);
+                        penalize(&server);
+                    }
+                }
+            }
+            if !fetched {
+                return Err(SnapshotError::ChunkUnavailable { height, index });
+            }
+        }
+        assembler.finish()
+    }
+
+    /// Delivers blocks sent by `from`. Returns `false` if we weren't waiting
+    /// on `from` for any.
+    pub fn complete_blocks(&self, from: &Pubkey, blocks: Vec<Vec<u8>>) -> bool {
+        match self.blocks.lock().remove(from) {
+            Some(reply) => reply.send(blocks).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Asks `server`, through its session, for the blocks from
+    /// `from_height` on. `None` if it is gone or didn't answer in time.
+    pub async fn request_blocks(&self, outboxes: &Outboxes, server: Pubkey, from_height: u64) -> Option<Vec<Vec<u8>>> {
+        let (reply, rx) = oneshot::channel();
+        self.blocks.lock().insert(server, reply);
+        let request = Message::GetBlocks { server: server.to_bytes(), from_height };
+        let result = if outboxes.send(&server, request).await {
+            timeout(self.timeout, rx).await.ok()
+        } else {
+            None
+        };
+        self.blocks.lock().remove(&server);
+        result?.ok()
+    }
+
+    /// Delivers headers sent by `from`. Returns `false` if we weren't
+    /// waiting on `from` for any.
+    pub fn complete_headers(&self, from: &Pubkey, tip_height: u64, headers: Vec<CertifiedHeader>) -> bool {
+        match self.headers.lock().remove(from) {
+            Some(reply) => reply.send((tip_height, headers)).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Asks `server`, through its session, for up to `limit` certified
+    /// headers from `start_height` on, along with its certified tip. `None`
+    /// if it is gone or didn't answer in time.
+    pub async fn request_headers(
+        &self,
+        outboxes: &Outboxes,
+        server: Pubkey,
+        start_height: u64,
+        limit: usize,
+    ) -> Option<(u64, Vec<CertifiedHeader>)> {
+        let (reply, rx) = oneshot::channel();
+        self.headers.lock().insert(server, reply);
+        let limit = limit.min(MAX_BLOCKS_PER_REQUEST) as u32;
+        let request = Message::GetHeaders { server: server.to_bytes(), start_height, limit };
+        let result = if outboxes.send(&server, request).await {
+            timeout(self.timeout, rx).await.ok()
+        } else {
+            None
+        };
+        self.headers.lock().remove(&server);
+        result?.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::{Keypair, Signer};
+
+    fn chain(proposer: &Keypair, length: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for height in 1..=length {
+            let parent = blocks.last().map_or_else(Hash::default, Block::hash);
+            blocks.push(Block::new(height, height, parent, height as i64, Vec::new(), proposer));
+        }
+        blocks
+    }
+
+    fn state(length: u64) -> SnapshotState {
+        let proposer = Keypair::new();
+        SnapshotState {
+            base: None,
+            blocks: chain(&proposer, length),
+            validators: vec![Validator { pubkey: proposer.pubkey(), stake: 10, locked_until: i64::MAX }],
+            model_version: Some("lightllm-v1".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_reassembled_from_verified_chunks() {
+        let original = state(40);
+        let snapshot = Snapshot::build(&original, 512).unwrap();
+        assert_eq!(snapshot.manifest.height, 40);
+        assert!(snapshot.manifest.chunk_hashes.len() > 1);
+
+        let mut assembler = SnapshotAssembler::new(snapshot.manifest.clone());
+        let mut tampered = snapshot.chunk(0).unwrap().to_vec();
+        tampered[0] ^= 1;
+        assert!(matches!(assembler.insert(0, tampered), Err(SnapshotError::BadChunk { index: 0 })));
+        let out_of_range = snapshot.manifest.chunk_hashes.len() as u32;
+        assert!(assembler.insert(out_of_range, Vec::new()).is_err());
+        for index in 0..out_of_range {
+            assembler.insert(index, snapshot.chunk(index).unwrap().to_vec()).unwrap();
+        }
+
+        let restored = assembler.finish().unwrap();
+        assert_eq!(restored.blocks, original.blocks);
+        assert_eq!(restored.validators[0].pubkey, original.validators[0].pubkey);
+        assert_eq!(restored.model_version, original.model_version);
+    }
+
+    #[test]
+    fn test_snapshot_with_broken_chain_rejected() {
+        let mut forged = state(5);
+        forged.blocks.remove(2);
+        let snapshot = Snapshot::build(&forged, SNAPSHOT_CHUNK_SIZE).unwrap();
+        let mut assembler = SnapshotAssembler::new(snapshot.manifest.clone());
+        assembler.insert(0, snapshot.chunk(0).unwrap().to_vec()).unwrap();
+        assert!(matches!(assembler.finish(), Err(SnapshotError::Chain(ChainError::BrokenLinkage(4)))));
+
+        let honest = state(3);
+        assert!(matches!(honest.verify(&Hash::new_unique()), Err(SnapshotError::TipMismatch { .. })));
+    }
+
+    #[test]
+    fn test_snapshot_carries_recent_blocks_on_their_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::open(dir.path()).unwrap();
+        let full = state(SNAPSHOT_BLOCKS + 10);
+        for block in &full.blocks {
+            storage.put_block(block).unwrap();
+        }
+        storage.put_stake_snapshot(1, &full.validators).unwrap();
+        storage.put_stake_snapshot(SNAPSHOT_BLOCKS + 5, &[]).unwrap();
+
+        let recent = SnapshotState::read(&storage, SNAPSHOT_BLOCKS + 4, None).unwrap().unwrap();
+        assert_eq!(recent.blocks.len() as u64, SNAPSHOT_BLOCKS);
+        assert_eq!(recent.blocks[0].header.height, 5);
+        assert_eq!(recent.base, Some(full.blocks[3].header.clone()));
+        assert_eq!(recent.validators.len(), 1);
+        recent.verify(&full.blocks[SNAPSHOT_BLOCKS as usize + 3].hash()).unwrap();
+
+        let mut rebased = recent.clone();
+        rebased.base = Some(full.blocks[2].header.clone());
+        assert!(matches!(rebased.verify(&recent.tip_hash()), Err(SnapshotError::Chain(ChainError::BrokenLinkage(5)))));
+    }
+
+    #[test]
+    fn test_manifest_backed_by_most_stake_chosen() {
+        let manifest = |height| SnapshotManifest {
+            height,
+            tip_hash: Hash::new_unique(),
+            model_version: None,
+            chunk_hashes: vec![Hash::new_unique()],
+        };
+        let (agreed, outlier) = (manifest(100), manifest(900));
+        let peers: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let mut offers = HashMap::from([
+            (peers[0], Some(agreed.clone())),
+            (peers[1], Some(outlier.clone())),
+            (peers[2], Some(agreed.clone())),
+            (peers[3], None),
+        ]);
+
+        // With no stake known, each peer counts once.
+        let (chosen, servers) = choose_manifest(&offers, &HashMap::new()).unwrap();
+        assert_eq!(chosen, agreed);
+        let mut expected = vec![peers[0], peers[2]];
+        expected.sort();
+        assert_eq!(servers, expected);
+
+        offers.insert(peers[3], Some(outlier.clone()));
+        offers.remove(&peers[2]);
+        assert_eq!(choose_manifest(&offers, &HashMap::new()).unwrap().0, outlier);
+        assert!(choose_manifest(&HashMap::from([(peers[0], None)]), &HashMap::new()).is_none());
+
+        // Unstaked peers add nothing, however many connections they open.
+        let stakes = HashMap::from([(peers[0], 30), (peers[1], 10)]);
+        assert_eq!(choose_manifest(&offers, &stakes).unwrap().0, agreed);
+        offers.insert(peers[0], None);
+        assert_eq!(choose_manifest(&offers, &stakes).unwrap().0, outlier);
+        offers.insert(peers[1], None);
+        assert!(choose_manifest(&offers, &stakes).is_none());
+    }
+}
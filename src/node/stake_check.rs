@@ -0,0 +1,223 @@
+use async_trait::async_trait;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use thiserror::Error;
+
+use crate::program::stake::{find_stake_address, StakeAccount};
+
+#[derive(Error, Debug)]
+pub enum StakeCheckError {
+    #[error("RPC error while fetching stake account: {0}")]
+    Rpc(String),
+    #[error("Stake account {0} does not exist")]
+    NotFound(Pubkey),
+    #[error("Account {account} is not the stake account of {staker}")]
+    NotStakeAddress { account: Pubkey, staker: Pubkey },
+    #[error("Stake account {0} is not owned by the stake program")]
+    WrongProgram(Pubkey),
+    #[error("Stake account {0} could not be decoded: {1}")]
+    Malformed(Pubkey, String),
+    #[error("Stake account {account} belongs to {owner}, not this node")]
+    WrongOwner { account: Pubkey, owner: Pubkey },
+    #[error("Stake account {0} is inactive")]
+    Inactive(Pubkey),
+    #[error("Stake account {account} holds {amount} lamports, below the minimum of {min_stake}")]
+    Insufficient { account: Pubkey, amount: u64, min_stake: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchedAccount {
+    pub owner: Pubkey,
+    pub data: Vec<u8>,
+}
+
+#[async_trait]
+pub trait AccountFetcher: Send + Sync + std::fmt::Debug {
+    async fn fetch_account(&self, address: &Pubkey) -> Result<Option<FetchedAccount>, String>;
+
+    /// Every account owned by `program_id`, with its address.
+    async fn fetch_program_accounts(&self, program_id: &Pubkey) -> Result<Vec<(Pubkey, FetchedAccount)>, String>;
+}
+
+#[async_trait]
+impl AccountFetcher for RpcClient {
+    async fn fetch_account(&self, address: &Pubkey) -> Result<Option<FetchedAccount>, String> {
+        let response = self
+            .get_account_with_commitment(address, self.commitment())
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(response.value.map(|account| FetchedAccount {
+            owner: account.owner,
+            data: account.data,
+        }))
+    }
+
+    async fn fetch_program_accounts(&self, program_id: &Pubkey) -> Result<Vec<(Pubkey, FetchedAccount)>, String> {
+        let accounts = self.get_program_accounts(program_id).await.map_err(|e| e.to_string())?;
+        Ok(accounts
+            .into_iter()
+            .map(|(address, account)| (address, FetchedAccount {
+                owner: account.owner,
+                data: account.data,
+            }))
+            .collect())
+    }
+}
+
+pub async fn verify_stake_account(
+    fetcher: &dyn AccountFetcher,
+    program_id: &Pubkey,
+    staker: &Pubkey,
+    min_stake: u64,
+) -> Result<StakeAccount, StakeCheckError> {
+    let (address, _) = find_stake_address(program_id, staker);
+
+    let account = fetcher
+        .fetch_account(&address)
+        .await
+        .map_err(StakeCheckError::Rpc)?
+        .ok_or(StakeCheckError::NotFound(address))?;
+
+    if account.owner != *program_id {
+        return Err(StakeCheckError::WrongProgram(address));
+    }
+
+    let stake = StakeAccount::unpack(&account.data)
+        .map_err(|e| StakeCheckError::Malformed(address, e.to_string()))?;
+
+    if stake.owner != *staker {
+        return Err(StakeCheckError::WrongOwner { account: address, owner: stake.owner });
+    }
+
+    if !stake.is_active {
+        return Err(StakeCheckError::Inactive(address));
+    }
+
+    if stake.amount < min_stake {
+        return Err(StakeCheckError::Insufficient {
+            account: address,
+            amount: stake.amount,
+            min_stake,
+        });
+    }
+
+    Ok(stake)
+}
+
+/// An in-memory stake program for tests that need stake accounts on chain.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use crate::program::stake::{find_validator_delegations_address, ValidatorDelegations};
+    use borsh::BorshSerialize;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Accounts keyed by address, as the stake program at `program_id`
+    /// would hold them.
+    #[derive(Debug)]
+    pub(crate) struct StakeLedger {
+        pub program_id: Pubkey,
+        pub accounts: HashMap<Pubkey, FetchedAccount>,
+        /// How many single-account fetches have been served.
+        pub fetches: AtomicUsize,
+    }
+
+    impl StakeLedger {
+        pub fn new(program_id: Pubkey) -> Self {
+            StakeLedger { program_id, accounts: HashMap::new(), fetches: AtomicUsize::new(0) }
+        }
+
+        /// Adds an active stake of `amount` lamports owned by `owner`.
+        pub fn with_stake(self, owner: Pubkey, amount: u64) -> Self {
+            self.with_account(StakeAccount::new(owner, amount, 0, 0))
+        }
+
+        /// Adds `stake` at the address derived from its owner.
+        pub fn with_account(self, stake: StakeAccount) -> Self {
+            let (address, _) = find_stake_address(&self.program_id, &stake.owner);
+            self.with_account_at(address, stake)
+        }
+
+        /// Adds `stake` at `address`, which need not be the derived one.
+        pub fn with_account_at(self, address: Pubkey, stake: StakeAccount) -> Self {
+            let data = stake.try_to_vec().unwrap();
+            self.with_data(address, data)
+        }
+
+        /// Adds the total delegated to `validator` at its derived address.
+        pub fn with_delegations(self, validator: Pubkey, total: u64) -> Self {
+            let (address, _) = find_validator_delegations_address(&self.program_id, &validator);
+            let data = ValidatorDelegations { validator, total }.try_to_vec().unwrap();
+            self.with_data(address, data)
+        }
+
+        /// Adds an account owned by the program holding arbitrary `data`.
+        pub fn with_data(self, address: Pubkey, data: Vec<u8>) -> Self {
+            let owner = self.program_id;
+            self.with_fetched(address, FetchedAccount { owner, data })
+        }
+
+        /// Adds `account` as is, whoever owns it.
+        pub fn with_fetched(mut self, address: Pubkey, account: FetchedAccount) -> Self {
+            self.accounts.insert(address, account);
+            self
+        }
+
+        pub fn program_accounts(&self) -> Vec<(Pubkey, FetchedAccount)> {
+            self.accounts.iter().map(|(address, account)| (*address, account.clone())).collect()
+        }
+    }
+
+    #[async_trait]
+    impl AccountFetcher for StakeLedger {
+        async fn fetch_account(&self, address: &Pubkey) -> Result<Option<FetchedAccount>, String> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            Ok(self.accounts.get(address).cloned())
+        }
+
+        async fn fetch_program_accounts(&self, _program_id: &Pubkey) -> Result<Vec<(Pubkey, FetchedAccount)>, String> {
+            Ok(self.program_accounts())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::StakeLedger;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_inactive_stake_fails_startup_check() {
+        let program_id = Pubkey::new_unique();
+        let staker = Pubkey::new_unique();
+        let rpc = StakeLedger::new(program_id)
+            .with_account(StakeAccount { is_active: false, ..StakeAccount::new(staker, 20_000_000_000, 0, 0) });
+
+        let result = verify_stake_account(&rpc, &program_id, &staker, 10_000_000_000).await;
+        assert!(matches!(result, Err(StakeCheckError::Inactive(_))));
+    }
+
+    #[tokio::test]
+    async fn test_active_stake_passes_startup_check() {
+        let program_id = Pubkey::new_unique();
+        let staker = Pubkey::new_unique();
+        let rpc = StakeLedger::new(program_id).with_stake(staker, 20_000_000_000);
+
+        let stake = verify_stake_account(&rpc, &program_id, &staker, 10_000_000_000).await.unwrap();
+        assert_eq!(stake.amount, 20_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_missing_and_underfunded_stake_reported() {
+        let program_id = Pubkey::new_unique();
+        let staker = Pubkey::new_unique();
+
+        let result = verify_stake_account(&StakeLedger::new(program_id), &program_id, &staker, 1).await;
+        assert!(matches!(result, Err(StakeCheckError::NotFound(_))));
+
+        let rpc = StakeLedger::new(program_id).with_stake(staker, 5);
+        let result = verify_stake_account(&rpc, &program_id, &staker, 10).await;
+        assert!(matches!(result, Err(StakeCheckError::Insufficient { amount: 5, .. })));
+    }
+}
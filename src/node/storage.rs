@@ -0,0 +1,314 @@
+use serde::{de::DeserializeOwned, Serialize};
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::{Db, Transactional, Tree};
+use solana_sdk::{hash::Hash, signature::Signature, transaction::Transaction};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::node::consensus::{Block, QuorumCertificate, Validator};
+use crate::node::message::PeerRecord;
+
+/// Directory under `storage_path` holding the chain database.
+pub const STORAGE_DIR: &str = "chain";
+
+const BLOCKS_TREE: &str = "blocks";
+const HEIGHTS_TREE: &str = "heights";
+const CERTIFICATES_TREE: &str = "certificates";
+const TRANSACTIONS_TREE: &str = "transactions";
+const STAKE_SNAPSHOTS_TREE: &str = "stake_snapshots";
+const PEERS_TREE: &str = "peers";
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Database error: {0}")]
+    Db(#[from] sled::Error),
+    #[error("Encoding error: {0}")]
+    Encoding(#[from] bincode::Error),
+    #[error("Height {height} already holds block {existing}")]
+    HeightConflict { height: u64, existing: Hash },
+}
+
+/// Blocks, the transactions in them, the certificates that committed them,
+/// validator stake snapshots and peer metadata, persisted under
+/// `storage_path`.
+///
+/// Heights are stored big-endian so each tree iterates in height order and
+/// its last entry is the newest.
+#[derive(Debug, Clone)]
+pub struct Storage {
+    root: PathBuf,
+    db: Db,
+    blocks: Tree,
+    heights: Tree,
+    certificates: Tree,
+    transactions: Tree,
+    stake_snapshots: Tree,
+    peers: Tree,
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, StorageError> {
+    Ok(bincode::serialize(value)?)
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StorageError> {
+    Ok(bincode::deserialize(bytes)?)
+}
+
+fn height_from_key(key: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&key[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+impl Storage {
+    /// Opens the database in `storage_path`, creating it on first run.
+    pub fn open(storage_path: &Path) -> Result<Self, StorageError> {
+        let db = sled::open(storage_path.join(STORAGE_DIR))?;
+        Ok(Storage {
+            blocks: db.open_tree(BLOCKS_TREE)?,
+            heights: db.open_tree(HEIGHTS_TREE)?,
+            certificates: db.open_tree(CERTIFICATES_TREE)?,
+            transactions: db.open_tree(TRANSACTIONS_TREE)?,
+            stake_snapshots: db.open_tree(STAKE_SNAPSHOTS_TREE)?,
+            peers: db.open_tree(PEERS_TREE)?,
+            db,
+            root: storage_path.to_path_buf(),
+        })
+    }
+
+    /// The `storage_path` this database was opened in, where other node
+    /// state such as the mempool snapshot lives alongside it.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Stores `block`, indexes it by height and indexes each of its
+    /// transactions by signature, all in one transaction. Storing the same
+    /// block again is a no-op; a different block at a stored height is refused.
+    pub fn put_block(&self, block: &Block) -> Result<Hash, StorageError> {
+        let hash = block.hash();
+        let height = block.header.height.to_be_bytes();
+        let encoded = encode(block)?;
+
+        (&self.blocks, &self.heights, &self.transactions)
+            .transaction(|(blocks, heights, transactions)| {
+                if let Some(existing) = heights.get(&height[..])? {
+                    if existing.as_ref() != hash.as_ref() {
+                        return Err(ConflictableTransactionError::Abort(Hash::new(&existing)));
+                    }
+                    return Ok(());
+                }
+                blocks.insert(hash.as_ref(), encoded.as_slice())?;
+                heights.insert(&height[..], hash.as_ref())?;
+                for transaction in &block.transactions {
+                    if let Some(signature) = transaction.signatures.first() {
+                        transactions.insert(signature.as_ref(), hash.as_ref())?;
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|e| match e {
+                TransactionError::Abort(existing) => StorageError::HeightConflict {
+                    height: block.header.height,
+                    existing,
+                },
+                TransactionError::Storage(e) => StorageError::Db(e),
+            })?;
+
+        self.db.flush()?;
+        Ok(hash)
+    }
+
+    pub fn get_block_by_hash(&self, hash: &Hash) -> Result<Option<Block>, StorageError> {
+        self.blocks.get(hash.as_ref())?.map(|bytes| decode(&bytes)).transpose()
+    }
+
+    pub fn get_block_by_height(&self, height: u64) -> Result<Option<Block>, StorageError> {
+        match self.heights.get(height.to_be_bytes())? {
+            Some(hash) => self.get_block_by_hash(&Hash::new(&hash)),
+            None => Ok(None),
+        }
+    }
+
+    /// Height of the newest stored block, or `None` before the first one.
+    pub fn latest_height(&self) -> Result<Option<u64>, StorageError> {
+        Ok(self.heights.last()?.map(|(key, _)| height_from_key(&key)))
+    }
+
+    pub fn latest_block(&self) -> Result<Option<Block>, StorageError> {
+        match self.heights.last()? {
+            Some((_, hash)) => self.get_block_by_hash(&Hash::new(&hash)),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the quorum certificate that committed the block at `height`,
+    /// so the block can be served to peers catching up.
+    pub fn put_certificate(&self, height: u64, qc: &QuorumCertificate) -> Result<(), StorageError> {
+        self.certificates.insert(height.to_be_bytes(), encode(qc)?)?;
+        Ok(())
+    }
+
+    pub fn certificate_at(&self, height: u64) -> Result<Option<QuorumCertificate>, StorageError> {
+        self.certificates.get(height.to_be_bytes())?.map(|bytes| decode(&bytes)).transpose()
+    }
+
+    /// Height of the newest block stored with its certificate.
+    pub fn latest_certified_height(&self) -> Result<Option<u64>, StorageError> {
+        Ok(self.certificates.last()?.map(|(key, _)| height_from_key(&key)))
+    }
+
+    /// Looks up a transaction through the block that included it.
+    pub fn get_transaction(&self, signature: &Signature) -> Result<Option<Transaction>, StorageError> {
+        let Some(hash) = self.transactions.get(signature.as_ref())? else {
+            return Ok(None);
+        };
+        let block = self.get_block_by_hash(&Hash::new(&hash))?;
+        Ok(block.and_then(|block| {
+            block
+                .transactions
+                .into_iter()
+                .find(|transaction| transaction.signatures.first() == Some(signature))
+        }))
+    }
+
+    /// Records the validator set as of `height`, replacing any snapshot
+    /// already taken at that height.
+    pub fn put_stake_snapshot(&self, height: u64, validators: &[Validator]) -> Result<(), StorageError> {
+        self.stake_snapshots.insert(height.to_be_bytes(), encode(&validators)?)?;
+        Ok(())
+    }
+
+    /// The most recent validator set snapshot and the height it was taken at.
+    pub fn latest_stake_snapshot(&self) -> Result<Option<(u64, Vec<Validator>)>, StorageError> {
+        self.stake_snapshots
+            .last()?
+            .map(|(key, bytes)| Ok((height_from_key(&key), decode(&bytes)?)))
+            .transpose()
+    }
+
+    /// The newest validator set snapshot taken at or below `height`.
+    pub fn stake_snapshot_at(&self, height: u64) -> Result<Option<(u64, Vec<Validator>)>, StorageError> {
+        self.stake_snapshots
+            .range(..=height.to_be_bytes())
+            .next_back()
+            .transpose()?
+            .map(|(key, bytes)| Ok((height_from_key(&key), decode(&bytes)?)))
+            .transpose()
+    }
+
+    /// Remembers where `record`'s node was last reachable, keyed by node ID.
+    pub fn put_peer(&self, record: &PeerRecord) -> Result<(), StorageError> {
+        self.peers.insert(record.node_id.as_bytes(), encode(record)?)?;
+        Ok(())
+    }
+
+    pub fn peers(&self) -> Result<Vec<PeerRecord>, StorageError> {
+        self.peers.iter().map(|entry| decode(&entry?.1)).collect()
+    }
+
+    pub fn flush(&self) -> Result<(), StorageError> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_transaction,
+    };
+    use std::net::SocketAddr;
+
+    fn block(height: u64, parent_hash: Hash, transactions: Vec<Transaction>) -> Block {
+        Block::new(height, height, parent_hash, 1_700_000_000 + height as i64, transactions, &Keypair::new())
+    }
+
+    fn transfer() -> Transaction {
+        let payer = Keypair::new();
+        system_transaction::transfer(&payer, &Pubkey::new_unique(), 1, Hash::default())
+    }
+
+    #[test]
+    fn test_blocks_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let tx = transfer();
+        let first = block(1, Hash::default(), vec![tx.clone()]);
+        let second = block(2, first.hash(), Vec::new());
+
+        {
+            let storage = Storage::open(dir.path()).unwrap();
+            assert_eq!(storage.latest_height().unwrap(), None);
+            storage.put_block(&first).unwrap();
+            storage.put_block(&second).unwrap();
+        }
+
+        let storage = Storage::open(dir.path()).unwrap();
+        assert_eq!(storage.latest_height().unwrap(), Some(2));
+        assert_eq!(storage.latest_block().unwrap(), Some(second.clone()));
+        assert_eq!(storage.get_block_by_hash(&first.hash()).unwrap(), Some(first));
+        assert_eq!(storage.get_block_by_height(2).unwrap(), Some(second));
+        assert_eq!(storage.get_transaction(&tx.signatures[0]).unwrap(), Some(tx));
+        assert_eq!(storage.get_block_by_height(3).unwrap(), None);
+    }
+
+    #[test]
+    fn test_certificates_stored_by_height() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::open(dir.path()).unwrap();
+        let voter = Keypair::new();
+        let first = block(1, Hash::default(), Vec::new());
+        let qc = QuorumCertificate {
+            block_hash: first.hash(),
+            votes: vec![(voter.pubkey(), voter.sign_message(first.hash().as_ref()))],
+        };
+
+        assert_eq!(storage.latest_certified_height().unwrap(), None);
+        storage.put_block(&first).unwrap();
+        storage.put_certificate(1, &qc).unwrap();
+        storage.put_block(&block(2, first.hash(), Vec::new())).unwrap();
+
+        assert_eq!(storage.certificate_at(1).unwrap(), Some(qc));
+        assert_eq!(storage.certificate_at(2).unwrap(), None);
+        assert_eq!(storage.latest_certified_height().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_conflicting_block_at_height_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::open(dir.path()).unwrap();
+        let stored = block(1, Hash::default(), Vec::new());
+        let rival = block(1, Hash::default(), vec![transfer()]);
+
+        storage.put_block(&stored).unwrap();
+        storage.put_block(&stored).unwrap();
+        let err = storage.put_block(&rival).unwrap_err();
+
+        assert!(matches!(err, StorageError::HeightConflict { height: 1, existing } if existing == stored.hash()));
+        assert_eq!(storage.get_block_by_hash(&rival.hash()).unwrap(), None);
+        assert_eq!(storage.get_transaction(&rival.transactions[0].signatures[0]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_stake_snapshots_and_peers_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::open(dir.path()).unwrap();
+        let validator = Validator { pubkey: Pubkey::new_unique(), stake: 42, locked_until: 100 };
+
+        storage.put_stake_snapshot(3, &[]).unwrap();
+        storage.put_stake_snapshot(7, &[validator.clone()]).unwrap();
+        let (height, validators) = storage.latest_stake_snapshot().unwrap().unwrap();
+        assert_eq!(height, 7);
+        assert_eq!(validators[0].pubkey, validator.pubkey);
+        assert_eq!(storage.stake_snapshot_at(6).unwrap().unwrap().0, 3);
+        assert!(storage.stake_snapshot_at(2).unwrap().is_none());
+
+        let record = PeerRecord { node_id: "node-b".to_string(), addr: SocketAddr::from(([10, 0, 0, 2], 8001)) };
+        storage.put_peer(&record).unwrap();
+        storage.put_peer(&record).unwrap();
+        assert_eq!(storage.peers().unwrap(), vec![record]);
+    }
+}
@@ -0,0 +1,202 @@
+use async_trait::async_trait;
+use log::{debug, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::Transaction;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::{sleep, timeout};
+
+#[derive(Error, Debug)]
+pub enum SubmitError {
+    #[error("Transaction is unsigned")]
+    Unsigned,
+    #[error("Transaction {0} failed on chain: {1}")]
+    Failed(Signature, String),
+    #[error("Transaction {signature} not confirmed after {attempts} attempts: {last_error}")]
+    Exhausted { signature: Signature, attempts: u32, last_error: String },
+}
+
+#[async_trait]
+pub trait TransactionSender: Send + Sync {
+    async fn send(&self, transaction: &Transaction) -> Result<Signature, String>;
+
+    /// `None` while the cluster has not seen the signature, otherwise the
+    /// transaction's execution result.
+    async fn status(&self, signature: &Signature) -> Result<Option<Result<(), String>>, String>;
+}
+
+#[async_trait]
+impl TransactionSender for RpcClient {
+    async fn send(&self, transaction: &Transaction) -> Result<Signature, String> {
+        self.send_transaction(transaction).await.map_err(|e| e.to_string())
+    }
+
+    async fn status(&self, signature: &Signature) -> Result<Option<Result<(), String>>, String> {
+        self.get_signature_status(signature)
+            .await
+            .map(|status| status.map(|result| result.map_err(|e| e.to_string())))
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub attempt_timeout: Duration,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            attempt_timeout: Duration::from_secs(3),
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Submits `transaction`, re-sending the same signed bytes on failure. The
+/// signature status is checked before every retry so a send that timed out
+/// but landed is reported as success rather than submitted again.
+pub async fn submit_with_retry(
+    sender: &dyn TransactionSender,
+    transaction: &Transaction,
+    policy: RetryPolicy,
+) -> Result<Signature, SubmitError> {
+    let signature = *transaction.signatures.first().ok_or(SubmitError::Unsigned)?;
+    if signature == Signature::default() {
+        return Err(SubmitError::Unsigned);
+    }
+
+    let attempts = policy.attempts.max(1);
+    let mut last_error = String::new();
+
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            sleep(policy.backoff).await;
+            if let Some(result) = check_landed(sender, &signature).await? {
+                return result;
+            }
+        }
+
+        match timeout(policy.attempt_timeout, sender.send(transaction)).await {
+            Ok(Ok(sent)) => {
+                debug!("Submitted {} on attempt {}", sent, attempt);
+                return Ok(sent);
+            }
+            Ok(Err(e)) => {
+                warn!("Attempt {} to submit {} failed: {}", attempt, signature, e);
+                last_error = e;
+            }
+            Err(_) => {
+                warn!("Attempt {} to submit {} timed out after {:?}", attempt, signature, policy.attempt_timeout);
+                last_error = "send timed out".to_string();
+            }
+        }
+    }
+
+    if let Some(result) = check_landed(sender, &signature).await? {
+        return result;
+    }
+
+    Err(SubmitError::Exhausted { signature, attempts, last_error })
+}
+
+async fn check_landed(
+    sender: &dyn TransactionSender,
+    signature: &Signature,
+) -> Result<Option<Result<Signature, SubmitError>>, SubmitError> {
+    match sender.status(signature).await {
+        Ok(Some(Ok(()))) => {
+            debug!("Transaction {} already landed, not re-sending", signature);
+            Ok(Some(Ok(*signature)))
+        }
+        Ok(Some(Err(e))) => Ok(Some(Err(SubmitError::Failed(*signature, e)))),
+        Ok(None) => Ok(None),
+        Err(e) => {
+            warn!("Could not fetch status for {}: {}", signature, e);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use solana_sdk::hash::Hash;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::Keypair;
+    use solana_sdk::system_transaction;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Accepts every transaction but the first send hangs past the timeout.
+    #[derive(Default)]
+    struct SlowFirstSend {
+        sends: AtomicUsize,
+        landed: Mutex<HashSet<Signature>>,
+        submitted: Mutex<Vec<Signature>>,
+    }
+
+    #[async_trait]
+    impl TransactionSender for SlowFirstSend {
+        async fn send(&self, transaction: &Transaction) -> Result<Signature, String> {
+            let signature = transaction.signatures[0];
+            self.submitted.lock().push(signature);
+            self.landed.lock().insert(signature);
+            if self.sends.fetch_add(1, Ordering::SeqCst) == 0 {
+                sleep(Duration::from_secs(60)).await;
+            }
+            Ok(signature)
+        }
+
+        async fn status(&self, signature: &Signature) -> Result<Option<Result<(), String>>, String> {
+            Ok(self.landed.lock().contains(signature).then_some(Ok(())))
+        }
+    }
+
+    fn signed_transfer() -> Transaction {
+        system_transaction::transfer(&Keypair::new(), &Pubkey::new_unique(), 1_000, Hash::new_unique())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timed_out_send_that_landed_is_not_resubmitted() {
+        let sender = SlowFirstSend::default();
+        let transaction = signed_transfer();
+
+        let result = submit_with_retry(&sender, &transaction, RetryPolicy::default()).await;
+
+        assert_eq!(result.unwrap(), transaction.signatures[0]);
+        assert_eq!(*sender.submitted.lock(), vec![transaction.signatures[0]]);
+    }
+
+    struct AlwaysFails {
+        sends: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl TransactionSender for AlwaysFails {
+        async fn send(&self, _transaction: &Transaction) -> Result<Signature, String> {
+            self.sends.fetch_add(1, Ordering::SeqCst);
+            Err("node is behind".to_string())
+        }
+
+        async fn status(&self, _signature: &Signature) -> Result<Option<Result<(), String>>, String> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_resend_same_transaction_until_exhausted() {
+        let sender = AlwaysFails { sends: AtomicUsize::new(0) };
+        let transaction = signed_transfer();
+
+        let result = submit_with_retry(&sender, &transaction, RetryPolicy::default()).await;
+
+        assert!(matches!(result, Err(SubmitError::Exhausted { attempts: 3, .. })));
+        assert_eq!(sender.sends.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,149 @@
+use axum::extract::ws::{Message as WsMessage, WebSocket};
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::node::activity::{Activity, TOPICS};
+use crate::node::control::{
+    RpcRequest, RpcResponse, INVALID_PARAMS, INVALID_REQUEST, JSONRPC_VERSION, METHOD_NOT_FOUND, PARSE_ERROR,
+};
+
+/// The topics one WebSocket client follows. Clients call `subscribe` and
+/// `unsubscribe` with the topic names in `params.topics`; each matching
+/// event is pushed to them as a `subscription` notification.
+#[derive(Debug, Default)]
+pub struct Subscription {
+    topics: BTreeSet<&'static str>,
+}
+
+impl Subscription {
+    pub fn handle(&mut self, request: RpcRequest) -> RpcResponse {
+        let id = request.id;
+        if request.jsonrpc != JSONRPC_VERSION {
+            return RpcResponse::err(id, INVALID_REQUEST, "jsonrpc must be \"2.0\"");
+        }
+
+        let subscribe = match request.method.as_str() {
+            "subscribe" => true,
+            "unsubscribe" => false,
+            other => return RpcResponse::err(id, METHOD_NOT_FOUND, format!("unknown method '{}'", other)),
+        };
+        let requested: Option<Vec<&'static str>> = request
+            .params
+            .get("topics")
+            .and_then(Value::as_array)
+            .and_then(|topics| {
+                topics
+                    .iter()
+                    .map(|topic| topic.as_str().and_then(|topic| TOPICS.iter().copied().find(|t| *t == topic)))
+                    .collect()
+            });
+        let Some(requested) = requested else {
+            return RpcResponse::err(id, INVALID_PARAMS, format!("expected params.topics from {:?}", TOPICS));
+        };
+
+        for topic in requested {
+            if subscribe {
+                self.topics.insert(topic);
+            } else {
+                self.topics.remove(topic);
+            }
+        }
+        RpcResponse::ok(id, json!({ "topics": self.topics }))
+    }
+
+    /// The notification to push for `activity`, if the client follows its topic.
+    pub fn notification(&self, activity: &Activity) -> Option<Value> {
+        self.topics.contains(activity.topic()).then(|| {
+            json!({ "jsonrpc": JSONRPC_VERSION, "method": "subscription", "params": activity })
+        })
+    }
+}
+
+/// Serves one WebSocket client until it disconnects or the node stops
+/// publishing activity.
+pub async fn run(mut socket: WebSocket, mut activity: broadcast::Receiver<Activity>) {
+    let mut subscription = Subscription::default();
+    loop {
+        let outgoing = tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(WsMessage::Text(text))) => {
+                    let response = match serde_json::from_str::<RpcRequest>(&text) {
+                        Ok(request) => subscription.handle(request),
+                        Err(e) => RpcResponse::err(Value::Null, PARSE_ERROR, e.to_string()),
+                    };
+                    json!(response)
+                }
+                Some(Ok(WsMessage::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    debug!("WebSocket subscriber failed: {}", e);
+                    break;
+                }
+            },
+            received = activity.recv() => match received {
+                Ok(event) => match subscription.notification(&event) {
+                    Some(notification) => notification,
+                    None => continue,
+                },
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("WebSocket subscriber fell behind and skipped {} events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        if socket.send(WsMessage::Text(outgoing.to_string())).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn request(method: &str, topics: Value) -> RpcRequest {
+        RpcRequest {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.to_string(),
+            params: json!({ "topics": topics }),
+            id: json!(1),
+        }
+    }
+
+    #[test]
+    fn test_only_subscribed_topics_are_notified() {
+        let mut subscription = Subscription::default();
+        let block = Activity::NewBlock { height: 3, hash: "abc".to_string(), transactions: 2 };
+        let peer = Activity::PeerConnected { addr: SocketAddr::from(([10, 0, 0, 2], 8001)) };
+        assert!(subscription.notification(&block).is_none());
+
+        let response = subscription.handle(request("subscribe", json!(["newBlocks", "peerConnected"])));
+        assert_eq!(response.result.unwrap()["topics"], json!(["newBlocks", "peerConnected"]));
+
+        let notification = subscription.notification(&block).unwrap();
+        assert_eq!(notification["params"]["event"], "newBlock");
+        assert_eq!(notification["params"]["height"], 3);
+        assert_eq!(subscription.notification(&peer).unwrap()["params"]["addr"], "10.0.0.2:8001");
+
+        subscription.handle(request("unsubscribe", json!(["peerConnected"])));
+        assert!(subscription.notification(&peer).is_none());
+        assert!(subscription.notification(&block).is_some());
+    }
+
+    #[test]
+    fn test_unknown_topics_and_methods_rejected() {
+        let mut subscription = Subscription::default();
+
+        let response = subscription.handle(request("subscribe", json!(["newBlocks", "everything"])));
+        assert_eq!(response.error.unwrap().code, INVALID_PARAMS);
+        let block = Activity::NewBlock { height: 1, hash: "abc".to_string(), transactions: 0 };
+        assert!(subscription.notification(&block).is_none());
+
+        let response = subscription.handle(request("getBlock", json!([])));
+        assert_eq!(response.error.unwrap().code, METHOD_NOT_FOUND);
+    }
+}
@@ -0,0 +1,419 @@
+use crate::node::consensus::{Block, CertifiedHeader, ChainError};
+use crate::node::error::NodeError;
+use crate::node::outbox::Outboxes;
+use crate::node::snapshot::StateSync;
+use async_trait::async_trait;
+use log::{info, warn};
+use solana_sdk::pubkey::Pubkey;
+use std::sync::Arc;
+use thiserror::Error;
+
+pub const DEFAULT_SYNC_BATCH: usize = 128;
+
+#[derive(Error, Debug)]
+pub enum SyncError {
+    #[error("Peer {0} failed to serve the chain: {1}")]
+    Peer(String, String),
+    #[error("Chain verification failed: {0}")]
+    Chain(#[from] ChainError),
+    #[error("Could not extend the local chain: {0}")]
+    Local(#[from] NodeError),
+    #[error("No peers available for catch-up")]
+    NoPeers,
+    #[error("Catch-up stopped at height {reached}, cluster tip is {target}")]
+    Incomplete { reached: u64, target: u64 },
+}
+
+#[async_trait]
+pub trait HeaderSource: Send + Sync {
+    fn id(&self) -> String;
+
+    /// The peer's validator identity, if known. Its stake weighs its tip.
+    fn pubkey(&self) -> Option<Pubkey>;
+
+    async fn tip_height(&self) -> Result<u64, SyncError>;
+
+    async fn headers_from(&self, start_height: u64, limit: usize) -> Result<Vec<CertifiedHeader>, SyncError>;
+
+    /// The blocks `headers` certify, in the same order.
+    async fn blocks_for(&self, headers: &[CertifiedHeader]) -> Result<Vec<Block>, SyncError>;
+}
+
+/// The local chain catch-up extends. Each call takes whatever locks it
+/// needs and releases them before returning, so none is held while
+/// waiting on a peer.
+pub trait SyncTarget: Send + Sync {
+    fn height(&self) -> u64;
+
+    /// Stake `pubkey` holds in the local validator set, zero if none.
+    fn stake_of(&self, pubkey: &Pubkey) -> u64;
+
+    /// Checks that `headers` extend the local tip under valid certificates.
+    fn verify_headers(&self, headers: &[CertifiedHeader]) -> Result<(), ChainError>;
+
+    /// Stores `blocks`, certified by `headers`, and moves the tip to the
+    /// last of them.
+    fn append(&self, headers: &[CertifiedHeader], blocks: &[Block]) -> Result<(), SyncError>;
+}
+
+/// A peer serving headers and blocks through its session.
+pub struct SessionHeaderSource {
+    server: Pubkey,
+    state_sync: Arc<StateSync>,
+    outboxes: Arc<Outboxes>,
+}
+
+impl SessionHeaderSource {
+    pub fn new(server: Pubkey, state_sync: Arc<StateSync>, outboxes: Arc<Outboxes>) -> Self {
+        SessionHeaderSource { server, state_sync, outboxes }
+    }
+
+    async fn request_headers(&self, start_height: u64, limit: usize) -> Result<(u64, Vec<CertifiedHeader>), SyncError> {
+        self.state_sync
+            .request_headers(&self.outboxes, self.server, start_height, limit)
+            .await
+            .ok_or_else(|| SyncError::Peer(self.id(), "did not answer a header request".to_string()))
+    }
+}
+
+#[async_trait]
+impl HeaderSource for SessionHeaderSource {
+    fn id(&self) -> String {
+        self.server.to_string()
+    }
+
+    fn pubkey(&self) -> Option<Pubkey> {
+        Some(self.server)
+    }
+
+    async fn tip_height(&self) -> Result<u64, SyncError> {
+        Ok(self.request_headers(0, 0).await?.0)
+    }
+
+    async fn headers_from(&self, start_height: u64, limit: usize) -> Result<Vec<CertifiedHeader>, SyncError> {
+        Ok(self.request_headers(start_height, limit).await?.1)
+    }
+
+    async fn blocks_for(&self, headers: &[CertifiedHeader]) -> Result<Vec<Block>, SyncError> {
+        let Some(first) = headers.first() else {
+            return Ok(Vec::new());
+        };
+        let mut blocks = Vec::with_capacity(headers.len());
+        while blocks.len() < headers.len() {
+            let from_height = first.header.height + blocks.len() as u64;
+            let payloads = self
+                .state_sync
+                .request_blocks(&self.outboxes, self.server, from_height)
+                .await
+                .ok_or_else(|| SyncError::Peer(self.id(), "did not answer a block request".to_string()))?;
+            if payloads.is_empty() {
+                return Err(SyncError::Peer(self.id(), format!("has no block at height {}", from_height)));
+            }
+            for payload in payloads.iter().take(headers.len() - blocks.len()) {
+                let block = bincode::deserialize(payload).map_err(|e| SyncError::Peer(self.id(), e.to_string()))?;
+                blocks.push(block);
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+pub struct CatchUp {
+    batch_size: usize,
+}
+
+impl Default for CatchUp {
+    fn default() -> Self {
+        CatchUp {
+            batch_size: DEFAULT_SYNC_BATCH,
+        }
+    }
+}
+
+impl CatchUp {
+    pub fn new(batch_size: usize) -> Self {
+        CatchUp {
+            batch_size: batch_size.max(1),
+        }
+    }
+
+    /// Syncs up to the highest tip advertised by sources holding more than
+    /// half of their combined stake, so one peer claiming an inflated tip
+    /// can't hold catch-up `Incomplete`. Sources that fail to serve up to
+    /// the tip they claimed are passed to `penalize` by id.
+    pub async fn run(
+        &self,
+        chain: &dyn SyncTarget,
+        sources: &[Arc<dyn HeaderSource>],
+        penalize: impl Fn(&str),
+    ) -> Result<u64, SyncError> {
+        if sources.is_empty() {
+            return Err(SyncError::NoPeers);
+        }
+
+        let mut tips = Vec::new();
+        for source in sources {
+            match source.tip_height().await {
+                Ok(tip) => tips.push((tip, Arc::clone(source))),
+                Err(e) => warn!("Skipping sync peer {}: {}", source.id(), e),
+            }
+        }
+        tips.sort_by(|a, b| b.0.cmp(&a.0));
+
+        let target = match majority_tip(chain, &tips) {
+            Some(tip) => tip,
+            None => return Err(SyncError::NoPeers),
+        };
+
+        if chain.height() >= target {
+            return Ok(chain.height());
+        }
+
+        info!("Catching up from height {} to {}", chain.height(), target);
+
+        for (tip, source) in &tips {
+            if chain.height() >= target {
+                break;
+            }
+
+            if let Err(e) = self.sync_from(chain, source.as_ref(), (*tip).min(target)).await {
+                warn!("Sync from peer {} failed at height {}: {}", source.id(), chain.height(), e);
+                penalize(&source.id());
+            }
+        }
+
+        if chain.height() < target {
+            return Err(SyncError::Incomplete {
+                reached: chain.height(),
+                target,
+            });
+        }
+
+        info!("Caught up to height {}", chain.height());
+        Ok(chain.height())
+    }
+
+    /// Checks each batch of headers before fetching the blocks behind it,
+    /// so a peer serving an uncertified chain costs one header request.
+    async fn sync_from(&self, chain: &dyn SyncTarget, source: &dyn HeaderSource, tip: u64) -> Result<(), SyncError> {
+        while chain.height() < tip {
+            let headers = source.headers_from(chain.height() + 1, self.batch_size).await?;
+            if headers.is_empty() {
+                return Err(SyncError::Peer(source.id(), "returned no headers before its tip".to_string()));
+            }
+            chain.verify_headers(&headers)?;
+
+            let blocks = source.blocks_for(&headers).await?;
+            let matching = blocks.len() == headers.len()
+                && blocks.iter().zip(&headers).all(|(block, certified)| block.hash() == certified.qc.block_hash);
+            if !matching {
+                return Err(SyncError::Peer(source.id(), "sent blocks that don't match its headers".to_string()));
+            }
+            chain.append(&headers, &blocks)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The highest tip that sources holding more than half of the advertising
+/// stake have reached. Without any staked source each counts once. `tips`
+/// must be sorted highest first.
+fn majority_tip(chain: &dyn SyncTarget, tips: &[(u64, Arc<dyn HeaderSource>)]) -> Option<u64> {
+    let stake_of = |source: &Arc<dyn HeaderSource>| source.pubkey().map_or(0, |pubkey| chain.stake_of(&pubkey));
+    let mut weights: Vec<u128> = tips.iter().map(|(_, source)| stake_of(source) as u128).collect();
+    if weights.iter().all(|w| *w == 0) {
+        weights.iter_mut().for_each(|w| *w = 1);
+    }
+    let total: u128 = weights.iter().sum();
+    let mut backing = 0u128;
+    for ((tip, _), weight) in tips.iter().zip(weights) {
+        backing += weight;
+        if backing * 2 > total {
+            return Some(*tip);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::consensus::{ConsensusManager, QuorumCertificate, Validator};
+    use solana_sdk::hash::Hash;
+    use solana_sdk::signature::{Keypair, Signer};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    struct ClusterNode {
+        id: String,
+        pubkey: Option<Pubkey>,
+        /// Tip advertised, which a lying peer sets past its chain.
+        tip: u64,
+        chain: Vec<CertifiedHeader>,
+        blocks: Vec<Block>,
+    }
+
+    impl ClusterNode {
+        fn new(id: &str, (chain, blocks): (Vec<CertifiedHeader>, Vec<Block>)) -> Self {
+            ClusterNode { id: id.to_string(), pubkey: None, tip: chain.len() as u64, chain, blocks }
+        }
+    }
+
+    #[async_trait]
+    impl HeaderSource for ClusterNode {
+        fn id(&self) -> String {
+            self.id.clone()
+        }
+
+        fn pubkey(&self) -> Option<Pubkey> {
+            self.pubkey
+        }
+
+        async fn tip_height(&self) -> Result<u64, SyncError> {
+            Ok(self.tip)
+        }
+
+        async fn headers_from(&self, start_height: u64, limit: usize) -> Result<Vec<CertifiedHeader>, SyncError> {
+            Ok(self.chain
+                .iter()
+                .filter(|h| h.header.height >= start_height)
+                .take(limit)
+                .cloned()
+                .collect())
+        }
+
+        async fn blocks_for(&self, headers: &[CertifiedHeader]) -> Result<Vec<Block>, SyncError> {
+            Ok(headers
+                .iter()
+                .filter_map(|h| self.blocks.iter().find(|b| b.header.height == h.header.height))
+                .cloned()
+                .collect())
+        }
+    }
+
+    /// A chain held in memory: the tip in `consensus`, the blocks appended
+    /// in `blocks`.
+    struct LocalChain {
+        consensus: parking_lot::RwLock<ConsensusManager>,
+        blocks: Mutex<Vec<Block>>,
+    }
+
+    impl SyncTarget for LocalChain {
+        fn height(&self) -> u64 {
+            self.consensus.read().height()
+        }
+
+        fn stake_of(&self, pubkey: &Pubkey) -> u64 {
+            self.consensus.read().validators().iter().find(|v| v.pubkey == *pubkey).map_or(0, |v| v.stake)
+        }
+
+        fn verify_headers(&self, headers: &[CertifiedHeader]) -> Result<(), ChainError> {
+            self.consensus.read().verify_header_chain(headers)
+        }
+
+        fn append(&self, headers: &[CertifiedHeader], blocks: &[Block]) -> Result<(), SyncError> {
+            self.consensus.write().fast_forward(headers)?;
+            self.blocks.lock().unwrap().extend_from_slice(blocks);
+            Ok(())
+        }
+    }
+
+    fn build_chain(keys: &[Keypair], length: u64) -> (Vec<CertifiedHeader>, Vec<Block>) {
+        let mut chain = Vec::new();
+        let mut blocks: Vec<Block> = Vec::new();
+        for height in 1..=length {
+            let parent_hash = blocks.last().map_or_else(Hash::default, Block::hash);
+            let block = Block::new(height, height, parent_hash, height as i64, Vec::new(), &keys[(height as usize) % keys.len()]);
+            let block_hash = block.hash();
+            let votes = keys
+                .iter()
+                .map(|k| (k.pubkey(), k.sign_message(block_hash.as_ref())))
+                .collect();
+            chain.push(CertifiedHeader {
+                header: block.header.clone(),
+                qc: QuorumCertificate { block_hash, votes },
+            });
+            blocks.push(block);
+        }
+        (chain, blocks)
+    }
+
+    fn chain_for(keys: &[Keypair]) -> LocalChain {
+        let mut manager = ConsensusManager::new(Duration::from_secs(5));
+        for key in keys {
+            manager.add_validator(Validator { pubkey: key.pubkey(), stake: 1, locked_until: i64::MAX });
+        }
+        LocalChain { consensus: parking_lot::RwLock::new(manager), blocks: Mutex::new(Vec::new()) }
+    }
+
+    #[tokio::test]
+    async fn test_late_node_catches_up_to_cluster_tip() {
+        let keys: Vec<Keypair> = (0..4).map(|_| Keypair::new()).collect();
+        let chain = build_chain(&keys, 300);
+
+        let mut tampered = chain.clone();
+        tampered.0[150].header.timestamp += 1;
+
+        let sources: Vec<Arc<dyn HeaderSource>> = vec![
+            Arc::new(ClusterNode::new("byzantine", tampered)),
+            Arc::new(ClusterNode::new("honest", chain.clone())),
+        ];
+
+        let late = chain_for(&keys);
+        let penalized = Mutex::new(Vec::new());
+        let height = CatchUp::new(64)
+            .run(&late, &sources, |id| penalized.lock().unwrap().push(id.to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(height, 300);
+        assert_eq!(late.consensus.read().last_block_hash(), chain.0.last().unwrap().header.hash());
+        assert_eq!(*late.blocks.lock().unwrap(), chain.1);
+        assert_eq!(*penalized.lock().unwrap(), vec!["byzantine".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_inflated_tip_outvoted_by_stake_and_penalized() {
+        let keys: Vec<Keypair> = (0..4).map(|_| Keypair::new()).collect();
+        let chain = build_chain(&keys, 50);
+        let liar = ClusterNode {
+            pubkey: Some(keys[0].pubkey()),
+            tip: 1_000_000,
+            ..ClusterNode::new("liar", (Vec::new(), Vec::new()))
+        };
+        let honest = |i: usize| ClusterNode {
+            pubkey: Some(keys[i].pubkey()),
+            ..ClusterNode::new(&format!("honest-{}", i), chain.clone())
+        };
+        let sources: Vec<Arc<dyn HeaderSource>> = vec![Arc::new(liar), Arc::new(honest(1)), Arc::new(honest(2))];
+
+        let late = chain_for(&keys);
+        let penalized = Mutex::new(Vec::new());
+        let height = CatchUp::default()
+            .run(&late, &sources, |id| penalized.lock().unwrap().push(id.to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(height, 50);
+        assert_eq!(*penalized.lock().unwrap(), vec!["liar".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_rejects_chain_without_quorum() {
+        let keys: Vec<Keypair> = (0..4).map(|_| Keypair::new()).collect();
+        let mut chain = build_chain(&keys, 10);
+        for certified in chain.0.iter_mut() {
+            certified.qc.votes.truncate(2);
+        }
+
+        let sources: Vec<Arc<dyn HeaderSource>> = vec![Arc::new(ClusterNode::new("weak", chain))];
+
+        let late = chain_for(&keys);
+        let result = CatchUp::default().run(&late, &sources, |_| {}).await;
+
+        assert!(matches!(result, Err(SyncError::Incomplete { reached: 0, target: 10 })));
+        assert_eq!(late.height(), 0);
+        assert!(late.blocks.lock().unwrap().is_empty());
+    }
+}
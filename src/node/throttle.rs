@@ -0,0 +1,181 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Instant, Sleep};
+
+use crate::node::transport::Connection;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendLimit {
+    pub bytes_per_sec: u64,
+    pub burst_bytes: u64,
+}
+
+/// Byte budget refilled continuously at `bytes_per_sec`, holding at most
+/// `burst_bytes`.
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: SendLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: SendLimit) -> Self {
+        let limit = SendLimit {
+            bytes_per_sec: limit.bytes_per_sec.max(1),
+            burst_bytes: limit.burst_bytes.max(1),
+        };
+        TokenBucket {
+            limit,
+            tokens: limit.burst_bytes as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.bytes_per_sec as f64).min(self.limit.burst_bytes as f64);
+        self.last_refill = now;
+    }
+
+    /// Bytes that may be sent now (at most `want`), or how long to wait for
+    /// at least one.
+    pub fn available(&mut self, want: usize, now: Instant) -> Result<usize, Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            return Ok((self.tokens as usize).min(want));
+        }
+        let missing = 1.0 - self.tokens;
+        Err(Duration::from_secs_f64(missing / self.limit.bytes_per_sec as f64))
+    }
+
+    pub fn consume(&mut self, bytes: usize) {
+        self.tokens -= bytes as f64;
+    }
+}
+
+/// Wraps a connection so writes to the peer are paced by a `TokenBucket`.
+/// Without a limit it passes writes straight through.
+#[derive(Debug)]
+pub struct Throttled<C> {
+    inner: C,
+    bucket: Option<TokenBucket>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<C> Throttled<C> {
+    pub fn new(inner: C, limit: Option<SendLimit>) -> Self {
+        Throttled {
+            inner,
+            bucket: limit.map(TokenBucket::new),
+            delay: None,
+        }
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+impl<C: Connection> Connection for Throttled<C> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+}
+
+impl<C: AsyncRead + Unpin> AsyncRead for Throttled<C> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<C: AsyncWrite + Unpin> AsyncWrite for Throttled<C> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let Some(bucket) = this.bucket.as_mut() else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+
+        loop {
+            if let Some(delay) = this.delay.as_mut() {
+                ready!(delay.as_mut().poll(cx));
+                this.delay = None;
+            }
+
+            match bucket.available(buf.len(), Instant::now()) {
+                Ok(allowed) => {
+                    let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..allowed]))?;
+                    bucket.consume(written);
+                    return Poll::Ready(Ok(written));
+                }
+                Err(wait) => this.delay = Some(Box::pin(sleep(wait))),
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::transport::MemoryConnection;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn pair(port: u16) -> (MemoryConnection, MemoryConnection) {
+        MemoryConnection::pair(
+            SocketAddr::from(([10, 0, 0, 1], 8000)),
+            SocketAddr::from(([10, 0, 0, 2], port)),
+        )
+    }
+
+    async fn timed_send<C: Connection>(mut conn: C, mut remote: MemoryConnection, bytes: usize) -> Duration {
+        let started = Instant::now();
+        let reader = tokio::spawn(async move {
+            let mut received = vec![0; bytes];
+            remote.read_exact(&mut received).await.unwrap();
+        });
+        conn.write_all(&vec![7; bytes]).await.unwrap();
+        reader.await.unwrap();
+        started.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttled_peer_capped_while_others_unaffected() {
+        let limit = SendLimit { bytes_per_sec: 10_000, burst_bytes: 1_000 };
+
+        let (slow, slow_remote) = pair(9001);
+        let (fast, fast_remote) = pair(9002);
+        let (throttled, unthrottled) = tokio::join!(
+            timed_send(Throttled::new(slow, Some(limit)), slow_remote, 20_000),
+            timed_send(Throttled::new(fast, None), fast_remote, 20_000),
+        );
+
+        // 1 KB of burst, then 19 KB at 10 KB/s.
+        assert!(throttled >= Duration::from_millis(1_850), "{:?}", throttled);
+        assert!(throttled <= Duration::from_millis(2_200), "{:?}", throttled);
+        assert!(unthrottled < Duration::from_millis(10), "{:?}", unthrottled);
+    }
+
+    #[test]
+    fn test_bucket_never_exceeds_burst() {
+        let mut bucket = TokenBucket::new(SendLimit { bytes_per_sec: 1_000, burst_bytes: 500 });
+        let later = Instant::now() + Duration::from_secs(60);
+        assert_eq!(bucket.available(10_000, later), Ok(500));
+
+        bucket.consume(500);
+        let wait = bucket.available(1, later).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(1));
+    }
+}
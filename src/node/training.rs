@@ -0,0 +1,448 @@
+use log::{debug, warn};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use solana_sdk::hash::{hashv, Hash};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::node::message::Message;
+use crate::node::outbox::Outboxes;
+
+const SHARD_DOMAIN: &[u8] = b"fractis-gradient-shard-v1";
+/// Gradient values per shard: 4 MiB of f32, well under the frame limit.
+pub const SHARD_VALUES: usize = 1 << 20;
+pub const DEFAULT_ROUND_TIMEOUT: Duration = Duration::from_secs(60);
+/// How far past the last finished round shards are still accepted, so a
+/// peer running slightly ahead is not dropped.
+const MAX_ROUNDS_AHEAD: u64 = 4;
+/// Shards one trainer may send for a round: the full f32 gradients of a 7B
+/// model fit.
+const MAX_SHARDS_PER_TRAINER: u32 = 8192;
+
+#[derive(Debug, Error)]
+pub enum TrainingError {
+    #[error("No staked participant contributed gradients for round {0}")]
+    NoGradients(u64),
+    #[error("Node is shutting down")]
+    ShuttingDown,
+}
+
+/// One named gradient tensor, flattened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub values: Vec<f32>,
+}
+
+/// A run of one flattened gradient as sent over the wire, in little-endian
+/// f32 bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GradientSlice {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub offset: u64,
+    pub values: Vec<u8>,
+}
+
+/// One signed piece of a trainer's gradients for a round. A trainer's
+/// gradients are complete once all `count` shards have arrived.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GradientShard {
+    pub round: u64,
+    pub trainer: [u8; 32],
+    pub index: u32,
+    pub count: u32,
+    pub slices: Vec<GradientSlice>,
+    pub signature: Vec<u8>,
+}
+
+impl GradientShard {
+    fn signed_hash(&self) -> Hash {
+        let slices = bincode::serialize(&self.slices).unwrap_or_default();
+        hashv(&[
+            SHARD_DOMAIN,
+            &self.round.to_le_bytes(),
+            &self.trainer,
+            &self.index.to_le_bytes(),
+            &self.count.to_le_bytes(),
+            &slices,
+        ])
+    }
+
+    pub fn trainer(&self) -> Pubkey {
+        Pubkey::new_from_array(self.trainer)
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        Signature::try_from(self.signature.as_slice())
+            .map_or(false, |signature| signature.verify(&self.trainer, self.signed_hash().as_ref()))
+    }
+}
+
+/// Splits `gradients` into signed shards of at most `shard_values` values.
+pub fn shard_gradients(keypair: &Keypair, round: u64, gradients: &[Gradient], shard_values: usize) -> Vec<GradientShard> {
+    let shard_values = shard_values.max(1);
+    let mut shards: Vec<Vec<GradientSlice>> = vec![Vec::new()];
+    let mut room = shard_values;
+    for gradient in gradients {
+        let mut offset = 0;
+        loop {
+            if room == 0 {
+                shards.push(Vec::new());
+                room = shard_values;
+            }
+            let take = room.min(gradient.values.len() - offset);
+            let values = gradient.values[offset..offset + take].iter().flat_map(|v| v.to_le_bytes()).collect();
+            shards.last_mut().expect("never empty").push(GradientSlice {
+                name: gradient.name.clone(),
+                shape: gradient.shape.clone(),
+                offset: offset as u64,
+                values,
+            });
+            offset += take;
+            room -= take;
+            if offset == gradient.values.len() {
+                break;
+            }
+        }
+    }
+
+    let count = shards.len() as u32;
+    let trainer = keypair.pubkey().to_bytes();
+    shards
+        .into_iter()
+        .enumerate()
+        .map(|(index, slices)| {
+            let mut shard = GradientShard { round, trainer, index: index as u32, count, slices, signature: Vec::new() };
+            shard.signature = keypair.sign_message(shard.signed_hash().as_ref()).as_ref().to_vec();
+            shard
+        })
+        .collect()
+}
+
+/// Rebuilds gradients laid out like `layout`, the local model's, from a
+/// full set of shards. `None` if a slice names a tensor `layout` lacks,
+/// disagrees with its shape or runs past it, or a tensor gets no slice.
+/// Tensors are sized from `layout`, never from what a peer claims.
+pub fn assemble(shards: &[GradientShard], layout: &[Gradient]) -> Option<Vec<Gradient>> {
+    let positions: HashMap<&str, usize> = layout.iter().enumerate().map(|(i, g)| (g.name.as_str(), i)).collect();
+    let mut gradients: Vec<Option<Gradient>> = vec![None; layout.len()];
+    for slice in shards.iter().flat_map(|shard| &shard.slices) {
+        let position = *positions.get(slice.name.as_str())?;
+        let expected = &layout[position];
+        let offset = usize::try_from(slice.offset).ok()?;
+        let len = slice.values.len() / 4;
+        if expected.shape != slice.shape
+            || slice.values.len() % 4 != 0
+            || offset.checked_add(len)? > expected.values.len()
+        {
+            return None;
+        }
+        let gradient = gradients[position].get_or_insert_with(|| Gradient {
+            name: expected.name.clone(),
+            shape: expected.shape.clone(),
+            values: vec![0.0; expected.values.len()],
+        });
+        for (value, bytes) in gradient.values[offset..offset + len].iter_mut().zip(slice.values.chunks_exact(4)) {
+            *value = f32::from_le_bytes(bytes.try_into().expect("chunks of four"));
+        }
+    }
+    gradients.into_iter().collect()
+}
+
+/// Mean of each gradient weighted by its contributor's stake. Contributions
+/// are summed in the order given, so every node passing the same list gets
+/// the same bits back. All contributions must share the first one's layout.
+pub fn stake_weighted_average(contributions: &[(u64, Vec<Gradient>)]) -> Option<Vec<Gradient>> {
+    let total: u64 = contributions.iter().map(|(stake, _)| stake).sum();
+    let (_, layout) = contributions.first()?;
+    if total == 0 {
+        return None;
+    }
+    let mut sums: Vec<Vec<f64>> = layout.iter().map(|g| vec![0.0; g.values.len()]).collect();
+    for (stake, gradients) in contributions {
+        let weight = *stake as f64 / total as f64;
+        for (sum, gradient) in sums.iter_mut().zip(gradients) {
+            for (acc, value) in sum.iter_mut().zip(&gradient.values) {
+                *acc += weight * *value as f64;
+            }
+        }
+    }
+    Some(
+        layout
+            .iter()
+            .zip(sums)
+            .map(|(g, sum)| Gradient {
+                name: g.name.clone(),
+                shape: g.shape.clone(),
+                values: sum.into_iter().map(|v| v as f32).collect(),
+            })
+            .collect(),
+    )
+}
+
+/// Collects gradient shards from the other trainers and averages each
+/// training round, weighted by stake. A trainer whose gradients miss the
+/// round's deadline is left out, and trainers may see different sets arrive
+/// in time, so their weights can drift apart.
+#[derive(Debug)]
+pub struct GradientExchange {
+    keypair: Arc<Keypair>,
+    timeout: Duration,
+    /// Peers whose shards are kept; anyone else's are dropped on arrival.
+    trainers: RwLock<HashSet<Pubkey>>,
+    shards: Mutex<HashMap<(u64, Pubkey), BTreeMap<u32, GradientShard>>>,
+    finished_round: AtomicU64,
+    arrived: Notify,
+}
+
+impl GradientExchange {
+    pub fn new(keypair: Arc<Keypair>) -> Self {
+        GradientExchange {
+            keypair,
+            timeout: DEFAULT_ROUND_TIMEOUT,
+            trainers: RwLock::new(HashSet::new()),
+            shards: Mutex::new(HashMap::new()),
+            finished_round: AtomicU64::new(0),
+            arrived: Notify::new(),
+        }
+    }
+
+    /// How long a round waits for participants before averaging without them.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn pubkey(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+
+    /// Replaces the peers whose shards are accepted.
+    pub fn set_trainers(&self, trainers: impl IntoIterator<Item = Pubkey>) {
+        *self.trainers.write() = trainers.into_iter().collect();
+    }
+
+    /// Keeps a shard whose signature has been checked. Returns `false` for
+    /// shards from anyone but a trainer, for rounds already finished or too
+    /// far ahead, and for shards past a trainer's share of a round.
+    pub fn receive(&self, shard: GradientShard) -> bool {
+        let finished = self.finished_round.load(Ordering::Relaxed);
+        if shard.round <= finished
+            || shard.round > finished + MAX_ROUNDS_AHEAD
+            || shard.index >= shard.count
+            || shard.count > MAX_SHARDS_PER_TRAINER
+            || shard.slices.iter().map(|s| s.values.len()).sum::<usize>() > SHARD_VALUES * 4
+            || !self.trainers.read().contains(&shard.trainer())
+        {
+            return false;
+        }
+        let mut shards = self.shards.lock();
+        let received = shards.entry((shard.round, shard.trainer())).or_default();
+        // Every shard of a round must agree with the first on the count.
+        if received.values().next().map_or(false, |first| first.count != shard.count) {
+            return false;
+        }
+        received.insert(shard.index, shard);
+        self.arrived.notify_waiters();
+        true
+    }
+
+    /// Whether all of the trainer's shards for `round` are in.
+    fn is_complete(&self, round: u64, trainer: &Pubkey) -> bool {
+        let shards = self.shards.lock();
+        shards
+            .get(&(round, *trainer))
+            .and_then(|received| Some(received.len() == received.values().next()?.count as usize))
+            .unwrap_or(false)
+    }
+
+    /// The trainer's gradients for `round`, laid out like `layout`.
+    fn gradients_from(&self, round: u64, trainer: &Pubkey, layout: &[Gradient]) -> Option<Vec<Gradient>> {
+        let received: Vec<GradientShard> = self.shards.lock().get(&(round, *trainer))?.values().cloned().collect();
+        assemble(&received, layout)
+    }
+
+    /// Sends `gradients` for `round` to the other trainers in `stakes` over
+    /// their sessions and waits for each to send theirs, or for the timeout.
+    /// Returns the stake-weighted average of everything that arrived,
+    /// including this node's own gradients if it has stake.
+    pub async fn all_reduce(
+        &self,
+        outboxes: &Outboxes,
+        round: u64,
+        gradients: Vec<Gradient>,
+        stakes: &HashMap<Pubkey, u64>,
+    ) -> Result<Vec<Gradient>, TrainingError> {
+        let local = self.pubkey();
+        let mut trainers: Vec<Pubkey> = stakes.iter().filter(|(_, stake)| **stake > 0).map(|(key, _)| *key).collect();
+        trainers.sort();
+        let deadline = Instant::now() + self.timeout;
+        // Session queues are bounded, so a slow peer holds up sending rather
+        // than piling shards up in memory.
+        for shard in shard_gradients(&self.keypair, round, &gradients, SHARD_VALUES) {
+            for trainer in trainers.iter().filter(|t| **t != local) {
+                let message = Message::GradientShard { shard: shard.clone() };
+                if !matches!(tokio::time::timeout_at(deadline, outboxes.send(trainer, message)).await, Ok(true)) {
+                    debug!("Could not send shard {} of round {} to {}", shard.index, round, trainer);
+                }
+            }
+        }
+
+        loop {
+            let arrived = self.arrived.notified();
+            let missing: Vec<&Pubkey> =
+                trainers.iter().filter(|t| **t != local && !self.is_complete(round, t)).collect();
+            if missing.is_empty() {
+                break;
+            }
+            if tokio::time::timeout_at(deadline, arrived).await.is_err() {
+                warn!("Averaging round {} without gradients from {:?}", round, missing);
+                break;
+            }
+        }
+
+        let mut contributions = Vec::new();
+        for trainer in &trainers {
+            if *trainer == local {
+                contributions.push((stakes[trainer], gradients.clone()));
+            } else if self.is_complete(round, trainer) {
+                match self.gradients_from(round, trainer, &gradients) {
+                    Some(received) => contributions.push((stakes[trainer], received)),
+                    None => warn!("Gradients from {} for round {} do not match the local model", trainer, round),
+                }
+            }
+        }
+        self.finish(round);
+        debug!("Averaged round {} over {} trainers", round, contributions.len());
+        stake_weighted_average(&contributions).ok_or(TrainingError::NoGradients(round))
+    }
+
+    /// Drops shards for `round` and everything before it.
+    fn finish(&self, round: u64) {
+        self.finished_round.fetch_max(round, Ordering::Relaxed);
+        self.shards.lock().retain(|(r, _), _| *r > round);
+    }
+}
+
+/// What a trainer needs from the network: shares its gradients and returns
+/// the round's average.
+#[async_trait::async_trait]
+pub trait GradientTransport: Send + Sync {
+    /// Names the other trainers; only their gradients are accepted.
+    fn register_trainers(&self, trainers: &[Pubkey]);
+
+    /// Averages `gradients` for `round` with those of `participants`,
+    /// weighted by stake.
+    async fn all_reduce(
+        &self,
+        round: u64,
+        gradients: Vec<Gradient>,
+        participants: &[Pubkey],
+    ) -> Result<Vec<Gradient>, TrainingError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(name: &str, values: Vec<f32>) -> Gradient {
+        Gradient { name: name.to_string(), shape: vec![values.len()], values }
+    }
+
+    #[test]
+    fn test_shards_reassemble_and_verify() {
+        let keypair = Keypair::new();
+        let gradients = vec![gradient("a", (0..7).map(|v| v as f32).collect()), gradient("b", vec![0.5; 4])];
+        let shards = shard_gradients(&keypair, 3, &gradients, 3);
+
+        assert_eq!(shards.len(), 4);
+        assert!(shards.iter().all(|s| s.verify_signature() && s.count == 4 && s.trainer() == keypair.pubkey()));
+        assert_eq!(assemble(&shards, &gradients).unwrap(), gradients);
+        assert!(assemble(&shards, &gradients[..1]).is_none());
+
+        let mut tampered = shards[0].clone();
+        tampered.slices[0].values[0] ^= 1;
+        assert!(!tampered.verify_signature());
+    }
+
+    #[test]
+    fn test_claimed_shape_checked_against_local_layout() {
+        let keypair = Keypair::new();
+        let layout = vec![gradient("w", vec![0.0; 4])];
+        let mut huge = shard_gradients(&keypair, 1, &[gradient("w", vec![1.0; 4])], SHARD_VALUES);
+        huge[0].slices[0].shape = vec![1 << 40];
+        assert!(assemble(&huge, &layout).is_none());
+
+        let mut overrun = shard_gradients(&keypair, 1, &[gradient("w", vec![1.0; 4])], SHARD_VALUES);
+        overrun[0].slices[0].offset = u64::MAX;
+        assert!(assemble(&overrun, &layout).is_none());
+    }
+
+    #[test]
+    fn test_shards_only_kept_from_trainers_within_their_share() {
+        let exchange = GradientExchange::new(Arc::new(Keypair::new()));
+        let (trainer, stranger) = (Keypair::new(), Keypair::new());
+        exchange.set_trainers([trainer.pubkey()]);
+        let grads = [gradient("w", vec![1.0; 4])];
+
+        assert!(!exchange.receive(shard_gradients(&stranger, 1, &grads, SHARD_VALUES).remove(0)));
+        let shards = shard_gradients(&trainer, 1, &grads, 2);
+        assert!(exchange.receive(shards[0].clone()));
+        // A shard claiming a different count for the same round is turned away.
+        assert!(!exchange.receive(shard_gradients(&trainer, 1, &grads, 1).remove(1)));
+        let mut oversized = shards[1].clone();
+        oversized.count = MAX_SHARDS_PER_TRAINER + 1;
+        assert!(!exchange.receive(oversized));
+        assert!(exchange.receive(shards[1].clone()));
+        assert!(exchange.is_complete(1, &trainer.pubkey()));
+    }
+
+    #[test]
+    fn test_average_weighted_by_stake() {
+        let contributions = vec![(300, vec![gradient("w", vec![1.0, 2.0])]), (100, vec![gradient("w", vec![5.0, -2.0])])];
+        let averaged = stake_weighted_average(&contributions).unwrap();
+        assert_eq!(averaged, vec![gradient("w", vec![2.0, 1.0])]);
+        assert!(stake_weighted_average(&[(0, vec![gradient("w", vec![1.0])])]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_all_reduce_waits_for_participants() {
+        let (local, remote) = (Arc::new(Keypair::new()), Keypair::new());
+        let exchange = GradientExchange::new(Arc::clone(&local)).with_timeout(Duration::from_secs(5));
+        exchange.set_trainers([remote.pubkey()]);
+        let outboxes = Outboxes::new();
+        let stakes = HashMap::from([(local.pubkey(), 100), (remote.pubkey(), 300)]);
+
+        let sent = shard_gradients(&remote, 1, &[gradient("w", vec![4.0])], SHARD_VALUES);
+        assert!(!exchange.receive(GradientShard { round: 9, ..sent[0].clone() }));
+        for shard in sent {
+            assert!(exchange.receive(shard));
+        }
+
+        let averaged = exchange.all_reduce(&outboxes, 1, vec![gradient("w", vec![0.0])], &stakes).await.unwrap();
+        assert_eq!(averaged, vec![gradient("w", vec![3.0])]);
+        // The round is done; late shards are turned away.
+        let late = shard_gradients(&remote, 1, &[gradient("w", vec![4.0])], SHARD_VALUES);
+        assert!(!exchange.receive(late[0].clone()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_missing_participant_times_out() {
+        let local = Arc::new(Keypair::new());
+        let exchange = GradientExchange::new(Arc::clone(&local)).with_timeout(Duration::from_secs(1));
+        let outboxes = Outboxes::new();
+        let stakes = HashMap::from([(local.pubkey(), 100), (Pubkey::new_unique(), 100)]);
+
+        let averaged = exchange.all_reduce(&outboxes, 1, vec![gradient("w", vec![2.0])], &stakes).await.unwrap();
+        assert_eq!(averaged, vec![gradient("w", vec![2.0])]);
+    }
+}
@@ -0,0 +1,80 @@
+use async_trait::async_trait;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::net::TcpStream;
+
+const MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Connection for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+}
+
+#[async_trait]
+pub trait Transport: Send + Sync {
+    type Conn: Connection;
+
+    async fn dial(&self, addr: &str) -> io::Result<Self::Conn>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+#[async_trait]
+impl Transport for TcpTransport {
+    type Conn = TcpStream;
+
+    async fn dial(&self, addr: &str) -> io::Result<TcpStream> {
+        TcpStream::connect(addr).await
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryConnection {
+    stream: DuplexStream,
+    peer: SocketAddr,
+}
+
+impl MemoryConnection {
+    pub fn pair(a: SocketAddr, b: SocketAddr) -> (MemoryConnection, MemoryConnection) {
+        let (a_stream, b_stream) = tokio::io::duplex(MEMORY_BUFFER_SIZE);
+        (
+            MemoryConnection { stream: a_stream, peer: b },
+            MemoryConnection { stream: b_stream, peer: a },
+        )
+    }
+}
+
+impl Connection for MemoryConnection {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.peer)
+    }
+}
+
+impl AsyncRead for MemoryConnection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MemoryConnection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,158 @@
+use borsh::BorshDeserialize;
+use log::debug;
+use parking_lot::Mutex;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+
+use crate::node::consensus::Validator;
+use crate::node::stake_check::{AccountFetcher, FetchedAccount, StakeCheckError};
+use crate::program::stake::{
+    find_stake_address, find_validator_delegations_address, StakeAccount, ValidatorDelegations,
+};
+
+/// Builds the validator set from every account the stake program owns and
+/// rebuilds it once per epoch of `epoch_slots` slots.
+#[derive(Debug)]
+pub struct ValidatorRegistry {
+    program_id: Pubkey,
+    min_stake: u64,
+    epoch_slots: u64,
+    refreshed_epoch: Mutex<Option<u64>>,
+}
+
+impl ValidatorRegistry {
+    pub fn new(program_id: Pubkey, min_stake: u64, epoch_slots: u64) -> Self {
+        ValidatorRegistry {
+            program_id,
+            min_stake,
+            epoch_slots: epoch_slots.max(1),
+            refreshed_epoch: Mutex::new(None),
+        }
+    }
+
+    pub fn epoch(&self, slot: u64) -> u64 {
+        slot / self.epoch_slots
+    }
+
+    /// Whether the set has not yet been rebuilt for `slot`'s epoch.
+    pub fn is_due(&self, slot: u64) -> bool {
+        *self.refreshed_epoch.lock() != Some(self.epoch(slot))
+    }
+
+    /// Fetches the stake program's accounts and returns the validator set
+    /// they describe, marking `slot`'s epoch as refreshed on success.
+    pub async fn refresh(&self, fetcher: &dyn AccountFetcher, slot: u64) -> Result<Vec<Validator>, StakeCheckError> {
+        let accounts = fetcher
+            .fetch_program_accounts(&self.program_id)
+            .await
+            .map_err(StakeCheckError::Rpc)?;
+        let validators = self.validators_from(accounts);
+        *self.refreshed_epoch.lock() = Some(self.epoch(slot));
+        Ok(validators)
+    }
+
+    /// Keeps the active stake accounts holding at least `min_stake` and
+    /// living at their staker's derived address, ordered by pubkey. Accounts
+    /// left behind by an ownership transfer no longer match that address and
+    /// are dropped. Each validator's weight is its own stake plus what holders
+    /// have delegated to it; delegations don't count toward `min_stake`.
+    pub fn validators_from(&self, accounts: Vec<(Pubkey, FetchedAccount)>) -> Vec<Validator> {
+        let accounts: Vec<(Pubkey, FetchedAccount)> = accounts
+            .into_iter()
+            .filter(|(_, account)| account.owner == self.program_id)
+            .collect();
+        let delegated: HashMap<Pubkey, u64> = accounts
+            .iter()
+            .filter_map(|(address, account)| {
+                let totals = ValidatorDelegations::try_from_slice(&account.data).ok()?;
+                let (expected, _) = find_validator_delegations_address(&self.program_id, &totals.validator);
+                (expected == *address).then_some((totals.validator, totals.total))
+            })
+            .collect();
+
+        let mut validators: Vec<Validator> = accounts
+            .into_iter()
+            .filter_map(|(address, account)| {
+                match StakeAccount::unpack(&account.data) {
+                    Ok(stake) => Some((address, stake)),
+                    Err(e) => {
+                        debug!("Skipping undecodable stake program account {}: {}", address, e);
+                        None
+                    }
+                }
+            })
+            .filter(|(address, stake)| find_stake_address(&self.program_id, &stake.owner).0 == *address)
+            .filter(|(_, stake)| stake.is_active && stake.amount >= self.min_stake)
+            .map(|(_, stake)| Validator {
+                pubkey: stake.owner,
+                stake: stake.amount.saturating_add(delegated.get(&stake.owner).copied().unwrap_or(0)),
+                locked_until: stake.locked_until,
+            })
+            .collect();
+        validators.sort_by_key(|v| v.pubkey);
+        validators
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::stake_check::testing::StakeLedger;
+    use borsh::BorshSerialize;
+
+    #[test]
+    fn test_only_active_sufficient_stakes_become_validators() {
+        let program_id = Pubkey::new_unique();
+        let registry = ValidatorRegistry::new(program_id, 100, 10);
+        let whale = Pubkey::new_unique();
+        let minnow = Pubkey::new_unique();
+
+        let foreign = StakeAccount::new(Pubkey::new_unique(), 5_000, 0, 0).try_to_vec().unwrap();
+        let ledger = StakeLedger::new(program_id)
+            .with_stake(whale, 9_000)
+            .with_stake(minnow, 100)
+            .with_stake(Pubkey::new_unique(), 99)
+            .with_account(StakeAccount { is_active: false, ..StakeAccount::new(Pubkey::new_unique(), 5_000, 0, 0) })
+            .with_fetched(Pubkey::new_unique(), FetchedAccount { owner: Pubkey::new_unique(), data: foreign })
+            .with_account_at(Pubkey::new_unique(), StakeAccount::new(Pubkey::new_unique(), 500, 0, 0))
+            .with_data(Pubkey::new_unique(), vec![1, 2, 3]);
+
+        let validators = registry.validators_from(ledger.program_accounts());
+        let mut expected = vec![(whale, 9_000), (minnow, 100)];
+        expected.sort();
+        let staked: Vec<(Pubkey, u64)> = validators.iter().map(|v| (v.pubkey, v.stake)).collect();
+        assert_eq!(staked, expected);
+    }
+
+    #[test]
+    fn test_delegations_add_to_validator_weight() {
+        let program_id = Pubkey::new_unique();
+        let registry = ValidatorRegistry::new(program_id, 100, 10);
+        let backed = Pubkey::new_unique();
+        let nodeless = Pubkey::new_unique();
+
+        let misplaced = ValidatorDelegations { validator: backed, total: 1_000_000 }.try_to_vec().unwrap();
+        let ledger = StakeLedger::new(program_id)
+            .with_stake(backed, 100)
+            .with_delegations(backed, 900)
+            .with_delegations(nodeless, 5_000)
+            .with_stake(nodeless, 50)
+            .with_data(Pubkey::new_unique(), misplaced);
+
+        let validators = registry.validators_from(ledger.program_accounts());
+        assert_eq!(validators.len(), 1);
+        assert_eq!((validators[0].pubkey, validators[0].stake), (backed, 1_000));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_runs_once_per_epoch() {
+        let program_id = Pubkey::new_unique();
+        let registry = ValidatorRegistry::new(program_id, 1, 10);
+        let source = StakeLedger::new(program_id).with_stake(Pubkey::new_unique(), 10);
+
+        assert!(registry.is_due(0));
+        assert_eq!(registry.refresh(&source, 3).await.unwrap().len(), 1);
+        assert!(!registry.is_due(9));
+        assert!(registry.is_due(10));
+    }
+}
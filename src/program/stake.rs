@@ -1,5 +1,7 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::Clock,
     entrypoint,
     entrypoint::ProgramResult,
     msg,
@@ -12,17 +14,182 @@ use solana_program::{
 
 use borsh::{BorshDeserialize, BorshSerialize};
 
+pub const STAKE_SEED: &[u8] = b"stake";
+pub const SLASH_CONFIG_SEED: &[u8] = b"slash_config";
+pub const DELEGATION_SEED: &[u8] = b"delegation";
+pub const VALIDATOR_DELEGATIONS_SEED: &[u8] = b"validator_delegations";
+pub const REWARD_CONFIG_SEED: &[u8] = b"reward_config";
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+/// Reward rates are in basis points of the staked amount per epoch.
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Serialized size of a `StakeAccount` in the current layout.
+pub const STAKE_ACCOUNT_LEN: usize = 90;
+/// Sizes of the layouts stake accounts were created with before, oldest
+/// first: before slashes were recorded, before rewards accrued, and before
+/// distributed rewards were tracked apart from accrued ones. Fields have
+/// only ever been appended, and each starts out zeroed.
+pub const LEGACY_STAKE_ACCOUNT_LENS: [usize; 3] = [49, 58, 74];
+/// Serialized size of a `RewardConfig` in the current layout.
+pub const REWARD_CONFIG_LEN: usize = 48;
+/// Size of the reward config before it tracked outstanding distributions.
+pub const LEGACY_REWARD_CONFIG_LEN: usize = 40;
+
+pub fn find_stake_address(program_id: &Pubkey, staker: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STAKE_SEED, staker.as_ref()], program_id)
+}
+
+pub fn find_slash_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SLASH_CONFIG_SEED], program_id)
+}
+
+pub fn find_reward_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REWARD_CONFIG_SEED], program_id)
+}
+
+pub fn find_delegation_address(program_id: &Pubkey, delegator: &Pubkey, validator: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[DELEGATION_SEED, delegator.as_ref(), validator.as_ref()], program_id)
+}
+
+pub fn find_validator_delegations_address(program_id: &Pubkey, validator: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VALIDATOR_DELEGATIONS_SEED, validator.as_ref()], program_id)
+}
+
+/// Why stake was slashed. `NotSlashed` keeps the account a fixed size for
+/// stakes that never were.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlashReason {
+    NotSlashed,
+    DoubleSign,
+    InvalidBlock,
+    Downtime,
+    /// Returned inference output contradicted by a re-execution of the
+    /// same signed job.
+    InvalidInference,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
 pub struct StakeAccount {
     pub owner: Pubkey,           
     pub amount: u64,             
     pub locked_until: i64,       
     pub is_active: bool,        
+    /// Total lamports slashed over the account's lifetime.
+    pub slashed: u64,
+    pub last_slash_reason: SlashReason,
+    /// Rewards earned but not yet claimed.
+    pub accrued_rewards: u64,
+    /// Epoch `accrued_rewards` is up to date with.
+    pub last_accrual_epoch: u64,
+    /// Rewards the reward authority distributed but not yet claimed.
+    pub distributed_rewards: u64,
+    /// Node epochs below this one have had their rewards distributed here.
+    pub next_reward_epoch: u64,
+}
+
+impl StakeAccount {
+    /// An active stake with nothing slashed or accrued yet, accruing from
+    /// `epoch`.
+    pub fn new(owner: Pubkey, amount: u64, locked_until: i64, epoch: u64) -> Self {
+        StakeAccount {
+            owner,
+            amount,
+            locked_until,
+            is_active: true,
+            slashed: 0,
+            last_slash_reason: SlashReason::NotSlashed,
+            accrued_rewards: 0,
+            last_accrual_epoch: epoch,
+            distributed_rewards: 0,
+            next_reward_epoch: 0,
+        }
+    }
+
+    /// Decodes an account in the current layout or a legacy one, reading the
+    /// fields a legacy account lacks as zero. The program only writes the
+    /// current layout, so legacy accounts go through `MigrateStake` first.
+    pub fn unpack(data: &[u8]) -> std::io::Result<Self> {
+        if LEGACY_STAKE_ACCOUNT_LENS.contains(&data.len()) {
+            let mut padded = data.to_vec();
+            padded.resize(STAKE_ACCOUNT_LEN, 0);
+            return Self::try_from_slice(&padded);
+        }
+        Self::try_from_slice(data)
+    }
+}
+
+/// The reward rate and who may change it. The account's lamports above
+/// its rent reserve are the pool rewards are paid from; anyone may top it
+/// up with a plain transfer.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RewardConfig {
+    pub authority: Pubkey,
+    pub rate_bps: u64,
+    /// Distributed rewards not yet claimed, across every stake account.
+    /// The pool holds this much back for them.
+    pub outstanding: u64,
+}
+
+impl RewardConfig {
+    /// Decodes a config in the current layout or the legacy one, reading
+    /// `outstanding` as zero for the latter.
+    pub fn unpack(data: &[u8]) -> std::io::Result<Self> {
+        if data.len() == LEGACY_REWARD_CONFIG_LEN {
+            let mut padded = data.to_vec();
+            padded.resize(REWARD_CONFIG_LEN, 0);
+            return Self::try_from_slice(&padded);
+        }
+        Self::try_from_slice(data)
+    }
+}
+
+/// Adds the rewards `stake` earned between its last accrual and `epoch` at
+/// `rate_bps` per epoch. Inactive stake earns nothing but still moves its
+/// accrual epoch forward.
+pub fn accrue_rewards(stake: &mut StakeAccount, rate_bps: u64, epoch: u64) -> Result<(), ProgramError> {
+    let epochs = epoch.saturating_sub(stake.last_accrual_epoch);
+    if stake.is_active && epochs > 0 {
+        let reward = (stake.amount as u128)
+            .checked_mul(rate_bps as u128)
+            .and_then(|reward| reward.checked_mul(epochs as u128))
+            .map(|reward| reward / BPS_DENOMINATOR as u128)
+            .and_then(|reward| u64::try_from(reward).ok())
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        stake.accrued_rewards = stake
+            .accrued_rewards
+            .checked_add(reward)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+    stake.last_accrual_epoch = stake.last_accrual_epoch.max(epoch);
+    Ok(())
+}
+
+/// Lamports one holder has delegated to one validator. The account holds
+/// the delegated lamports themselves.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Delegation {
+    pub delegator: Pubkey,
+    pub validator: Pubkey,
+    pub amount: u64,
+}
+
+/// Running total of the lamports delegated to `validator`, counted toward
+/// its stake weight in consensus.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ValidatorDelegations {
+    pub validator: Pubkey,
+    pub total: u64,
+}
+
+/// Holds the key allowed to slash. It may be a plain keypair or a
+/// multisig PDA, so a quorum of validators can share the role.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SlashConfig {
+    pub authority: Pubkey,
 }
 
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
 pub enum StakeInstruction {
     
     CreateStake {
@@ -33,6 +200,63 @@ pub enum StakeInstruction {
     Withdraw {
         amount: u64,
     },
+
+    TransferOwnership {
+        new_owner: Pubkey,
+    },
+
+    /// Creates the slash config naming `authority`, signed by the program's
+    /// upgrade authority, or hands the role on when the current authority
+    /// co-signs.
+    SetSlashAuthority {
+        authority: Pubkey,
+    },
+
+    /// Moves `amount` of a validator's stake to the destination account:
+    /// the incinerator to burn it, or any other account to redirect it.
+    Slash {
+        amount: u64,
+        reason: SlashReason,
+    },
+
+    /// Backs `validator` with `amount` lamports from a holder who doesn't
+    /// run a node.
+    Delegate {
+        validator: Pubkey,
+        amount: u64,
+    },
+
+    /// Returns a delegation in full and closes it.
+    Undelegate,
+
+    /// Creates the reward config with the signer, who must be the program's
+    /// upgrade authority, as its authority, or lets the current authority
+    /// change the rate.
+    SetRewardRate {
+        rate_bps: u64,
+    },
+
+    /// Pays the staker the rewards accrued on their stake account.
+    ClaimRewards,
+
+    /// Locks the stake for `lock_period` more seconds, counted from the
+    /// current lock's expiry or from now if it has already expired.
+    ExtendLock {
+        lock_period: i64,
+    },
+
+    /// Credits a validator's stake account with `amount` of rewards earned
+    /// in the node's `epoch`, claimed later like accrued rewards. Only the
+    /// reward authority may, once per epoch and account, and only while the
+    /// pool covers every distributed reward not yet claimed.
+    DistributeRewards {
+        epoch: u64,
+        amount: u64,
+    },
+
+    /// Rewrites a stake account created in a legacy layout in the current
+    /// one, topping up its rent from the staker.
+    MigrateStake,
 }
 
 
@@ -52,6 +276,36 @@ pub fn process_instruction(
         StakeInstruction::Withdraw { amount } => {
             process_withdraw(program_id, accounts, amount)
         }
+        StakeInstruction::TransferOwnership { new_owner } => {
+            process_transfer_ownership(program_id, accounts, new_owner)
+        }
+        StakeInstruction::SetSlashAuthority { authority } => {
+            process_set_slash_authority(program_id, accounts, authority)
+        }
+        StakeInstruction::Slash { amount, reason } => {
+            process_slash(program_id, accounts, amount, reason)
+        }
+        StakeInstruction::Delegate { validator, amount } => {
+            process_delegate(program_id, accounts, validator, amount)
+        }
+        StakeInstruction::Undelegate => {
+            process_undelegate(program_id, accounts)
+        }
+        StakeInstruction::SetRewardRate { rate_bps } => {
+            process_set_reward_rate(program_id, accounts, rate_bps)
+        }
+        StakeInstruction::ClaimRewards => {
+            process_claim_rewards(program_id, accounts)
+        }
+        StakeInstruction::ExtendLock { lock_period } => {
+            process_extend_lock(program_id, accounts, lock_period)
+        }
+        StakeInstruction::DistributeRewards { epoch, amount } => {
+            process_distribute_rewards(program_id, accounts, epoch, amount)
+        }
+        StakeInstruction::MigrateStake => {
+            process_migrate_stake(program_id, accounts)
+        }
     }
 }
 
@@ -69,26 +323,38 @@ fn process_create_stake(
     let stake_account = next_account_info(account_info_iter)?;     
     let system_program = next_account_info(account_info_iter)?;   
     
-    
-    if amount < 10_000_000_000 {
+    if !staker_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // One stake account per staker, at an address only this program can
+    // sign for. Every program-owned stake account was created here, so
+    // the other instructions can trust the ones they are handed.
+    let (stake_address, bump) = find_stake_address(program_id, staker_account.key);
+    if *stake_account.key != stake_address {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    if amount < 10_000_000_000 || lock_period < 0 {
         return Err(ProgramError::InvalidArgument);
     }
 
+    let clock = Clock::get()?;
+    let locked_until = clock
+        .unix_timestamp
+        .checked_add(lock_period)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
     
     let rent = Rent::get()?;
-    let stake_account_data = StakeAccount {
-        owner: *staker_account.key,
-        amount,
-        locked_until: lock_period,
-        is_active: true,
-    };
+    let stake_account_data = StakeAccount::new(*staker_account.key, amount, locked_until, clock.epoch);
 
    
     let space = stake_account_data.try_to_vec()?.len();
     let rent_lamports = rent.minimum_balance(space);
 
     
-    invoke(
+    invoke_signed(
         &system_instruction::create_account(
             staker_account.key,
             stake_account.key,
@@ -101,6 +367,7 @@ fn process_create_stake(
             stake_account.clone(),
             system_program.clone(),
         ],
+        &[&[STAKE_SEED, staker_account.key.as_ref(), &[bump]]],
     )?;
 
     
@@ -121,6 +388,9 @@ fn process_withdraw(
     let staker_account = next_account_info(account_info_iter)?;
     let stake_account = next_account_info(account_info_iter)?;
     
+    if !staker_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
     
     if stake_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
@@ -134,8 +404,10 @@ fn process_withdraw(
         return Err(ProgramError::InvalidAccountData);
     }
 
+    accrue_if_configured(program_id, accounts, &mut stake_data)?;
+
    
-    if stake_data.locked_until > 0 {
+    if Clock::get()?.unix_timestamp < stake_data.locked_until {
         return Err(ProgramError::InvalidArgument);
     }
 
@@ -155,3 +427,1454 @@ fn process_withdraw(
     msg!("Withdrew {} lamports from stake account", amount);
     Ok(())
 }
+
+fn process_transfer_ownership(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_owner: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let owner_account = next_account_info(account_info_iter)?;
+    let stake_account = next_account_info(account_info_iter)?;
+
+    if !owner_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if stake_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut stake_data = StakeAccount::try_from_slice(&stake_account.data.borrow())?;
+
+    if stake_data.owner != *owner_account.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    accrue_if_configured(program_id, accounts, &mut stake_data)?;
+
+    // Passing the new owner's account opts in to a co-signature check, which
+    // guards against handing the stake to a key nobody controls.
+    if let Some(new_owner_account) = account_info_iter.next() {
+        if *new_owner_account.key == new_owner && !new_owner_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+    }
+
+    stake_data.owner = new_owner;
+    stake_data.serialize(&mut &mut stake_account.data.borrow_mut()[..])?;
+
+    msg!("Stake account ownership transferred to {}", new_owner);
+    Ok(())
+}
+
+fn process_set_slash_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    authority: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let payer_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    if !payer_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (config_address, bump) = find_slash_config_address(program_id);
+    if *config_account.key != config_address {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    // Once created, only the current authority can name a successor.
+    if config_account.owner == program_id {
+        let current_authority = next_account_info(account_info_iter)?;
+        let mut config = SlashConfig::try_from_slice(&config_account.data.borrow())?;
+        if !current_authority.is_signer || *current_authority.key != config.authority {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        config.authority = authority;
+        config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+        msg!("Slash authority handed to {}", authority);
+        return Ok(());
+    }
+
+    // Whoever may deploy the program names the first authority, rather
+    // than whoever gets here first.
+    let program_data = next_account_info(account_info_iter)?;
+    check_upgrade_authority(program_id, program_data, payer_account)?;
+
+    let config = SlashConfig { authority };
+    let space = config.try_to_vec()?.len();
+    let rent_lamports = Rent::get()?.minimum_balance(space);
+
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_account.key,
+            config_account.key,
+            rent_lamports,
+            space as u64,
+            program_id,
+        ),
+        &[
+            payer_account.clone(),
+            config_account.clone(),
+            system_program.clone(),
+        ],
+        &[&[SLASH_CONFIG_SEED, &[bump]]],
+    )?;
+
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+    msg!("Slash authority set to {}", authority);
+    Ok(())
+}
+
+fn process_slash(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    reason: SlashReason,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let authority_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let stake_account = next_account_info(account_info_iter)?;
+    let destination_account = next_account_info(account_info_iter)?;
+
+    if !authority_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if config_account.owner != program_id || stake_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    if *config_account.key != find_slash_config_address(program_id).0 {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let config = SlashConfig::try_from_slice(&config_account.data.borrow())?;
+    if config.authority != *authority_account.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if reason == SlashReason::NotSlashed {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut stake_data = StakeAccount::try_from_slice(&stake_account.data.borrow())?;
+
+    accrue_if_configured(program_id, accounts, &mut stake_data)?;
+
+    if amount > stake_data.amount {
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    **stake_account.try_borrow_mut_lamports()? -= amount;
+    **destination_account.try_borrow_mut_lamports()? += amount;
+
+    stake_data.amount -= amount;
+    stake_data.slashed = stake_data.slashed.saturating_add(amount);
+    stake_data.last_slash_reason = reason;
+    stake_data.serialize(&mut &mut stake_account.data.borrow_mut()[..])?;
+
+    msg!("Slashed {} lamports from {} for {:?}", amount, stake_data.owner, reason);
+    Ok(())
+}
+
+fn process_delegate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    validator: Pubkey,
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let delegator_account = next_account_info(account_info_iter)?;
+    let delegation_account = next_account_info(account_info_iter)?;
+    let totals_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    if !delegator_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if amount == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let (delegation_address, delegation_bump) =
+        find_delegation_address(program_id, delegator_account.key, &validator);
+    let (totals_address, totals_bump) = find_validator_delegations_address(program_id, &validator);
+    if *delegation_account.key != delegation_address || *totals_account.key != totals_address {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let rent = Rent::get()?;
+
+    let mut delegation = if delegation_account.owner == program_id {
+        invoke(
+            &system_instruction::transfer(delegator_account.key, delegation_account.key, amount),
+            &[
+                delegator_account.clone(),
+                delegation_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+        Delegation::try_from_slice(&delegation_account.data.borrow())?
+    } else {
+        let delegation = Delegation {
+            delegator: *delegator_account.key,
+            validator,
+            amount: 0,
+        };
+        let space = delegation.try_to_vec()?.len();
+        invoke_signed(
+            &system_instruction::create_account(
+                delegator_account.key,
+                delegation_account.key,
+                amount + rent.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                delegator_account.clone(),
+                delegation_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[DELEGATION_SEED, delegator_account.key.as_ref(), validator.as_ref(), &[delegation_bump]]],
+        )?;
+        delegation
+    };
+
+    let mut totals = if totals_account.owner == program_id {
+        ValidatorDelegations::try_from_slice(&totals_account.data.borrow())?
+    } else {
+        let totals = ValidatorDelegations { validator, total: 0 };
+        let space = totals.try_to_vec()?.len();
+        invoke_signed(
+            &system_instruction::create_account(
+                delegator_account.key,
+                totals_account.key,
+                rent.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                delegator_account.clone(),
+                totals_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[VALIDATOR_DELEGATIONS_SEED, validator.as_ref(), &[totals_bump]]],
+        )?;
+        totals
+    };
+
+    delegation.amount = delegation.amount.checked_add(amount).ok_or(ProgramError::ArithmeticOverflow)?;
+    totals.total = totals.total.checked_add(amount).ok_or(ProgramError::ArithmeticOverflow)?;
+    delegation.serialize(&mut &mut delegation_account.data.borrow_mut()[..])?;
+    totals.serialize(&mut &mut totals_account.data.borrow_mut()[..])?;
+
+    msg!("Delegated {} lamports to {}", amount, validator);
+    Ok(())
+}
+
+fn process_undelegate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let delegator_account = next_account_info(account_info_iter)?;
+    let delegation_account = next_account_info(account_info_iter)?;
+    let totals_account = next_account_info(account_info_iter)?;
+
+    if !delegator_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if delegation_account.owner != program_id || totals_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let delegation = Delegation::try_from_slice(&delegation_account.data.borrow())?;
+
+    if delegation.delegator != *delegator_account.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let (delegation_address, _) = find_delegation_address(program_id, delegator_account.key, &delegation.validator);
+    let (totals_address, _) = find_validator_delegations_address(program_id, &delegation.validator);
+    if *delegation_account.key != delegation_address || *totals_account.key != totals_address {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut totals = ValidatorDelegations::try_from_slice(&totals_account.data.borrow())?;
+    totals.total = totals.total.saturating_sub(delegation.amount);
+    totals.serialize(&mut &mut totals_account.data.borrow_mut()[..])?;
+
+    // Closing the delegation returns its rent along with the stake.
+    let lamports = delegation_account.lamports();
+    **delegation_account.try_borrow_mut_lamports()? = 0;
+    **delegator_account.try_borrow_mut_lamports()? += lamports;
+    delegation_account.data.borrow_mut().fill(0);
+
+    msg!("Undelegated {} lamports from {}", delegation.amount, delegation.validator);
+    Ok(())
+}
+
+fn process_set_reward_rate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    rate_bps: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let authority_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    if !authority_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if rate_bps > BPS_DENOMINATOR {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let (config_address, bump) = find_reward_config_address(program_id);
+    if *config_account.key != config_address {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    if config_account.owner == program_id {
+        let mut config = RewardConfig::unpack(&config_account.data.borrow())?;
+        if config.authority != *authority_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        config.rate_bps = rate_bps;
+        store_reward_config(config_account, &config)?;
+        msg!("Reward rate set to {} bps per epoch", rate_bps);
+        return Ok(());
+    }
+
+    // As with the slash config, only whoever may deploy the program takes
+    // the role.
+    let program_data = next_account_info(account_info_iter)?;
+    check_upgrade_authority(program_id, program_data, authority_account)?;
+
+    let config = RewardConfig {
+        authority: *authority_account.key,
+        rate_bps,
+        outstanding: 0,
+    };
+    let space = config.try_to_vec()?.len();
+    let rent_lamports = Rent::get()?.minimum_balance(space);
+
+    invoke_signed(
+        &system_instruction::create_account(
+            authority_account.key,
+            config_account.key,
+            rent_lamports,
+            space as u64,
+            program_id,
+        ),
+        &[
+            authority_account.clone(),
+            config_account.clone(),
+            system_program.clone(),
+        ],
+        &[&[REWARD_CONFIG_SEED, &[bump]]],
+    )?;
+
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+    msg!("Reward config created at {} bps per epoch", rate_bps);
+    Ok(())
+}
+
+fn process_claim_rewards(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let staker_account = next_account_info(account_info_iter)?;
+    let stake_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+
+    if !staker_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if stake_account.owner != program_id || config_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    if *config_account.key != find_reward_config_address(program_id).0 {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut stake_data = StakeAccount::try_from_slice(&stake_account.data.borrow())?;
+
+    if stake_data.owner != *staker_account.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut config = RewardConfig::unpack(&config_account.data.borrow())?;
+    accrue_rewards(&mut stake_data, config.rate_bps, Clock::get()?.epoch)?;
+
+    let reward = stake_data
+        .accrued_rewards
+        .checked_add(stake_data.distributed_rewards)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    // What other stakers were distributed stays in the pool for them.
+    config.outstanding = config.outstanding.saturating_sub(stake_data.distributed_rewards);
+    store_reward_config(config_account, &config)?;
+    let reserve = Rent::get()?.minimum_balance(config_account.data_len());
+    let available = config_account
+        .lamports()
+        .saturating_sub(reserve)
+        .saturating_sub(config.outstanding);
+    if reward > available {
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    **config_account.try_borrow_mut_lamports()? -= reward;
+    **staker_account.try_borrow_mut_lamports()? += reward;
+
+    stake_data.accrued_rewards = 0;
+    stake_data.distributed_rewards = 0;
+    stake_data.serialize(&mut &mut stake_account.data.borrow_mut()[..])?;
+
+    msg!("Paid {} lamports of rewards to {}", reward, staker_account.key);
+    Ok(())
+}
+
+fn process_extend_lock(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    lock_period: i64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let staker_account = next_account_info(account_info_iter)?;
+    let stake_account = next_account_info(account_info_iter)?;
+
+    if !staker_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if stake_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    if lock_period <= 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut stake_data = StakeAccount::try_from_slice(&stake_account.data.borrow())?;
+
+    if stake_data.owner != *staker_account.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    accrue_if_configured(program_id, accounts, &mut stake_data)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    stake_data.locked_until = stake_data
+        .locked_until
+        .max(now)
+        .checked_add(lock_period)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    stake_data.serialize(&mut &mut stake_account.data.borrow_mut()[..])?;
+
+    msg!("Stake locked until {}", stake_data.locked_until);
+    Ok(())
+}
+
+fn process_distribute_rewards(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    epoch: u64,
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let authority_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let stake_account = next_account_info(account_info_iter)?;
+
+    if !authority_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if config_account.owner != program_id || stake_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    if *config_account.key != find_reward_config_address(program_id).0 {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut config = RewardConfig::unpack(&config_account.data.borrow())?;
+    if config.authority != *authority_account.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut stake_data = StakeAccount::try_from_slice(&stake_account.data.borrow())?;
+    if !stake_data.is_active {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Each epoch is paid once per account, however often it is submitted.
+    if epoch < stake_data.next_reward_epoch {
+        msg!("Epoch {} rewards were already distributed to {}", epoch, stake_data.owner);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    accrue_rewards(&mut stake_data, config.rate_bps, Clock::get()?.epoch)?;
+
+    // Never promise more than the pool holds beyond what it already owes.
+    config.outstanding = config
+        .outstanding
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    store_reward_config(config_account, &config)?;
+    let reserve = Rent::get()?.minimum_balance(config_account.data_len());
+    if config.outstanding > config_account.lamports().saturating_sub(reserve) {
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    stake_data.distributed_rewards = stake_data
+        .distributed_rewards
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    stake_data.next_reward_epoch = epoch.checked_add(1).ok_or(ProgramError::ArithmeticOverflow)?;
+    stake_data.serialize(&mut &mut stake_account.data.borrow_mut()[..])?;
+
+    msg!("Credited {} lamports of epoch {} rewards to {}", amount, epoch, stake_data.owner);
+    Ok(())
+}
+
+fn process_migrate_stake(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let staker_account = next_account_info(account_info_iter)?;
+    let stake_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    if !staker_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if stake_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let legacy_len = stake_account.data_len();
+    if !LEGACY_STAKE_ACCOUNT_LENS.contains(&legacy_len) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut stake_data = StakeAccount::unpack(&stake_account.data.borrow())?;
+    if stake_data.owner != *staker_account.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // The two oldest layouts predate accrual; their rewards start from now
+    // rather than from epoch 0.
+    if legacy_len < LEGACY_STAKE_ACCOUNT_LENS[2] {
+        stake_data.last_accrual_epoch = Clock::get()?.epoch;
+    }
+
+    let rent = Rent::get()?;
+    let top_up = rent
+        .minimum_balance(STAKE_ACCOUNT_LEN)
+        .saturating_sub(rent.minimum_balance(legacy_len));
+    if top_up > 0 {
+        invoke(
+            &system_instruction::transfer(staker_account.key, stake_account.key, top_up),
+            &[
+                staker_account.clone(),
+                stake_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    }
+
+    stake_account.realloc(STAKE_ACCOUNT_LEN, true)?;
+    stake_data.serialize(&mut &mut stake_account.data.borrow_mut()[..])?;
+
+    msg!(
+        "Migrated stake account of {} from a {} byte layout",
+        stake_data.owner,
+        legacy_len
+    );
+    Ok(())
+}
+
+/// Checks that `signer` signed and is the upgrade authority recorded in the
+/// program's `ProgramData` account, which only the upgradeable loader can
+/// write.
+fn check_upgrade_authority(
+    program_id: &Pubkey,
+    program_data: &AccountInfo,
+    signer: &AccountInfo,
+) -> ProgramResult {
+    if *program_data.key != bpf_loader_upgradeable::get_program_data_address(program_id) {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    if *program_data.owner != bpf_loader_upgradeable::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let upgrade_authority = match bincode::deserialize(&program_data.data.borrow()) {
+        Ok(UpgradeableLoaderState::ProgramData {
+            upgrade_authority_address,
+            ..
+        }) => upgrade_authority_address,
+        _ => return Err(ProgramError::InvalidAccountData),
+    };
+    if !signer.is_signer || upgrade_authority != Some(*signer.key) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+/// Brings `stake`'s rewards up to date when the reward config is among the
+/// instruction's accounts. Without it the rate is unknown, so accrual waits
+/// for an interaction that passes it.
+fn accrue_if_configured(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    stake: &mut StakeAccount,
+) -> ProgramResult {
+    let (config_address, _) = find_reward_config_address(program_id);
+    let Some(config_account) = accounts.iter().find(|account| *account.key == config_address) else {
+        return Ok(());
+    };
+    if config_account.owner != program_id {
+        return Ok(());
+    }
+    let config = RewardConfig::unpack(&config_account.data.borrow())?;
+    accrue_rewards(stake, config.rate_bps, Clock::get()?.epoch)
+}
+
+/// Writes `config` in the current layout, growing an account created in the
+/// legacy one. The pool's own lamports cover the extra rent.
+fn store_reward_config(config_account: &AccountInfo, config: &RewardConfig) -> ProgramResult {
+    if config_account.data_len() < REWARD_CONFIG_LEN {
+        config_account.realloc(REWARD_CONFIG_LEN, true)?;
+    }
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::{
+        clock::Clock,
+        instruction::{AccountMeta, Instruction},
+        sysvar,
+    };
+    use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+    use solana_sdk::{
+        account::Account,
+        signature::{Keypair, Signer},
+        transaction::Transaction,
+    };
+
+    const MIN_STAKE: u64 = 10_000_000_000;
+
+    // Pinned wire formats. Deployed programs and clients decode these exact
+    // bytes, so a change here is a breaking on-chain change.
+    fn layout_vectors() -> (Vec<(StakeAccount, Vec<u8>)>, Vec<(StakeInstruction, Vec<u8>)>) {
+        let owner = Pubkey::new_from_array([0x11; 32]);
+        let new_owner = Pubkey::new_from_array([0x22; 32]);
+
+        let mut active = vec![0x11; 32];
+        active.extend_from_slice(&[0x00, 0xe4, 0x0b, 0x54, 0x02, 0x00, 0x00, 0x00]);
+        active.extend_from_slice(&[0x00, 0xf1, 0x53, 0x65, 0x00, 0x00, 0x00, 0x00]);
+        active.push(0x01);
+        active.extend_from_slice(&[0x00; 8]);
+        active.push(0x00);
+        active.extend_from_slice(&[0x00; 32]);
+
+        let mut inactive = vec![0x11; 32];
+        inactive.extend_from_slice(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        inactive.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        inactive.push(0x00);
+        inactive.extend_from_slice(&[0x00, 0xf2, 0x05, 0x2a, 0x01, 0x00, 0x00, 0x00]);
+        inactive.push(0x03);
+        inactive.extend_from_slice(&[0x2c, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        inactive.extend_from_slice(&[0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        inactive.extend_from_slice(&[0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        inactive.extend_from_slice(&[0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        let accounts = vec![
+            (StakeAccount::new(owner, MIN_STAKE, 1_700_000_000, 0), active),
+            (
+                StakeAccount {
+                    owner,
+                    amount: 1,
+                    locked_until: -1,
+                    is_active: false,
+                    slashed: 5_000_000_000,
+                    last_slash_reason: SlashReason::Downtime,
+                    accrued_rewards: 300,
+                    last_accrual_epoch: 512,
+                    distributed_rewards: 100,
+                    next_reward_epoch: 8,
+                },
+                inactive,
+            ),
+        ];
+
+        let mut transfer = vec![0x02];
+        transfer.extend_from_slice(&[0x22; 32]);
+
+        let mut set_authority = vec![0x03];
+        set_authority.extend_from_slice(&[0x33; 32]);
+
+        let mut delegate = vec![0x05];
+        delegate.extend_from_slice(&[0x44; 32]);
+        delegate.extend_from_slice(&[0x00, 0xe4, 0x0b, 0x54, 0x02, 0x00, 0x00, 0x00]);
+
+        let instructions = vec![
+            (
+                StakeInstruction::CreateStake { amount: MIN_STAKE, lock_period: 86_400 },
+                vec![
+                    0x00,
+                    0x00, 0xe4, 0x0b, 0x54, 0x02, 0x00, 0x00, 0x00,
+                    0x80, 0x51, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+                ],
+            ),
+            (
+                StakeInstruction::Withdraw { amount: 1 },
+                vec![0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ),
+            (StakeInstruction::TransferOwnership { new_owner }, transfer),
+            (
+                StakeInstruction::SetSlashAuthority { authority: Pubkey::new_from_array([0x33; 32]) },
+                set_authority,
+            ),
+            (
+                StakeInstruction::Slash { amount: 1, reason: SlashReason::DoubleSign },
+                vec![0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01],
+            ),
+            (
+                StakeInstruction::Delegate { validator: Pubkey::new_from_array([0x44; 32]), amount: MIN_STAKE },
+                delegate,
+            ),
+            (StakeInstruction::Undelegate, vec![0x06]),
+            (
+                StakeInstruction::SetRewardRate { rate_bps: 250 },
+                vec![0x07, 0xfa, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ),
+            (StakeInstruction::ClaimRewards, vec![0x08]),
+            (
+                StakeInstruction::ExtendLock { lock_period: 86_400 },
+                vec![0x09, 0x80, 0x51, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ),
+            (
+                StakeInstruction::DistributeRewards { epoch: 7, amount: 1 },
+                vec![
+                    0x0a,
+                    0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                ],
+            ),
+            (StakeInstruction::MigrateStake, vec![0x0b]),
+        ];
+
+        (accounts, instructions)
+    }
+
+    #[test]
+    fn test_stake_account_layout_is_pinned() {
+        let (accounts, _) = layout_vectors();
+        for (account, bytes) in accounts {
+            assert_eq!(bytes.len(), STAKE_ACCOUNT_LEN);
+            assert_eq!(account.try_to_vec().unwrap(), bytes);
+            assert_eq!(StakeAccount::try_from_slice(&bytes).unwrap(), account);
+        }
+    }
+
+    #[test]
+    fn test_legacy_stake_layouts_unpack_with_zeroed_fields() {
+        let (accounts, _) = layout_vectors();
+        let (active, bytes) = &accounts[0];
+        for len in LEGACY_STAKE_ACCOUNT_LENS {
+            assert_eq!(StakeAccount::unpack(&bytes[..len]).unwrap(), *active);
+        }
+        assert_eq!(StakeAccount::unpack(bytes).unwrap(), *active);
+        assert!(StakeAccount::unpack(&bytes[..50]).is_err());
+
+        let config = RewardConfig { authority: Pubkey::new_from_array([0x55; 32]), rate_bps: 250, outstanding: 0 };
+        let bytes = config.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), REWARD_CONFIG_LEN);
+        assert_eq!(RewardConfig::unpack(&bytes[..LEGACY_REWARD_CONFIG_LEN]).unwrap(), config);
+    }
+
+    #[test]
+    fn test_stake_instruction_layout_is_pinned() {
+        let (_, instructions) = layout_vectors();
+        for (instruction, bytes) in instructions {
+            assert_eq!(instruction.try_to_vec().unwrap(), bytes);
+            assert_eq!(StakeInstruction::try_from_slice(&bytes).unwrap(), instruction);
+        }
+    }
+
+    #[test]
+    fn test_reward_accrual_is_overflow_safe() {
+        let mut stake = StakeAccount::new(Pubkey::new_unique(), MIN_STAKE, 0, 10);
+
+        accrue_rewards(&mut stake, 100, 13).unwrap();
+        assert_eq!(stake.accrued_rewards, MIN_STAKE / 100 * 3);
+        assert_eq!(stake.last_accrual_epoch, 13);
+
+        accrue_rewards(&mut stake, 100, 12).unwrap();
+        assert_eq!(stake.accrued_rewards, MIN_STAKE / 100 * 3);
+        assert_eq!(stake.last_accrual_epoch, 13);
+
+        stake.amount = u64::MAX;
+        assert_eq!(accrue_rewards(&mut stake, BPS_DENOMINATOR, u64::MAX), Err(ProgramError::ArithmeticOverflow));
+
+        stake.is_active = false;
+        let accrued = stake.accrued_rewards;
+        accrue_rewards(&mut stake, BPS_DENOMINATOR, u64::MAX).unwrap();
+        assert_eq!(stake.accrued_rewards, accrued);
+        assert_eq!(stake.last_accrual_epoch, u64::MAX);
+    }
+
+    fn program_test(program_id: Pubkey) -> ProgramTest {
+        ProgramTest::new("fractis_stake", program_id, processor!(process_instruction))
+    }
+
+    /// Records `admin` as the program's upgrade authority and funds it.
+    fn program_test_with_admin(program_id: Pubkey, admin: &Keypair) -> ProgramTest {
+        let mut program_test = program_test(program_id);
+        let state = UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: Some(admin.pubkey()),
+        };
+        let data = bincode::serialize(&state).unwrap();
+        program_test.add_account(
+            bpf_loader_upgradeable::get_program_data_address(&program_id),
+            Account {
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                owner: bpf_loader_upgradeable::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        program_test.add_account(
+            admin.pubkey(),
+            Account::new(1_000_000_000, 0, &solana_program::system_program::id()),
+        );
+        program_test
+    }
+
+    fn instruction_with_clock(
+        program_id: Pubkey,
+        mut accounts: Vec<AccountMeta>,
+        instruction: &StakeInstruction,
+    ) -> Instruction {
+        accounts.push(AccountMeta::new_readonly(sysvar::clock::id(), false));
+        Instruction::new_with_borsh(program_id, instruction, accounts)
+    }
+
+    async fn warp_clock(context: &mut ProgramTestContext, unix_timestamp: i64) {
+        let mut clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp = unix_timestamp;
+        context.set_sysvar(&clock);
+    }
+
+    async fn current_time(context: &mut ProgramTestContext) -> i64 {
+        let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp
+    }
+
+    async fn create_stake(
+        context: &mut ProgramTestContext,
+        program_id: Pubkey,
+        lock_period: i64,
+    ) -> Pubkey {
+        let (stake_account, _) = find_stake_address(&program_id, &context.payer.pubkey());
+        let instruction = instruction_with_clock(
+            program_id,
+            vec![
+                AccountMeta::new(context.payer.pubkey(), true),
+                AccountMeta::new(stake_account, false),
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            ],
+            &StakeInstruction::CreateStake { amount: MIN_STAKE, lock_period },
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer],
+            context.last_blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+        stake_account
+    }
+
+    async fn withdraw(
+        context: &mut ProgramTestContext,
+        program_id: Pubkey,
+        stake_account: Pubkey,
+        amount: u64,
+    ) -> Result<(), solana_program_test::BanksClientError> {
+        let instruction = instruction_with_clock(
+            program_id,
+            vec![
+                AccountMeta::new(context.payer.pubkey(), true),
+                AccountMeta::new(stake_account, false),
+            ],
+            &StakeInstruction::Withdraw { amount },
+        );
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer],
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_rejected_while_locked() {
+        let program_id = Pubkey::new_unique();
+        let mut context = program_test(program_id).start_with_context().await;
+
+        let now = current_time(&mut context).await;
+        let stake_account = create_stake(&mut context, program_id, 3600).await;
+
+        warp_clock(&mut context, now + 1800).await;
+        assert!(withdraw(&mut context, program_id, stake_account, 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_create_stake_rejects_account_off_the_staker_address() {
+        let program_id = Pubkey::new_unique();
+        let mut context = program_test(program_id).start_with_context().await;
+
+        let forged = Keypair::new();
+        let instruction = instruction_with_clock(
+            program_id,
+            vec![
+                AccountMeta::new(context.payer.pubkey(), true),
+                AccountMeta::new(forged.pubkey(), true),
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            ],
+            &StakeInstruction::CreateStake { amount: MIN_STAKE, lock_period: 0 },
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer, &forged],
+            context.last_blockhash,
+        );
+        assert!(context.banks_client.process_transaction(transaction).await.is_err());
+        assert!(context.banks_client.get_account(forged.pubkey()).await.unwrap().is_none());
+    }
+
+    async fn extend_lock(
+        context: &mut ProgramTestContext,
+        program_id: Pubkey,
+        stake_account: Pubkey,
+        lock_period: i64,
+    ) {
+        let instruction = Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::ExtendLock { lock_period },
+            vec![
+                AccountMeta::new_readonly(context.payer.pubkey(), true),
+                AccountMeta::new(stake_account, false),
+            ],
+        );
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer],
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extended_lock_postpones_withdrawal_until_it_expires() {
+        let program_id = Pubkey::new_unique();
+        let mut context = program_test(program_id).start_with_context().await;
+
+        let now = current_time(&mut context).await;
+        let stake_account = create_stake(&mut context, program_id, 3600).await;
+        extend_lock(&mut context, program_id, stake_account, 3600).await;
+
+        warp_clock(&mut context, now + 3601).await;
+        assert!(withdraw(&mut context, program_id, stake_account, 1).await.is_err());
+
+        warp_clock(&mut context, now + 7200).await;
+        withdraw(&mut context, program_id, stake_account, 2).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_unlocked_stake() {
+        let program_id = Pubkey::new_unique();
+        let mut context = program_test(program_id).start_with_context().await;
+
+        let stake_account = create_stake(&mut context, program_id, 0).await;
+        withdraw(&mut context, program_id, stake_account, MIN_STAKE).await.unwrap();
+
+        let account = context.banks_client
+            .get_account(stake_account)
+            .await
+            .unwrap()
+            .unwrap();
+        let stake_data = StakeAccount::try_from_slice(&account.data).unwrap();
+        assert_eq!(stake_data.amount, 0);
+    }
+
+    async fn transfer_ownership(
+        context: &mut ProgramTestContext,
+        program_id: Pubkey,
+        stake_account: Pubkey,
+        owner: &Keypair,
+        new_owner: &Keypair,
+    ) -> Result<(), solana_program_test::BanksClientError> {
+        let instruction = Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::TransferOwnership { new_owner: new_owner.pubkey() },
+            vec![
+                AccountMeta::new_readonly(owner.pubkey(), true),
+                AccountMeta::new(stake_account, false),
+                AccountMeta::new_readonly(new_owner.pubkey(), true),
+            ],
+        );
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer, owner, new_owner],
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    async fn test_transfer_ownership() {
+        let program_id = Pubkey::new_unique();
+        let mut context = program_test(program_id).start_with_context().await;
+        let new_owner = Keypair::new();
+
+        let stake_account = create_stake(&mut context, program_id, 0).await;
+        let owner = context.payer.insecure_clone();
+        transfer_ownership(&mut context, program_id, stake_account, &owner, &new_owner)
+            .await
+            .unwrap();
+
+        let account = context.banks_client
+            .get_account(stake_account)
+            .await
+            .unwrap()
+            .unwrap();
+        let stake_data = StakeAccount::try_from_slice(&account.data).unwrap();
+        assert_eq!(stake_data.owner, new_owner.pubkey());
+    }
+
+    #[tokio::test]
+    async fn test_transfer_ownership_rejected_for_non_owner() {
+        let program_id = Pubkey::new_unique();
+        let mut context = program_test(program_id).start_with_context().await;
+        let intruder = Keypair::new();
+        let new_owner = Keypair::new();
+
+        let stake_account = create_stake(&mut context, program_id, 0).await;
+        let result = transfer_ownership(&mut context, program_id, stake_account, &intruder, &new_owner).await;
+        assert!(result.is_err());
+    }
+
+    async fn set_slash_authority(
+        context: &mut ProgramTestContext,
+        program_id: Pubkey,
+        signer: &Keypair,
+        authority: Pubkey,
+    ) -> Result<(), solana_program_test::BanksClientError> {
+        let (config_address, _) = find_slash_config_address(&program_id);
+        let instruction = Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::SetSlashAuthority { authority },
+            vec![
+                AccountMeta::new(signer.pubkey(), true),
+                AccountMeta::new(config_address, false),
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+                AccountMeta::new_readonly(bpf_loader_upgradeable::get_program_data_address(&program_id), false),
+            ],
+        );
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer, signer],
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await
+    }
+
+    async fn slash(
+        context: &mut ProgramTestContext,
+        program_id: Pubkey,
+        authority: &Keypair,
+        stake_account: Pubkey,
+        destination: Pubkey,
+        amount: u64,
+    ) -> Result<(), solana_program_test::BanksClientError> {
+        let (config_address, _) = find_slash_config_address(&program_id);
+        let instruction = Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::Slash { amount, reason: SlashReason::DoubleSign },
+            vec![
+                AccountMeta::new_readonly(authority.pubkey(), true),
+                AccountMeta::new_readonly(config_address, false),
+                AccountMeta::new(stake_account, false),
+                AccountMeta::new(destination, false),
+            ],
+        );
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer, authority],
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    async fn test_slash_redirects_stake_and_records_reason() {
+        let program_id = Pubkey::new_unique();
+        let admin = Keypair::new();
+        let mut context = program_test_with_admin(program_id, &admin).start_with_context().await;
+        let authority = Keypair::new();
+        let treasury = Pubkey::new_unique();
+
+        let stake_account = create_stake(&mut context, program_id, 0).await;
+
+        let intruder = Keypair::new();
+        assert!(set_slash_authority(&mut context, program_id, &intruder, intruder.pubkey()).await.is_err());
+        set_slash_authority(&mut context, program_id, &admin, authority.pubkey()).await.unwrap();
+
+        assert!(slash(&mut context, program_id, &intruder, stake_account, treasury, MIN_STAKE / 2).await.is_err());
+        assert!(slash(&mut context, program_id, &authority, stake_account, treasury, MIN_STAKE + 1).await.is_err());
+
+        slash(&mut context, program_id, &authority, stake_account, treasury, MIN_STAKE / 2).await.unwrap();
+
+        let account = context.banks_client
+            .get_account(stake_account)
+            .await
+            .unwrap()
+            .unwrap();
+        let stake_data = StakeAccount::try_from_slice(&account.data).unwrap();
+        assert_eq!(stake_data.amount, MIN_STAKE / 2);
+        assert_eq!(stake_data.slashed, MIN_STAKE / 2);
+        assert_eq!(stake_data.last_slash_reason, SlashReason::DoubleSign);
+        assert_eq!(context.banks_client.get_balance(treasury).await.unwrap(), MIN_STAKE / 2);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_stake_account_migrated_to_current_layout() {
+        let program_id = Pubkey::new_unique();
+        let staker = Keypair::new();
+        let (stake_account, _) = find_stake_address(&program_id, &staker.pubkey());
+        let legacy_len = LEGACY_STAKE_ACCOUNT_LENS[0];
+
+        let mut legacy = StakeAccount::new(staker.pubkey(), MIN_STAKE, 0, 0).try_to_vec().unwrap();
+        legacy.truncate(legacy_len);
+        let mut program_test = program_test(program_id);
+        program_test.add_account(
+            stake_account,
+            Account {
+                lamports: MIN_STAKE + Rent::default().minimum_balance(legacy_len),
+                data: legacy,
+                owner: program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        program_test.add_account(
+            staker.pubkey(),
+            Account::new(1_000_000_000, 0, &solana_program::system_program::id()),
+        );
+        let mut context = program_test.start_with_context().await;
+
+        let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+        warp_epoch(&mut context, clock.epoch + 2).await;
+
+        for accepted in [true, false] {
+            let instruction = instruction_with_clock(
+                program_id,
+                vec![
+                    AccountMeta::new(staker.pubkey(), true),
+                    AccountMeta::new(stake_account, false),
+                    AccountMeta::new_readonly(solana_program::system_program::id(), false),
+                ],
+                &StakeInstruction::MigrateStake,
+            );
+            // A fresh blockhash, so the retry isn't dropped as a duplicate.
+            let blockhash = context.get_new_latest_blockhash().await.unwrap();
+            let transaction = Transaction::new_signed_with_payer(
+                &[instruction],
+                Some(&context.payer.pubkey()),
+                &[&context.payer, &staker],
+                blockhash,
+            );
+            assert_eq!(context.banks_client.process_transaction(transaction).await.is_ok(), accepted);
+        }
+
+        let account = context.banks_client.get_account(stake_account).await.unwrap().unwrap();
+        assert_eq!(account.data.len(), STAKE_ACCOUNT_LEN);
+        assert_eq!(account.lamports, MIN_STAKE + Rent::default().minimum_balance(STAKE_ACCOUNT_LEN));
+        assert_eq!(
+            StakeAccount::try_from_slice(&account.data).unwrap(),
+            StakeAccount::new(staker.pubkey(), MIN_STAKE, 0, clock.epoch + 2),
+        );
+    }
+
+    async fn delegate(context: &mut ProgramTestContext, program_id: Pubkey, validator: Pubkey, amount: u64) {
+        let delegator = context.payer.pubkey();
+        let instruction = Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::Delegate { validator, amount },
+            vec![
+                AccountMeta::new(delegator, true),
+                AccountMeta::new(find_delegation_address(&program_id, &delegator, &validator).0, false),
+                AccountMeta::new(find_validator_delegations_address(&program_id, &validator).0, false),
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            ],
+        );
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&delegator),
+            &[&context.payer],
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+    }
+
+    async fn delegated_total(context: &mut ProgramTestContext, program_id: Pubkey, validator: Pubkey) -> u64 {
+        let (totals_address, _) = find_validator_delegations_address(&program_id, &validator);
+        let account = context.banks_client.get_account(totals_address).await.unwrap().unwrap();
+        ValidatorDelegations::try_from_slice(&account.data).unwrap().total
+    }
+
+    #[tokio::test]
+    async fn test_delegations_tracked_per_validator_and_returned_on_undelegate() {
+        let program_id = Pubkey::new_unique();
+        let mut context = program_test(program_id).start_with_context().await;
+        let validator = Pubkey::new_unique();
+        let delegator = context.payer.pubkey();
+
+        delegate(&mut context, program_id, validator, 3_000_000_000).await;
+        delegate(&mut context, program_id, validator, 2_000_000_000).await;
+        assert_eq!(delegated_total(&mut context, program_id, validator).await, 5_000_000_000);
+
+        let (delegation_address, _) = find_delegation_address(&program_id, &delegator, &validator);
+        let account = context.banks_client.get_account(delegation_address).await.unwrap().unwrap();
+        let delegation = Delegation::try_from_slice(&account.data).unwrap();
+        assert_eq!(delegation.amount, 5_000_000_000);
+        assert_eq!(delegation.validator, validator);
+
+        let balance_before = context.banks_client.get_balance(delegator).await.unwrap();
+        let instruction = Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::Undelegate,
+            vec![
+                AccountMeta::new(delegator, true),
+                AccountMeta::new(delegation_address, false),
+                AccountMeta::new(find_validator_delegations_address(&program_id, &validator).0, false),
+            ],
+        );
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&delegator),
+            &[&context.payer],
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+
+        assert_eq!(delegated_total(&mut context, program_id, validator).await, 0);
+        assert!(context.banks_client.get_account(delegation_address).await.unwrap().is_none());
+        let balance_after = context.banks_client.get_balance(delegator).await.unwrap();
+        assert!(balance_after > balance_before + 4_999_000_000);
+    }
+
+    async fn set_reward_rate(
+        context: &mut ProgramTestContext,
+        program_id: Pubkey,
+        signer: &Keypair,
+        rate_bps: u64,
+    ) -> Result<(), solana_program_test::BanksClientError> {
+        let instruction = Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::SetRewardRate { rate_bps },
+            vec![
+                AccountMeta::new(signer.pubkey(), true),
+                AccountMeta::new(find_reward_config_address(&program_id).0, false),
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+                AccountMeta::new_readonly(bpf_loader_upgradeable::get_program_data_address(&program_id), false),
+            ],
+        );
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer, signer],
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await
+    }
+
+    async fn warp_epoch(context: &mut ProgramTestContext, epoch: u64) {
+        let mut clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+        clock.epoch = epoch;
+        context.set_sysvar(&clock);
+    }
+
+    #[tokio::test]
+    async fn test_rewards_accrue_per_epoch_and_are_claimed_from_the_pool() {
+        let program_id = Pubkey::new_unique();
+        let admin = Keypair::new();
+        let mut context = program_test_with_admin(program_id, &admin).start_with_context().await;
+        let (config_address, _) = find_reward_config_address(&program_id);
+
+        let intruder = Keypair::new();
+        assert!(set_reward_rate(&mut context, program_id, &intruder, 100).await.is_err());
+        set_reward_rate(&mut context, program_id, &admin, 100).await.unwrap();
+        let fund = solana_sdk::system_transaction::transfer(
+            &context.payer,
+            &config_address,
+            MIN_STAKE,
+            context.banks_client.get_latest_blockhash().await.unwrap(),
+        );
+        context.banks_client.process_transaction(fund).await.unwrap();
+
+        let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+        let stake_account = create_stake(&mut context, program_id, 0).await;
+        warp_epoch(&mut context, clock.epoch + 3).await;
+
+        let pool_before = context.banks_client.get_balance(config_address).await.unwrap();
+        let instruction = Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::ClaimRewards,
+            vec![
+                AccountMeta::new(context.payer.pubkey(), true),
+                AccountMeta::new(stake_account, false),
+                AccountMeta::new(config_address, false),
+            ],
+        );
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer],
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+
+        let reward = MIN_STAKE / 100 * 3;
+        let pool_after = context.banks_client.get_balance(config_address).await.unwrap();
+        assert_eq!(pool_before - pool_after, reward);
+
+        let account = context.banks_client
+            .get_account(stake_account)
+            .await
+            .unwrap()
+            .unwrap();
+        let stake_data = StakeAccount::try_from_slice(&account.data).unwrap();
+        assert_eq!(stake_data.accrued_rewards, 0);
+        assert_eq!(stake_data.last_accrual_epoch, clock.epoch + 3);
+    }
+
+    fn distribute_instruction(
+        program_id: Pubkey,
+        authority: Pubkey,
+        stake_account: Pubkey,
+        epoch: u64,
+        amount: u64,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::DistributeRewards { epoch, amount },
+            vec![
+                AccountMeta::new_readonly(authority, true),
+                AccountMeta::new(find_reward_config_address(&program_id).0, false),
+                AccountMeta::new(stake_account, false),
+            ],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_distributed_rewards_credited_by_reward_authority_only() {
+        let program_id = Pubkey::new_unique();
+        let admin = Keypair::new();
+        let mut context = program_test_with_admin(program_id, &admin).start_with_context().await;
+        let (config_address, _) = find_reward_config_address(&program_id);
+
+        set_reward_rate(&mut context, program_id, &admin, 0).await.unwrap();
+        let fund = solana_sdk::system_transaction::transfer(
+            &context.payer,
+            &config_address,
+            1_000_000,
+            context.banks_client.get_latest_blockhash().await.unwrap(),
+        );
+        context.banks_client.process_transaction(fund).await.unwrap();
+        let stake_account = create_stake(&mut context, program_id, 0).await;
+
+        let intruder = Keypair::new();
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let forged = Transaction::new_signed_with_payer(
+            &[distribute_instruction(program_id, intruder.pubkey(), stake_account, 1, 5_000)],
+            Some(&context.payer.pubkey()),
+            &[&context.payer, &intruder],
+            blockhash,
+        );
+        assert!(context.banks_client.process_transaction(forged).await.is_err());
+
+        // The pool holds back what it already owes, and no epoch is paid twice.
+        for (epoch, amount, accepted) in [(1, 5_000, true), (2, 995_001, false), (1, 4_000, false), (3, 995_000, true)] {
+            let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+            let transaction = Transaction::new_signed_with_payer(
+                &[distribute_instruction(program_id, admin.pubkey(), stake_account, epoch, amount)],
+                Some(&context.payer.pubkey()),
+                &[&context.payer, &admin],
+                blockhash,
+            );
+            assert_eq!(context.banks_client.process_transaction(transaction).await.is_ok(), accepted);
+        }
+
+        let account = context.banks_client.get_account(stake_account).await.unwrap().unwrap();
+        let stake_data = StakeAccount::try_from_slice(&account.data).unwrap();
+        assert_eq!(stake_data.distributed_rewards, 1_000_000);
+        assert_eq!(stake_data.next_reward_epoch, 4);
+        let config = context.banks_client.get_account(config_address).await.unwrap().unwrap();
+        assert_eq!(RewardConfig::unpack(&config.data).unwrap().outstanding, 1_000_000);
+    }
+}
@@ -1,8 +1,51 @@
+use bech32::{FromBase32, ToBase32, Variant};
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use solana_sdk::pubkey::Pubkey;
 use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
 use thiserror::Error;
 
 const FRACTIS_PREFIX: &str = "fractis";
-const SOLANA_ADDRESS_LENGTH: usize = 44;
+/// Human-readable part of bech32m-encoded addresses.
+const FRACTIS_HRP: &str = "fractis";
+
+/// Domain-separation tag mixed into every derivation round so FRACTIS
+/// addresses never coincide with another scheme hashing pubkeys the same way.
+/// Changing the tag changes every derived address; add a new version instead
+/// of editing an existing one.
+pub const DOMAIN_TAG_V1: &str = "FRACTIS-v1";
+pub const CURRENT_DOMAIN_TAG: &str = DOMAIN_TAG_V1;
+
+/// Every derivation the network has used. The same Solana account maps to a
+/// different FRACTIS address under each one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DerivationScheme {
+    /// The original derivation, before domain tags were introduced.
+    Legacy,
+    V1,
+    /// The pubkey bytes themselves, bech32m-encoded under the `fractis` HRP.
+    /// The only scheme that can be converted back to a Solana address.
+    Bech32,
+}
+
+impl DerivationScheme {
+    pub const ALL: [DerivationScheme; 3] = [DerivationScheme::Legacy, DerivationScheme::V1, DerivationScheme::Bech32];
+    pub const CURRENT: DerivationScheme = DerivationScheme::Bech32;
+
+    /// The tag mixed into the hash, for the one-way hashed schemes.
+    pub fn domain_tag(self) -> Option<&'static str> {
+        match self {
+            DerivationScheme::Legacy | DerivationScheme::Bech32 => None,
+            DerivationScheme::V1 => Some(DOMAIN_TAG_V1),
+        }
+    }
+
+    pub fn is_reversible(self) -> bool {
+        self == DerivationScheme::Bech32
+    }
+}
 
 #[derive(Error, Debug)]
 pub enum AddressError {
@@ -10,31 +53,132 @@ pub enum AddressError {
     InvalidSolanaAddress(String),
     #[error("Invalid FRACTIS address: {0}")]
     InvalidFRACTISAddress(String),
+    #[error("FRACTIS address {0} was derived by a one-way hash and cannot be converted back")]
+    Irreversible(String),
+    #[error("FRACTIS address {0} fails its checksum, check it for typos")]
+    ChecksumMismatch(String),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct FRACTISAddress(String);
 
 impl FRACTISAddress {
-    
+    /// Encodes `solana_address` under the current, reversible scheme.
     pub fn from_solana(solana_address: &str) -> Result<Self, AddressError> {
-       
-        if solana_address.len() != SOLANA_ADDRESS_LENGTH || 
-           !solana_address.chars().all(|c| c.is_ascii_alphanumeric()) {
-            return Err(AddressError::InvalidSolanaAddress(
-                format!("Invalid Solana address format: must be {} characters long and alphanumeric", 
-                       SOLANA_ADDRESS_LENGTH)
-            ));
+        Self::from_solana_with_scheme(solana_address, DerivationScheme::CURRENT)
+    }
+
+    /// Derives the one-way hashed address for `solana_address` under `domain_tag`.
+    pub fn from_solana_with_tag(solana_address: &str, domain_tag: &str) -> Result<Self, AddressError> {
+        Self::parse_solana_address(solana_address)?;
+        Ok(Self::derive(solana_address, Some(domain_tag)))
+    }
+
+    pub fn from_solana_with_scheme(solana_address: &str, scheme: DerivationScheme) -> Result<Self, AddressError> {
+        let pubkey = Self::parse_solana_address(solana_address)?;
+        if scheme.is_reversible() {
+            return Ok(Self::from_pubkey(&pubkey));
+        }
+        Ok(Self::derive(solana_address, scheme.domain_tag()))
+    }
+
+    /// The address `solana_address` derives to under each known scheme.
+    pub fn all_schemes(solana_address: &str) -> Result<Vec<(DerivationScheme, Self)>, AddressError> {
+        DerivationScheme::ALL
+            .iter()
+            .map(|scheme| Ok((*scheme, Self::from_solana_with_scheme(solana_address, *scheme)?)))
+            .collect()
+    }
+
+    /// True if both addresses derive from `solana_address`, under any
+    /// combination of known schemes.
+    pub fn same_origin(a: &Self, b: &Self, solana_address: &str) -> bool {
+        match Self::all_schemes(solana_address) {
+            Ok(derived) => {
+                let derives = |addr: &Self| derived.iter().any(|(_, d)| d == addr);
+                derives(a) && derives(b)
+            }
+            Err(_) => false,
         }
+    }
 
-        
+    pub fn from_pubkey(pubkey: &Pubkey) -> Self {
+        let encoded = bech32::encode(FRACTIS_HRP, pubkey.to_bytes().to_base32(), Variant::Bech32m)
+            .expect("a valid HRP and a 32-byte payload always encode");
+        FRACTISAddress(encoded)
+    }
+
+    /// The Solana account this address encodes. Fails for addresses from the
+    /// hashed schemes, which never kept the key.
+    pub fn to_pubkey(&self) -> Result<Pubkey, AddressError> {
+        if Self::is_hashed_format(&self.0) {
+            return Err(AddressError::Irreversible(self.0.clone()));
+        }
+        Self::decode_bech32(&self.0)
+    }
+
+    /// The base58 Solana address this address encodes.
+    pub fn to_solana(&self) -> Result<String, AddressError> {
+        self.to_pubkey().map(|pubkey| pubkey.to_string())
+    }
+
+    /// Checks the bech32m checksum carried in the last six characters of
+    /// `address`, which catches any single mistyped character and any two
+    /// swapped neighbours. Addresses from the hashed schemes carry no
+    /// checksum and never pass.
+    pub fn verify_checksum(address: &str) -> Result<(), AddressError> {
+        Self::decode_checked(address).map(|_| ())
+    }
+
+    fn decode_checked(address: &str) -> Result<Vec<bech32::u5>, AddressError> {
+        match bech32::decode(address) {
+            Ok((hrp, data, Variant::Bech32m)) if hrp == FRACTIS_HRP => Ok(data),
+            Ok(_) => Err(AddressError::InvalidFRACTISAddress(format!(
+                "{}: expected a bech32m address with the \"{}\" prefix",
+                address, FRACTIS_HRP
+            ))),
+            Err(bech32::Error::InvalidChecksum) => Err(AddressError::ChecksumMismatch(address.to_string())),
+            Err(e) => Err(AddressError::InvalidFRACTISAddress(format!("{}: {}", address, e))),
+        }
+    }
+
+    fn decode_bech32(address: &str) -> Result<Pubkey, AddressError> {
+        let data = Self::decode_checked(address)?;
+        let bytes = Vec::<u8>::from_base32(&data)
+            .map_err(|e| AddressError::InvalidFRACTISAddress(format!("{}: {}", address, e)))?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            AddressError::InvalidFRACTISAddress(format!("{}: expected 32 bytes, found {}", address, bytes.len()))
+        })?;
+        Ok(Pubkey::new_from_array(bytes))
+    }
+
+    /// `fractis` followed by 64 hex digits, the shape of the hashed schemes.
+    fn is_hashed_format(address: &str) -> bool {
+        address
+            .strip_prefix(FRACTIS_PREFIX)
+            .map_or(false, |hex| hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+    }
+
+    /// Decodes a base58 Solana address. Anything that decodes to exactly 32
+    /// bytes is accepted, which puts legal addresses between 32 and 44
+    /// characters.
+    pub fn parse_solana_address(solana_address: &str) -> Result<Pubkey, AddressError> {
+        Pubkey::from_str(solana_address).map_err(|e| {
+            AddressError::InvalidSolanaAddress(format!("{}: not a base58 32-byte public key ({})", solana_address, e))
+        })
+    }
+
+    fn derive(solana_address: &str, domain_tag: Option<&str>) -> Self {
         let mut hashes = Vec::new();
         let mut prev_hash = solana_address.to_string();
 
         
         for i in 0..4 {
             let mut hash: u128 = 5381; // DJB2 初始值
-            let input = format!("{}{}", prev_hash, i);
+            let input = match domain_tag {
+                Some(tag) => format!("{}:{}{}", tag, prev_hash, i),
+                None => format!("{}{}", prev_hash, i),
+            };
 
            
             for c in input.chars() {
@@ -43,33 +187,22 @@ impl FRACTISAddress {
 
             
             let hash_hex = format!("{:016x}", hash % (1u128 << 64));
-            hashes.push(hash_hex);
+            hashes.push(hash_hex.clone());
             prev_hash = hash_hex;
         }
 
         
-        let fractis_addr = format!("{}{}", FRACTIS_PREFIX, hashes.join(""));
-        Ok(FRACTISAddress(fractis_addr))
+        FRACTISAddress(format!("{}{}", FRACTIS_PREFIX, hashes.join("")))
     }
 
-   
+    /// Parses either a bech32m address, normalized to lowercase, or a
+    /// hex address from one of the hashed schemes. Hashed addresses have no
+    /// checksum, so only their shape can be checked.
     pub fn from_string(fractis_address: &str) -> Result<Self, AddressError> {
-        
-        if !fractis_address.starts_with(FRACTIS_PREFIX) {
-            return Err(AddressError::InvalidFRACTISAddress(
-                "Invalid FRACTIS address prefix".to_string()
-            ));
+        if Self::is_hashed_format(fractis_address) {
+            return Ok(FRACTISAddress(fractis_address.to_string()));
         }
-
-       
-        let addr_part = &fractis_address[FRACTIS_PREFIX.len()..];
-        if addr_part.len() != 64 || !addr_part.chars().all(|c| c.is_ascii_hexdigit()) {
-            return Err(AddressError::InvalidFRACTISAddress(
-                format!("Invalid FRACTIS address format: must be {} characters after prefix and hexadecimal", 64)
-            ));
-        }
-
-        Ok(FRACTISSAddress(fractis_address.to_string()))
+        Self::decode_bech32(fractis_address).map(|pubkey| Self::from_pubkey(&pubkey))
     }
 
     
@@ -84,10 +217,43 @@ impl fmt::Display for FRACTISAddress {
     }
 }
 
+// Both encodings carry the address text, so hashed addresses survive a
+// round trip too; decoding goes through `from_string` and rejects typos.
+
+impl Serialize for FRACTISAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for FRACTISAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let address = <String as Deserialize>::deserialize(deserializer)?;
+        Self::from_string(&address).map_err(de::Error::custom)
+    }
+}
+
+impl BorshSerialize for FRACTISAddress {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        BorshSerialize::serialize(&self.0, writer)
+    }
+}
+
+impl BorshDeserialize for FRACTISAddress {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let address = String::deserialize_reader(reader)?;
+        Self::from_string(&address).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const V1_VECTOR: &str = "fractisa7d46dd0d471cb32e1471d6b09e200588402a8ac2e8b592c301125d01cbdc3b1";
+    const LEGACY_VECTOR: &str = "fractis269c3ffc7fcd74f8c97ab08fdcaba2a9b824dd81cdf6a0b25df13cd0e039d431";
+    const BECH32_VECTOR: &str = "fractis1hfa8fum54vzmwrg3ffupzths60cxjk5pj4ev09csk5mjqqxcrt3qgasqlz";
+
     #[test]
     fn test_solana_to_fractis_conversion() {
         
@@ -97,8 +263,137 @@ mod tests {
         let fractis_addr = FRACTISAddress::from_solana(solana_addr).unwrap();
         
        
-        assert!(fractis_addr.as_string().starts_with(FRACTIS_PREFIX));
-        assert_eq!(fractis_addr.as_string().len(), FRACTIS_PREFIX.len() + 64);
+        assert_eq!(fractis_addr.as_string(), BECH32_VECTOR);
+        assert_eq!(fractis_addr.to_solana().unwrap(), solana_addr);
+    }
+
+    #[test]
+    fn test_round_trip_through_string() {
+        use solana_sdk::signature::{Keypair, Signer};
+
+        for _ in 0..32 {
+            let pubkey = Keypair::new().pubkey();
+            let address = FRACTISAddress::from_pubkey(&pubkey);
+            let parsed = FRACTISAddress::from_string(address.as_string()).unwrap();
+            assert_eq!(parsed, address);
+            assert_eq!(parsed.to_pubkey().unwrap(), pubkey);
+        }
+
+        let upper = FRACTISAddress::from_string(&BECH32_VECTOR.to_uppercase()).unwrap();
+        assert_eq!(upper.as_string(), BECH32_VECTOR);
+    }
+
+    #[test]
+    fn test_bech32_checksum_and_prefix_enforced() {
+        let mut typo = BECH32_VECTOR.to_string();
+        typo.replace_range(20..21, if &typo[20..21] == "q" { "p" } else { "q" });
+        assert!(FRACTISAddress::from_string(&typo).is_err());
+
+        let pubkey = FRACTISAddress::from_string(BECH32_VECTOR).unwrap().to_pubkey().unwrap();
+        let other_hrp = bech32::encode("solana", pubkey.to_bytes().to_base32(), Variant::Bech32m).unwrap();
+        assert!(FRACTISAddress::from_string(&other_hrp).is_err());
+        let short = bech32::encode(FRACTIS_HRP, [0u8; 20].to_base32(), Variant::Bech32m).unwrap();
+        assert!(FRACTISAddress::from_string(&short).is_err());
+    }
+
+    #[test]
+    fn test_serde_round_trip_validates() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Payout {
+            address: FRACTISAddress,
+        }
+
+        let payout = Payout { address: FRACTISAddress::from_string(BECH32_VECTOR).unwrap() };
+        let json = serde_json::to_string(&payout).unwrap();
+        assert_eq!(json, format!("{{\"address\":\"{}\"}}", BECH32_VECTOR));
+        assert_eq!(serde_json::from_str::<Payout>(&json).unwrap(), payout);
+
+        let toml_text = toml::to_string(&payout).unwrap();
+        assert_eq!(toml::from_str::<Payout>(&toml_text).unwrap(), payout);
+
+        let legacy = format!("{{\"address\":\"{}\"}}", LEGACY_VECTOR);
+        assert_eq!(serde_json::from_str::<Payout>(&legacy).unwrap().address.as_string(), LEGACY_VECTOR);
+
+        let typo = json.replacen("hfa8", "hfa9", 1);
+        assert!(serde_json::from_str::<Payout>(&typo).is_err());
+    }
+
+    #[test]
+    fn test_borsh_round_trip_validates() {
+        let address = FRACTISAddress::from_string(BECH32_VECTOR).unwrap();
+        let bytes = address.try_to_vec().unwrap();
+        assert_eq!(FRACTISAddress::try_from_slice(&bytes).unwrap(), address);
+
+        let mut tampered = bytes.clone();
+        let last = tampered.len() - 1;
+        tampered[last] = b'q';
+        assert!(FRACTISAddress::try_from_slice(&tampered).is_err());
+        assert!(FRACTISAddress::try_from_slice(&"fractis".to_string().try_to_vec().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        assert!(FRACTISAddress::verify_checksum(BECH32_VECTOR).is_ok());
+        assert!(FRACTISAddress::verify_checksum(&BECH32_VECTOR.to_uppercase()).is_ok());
+        assert!(FRACTISAddress::verify_checksum(V1_VECTOR).is_err());
+        assert!(FRACTISAddress::verify_checksum("not an address").is_err());
+    }
+
+    const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+    #[test]
+    fn test_single_character_typos_rejected() {
+        use rand::{seq::SliceRandom, Rng};
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let address = FRACTISAddress::from_pubkey(&Pubkey::new_from_array(rng.gen())).to_string();
+            let mut bytes = address.into_bytes();
+            // Anywhere but the `1` separating the prefix from the data.
+            let position = *(0..bytes.len())
+                .filter(|i| *i != FRACTIS_HRP.len())
+                .collect::<Vec<_>>()
+                .choose(&mut rng)
+                .unwrap();
+            let original = bytes[position];
+            bytes[position] = *BECH32_CHARSET.iter().filter(|c| **c != original).collect::<Vec<_>>().choose(&mut rng).unwrap();
+            let mutated = String::from_utf8(bytes).unwrap();
+
+            assert!(
+                matches!(FRACTISAddress::verify_checksum(&mutated), Err(AddressError::ChecksumMismatch(_))),
+                "{}",
+                mutated
+            );
+            assert!(FRACTISAddress::from_string(&mutated).is_err());
+        }
+    }
+
+    #[test]
+    fn test_swapped_neighbours_rejected() {
+        use rand::Rng;
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let address = FRACTISAddress::from_pubkey(&Pubkey::new_from_array(rng.gen())).to_string();
+            let mut bytes = address.into_bytes();
+            let position = rng.gen_range(FRACTIS_HRP.len() + 1..bytes.len() - 1);
+            if bytes[position] == bytes[position + 1] {
+                continue;
+            }
+            bytes.swap(position, position + 1);
+            let mutated = String::from_utf8(bytes).unwrap();
+
+            assert!(FRACTISAddress::verify_checksum(&mutated).is_err(), "{}", mutated);
+            assert!(FRACTISAddress::from_string(&mutated).is_err());
+        }
+    }
+
+    #[test]
+    fn test_hashed_addresses_cannot_be_reversed() {
+        let legacy = FRACTISAddress::from_string(LEGACY_VECTOR).unwrap();
+        assert!(matches!(legacy.to_solana(), Err(AddressError::Irreversible(_))));
+        let v1 = FRACTISAddress::from_string(V1_VECTOR).unwrap();
+        assert!(matches!(v1.to_pubkey(), Err(AddressError::Irreversible(_))));
     }
 
     #[test]
@@ -110,6 +405,27 @@ mod tests {
         
         let invalid_chars = "DYw8jCTfwHNRJhhmFcbXvVDTqWMEVFBX6ZKUmG5CNSKK!@";
         assert!(FRACTISAddress::from_solana(invalid_chars).is_err());
+
+        // Alphanumeric, but outside the base58 alphabet.
+        for excluded in ['0', 'O', 'I', 'l'] {
+            let mut address = "DYw8jCTfwHNRJhhmFcbXvVDTqWMEVFBX6ZKUmG5CNSKK".to_string();
+            address.replace_range(5..6, &excluded.to_string());
+            assert!(FRACTISAddress::from_solana(&address).is_err(), "{}", address);
+            assert!(FRACTISAddress::from_solana_with_tag(&address, DOMAIN_TAG_V1).is_err());
+        }
+    }
+
+    #[test]
+    fn test_shorter_pubkeys_accepted() {
+        let mut bytes = [0u8; 32];
+        bytes[31] = 1;
+        for pubkey in [Pubkey::new_from_array([0; 32]), Pubkey::new_from_array(bytes), Pubkey::new_unique()] {
+            let text = pubkey.to_string();
+            assert!((32..=44).contains(&text.len()));
+            assert_eq!(FRACTISAddress::parse_solana_address(&text).unwrap(), pubkey);
+            assert_eq!(FRACTISAddress::from_solana(&text).unwrap().to_pubkey().unwrap(), pubkey);
+            assert_eq!(FRACTISAddress::all_schemes(&text).unwrap().len(), DerivationScheme::ALL.len());
+        }
     }
 
     #[test]
@@ -150,4 +466,61 @@ mod tests {
         let addr2 = FRACTISAddress::from_solana(solana_addr).unwrap();
         assert_eq!(addr1, addr2);
     }
+
+    #[test]
+    fn test_v1_domain_tag_vector() {
+        let solana_addr = "DYw8jCTfwHNRJhhmFcbXvVDTqWMEVFBX6ZKUmG5CNSKK";
+        let v1 = FRACTISAddress::from_solana_with_tag(solana_addr, DOMAIN_TAG_V1).unwrap();
+
+        assert_eq!(FRACTISAddress::from_solana_with_scheme(solana_addr, DerivationScheme::V1).unwrap(), v1);
+        assert_eq!(v1.as_string(), V1_VECTOR);
+    }
+
+    #[test]
+    fn test_domain_tag_changes_output() {
+        let solana_addr = "DYw8jCTfwHNRJhhmFcbXvVDTqWMEVFBX6ZKUmG5CNSKK";
+        let v1 = FRACTISAddress::from_solana_with_tag(solana_addr, DOMAIN_TAG_V1).unwrap();
+        let v2 = FRACTISAddress::from_solana_with_tag(solana_addr, "FRACTIS-v2").unwrap();
+        let untagged = FRACTISAddress::from_solana_with_tag(solana_addr, "").unwrap();
+
+        assert_ne!(v1, v2);
+        assert_ne!(v1, untagged);
+    }
+
+    #[test]
+    fn test_from_pubkey_matches_from_solana() {
+        use solana_sdk::signature::{Keypair, Signer};
+
+        for pubkey in (0..32).map(|_| Keypair::new().pubkey()) {
+            let from_string = FRACTISAddress::from_solana(&pubkey.to_string()).unwrap();
+            assert_eq!(FRACTISAddress::from_pubkey(&pubkey), from_string);
+        }
+    }
+
+    #[test]
+    fn test_all_schemes_covers_legacy_and_v1() {
+        let solana_addr = "DYw8jCTfwHNRJhhmFcbXvVDTqWMEVFBX6ZKUmG5CNSKK";
+        let derived = FRACTISAddress::all_schemes(solana_addr).unwrap();
+
+        assert_eq!(derived.len(), DerivationScheme::ALL.len());
+        assert_eq!(derived[0], (DerivationScheme::Legacy, FRACTISAddress(LEGACY_VECTOR.to_string())));
+        assert_eq!(derived[1], (DerivationScheme::V1, FRACTISAddress(V1_VECTOR.to_string())));
+        assert_eq!(derived[2], (DerivationScheme::Bech32, FRACTISAddress(BECH32_VECTOR.to_string())));
+    }
+
+    #[test]
+    fn test_same_origin_links_addresses_across_schemes() {
+        let solana_addr = "DYw8jCTfwHNRJhhmFcbXvVDTqWMEVFBX6ZKUmG5CNSKK";
+        let other_addr = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+        let legacy = FRACTISAddress::from_solana_with_scheme(solana_addr, DerivationScheme::Legacy).unwrap();
+        let v1 = FRACTISAddress::from_solana_with_scheme(solana_addr, DerivationScheme::V1).unwrap();
+        let unrelated = FRACTISAddress::from_solana(other_addr).unwrap();
+
+        assert_ne!(legacy, v1);
+        assert!(FRACTISAddress::same_origin(&legacy, &v1, solana_addr));
+        assert!(FRACTISAddress::same_origin(&v1, &v1, solana_addr));
+        assert!(!FRACTISAddress::same_origin(&legacy, &v1, other_addr));
+        assert!(!FRACTISAddress::same_origin(&legacy, &unrelated, solana_addr));
+        assert!(!FRACTISAddress::same_origin(&legacy, &v1, "not-a-solana-address"));
+    }
 }
@@ -1,3 +1,5 @@
 pub mod address;
+pub mod registry;
 
-pub use address::{FRACTISAddress, AddressError};
+pub use address::{FRACTISAddress, AddressError, DerivationScheme, CURRENT_DOMAIN_TAG, DOMAIN_TAG_V1};
+pub use registry::{FractisRegistry, RegistryError};
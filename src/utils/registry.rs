@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use thiserror::Error;
+
+use crate::utils::address::{AddressError, FRACTISAddress};
+
+const CSV_HEADER: &str = "solana,fractis";
+
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Address error: {0}")]
+    Address(#[from] AddressError),
+    #[error("Malformed row {row}: {reason}")]
+    Malformed { row: usize, reason: String },
+    #[error("FRACTIS address {fractis} does not derive from Solana address {solana}")]
+    Mismatch { solana: String, fractis: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MappingRow {
+    solana: String,
+    fractis: String,
+}
+
+/// Known Solana → FRACTIS address mappings, kept sorted by Solana address so
+/// exports are stable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FractisRegistry {
+    mappings: BTreeMap<String, FRACTISAddress>,
+}
+
+impl FractisRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, solana_address: &str) -> Result<FRACTISAddress, AddressError> {
+        let fractis = FRACTISAddress::from_solana(solana_address)?;
+        self.mappings.insert(solana_address.to_string(), fractis.clone());
+        Ok(fractis)
+    }
+
+    pub fn get(&self, solana_address: &str) -> Option<&FRACTISAddress> {
+        self.mappings.get(solana_address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    pub fn export_csv<W: Write>(&self, mut writer: W) -> Result<(), RegistryError> {
+        writeln!(writer, "{}", CSV_HEADER)?;
+        for (solana, fractis) in &self.mappings {
+            writeln!(writer, "{},{}", solana, fractis)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn export_json<W: Write>(&self, writer: W) -> Result<(), RegistryError> {
+        let rows: Vec<MappingRow> = self
+            .mappings
+            .iter()
+            .map(|(solana, fractis)| MappingRow {
+                solana: solana.clone(),
+                fractis: fractis.to_string(),
+            })
+            .collect();
+        serde_json::to_writer_pretty(writer, &rows)?;
+        Ok(())
+    }
+
+    pub fn import_csv<R: Read>(reader: R) -> Result<Self, RegistryError> {
+        let mut registry = Self::new();
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || (index == 0 && line == CSV_HEADER) {
+                continue;
+            }
+
+            let mut fields = line.split(',');
+            let (Some(solana), Some(fractis), None) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(RegistryError::Malformed {
+                    row: index + 1,
+                    reason: "expected exactly two columns".to_string(),
+                });
+            };
+            registry.insert_validated(solana.trim(), fractis.trim())?;
+        }
+        Ok(registry)
+    }
+
+    pub fn import_json<R: Read>(reader: R) -> Result<Self, RegistryError> {
+        let rows: Vec<MappingRow> = serde_json::from_reader(reader)?;
+        let mut registry = Self::new();
+        for row in rows {
+            registry.insert_validated(&row.solana, &row.fractis)?;
+        }
+        Ok(registry)
+    }
+
+    /// Accepts mappings exported under any known scheme and stores them
+    /// under the current one.
+    fn insert_validated(&mut self, solana: &str, fractis: &str) -> Result<(), RegistryError> {
+        let claimed = FRACTISAddress::from_string(fractis)?;
+        let derived = FRACTISAddress::all_schemes(solana)?;
+        if !derived.iter().any(|(_, address)| *address == claimed) {
+            return Err(RegistryError::Mismatch {
+                solana: solana.to_string(),
+                fractis: fractis.to_string(),
+            });
+        }
+        self.mappings.insert(solana.to_string(), FRACTISAddress::from_solana(solana)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::{Keypair, Signer};
+
+    fn sample_registry() -> FractisRegistry {
+        let mut registry = FractisRegistry::new();
+        let mut added = 0;
+        while added < 8 {
+            let address = Keypair::new().pubkey().to_string();
+            if registry.register(&address).is_ok() {
+                added += 1;
+            }
+        }
+        registry
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let registry = sample_registry();
+        let mut exported = Vec::new();
+        registry.export_csv(&mut exported).unwrap();
+
+        let text = String::from_utf8(exported.clone()).unwrap();
+        assert!(text.starts_with(CSV_HEADER));
+        assert_eq!(FractisRegistry::import_csv(exported.as_slice()).unwrap(), registry);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let registry = sample_registry();
+        let mut exported = Vec::new();
+        registry.export_json(&mut exported).unwrap();
+
+        assert_eq!(FractisRegistry::import_json(exported.as_slice()).unwrap(), registry);
+    }
+
+    #[test]
+    fn test_export_is_sorted_and_stable() {
+        let registry = sample_registry();
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        registry.export_csv(&mut first).unwrap();
+        registry.clone().export_csv(&mut second).unwrap();
+        assert_eq!(first, second);
+
+        let text = String::from_utf8(first).unwrap();
+        let solana: Vec<&str> = text.lines().skip(1).map(|l| l.split(',').next().unwrap()).collect();
+        let mut sorted = solana.clone();
+        sorted.sort();
+        assert_eq!(solana, sorted);
+    }
+
+    #[test]
+    fn test_import_migrates_hashed_mappings() {
+        use crate::utils::address::DerivationScheme;
+
+        let solana = "DYw8jCTfwHNRJhhmFcbXvVDTqWMEVFBX6ZKUmG5CNSKK";
+        let v1 = FRACTISAddress::from_solana_with_scheme(solana, DerivationScheme::V1).unwrap();
+        let csv = format!("{}\n{},{}\n", CSV_HEADER, solana, v1);
+
+        let registry = FractisRegistry::import_csv(csv.as_bytes()).unwrap();
+        let stored = registry.get(solana).unwrap();
+        assert_eq!(*stored, FRACTISAddress::from_solana(solana).unwrap());
+        assert_eq!(stored.to_solana().unwrap(), solana);
+    }
+
+    #[test]
+    fn test_import_rejects_mismatched_mapping() {
+        let registry = sample_registry();
+        let mut exported = Vec::new();
+        registry.export_csv(&mut exported).unwrap();
+
+        let mut lines: Vec<String> = String::from_utf8(exported).unwrap().lines().map(String::from).collect();
+        let other = lines[2].split(',').nth(1).unwrap().to_string();
+        let solana = lines[1].split(',').next().unwrap().to_string();
+        lines[1] = format!("{},{}", solana, other);
+
+        let result = FractisRegistry::import_csv(lines.join("\n").as_bytes());
+        assert!(matches!(result, Err(RegistryError::Mismatch { .. })));
+    }
+}